@@ -0,0 +1,95 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Benchmarks of the derivation of the scripts cached during sync
+//!
+//! Run them from the `bench` directory, with and without the `parallel-derivation` feature to
+//! compare the two:
+//!
+//! ```text
+//! cargo bench --bench derivation
+//! cargo bench --bench derivation --features parallel-derivation
+//! ```
+//!
+//! They live in their own crate, outside of the workspace, because criterion doesn't build with
+//! the MSRV of `bdk`.
+
+extern crate bdk;
+extern crate criterion;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use bdk::bitcoin::secp256k1::Secp256k1;
+use bdk::bitcoin::Network;
+use bdk::database::MemoryDatabase;
+use bdk::descriptor::{derive_script_pubkeys, ToWalletDescriptor};
+use bdk::{KeychainKind, OfflineWallet, Wallet};
+
+const SINGLE_SIG: &str = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)";
+const MULTISIG: &str = "wsh(sortedmulti(2,tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*,tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*,tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*))";
+
+fn derive_scripts(c: &mut Criterion) {
+    let secp = Secp256k1::new();
+
+    let mut group = c.benchmark_group("derive_script_pubkeys");
+    for (name, descriptor) in &[("wpkh", SINGLE_SIG), ("wsh_sortedmulti", MULTISIG)] {
+        let (descriptor, _) = descriptor.to_wallet_descriptor(Network::Testnet).unwrap();
+
+        for count in &[100u32, 1_000, 10_000] {
+            group.throughput(Throughput::Elements(*count as u64));
+            group.bench_with_input(BenchmarkId::new(*name, count), count, |b, count| {
+                b.iter(|| derive_script_pubkeys(&descriptor, 0..*count, &secp).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+// The first address requested from a new wallet caches the first batch of scripts
+fn first_address(c: &mut Criterion) {
+    c.bench_function("first_address_wsh_sortedmulti", |b| {
+        b.iter(|| {
+            let wallet: OfflineWallet<_> =
+                Wallet::new_offline(MULTISIG, None, Network::Testnet, MemoryDatabase::new())
+                    .unwrap();
+            wallet.get_new_address().unwrap()
+        })
+    });
+}
+
+fn list_addresses(c: &mut Criterion) {
+    let wallet: OfflineWallet<_> =
+        Wallet::new_offline(MULTISIG, None, Network::Testnet, MemoryDatabase::new()).unwrap();
+
+    c.bench_function("list_addresses_wsh_sortedmulti_1000", |b| {
+        b.iter(|| {
+            wallet
+                .list_addresses(KeychainKind::External, 0..1_000)
+                .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, derive_scripts, first_address, list_addresses);
+criterion_main!(benches);
@@ -13,5 +13,5 @@ ADDR=$(/root/bitcoin-cli -regtest -rpcuser=admin -rpcpassword=passw getnewaddres
 /root/bitcoin-cli -regtest -rpcuser=admin -rpcpassword=passw generatetoaddress 150 $ADDR
 
 echo "Starting electrs node."
-nohup /root/electrs --network regtest --jsonrpc-import &
+nohup /root/electrs --network regtest --jsonrpc-import --http-addr 0.0.0.0:3002 &
 sleep 5
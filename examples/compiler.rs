@@ -29,18 +29,15 @@ extern crate log;
 extern crate miniscript;
 extern crate serde_json;
 
-use std::str::FromStr;
-
 use log::info;
 
 use clap::{App, Arg};
 
 use bitcoin::Network;
-use miniscript::policy::Concrete;
-use miniscript::Descriptor;
 
 use bdk::database::memory::MemoryDatabase;
-use bdk::{KeychainKind, OfflineWallet, Wallet};
+use bdk::descriptor::compiler::compile_policy;
+use bdk::{KeychainKind, OfflineWallet, ScriptType, Wallet};
 
 fn main() {
     env_logger::init_from_env(
@@ -81,15 +78,15 @@ fn main() {
     let policy_str = matches.value_of("POLICY").unwrap();
     info!("Compiling policy: {}", policy_str);
 
-    let policy = Concrete::<String>::from_str(&policy_str).unwrap();
-
-    let descriptor = match matches.value_of("TYPE").unwrap() {
-        "sh" => Descriptor::Sh(policy.compile().unwrap()),
-        "wsh" => Descriptor::Wsh(policy.compile().unwrap()),
-        "sh-wsh" => Descriptor::ShWsh(policy.compile().unwrap()),
+    let script_type = match matches.value_of("TYPE").unwrap() {
+        "sh" => ScriptType::P2sh,
+        "wsh" => ScriptType::P2wsh,
+        "sh-wsh" => ScriptType::P2shP2wsh,
         _ => panic!("Invalid type"),
     };
 
+    let descriptor = compile_policy(policy_str, script_type).unwrap();
+
     info!("... Descriptor: {}", descriptor);
 
     let database = MemoryDatabase::new();
@@ -99,7 +96,7 @@ fn main() {
         Some("testnet") | _ => Network::Testnet,
     };
     let wallet: OfflineWallet<_> =
-        Wallet::new_offline(&format!("{}", descriptor), None, network, database).unwrap();
+        Wallet::new_offline(&descriptor, None, network, database).unwrap();
 
     info!("... First address: {}", wallet.get_new_address().unwrap());
 
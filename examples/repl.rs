@@ -23,9 +23,12 @@
 // SOFTWARE.
 
 use std::fs;
+use std::io;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
 
 use bitcoin::Network;
 use clap::AppSettings;
@@ -40,9 +43,9 @@ use bdk::blockchain::esplora::EsploraBlockchainConfig;
 use bdk::blockchain::{
     AnyBlockchain, AnyBlockchainConfig, ConfigurableBlockchain, ElectrumBlockchainConfig,
 };
-use bdk::cli::{self, WalletOpt, WalletSubCommand};
+use bdk::cli::{self, WalletConfig, WalletOpt, WalletProfiles, WalletSubCommand};
 use bdk::sled;
-use bdk::Wallet;
+use bdk::{FeeRate, Wallet};
 
 #[derive(Debug, StructOpt, Clone, PartialEq)]
 #[structopt(name = "BDK Wallet", setting = AppSettings::NoBinaryName,
@@ -64,27 +67,137 @@ fn prepare_home_dir() -> PathBuf {
         fs::create_dir(&dir).unwrap();
     }
 
-    dir.push("database.sled");
     dir
 }
 
+// Run a sub-command that needs a wallet, printing the events of `watch` as they are reported
+fn run_subcommand<D: bdk::database::BatchDatabase>(
+    wallet: &Wallet<AnyBlockchain, D>,
+    subcommand: WalletSubCommand,
+    json: bool,
+) -> Result<serde_json::Value, bdk::Error> {
+    match subcommand {
+        subcommand @ WalletSubCommand::Watch { .. } => {
+            cli::handle_watch_subcommand(wallet, subcommand, |event| {
+                println!("{}", cli::format_output(&Ok(event), json))
+            })
+        }
+        subcommand => cli::handle_wallet_subcommand(wallet, subcommand),
+    }
+}
+
+fn print_result(result: &Result<serde_json::Value, bdk::Error>, json: bool, qr: bool) {
+    println!("{}", cli::format_output(result, json));
+
+    let frames = match result {
+        Ok(value) if qr => cli::qr_frames(value),
+        _ => return,
+    };
+    for frames in frames.unwrap_or_else(|e| vec![vec![format!("Error: {}", e)]]) {
+        match frames.as_slice() {
+            [frame] => match cli::render_qr(frame) {
+                Ok(code) => println!("{}", code),
+                Err(e) => println!("Error: {}", e),
+            },
+            _ => show_animated_qr(&frames),
+        }
+    }
+}
+
+// Loop over the frames of an animated QR code until the user presses enter
+fn show_animated_qr(frames: &[String]) {
+    let codes = match frames
+        .iter()
+        .map(|f| cli::render_qr(f))
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(codes) => codes,
+        Err(e) => {
+            println!("Error: {}", e);
+            return;
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line).ok();
+        sender.send(()).ok();
+    });
+
+    for (i, code) in codes.iter().enumerate().cycle() {
+        // clear the screen and move the cursor to the top
+        print!("\x1B[2J\x1B[H");
+        println!("{}", code);
+        println!("Part {}/{}, press enter to stop", i + 1, codes.len());
+
+        if receiver.recv_timeout(Duration::from_millis(300)).is_ok() {
+            break;
+        }
+    }
+}
+
 fn main() {
-    let cli_opt: WalletOpt = WalletOpt::from_args();
+    let matches = WalletOpt::clap().get_matches();
+    let mut cli_opt: WalletOpt = WalletOpt::from_clap(&matches);
 
     let level = LevelFilter::from_str(cli_opt.log_level.as_str()).unwrap_or(LevelFilter::Info);
     env_logger::builder().filter_level(level).init();
 
+    let config_path = match &cli_opt.config {
+        Some(path) => PathBuf::from(path),
+        None => dirs_next::home_dir()
+            .unwrap()
+            .join(".bdk")
+            .join("config.toml"),
+    };
+    let wallet_config = match WalletConfig::load(&config_path) {
+        Ok(wallet_config) => wallet_config,
+        Err(e) => {
+            println!("{}", cli::format_output(&Err(e), cli_opt.json));
+            std::process::exit(1);
+        }
+    };
+    wallet_config.apply(&mut cli_opt, &matches);
+    debug!("config loaded from {}", config_path.display());
+
+    if !cli_opt.subcommand.needs_wallet() {
+        let network = Network::from_str(cli_opt.network.as_str()).unwrap_or(Network::Testnet);
+        let result = cli::handle_key_subcommand(network, cli_opt.subcommand);
+        println!("{}", cli::format_output(&result, cli_opt.json));
+        if result.is_err() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let home_dir = prepare_home_dir();
+    let profiles_path = home_dir.join("profiles.json");
+    let mut profiles = WalletProfiles::load(&profiles_path).unwrap();
+    if let Err(e) = cli_opt.resolve_profile(&mut profiles) {
+        println!("{}", cli::format_output(&Err(e), cli_opt.json));
+        std::process::exit(1);
+    }
+    if cli_opt.save_profile {
+        profiles.save(&profiles_path).unwrap();
+        info!("Profile `{}` saved", cli_opt.wallet);
+    }
+
     let network = Network::from_str(cli_opt.network.as_str()).unwrap_or(Network::Testnet);
     debug!("network: {:?}", network);
     if network == Network::Bitcoin {
         warn!("This is experimental software and not currently recommended for use on Bitcoin mainnet, proceed with caution.")
     }
 
-    let descriptor = cli_opt.descriptor.as_str();
+    let descriptor = cli_opt.descriptor.as_deref().unwrap();
     let change_descriptor = cli_opt.change_descriptor.as_deref();
     debug!("descriptors: {:?} {:?}", descriptor, change_descriptor);
 
-    let database = sled::open(prepare_home_dir().to_str().unwrap()).unwrap();
+    let database_path = match &cli_opt.database_path {
+        Some(path) => PathBuf::from(path),
+        None => home_dir.join("database.sled"),
+    };
+    let database = sled::open(database_path.to_str().unwrap()).unwrap();
     let tree = database.open_tree(cli_opt.wallet).unwrap();
     debug!("database opened successfully");
 
@@ -96,6 +209,7 @@ fn main() {
             AnyBlockchainConfig::Esplora(EsploraBlockchainConfig {
                 base_url: base_url.to_string(),
                 concurrency: Some(esplora_concurrency),
+                rate_limit: None,
             })
         })
     };
@@ -109,9 +223,10 @@ fn main() {
             socks5: cli_opt.proxy,
             retry: 10,
             timeout: 10,
+            rate_limit: None,
         }));
 
-    let wallet = Wallet::new(
+    let mut wallet = Wallet::new(
         descriptor,
         change_descriptor,
         network,
@@ -119,8 +234,10 @@ fn main() {
         AnyBlockchain::from_config(&config).unwrap(),
     )
     .unwrap();
+    wallet.set_max_fee_rate(wallet_config.max_fee_rate.map(FeeRate::from_sat_per_vb));
 
     let wallet = Arc::new(wallet);
+    let (json, qr) = (cli_opt.json, cli_opt.qr);
 
     match cli_opt.subcommand {
         WalletSubCommand::Other(external) if external.contains(&"repl".to_string()) => {
@@ -148,12 +265,11 @@ fn main() {
                             continue;
                         }
 
-                        let result = cli::handle_wallet_subcommand(
-                            &Arc::clone(&wallet),
-                            repl_subcommand.unwrap().subcommand,
-                        )
-                        .unwrap();
-                        println!("{}", serde_json::to_string_pretty(&result).unwrap());
+                        let mut subcommand = repl_subcommand.unwrap().subcommand;
+                        wallet_config.apply_subcommand(&mut subcommand);
+
+                        let result = run_subcommand(&wallet, subcommand, json);
+                        print_result(&result, json, qr);
                     }
                     Err(ReadlineError::Interrupted) => continue,
                     Err(ReadlineError::Eof) => break,
@@ -167,8 +283,11 @@ fn main() {
             // rl.save_history("history.txt").unwrap();
         }
         _ => {
-            let result = cli::handle_wallet_subcommand(&wallet, cli_opt.subcommand).unwrap();
-            println!("{}", serde_json::to_string_pretty(&result).unwrap());
+            let result = run_subcommand(&wallet, cli_opt.subcommand, json);
+            print_result(&result, json, qr);
+            if result.is_err() {
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -157,3 +157,34 @@ pub fn await_or_block(expr: TokenStream) -> TokenStream {
 
     quoted.into()
 }
+
+/// Blocks on the future with a `tokio` runtime if the async interface is enabled, does nothing otherwise
+///
+/// This is the counterpart of [`maybe_await!`] that can be used from blocking code, like tests,
+/// to call a method annotated with [`macro@maybe_async`] regardless of the interface enabled.
+///
+/// Requires the `tokio` crate as a dependecy with `rt-core` to build with the async interface.
+#[proc_macro]
+pub fn block_on_maybe_async(expr: TokenStream) -> TokenStream {
+    let expr: proc_macro2::TokenStream = expr.into();
+    let quoted = quote! {
+        {
+            #[cfg(all(not(target_arch = "wasm32"), not(feature = "async-interface")))]
+            {
+                #expr
+            }
+
+            #[cfg(any(target_arch = "wasm32", feature = "async-interface"))]
+            {
+                tokio::runtime::Builder::new()
+                    .basic_scheduler()
+                    .enable_all()
+                    .build()
+                    .unwrap()
+                    .block_on(#expr)
+            }
+        }
+    };
+
+    quoted.into()
+}
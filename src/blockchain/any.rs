@@ -179,6 +179,10 @@ impl Blockchain for AnyBlockchain {
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
         maybe_await!(impl_inner_method!(self, estimate_fee, target))
     }
+
+    fn get_block_txs(&self, height: u32) -> Result<Vec<(Transaction, Vec<TxOut>)>, Error> {
+        maybe_await!(impl_inner_method!(self, get_block_txs, height))
+    }
 }
 
 impl_from!(electrum::ElectrumBlockchain, AnyBlockchain, Electrum, #[cfg(feature = "electrum")]);
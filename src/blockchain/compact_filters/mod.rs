@@ -37,6 +37,9 @@
 //! connecting to a single peer at a time, optionally by opening multiple connections if it's
 //! desirable to use multiple threads at once to sync in parallel.
 //!
+//! When the `verify` feature is enabled, unconfirmed transactions are only added to the database
+//! if they can be verified against their parents already stored in it.
+//!
 //! This is an **EXPERIMENTAL** feature, API and other major changes are expected.
 //!
 //! ## Example
@@ -163,6 +166,18 @@ impl CompactFiltersBlockchain {
         internal_max_deriv: &mut Option<u32>,
         external_max_deriv: &mut Option<u32>,
     ) -> Result<(), Error> {
+        // unconfirmed txs come straight from the peer's mempool, so they are only trusted if they
+        // can be verified. this also skips unconfirmed txs whose parents we don't know about.
+        #[cfg(feature = "verify")]
+        {
+            if height.is_none() {
+                if let Err(e) = crate::wallet::verify::verify_tx(tx, database) {
+                    debug!("Skipping unconfirmed tx {}: {:?}", tx.txid(), e);
+                    return Ok(());
+                }
+            }
+        }
+
         let mut updates = database.begin_batch();
 
         let mut incoming: u64 = 0;
@@ -222,6 +237,7 @@ impl CompactFiltersBlockchain {
                 height,
                 timestamp,
                 fees: inputs_sum.checked_sub(outputs_sum).unwrap_or(0),
+                recipients: vec![],
             };
 
             info!("Saving tx {}", tx.txid);
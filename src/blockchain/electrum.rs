@@ -46,6 +46,7 @@ use bitcoin::{BlockHeader, Script, Transaction, Txid};
 
 use electrum_client::{Client, ConfigBuilder, ElectrumApi, Socks5Config};
 
+use self::rate_limiter::{RateLimitConfig, RateLimiter};
 use self::utils::{ELSGetHistoryRes, ElectrumLikeSync};
 use super::*;
 use crate::database::BatchDatabase;
@@ -56,7 +57,10 @@ use crate::FeeRate;
 ///
 /// ## Example
 /// See the [`blockchain::electrum`](crate::blockchain::electrum) module for a usage example.
-pub struct ElectrumBlockchain(Client);
+pub struct ElectrumBlockchain {
+    client: Client,
+    rate_limiter: Option<RateLimiter>,
+}
 
 #[cfg(test)]
 #[cfg(feature = "test-electrum")]
@@ -67,7 +71,26 @@ fn local_electrs() -> ElectrumBlockchain {
 
 impl std::convert::From<Client> for ElectrumBlockchain {
     fn from(client: Client) -> Self {
-        ElectrumBlockchain(client)
+        ElectrumBlockchain {
+            client,
+            rate_limiter: None,
+        }
+    }
+}
+
+impl ElectrumBlockchain {
+    /// Limit the rate of the requests sent to the server
+    ///
+    /// See the [`rate_limiter`](crate::blockchain::rate_limiter) module for more details.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    fn rate_limit(&self, count: usize) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(count as u32);
+        }
     }
 }
 
@@ -88,40 +111,46 @@ impl Blockchain for ElectrumBlockchain {
         database: &mut D,
         progress_update: P,
     ) -> Result<(), Error> {
-        self.0
-            .electrum_like_setup(stop_gap, database, progress_update)
+        self.electrum_like_setup(stop_gap, database, progress_update)
     }
 
     fn get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
-        Ok(self.0.transaction_get(txid).map(Option::Some)?)
+        self.rate_limit(1);
+        Ok(self.client.transaction_get(txid).map(Option::Some)?)
     }
 
     fn broadcast(&self, tx: &Transaction) -> Result<(), Error> {
-        Ok(self.0.transaction_broadcast(tx).map(|_| ())?)
+        self.rate_limit(1);
+        Ok(self.client.transaction_broadcast(tx).map(|_| ())?)
     }
 
     fn get_height(&self) -> Result<u32, Error> {
         // TODO: unsubscribe when added to the client, or is there a better call to use here?
 
+        self.rate_limit(1);
         Ok(self
-            .0
+            .client
             .block_headers_subscribe()
             .map(|data| data.height as u32)?)
     }
 
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
+        self.rate_limit(1);
         Ok(FeeRate::from_btc_per_kvb(
-            self.0.estimate_fee(target)? as f32
+            self.client.estimate_fee(target)? as f32
         ))
     }
 }
 
-impl ElectrumLikeSync for Client {
+impl ElectrumLikeSync for ElectrumBlockchain {
     fn els_batch_script_get_history<'s, I: IntoIterator<Item = &'s Script> + Clone>(
         &self,
         scripts: I,
     ) -> Result<Vec<Vec<ELSGetHistoryRes>>, Error> {
-        self.batch_script_get_history(scripts)
+        // every element in a batch counts as a separate request
+        self.rate_limit(scripts.clone().into_iter().count());
+        self.client
+            .batch_script_get_history(scripts)
             .map(|v| {
                 v.into_iter()
                     .map(|v| {
@@ -145,14 +174,20 @@ impl ElectrumLikeSync for Client {
         &self,
         txids: I,
     ) -> Result<Vec<Transaction>, Error> {
-        self.batch_transaction_get(txids).map_err(Error::Electrum)
+        self.rate_limit(txids.clone().into_iter().count());
+        self.client
+            .batch_transaction_get(txids)
+            .map_err(Error::Electrum)
     }
 
     fn els_batch_block_header<I: IntoIterator<Item = u32> + Clone>(
         &self,
         heights: I,
     ) -> Result<Vec<BlockHeader>, Error> {
-        self.batch_block_header(heights).map_err(Error::Electrum)
+        self.rate_limit(heights.clone().into_iter().count());
+        self.client
+            .batch_block_header(heights)
+            .map_err(Error::Electrum)
     }
 }
 
@@ -169,6 +204,8 @@ pub struct ElectrumBlockchainConfig {
     pub retry: u8,
     /// Request timeout (seconds)
     pub timeout: u8,
+    /// Optional limit on the rate of requests sent to the server
+    pub rate_limit: Option<RateLimitConfig>,
 }
 
 impl ConfigurableBlockchain for ElectrumBlockchain {
@@ -182,9 +219,12 @@ impl ConfigurableBlockchain for ElectrumBlockchain {
             .timeout(config.timeout)?
             .build();
 
-        Ok(ElectrumBlockchain(Client::from_config(
-            config.url.as_str(),
-            electrum_config,
-        )?))
+        let mut blockchain =
+            ElectrumBlockchain::from(Client::from_config(config.url.as_str(), electrum_config)?);
+        if let Some(rate_limit) = config.rate_limit {
+            blockchain = blockchain.with_rate_limiter(RateLimiter::new(rate_limit));
+        }
+
+        Ok(blockchain)
     }
 }
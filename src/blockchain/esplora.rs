@@ -37,6 +37,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::time::Duration;
 
 use futures::stream::{self, FuturesOrdered, StreamExt, TryStreamExt};
 
@@ -50,12 +51,14 @@ use reqwest::{Client, StatusCode};
 use bitcoin::consensus::{self, deserialize, serialize};
 use bitcoin::hashes::hex::{FromHex, ToHex};
 use bitcoin::hashes::{sha256, Hash};
-use bitcoin::{BlockHash, BlockHeader, Script, Transaction, Txid};
+use bitcoin::{Block, BlockHash, BlockHeader, Script, Transaction, TxOut, Txid};
 
+use self::rate_limiter::{RateLimitConfig, RateLimiter};
 use self::utils::{ELSGetHistoryRes, ElectrumLikeSync};
 use super::*;
 use crate::database::BatchDatabase;
 use crate::error::Error;
+use crate::wallet::silent_payments::is_taproot;
 use crate::wallet::utils::ChunksIterator;
 use crate::FeeRate;
 
@@ -68,6 +71,7 @@ struct UrlClient {
     // when the target platform is wasm32.
     client: Client,
     concurrency: u8,
+    rate_limiter: Option<RateLimiter>,
 }
 
 /// Structure that implements the logic to sync with Esplora
@@ -77,6 +81,13 @@ struct UrlClient {
 #[derive(Debug)]
 pub struct EsploraBlockchain(UrlClient);
 
+#[cfg(test)]
+#[cfg(feature = "test-esplora")]
+#[bdk_blockchain_tests(crate)]
+fn local_esplora() -> EsploraBlockchain {
+    EsploraBlockchain::new(&testutils::get_esplora_url(), None)
+}
+
 impl std::convert::From<UrlClient> for EsploraBlockchain {
     fn from(url_client: UrlClient) -> Self {
         EsploraBlockchain(url_client)
@@ -90,8 +101,17 @@ impl EsploraBlockchain {
             url: base_url.to_string(),
             client: Client::new(),
             concurrency: concurrency.unwrap_or(DEFAULT_CONCURRENT_REQUESTS),
+            rate_limiter: None,
         })
     }
+
+    /// Limit the rate of the requests sent to the server
+    ///
+    /// See the [`rate_limiter`](crate::blockchain::rate_limiter) module for more details.
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.0.rate_limiter = Some(rate_limiter);
+        self
+    }
 }
 
 #[maybe_async]
@@ -101,6 +121,7 @@ impl Blockchain for EsploraBlockchain {
             Capability::FullHistory,
             Capability::GetAnyTx,
             Capability::AccurateFees,
+            Capability::BlockTransactions,
         ]
         .into_iter()
         .collect()
@@ -145,6 +166,10 @@ impl Blockchain for EsploraBlockchain {
 
         Ok(FeeRate::from_sat_per_vb(fee_val as f32))
     }
+
+    fn get_block_txs(&self, height: u32) -> Result<Vec<(Transaction, Vec<TxOut>)>, Error> {
+        Ok(await_or_block!(self.0._get_block_txs(height))?)
+    }
 }
 
 impl UrlClient {
@@ -152,7 +177,24 @@ impl UrlClient {
         sha256::Hash::hash(script.as_bytes()).into_inner().to_hex()
     }
 
+    async fn rate_limit(&self) {
+        let delay = match &self.rate_limiter {
+            Some(rate_limiter) => rate_limiter.reserve(1),
+            None => return,
+        };
+        if delay == Duration::from_secs(0) {
+            return;
+        }
+
+        debug!("Rate limit reached, waiting {} ms", delay.as_millis());
+
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::time::delay_for(delay).await;
+        // TODO: there's no timer we can use on wasm32 yet, so requests are sent right away
+    }
+
     async fn _get_tx(&self, txid: &Txid) -> Result<Option<Transaction>, EsploraError> {
+        self.rate_limit().await;
         let resp = self
             .client
             .get(&format!("{}/tx/{}/raw", self.url, txid))
@@ -174,7 +216,8 @@ impl UrlClient {
         }
     }
 
-    async fn _get_header(&self, block_height: u32) -> Result<BlockHeader, EsploraError> {
+    async fn _get_block_hash(&self, block_height: u32) -> Result<String, EsploraError> {
+        self.rate_limit().await;
         let resp = self
             .client
             .get(&format!("{}/block-height/{}", self.url, block_height))
@@ -188,6 +231,13 @@ impl UrlClient {
         let hash = std::str::from_utf8(&bytes)
             .map_err(|_| EsploraError::HeaderHeightNotFound(block_height))?;
 
+        Ok(hash.to_string())
+    }
+
+    async fn _get_header(&self, block_height: u32) -> Result<BlockHeader, EsploraError> {
+        let hash = self._get_block_hash(block_height).await?;
+
+        self.rate_limit().await;
         let resp = self
             .client
             .get(&format!("{}/block/{}/header", self.url, hash))
@@ -199,7 +249,75 @@ impl UrlClient {
         Ok(header)
     }
 
+    async fn _get_block_txs(
+        &self,
+        block_height: u32,
+    ) -> Result<Vec<(Transaction, Vec<TxOut>)>, EsploraError> {
+        let hash = self._get_block_hash(block_height).await?;
+
+        self.rate_limit().await;
+        let resp = self
+            .client
+            .get(&format!("{}/block/{}/raw", self.url, hash))
+            .send()
+            .await?;
+        let block: Block = deserialize(&resp.error_for_status()?.bytes().await?)?;
+
+        // the raw block doesn't contain the outputs spent, they are fetched from the pages of 25
+        // transactions returned by esplora, but only for the transactions that need them
+        let mut page_start = None;
+        let mut page_txs: Vec<EsploraBlockTx> = vec![];
+        let mut result = Vec::with_capacity(block.txdata.len());
+        for (index, tx) in block.txdata.into_iter().enumerate() {
+            if index == 0
+                || !tx
+                    .output
+                    .iter()
+                    .any(|txout| is_taproot(&txout.script_pubkey))
+            {
+                result.push((tx, vec![]));
+                continue;
+            }
+
+            let start_index = index - index % 25;
+            if page_start != Some(start_index) {
+                self.rate_limit().await;
+                page_txs = self
+                    .client
+                    .get(&format!("{}/block/{}/txs/{}", self.url, hash, start_index))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<Vec<EsploraBlockTx>>()
+                    .await?;
+                page_start = Some(start_index);
+            }
+
+            let prevouts = page_txs
+                .get(index - start_index)
+                .filter(|esplora_tx| esplora_tx.txid == tx.txid())
+                .ok_or_else(|| EsploraError::TransactionNotFound(tx.txid()))?
+                .vin
+                .iter()
+                .map(|vin| -> Result<_, EsploraError> {
+                    let prevout = vin
+                        .prevout
+                        .as_ref()
+                        .ok_or_else(|| EsploraError::TransactionNotFound(tx.txid()))?;
+                    Ok(TxOut {
+                        value: prevout.value,
+                        script_pubkey: Script::from(Vec::<u8>::from_hex(&prevout.scriptpubkey)?),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            result.push((tx, prevouts));
+        }
+
+        Ok(result)
+    }
+
     async fn _broadcast(&self, transaction: &Transaction) -> Result<(), EsploraError> {
+        self.rate_limit().await;
         self.client
             .post(&format!("{}/tx", self.url))
             .body(serialize(transaction).to_hex())
@@ -211,6 +329,7 @@ impl UrlClient {
     }
 
     async fn _get_height(&self) -> Result<u32, EsploraError> {
+        self.rate_limit().await;
         let req = self
             .client
             .get(&format!("{}/blocks/tip/height", self.url))
@@ -228,6 +347,7 @@ impl UrlClient {
         let scripthash = Self::script_to_scripthash(script);
 
         // Add the unconfirmed transactions first
+        self.rate_limit().await;
         result.extend(
             self.client
                 .get(&format!(
@@ -256,6 +376,7 @@ impl UrlClient {
         // Then go through all the pages of confirmed transactions
         let mut last_txid = String::new();
         loop {
+            self.rate_limit().await;
             let response = self
                 .client
                 .get(&format!(
@@ -288,6 +409,7 @@ impl UrlClient {
     }
 
     async fn _get_fee_estimates(&self) -> Result<HashMap<String, f64>, EsploraError> {
+        self.rate_limit().await;
         Ok(self
             .client
             .get(&format!("{}/fee-estimates", self.url,))
@@ -373,6 +495,23 @@ struct EsploraGetHistory {
     status: EsploraGetHistoryStatus,
 }
 
+#[derive(Deserialize)]
+struct EsploraPrevout {
+    scriptpubkey: String,
+    value: u64,
+}
+
+#[derive(Deserialize)]
+struct EsploraTxIn {
+    prevout: Option<EsploraPrevout>,
+}
+
+#[derive(Deserialize)]
+struct EsploraBlockTx {
+    txid: Txid,
+    vin: Vec<EsploraTxIn>,
+}
+
 /// Configuration for an [`EsploraBlockchain`]
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct EsploraBlockchainConfig {
@@ -382,16 +521,20 @@ pub struct EsploraBlockchainConfig {
     pub base_url: String,
     /// Number of parallel requests sent to the esplora service (default: 4)
     pub concurrency: Option<u8>,
+    /// Optional limit on the rate of requests sent to the esplora service
+    pub rate_limit: Option<RateLimitConfig>,
 }
 
 impl ConfigurableBlockchain for EsploraBlockchain {
     type Config = EsploraBlockchainConfig;
 
     fn from_config(config: &Self::Config) -> Result<Self, Error> {
-        Ok(EsploraBlockchain::new(
-            config.base_url.as_str(),
-            config.concurrency,
-        ))
+        let mut blockchain = EsploraBlockchain::new(config.base_url.as_str(), config.concurrency);
+        if let Some(rate_limit) = config.rate_limit {
+            blockchain = blockchain.with_rate_limiter(RateLimiter::new(rate_limit));
+        }
+
+        Ok(blockchain)
     }
 }
 
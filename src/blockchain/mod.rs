@@ -30,16 +30,24 @@
 //! [`Blockchain`] that can be implemented to build customized backends.
 
 use std::collections::HashSet;
+#[cfg(feature = "async-interface")]
+use std::future::Future;
 use std::ops::Deref;
+#[cfg(feature = "async-interface")]
+use std::pin::Pin;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
+#[cfg(feature = "async-interface")]
+use std::task::{Context, Poll};
 
-use bitcoin::{Transaction, Txid};
+use bitcoin::{Transaction, TxOut, Txid};
 
 use crate::database::BatchDatabase;
 use crate::error::Error;
 use crate::FeeRate;
 
+#[cfg(any(feature = "electrum", feature = "esplora"))]
+pub mod rate_limiter;
 #[cfg(any(feature = "electrum", feature = "esplora"))]
 pub(crate) mod utils;
 
@@ -77,6 +85,8 @@ pub enum Capability {
     GetAnyTx,
     /// Can compute accurate fees for the transactions found during sync
     AccurateFees,
+    /// Can fetch every transaction of a block, together with the outputs spent by its inputs
+    BlockTransactions,
 }
 
 /// Marker trait for a blockchain backend
@@ -158,6 +168,75 @@ pub trait Blockchain: BlockchainMarker {
     fn get_height(&self) -> Result<u32, Error>;
     /// Estimate the fee rate required to confirm a transaction in a given `target` of blocks
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error>;
+
+    /// Fetch the transactions of the block at `height`, together with the outputs spent by their
+    /// inputs
+    ///
+    /// This is used to scan the blocks for silent payments, which can't be found by looking for
+    /// the wallet's script_pubkeys (see
+    /// [`Wallet::set_silent_payment_receiver`](crate::wallet::Wallet::set_silent_payment_receiver)).
+    /// The outputs spent are only needed for the transactions that can contain silent payments,
+    /// so the list is left empty for the coinbase and for the transactions without any taproot
+    /// output.
+    ///
+    /// Only the backends that support [`Capability::BlockTransactions`] implement this method,
+    /// the others return an error.
+    fn get_block_txs(&self, height: u32) -> Result<Vec<(Transaction, Vec<TxOut>)>, Error> {
+        Err(Error::Generic(format!(
+            "The blockchain backend can't fetch the transactions of block {}",
+            height
+        )))
+    }
+}
+
+/// Trait for types that know the current tip of the chain
+///
+/// This is used by the [`Wallet`](crate::wallet::Wallet) to check whether the timelocks of its
+/// descriptors have expired, and it allows it to do so without a full [`Blockchain`] instance,
+/// for instance if the application already tracks the tip of the chain by other means. See
+/// [`Wallet::set_tip_oracle`](crate::wallet::Wallet::set_tip_oracle).
+///
+/// Like [`Blockchain`], the methods of this trait are `async` when the library is built with the
+/// `async-interface` feature or for the `wasm32` target. It is automatically implemented for
+/// every [`Blockchain`], and [`StaticTip`] can be used to provide fixed values.
+#[maybe_async]
+pub trait TipOracle: Send + Sync {
+    /// Return the height of the tip of the chain
+    fn get_tip_height(&self) -> Result<u32, Error>;
+
+    /// Return the median time past of the tip of the chain, if known
+    ///
+    /// If not overridden, it defaults to returning `None`.
+    fn get_tip_median_time_past(&self) -> Result<Option<u32>, Error> {
+        Ok(None)
+    }
+}
+
+#[maybe_async]
+impl<T: Blockchain + Send + Sync> TipOracle for T {
+    fn get_tip_height(&self) -> Result<u32, Error> {
+        maybe_await!(self.get_height())
+    }
+}
+
+/// Type that implements [`TipOracle`] and always returns the same tip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticTip {
+    /// Height of the tip
+    pub height: u32,
+    /// Median time past of the tip, if known
+    pub median_time_past: Option<u32>,
+}
+
+#[maybe_async]
+impl TipOracle for StaticTip {
+    fn get_tip_height(&self) -> Result<u32, Error> {
+        Ok(self.height)
+    }
+
+    fn get_tip_median_time_past(&self) -> Result<Option<u32>, Error> {
+        Ok(self.median_time_past)
+    }
 }
 
 /// Trait for [`Blockchain`] types that can be created given a configuration
@@ -234,6 +313,90 @@ impl Progress for LogProgress {
     }
 }
 
+/// Update emitted by the [`Stream`](futures::stream::Stream) returned by
+/// [`Wallet::sync_stream`](crate::wallet::Wallet::sync_stream)
+#[cfg(feature = "async-interface")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+#[derive(Debug)]
+pub enum SyncUpdate {
+    /// Progress update, see [`Progress::update`]
+    Progress {
+        /// Progress in the range 0.0 - 100.0
+        progress: f32,
+        /// Optional message that can be displayed to the user
+        message: Option<String>,
+    },
+    /// The sync is over, this is always the last update emitted by the stream
+    Finished(Result<(), Error>),
+}
+
+#[cfg(feature = "async-interface")]
+impl Progress for futures::channel::mpsc::UnboundedSender<ProgressData> {
+    fn update(&self, progress: f32, message: Option<String>) -> Result<(), Error> {
+        if progress < 0.0 || progress > 100.0 {
+            return Err(Error::InvalidProgressValue(progress));
+        }
+
+        self.unbounded_send((progress, message))
+            .map_err(|_| Error::ProgressUpdateError)
+    }
+}
+
+/// Stream that drives a sync future and emits its progress updates
+///
+/// The future is only polled while the stream is, and dropping the stream cancels the sync.
+#[cfg(feature = "async-interface")]
+pub(crate) struct SyncStream<F> {
+    sync: Option<Pin<Box<F>>>,
+    updates: futures::channel::mpsc::UnboundedReceiver<ProgressData>,
+    result: Option<Result<(), Error>>,
+}
+
+#[cfg(feature = "async-interface")]
+impl<F: Future<Output = Result<(), Error>>> SyncStream<F> {
+    /// Create a new stream, `make_sync` receives the [`Progress`] that must be passed to the sync
+    pub(crate) fn new<M>(make_sync: M) -> Self
+    where
+        M: FnOnce(futures::channel::mpsc::UnboundedSender<ProgressData>) -> F,
+    {
+        let (sender, updates) = futures::channel::mpsc::unbounded();
+
+        SyncStream {
+            sync: Some(Box::pin(make_sync(sender))),
+            updates,
+            result: None,
+        }
+    }
+}
+
+#[cfg(feature = "async-interface")]
+impl<F: Future<Output = Result<(), Error>>> futures::stream::Stream for SyncStream<F> {
+    type Item = SyncUpdate;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        use futures::stream::StreamExt;
+
+        let this = self.get_mut();
+
+        if let Some(sync) = this.sync.as_mut() {
+            if let Poll::Ready(result) = sync.as_mut().poll(cx) {
+                // this also drops the sender owned by the future, so that the channel is closed
+                // once every update has been received
+                this.sync = None;
+                this.result = Some(result);
+            }
+        }
+
+        match this.updates.poll_next_unpin(cx) {
+            Poll::Ready(Some((progress, message))) => {
+                Poll::Ready(Some(SyncUpdate::Progress { progress, message }))
+            }
+            Poll::Ready(None) => Poll::Ready(this.result.take().map(SyncUpdate::Finished)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
 #[maybe_async]
 impl<T: Blockchain> Blockchain for Arc<T> {
     fn get_capabilities(&self) -> HashSet<Capability> {
@@ -271,4 +434,51 @@ impl<T: Blockchain> Blockchain for Arc<T> {
     fn estimate_fee(&self, target: usize) -> Result<FeeRate, Error> {
         maybe_await!(self.deref().estimate_fee(target))
     }
+
+    fn get_block_txs(&self, height: u32) -> Result<Vec<(Transaction, Vec<TxOut>)>, Error> {
+        maybe_await!(self.deref().get_block_txs(height))
+    }
+}
+
+#[cfg(all(test, feature = "async-interface"))]
+mod test {
+    use futures::executor::block_on;
+    use futures::stream::StreamExt;
+
+    use super::*;
+
+    #[test]
+    fn test_sync_stream() {
+        let stream = SyncStream::new(|progress| async move {
+            progress.update(0.0, None)?;
+            progress.update(100.0, Some("done".into()))?;
+            Ok(())
+        });
+        let updates = block_on(stream.collect::<Vec<_>>());
+
+        assert_eq!(updates.len(), 3);
+        assert!(
+            matches!(&updates[0], SyncUpdate::Progress { progress, message: None } if *progress == 0.0)
+        );
+        assert!(
+            matches!(&updates[1], SyncUpdate::Progress { progress, message: Some(message) } if *progress == 100.0 && message == "done")
+        );
+        assert!(matches!(&updates[2], SyncUpdate::Finished(Ok(()))));
+    }
+
+    #[test]
+    fn test_sync_stream_error() {
+        let stream = SyncStream::new(|progress| async move {
+            progress.update(50.0, None)?;
+            progress.update(150.0, None)?;
+            Ok(())
+        });
+        let updates = block_on(stream.collect::<Vec<_>>());
+
+        assert_eq!(updates.len(), 2);
+        assert!(matches!(
+            &updates[1],
+            SyncUpdate::Finished(Err(Error::InvalidProgressValue(_)))
+        ));
+    }
 }
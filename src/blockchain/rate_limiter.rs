@@ -0,0 +1,196 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Rate limiting
+//!
+//! This module provides a token-bucket [`RateLimiter`] that can be attached to the
+//! [Electrum](crate::blockchain::electrum) and [Esplora](crate::blockchain::esplora) backends to
+//! cap the rate of requests sent to a server, which is useful to avoid getting banned by public
+//! instances when many wallets are synced at the same time.
+//!
+//! Requests that exceed the limit are not rejected: they are delayed until enough tokens are
+//! available in the bucket, in the order in which they were made.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bdk::blockchain::electrum::ElectrumBlockchain;
+//! # use bdk::blockchain::rate_limiter::{RateLimitConfig, RateLimiter};
+//! let client = electrum_client::Client::new("ssl://electrum.blockstream.info:50002")?;
+//! let blockchain = ElectrumBlockchain::from(client).with_rate_limiter(RateLimiter::new(
+//!     RateLimitConfig {
+//!         requests_per_second: 10.0,
+//!         burst: 50,
+//!     },
+//! ));
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::wallet::time::Instant;
+
+/// Configuration for a [`RateLimiter`]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct RateLimitConfig {
+    /// Average number of requests allowed per second. Values `<= 0.0` disable the limit
+    pub requests_per_second: f32,
+    /// Maximum number of requests that can be sent in a burst, when the bucket is full
+    pub burst: u32,
+}
+
+struct BucketState {
+    tokens: f32,
+    start: Instant,
+    last_refill: Duration,
+}
+
+/// Token-bucket rate limiter
+///
+/// The bucket starts full, with [`RateLimitConfig::burst`] tokens, and is refilled at a rate of
+/// [`RateLimitConfig::requests_per_second`] tokens per second. Every request consumes a token.
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<BucketState>,
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    /// Create a new rate limiter with a full bucket
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            state: Mutex::new(BucketState {
+                tokens: config.burst as f32,
+                start: Instant::new(),
+                last_refill: Duration::from_secs(0),
+            }),
+        }
+    }
+
+    /// Return the configuration of the rate limiter
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    /// Reserve `count` tokens and return how long the caller has to wait before sending its
+    /// requests
+    ///
+    /// Tokens are taken even if the bucket doesn't contain enough of them, which means that
+    /// subsequent callers will have to wait longer: this effectively queues the requests in the
+    /// order in which this method is called.
+    pub fn reserve(&self, count: u32) -> Duration {
+        if self.config.requests_per_second <= 0.0 {
+            return Duration::from_secs(0);
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        let now = state.start.elapsed();
+        let refilled = (now - state.last_refill).as_secs_f32() * self.config.requests_per_second;
+        state.tokens = (state.tokens + refilled).min(self.config.burst as f32);
+        state.last_refill = now;
+
+        state.tokens -= count as f32;
+        if state.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f32(-state.tokens / self.config.requests_per_second)
+        }
+    }
+
+    /// Block the current thread until `count` requests can be sent
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn acquire(&self, count: u32) {
+        let delay = self.reserve(count);
+        if delay > Duration::from_secs(0) {
+            log::debug!("Rate limit reached, waiting {} ms", delay.as_millis());
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_burst() {
+        let rate_limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: 10.0,
+            burst: 2,
+        });
+
+        assert_eq!(rate_limiter.reserve(1), Duration::from_secs(0));
+        assert_eq!(rate_limiter.reserve(1), Duration::from_secs(0));
+
+        let delay = rate_limiter.reserve(1);
+        assert!(delay > Duration::from_millis(50) && delay <= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_rate_limiter_queue() {
+        let rate_limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: 10.0,
+            burst: 0,
+        });
+
+        let first = rate_limiter.reserve(1);
+        let second = rate_limiter.reserve(1);
+        assert!(second > first);
+        assert!(second - first > Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_rate_limiter_batch() {
+        let rate_limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: 10.0,
+            burst: 5,
+        });
+
+        let delay = rate_limiter.reserve(10);
+        assert!(delay > Duration::from_millis(450) && delay <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_rate_limiter_disabled() {
+        let rate_limiter = RateLimiter::new(RateLimitConfig {
+            requests_per_second: 0.0,
+            burst: 0,
+        });
+
+        for _ in 0..100 {
+            assert_eq!(rate_limiter.reserve(1), Duration::from_secs(0));
+        }
+    }
+}
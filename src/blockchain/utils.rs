@@ -142,9 +142,9 @@ pub trait ElectrumLikeSync {
             .collect();
         let utxos_deps = utxos_deps(db, &txs_raw_in_db)?;
 
-        // download new txs and headers
+        // download new txs and headers. invalid txs are skipped and removed from `history_txs_id`
         let new_txs = maybe_await!(self.download_and_save_needed_raw_txs(
-            &history_txs_id,
+            &mut history_txs_id,
             &txs_raw_in_db,
             chunk_size,
             db
@@ -202,23 +202,27 @@ pub trait ElectrumLikeSync {
     }
 
     /// download txs identified by `history_txs_id` and theirs previous outputs if not already present in db
+    ///
+    /// with the `verify` feature the wallet txs are verified before being saved: the invalid ones
+    /// are not saved, and they are removed from `history_txs_id`
     fn download_and_save_needed_raw_txs<D: BatchDatabase>(
         &self,
-        history_txs_id: &HashSet<Txid>,
+        history_txs_id: &mut HashSet<Txid>,
         txs_raw_in_db: &HashMap<Txid, Transaction>,
         chunk_size: usize,
         db: &mut D,
     ) -> Result<Vec<Transaction>, Error> {
         let mut txs_downloaded = vec![];
         let txids_raw_in_db: HashSet<Txid> = txs_raw_in_db.keys().cloned().collect();
-        let txids_to_download: Vec<&Txid> = history_txs_id.difference(&txids_raw_in_db).collect();
+        let txids_to_download: Vec<Txid> = history_txs_id
+            .difference(&txids_raw_in_db)
+            .cloned()
+            .collect();
         if !txids_to_download.is_empty() {
             info!("got {} txs to download", txids_to_download.len());
-            txs_downloaded.extend(maybe_await!(self.download_and_save_in_chunks(
-                txids_to_download,
-                chunk_size,
-                db,
-            ))?);
+            txs_downloaded.extend(maybe_await!(
+                self.download_in_chunks(txids_to_download.iter().collect(), chunk_size)
+            )?);
             let mut prev_txids = HashSet::new();
             let mut txids_downloaded = HashSet::new();
             for tx in txs_downloaded.iter() {
@@ -233,11 +237,46 @@ pub trait ElectrumLikeSync {
             let prev_txs_to_download: Vec<&Txid> =
                 prev_txids.difference(&already_present).collect();
             info!("{} previous txs to download", prev_txs_to_download.len());
-            txs_downloaded.extend(maybe_await!(self.download_and_save_in_chunks(
-                prev_txs_to_download,
-                chunk_size,
-                db,
-            ))?);
+            let prev_txs_downloaded =
+                maybe_await!(self.download_in_chunks(prev_txs_to_download, chunk_size))?;
+
+            // now that all the previous txs are available, make sure that the wallet txs are valid
+            // before saving them
+            #[cfg(feature = "verify")]
+            {
+                let available: HashMap<Txid, &Transaction> = txs_downloaded
+                    .iter()
+                    .chain(prev_txs_downloaded.iter())
+                    .map(|tx| (tx.txid(), tx))
+                    .collect();
+                let get_prev_tx = |txid: &Txid| match available.get(txid) {
+                    Some(tx) => Ok(Some((*tx).clone())),
+                    None => db.get_raw_tx(txid),
+                };
+
+                let mut invalid_txids = HashSet::new();
+                for tx in txs_downloaded.iter() {
+                    match crate::wallet::verify::verify_tx_with(tx, get_prev_tx) {
+                        Ok(()) => {}
+                        Err(crate::wallet::verify::VerifyError::Global(e)) => return Err(*e),
+                        Err(e) => {
+                            error!("Skipping invalid tx {}: {:?}", tx.txid(), e);
+                            invalid_txids.insert(tx.txid());
+                        }
+                    }
+                }
+
+                txs_downloaded.retain(|tx| !invalid_txids.contains(&tx.txid()));
+                history_txs_id.retain(|txid| !invalid_txids.contains(txid));
+            }
+
+            let mut batch = db.begin_batch();
+            for tx in txs_downloaded.iter().chain(prev_txs_downloaded.iter()) {
+                batch.set_raw_tx(tx)?;
+            }
+            db.commit_batch(batch)?;
+
+            txs_downloaded.extend(prev_txs_downloaded);
         }
 
         Ok(txs_downloaded)
@@ -280,21 +319,15 @@ pub trait ElectrumLikeSync {
         Ok(txid_timestamp)
     }
 
-    fn download_and_save_in_chunks<D: BatchDatabase>(
+    fn download_in_chunks(
         &self,
         to_download: Vec<&Txid>,
         chunk_size: usize,
-        db: &mut D,
     ) -> Result<Vec<Transaction>, Error> {
         let mut txs_downloaded = vec![];
         for chunk in ChunksIterator::new(to_download.into_iter(), chunk_size) {
             let call_result: Vec<Transaction> =
                 maybe_await!(self.els_batch_transaction_get(chunk))?;
-            let mut batch = db.begin_batch();
-            for new_tx in call_result.iter() {
-                batch.set_raw_tx(new_tx)?;
-            }
-            db.commit_batch(batch)?;
             txs_downloaded.extend(call_result);
         }
 
@@ -371,6 +404,7 @@ fn save_transaction_details_and_utxos<D: BatchDatabase>(
         height,
         timestamp,
         fees: inputs_sum.saturating_sub(outputs_sum), /* if the tx is a coinbase, fees would be negative */
+        recipients: vec![],
     };
     updates.set_tx(&tx_details)?;
 
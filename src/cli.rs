@@ -57,7 +57,7 @@
 //!
 //! let network = Network::from_str(cli_opt.network.as_str()).unwrap_or(Network::Testnet);
 //!
-//! let descriptor = cli_opt.descriptor.as_str();
+//! let descriptor = cli_opt.descriptor.as_deref().unwrap();
 //! let change_descriptor = cli_opt.change_descriptor.as_deref();
 //!
 //! let database = MemoryDatabase::new();
@@ -66,12 +66,14 @@
 //!     Some(base_url) => AnyBlockchainConfig::Esplora(EsploraBlockchainConfig {
 //!         base_url: base_url.to_string(),
 //!         concurrency: Some(cli_opt.esplora_concurrency),
+//!         rate_limit: None,
 //!     }),
 //!     None => AnyBlockchainConfig::Electrum(ElectrumBlockchainConfig {
 //!         url: cli_opt.electrum,
 //!         socks5: cli_opt.proxy,
 //!         retry: 3,
 //!         timeout: 5,
+//!         rate_limit: None,
 //!     }),
 //! };
 //!
@@ -85,26 +87,49 @@
 //!
 //! let wallet = Arc::new(wallet);
 //!
-//! let result = cli::handle_wallet_subcommand(&wallet, cli_opt.subcommand).unwrap();
-//! println!("{}", serde_json::to_string_pretty(&result).unwrap());
+//! let result = cli::handle_wallet_subcommand(&wallet, cli_opt.subcommand);
+//! println!("{}", cli::format_output(&result, cli_opt.json));
 //! ```
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process::Command;
 use std::str::FromStr;
+use std::time::Duration;
 
+use clap::ArgMatches;
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
+use zeroize::Zeroize;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, LevelFilter};
 
-use bitcoin::consensus::encode::{deserialize, serialize, serialize_hex};
+use bitcoin::consensus::encode::{deserialize, serialize_hex};
 use bitcoin::hashes::hex::FromHex;
-use bitcoin::util::psbt::PartiallySignedTransaction;
-use bitcoin::{Address, OutPoint, Script, Txid};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::bip32;
+use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+use bitcoin::{Address, Network, OutPoint, Script, Txid};
 
-use crate::blockchain::log_progress;
+use miniscript::Segwitv0;
+
+use crate::blockchain::{log_progress, noop_progress, BlockchainMarker};
+use crate::database::BatchDatabase;
+use crate::descriptor::{add_checksum, ToWalletDescriptor};
 use crate::error::Error;
-use crate::types::KeychainKind;
+use crate::keys::bip39::{Mnemonic, MnemonicOptions, MnemonicType, MnemonicWithPassphrase, Seed};
+use crate::keys::{GeneratableKey, GeneratedKey};
+use crate::psbt;
+use crate::types::{KeychainKind, TransactionDetails};
+use crate::wallet::airgap::{UrDecoder, UrEncoder, MAX_SEQ_LEN};
+use crate::wallet::export::CoreImport;
+use crate::wallet::signer::SignOptions;
+use crate::wallet::tx_builder::TxOrdering;
 use crate::{FeeRate, TxBuilder, Wallet};
 
 /// Wallet global options and sub-command
@@ -131,10 +156,15 @@ use crate::{FeeRate, TxBuilder, Wallet};
 /// let expected_wallet_opt = WalletOpt {
 ///         network: "testnet".to_string(),
 ///         wallet: "main".to_string(),
+///         config: None,
+///         database_path: None,
+///         save_profile: false,
 ///         proxy: None,
-///         descriptor: "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/44'/1'/0'/0/*)".to_string(),
+///         descriptor: Some("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/44'/1'/0'/0/*)".to_string()),
 ///         change_descriptor: None,
 ///         log_level: "info".to_string(),
+///         json: false,
+///         qr: false,
 ///         #[cfg(feature = "esplora")]
 ///         esplora: None,
 ///         #[cfg(feature = "esplora")]
@@ -169,19 +199,37 @@ pub struct WalletOpt {
         default_value = "main"
     )]
     pub wallet: String,
+    /// Sets the path of the configuration file, which provides the default value of the options.
+    /// Defaults to `~/.bdk/config.toml`
+    #[structopt(name = "CONFIG", long = "config")]
+    pub config: Option<String>,
+    /// Sets the path of the database
+    #[structopt(name = "DATABASE_PATH", long = "database_path")]
+    pub database_path: Option<String>,
+    /// Saves the options of the wallet in a profile named after the wallet, so that they don't
+    /// have to be provided again
+    #[structopt(long = "save_profile", requires = "DESCRIPTOR")]
+    pub save_profile: bool,
     #[cfg(feature = "electrum")]
     /// Sets the SOCKS5 proxy for the Electrum client
     #[structopt(name = "PROXY_SERVER:PORT", short = "p", long = "proxy")]
     pub proxy: Option<String>,
-    /// Sets the descriptor to use for the external addresses
-    #[structopt(name = "DESCRIPTOR", short = "d", long = "descriptor", required = true)]
-    pub descriptor: String,
+    /// Sets the descriptor to use for the external addresses. When missing, the options are loaded
+    /// from the profile of the wallet
+    #[structopt(name = "DESCRIPTOR", short = "d", long = "descriptor")]
+    pub descriptor: Option<String>,
     /// Sets the descriptor to use for internal addresses
     #[structopt(name = "CHANGE_DESCRIPTOR", short = "c", long = "change_descriptor")]
     pub change_descriptor: Option<String>,
     /// Sets the logging level filter (off, error, warn, info, debug, trace)
     #[structopt(long = "log_level", short = "l", default_value = "info")]
     pub log_level: String,
+    /// Prints the results, and the errors, as compact JSON on a single line
+    #[structopt(long = "json")]
+    pub json: bool,
+    /// Also renders the addresses and the PSBTs returned by the command as QR codes. PSBTs too large for a single QR code are shown as an animated sequence of `crypto-psbt` URs
+    #[structopt(long = "qr")]
+    pub qr: bool,
     #[cfg(feature = "esplora")]
     /// Use the esplora server if given as parameter
     #[structopt(name = "ESPLORA_URL", short = "e", long = "esplora")]
@@ -208,6 +256,271 @@ pub struct WalletOpt {
     pub subcommand: WalletSubCommand,
 }
 
+impl WalletOpt {
+    /// Return a profile with the options of the wallet, or `None` if the descriptor is missing
+    pub fn to_profile(&self) -> Option<WalletProfile> {
+        Some(WalletProfile {
+            network: self.network.clone(),
+            descriptor: self.descriptor.clone()?,
+            change_descriptor: self.change_descriptor.clone(),
+            database_path: self.database_path.clone(),
+            #[cfg(feature = "electrum")]
+            electrum: Some(self.electrum.clone()),
+            #[cfg(not(feature = "electrum"))]
+            electrum: None,
+            #[cfg(feature = "electrum")]
+            proxy: self.proxy.clone(),
+            #[cfg(not(feature = "electrum"))]
+            proxy: None,
+            #[cfg(feature = "esplora")]
+            esplora: self.esplora.clone(),
+            #[cfg(not(feature = "esplora"))]
+            esplora: None,
+        })
+    }
+
+    /// Replace the options of the wallet with the ones stored in `profile`
+    pub fn apply_profile(&mut self, profile: &WalletProfile) {
+        self.network = profile.network.clone();
+        self.descriptor = Some(profile.descriptor.clone());
+        self.change_descriptor = profile.change_descriptor.clone();
+        self.database_path = profile.database_path.clone();
+        #[cfg(feature = "electrum")]
+        {
+            if let Some(electrum) = &profile.electrum {
+                self.electrum = electrum.clone();
+            }
+            self.proxy = profile.proxy.clone();
+        }
+        #[cfg(feature = "esplora")]
+        {
+            self.esplora = profile.esplora.clone();
+        }
+    }
+
+    /// Resolve the options of the wallet using the profile named after it
+    ///
+    /// When the descriptor is not given on the command line, every option is loaded from the
+    /// profile, and an error is returned if it doesn't exist. When `--save_profile` is set, the
+    /// options are stored in the profile instead, and `profiles` should then be saved by the
+    /// caller.
+    pub fn resolve_profile(&mut self, profiles: &mut WalletProfiles) -> Result<(), Error> {
+        match self.to_profile() {
+            Some(profile) if self.save_profile => {
+                profiles.insert(self.wallet.clone(), profile);
+            }
+            Some(_) => {}
+            None => {
+                let profile = profiles.get(&self.wallet).ok_or_else(|| {
+                    Error::Generic(format!(
+                        "Missing descriptor and no profile found for the wallet `{}`",
+                        self.wallet
+                    ))
+                })?;
+                self.apply_profile(profile);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Options needed to open a wallet, stored with a name in [`WalletProfiles`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalletProfile {
+    /// Network of the wallet
+    pub network: String,
+    /// Descriptor used for the external addresses
+    pub descriptor: String,
+    /// Descriptor used for the internal addresses
+    #[serde(default)]
+    pub change_descriptor: Option<String>,
+    /// Path of the database
+    #[serde(default)]
+    pub database_path: Option<String>,
+    /// Electrum server
+    #[serde(default)]
+    pub electrum: Option<String>,
+    /// SOCKS5 proxy for the Electrum client
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Esplora server
+    #[serde(default)]
+    pub esplora: Option<String>,
+}
+
+/// Named wallet profiles, stored in a JSON file
+///
+/// The profiles are selected with the `--wallet` option. See [`WalletOpt::resolve_profile`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WalletProfiles(BTreeMap<String, WalletProfile>);
+
+impl WalletProfiles {
+    /// Load the profiles from a file, returning no profiles if it doesn't exist
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(WalletProfiles::default()),
+            Err(e) => Err(Error::Generic(format!(
+                "Unable to read the profiles: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Save the profiles to a file
+    ///
+    /// The descriptors can contain private keys, so on unix the file is only readable and
+    /// writable by its owner.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        write_private_file(path, &serde_json::to_string_pretty(self)?)
+            .map_err(|e| Error::Generic(format!("Unable to save the profiles: {}", e)))
+    }
+
+    /// Return the profile called `name`
+    pub fn get(&self, name: &str) -> Option<&WalletProfile> {
+        self.0.get(name)
+    }
+
+    /// Add or replace the profile called `name`, returning the old one
+    pub fn insert(&mut self, name: String, profile: WalletProfile) -> Option<WalletProfile> {
+        self.0.insert(name, profile)
+    }
+
+    /// Remove the profile called `name`
+    pub fn remove(&mut self, name: &str) -> Option<WalletProfile> {
+        self.0.remove(name)
+    }
+
+    /// Return the names of the profiles
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.0.keys()
+    }
+}
+
+// Write `content` to the file at `path`, making it only readable and writable by its owner on unix
+fn write_private_file<P: AsRef<Path>>(path: P, content: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    // the mode is only used when the file is created, so the permissions of an existing file are
+    // also updated
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())
+}
+
+/// Default options of the command line interface, stored in a TOML file
+///
+/// Every field is optional, and is only used when the corresponding option is not given on the
+/// command line. The fee settings are used by the commands that create a transaction.
+///
+/// ## Example
+///
+/// ```toml
+/// network = "bitcoin"
+/// electrum = "ssl://electrum.blockstream.info:50002"
+/// proxy = "127.0.0.1:9050"
+/// database_path = "/home/satoshi/.bdk/wallet.sled"
+/// fee_rate = 2.0
+/// max_fee_rate = 100.0
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WalletConfig {
+    /// Network of the wallet
+    pub network: Option<String>,
+    /// Electrum server
+    pub electrum: Option<String>,
+    /// SOCKS5 proxy for the Electrum client
+    pub proxy: Option<String>,
+    /// Esplora server
+    pub esplora: Option<String>,
+    /// Concurrency of requests made to the esplora server
+    pub esplora_concurrency: Option<u8>,
+    /// Path of the database
+    pub database_path: Option<String>,
+    /// Fee rate in sat/vbyte used when a command doesn't set it
+    pub fee_rate: Option<f32>,
+    /// Maximum fee rate in sat/vbyte of the transactions, see [`Wallet::set_max_fee_rate`]
+    pub max_fee_rate: Option<f32>,
+}
+
+impl WalletConfig {
+    /// Load the configuration from a file, returning an empty configuration if it doesn't exist
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| Error::Generic(format!("Invalid configuration file: {}", e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(WalletConfig::default()),
+            Err(e) => Err(Error::Generic(format!(
+                "Unable to read the configuration: {}",
+                e
+            ))),
+        }
+    }
+
+    /// Use the values of the configuration for the options that are not given on the command
+    /// line
+    ///
+    /// `matches` must be the ones `opt` was parsed from, to tell the options that were given
+    /// explicitly from the ones that have a default value.
+    pub fn apply(&self, opt: &mut WalletOpt, matches: &ArgMatches) {
+        let is_default = |name: &str| matches.occurrences_of(name) == 0;
+
+        if let (Some(network), true) = (&self.network, is_default("NETWORK")) {
+            opt.network = network.clone();
+        }
+        if opt.database_path.is_none() {
+            opt.database_path = self.database_path.clone();
+        }
+        #[cfg(feature = "electrum")]
+        {
+            if let (Some(electrum), true) = (&self.electrum, is_default("SERVER:PORT")) {
+                opt.electrum = electrum.clone();
+            }
+            if opt.proxy.is_none() {
+                opt.proxy = self.proxy.clone();
+            }
+        }
+        #[cfg(feature = "esplora")]
+        {
+            if opt.esplora.is_none() {
+                opt.esplora = self.esplora.clone();
+            }
+            if let (Some(concurrency), true) =
+                (self.esplora_concurrency, is_default("ESPLORA_CONCURRENCY"))
+            {
+                opt.esplora_concurrency = concurrency;
+            }
+        }
+
+        self.apply_subcommand(&mut opt.subcommand);
+    }
+
+    /// Use the fee rate of the configuration for a sub-command that doesn't set it
+    pub fn apply_subcommand(&self, subcommand: &mut WalletSubCommand) {
+        match subcommand {
+            WalletSubCommand::CreateTx { fee_rate, .. }
+            | WalletSubCommand::CancelTx { fee_rate, .. } => {
+                if fee_rate.is_none() {
+                    *fee_rate = self.fee_rate;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 /// Wallet sub-command
 ///
 /// A [structopt](https://docs.rs/crate/structopt) enum that parses wallet sub-command arguments from
@@ -269,6 +582,18 @@ pub struct WalletOpt {
 pub enum WalletSubCommand {
     /// Generates a new external address
     GetNewAddress,
+    /// Lists the addresses of the wallet at a range of indexes, without marking them as used
+    ListAddresses {
+        /// Index of the first address
+        #[structopt(name = "FROM", long = "from", default_value = "0")]
+        from: u32,
+        /// Number of addresses to list
+        #[structopt(name = "COUNT", long = "count", default_value = "20")]
+        count: u32,
+        /// Lists the addresses of the internal descriptor
+        #[structopt(long = "internal")]
+        internal: bool,
+    },
     /// Syncs with the chosen blockchain server
     Sync {
         /// max addresses to consider
@@ -279,12 +604,21 @@ pub enum WalletSubCommand {
     ListUnspent,
     /// Lists all the incoming and outgoing transactions of the wallet
     ListTransactions,
+    /// Returns the details of a transaction of the wallet
+    GetTx {
+        /// TXID of the transaction
+        #[structopt(name = "TXID", short = "txid", long = "txid")]
+        txid: String,
+        /// Includes the raw transaction in the output
+        #[structopt(long = "raw")]
+        include_raw: bool,
+    },
     /// Returns the current wallet balance
     GetBalance,
     /// Creates a new unsigned transaction
     CreateTx {
         /// Adds a recipient to the transaction
-        #[structopt(name = "ADDRESS:SAT", long = "to", required = true, parse(try_from_str = parse_recipient))]
+        #[structopt(name = "ADDRESS:SAT", long = "to", required_unless = "DRAIN_TO", parse(try_from_str = parse_recipient))]
         recipients: Vec<(Script, u64)>,
         /// Sends all the funds (or all the selected utxos). Requires only one recipients of value 0
         #[structopt(short = "all", long = "send_all")]
@@ -310,6 +644,18 @@ pub enum WalletSubCommand {
         /// Selects which policy should be used to satisfy the internal descriptor
         #[structopt(name = "INT_POLICY", long = "internal_policy")]
         internal_policy: Option<String>,
+        /// Sends all the selected utxos, or the whole wallet if none is selected, minus the fee to an address, without creating a change output
+        #[structopt(name = "DRAIN_TO", long = "drain_to", conflicts_with_all = &["ADDRESS:SAT", "send_all"], parse(try_from_str = parse_address))]
+        drain_to: Option<Script>,
+        /// Adds an `OP_RETURN` output with the given data, in hex
+        #[structopt(name = "HEX_DATA", long = "add_data", conflicts_with = "DRAIN_TO")]
+        add_data: Option<String>,
+        /// Sets the nLockTime of the transaction, as a block height or a unix timestamp
+        #[structopt(name = "LOCKTIME", long = "locktime")]
+        locktime: Option<u32>,
+        /// Sets the ordering of the inputs and outputs: `shuffle` (default), `untouched` or `bip69`
+        #[structopt(name = "ORDERING", long = "ordering", parse(try_from_str = parse_ordering))]
+        ordering: Option<TxOrdering>,
     },
     /// Bumps the fees of an RBF transaction
     BumpFee {
@@ -332,13 +678,46 @@ pub enum WalletSubCommand {
         #[structopt(name = "SATS_VBYTE", short = "fee", long = "fee_rate")]
         fee_rate: f32,
     },
+    /// Cancels an RBF transaction, replacing it with one that sends its inputs back to the wallet
+    CancelTx {
+        /// TXID of the transaction to cancel
+        #[structopt(name = "TXID", short = "txid", long = "txid")]
+        txid: String,
+        /// Make a PSBT that can be signed by offline signers and hardware wallets. Forces the addition of `non_witness_utxo` and more details to let the signer identify the change output.
+        #[structopt(long = "offline_signer")]
+        offline_signer: bool,
+        /// The fee rate of the replacement in sat/vbyte. Defaults to the lowest fee rate that can replace the transaction
+        #[structopt(name = "SATS_VBYTE", short = "fee", long = "fee_rate")]
+        fee_rate: Option<f32>,
+    },
+    /// Keeps syncing the wallet on an interval, reporting the new transactions, the confirmations and the reorgs
+    Watch {
+        /// Seconds to wait between two syncs
+        #[structopt(name = "SECONDS", short = "i", long = "interval", default_value = "30")]
+        interval: u64,
+        /// Runs a command for every event, with the details in the `BDK_EVENT`, `BDK_TXID` and `BDK_EVENT_JSON` environment variables
+        #[structopt(name = "COMMAND", long = "exec")]
+        exec: Option<String>,
+        /// Stops after reporting this many events. Defaults to watching the wallet forever
+        #[structopt(name = "EVENTS", long = "max_events")]
+        max_events: Option<usize>,
+        /// max addresses to consider
+        #[structopt(short = "v", long = "max_addresses")]
+        max_addresses: Option<u32>,
+    },
     /// Returns the available spending policies for the descriptor
     Policies,
     /// Returns the public version of the wallet's descriptor(s)
     PublicDescriptor,
+    /// Returns the wallet's descriptors in the format accepted by the `importdescriptors` RPC of Bitcoin Core, to mirror the wallet into a watch-only Core wallet
+    ExportCore {
+        /// Sets the UNIX timestamp from which Core should rescan the chain. Defaults to the time of the oldest transaction of the wallet
+        #[structopt(name = "TIMESTAMP", long = "birthday")]
+        birthday: Option<u64>,
+    },
     /// Signs and tries to finalize a PSBT
     Sign {
-        /// Sets the PSBT to sign
+        /// Sets the PSBT to sign, as base64 or as `qr:<FILE>` to read it from scanned QR codes, one per line
         #[structopt(name = "BASE64_PSBT", long = "psbt")]
         psbt: String,
         /// Assume the blockchain has reached a specific height. This affects the transaction finalization, if there are timelocks in the descriptor
@@ -347,7 +726,7 @@ pub enum WalletSubCommand {
     },
     /// Broadcasts a transaction to the network. Takes either a raw transaction or a PSBT to extract
     Broadcast {
-        /// Sets the PSBT to sign
+        /// Sets the PSBT to sign, as base64 or as `qr:<FILE>` to read it from scanned QR codes, one per line
         #[structopt(
             name = "BASE64_PSBT",
             long = "psbt",
@@ -366,13 +745,13 @@ pub enum WalletSubCommand {
     },
     /// Extracts a raw transaction from a PSBT
     ExtractPsbt {
-        /// Sets the PSBT to extract
+        /// Sets the PSBT to extract, as base64 or as `qr:<FILE>` to read it from scanned QR codes, one per line
         #[structopt(name = "BASE64_PSBT", long = "psbt")]
         psbt: String,
     },
     /// Finalizes a PSBT
     FinalizePsbt {
-        /// Sets the PSBT to finalize
+        /// Sets the PSBT to finalize, as base64 or as `qr:<FILE>` to read it from scanned QR codes, one per line
         #[structopt(name = "BASE64_PSBT", long = "psbt")]
         psbt: String,
         /// Assume the blockchain has reached a specific height
@@ -381,15 +760,60 @@ pub enum WalletSubCommand {
     },
     /// Combines multiple PSBTs into one
     CombinePsbt {
-        /// Add one PSBT to combine. This option can be repeated multiple times, one for each PSBT
+        /// Add one PSBT to combine, as base64 or as `qr:<FILE>`. This option can be repeated multiple times, one for each PSBT
         #[structopt(name = "BASE64_PSBT", long = "psbt", required = true)]
         psbt: Vec<String>,
     },
+    /// Generates a new BIP39 mnemonic and its master extended private key
+    GenerateKey {
+        /// Number of words of the mnemonic: 12, 15, 18, 21 or 24
+        #[structopt(name = "WORD_COUNT", long = "word_count", default_value = "24")]
+        word_count: usize,
+        /// Sets a passphrase to protect the mnemonic
+        #[structopt(name = "PASSPHRASE", long = "passphrase")]
+        passphrase: Option<String>,
+    },
+    /// Generates the external and internal descriptors of a new wallet, from a new or an existing mnemonic
+    GenerateDescriptor {
+        /// Template of the descriptors: `44` (P2PKH), `49` (P2WPKH-P2SH), `84` (P2WPKH) or `multi` (P2WSH sorted multisig)
+        #[structopt(name = "TEMPLATE", long = "template", default_value = "84")]
+        template: String,
+        /// Uses an existing mnemonic instead of generating a new one
+        #[structopt(name = "MNEMONIC", long = "mnemonic")]
+        mnemonic: Option<String>,
+        /// Number of words of the mnemonic generated: 12, 15, 18, 21 or 24
+        #[structopt(name = "WORD_COUNT", long = "word_count", default_value = "24")]
+        word_count: usize,
+        /// Sets the passphrase of the mnemonic
+        #[structopt(name = "PASSPHRASE", long = "passphrase")]
+        passphrase: Option<String>,
+        /// Number of signatures required by the `multi` template
+        #[structopt(
+            name = "THRESHOLD",
+            long = "threshold",
+            required_if("TEMPLATE", "multi")
+        )]
+        threshold: Option<usize>,
+        /// Adds the account xpub of a cosigner to the `multi` template, with its origin like `[fingerprint/48'/0'/0'/2']xpub...`. This option can be repeated multiple times, one for each cosigner
+        #[structopt(name = "XPUB", long = "xpub")]
+        xpubs: Vec<String>,
+    },
     /// Put any extra arguments into this Vec
     #[structopt(external_subcommand)]
     Other(Vec<String>),
 }
 
+impl WalletSubCommand {
+    /// Return whether the sub-command needs a wallet, or can be executed with
+    /// [`handle_key_subcommand`]
+    pub fn needs_wallet(&self) -> bool {
+        !matches!(
+            self,
+            WalletSubCommand::GenerateKey { .. } | WalletSubCommand::GenerateDescriptor { .. }
+        )
+    }
+}
+
 fn parse_recipient(s: &str) -> Result<(Script, u64), String> {
     let parts: Vec<_> = s.split(':').collect();
     if parts.len() != 2 {
@@ -412,6 +836,523 @@ fn parse_outpoint(s: &str) -> Result<OutPoint, String> {
     OutPoint::from_str(s).map_err(|e| format!("{:?}", e))
 }
 
+fn parse_address(s: &str) -> Result<Script, String> {
+    Address::from_str(s)
+        .map(|addr| addr.script_pubkey())
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn parse_ordering(s: &str) -> Result<TxOrdering, String> {
+    match s {
+        "shuffle" => Ok(TxOrdering::Shuffle),
+        "untouched" => Ok(TxOrdering::Untouched),
+        "bip69" => Ok(TxOrdering::BIP69Lexicographic),
+        _ => Err(format!("Invalid ordering `{}`", s)),
+    }
+}
+
+/// Format the result of [`handle_wallet_subcommand`] to be printed
+///
+/// In JSON mode the result is printed on a single line, and errors are reported as an object with
+/// an `error` field, so that the output can always be parsed by other programs. Otherwise the
+/// result is pretty-printed and errors are reported as text.
+pub fn format_output(result: &Result<serde_json::Value, Error>, json: bool) -> String {
+    match (result, json) {
+        (Ok(value), true) => value.to_string(),
+        (Ok(value), false) => serde_json::to_string_pretty(value).unwrap(),
+        (Err(e), true) => json!({ "error": e.to_string() }).to_string(),
+        (Err(e), false) => format!("Error: {}", e),
+    }
+}
+
+/// Maximum length of the fragments of a PSBT displayed as an animated QR code
+pub const QR_MAX_FRAGMENT_LEN: usize = 200;
+
+/// Prefix of the PSBT arguments that read the PSBT from scanned QR codes
+pub const QR_INPUT_PREFIX: &str = "qr:";
+
+/// Maximum number of parts of an animated QR code read by [`read_qr_input`]
+///
+/// The parts of a fountain code are endless, so this bounds the work done on an input that never
+/// completes the PSBT.
+pub const QR_MAX_PARTS: usize = 2 * MAX_SEQ_LEN;
+
+/// Render `data` as a QR code made of Unicode blocks, to print it on a terminal
+///
+/// The colors are inverted, so that the QR code can be scanned from terminals with a dark
+/// background.
+pub fn render_qr(data: &str) -> Result<String, Error> {
+    let code = QrCode::new(data.as_bytes())
+        .map_err(|e| Error::Generic(format!("Unable to render the QR code: {}", e)))?;
+
+    Ok(code
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+/// Return the QR codes to display for the result of a command
+///
+/// Addresses are encoded as BIP21 URIs and PSBTs as upper-case `crypto-psbt` URs, to keep the QR
+/// codes small. Every element of the returned vector is a sequence of frames: PSBTs that don't fit
+/// in [`QR_MAX_FRAGMENT_LEN`] bytes are split in more than one frame, which should be shown in a
+/// loop as an animated QR code.
+pub fn qr_frames(result: &serde_json::Value) -> Result<Vec<Vec<String>>, Error> {
+    let mut frames = vec![];
+
+    if let Some(address) = result.get("address").and_then(|a| a.as_str()) {
+        frames.push(vec![format!("bitcoin:{}", address)]);
+    }
+    if let Some(psbt) = result.get("psbt").and_then(|p| p.as_str()) {
+        let psbt = psbt::from_base64(psbt)?;
+        let mut encoder = UrEncoder::new(&psbt, QR_MAX_FRAGMENT_LEN)
+            .map_err(|e| Error::Generic(e.to_string()))?;
+        frames.push(
+            (0..encoder.seq_len())
+                .map(|_| encoder.next_part().to_uppercase())
+                .collect(),
+        );
+    }
+
+    Ok(frames)
+}
+
+/// Read a PSBT from the payloads of scanned QR codes, one per line
+///
+/// Every line can contain a base64 PSBT or a `crypto-psbt` UR, like the output of `zbarcam --raw`
+/// or of other tools that decode QR codes from a camera or an image. The parts of an animated QR
+/// code are collected until the PSBT can be rebuilt: they can be received in any order, and
+/// duplicates are ignored. The parts are split in at most [`MAX_SEQ_LEN`] fragments, and an error
+/// is returned after reading more than [`QR_MAX_PARTS`] parts.
+pub fn read_qr_input<R: BufRead>(reader: R) -> Result<PSBT, Error> {
+    let mut decoder = UrDecoder::new();
+    let mut parts = 0;
+
+    for line in reader.lines() {
+        let line =
+            line.map_err(|e| Error::Generic(format!("Unable to read the QR input: {}", e)))?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        } else if line.to_lowercase().starts_with("ur:") {
+            parts += 1;
+            if parts > QR_MAX_PARTS {
+                return Err(Error::Generic(format!(
+                    "The PSBT wasn't complete after {} QR parts",
+                    QR_MAX_PARTS
+                )));
+            }
+
+            let part = decoder
+                .receive_part(line)
+                .map_err(|e| Error::Generic(e.to_string()))?;
+            if let Some(psbt) = part {
+                return Ok(psbt);
+            }
+            debug!("QR input progress: {:.0}%", decoder.progress() * 100.0);
+        } else {
+            return psbt::from_base64(line);
+        }
+    }
+
+    Err(Error::Generic(
+        "The QR input ended before the PSBT was complete".into(),
+    ))
+}
+
+// Parse a PSBT argument, either in base64 or as `qr:<FILE>`, where `-` reads from stdin
+fn parse_psbt_arg(arg: &str) -> Result<PSBT, Error> {
+    if !arg.starts_with(QR_INPUT_PREFIX) {
+        return psbt::from_base64(arg);
+    }
+
+    match &arg[QR_INPUT_PREFIX.len()..] {
+        "-" => {
+            let stdin = io::stdin();
+            let reader = stdin.lock();
+            read_qr_input(reader)
+        }
+        path => {
+            let file = fs::File::open(path)
+                .map_err(|e| Error::Generic(format!("Unable to open {}: {}", path, e)))?;
+            read_qr_input(io::BufReader::new(file))
+        }
+    }
+}
+
+/// Change of the transactions of a wallet, reported by a [`WalletWatcher`]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WalletEvent {
+    /// A new transaction, either unconfirmed or already confirmed
+    NewTransaction(TransactionDetails),
+    /// A transaction has been confirmed, or confirmed at a different height after a reorg
+    Confirmed(TransactionDetails),
+    /// A transaction that was confirmed is unconfirmed again after a reorg
+    Unconfirmed(TransactionDetails),
+    /// A transaction is not part of the wallet anymore, because it was replaced or dropped
+    Removed {
+        /// Id of the transaction
+        txid: Txid,
+    },
+}
+
+impl WalletEvent {
+    /// Return the id of the transaction
+    pub fn txid(&self) -> Txid {
+        match self {
+            WalletEvent::NewTransaction(details)
+            | WalletEvent::Confirmed(details)
+            | WalletEvent::Unconfirmed(details) => details.txid,
+            WalletEvent::Removed { txid } => *txid,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            WalletEvent::NewTransaction(_) => "new_transaction",
+            WalletEvent::Confirmed(_) => "confirmed",
+            WalletEvent::Unconfirmed(_) => "unconfirmed",
+            WalletEvent::Removed { .. } => "removed",
+        }
+    }
+}
+
+/// Keep track of the transactions of a wallet to report their changes as [`WalletEvent`]s
+#[derive(Debug, Clone, Default)]
+pub struct WalletWatcher {
+    known: HashMap<Txid, Option<u32>>,
+}
+
+impl WalletWatcher {
+    /// Create a watcher that only reports the changes after the current state of `wallet`
+    pub fn new<B, D>(wallet: &Wallet<B, D>) -> Result<Self, Error>
+    where
+        B: BlockchainMarker,
+        D: BatchDatabase,
+    {
+        let mut watcher = WalletWatcher::default();
+        watcher.poll(wallet)?;
+
+        Ok(watcher)
+    }
+
+    /// Return the changes of the transactions of `wallet` since the last call
+    ///
+    /// This only reads the internal database, which has to be synced first.
+    pub fn poll<B, D>(&mut self, wallet: &Wallet<B, D>) -> Result<Vec<WalletEvent>, Error>
+    where
+        B: BlockchainMarker,
+        D: BatchDatabase,
+    {
+        Ok(self.update(wallet.list_transactions(false)?))
+    }
+
+    /// Return the changes between `transactions`, the current list of transactions, and the
+    /// previous one
+    pub fn update(&mut self, transactions: Vec<TransactionDetails>) -> Vec<WalletEvent> {
+        let mut events = vec![];
+        let mut current = HashMap::new();
+
+        for details in transactions {
+            current.insert(details.txid, details.height);
+            match self.known.get(&details.txid) {
+                None => events.push(WalletEvent::NewTransaction(details)),
+                Some(height) if *height == details.height => {}
+                Some(_) if details.height.is_some() => events.push(WalletEvent::Confirmed(details)),
+                Some(_) => events.push(WalletEvent::Unconfirmed(details)),
+            }
+        }
+        events.extend(
+            self.known
+                .keys()
+                .filter(|txid| !current.contains_key(*txid))
+                .map(|txid| WalletEvent::Removed { txid: *txid }),
+        );
+
+        self.known = current;
+        events
+    }
+}
+
+/// Run the command `hook` with a shell for `event`
+///
+/// The type of the event and the id of the transaction are passed in the `BDK_EVENT` and
+/// `BDK_TXID` environment variables, and the whole event serialized as JSON in `BDK_EVENT_JSON`.
+pub fn run_hook(hook: &str, event: &WalletEvent) -> Result<(), Error> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+
+    let status = command
+        .arg(hook)
+        .env("BDK_EVENT", event.name())
+        .env("BDK_TXID", event.txid().to_string())
+        .env("BDK_EVENT_JSON", serde_json::to_string(event)?)
+        .status()
+        .map_err(|e| Error::Generic(format!("Unable to run the hook: {}", e)))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Generic(format!("The hook failed with {}", status)))
+    }
+}
+
+/// Keep syncing `wallet` every `interval`, calling `on_event` for every change of its
+/// transactions
+///
+/// `on_event` returns whether the wallet should still be watched: this returns as soon as it
+/// returns `false` or fails. Failed syncs are logged and retried at the next interval.
+#[maybe_async]
+pub fn watch_wallet<C, D, F>(
+    wallet: &Wallet<C, D>,
+    interval: Duration,
+    max_addresses: Option<u32>,
+    mut on_event: F,
+) -> Result<(), Error>
+where
+    C: crate::blockchain::Blockchain,
+    D: BatchDatabase,
+    F: FnMut(&WalletEvent) -> Result<bool, Error>,
+{
+    let mut watcher = WalletWatcher::new(wallet)?;
+
+    loop {
+        match maybe_await!(wallet.sync(noop_progress(), max_addresses)) {
+            Ok(()) => {
+                for event in watcher.poll(wallet)? {
+                    if !on_event(&event)? {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(e) => error!("Unable to sync the wallet: {}", e),
+        }
+
+        #[cfg(not(feature = "async-interface"))]
+        std::thread::sleep(interval);
+        #[cfg(all(feature = "async-interface", not(target_arch = "wasm32")))]
+        tokio::time::delay_for(interval).await;
+        // TODO: there's no timer we can use on wasm32 yet, stop instead of syncing in a loop
+        #[cfg(all(feature = "async-interface", target_arch = "wasm32"))]
+        return Err(Error::Generic(
+            "Watching a wallet is not supported on wasm32 yet".into(),
+        ));
+    }
+}
+
+/// Execute the `watch` sub-command with a given [`Wallet`]
+///
+/// The sub-command reports an event every time the transactions of the wallet change, so it
+/// can't be handled by [`handle_wallet_subcommand`], which only returns a single result. Instead,
+/// every [`WalletEvent`] serialized as JSON is passed to `output`, so that it can be formatted
+/// like the other results, for example with [`format_output`]. The result is returned once
+/// `--max_events` events have been reported.
+#[maybe_async]
+pub fn handle_watch_subcommand<C, D, F>(
+    wallet: &Wallet<C, D>,
+    wallet_subcommand: WalletSubCommand,
+    mut output: F,
+) -> Result<serde_json::Value, Error>
+where
+    C: crate::blockchain::Blockchain,
+    D: BatchDatabase,
+    F: FnMut(serde_json::Value),
+{
+    let (interval, exec, max_addresses, max_events) = match wallet_subcommand {
+        WalletSubCommand::Watch {
+            interval,
+            exec,
+            max_addresses,
+            max_events,
+        } => (interval, exec, max_addresses, max_events),
+        _ => return Err(Error::Generic("Not a `watch` sub-command".into())),
+    };
+
+    let mut count = 0;
+    let on_event = |event: &WalletEvent| -> Result<bool, Error> {
+        output(serde_json::to_value(event)?);
+        if let Some(hook) = &exec {
+            if let Err(e) = run_hook(hook, event) {
+                error!("{}", e);
+            }
+        }
+
+        count += 1;
+        Ok(max_events.map_or(true, |max_events| count < max_events))
+    };
+    maybe_await!(watch_wallet(
+        wallet,
+        Duration::from_secs(interval),
+        max_addresses,
+        on_event
+    ))?;
+
+    Ok(json!({ "events": count }))
+}
+
+// Restore `phrase` if given, or generate a new mnemonic of `word_count` words otherwise
+fn get_mnemonic(
+    phrase: Option<&str>,
+    word_count: usize,
+    passphrase: Option<String>,
+) -> Result<MnemonicWithPassphrase, Error> {
+    let mut options = MnemonicOptions::new();
+    if let Some(passphrase) = passphrase {
+        options = options.passphrase(passphrase);
+    }
+
+    match phrase {
+        Some(phrase) => Ok(options.restore(phrase)?),
+        None => {
+            let mnemonic_type = MnemonicType::for_word_count(word_count)
+                .map_err(|_| Error::Generic(format!("Invalid word count {}", word_count)))?;
+            let generated: GeneratedKey<_, Segwitv0> =
+                MnemonicWithPassphrase::generate(options.word_count(mnemonic_type))
+                    .map_err(|_| Error::Generic("Unable to generate the mnemonic".into()))?;
+            Ok(generated.into_key())
+        }
+    }
+}
+
+// Compute the master extended private key of `mnemonic`, zeroing out its passphrase
+fn get_master_key(
+    network: Network,
+    mnemonic: &Mnemonic,
+    mut passphrase: Option<String>,
+) -> Result<bip32::ExtendedPrivKey, Error> {
+    let seed = Seed::new(mnemonic, passphrase.as_deref().unwrap_or(""));
+    if let Some(passphrase) = passphrase.as_mut() {
+        passphrase.zeroize();
+    }
+
+    Ok(bip32::ExtendedPrivKey::new_master(
+        network,
+        seed.as_bytes(),
+    )?)
+}
+
+/// Execute a sub-command that doesn't need a wallet, like the key generation ones
+///
+/// The keys are generated for `network`. Descriptors follow the derivation paths of the
+/// [templates](crate::template), always using the coin type `0'`: `m/48'/0'/0'/2'` is used for
+/// the key of the `multi` template. An error is returned for the sub-commands that need a wallet,
+/// see [`WalletSubCommand::needs_wallet`].
+pub fn handle_key_subcommand(
+    network: Network,
+    subcommand: WalletSubCommand,
+) -> Result<serde_json::Value, Error> {
+    let secp = Secp256k1::new();
+
+    match subcommand {
+        WalletSubCommand::GenerateKey {
+            word_count,
+            passphrase,
+        } => {
+            let (mnemonic, passphrase) = get_mnemonic(None, word_count, passphrase)?;
+            let xprv = get_master_key(network, &mnemonic, passphrase)?;
+
+            Ok(json!({
+                "mnemonic": mnemonic.phrase(),
+                "fingerprint": xprv.fingerprint(&secp).to_string(),
+                "xprv": xprv.to_string(),
+            }))
+        }
+        WalletSubCommand::GenerateDescriptor {
+            template,
+            mnemonic,
+            word_count,
+            passphrase,
+            threshold,
+            xpubs,
+        } => {
+            let (purpose, script_type) = match template.as_str() {
+                "44" => (44, None),
+                "49" => (49, None),
+                "84" => (84, None),
+                "multi" => (48, Some(2)),
+                _ => return Err(Error::Generic(format!("Invalid template `{}`", template))),
+            };
+            if script_type.is_none() && !xpubs.is_empty() {
+                return Err(Error::Generic(
+                    "Cosigners can only be added to the `multi` template".into(),
+                ));
+            }
+
+            let generated = mnemonic.is_none();
+            let (mnemonic, passphrase) = get_mnemonic(mnemonic.as_deref(), word_count, passphrase)?;
+            let master = get_master_key(network, &mnemonic, passphrase)?;
+            let fingerprint = master.fingerprint(&secp);
+
+            let mut path = vec![
+                bip32::ChildNumber::from_hardened_idx(purpose)?,
+                bip32::ChildNumber::from_hardened_idx(0)?,
+                bip32::ChildNumber::from_hardened_idx(0)?,
+            ];
+            if let Some(script_type) = script_type {
+                path.push(bip32::ChildNumber::from_hardened_idx(script_type)?);
+            }
+            let path: bip32::DerivationPath = path.into();
+            let account = master.derive_priv(&secp, &path)?;
+            let account_pub = bip32::ExtendedPubKey::from_private(&secp, &account);
+            // the derivation path is formatted with a leading `m`
+            let origin = format!("[{}{}]", fingerprint, &path.to_string()[1..]);
+
+            let make_descriptor = |key: &str, keychain: u32| -> Result<String, Error> {
+                let key = format!("{}{}/{}/*", origin, key, keychain);
+                let descriptor = match purpose {
+                    44 => format!("pkh({})", key),
+                    49 => format!("sh(wpkh({}))", key),
+                    84 => format!("wpkh({})", key),
+                    _ => {
+                        let threshold = threshold.unwrap_or(0);
+                        if threshold == 0 || threshold > xpubs.len() + 1 {
+                            return Err(Error::Generic(format!(
+                                "Invalid threshold {} for {} keys",
+                                threshold,
+                                xpubs.len() + 1
+                            )));
+                        }
+
+                        let cosigners = xpubs
+                            .iter()
+                            .map(|xpub| format!(",{}/{}/*", xpub, keychain))
+                            .collect::<String>();
+                        format!("wsh(sortedmulti({},{}{}))", threshold, key, cosigners)
+                    }
+                };
+
+                // make sure that the descriptor is valid for `network`
+                descriptor.as_str().to_wallet_descriptor(network)?;
+                Ok(add_checksum(&descriptor)?)
+            };
+
+            let mut result = json!({
+                "fingerprint": fingerprint.to_string(),
+                "descriptor": make_descriptor(&account.to_string(), 0)?,
+                "change_descriptor": make_descriptor(&account.to_string(), 1)?,
+                "public_descriptor": make_descriptor(&account_pub.to_string(), 0)?,
+                "public_change_descriptor": make_descriptor(&account_pub.to_string(), 1)?,
+            });
+            if generated {
+                result["mnemonic"] = json!(mnemonic.phrase());
+            }
+
+            Ok(result)
+        }
+        _ => Err(Error::Generic("The sub-command requires a wallet".into())),
+    }
+}
+
 /// Execute a wallet sub-command with a given [`Wallet`].
 ///
 /// Wallet sub-commands are described in [`WalletSubCommand`]. See [`super::cli`] for example usage.
@@ -426,6 +1367,18 @@ where
 {
     match wallet_subcommand {
         WalletSubCommand::GetNewAddress => Ok(json!({"address": wallet.get_new_address()?})),
+        WalletSubCommand::ListAddresses {
+            from,
+            count,
+            internal,
+        } => {
+            let keychain = match internal {
+                true => KeychainKind::Internal,
+                false => KeychainKind::External,
+            };
+            let addresses = wallet.list_addresses(keychain, from..from.saturating_add(count))?;
+            Ok(json!({ "addresses": addresses }))
+        }
         WalletSubCommand::Sync { max_addresses } => {
             maybe_await!(wallet.sync(log_progress(), max_addresses))?;
             Ok(json!({}))
@@ -434,6 +1387,13 @@ where
         WalletSubCommand::ListTransactions => {
             Ok(serde_json::to_value(&wallet.list_transactions(false)?)?)
         }
+        WalletSubCommand::GetTx { txid, include_raw } => {
+            let txid = Txid::from_str(txid.as_str()).map_err(|s| Error::Generic(s.to_string()))?;
+            let details = wallet
+                .get_tx(&txid, include_raw)?
+                .ok_or(Error::TransactionNotFound)?;
+            Ok(serde_json::to_value(&details)?)
+        }
         WalletSubCommand::GetBalance => Ok(json!({"satoshi": wallet.get_balance()?})),
         WalletSubCommand::CreateTx {
             recipients,
@@ -445,6 +1405,10 @@ where
             fee_rate,
             external_policy,
             internal_policy,
+            drain_to,
+            add_data,
+            locktime,
+            ordering,
         } => {
             let mut tx_builder = TxBuilder::new();
 
@@ -452,10 +1416,28 @@ where
                 tx_builder = tx_builder
                     .drain_wallet()
                     .set_single_recipient(recipients[0].0.clone());
+            } else if let Some(drain_to) = drain_to {
+                if utxos.is_none() {
+                    tx_builder = tx_builder.drain_wallet();
+                }
+                tx_builder = tx_builder.drain_to(drain_to);
             } else {
                 tx_builder = tx_builder.set_recipients(recipients);
             }
 
+            if let Some(data) = add_data {
+                let data = Vec::<u8>::from_hex(&data).map_err(|e| Error::Generic(e.to_string()))?;
+                tx_builder = tx_builder.add_data(&data);
+            }
+
+            if let Some(locktime) = locktime {
+                tx_builder = tx_builder.nlocktime(locktime);
+            }
+
+            if let Some(ordering) = ordering {
+                tx_builder = tx_builder.ordering(ordering);
+            }
+
             if enable_rbf {
                 tx_builder = tx_builder.enable_rbf();
             }
@@ -490,7 +1472,7 @@ where
             }
 
             let (psbt, details) = wallet.create_tx(tx_builder)?;
-            Ok(json!({"psbt": base64::encode(&serialize(&psbt)),"details": details,}))
+            Ok(json!({"psbt": psbt::to_base64(&psbt),"details": details,}))
         }
         WalletSubCommand::BumpFee {
             txid,
@@ -523,7 +1505,29 @@ where
             }
 
             let (psbt, details) = wallet.bump_fee(&txid, tx_builder)?;
-            Ok(json!({"psbt": base64::encode(&serialize(&psbt)),"details": details,}))
+            Ok(json!({"psbt": psbt::to_base64(&psbt),"details": details,}))
+        }
+        WalletSubCommand::CancelTx {
+            txid,
+            offline_signer,
+            fee_rate,
+        } => {
+            let txid = Txid::from_str(txid.as_str()).map_err(|s| Error::Generic(s.to_string()))?;
+
+            let mut tx_builder = TxBuilder::new();
+
+            if offline_signer {
+                tx_builder = tx_builder
+                    .force_non_witness_utxo()
+                    .include_output_redeem_witness_script();
+            }
+
+            if let Some(fee_rate) = fee_rate {
+                tx_builder = tx_builder.fee_rate(FeeRate::from_sat_per_vb(fee_rate));
+            }
+
+            let (psbt, details) = wallet.cancel_tx(&txid, tx_builder)?;
+            Ok(json!({"psbt": psbt::to_base64(&psbt),"details": details,}))
         }
         WalletSubCommand::Policies => Ok(json!({
             "external": wallet.policies(KeychainKind::External)?,
@@ -533,22 +1537,26 @@ where
             "external": wallet.public_descriptor(KeychainKind::External)?.map(|d| d.to_string()),
             "internal": wallet.public_descriptor(KeychainKind::Internal)?.map(|d| d.to_string()),
         })),
+        WalletSubCommand::ExportCore { birthday } => {
+            let import = CoreImport::export_wallet(wallet, birthday)
+                .map_err(|e| Error::Generic(e.to_string()))?;
+            Ok(serde_json::to_value(import)?)
+        }
         WalletSubCommand::Sign {
             psbt,
             assume_height,
         } => {
-            let psbt = base64::decode(&psbt).unwrap();
-            let psbt: PartiallySignedTransaction = deserialize(&psbt).unwrap();
-            let (psbt, finalized) = wallet.sign(psbt, assume_height)?;
-            Ok(json!({"psbt": base64::encode(&serialize(&psbt)),"is_finalized": finalized,}))
+            let psbt = parse_psbt_arg(&psbt)?;
+            let sign_options = SignOptions {
+                assume_height,
+                ..Default::default()
+            };
+            let (psbt, finalized) = wallet.sign(psbt, sign_options)?;
+            Ok(json!({"psbt": psbt::to_base64(&psbt),"is_finalized": finalized,}))
         }
         WalletSubCommand::Broadcast { psbt, tx } => {
             let tx = match (psbt, tx) {
-                (Some(psbt), None) => {
-                    let psbt = base64::decode(&psbt).unwrap();
-                    let psbt: PartiallySignedTransaction = deserialize(&psbt).unwrap();
-                    psbt.extract_tx()
-                }
+                (Some(psbt), None) => parse_psbt_arg(&psbt)?.extract_tx(),
                 (None, Some(tx)) => deserialize(&Vec::<u8>::from_hex(&tx).unwrap()).unwrap(),
                 (Some(_), Some(_)) => panic!("Both `psbt` and `tx` options not allowed"),
                 (None, None) => panic!("Missing `psbt` and `tx` option"),
@@ -558,42 +1566,37 @@ where
             Ok(json!({ "txid": txid }))
         }
         WalletSubCommand::ExtractPsbt { psbt } => {
-            let psbt = base64::decode(&psbt).unwrap();
-            let psbt: PartiallySignedTransaction = deserialize(&psbt).unwrap();
+            let psbt = parse_psbt_arg(&psbt)?;
             Ok(json!({"raw_tx": serialize_hex(&psbt.extract_tx()),}))
         }
         WalletSubCommand::FinalizePsbt {
             psbt,
             assume_height,
         } => {
-            let psbt = base64::decode(&psbt).unwrap();
-            let psbt: PartiallySignedTransaction = deserialize(&psbt).unwrap();
+            let mut psbt = parse_psbt_arg(&psbt)?;
 
-            let (psbt, finalized) = wallet.finalize_psbt(psbt, assume_height)?;
-            Ok(json!({ "psbt": base64::encode(&serialize(&psbt)),"is_finalized": finalized,}))
+            let sign_options = SignOptions {
+                assume_height,
+                ..Default::default()
+            };
+            let finalized = wallet.finalize_psbt(&mut psbt, sign_options)?;
+            Ok(json!({ "psbt": psbt::to_base64(&psbt),"is_finalized": finalized,}))
         }
         WalletSubCommand::CombinePsbt { psbt } => {
-            let mut psbts = psbt
+            let psbts = psbt
                 .iter()
-                .map(|s| {
-                    let psbt = base64::decode(&s).unwrap();
-                    let psbt: PartiallySignedTransaction = deserialize(&psbt).unwrap();
-                    psbt
-                })
-                .collect::<Vec<_>>();
-
-            let init_psbt = psbts.pop().unwrap();
-            let final_psbt = psbts
-                .into_iter()
-                .try_fold::<_, _, Result<PartiallySignedTransaction, Error>>(
-                    init_psbt,
-                    |mut acc, x| {
-                        acc.merge(x)?;
-                        Ok(acc)
-                    },
-                )?;
-
-            Ok(json!({ "psbt": base64::encode(&serialize(&final_psbt)) }))
+                .map(|s| parse_psbt_arg(s))
+                .collect::<Result<Vec<_>, _>>()?;
+            let final_psbt = psbt::combine(psbts)?;
+
+            Ok(json!({ "psbt": psbt::to_base64(&final_psbt) }))
+        }
+        WalletSubCommand::Watch { .. } => Err(Error::Generic(
+            "The `watch` sub-command must be run with `handle_watch_subcommand`".into(),
+        )),
+        subcommand @ WalletSubCommand::GenerateKey { .. }
+        | subcommand @ WalletSubCommand::GenerateDescriptor { .. } => {
+            handle_key_subcommand(wallet.network(), subcommand)
         }
         WalletSubCommand::Other(_) => Ok(json!({})),
     }
@@ -601,9 +1604,20 @@ where
 
 #[cfg(test)]
 mod test {
-    use super::{WalletOpt, WalletSubCommand};
+    use std::fs;
+
+    use super::{
+        format_output, handle_key_subcommand, qr_frames, read_qr_input, render_qr, WalletConfig,
+        WalletEvent, WalletOpt, WalletProfiles, WalletSubCommand, WalletWatcher, QR_MAX_PARTS,
+    };
+    use crate::error::Error;
+    use crate::psbt;
+    use crate::types::TransactionDetails;
+    use crate::wallet::airgap::MAX_SEQ_LEN;
+    use crate::wallet::tx_builder::TxOrdering;
     use bitcoin::hashes::core::str::FromStr;
-    use bitcoin::{Address, OutPoint};
+    use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+    use bitcoin::{Address, Network, OutPoint, Txid};
     use structopt::StructOpt;
 
     #[test]
@@ -620,10 +1634,15 @@ mod test {
         let expected_wallet_opt = WalletOpt {
             network: "bitcoin".to_string(),
             wallet: "main".to_string(),
+            config: None,
+            database_path: None,
+            save_profile: false,
             proxy: None,
-            descriptor: "wpkh(xpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string(),
+            descriptor: Some("wpkh(xpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string()),
             change_descriptor: Some("wpkh(xpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/1/*)".to_string()),
             log_level: "info".to_string(),
+            json: false,
+            qr: false,
             #[cfg(feature = "esplora")]
             esplora: Some("https://blockstream.info/api/".to_string()),
             #[cfg(feature = "esplora")]
@@ -646,10 +1665,15 @@ mod test {
         let expected_wallet_opt = WalletOpt {
             network: "testnet".to_string(),
             wallet: "main".to_string(),
+            config: None,
+            database_path: None,
+            save_profile: false,
             proxy: None,
-            descriptor: "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string(),
+            descriptor: Some("wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string()),
             change_descriptor: None,
             log_level: "info".to_string(),
+            json: false,
+            qr: false,
             #[cfg(feature = "esplora")]
             esplora: None,
             #[cfg(feature = "esplora")]
@@ -663,6 +1687,41 @@ mod test {
         assert_eq!(expected_wallet_opt, wallet_opt);
     }
 
+    #[test]
+    fn test_list_addresses() {
+        let cli_args = vec!["repl", "--network", "testnet",
+                            "--descriptor", "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)",
+                            "list_addresses", "--from", "100", "--count", "1000", "--internal"];
+
+        let wallet_opt = WalletOpt::from_iter(&cli_args);
+
+        let expected_wallet_opt = WalletOpt {
+            network: "testnet".to_string(),
+            wallet: "main".to_string(),
+            config: None,
+            database_path: None,
+            save_profile: false,
+            proxy: None,
+            descriptor: Some("wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string()),
+            change_descriptor: None,
+            log_level: "info".to_string(),
+            json: false,
+            qr: false,
+            #[cfg(feature = "esplora")]
+            esplora: None,
+            #[cfg(feature = "esplora")]
+            esplora_concurrency: 4,
+            electrum: "ssl://electrum.blockstream.info:60002".to_string(),
+            subcommand: WalletSubCommand::ListAddresses {
+                from: 100,
+                count: 1000,
+                internal: true,
+            },
+        };
+
+        assert_eq!(expected_wallet_opt, wallet_opt);
+    }
+
     #[test]
     fn test_create_tx() {
         let cli_args = vec!["repl", "--network", "testnet", "--proxy", "127.0.0.1:9150",
@@ -693,10 +1752,15 @@ mod test {
         let expected_wallet_opt = WalletOpt {
             network: "testnet".to_string(),
             wallet: "main".to_string(),
+            config: None,
+            database_path: None,
+            save_profile: false,
             proxy: Some("127.0.0.1:9150".to_string()),
-            descriptor: "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string(),
+            descriptor: Some("wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string()),
             change_descriptor: Some("wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/1/*)".to_string()),
             log_level: "info".to_string(),
+            json: false,
+            qr: false,
             #[cfg(feature = "esplora")]
             esplora: None,
             #[cfg(feature = "esplora")]
@@ -712,12 +1776,120 @@ mod test {
                 fee_rate: None,
                 external_policy: None,
                 internal_policy: None,
+                drain_to: None,
+                add_data: None,
+                locktime: None,
+                ordering: None,
             },
         };
 
         assert_eq!(expected_wallet_opt, wallet_opt);
     }
 
+    #[test]
+    fn test_create_tx_coin_control() {
+        let cli_args = vec!["repl", "--network", "testnet",
+                            "--descriptor", "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)",
+                            "create_tx", "--drain_to", "n2Z3YNXtceeJhFkTknVaNjT1mnCGWesykJ",
+                            "--utxos","87345e46bfd702d24d54890cc094d08a005f773b27c8f965dfe0eb1e23eef88e:1",
+                            "--unspendable","87345e46bfd702d24d54890cc094d08a005f773b27c8f965dfe0eb1e23eef88e:2",
+                            "--locktime", "630000", "--ordering", "bip69"];
+
+        let wallet_opt = WalletOpt::from_iter(&cli_args);
+
+        let script = Address::from_str("n2Z3YNXtceeJhFkTknVaNjT1mnCGWesykJ")
+            .unwrap()
+            .script_pubkey();
+        let outpoint1 = OutPoint::from_str(
+            "87345e46bfd702d24d54890cc094d08a005f773b27c8f965dfe0eb1e23eef88e:1",
+        )
+        .unwrap();
+        let outpoint2 = OutPoint::from_str(
+            "87345e46bfd702d24d54890cc094d08a005f773b27c8f965dfe0eb1e23eef88e:2",
+        )
+        .unwrap();
+
+        assert_eq!(
+            wallet_opt.subcommand,
+            WalletSubCommand::CreateTx {
+                recipients: vec![],
+                send_all: false,
+                enable_rbf: false,
+                offline_signer: false,
+                utxos: Some(vec![outpoint1]),
+                unspendable: Some(vec![outpoint2]),
+                fee_rate: None,
+                external_policy: None,
+                internal_policy: None,
+                drain_to: Some(script),
+                add_data: None,
+                locktime: Some(630_000),
+                ordering: Some(TxOrdering::BIP69Lexicographic),
+            }
+        );
+
+        let cli_args = vec!["repl", "--descriptor", "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)",
+                            "create_tx", "--to", "n2Z3YNXtceeJhFkTknVaNjT1mnCGWesykJ:123456",
+                            "--drain_to", "n2Z3YNXtceeJhFkTknVaNjT1mnCGWesykJ"];
+        assert!(WalletOpt::from_iter_safe(&cli_args).is_err());
+        let cli_args = vec!["repl", "--descriptor", "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)",
+                            "create_tx", "--to", "n2Z3YNXtceeJhFkTknVaNjT1mnCGWesykJ:123456",
+                            "--ordering", "random"];
+        assert!(WalletOpt::from_iter_safe(&cli_args).is_err());
+    }
+
+    #[test]
+    fn test_cancel_tx() {
+        let cli_args = vec!["repl", "--network", "testnet",
+                            "--descriptor", "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)",
+                            "cancel_tx", "--txid", "87345e46bfd702d24d54890cc094d08a005f773b27c8f965dfe0eb1e23eef88e",
+                            "--fee_rate", "5"];
+
+        let wallet_opt = WalletOpt::from_iter(&cli_args);
+
+        let expected_wallet_opt = WalletOpt {
+            network: "testnet".to_string(),
+            wallet: "main".to_string(),
+            config: None,
+            database_path: None,
+            save_profile: false,
+            proxy: None,
+            descriptor: Some("wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string()),
+            change_descriptor: None,
+            log_level: "info".to_string(),
+            json: false,
+            qr: false,
+            #[cfg(feature = "esplora")]
+            esplora: None,
+            #[cfg(feature = "esplora")]
+            esplora_concurrency: 4,
+            electrum: "ssl://electrum.blockstream.info:60002".to_string(),
+            subcommand: WalletSubCommand::CancelTx {
+                txid: "87345e46bfd702d24d54890cc094d08a005f773b27c8f965dfe0eb1e23eef88e".to_string(),
+                offline_signer: false,
+                fee_rate: Some(5.0),
+            },
+        };
+
+        assert_eq!(expected_wallet_opt, wallet_opt);
+    }
+
+    #[test]
+    fn test_export_core() {
+        let cli_args = vec!["repl", "--network", "testnet",
+                            "--descriptor", "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)",
+                            "export_core", "--birthday", "1600000000"];
+
+        let wallet_opt = WalletOpt::from_iter(&cli_args);
+
+        assert_eq!(
+            wallet_opt.subcommand,
+            WalletSubCommand::ExportCore {
+                birthday: Some(1_600_000_000)
+            }
+        );
+    }
+
     #[test]
     fn test_broadcast() {
         let cli_args = vec!["repl", "--network", "testnet",
@@ -730,10 +1902,15 @@ mod test {
         let expected_wallet_opt = WalletOpt {
             network: "testnet".to_string(),
             wallet: "main".to_string(),
+            config: None,
+            database_path: None,
+            save_profile: false,
             proxy: None,
-            descriptor: "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string(),
+            descriptor: Some("wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)".to_string()),
             change_descriptor: None,
             log_level: "info".to_string(),
+            json: false,
+            qr: false,
             #[cfg(feature = "esplora")]
             esplora: None,
             #[cfg(feature = "esplora")]
@@ -747,4 +1924,324 @@ mod test {
 
         assert_eq!(expected_wallet_opt, wallet_opt);
     }
+
+    #[test]
+    fn test_json_output() {
+        let cli_args = vec!["repl", "--network", "testnet", "--json",
+                            "--descriptor", "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)",
+                            "get_balance"];
+
+        let wallet_opt = WalletOpt::from_iter(&cli_args);
+        assert!(wallet_opt.json);
+
+        let result = Ok(json!({ "satoshi": 50_000 }));
+        assert_eq!(format_output(&result, true), "{\"satoshi\":50000}");
+        assert_eq!(format_output(&result, false), "{\n  \"satoshi\": 50000\n}");
+
+        let result = Err(Error::InsufficientFunds);
+        assert_eq!(
+            format_output(&result, true),
+            "{\"error\":\"InsufficientFunds\"}"
+        );
+        assert_eq!(format_output(&result, false), "Error: InsufficientFunds");
+    }
+
+    #[test]
+    fn test_profiles() {
+        let descriptor = "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)";
+        let mut profiles = WalletProfiles::default();
+
+        let mut wallet_opt = WalletOpt::from_iter(&["repl", "--wallet", "savings", "get_balance"]);
+        assert_eq!(wallet_opt.descriptor, None);
+        assert!(wallet_opt.resolve_profile(&mut profiles).is_err());
+
+        let mut save_opt = WalletOpt::from_iter(&[
+            "repl",
+            "--wallet",
+            "savings",
+            "--network",
+            "regtest",
+            "--descriptor",
+            descriptor,
+            "--save_profile",
+            "get_balance",
+        ]);
+        save_opt.resolve_profile(&mut profiles).unwrap();
+        assert_eq!(profiles.names().collect::<Vec<_>>(), vec!["savings"]);
+
+        let json = serde_json::to_string(&profiles).unwrap();
+        let mut profiles: WalletProfiles = serde_json::from_str(&json).unwrap();
+
+        wallet_opt.resolve_profile(&mut profiles).unwrap();
+        assert_eq!(wallet_opt.descriptor.as_deref(), Some(descriptor));
+        assert_eq!(wallet_opt.network, "regtest");
+        assert_eq!(wallet_opt.to_profile(), save_opt.to_profile());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_profiles_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let mut path = std::env::temp_dir();
+        path.push(format!("bdk_profiles_{}.json", time.as_nanos()));
+
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        WalletProfiles::default().save(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            WalletProfiles::load(&path).unwrap(),
+            WalletProfiles::default()
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_qr() {
+        let small = "cHNidP8BAEICAAAAASWhGE1AhvtO+2GjJHopssFmgfbq+WweHd8zN/DeaqmDAAAAAAD/////AQAAAAAAAAAABmoEAAECAwAAAAAAAAA=";
+        let address = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+
+        let frames = qr_frames(&json!({ "address": address, "psbt": small })).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], vec![format!("bitcoin:{}", address)]);
+        assert_eq!(frames[1].len(), 1);
+        assert!(frames[1][0].starts_with("UR:CRYPTO-PSBT/"));
+        assert!(render_qr(&frames[1][0]).is_ok());
+
+        let input = format!("\n{}\n", frames[1][0]);
+        let psbt = read_qr_input(input.as_bytes()).unwrap();
+        assert_eq!(psbt::to_base64(&psbt), small);
+        let psbt = read_qr_input(small.as_bytes()).unwrap();
+        assert_eq!(psbt::to_base64(&psbt), small);
+
+        let mut tx = psbt.global.unsigned_tx.clone();
+        tx.output = vec![tx.output[0].clone(); 50];
+        let large = PSBT::from_unsigned_tx(tx).unwrap();
+        let frames = qr_frames(&json!({ "psbt": psbt::to_base64(&large) })).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].len() > 1);
+
+        let input = frames[0]
+            .iter()
+            .rev()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(read_qr_input(input.as_bytes()).unwrap(), large);
+        let input = frames[0][1..].join("\n");
+        assert!(read_qr_input(input.as_bytes()).is_err());
+
+        // a part announcing too many fragments is rejected by the decoder
+        let input = frames[0][0].replacen(
+            &format!("/1-{}/", frames[0].len()),
+            &format!("/1-{}/", MAX_SEQ_LEN + 1),
+            1,
+        );
+        assert!(input.contains(&format!("/1-{}/", MAX_SEQ_LEN + 1)));
+        assert!(read_qr_input(input.as_bytes()).is_err());
+
+        let input = vec![frames[0][1].clone(); QR_MAX_PARTS + 1].join("\n");
+        assert!(matches!(
+            read_qr_input(input.as_bytes()),
+            Err(Error::Generic(msg)) if msg.contains(&QR_MAX_PARTS.to_string())
+        ));
+    }
+
+    #[test]
+    fn test_config() {
+        let config: WalletConfig = toml::from_str(
+            r#"
+            network = "regtest"
+            electrum = "tcp://127.0.0.1:50001"
+            database_path = "/tmp/wallet.sled"
+            fee_rate = 2.5
+        "#,
+        )
+        .unwrap();
+        assert_eq!(config.network, Some("regtest".to_string()));
+        assert_eq!(config.fee_rate, Some(2.5));
+        assert_eq!(config.max_fee_rate, None);
+        assert!(toml::from_str::<WalletConfig>("netwrok = \"regtest\"").is_err());
+
+        let descriptor = "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)";
+        let args = [
+            "repl",
+            "--descriptor",
+            descriptor,
+            "create_tx",
+            "--to",
+            "n2Z3YNXtceeJhFkTknVaNjT1mnCGWesykJ:123456",
+        ];
+        let matches = WalletOpt::clap().get_matches_from(&args);
+        let mut wallet_opt = WalletOpt::from_clap(&matches);
+        config.apply(&mut wallet_opt, &matches);
+        assert_eq!(wallet_opt.network, "regtest");
+        assert_eq!(
+            wallet_opt.database_path,
+            Some("/tmp/wallet.sled".to_string())
+        );
+        #[cfg(feature = "electrum")]
+        assert_eq!(wallet_opt.electrum, "tcp://127.0.0.1:50001");
+        assert!(matches!(
+            wallet_opt.subcommand,
+            WalletSubCommand::CreateTx { fee_rate: Some(f), .. } if f == 2.5
+        ));
+
+        // the options given on the command line take precedence
+        let args = [
+            "repl",
+            "--network",
+            "testnet",
+            "--descriptor",
+            descriptor,
+            "create_tx",
+            "--to",
+            "n2Z3YNXtceeJhFkTknVaNjT1mnCGWesykJ:123456",
+            "--fee_rate",
+            "10",
+        ];
+        let matches = WalletOpt::clap().get_matches_from(&args);
+        let mut wallet_opt = WalletOpt::from_clap(&matches);
+        config.apply(&mut wallet_opt, &matches);
+        assert_eq!(wallet_opt.network, "testnet");
+        assert!(matches!(
+            wallet_opt.subcommand,
+            WalletSubCommand::CreateTx { fee_rate: Some(f), .. } if f == 10.0
+        ));
+    }
+
+    #[test]
+    fn test_watcher() {
+        let mut details = TransactionDetails {
+            txid: Txid::from_str(
+                "87345e46bfd702d24d54890cc094d08a005f773b27c8f965dfe0eb1e23eef88e",
+            )
+            .unwrap(),
+            received: 50_000,
+            ..Default::default()
+        };
+
+        let mut watcher = WalletWatcher::default();
+        assert_eq!(
+            watcher.update(vec![details.clone()]),
+            vec![WalletEvent::NewTransaction(details.clone())]
+        );
+        assert_eq!(watcher.update(vec![details.clone()]), vec![]);
+
+        details.height = Some(100);
+        assert_eq!(
+            watcher.update(vec![details.clone()]),
+            vec![WalletEvent::Confirmed(details.clone())]
+        );
+        assert_eq!(watcher.update(vec![details.clone()]), vec![]);
+
+        details.height = Some(101);
+        assert_eq!(
+            watcher.update(vec![details.clone()]),
+            vec![WalletEvent::Confirmed(details.clone())]
+        );
+
+        let mut reorged = details.clone();
+        reorged.height = None;
+        assert_eq!(
+            watcher.update(vec![reorged.clone()]),
+            vec![WalletEvent::Unconfirmed(reorged)]
+        );
+        assert_eq!(
+            watcher.update(vec![details.clone()]),
+            vec![WalletEvent::Confirmed(details.clone())]
+        );
+
+        assert_eq!(
+            watcher.update(vec![]),
+            vec![WalletEvent::Removed { txid: details.txid }]
+        );
+
+        let event = serde_json::to_value(&WalletEvent::Confirmed(details.clone())).unwrap();
+        assert_eq!(event["event"], "confirmed");
+        assert_eq!(event["height"], 101);
+    }
+
+    #[test]
+    fn test_generate_key() {
+        let subcommand =
+            WalletSubCommand::from_iter(&["repl", "generate_key", "--word_count", "12"]);
+        assert!(!subcommand.needs_wallet());
+
+        let result = handle_key_subcommand(Network::Testnet, subcommand).unwrap();
+        assert_eq!(result["mnemonic"].as_str().unwrap().split(' ').count(), 12);
+        assert!(result["xprv"].as_str().unwrap().starts_with("tprv"));
+
+        let subcommand =
+            WalletSubCommand::from_iter(&["repl", "generate_key", "--word_count", "13"]);
+        assert!(handle_key_subcommand(Network::Testnet, subcommand).is_err());
+        assert!(handle_key_subcommand(Network::Testnet, WalletSubCommand::GetBalance).is_err());
+    }
+
+    #[test]
+    fn test_generate_descriptor() {
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+        let subcommand =
+            WalletSubCommand::from_iter(&["repl", "generate_descriptor", "--mnemonic", mnemonic]);
+        let result = handle_key_subcommand(Network::Testnet, subcommand).unwrap();
+        assert_eq!(result["fingerprint"], "73c5da0a");
+        assert!(result.get("mnemonic").is_none());
+        let descriptor = result["descriptor"].as_str().unwrap();
+        assert!(descriptor.starts_with("wpkh([73c5da0a/84'/0'/0']tprv"));
+        assert!(descriptor.contains("/0/*)#"));
+        let public_change_descriptor = result["public_change_descriptor"].as_str().unwrap();
+        assert!(public_change_descriptor.starts_with("wpkh([73c5da0a/84'/0'/0']tpub"));
+        assert!(public_change_descriptor.contains("/1/*)#"));
+
+        let subcommand =
+            WalletSubCommand::from_iter(&["repl", "generate_descriptor", "--template", "49"]);
+        let result = handle_key_subcommand(Network::Testnet, subcommand).unwrap();
+        assert_eq!(result["mnemonic"].as_str().unwrap().split(' ').count(), 24);
+        assert!(result["descriptor"]
+            .as_str()
+            .unwrap()
+            .starts_with("sh(wpkh(["));
+
+        let cosigner = "[c258d2e4/48'/0'/0'/2']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev";
+        let subcommand = WalletSubCommand::from_iter(&[
+            "repl",
+            "generate_descriptor",
+            "--mnemonic",
+            mnemonic,
+            "--template",
+            "multi",
+            "--threshold",
+            "2",
+            "--xpub",
+            cosigner,
+        ]);
+        let result = handle_key_subcommand(Network::Testnet, subcommand).unwrap();
+        let public_descriptor = result["public_descriptor"].as_str().unwrap();
+        assert!(public_descriptor.starts_with("wsh(sortedmulti(2,[73c5da0a/48'/0'/0'/2']tpub"));
+        assert!(public_descriptor.contains(&format!(",{}/0/*))#", cosigner)));
+
+        let subcommand = WalletSubCommand::from_iter(&[
+            "repl",
+            "generate_descriptor",
+            "--mnemonic",
+            mnemonic,
+            "--template",
+            "multi",
+            "--threshold",
+            "3",
+            "--xpub",
+            cosigner,
+        ]);
+        assert!(handle_key_subcommand(Network::Testnet, subcommand).is_err());
+        let subcommand =
+            WalletSubCommand::from_iter(&["repl", "generate_descriptor", "--xpub", cosigner]);
+        assert!(handle_key_subcommand(Network::Testnet, subcommand).is_err());
+    }
 }
@@ -147,6 +147,28 @@ impl BatchOperations for AnyDatabase {
     fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
         impl_inner_method!(AnyDatabase, self, set_last_index, keychain, value)
     }
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<(), Error> {
+        impl_inner_method!(AnyDatabase, self, set_label, label_ref, label)
+    }
+    fn set_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+        address: &SilentPaymentAddress,
+    ) -> Result<(), Error> {
+        impl_inner_method!(
+            AnyDatabase,
+            self,
+            set_silent_payment_recipient,
+            script,
+            address
+        )
+    }
+    fn set_silent_payment_output(&mut self, output: &SilentPaymentOutput) -> Result<(), Error> {
+        impl_inner_method!(AnyDatabase, self, set_silent_payment_output, output)
+    }
+    fn set_silent_payment_scan_height(&mut self, height: u32) -> Result<(), Error> {
+        impl_inner_method!(AnyDatabase, self, set_silent_payment_scan_height, height)
+    }
 
     fn del_script_pubkey_from_path(
         &mut self,
@@ -183,6 +205,24 @@ impl BatchOperations for AnyDatabase {
     fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
         impl_inner_method!(AnyDatabase, self, del_last_index, keychain)
     }
+    fn del_label(&mut self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        impl_inner_method!(AnyDatabase, self, del_label, label_ref)
+    }
+    fn del_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error> {
+        impl_inner_method!(AnyDatabase, self, del_silent_payment_recipient, script)
+    }
+    fn del_silent_payment_output(
+        &mut self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error> {
+        impl_inner_method!(AnyDatabase, self, del_silent_payment_output, outpoint)
+    }
+    fn del_silent_payment_scan_height(&mut self) -> Result<Option<u32>, Error> {
+        impl_inner_method!(AnyDatabase, self, del_silent_payment_scan_height)
+    }
 }
 
 impl Database for AnyDatabase {
@@ -212,6 +252,15 @@ impl Database for AnyDatabase {
     fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
         impl_inner_method!(AnyDatabase, self, iter_txs, include_raw)
     }
+    fn iter_labels(&self) -> Result<Vec<(LabelRef, String)>, Error> {
+        impl_inner_method!(AnyDatabase, self, iter_labels)
+    }
+    fn iter_silent_payment_recipients(&self) -> Result<Vec<(Script, SilentPaymentAddress)>, Error> {
+        impl_inner_method!(AnyDatabase, self, iter_silent_payment_recipients)
+    }
+    fn iter_silent_payment_outputs(&self) -> Result<Vec<SilentPaymentOutput>, Error> {
+        impl_inner_method!(AnyDatabase, self, iter_silent_payment_outputs)
+    }
 
     fn get_script_pubkey_from_path(
         &self,
@@ -244,6 +293,24 @@ impl Database for AnyDatabase {
     fn get_last_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
         impl_inner_method!(AnyDatabase, self, get_last_index, keychain)
     }
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        impl_inner_method!(AnyDatabase, self, get_label, label_ref)
+    }
+    fn get_silent_payment_recipient(
+        &self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error> {
+        impl_inner_method!(AnyDatabase, self, get_silent_payment_recipient, script)
+    }
+    fn get_silent_payment_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error> {
+        impl_inner_method!(AnyDatabase, self, get_silent_payment_output, outpoint)
+    }
+    fn get_silent_payment_scan_height(&self) -> Result<Option<u32>, Error> {
+        impl_inner_method!(AnyDatabase, self, get_silent_payment_scan_height)
+    }
 
     fn increment_last_index(&mut self, keychain: KeychainKind) -> Result<u32, Error> {
         impl_inner_method!(AnyDatabase, self, increment_last_index, keychain)
@@ -271,6 +338,28 @@ impl BatchOperations for AnyBatch {
     fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
         impl_inner_method!(AnyBatch, self, set_last_index, keychain, value)
     }
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<(), Error> {
+        impl_inner_method!(AnyBatch, self, set_label, label_ref, label)
+    }
+    fn set_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+        address: &SilentPaymentAddress,
+    ) -> Result<(), Error> {
+        impl_inner_method!(
+            AnyBatch,
+            self,
+            set_silent_payment_recipient,
+            script,
+            address
+        )
+    }
+    fn set_silent_payment_output(&mut self, output: &SilentPaymentOutput) -> Result<(), Error> {
+        impl_inner_method!(AnyBatch, self, set_silent_payment_output, output)
+    }
+    fn set_silent_payment_scan_height(&mut self, height: u32) -> Result<(), Error> {
+        impl_inner_method!(AnyBatch, self, set_silent_payment_scan_height, height)
+    }
 
     fn del_script_pubkey_from_path(
         &mut self,
@@ -301,6 +390,24 @@ impl BatchOperations for AnyBatch {
     fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
         impl_inner_method!(AnyBatch, self, del_last_index, keychain)
     }
+    fn del_label(&mut self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        impl_inner_method!(AnyBatch, self, del_label, label_ref)
+    }
+    fn del_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error> {
+        impl_inner_method!(AnyBatch, self, del_silent_payment_recipient, script)
+    }
+    fn del_silent_payment_output(
+        &mut self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error> {
+        impl_inner_method!(AnyBatch, self, del_silent_payment_output, outpoint)
+    }
+    fn del_silent_payment_scan_height(&mut self) -> Result<Option<u32>, Error> {
+        impl_inner_method!(AnyBatch, self, del_silent_payment_scan_height)
+    }
 }
 
 impl BatchDatabase for AnyDatabase {
@@ -34,6 +34,7 @@ use crate::database::memory::MapKey;
 use crate::database::{BatchDatabase, BatchOperations, Database};
 use crate::error::Error;
 use crate::types::*;
+use crate::wallet::silent_payments::{SilentPaymentAddress, SilentPaymentOutput};
 
 macro_rules! impl_batch_operations {
     ( { $($after_insert:tt)* }, $process_delete:ident ) => {
@@ -95,6 +96,42 @@ macro_rules! impl_batch_operations {
             Ok(())
         }
 
+        fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<(), Error> {
+            let key = MapKey::Label(Some(label_ref)).as_map_key();
+            let value = json!({
+                "r": label_ref,
+                "l": label,
+            });
+            self.insert(key, serde_json::to_vec(&value)?)$($after_insert)*;
+
+            Ok(())
+        }
+
+        fn set_silent_payment_recipient(&mut self, script: &Script, address: &SilentPaymentAddress) -> Result<(), Error> {
+            let key = MapKey::SilentPaymentRecipient(Some(script)).as_map_key();
+            let value = json!({
+                "s": script,
+                "a": address,
+            });
+            self.insert(key, serde_json::to_vec(&value)?)$($after_insert)*;
+
+            Ok(())
+        }
+
+        fn set_silent_payment_output(&mut self, output: &SilentPaymentOutput) -> Result<(), Error> {
+            let key = MapKey::SilentPaymentOutput(Some(&output.outpoint)).as_map_key();
+            self.insert(key, serde_json::to_vec(output)?)$($after_insert)*;
+
+            Ok(())
+        }
+
+        fn set_silent_payment_scan_height(&mut self, height: u32) -> Result<(), Error> {
+            let key = MapKey::SilentPaymentScanHeight.as_map_key();
+            self.insert(key, &height.to_be_bytes())$($after_insert)*;
+
+            Ok(())
+        }
+
         fn del_script_pubkey_from_path(&mut self, keychain: KeychainKind, path: u32) -> Result<Option<Script>, Error> {
             let key = MapKey::Path((Some(keychain), Some(path))).as_map_key();
             let res = self.remove(key);
@@ -181,6 +218,60 @@ macro_rules! impl_batch_operations {
                 }
             }
         }
+
+        fn del_label(&mut self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+            let key = MapKey::Label(Some(label_ref)).as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            match res {
+                None => Ok(None),
+                Some(b) => {
+                    let mut val: serde_json::Value = serde_json::from_slice(&b)?;
+                    Ok(Some(serde_json::from_value(val["l"].take())?))
+                }
+            }
+        }
+
+        fn del_silent_payment_recipient(&mut self, script: &Script) -> Result<Option<SilentPaymentAddress>, Error> {
+            let key = MapKey::SilentPaymentRecipient(Some(script)).as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            match res {
+                None => Ok(None),
+                Some(b) => {
+                    let mut val: serde_json::Value = serde_json::from_slice(&b)?;
+                    Ok(Some(serde_json::from_value(val["a"].take())?))
+                }
+            }
+        }
+
+        fn del_silent_payment_output(&mut self, outpoint: &OutPoint) -> Result<Option<SilentPaymentOutput>, Error> {
+            let key = MapKey::SilentPaymentOutput(Some(outpoint)).as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            match res {
+                None => Ok(None),
+                Some(b) => Ok(Some(serde_json::from_slice(&b)?)),
+            }
+        }
+
+        fn del_silent_payment_scan_height(&mut self) -> Result<Option<u32>, Error> {
+            let key = MapKey::SilentPaymentScanHeight.as_map_key();
+            let res = self.remove(key);
+            let res = $process_delete!(res);
+
+            match res {
+                None => Ok(None),
+                Some(b) => {
+                    let array: [u8; 4] = b.as_ref().try_into().map_err(|_| Error::InvalidU32Bytes(b.to_vec()))?;
+                    let val = u32::from_be_bytes(array);
+                    Ok(Some(val))
+                }
+            }
+        }
     }
 }
 
@@ -280,6 +371,46 @@ impl Database for Tree {
             .collect()
     }
 
+    fn iter_labels(&self) -> Result<Vec<(LabelRef, String)>, Error> {
+        let key = MapKey::Label(None).as_map_key();
+        self.scan_prefix(key)
+            .map(|x| -> Result<_, Error> {
+                let (_, v) = x?;
+
+                let mut val: serde_json::Value = serde_json::from_slice(&v)?;
+                let label_ref = serde_json::from_value(val["r"].take())?;
+                let label = serde_json::from_value(val["l"].take())?;
+
+                Ok((label_ref, label))
+            })
+            .collect()
+    }
+
+    fn iter_silent_payment_recipients(&self) -> Result<Vec<(Script, SilentPaymentAddress)>, Error> {
+        let key = MapKey::SilentPaymentRecipient(None).as_map_key();
+        self.scan_prefix(key)
+            .map(|x| -> Result<_, Error> {
+                let (_, v) = x?;
+
+                let mut val: serde_json::Value = serde_json::from_slice(&v)?;
+                let script = serde_json::from_value(val["s"].take())?;
+                let address = serde_json::from_value(val["a"].take())?;
+
+                Ok((script, address))
+            })
+            .collect()
+    }
+
+    fn iter_silent_payment_outputs(&self) -> Result<Vec<SilentPaymentOutput>, Error> {
+        let key = MapKey::SilentPaymentOutput(None).as_map_key();
+        self.scan_prefix(key)
+            .map(|x| -> Result<_, Error> {
+                let (_, v) = x?;
+                Ok(serde_json::from_slice(&v)?)
+            })
+            .collect()
+    }
+
     fn get_script_pubkey_from_path(
         &self,
         keychain: KeychainKind,
@@ -355,6 +486,53 @@ impl Database for Tree {
             .transpose()
     }
 
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        let key = MapKey::Label(Some(label_ref)).as_map_key();
+        self.get(key)?
+            .map(|b| -> Result<_, Error> {
+                let mut val: serde_json::Value = serde_json::from_slice(&b)?;
+                Ok(serde_json::from_value(val["l"].take())?)
+            })
+            .transpose()
+    }
+
+    fn get_silent_payment_recipient(
+        &self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error> {
+        let key = MapKey::SilentPaymentRecipient(Some(script)).as_map_key();
+        self.get(key)?
+            .map(|b| -> Result<_, Error> {
+                let mut val: serde_json::Value = serde_json::from_slice(&b)?;
+                Ok(serde_json::from_value(val["a"].take())?)
+            })
+            .transpose()
+    }
+
+    fn get_silent_payment_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error> {
+        let key = MapKey::SilentPaymentOutput(Some(outpoint)).as_map_key();
+        self.get(key)?
+            .map(|b| -> Result<_, Error> { Ok(serde_json::from_slice(&b)?) })
+            .transpose()
+    }
+
+    fn get_silent_payment_scan_height(&self) -> Result<Option<u32>, Error> {
+        let key = MapKey::SilentPaymentScanHeight.as_map_key();
+        self.get(key)?
+            .map(|b| -> Result<_, Error> {
+                let array: [u8; 4] = b
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| Error::InvalidU32Bytes(b.to_vec()))?;
+                let val = u32::from_be_bytes(array);
+                Ok(val)
+            })
+            .transpose()
+    }
+
     // inserts 0 if not present
     fn increment_last_index(&mut self, keychain: KeychainKind) -> Result<u32, Error> {
         let key = MapKey::LastIndex(keychain).as_map_key();
@@ -478,4 +656,24 @@ mod test {
     fn test_last_index() {
         crate::database::test::test_last_index(get_tree());
     }
+
+    #[test]
+    fn test_check_descriptor_checksum() {
+        crate::database::test::test_check_descriptor_checksum(get_tree());
+    }
+
+    #[test]
+    fn test_label() {
+        crate::database::test::test_label(get_tree());
+    }
+
+    #[test]
+    fn test_silent_payment_recipient() {
+        crate::database::test::test_silent_payment_recipient(get_tree());
+    }
+
+    #[test]
+    fn test_silent_payment_output() {
+        crate::database::test::test_silent_payment_output(get_tree());
+    }
 }
@@ -37,6 +37,7 @@ use bitcoin::{OutPoint, Script, Transaction};
 use crate::database::{BatchDatabase, BatchOperations, ConfigurableDatabase, Database};
 use crate::error::Error;
 use crate::types::*;
+use crate::wallet::silent_payments::{SilentPaymentAddress, SilentPaymentOutput};
 
 // path -> script       p{i,e}<path> -> script
 // script -> path       s<script> -> {i,e}<path>
@@ -45,6 +46,10 @@ use crate::types::*;
 // transactions         t<txid> -> tx details
 // deriv indexes        c{i,e} -> u32
 // descriptor checksum  d{i,e} -> vec<u8>
+// labels               l{t,a,i,o}<ref> -> (label ref, label)
+// silent payments      a<script> -> (script, silent payment address)
+// received sp outputs  o<outpoint> -> silent payment output
+// sp scan height       h -> u32
 
 pub(crate) enum MapKey<'a> {
     Path((Option<KeychainKind>, Option<u32>)),
@@ -54,6 +59,10 @@ pub(crate) enum MapKey<'a> {
     Transaction(Option<&'a Txid>),
     LastIndex(KeychainKind),
     DescriptorChecksum(KeychainKind),
+    Label(Option<&'a LabelRef>),
+    SilentPaymentRecipient(Option<&'a Script>),
+    SilentPaymentOutput(Option<&'a OutPoint>),
+    SilentPaymentScanHeight,
 }
 
 impl MapKey<'_> {
@@ -72,6 +81,10 @@ impl MapKey<'_> {
             MapKey::Transaction(_) => b"t".to_vec(),
             MapKey::LastIndex(st) => [b"c", st.as_ref()].concat(),
             MapKey::DescriptorChecksum(st) => [b"d", st.as_ref()].concat(),
+            MapKey::Label(_) => b"l".to_vec(),
+            MapKey::SilentPaymentRecipient(_) => b"a".to_vec(),
+            MapKey::SilentPaymentOutput(_) => b"o".to_vec(),
+            MapKey::SilentPaymentScanHeight => b"h".to_vec(),
         }
     }
 
@@ -82,6 +95,20 @@ impl MapKey<'_> {
             MapKey::UTXO(Some(s)) => serialize(*s),
             MapKey::RawTx(Some(s)) => serialize(*s),
             MapKey::Transaction(Some(s)) => serialize(*s),
+            MapKey::SilentPaymentRecipient(Some(s)) => serialize(*s),
+            MapKey::SilentPaymentOutput(Some(s)) => serialize(*s),
+            MapKey::Label(Some(LabelRef::Transaction(txid))) => {
+                [b"t", &serialize(txid)[..]].concat()
+            }
+            MapKey::Label(Some(LabelRef::Address(script))) => {
+                [b"a", &serialize(script)[..]].concat()
+            }
+            MapKey::Label(Some(LabelRef::Input(outpoint))) => {
+                [b"i", &serialize(outpoint)[..]].concat()
+            }
+            MapKey::Label(Some(LabelRef::Output(outpoint))) => {
+                [b"o", &serialize(outpoint)[..]].concat()
+            }
             _ => vec![],
         }
     }
@@ -123,7 +150,7 @@ fn after(key: &[u8]) -> Vec<u8> {
 /// [`database`]: crate::database
 #[derive(Debug, Default)]
 pub struct MemoryDatabase {
-    map: BTreeMap<Vec<u8>, Box<dyn std::any::Any>>,
+    map: BTreeMap<Vec<u8>, Box<dyn std::any::Any + Send + Sync>>,
     deleted_keys: Vec<Vec<u8>>,
 }
 
@@ -192,6 +219,35 @@ impl BatchOperations for MemoryDatabase {
 
         Ok(())
     }
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<(), Error> {
+        let key = MapKey::Label(Some(label_ref)).as_map_key();
+        self.map
+            .insert(key, Box::new((label_ref.clone(), label.to_string())));
+
+        Ok(())
+    }
+    fn set_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+        address: &SilentPaymentAddress,
+    ) -> Result<(), Error> {
+        let key = MapKey::SilentPaymentRecipient(Some(script)).as_map_key();
+        self.map.insert(key, Box::new((script.clone(), *address)));
+
+        Ok(())
+    }
+    fn set_silent_payment_output(&mut self, output: &SilentPaymentOutput) -> Result<(), Error> {
+        let key = MapKey::SilentPaymentOutput(Some(&output.outpoint)).as_map_key();
+        self.map.insert(key, Box::new(output.clone()));
+
+        Ok(())
+    }
+    fn set_silent_payment_scan_height(&mut self, height: u32) -> Result<(), Error> {
+        let key = MapKey::SilentPaymentScanHeight.as_map_key();
+        self.map.insert(key, Box::new(height));
+
+        Ok(())
+    }
 
     fn del_script_pubkey_from_path(
         &mut self,
@@ -282,6 +338,46 @@ impl BatchOperations for MemoryDatabase {
             Some(b) => Ok(Some(*b.downcast_ref().unwrap())),
         }
     }
+    fn del_label(&mut self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        let key = MapKey::Label(Some(label_ref)).as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        Ok(res.map(|b| {
+            let (_, label): (LabelRef, String) = b.downcast_ref().cloned().unwrap();
+            label
+        }))
+    }
+    fn del_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error> {
+        let key = MapKey::SilentPaymentRecipient(Some(script)).as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        Ok(res.map(|b| {
+            let (_, address): (Script, SilentPaymentAddress) = b.downcast_ref().cloned().unwrap();
+            address
+        }))
+    }
+    fn del_silent_payment_output(
+        &mut self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error> {
+        let key = MapKey::SilentPaymentOutput(Some(outpoint)).as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        Ok(res.map(|b| b.downcast_ref().cloned().unwrap()))
+    }
+    fn del_silent_payment_scan_height(&mut self) -> Result<Option<u32>, Error> {
+        let key = MapKey::SilentPaymentScanHeight.as_map_key();
+        let res = self.map.remove(&key);
+        self.deleted_keys.push(key);
+
+        Ok(res.map(|b| *b.downcast_ref().unwrap()))
+    }
 }
 
 impl Database for MemoryDatabase {
@@ -356,6 +452,30 @@ impl Database for MemoryDatabase {
             .collect()
     }
 
+    fn iter_labels(&self) -> Result<Vec<(LabelRef, String)>, Error> {
+        let key = MapKey::Label(None).as_map_key();
+        self.map
+            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+            .map(|(_, v)| Ok(v.downcast_ref().cloned().unwrap()))
+            .collect()
+    }
+
+    fn iter_silent_payment_recipients(&self) -> Result<Vec<(Script, SilentPaymentAddress)>, Error> {
+        let key = MapKey::SilentPaymentRecipient(None).as_map_key();
+        self.map
+            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+            .map(|(_, v)| Ok(v.downcast_ref().cloned().unwrap()))
+            .collect()
+    }
+
+    fn iter_silent_payment_outputs(&self) -> Result<Vec<SilentPaymentOutput>, Error> {
+        let key = MapKey::SilentPaymentOutput(None).as_map_key();
+        self.map
+            .range::<Vec<u8>, _>((Included(&key), Excluded(&after(&key))))
+            .map(|(_, v)| Ok(v.downcast_ref().cloned().unwrap()))
+            .collect()
+    }
+
     fn get_script_pubkey_from_path(
         &self,
         keychain: KeychainKind,
@@ -419,6 +539,41 @@ impl Database for MemoryDatabase {
         Ok(self.map.get(&key).map(|b| *b.downcast_ref().unwrap()))
     }
 
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        let key = MapKey::Label(Some(label_ref)).as_map_key();
+        Ok(self.map.get(&key).map(|b| {
+            let (_, label): (LabelRef, String) = b.downcast_ref().cloned().unwrap();
+            label
+        }))
+    }
+
+    fn get_silent_payment_recipient(
+        &self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error> {
+        let key = MapKey::SilentPaymentRecipient(Some(script)).as_map_key();
+        Ok(self.map.get(&key).map(|b| {
+            let (_, address): (Script, SilentPaymentAddress) = b.downcast_ref().cloned().unwrap();
+            address
+        }))
+    }
+
+    fn get_silent_payment_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error> {
+        let key = MapKey::SilentPaymentOutput(Some(outpoint)).as_map_key();
+        Ok(self
+            .map
+            .get(&key)
+            .map(|b| b.downcast_ref().cloned().unwrap()))
+    }
+
+    fn get_silent_payment_scan_height(&self) -> Result<Option<u32>, Error> {
+        let key = MapKey::SilentPaymentScanHeight.as_map_key();
+        Ok(self.map.get(&key).map(|b| *b.downcast_ref().unwrap()))
+    }
+
     // inserts 0 if not present
     fn increment_last_index(&mut self, keychain: KeychainKind) -> Result<u32, Error> {
         let key = MapKey::LastIndex(keychain).as_map_key();
@@ -497,6 +652,7 @@ impl MemoryDatabase {
             received: 0,
             sent: 0,
             fees: 0,
+            recipients: vec![],
         };
 
         self.set_tx(&tx_details).unwrap();
@@ -563,4 +719,24 @@ mod test {
     fn test_last_index() {
         crate::database::test::test_last_index(get_tree());
     }
+
+    #[test]
+    fn test_check_descriptor_checksum() {
+        crate::database::test::test_check_descriptor_checksum(get_tree());
+    }
+
+    #[test]
+    fn test_label() {
+        crate::database::test::test_label(get_tree());
+    }
+
+    #[test]
+    fn test_silent_payment_recipient() {
+        crate::database::test::test_silent_payment_recipient(get_tree());
+    }
+
+    #[test]
+    fn test_silent_payment_output() {
+        crate::database::test::test_silent_payment_output(get_tree());
+    }
 }
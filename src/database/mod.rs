@@ -42,6 +42,7 @@ use bitcoin::{OutPoint, Script, Transaction, TxOut};
 
 use crate::error::Error;
 use crate::types::*;
+use crate::wallet::silent_payments::{SilentPaymentAddress, SilentPaymentOutput};
 
 pub mod any;
 pub use any::{AnyDatabase, AnyDatabaseConfig};
@@ -72,6 +73,18 @@ pub trait BatchOperations {
     fn set_tx(&mut self, transaction: &TransactionDetails) -> Result<(), Error>;
     /// Store the last derivation index for a given keychain.
     fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error>;
+    /// Store the label attached to an object
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<(), Error>;
+    /// Store the silent payment address paid by an output with the given script_pubkey
+    fn set_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+        address: &SilentPaymentAddress,
+    ) -> Result<(), Error>;
+    /// Store a silent payment received by the wallet
+    fn set_silent_payment_output(&mut self, output: &SilentPaymentOutput) -> Result<(), Error>;
+    /// Store the height of the next block to scan for silent payments
+    fn set_silent_payment_scan_height(&mut self, height: u32) -> Result<(), Error>;
 
     /// Delete a script_pubkey given the keychain and its child number.
     fn del_script_pubkey_from_path(
@@ -97,6 +110,20 @@ pub trait BatchOperations {
     ) -> Result<Option<TransactionDetails>, Error>;
     /// Delete the last derivation index for a keychain.
     fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error>;
+    /// Delete the label attached to an object
+    fn del_label(&mut self, label_ref: &LabelRef) -> Result<Option<String>, Error>;
+    /// Delete the silent payment address paid by an output with the given script_pubkey
+    fn del_silent_payment_recipient(
+        &mut self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error>;
+    /// Delete a silent payment received by the wallet given its outpoint
+    fn del_silent_payment_output(
+        &mut self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error>;
+    /// Delete the height of the next block to scan for silent payments
+    fn del_silent_payment_scan_height(&mut self) -> Result<Option<u32>, Error>;
 }
 
 /// Trait for reading data from a database
@@ -122,6 +149,13 @@ pub trait Database: BatchOperations {
     fn iter_raw_txs(&self) -> Result<Vec<Transaction>, Error>;
     /// Return the list of transactions metadata
     fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error>;
+    /// Return the list of labels and the objects they are attached to
+    fn iter_labels(&self) -> Result<Vec<(LabelRef, String)>, Error>;
+    /// Return the list of script_pubkeys of the outputs paying a silent payment address, and
+    /// the addresses they pay
+    fn iter_silent_payment_recipients(&self) -> Result<Vec<(Script, SilentPaymentAddress)>, Error>;
+    /// Return the list of unspent silent payments received by the wallet
+    fn iter_silent_payment_outputs(&self) -> Result<Vec<SilentPaymentOutput>, Error>;
 
     /// Fetch a script_pubkey given the child number of a keychain.
     fn get_script_pubkey_from_path(
@@ -142,6 +176,20 @@ pub trait Database: BatchOperations {
     fn get_tx(&self, txid: &Txid, include_raw: bool) -> Result<Option<TransactionDetails>, Error>;
     /// Return the last defivation index for a keychain.
     fn get_last_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error>;
+    /// Fetch the label attached to an object
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>, Error>;
+    /// Fetch the silent payment address paid by an output with the given script_pubkey
+    fn get_silent_payment_recipient(
+        &self,
+        script: &Script,
+    ) -> Result<Option<SilentPaymentAddress>, Error>;
+    /// Fetch a silent payment received by the wallet given its outpoint
+    fn get_silent_payment_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<SilentPaymentOutput>, Error>;
+    /// Fetch the height of the next block to scan for silent payments
+    fn get_silent_payment_scan_height(&self) -> Result<Option<u32>, Error>;
 
     /// Increment the last derivation index for a keychain and return it
     ///
@@ -332,6 +380,7 @@ pub mod test {
             sent: 420420,
             fees: 140,
             height: Some(1000),
+            recipients: vec![],
         };
 
         tree.set_tx(&tx_details).unwrap();
@@ -379,5 +428,127 @@ pub mod test {
         );
     }
 
+    pub fn test_check_descriptor_checksum<D: Database>(mut tree: D) {
+        // the first time the checksum is simply stored
+        tree.check_descriptor_checksum(KeychainKind::External, b"y5jvx5s4")
+            .unwrap();
+        tree.check_descriptor_checksum(KeychainKind::External, b"y5jvx5s4")
+            .unwrap();
+
+        assert!(matches!(
+            tree.check_descriptor_checksum(KeychainKind::External, b"hsuhzezx"),
+            Err(crate::Error::ChecksumMismatch)
+        ));
+        // every keychain has its own checksum
+        tree.check_descriptor_checksum(KeychainKind::Internal, b"hsuhzezx")
+            .unwrap();
+    }
+
+    pub fn test_label<D: Database>(mut tree: D) {
+        let txid =
+            Txid::from_hex("fd3b9cea1c6b48a1d7ae9ba2de2e90e7e81e3d0ba8d7ad3e6b8ac9f6b8f7f0ae")
+                .unwrap();
+        let tx_ref = LabelRef::Transaction(txid);
+        let output_ref = LabelRef::Output(OutPoint { txid, vout: 0 });
+
+        tree.set_label(&tx_ref, "rent").unwrap();
+        tree.set_label(&output_ref, "change").unwrap();
+        assert_eq!(tree.get_label(&tx_ref).unwrap(), Some("rent".to_string()));
+        assert_eq!(
+            tree.get_label(&LabelRef::Input(OutPoint { txid, vout: 0 }))
+                .unwrap(),
+            None
+        );
+        assert_eq!(tree.iter_labels().unwrap().len(), 2);
+
+        tree.set_label(&tx_ref, "rent (march)").unwrap();
+        assert_eq!(
+            tree.get_label(&tx_ref).unwrap(),
+            Some("rent (march)".to_string())
+        );
+
+        assert_eq!(
+            tree.del_label(&output_ref).unwrap(),
+            Some("change".to_string())
+        );
+        assert_eq!(
+            tree.iter_labels().unwrap(),
+            vec![(tx_ref, "rent (march)".to_string())]
+        );
+    }
+
+    pub fn test_silent_payment_recipient<D: Database>(mut tree: D) {
+        let address = SilentPaymentAddress::from_str("tsp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc3wk4yh").unwrap();
+        let script = Script::from(
+            Vec::<u8>::from_hex(
+                "51205a1ca9f2f5f5e7f2a6a1bb4e4d3b6dff2a5ce9e1bd4e0a1e4f2e6c3f6a7e9b0d",
+            )
+            .unwrap(),
+        );
+
+        tree.set_silent_payment_recipient(&script, &address)
+            .unwrap();
+        assert_eq!(
+            tree.get_silent_payment_recipient(&script).unwrap(),
+            Some(address)
+        );
+        assert_eq!(
+            tree.get_silent_payment_recipient(&Script::new()).unwrap(),
+            None
+        );
+        assert_eq!(
+            tree.iter_silent_payment_recipients().unwrap(),
+            vec![(script.clone(), address)]
+        );
+
+        assert_eq!(
+            tree.del_silent_payment_recipient(&script).unwrap(),
+            Some(address)
+        );
+        assert!(tree.iter_silent_payment_recipients().unwrap().is_empty());
+    }
+
+    pub fn test_silent_payment_output<D: Database>(mut tree: D) {
+        let outpoint = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+        )
+        .unwrap();
+        let output = SilentPaymentOutput {
+            outpoint,
+            txout: TxOut {
+                value: 133742,
+                script_pubkey: Script::from(
+                    Vec::<u8>::from_hex(
+                        "51205a1ca9f2f5f5e7f2a6a1bb4e4d3b6dff2a5ce9e1bd4e0a1e4f2e6c3f6a7e9b0d",
+                    )
+                    .unwrap(),
+                ),
+            },
+            tweak: [0x42; 32],
+            height: Some(1000),
+        };
+
+        tree.set_silent_payment_output(&output).unwrap();
+        assert_eq!(
+            tree.get_silent_payment_output(&outpoint).unwrap(),
+            Some(output.clone())
+        );
+        assert_eq!(
+            tree.iter_silent_payment_outputs().unwrap(),
+            vec![output.clone()]
+        );
+        assert_eq!(
+            tree.del_silent_payment_output(&outpoint).unwrap(),
+            Some(output)
+        );
+        assert_eq!(tree.get_silent_payment_output(&outpoint).unwrap(), None);
+
+        assert_eq!(tree.get_silent_payment_scan_height().unwrap(), None);
+        tree.set_silent_payment_scan_height(1001).unwrap();
+        assert_eq!(tree.get_silent_payment_scan_height().unwrap(), Some(1001));
+        assert_eq!(tree.del_silent_payment_scan_height().unwrap(), Some(1001));
+        assert_eq!(tree.get_silent_payment_scan_height().unwrap(), None);
+    }
+
     // TODO: more tests...
 }
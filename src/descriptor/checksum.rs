@@ -93,6 +93,25 @@ pub fn get_checksum(desc: &str) -> Result<String, Error> {
     Ok(String::from_iter(chars))
 }
 
+/// Verify the checksum of a descriptor, if present, and return the descriptor without it
+pub fn strip_checksum(desc: &str) -> Result<&str, Error> {
+    match desc.find('#') {
+        Some(pos) if get_checksum(&desc[..pos])? != desc[pos + 1..] => {
+            Err(Error::InvalidDescriptorChecksum)
+        }
+        Some(pos) => Ok(&desc[..pos]),
+        None => Ok(desc),
+    }
+}
+
+/// Return the descriptor followed by its checksum
+///
+/// The checksum is verified if already present, or computed and appended otherwise.
+pub fn add_checksum(desc: &str) -> Result<String, Error> {
+    let desc = strip_checksum(desc)?;
+    Ok(format!("{}#{}", desc, get_checksum(desc)?))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -108,6 +127,27 @@ mod test {
         assert_eq!(get_checksum(desc).unwrap(), "lasegmfs");
     }
 
+    #[test]
+    fn test_add_strip_checksum() {
+        let desc = "wpkh(tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N/1/2/*)";
+        let with_checksum = format!("{}#tqz0nc62", desc);
+
+        assert_eq!(add_checksum(desc).unwrap(), with_checksum);
+        assert_eq!(add_checksum(&with_checksum).unwrap(), with_checksum);
+        assert_eq!(strip_checksum(&with_checksum).unwrap(), desc);
+        assert_eq!(strip_checksum(desc).unwrap(), desc);
+
+        let invalid = format!("{}#tqz0nc63", desc);
+        assert!(matches!(
+            strip_checksum(&invalid),
+            Err(Error::InvalidDescriptorChecksum)
+        ));
+        assert!(matches!(
+            add_checksum(&invalid),
+            Err(Error::InvalidDescriptorChecksum)
+        ));
+    }
+
     #[test]
     fn test_get_checksum_invalid_character() {
         let sparkle_heart = vec![240, 159, 146, 150];
@@ -0,0 +1,128 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Policy compiler
+//!
+//! This module, enabled by the `compiler` feature, compiles spending policies written in the
+//! [policy language](http://bitcoin.sipa.be/miniscript/) into descriptors that can be used to
+//! create a [`Wallet`](crate::Wallet), using the compiler of [`miniscript`].
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::descriptor::compiler::compile_policy;
+//! # use bdk::{OfflineWallet, ScriptType, Wallet};
+//! // Alice can spend at any time, Bob only after 144 blocks
+//! let descriptor = compile_policy(
+//!     "or(99@pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),and(pk(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*),older(144)))",
+//!     ScriptType::P2wsh,
+//! )?;
+//!
+//! let wallet: OfflineWallet<_> =
+//!     Wallet::new_offline(&descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! # Ok::<_, bdk::Error>(())
+//! ```
+
+use std::str::FromStr;
+
+use miniscript::policy::Concrete;
+use miniscript::Descriptor;
+
+use super::error::Error;
+use crate::types::ScriptType;
+
+/// Compile a spending `policy` into a descriptor of type `script_type`
+///
+/// The script type also selects the script context used by the compiler: [`ScriptType::P2sh`]
+/// compiles for the legacy context, while [`ScriptType::P2wsh`] and [`ScriptType::P2shP2wsh`]
+/// compile for segwit v0, which is usually cheaper to spend. Any other script type returns
+/// [`Error::InvalidCompilerScriptType`].
+///
+/// The keys are copied verbatim from the policy, so they can be anything accepted in a descriptor,
+/// including extended keys with a derivation path and private keys.
+pub fn compile_policy(policy: &str, script_type: ScriptType) -> Result<String, Error> {
+    let policy = Concrete::<String>::from_str(policy)?;
+
+    let descriptor = match script_type {
+        ScriptType::P2sh => Descriptor::Sh(policy.compile()?),
+        ScriptType::P2wsh => Descriptor::Wsh(policy.compile()?),
+        ScriptType::P2shP2wsh => Descriptor::ShWsh(policy.compile()?),
+        other => return Err(Error::InvalidCompilerScriptType(other)),
+    };
+
+    Ok(descriptor.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::Network;
+
+    use super::*;
+    use crate::database::MemoryDatabase;
+    use crate::{OfflineWallet, Wallet};
+
+    fn get_first_address(descriptor: &str) -> bitcoin::Address {
+        let wallet: OfflineWallet<_> =
+            Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::new()).unwrap();
+        wallet.get_new_address().unwrap()
+    }
+
+    #[test]
+    fn test_compile_policy() {
+        let policy = "pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)";
+
+        let wsh = compile_policy(policy, ScriptType::P2wsh).unwrap();
+        assert!(wsh.starts_with("wsh("));
+        assert_eq!(
+            get_first_address(&wsh),
+            get_first_address("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))")
+        );
+
+        let sh = compile_policy(policy, ScriptType::P2sh).unwrap();
+        assert_eq!(
+            get_first_address(&sh),
+            get_first_address("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))")
+        );
+
+        let sh_wsh = compile_policy(policy, ScriptType::P2shP2wsh).unwrap();
+        assert!(sh_wsh.starts_with("sh(wsh("));
+    }
+
+    #[test]
+    fn test_compile_policy_errors() {
+        assert!(matches!(
+            compile_policy(
+                "pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)",
+                ScriptType::P2wpkh
+            ),
+            Err(Error::InvalidCompilerScriptType(ScriptType::P2wpkh))
+        ));
+        assert!(matches!(
+            compile_policy("pk(A", ScriptType::P2wsh),
+            Err(Error::Miniscript(_))
+        ));
+    }
+}
@@ -45,6 +45,15 @@ pub enum Error {
     //MissingDetails,
     /// Invalid character found in the descriptor checksum
     InvalidDescriptorCharacter(char),
+    /// The checksum of the descriptor doesn't match
+    InvalidDescriptorChecksum,
+    /// The script type can't embed a policy compiled with
+    /// [`compile_policy`](crate::descriptor::compiler::compile_policy)
+    #[cfg(feature = "compiler")]
+    InvalidCompilerScriptType(crate::types::ScriptType),
+    /// Error while compiling a policy
+    #[cfg(feature = "compiler")]
+    Compiler(miniscript::policy::compiler::CompilerError),
 
     //CantDeriveWithMiniscript,
     /// BIP32 error
@@ -83,3 +92,5 @@ impl_error!(bitcoin::util::key::Error, PK);
 impl_error!(miniscript::Error, Miniscript);
 impl_error!(bitcoin::hashes::hex::Error, Hex);
 impl_error!(crate::descriptor::policy::PolicyError, Policy);
+#[cfg(feature = "compiler")]
+impl_error!(miniscript::policy::compiler::CompilerError, Compiler);
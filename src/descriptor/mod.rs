@@ -27,32 +27,44 @@
 //! This module contains generic utilities to work with descriptors, plus some re-exported types
 //! from [`miniscript`].
 
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
+use std::ops::Range;
 
-use bitcoin::secp256k1::Secp256k1;
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{hash160, Hash};
+use bitcoin::secp256k1::{self, Secp256k1};
 use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint, KeySource};
 use bitcoin::util::psbt;
-use bitcoin::{Network, PublicKey, Script, TxOut};
+use bitcoin::{Network, PublicKey, Script, SigHashType, TxIn, TxOut};
 
-use miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey, InnerXKey};
+use miniscript::descriptor::{
+    DescriptorPublicKey, DescriptorSecretKey, DescriptorSinglePriv, DescriptorXKey, InnerXKey,
+};
 pub use miniscript::{
     descriptor::KeyMap, Descriptor, Legacy, Miniscript, MiniscriptKey, ScriptContext, Segwitv0,
     Terminal, ToPublicKey,
 };
+use miniscript::{BitcoinSig, Satisfier};
 
 pub mod checksum;
+#[cfg(feature = "compiler")]
+#[cfg_attr(docsrs, doc(cfg(feature = "compiler")))]
+pub mod compiler;
 #[doc(hidden)]
 pub mod dsl;
 pub mod error;
 pub mod policy;
 pub mod template;
+pub mod wallet_policy;
 
-pub use self::checksum::get_checksum;
+pub use self::checksum::{add_checksum, get_checksum, strip_checksum};
 use self::error::Error;
 pub use self::policy::Policy;
 use self::template::DescriptorTemplateOut;
+use crate::keys::slip132::replace_slip132_keys;
 use crate::keys::{KeyError, ToDescriptorKey};
+use crate::types::ScriptType;
 use crate::wallet::signer::SignersContainer;
 use crate::wallet::utils::{descriptor_to_pk_ctx, SecpCtx};
 
@@ -66,6 +78,12 @@ pub type ExtendedDescriptor = Descriptor<DescriptorPublicKey>;
 /// [`psbt::Output`]: bitcoin::util::psbt::Output
 pub type HDKeyPaths = BTreeMap<PublicKey, KeySource>;
 
+// Hardened wildcards (`/*'` or `/*h`) can't be derived from extended public keys, and miniscript
+// doesn't support them at all: it rejects them with a generic parse error
+fn has_hardened_wildcard(descriptor: &str) -> bool {
+    descriptor.contains("/*'") || descriptor.contains("/*h")
+}
+
 /// Trait for types which can be converted into an [`ExtendedDescriptor`] and a [`KeyMap`] usable by a wallet in a specific [`Network`]
 pub trait ToWalletDescriptor {
     /// Convert to wallet descriptor
@@ -73,6 +91,27 @@ pub trait ToWalletDescriptor {
         self,
         network: Network,
     ) -> Result<(ExtendedDescriptor, KeyMap), KeyError>;
+
+    /// Convert to the external wallet descriptor and, if the descriptor contains multipath
+    /// derivation steps like `<0;1>`, also to the internal one
+    ///
+    /// The default implementation never returns an internal descriptor.
+    #[allow(clippy::type_complexity)]
+    fn to_wallet_descriptors(
+        self,
+        network: Network,
+    ) -> Result<
+        (
+            (ExtendedDescriptor, KeyMap),
+            Option<(ExtendedDescriptor, KeyMap)>,
+        ),
+        KeyError,
+    >
+    where
+        Self: Sized,
+    {
+        Ok((self.to_wallet_descriptor(network)?, None))
+    }
 }
 
 impl ToWalletDescriptor for &str {
@@ -80,22 +119,48 @@ impl ToWalletDescriptor for &str {
         self,
         network: Network,
     ) -> Result<(ExtendedDescriptor, KeyMap), KeyError> {
-        let descriptor = if self.contains('#') {
-            let parts: Vec<&str> = self.splitn(2, '#').collect();
-            if !get_checksum(parts[0])
-                .ok()
-                .map(|computed| computed == parts[1])
-                .unwrap_or(false)
-            {
-                return Err(KeyError::InvalidChecksum);
-            }
+        let descriptor = strip_checksum(self).map_err(|_| KeyError::InvalidChecksum)?;
+        let (descriptor, slip132_script_types) = replace_slip132_keys(descriptor)?;
+        let (descriptor, keymap) =
+            ExtendedDescriptor::parse_descriptor(&descriptor).map_err(|e| {
+                match has_hardened_wildcard(&descriptor) {
+                    true => KeyError::HardenedDerivationXpub,
+                    false => e.into(),
+                }
+            })?;
 
-            parts[0]
-        } else {
-            self
-        };
+        // the prefix of SLIP-132 keys must agree with the type of the descriptor
+        let descriptor_script_type = descriptor.script_type();
+        if let Some(key_script_type) = slip132_script_types
+            .into_iter()
+            .find(|script_type| *script_type != descriptor_script_type)
+        {
+            return Err(KeyError::ScriptTypeMismatch {
+                key_script_type,
+                descriptor_script_type,
+            });
+        }
 
-        ExtendedDescriptor::parse_descriptor(descriptor)?.to_wallet_descriptor(network)
+        (descriptor, keymap).to_wallet_descriptor(network)
+    }
+
+    fn to_wallet_descriptors(
+        self,
+        network: Network,
+    ) -> Result<
+        (
+            (ExtendedDescriptor, KeyMap),
+            Option<(ExtendedDescriptor, KeyMap)>,
+        ),
+        KeyError,
+    > {
+        match split_multipath_descriptor(self)? {
+            Some((external, internal)) => Ok((
+                external.as_str().to_wallet_descriptor(network)?,
+                Some(internal.as_str().to_wallet_descriptor(network)?),
+            )),
+            None => Ok((self.to_wallet_descriptor(network)?, None)),
+        }
     }
 }
 
@@ -106,6 +171,19 @@ impl ToWalletDescriptor for &String {
     ) -> Result<(ExtendedDescriptor, KeyMap), KeyError> {
         self.as_str().to_wallet_descriptor(network)
     }
+
+    fn to_wallet_descriptors(
+        self,
+        network: Network,
+    ) -> Result<
+        (
+            (ExtendedDescriptor, KeyMap),
+            Option<(ExtendedDescriptor, KeyMap)>,
+        ),
+        KeyError,
+    > {
+        self.as_str().to_wallet_descriptors(network)
+    }
 }
 
 impl ToWalletDescriptor for ExtendedDescriptor {
@@ -188,6 +266,168 @@ impl ToWalletDescriptor for DescriptorTemplateOut {
     }
 }
 
+/// Derive the conventional internal descriptor from an external one
+///
+/// Following the structure defined in BIP44, every extended key that derives from `.../0/*` is
+/// replaced with the same key deriving from `.../1/*`, both in the `descriptor` and in the
+/// `keymap`. Single keys are left untouched.
+///
+/// Returns `None` if the descriptor doesn't contain any extended key, or if any of them doesn't
+/// follow this structure.
+///
+/// ## Example
+///
+/// ```
+/// # use bdk::bitcoin::Network;
+/// # use bdk::descriptor::{derive_internal_descriptor, ToWalletDescriptor};
+/// let (descriptor, keymap) = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)"
+///     .to_wallet_descriptor(Network::Testnet)?;
+/// let (internal, _) = derive_internal_descriptor(&descriptor, &keymap).unwrap();
+/// assert_eq!(internal.to_string(), "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/*)");
+/// # Ok::<_, bdk::Error>(())
+/// ```
+pub fn derive_internal_descriptor(
+    descriptor: &ExtendedDescriptor,
+    keymap: &KeyMap,
+) -> Option<(ExtendedDescriptor, KeyMap)> {
+    fn internal_path(path: &DerivationPath, is_wildcard: bool) -> Option<DerivationPath> {
+        match path.as_ref().split_last() {
+            Some((ChildNumber::Normal { index: 0 }, parent)) if is_wildcard => {
+                let mut path = parent.to_vec();
+                path.push(ChildNumber::Normal { index: 1 });
+
+                Some(path.into())
+            }
+            _ => None,
+        }
+    }
+
+    let translate_key = |pk: &DescriptorPublicKey| match pk {
+        DescriptorPublicKey::XPub(xpub) => Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+            derivation_path: internal_path(&xpub.derivation_path, xpub.is_wildcard).ok_or(())?,
+            ..xpub.clone()
+        })),
+        other => Ok::<_, ()>(other.clone()),
+    };
+
+    if descriptor.get_extended_keys().ok()?.is_empty() {
+        return None;
+    }
+    let internal_descriptor = descriptor.translate_pk(translate_key, translate_key).ok()?;
+
+    let internal_keymap = keymap
+        .iter()
+        .map(|(pk, sk)| {
+            let sk = match sk {
+                DescriptorSecretKey::XPrv(xprv) => DescriptorSecretKey::XPrv(DescriptorXKey {
+                    derivation_path: internal_path(&xprv.derivation_path, xprv.is_wildcard)?,
+                    ..xprv.clone()
+                }),
+                DescriptorSecretKey::SinglePriv(single) => {
+                    DescriptorSecretKey::SinglePriv(DescriptorSinglePriv {
+                        origin: single.origin.clone(),
+                        key: single.key,
+                    })
+                }
+            };
+
+            Some((translate_key(pk).ok()?, sk))
+        })
+        .collect::<Option<KeyMap>>()?;
+
+    Some((internal_descriptor, internal_keymap))
+}
+
+/// Derive the `script_pubkey` of a descriptor for every child index in `range`
+///
+/// The scripts are returned in the same order as the indexes. With the `parallel-derivation`
+/// feature they are derived on the global thread pool of [rayon](https://docs.rs/rayon), which
+/// makes caching thousands of addresses during the first sync of a wallet much faster on
+/// multi-core machines.
+///
+/// ## Example
+///
+/// ```
+/// # use bdk::bitcoin::secp256k1::Secp256k1;
+/// # use bdk::bitcoin::Network;
+/// # use bdk::descriptor::{derive_script_pubkeys, ToWalletDescriptor};
+/// let secp = Secp256k1::new();
+/// let (descriptor, _) = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)"
+///     .to_wallet_descriptor(Network::Testnet)?;
+/// let scripts = derive_script_pubkeys(&descriptor, 0..100, &secp)?;
+/// assert_eq!(scripts.len(), 100);
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub fn derive_script_pubkeys(
+    descriptor: &ExtendedDescriptor,
+    range: Range<u32>,
+    secp: &SecpCtx,
+) -> Result<Vec<Script>, Error> {
+    let derive = |index: u32| -> Result<Script, Error> {
+        let deriv_ctx = descriptor_to_pk_ctx(secp);
+        Ok(descriptor
+            .derive(ChildNumber::from_normal_idx(index)?)
+            .script_pubkey(deriv_ctx))
+    };
+
+    #[cfg(feature = "parallel-derivation")]
+    use rayon::prelude::*;
+    #[cfg(feature = "parallel-derivation")]
+    let range = range.into_par_iter();
+
+    range.map(derive).collect()
+}
+
+/// Split a descriptor with multipath derivation steps like `<0;1>`, defined in
+/// [BIP389](https://github.com/bitcoin/bips/blob/master/bip-0389.mediawiki), into the external and
+/// internal descriptors
+///
+/// Every multipath step must have exactly two elements: the first one is used for the external
+/// descriptor and the second one for the internal descriptor. The checksum, if present, is
+/// verified and removed. Returns `None` if the descriptor doesn't contain any multipath step.
+///
+/// ## Example
+///
+/// ```
+/// # use bdk::descriptor::split_multipath_descriptor;
+/// let (external, internal) = split_multipath_descriptor("wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/<0;1>/*)")?
+///     .unwrap();
+/// assert_eq!(external, "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)");
+/// assert_eq!(internal, "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/1/*)");
+/// # Ok::<_, bdk::Error>(())
+/// ```
+pub fn split_multipath_descriptor(descriptor: &str) -> Result<Option<(String, String)>, KeyError> {
+    let mut rest = strip_checksum(descriptor).map_err(|_| KeyError::InvalidChecksum)?;
+    if !rest.contains('<') {
+        return Ok(None);
+    }
+
+    let (mut external, mut internal) = (String::new(), String::new());
+    while let Some(start) = rest.find('<') {
+        let end = rest[start..]
+            .find('>')
+            .map(|end| start + end)
+            .ok_or_else(|| KeyError::Message("Unterminated multipath step".to_string()))?;
+        let steps = rest[start + 1..end].split(';').collect::<Vec<_>>();
+        if steps.len() != 2 {
+            return Err(KeyError::Message(format!(
+                "Multipath step `{}` must have two elements, one for each keychain",
+                &rest[start..=end]
+            )));
+        }
+
+        external.push_str(&rest[..start]);
+        external.push_str(steps[0]);
+        internal.push_str(&rest[..start]);
+        internal.push_str(steps[1]);
+        rest = &rest[end + 1..];
+    }
+    external.push_str(rest);
+    internal.push_str(rest);
+
+    Ok(Some((external, internal)))
+}
+
 /// Trait implemented on [`Descriptor`]s to add a method to extract the spending [`policy`]
 pub trait ExtractPolicy {
     /// Extract the spending [`policy`]
@@ -198,6 +438,176 @@ pub trait ExtractPolicy {
     ) -> Result<Option<Policy>, Error>;
 }
 
+/// Trait implemented on [`Descriptor`]s to inspect the origin of their keys
+///
+/// This can be used to tell which of the participants of a multisig wallet a key belongs to,
+/// for example to show which devices have already signed a PSBT.
+pub trait DescriptorKeyOrigins {
+    /// Return every key in the descriptor together with its origin
+    ///
+    /// Keys without an explicit origin are considered the root of their own derivation, so they
+    /// are returned with their own fingerprint and an empty path.
+    fn key_origins(&self, secp: &SecpCtx) -> Vec<(DescriptorPublicKey, KeySource)>;
+
+    /// Return the key in the descriptor that corresponds to an entry of the `hd_keypaths` map of a
+    /// [`psbt::Input`] or [`psbt::Output`]
+    ///
+    /// An extended key only matches if deriving it along the path in `key_source` produces
+    /// `public_key`.
+    fn find_key_for_keypath(
+        &self,
+        public_key: &PublicKey,
+        key_source: &KeySource,
+        secp: &SecpCtx,
+    ) -> Option<DescriptorPublicKey>;
+}
+
+impl DescriptorKeyOrigins for Descriptor<DescriptorPublicKey> {
+    fn key_origins(&self, secp: &SecpCtx) -> Vec<(DescriptorPublicKey, KeySource)> {
+        self.get_keys()
+            .unwrap()
+            .into_iter()
+            .map(|key| {
+                let key_source = match &key {
+                    DescriptorPublicKey::XPub(xpub) => {
+                        (xpub.root_fingerprint(secp), xpub.full_path(&[]))
+                    }
+                    DescriptorPublicKey::SinglePub(single) => match &single.origin {
+                        Some(origin) => origin.clone(),
+                        None => {
+                            let hash = hash160::Hash::hash(&single.key.to_bytes());
+                            (Fingerprint::from(&hash[..4]), DerivationPath::from(vec![]))
+                        }
+                    },
+                };
+
+                (key, key_source)
+            })
+            .collect()
+    }
+
+    fn find_key_for_keypath(
+        &self,
+        public_key: &PublicKey,
+        key_source: &KeySource,
+        secp: &SecpCtx,
+    ) -> Option<DescriptorPublicKey> {
+        let is_match = |key: &DescriptorPublicKey| match key {
+            DescriptorPublicKey::SinglePub(single) => &single.key == public_key,
+            DescriptorPublicKey::XPub(xpub) => {
+                if xpub.matches(key_source, secp).is_none() {
+                    return false;
+                }
+
+                // the steps after the origin are the ones that have to be derived from `xkey`
+                let origin_len = xpub
+                    .origin
+                    .as_ref()
+                    .map_or(0, |(_, path)| path.into_iter().count());
+                let derive_path: Vec<ChildNumber> =
+                    key_source.1.into_iter().skip(origin_len).cloned().collect();
+
+                match xpub.xkey.derive_pub(secp, &derive_path) {
+                    Ok(derived) => &derived.public_key == public_key,
+                    Err(_) => false,
+                }
+            }
+        };
+
+        self.get_keys().unwrap().into_iter().find(is_match)
+    }
+}
+
+/// Spending branch of a descriptor, used to estimate the weight of a satisfaction with
+/// [`SatisfactionWeight::branch_satisfaction_weight`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingBranch {
+    /// Fingerprints of the keys that will sign, as returned by
+    /// [`DescriptorKeyOrigins::key_origins`]
+    pub signers: Vec<Fingerprint>,
+    /// Relative timelock (`older`) satisfied by the input, if any
+    pub older: Option<u32>,
+    /// Absolute timelock (`after`) satisfied by the transaction, if any
+    pub after: Option<u32>,
+}
+
+/// Trait implemented on [`Descriptor`]s to estimate the weight of the `scriptSig` and witness
+/// needed to spend their outputs
+///
+/// This is the same model used by the wallet to compute the fees of a transaction, so it can be
+/// used to estimate the weight of foreign inputs or of inputs spent by other parties in a
+/// multi-party transaction. Signatures are assumed to be 72 bytes long, including the sighash.
+pub trait SatisfactionWeight {
+    /// Return the maximum weight over every way of satisfying the descriptor, or `None` if it
+    /// can't be satisfied
+    fn satisfaction_weight(&self, secp: &SecpCtx) -> Option<usize>;
+
+    /// Return the weight of the satisfaction that uses the keys and timelocks in `branch`, or
+    /// `None` if they are not enough to satisfy the descriptor
+    ///
+    /// When there's more than one way of satisfying the descriptor with `branch`, the weight of
+    /// the cheapest one is returned.
+    fn branch_satisfaction_weight(&self, branch: &SpendingBranch, secp: &SecpCtx) -> Option<usize>;
+}
+
+impl SatisfactionWeight for Descriptor<DescriptorPublicKey> {
+    fn satisfaction_weight(&self, secp: &SecpCtx) -> Option<usize> {
+        self.max_satisfaction_weight(descriptor_to_pk_ctx(secp))
+    }
+
+    fn branch_satisfaction_weight(&self, branch: &SpendingBranch, secp: &SecpCtx) -> Option<usize> {
+        let deriv_ctx = descriptor_to_pk_ctx(secp);
+        let signers = self
+            .key_origins(secp)
+            .into_iter()
+            .filter(|(_, (fingerprint, _))| branch.signers.contains(fingerprint))
+            .map(|(key, _)| key.to_public_key(deriv_ctx))
+            .collect();
+        let satisfier = BranchSatisfier {
+            signers,
+            older: branch.older,
+            after: branch.after,
+        };
+
+        let mut txin = TxIn::default();
+        self.satisfy(&mut txin, satisfier, deriv_ctx).ok()?;
+        Some(serialize(&txin.script_sig).len() * 4 + serialize(&txin.witness).len())
+    }
+}
+
+// Satisfier that produces dummy signatures for the keys of a spending branch
+struct BranchSatisfier {
+    signers: HashSet<PublicKey>,
+    older: Option<u32>,
+    after: Option<u32>,
+}
+
+impl<ToPkCtx: Copy, Pk: MiniscriptKey + ToPublicKey<ToPkCtx>> Satisfier<ToPkCtx, Pk>
+    for BranchSatisfier
+{
+    fn lookup_sig(&self, pk: &Pk, to_pk_ctx: ToPkCtx) -> Option<BitcoinSig> {
+        if !self.signers.contains(&pk.to_public_key(to_pk_ctx)) {
+            return None;
+        }
+
+        // the largest low-s signature: 33 bytes for `r`, 32 bytes for `s`
+        let mut compact = [0; 64];
+        compact[0] = 0x80;
+        compact[32] = 0x7F;
+        let signature = secp256k1::Signature::from_compact(&compact).expect("Valid signature");
+
+        Some((signature, SigHashType::All))
+    }
+
+    fn check_older(&self, n: u32) -> bool {
+        self.older.map_or(false, |older| n <= older)
+    }
+
+    fn check_after(&self, n: u32) -> bool {
+        self.after.map_or(false, |after| n <= after)
+    }
+}
+
 pub(crate) trait XKeyUtils {
     fn full_path(&self, append: &[ChildNumber]) -> DerivationPath;
     fn root_fingerprint(&self, secp: &SecpCtx) -> Fingerprint;
@@ -235,8 +645,10 @@ impl<K: InnerXKey> XKeyUtils for DescriptorXKey<K> {
 
 pub(crate) trait DescriptorMeta: Sized {
     fn is_witness(&self) -> bool;
+    fn script_type(&self) -> ScriptType;
     fn get_hd_keypaths(&self, index: u32, secp: &SecpCtx) -> Result<HDKeyPaths, Error>;
     fn get_extended_keys(&self) -> Result<Vec<DescriptorXKey<ExtendedPubKey>>, Error>;
+    fn get_keys(&self) -> Result<Vec<DescriptorPublicKey>, Error>;
     fn is_fixed(&self) -> bool;
     fn derive_from_hd_keypaths(&self, hd_keypaths: &HDKeyPaths, secp: &SecpCtx) -> Option<Self>;
     fn derive_from_psbt_input(
@@ -262,6 +674,7 @@ impl DescriptorScripts for Descriptor<DescriptorPublicKey> {
             Descriptor::Sh(ref script) => Some(script.encode(deriv_ctx)),
             Descriptor::Bare(ref script) => Some(script.encode(deriv_ctx)),
             Descriptor::ShSortedMulti(ref keys) => Some(keys.encode(deriv_ctx)),
+            Descriptor::ShWshSortedMulti(ref keys) => Some(keys.encode(deriv_ctx).to_v0_p2wsh()),
             _ => None,
         }
     }
@@ -297,6 +710,19 @@ impl DescriptorMeta for Descriptor<DescriptorPublicKey> {
         }
     }
 
+    fn script_type(&self) -> ScriptType {
+        match self {
+            Descriptor::Bare(_) => ScriptType::Bare,
+            Descriptor::Pk(_) => ScriptType::P2pk,
+            Descriptor::Pkh(_) => ScriptType::P2pkh,
+            Descriptor::Sh(_) | Descriptor::ShSortedMulti(_) => ScriptType::P2sh,
+            Descriptor::Wpkh(_) => ScriptType::P2wpkh,
+            Descriptor::Wsh(_) | Descriptor::WshSortedMulti(_) => ScriptType::P2wsh,
+            Descriptor::ShWpkh(_) => ScriptType::P2shP2wpkh,
+            Descriptor::ShWsh(_) | Descriptor::ShWshSortedMulti(_) => ScriptType::P2shP2wsh,
+        }
+    }
+
     fn get_hd_keypaths(&self, index: u32, secp: &SecpCtx) -> Result<HDKeyPaths, Error> {
         let translate_key = |key: &DescriptorPublicKey,
                              index: u32,
@@ -368,6 +794,28 @@ impl DescriptorMeta for Descriptor<DescriptorPublicKey> {
         Ok(answer_pk)
     }
 
+    fn get_keys(&self) -> Result<Vec<DescriptorPublicKey>, Error> {
+        let get_key = |key: &DescriptorPublicKey,
+                       keys: &mut Vec<DescriptorPublicKey>|
+         -> Result<DummyKey, Error> {
+            keys.push(key.clone());
+
+            Ok(DummyKey::default())
+        };
+
+        let mut answer_pk = Vec::new();
+        let mut answer_pkh = Vec::new();
+
+        self.translate_pk(
+            |pk| get_key(pk, &mut answer_pk),
+            |pkh| get_key(pkh, &mut answer_pkh),
+        )?;
+
+        answer_pk.append(&mut answer_pkh);
+
+        Ok(answer_pk)
+    }
+
     fn is_fixed(&self) -> bool {
         fn check_key(key: &DescriptorPublicKey, flag: &mut bool) -> Result<DummyKey, Error> {
             match key {
@@ -563,6 +1011,176 @@ mod test {
     use super::*;
     use crate::psbt::PSBTUtils;
 
+    #[test]
+    fn test_derive_script_pubkeys() {
+        let secp = Secp256k1::new();
+        let (descriptor, _) = "wsh(sortedmulti(2,tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*,tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*))"
+            .to_wallet_descriptor(Network::Testnet)
+            .unwrap();
+
+        let scripts = derive_script_pubkeys(&descriptor, 10..60, &secp).unwrap();
+        assert_eq!(scripts.len(), 50);
+        // the scripts are in the same order as the indexes
+        for (index, script) in (10..).zip(scripts.iter()) {
+            let expected = descriptor
+                .derive(ChildNumber::from_normal_idx(index).unwrap())
+                .script_pubkey(descriptor_to_pk_ctx(&secp));
+            assert_eq!(script, &expected);
+        }
+
+        assert!(matches!(
+            derive_script_pubkeys(&descriptor, (1 << 31)..(1 << 31) + 1, &secp),
+            Err(Error::BIP32(_))
+        ));
+    }
+
+    #[test]
+    fn test_split_multipath_descriptor() {
+        let descriptor = "wsh(multi(2,tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/<0;1>/*,tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<2;3>/*))";
+        let (external, internal) = split_multipath_descriptor(descriptor).unwrap().unwrap();
+        assert_eq!(external, "wsh(multi(2,tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*,tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/2/*))");
+        assert_eq!(internal, "wsh(multi(2,tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/*,tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/3/*))");
+
+        let with_checksum = format!("{}#{}", descriptor, get_checksum(descriptor).unwrap());
+        assert!(split_multipath_descriptor(&with_checksum)
+            .unwrap()
+            .is_some());
+
+        assert!(split_multipath_descriptor(
+            "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)"
+        )
+        .unwrap()
+        .is_none());
+        assert!(matches!(
+            split_multipath_descriptor("wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/<0;1;2>/*)"),
+            Err(KeyError::Message(_))
+        ));
+    }
+
+    #[test]
+    fn test_satisfaction_weight() {
+        let secp = Secp256k1::new();
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(
+            "wpkh([0f056943/84'/1'/0'/0/0]02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737)",
+        )
+        .unwrap();
+
+        let max_weight = descriptor.satisfaction_weight(&secp).unwrap();
+        let branch = SpendingBranch {
+            signers: vec![Fingerprint::from_str("0f056943").unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(
+            descriptor.branch_satisfaction_weight(&branch, &secp),
+            Some(max_weight)
+        );
+        assert_eq!(
+            descriptor.branch_satisfaction_weight(&SpendingBranch::default(), &secp),
+            None
+        );
+    }
+
+    #[test]
+    fn test_branch_satisfaction_weight() {
+        let secp = Secp256k1::new();
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(
+            "wsh(or_d(pk([0f056943/48'/1'/0'/2']02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737),and_v(v:pk([73756c7f/48'/1'/0'/2']02864bb4ad00cefa806098a69e192bbda937494e69eb452b87bb3f20f6283baedb),older(144))))",
+        )
+        .unwrap();
+        let alice = Fingerprint::from_str("0f056943").unwrap();
+        let bob = Fingerprint::from_str("73756c7f").unwrap();
+
+        let alice_weight = descriptor
+            .branch_satisfaction_weight(
+                &SpendingBranch {
+                    signers: vec![alice],
+                    ..Default::default()
+                },
+                &secp,
+            )
+            .unwrap();
+
+        // bob can only spend after the timelock
+        let bob_branch = SpendingBranch {
+            signers: vec![bob],
+            older: Some(144),
+            after: None,
+        };
+        let bob_weight = descriptor
+            .branch_satisfaction_weight(&bob_branch, &secp)
+            .unwrap();
+        assert_eq!(
+            descriptor.branch_satisfaction_weight(
+                &SpendingBranch {
+                    older: None,
+                    ..bob_branch
+                },
+                &secp
+            ),
+            None
+        );
+
+        // the second branch also has to dissatisfy the first one
+        assert!(alice_weight < bob_weight);
+        assert!(bob_weight <= descriptor.satisfaction_weight(&secp).unwrap());
+    }
+
+    #[test]
+    fn test_key_origins() {
+        let secp = Secp256k1::new();
+        let descriptor = Descriptor::<DescriptorPublicKey>::from_str(
+            "wsh(multi(2,[0f056943/48'/1'/0'/2']tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*,tpubD6NzVbkrYhZ4WWGhiCNyq7EUNpoFdcVrxB4SqRUv55oSXHPAt47E5Et1xYM8S5ZqtwPuPZ5j2s1pFALLGg9uK5emMbKqdi8r2B2SRx9oQSk/1/*,02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737))",
+        )
+        .unwrap();
+
+        let origins = descriptor.key_origins(&secp);
+        let keys = descriptor.get_keys().unwrap();
+        assert_eq!(
+            origins
+                .iter()
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>(),
+            keys
+        );
+        assert_eq!(
+            origins[0].1,
+            (
+                Fingerprint::from_str("0f056943").unwrap(),
+                DerivationPath::from_str("m/48'/1'/0'/2'/0").unwrap()
+            )
+        );
+        // keys without an origin are the root of their own derivation
+        let xpub = bip32::ExtendedPubKey::from_str("tpubD6NzVbkrYhZ4WWGhiCNyq7EUNpoFdcVrxB4SqRUv55oSXHPAt47E5Et1xYM8S5ZqtwPuPZ5j2s1pFALLGg9uK5emMbKqdi8r2B2SRx9oQSk").unwrap();
+        assert_eq!(
+            origins[1].1,
+            (xpub.fingerprint(), DerivationPath::from_str("m/1").unwrap())
+        );
+        assert_eq!(origins[2].1 .1, DerivationPath::from(vec![]));
+
+        let hd_keypaths = descriptor.get_hd_keypaths(42, &secp).unwrap();
+        assert_eq!(hd_keypaths.len(), 2);
+        for (public_key, key_source) in &hd_keypaths {
+            let key = descriptor
+                .find_key_for_keypath(public_key, key_source, &secp)
+                .unwrap();
+            assert!(keys.contains(&key));
+
+            let wrong_key = hd_keypaths.keys().find(|pk| pk != &public_key).unwrap();
+            assert!(descriptor
+                .find_key_for_keypath(wrong_key, key_source, &secp)
+                .is_none());
+        }
+
+        let single = PublicKey::from_str(
+            "02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737",
+        )
+        .unwrap();
+        assert_eq!(
+            descriptor.find_key_for_keypath(&single, &origins[2].1, &secp),
+            Some(keys[2].clone())
+        );
+    }
+
     #[test]
     fn test_derive_from_psbt_input_wpkh_wif() {
         let descriptor = Descriptor::<DescriptorPublicKey>::from_str(
@@ -692,6 +1310,21 @@ mod test {
         assert_eq!(wallet_desc.to_string(), "wpkh(tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
     }
 
+    #[test]
+    fn test_to_wallet_descriptor_hardened_xpub() {
+        let desc = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*')"
+            .to_wallet_descriptor(Network::Testnet);
+        assert!(matches!(desc, Err(KeyError::HardenedDerivationXpub)));
+        let desc = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*h)"
+            .to_wallet_descriptor(Network::Testnet);
+        assert!(matches!(desc, Err(KeyError::HardenedDerivationXpub)));
+
+        // hardened steps before the wildcard are fine if we have the xprv
+        let desc = "wpkh(tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N/44'/0/*)"
+            .to_wallet_descriptor(Network::Testnet);
+        assert!(desc.is_ok());
+    }
+
     // test ToWalletDescriptor trait from &str with and without checksum appended
     #[test]
     fn test_descriptor_from_str_with_checksum() {
@@ -757,6 +1390,37 @@ mod test {
     }
 
     // test ToWalletDescriptor trait from the output of the descriptor!() macro
+    #[test]
+    fn test_descriptor_from_str_with_slip132_keys() {
+        let (desc, _) = "wpkh([0f056943/84'/1'/0']vpub5SLqN2bLY4WeZH5meCqeSQGMxYpqPMFSXDgKoDgUxtyiGiThVH7HRNU5EjKUBCpzDZfTC2bNdXdBGJkUYXf87AzY5ABNrJebRYMa4JKtehx/0/*)"
+            .to_wallet_descriptor(Network::Testnet)
+            .unwrap();
+        assert_eq!(desc.to_string(), "wpkh([0f056943/84'/1'/0']tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)");
+
+        // the checksum is computed over the original string
+        let original = "sh(wpkh(upub57Wa4MvRPNyAhyteor42EKArnagPSjFwc7A71pnbatbqDceUEcwioJowDXMtBJB4ovYeSYzpAsGdP28upqF7JwJwCpUxGPq79pHvfkdSwjJ/0/*))";
+        let with_checksum = format!("{}#{}", original, get_checksum(original).unwrap());
+        assert!(with_checksum
+            .as_str()
+            .to_wallet_descriptor(Network::Testnet)
+            .is_ok());
+
+        let desc = "pkh(vpub5SLqN2bLY4WeZH5meCqeSQGMxYpqPMFSXDgKoDgUxtyiGiThVH7HRNU5EjKUBCpzDZfTC2bNdXdBGJkUYXf87AzY5ABNrJebRYMa4JKtehx/0/*)"
+            .to_wallet_descriptor(Network::Testnet);
+        assert!(matches!(
+            desc,
+            Err(KeyError::ScriptTypeMismatch {
+                key_script_type: ScriptType::P2wpkh,
+                descriptor_script_type: ScriptType::P2pkh,
+            })
+        ));
+
+        // the key is still checked against the network
+        let desc = "wpkh(zpub6jftahH18ngZxUuv6oSniLNrBCSSE1B4EEU59bwTCEt8x6aS6b2mdfLxbS4QS53g85SWWP6wexqeer516433gYpZQoJie2tcMYdJ1SYYYAL/0/*)"
+            .to_wallet_descriptor(Network::Testnet);
+        assert!(matches!(desc, Err(KeyError::InvalidNetwork)));
+    }
+
     #[test]
     fn test_descriptor_from_str_from_output_of_macro() {
         let tpub = bip32::ExtendedPubKey::from_str("tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK").unwrap();
@@ -775,4 +1439,48 @@ mod test {
             .unwrap();
         assert_eq!(wallet_desc, wallet_desc2)
     }
+
+    #[test]
+    fn test_derive_internal_descriptor() {
+        let (descriptor, keymap) = "wsh(multi(2,tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N/84'/1'/0'/0/*,tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*,02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737))"
+            .to_wallet_descriptor(Network::Testnet)
+            .unwrap();
+        let (internal, internal_keymap) = derive_internal_descriptor(&descriptor, &keymap).unwrap();
+
+        let (expected, expected_keymap) = "wsh(multi(2,tprv8ZgxMBicQKsPdpkqS7Eair4YxjcuuvDPNYmKX3sCniCf16tHEVrjjiSXEkFRnUH77yXc6ZcwHHcLNfjdi5qUvw3VDfgYiH5mNsj5izuiu2N/84'/1'/0'/1/*,tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/*,02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737))"
+            .to_wallet_descriptor(Network::Testnet)
+            .unwrap();
+        assert_eq!(internal, expected);
+
+        // secret keys aren't `PartialEq`, compare their string representation
+        let to_strings = |keymap: KeyMap| {
+            keymap
+                .iter()
+                .map(|(pk, sk)| (pk.to_string(), sk.to_string()))
+                .collect::<BTreeMap<_, _>>()
+        };
+        assert_eq!(to_strings(internal_keymap), to_strings(expected_keymap));
+    }
+
+    #[test]
+    fn test_derive_internal_descriptor_non_standard() {
+        // not ending with `/0/*`
+        let (descriptor, keymap) = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/2/*)"
+            .to_wallet_descriptor(Network::Testnet)
+            .unwrap();
+        assert!(derive_internal_descriptor(&descriptor, &keymap).is_none());
+
+        // not a wildcard
+        let (descriptor, keymap) = "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0)"
+            .to_wallet_descriptor(Network::Testnet)
+            .unwrap();
+        assert!(derive_internal_descriptor(&descriptor, &keymap).is_none());
+
+        // no extended keys
+        let (descriptor, keymap) =
+            "wpkh(02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737)"
+                .to_wallet_descriptor(Network::Testnet)
+                .unwrap();
+        assert!(derive_internal_descriptor(&descriptor, &keymap).is_none());
+    }
 }
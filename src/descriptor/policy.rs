@@ -503,6 +503,122 @@ impl Condition {
     pub fn is_null(&self) -> bool {
         self.csv.is_none() && self.timelock.is_none()
     }
+
+    /// Returns `true` if the conditions are met by a transaction mined in the block that follows
+    /// the tip described by `chain`
+    pub fn is_met(&self, chain: &ChainState) -> bool {
+        let timelock_met = match self.timelock {
+            None => true,
+            Some(value) if value < utils::BLOCKS_TIMELOCK_THRESHOLD => value <= chain.height,
+            Some(value) => value < chain.median_time_past,
+        };
+
+        let csv_met = match self.csv {
+            None => true,
+            Some(value) if value & utils::SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 => true,
+            Some(value) if value & utils::SEQUENCE_LOCKTIME_TYPE_FLAG == 0 => {
+                chain.coins_height.map_or(false, |coins_height| {
+                    chain.height.saturating_add(1).saturating_sub(coins_height)
+                        >= value & utils::SEQUENCE_LOCKTIME_MASK
+                })
+            }
+            Some(value) => chain.coins_median_time_past.map_or(false, |coins_time| {
+                chain.median_time_past.saturating_sub(coins_time)
+                    >= (value & utils::SEQUENCE_LOCKTIME_MASK) * 512
+            }),
+        };
+
+        timelock_met && csv_met
+    }
+}
+
+/// State of the chain and of the coins being spent, used to check which branches of a policy are
+/// satisfiable, see [`Policy::is_satisfiable`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChainState {
+    /// Height of the tip of the chain
+    pub height: u32,
+    /// Median time past of the tip of the chain
+    pub median_time_past: u32,
+    /// Height of the block that confirmed the coins being spent, or `None` if they are still
+    /// unconfirmed. When spending more than one coin this should be the most recent one
+    pub coins_height: Option<u32>,
+    /// Median time past of the block preceding the one that confirmed the coins being spent, as
+    /// defined by BIP68 for time-based relative timelocks. `None` if they are still unconfirmed
+    pub coins_median_time_past: Option<u32>,
+}
+
+/// Selector for a branch of a policy tree, see [`Policy::select_path`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BranchSelector {
+    /// The node with the given [`Policy::id`]
+    Id(String),
+    /// A `threshold`-of-`keys` multisig, like the "2-of-3" branch of a policy
+    Multisig {
+        /// Number of signatures required
+        threshold: usize,
+        /// Total number of keys
+        keys: usize,
+    },
+    /// A relative timelock (`older`), with a specific value or any value if `None`
+    RelativeTimelock(Option<u32>),
+    /// An absolute timelock (`after`), with a specific value or any value if `None`
+    AbsoluteTimelock(Option<u32>),
+    /// A signature made with an extended key that has the given fingerprint
+    Fingerprint(Fingerprint),
+    /// A signature made with the given public key
+    PublicKey(PublicKey),
+}
+
+impl BranchSelector {
+    fn matches(&self, policy: &Policy) -> bool {
+        let is_signature = |item: &Policy| {
+            matches!(
+                item.item,
+                SatisfiableItem::Signature(_) | SatisfiableItem::SignatureKey(_)
+            )
+        };
+
+        match (self, &policy.item) {
+            (BranchSelector::Id(id), _) => &policy.id == id,
+            (
+                BranchSelector::Multisig { threshold, keys },
+                SatisfiableItem::Multisig {
+                    keys: multisig_keys,
+                    threshold: multisig_threshold,
+                },
+            ) => multisig_keys.len() == *keys && multisig_threshold == threshold,
+            (
+                BranchSelector::Multisig { threshold, keys },
+                SatisfiableItem::Thresh {
+                    items,
+                    threshold: thresh_threshold,
+                },
+            ) => {
+                items.len() == *keys
+                    && thresh_threshold == threshold
+                    && items.iter().all(is_signature)
+            }
+            (
+                BranchSelector::RelativeTimelock(expected),
+                SatisfiableItem::RelativeTimelock { value },
+            )
+            | (
+                BranchSelector::AbsoluteTimelock(expected),
+                SatisfiableItem::AbsoluteTimelock { value },
+            ) => expected.map(|expected| expected == *value).unwrap_or(true),
+            (BranchSelector::Fingerprint(fingerprint), SatisfiableItem::Signature(key))
+            | (BranchSelector::Fingerprint(fingerprint), SatisfiableItem::SignatureKey(key)) => {
+                key.fingerprint == Some(*fingerprint)
+            }
+            (BranchSelector::PublicKey(pubkey), SatisfiableItem::Signature(key))
+            | (BranchSelector::PublicKey(pubkey), SatisfiableItem::SignatureKey(key)) => {
+                key.pubkey == Some(*pubkey)
+                    || key.pubkey_hash == Some(hash160::Hash::hash(&pubkey.to_bytes()))
+            }
+            _ => false,
+        }
+    }
 }
 
 /// Errors that can happen while extracting and manipulating policies
@@ -522,6 +638,11 @@ pub enum PolicyError {
     MixedTimelockUnits,
     /// Incompatible conditions (not currently used)
     IncompatibleConditions,
+    /// No node of the policy tree matches the [`BranchSelector`]
+    BranchNotFound(BranchSelector),
+    /// More than one node of the policy tree matches a [`BranchSelector`]. Contains the ids of
+    /// the nodes, so that one of them can be picked with [`BranchSelector::Id`]
+    AmbiguousBranch(Vec<String>),
 }
 
 impl fmt::Display for PolicyError {
@@ -702,6 +823,198 @@ impl Policy {
             _ => Ok(Condition::default()),
         }
     }
+
+    /// Return whether the wallet can satisfy this node of the policy tree with a transaction mined
+    /// in the block that follows the tip described by `chain`
+    ///
+    /// Signatures and multisigs are satisfiable when the signers of the wallet can produce enough
+    /// signatures (see [`Policy::contribution`]), timelocks when they have expired and thresholds
+    /// when enough of their items are satisfiable. Hash preimages are never considered
+    /// satisfiable, since the wallet has no way to know them.
+    ///
+    /// This can be used to tell apart the branches that can be spent right now from the ones that
+    /// are still locked, like the recovery branch of a wallet that is only available after some
+    /// time.
+    pub fn is_satisfiable(&self, chain: &ChainState) -> bool {
+        match &self.item {
+            SatisfiableItem::Thresh { items, threshold } => {
+                items
+                    .iter()
+                    .filter(|item| item.is_satisfiable(chain))
+                    .count()
+                    >= *threshold
+            }
+            SatisfiableItem::Signature(_)
+            | SatisfiableItem::SignatureKey(_)
+            | SatisfiableItem::Multisig { .. } => matches!(
+                self.contribution,
+                Satisfaction::Complete { .. } | Satisfaction::PartialComplete { .. }
+            ),
+            SatisfiableItem::AbsoluteTimelock { value } => Condition {
+                csv: None,
+                timelock: Some(*value),
+            }
+            .is_met(chain),
+            SatisfiableItem::RelativeTimelock { value } => Condition {
+                csv: Some(*value),
+                timelock: None,
+            }
+            .is_met(chain),
+            _ => false,
+        }
+    }
+
+    /// Build a policy path that only selects branches satisfiable at `chain`, or return `None` if
+    /// the policy can't be satisfied
+    ///
+    /// In every threshold the first satisfiable items are selected, preferring the ones that
+    /// don't add any extra condition to the transaction. The path can be passed directly to
+    /// [`TxBuilder::policy_path`](crate::wallet::tx_builder::TxBuilder::policy_path).
+    pub fn satisfiable_path(&self, chain: &ChainState) -> Option<BTreeMap<String, Vec<usize>>> {
+        if !self.is_satisfiable(chain) {
+            return None;
+        }
+
+        let mut path = BTreeMap::new();
+        self.fill_satisfiable_path(chain, &mut path);
+        Some(path)
+    }
+
+    fn fill_satisfiable_path(&self, chain: &ChainState, path: &mut BTreeMap<String, Vec<usize>>) {
+        if let SatisfiableItem::Thresh { items, threshold } = &self.item {
+            let has_condition = |item: &Policy| {
+                !matches!(
+                    item.get_condition(&BTreeMap::new()),
+                    Ok(condition) if condition.is_null()
+                )
+            };
+
+            let mut selected = (0..items.len())
+                .filter(|index| items[*index].is_satisfiable(chain))
+                .collect::<Vec<_>>();
+            // `false` sorts first, and the sort is stable so the order of the items is preserved
+            selected.sort_by_key(|index| has_condition(&items[*index]));
+            selected.truncate(*threshold);
+            selected.sort_unstable();
+
+            for index in &selected {
+                items[*index].fill_satisfiable_path(chain, path);
+            }
+            path.insert(self.id.clone(), selected);
+        }
+    }
+
+    /// Build the policy path that satisfies the branches matched by `selectors`
+    ///
+    /// This is a more convenient way to build the path for
+    /// [`TxBuilder::policy_path`](crate::wallet::tx_builder::TxBuilder::policy_path): instead of
+    /// looking up the id and the position of every node, the branches can be described by their
+    /// content, like "the 2-of-3 multisig" or "the timelock". Every selector must match exactly
+    /// one node of the tree. The thresholds on the way to the selected nodes are completed with
+    /// the first branches that don't add any extra condition, and the resulting path is
+    /// validated with [`Policy::get_condition`].
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use bdk::descriptor::*;
+    /// # use bdk::descriptor::policy::BranchSelector;
+    /// # use bdk::bitcoin::secp256k1::Secp256k1;
+    /// # let secp = Secp256k1::new();
+    /// let desc = "wsh(or_d(pk(cV3oCth6zxZ1UVsHLnGothsWNsaoxRhC6aeNi5VbSdFpwUkgkEci),and_v(v:pk(cVMTy7uebJgvFaSBwcgvwk8qn8xSLc97dKow4MBetjrrahZoimm2),older(12960))))";
+    /// # let (extended_desc, key_map) = ExtendedDescriptor::parse_descriptor(desc)?;
+    /// # let signers = Arc::new(key_map.into());
+    /// let policy = extended_desc.extract_policy(&signers, &secp)?.unwrap();
+    ///
+    /// // spend using the branch with the timelock
+    /// let path = policy.select_path(&[BranchSelector::RelativeTimelock(None)])?;
+    /// assert_eq!(policy.get_condition(&path)?.csv, Some(12960));
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    pub fn select_path(
+        &self,
+        selectors: &[BranchSelector],
+    ) -> Result<BTreeMap<String, Vec<usize>>, PolicyError> {
+        let mut path: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for selector in selectors {
+            let mut matches = vec![];
+            self.find_branch(selector, &mut vec![], &mut matches);
+
+            let steps = match matches.len() {
+                0 => return Err(PolicyError::BranchNotFound(selector.clone())),
+                1 => matches.remove(0).1,
+                _ => {
+                    return Err(PolicyError::AmbiguousBranch(
+                        matches.into_iter().map(|(id, _)| id).collect(),
+                    ))
+                }
+            };
+            for (id, index) in steps {
+                let selected = path.entry(id).or_default();
+                if !selected.contains(&index) {
+                    selected.push(index);
+                }
+            }
+        }
+
+        self.complete_path(&mut path);
+        for selected in path.values_mut() {
+            selected.sort_unstable();
+        }
+
+        self.get_condition(&path)?;
+        Ok(path)
+    }
+
+    // Find the nodes matched by `selector`. For each of them, return its id and the items that
+    // must be selected to reach it from the root
+    #[allow(clippy::type_complexity)]
+    fn find_branch(
+        &self,
+        selector: &BranchSelector,
+        steps: &mut Vec<(String, usize)>,
+        matches: &mut Vec<(String, Vec<(String, usize)>)>,
+    ) {
+        if selector.matches(self) {
+            matches.push((self.id.clone(), steps.clone()));
+            return;
+        }
+
+        if let SatisfiableItem::Thresh { items, .. } = &self.item {
+            for (index, item) in items.iter().enumerate() {
+                steps.push((self.id.clone(), index));
+                item.find_branch(selector, steps, matches);
+                steps.pop();
+            }
+        }
+    }
+
+    // Complete the thresholds that have some items selected with the first items that don't
+    // add any condition
+    fn complete_path(&self, path: &mut BTreeMap<String, Vec<usize>>) {
+        if let SatisfiableItem::Thresh { items, threshold } = &self.item {
+            if let Some(selected) = path.get_mut(&self.id) {
+                for (index, item) in items.iter().enumerate() {
+                    if selected.len() >= *threshold {
+                        break;
+                    }
+
+                    let no_condition = matches!(
+                        item.get_condition(&BTreeMap::new()),
+                        Ok(condition) if condition.is_null()
+                    );
+                    if no_condition && !selected.contains(&index) {
+                        selected.push(index);
+                    }
+                }
+            }
+
+            for item in items {
+                item.complete_path(path);
+            }
+        }
+    }
 }
 
 impl From<SatisfiableItem> for Policy {
@@ -1173,6 +1486,158 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_select_path() {
+        let (prvkey0, _pubkey0, _fingerprint0) = setup_keys(TPRV0_STR);
+        let (_prvkey1, pubkey1, fingerprint1) = setup_keys(TPRV1_STR);
+        let sequence = 50;
+        let desc = descriptor!(wsh(thresh(
+            2,
+            pk(prvkey0),
+            s: pk(pubkey1),
+            s: n: d: v: older(sequence)
+        )))
+        .unwrap();
+
+        let (wallet_desc, keymap) = desc.to_wallet_descriptor(Network::Testnet).unwrap();
+        let signers_container = Arc::new(SignersContainer::from(keymap));
+        let policy = wallet_desc
+            .extract_policy(&signers_container, &Secp256k1::new())
+            .unwrap()
+            .unwrap();
+
+        // the threshold is completed with the first key
+        let path = policy
+            .select_path(&[BranchSelector::RelativeTimelock(None)])
+            .unwrap();
+        assert_eq!(path.get(&policy.id), Some(&vec![0, 2]));
+        assert_eq!(policy.get_condition(&path).unwrap().csv, Some(sequence));
+
+        let path = policy
+            .select_path(&[
+                BranchSelector::Fingerprint(fingerprint1),
+                BranchSelector::RelativeTimelock(Some(sequence)),
+            ])
+            .unwrap();
+        assert_eq!(path.get(&policy.id), Some(&vec![1, 2]));
+
+        // the keys alone don't have any condition
+        let path = policy
+            .select_path(&[BranchSelector::Fingerprint(fingerprint1)])
+            .unwrap();
+        assert_eq!(path.get(&policy.id), Some(&vec![0, 1]));
+        assert!(policy.get_condition(&path).unwrap().is_null());
+    }
+
+    #[test]
+    fn test_select_path_errors() {
+        let (prvkey0, _pubkey0, _fingerprint0) = setup_keys(TPRV0_STR);
+        let (_prvkey1, pubkey1, _fingerprint1) = setup_keys(TPRV1_STR);
+        let desc = descriptor!(wsh(thresh(
+            2,
+            pk(prvkey0),
+            s: pk(pubkey1),
+            s: n: d: v: older(50)
+        )))
+        .unwrap();
+
+        let (wallet_desc, keymap) = desc.to_wallet_descriptor(Network::Testnet).unwrap();
+        let signers_container = Arc::new(SignersContainer::from(keymap));
+        let policy = wallet_desc
+            .extract_policy(&signers_container, &Secp256k1::new())
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(
+            policy.select_path(&[BranchSelector::AbsoluteTimelock(None)]),
+            Err(PolicyError::BranchNotFound(
+                BranchSelector::AbsoluteTimelock(None)
+            ))
+        ));
+        assert!(matches!(
+            policy.select_path(&[BranchSelector::RelativeTimelock(Some(10))]),
+            Err(PolicyError::BranchNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_condition_is_met() {
+        let chain = ChainState {
+            height: 100,
+            median_time_past: 1_600_000_000,
+            coins_height: Some(91),
+            coins_median_time_past: Some(1_599_990_000),
+        };
+
+        assert!(Condition::default().is_met(&chain));
+
+        let after = |timelock| Condition {
+            csv: None,
+            timelock: Some(timelock),
+        };
+        assert!(after(100).is_met(&chain));
+        assert!(!after(101).is_met(&chain));
+        assert!(after(1_599_999_999).is_met(&chain));
+        assert!(!after(1_600_000_000).is_met(&chain));
+
+        let older = |csv| Condition {
+            csv: Some(csv),
+            timelock: None,
+        };
+        assert!(older(10).is_met(&chain));
+        assert!(!older(11).is_met(&chain));
+        // 19 * 512 = 9728 seconds
+        assert!(older(utils::SEQUENCE_LOCKTIME_TYPE_FLAG | 19).is_met(&chain));
+        assert!(!older(utils::SEQUENCE_LOCKTIME_TYPE_FLAG | 20).is_met(&chain));
+
+        let unconfirmed = ChainState {
+            coins_height: None,
+            coins_median_time_past: None,
+            ..chain
+        };
+        assert!(!older(1).is_met(&unconfirmed));
+        assert!(after(100).is_met(&unconfirmed));
+    }
+
+    #[test]
+    fn test_satisfiable_path() {
+        let (prvkey0, _pubkey0, _fingerprint0) = setup_keys(TPRV0_STR);
+        let (_prvkey1, pubkey1, _fingerprint1) = setup_keys(TPRV1_STR);
+        let sequence = 50;
+        let desc = descriptor!(wsh(thresh(
+            2,
+            pk(prvkey0),
+            s: pk(pubkey1),
+            s: n: d: v: older(sequence)
+        )))
+        .unwrap();
+
+        let (wallet_desc, keymap) = desc.to_wallet_descriptor(Network::Testnet).unwrap();
+        let signers_container = Arc::new(SignersContainer::from(keymap));
+        let policy = wallet_desc
+            .extract_policy(&signers_container, &Secp256k1::new())
+            .unwrap()
+            .unwrap();
+
+        let chain = ChainState {
+            height: 100,
+            coins_height: Some(52),
+            ..Default::default()
+        };
+        // we only hold the first key, and the timelock hasn't expired yet
+        assert!(!policy.is_satisfiable(&chain));
+        assert_eq!(policy.satisfiable_path(&chain), None);
+
+        let chain = ChainState {
+            coins_height: Some(51),
+            ..chain
+        };
+        assert!(policy.is_satisfiable(&chain));
+        let path = policy.satisfiable_path(&chain).unwrap();
+        assert_eq!(path.get(&policy.id), Some(&vec![0, 2]));
+        assert_eq!(policy.get_condition(&path).unwrap().csv, Some(sequence));
+    }
+
     // - mixed timelocks should fail
 
     // #[test]
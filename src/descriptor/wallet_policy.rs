@@ -0,0 +1,606 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Wallet policies
+//!
+//! This module implements the wallet policies defined in
+//! [BIP-388](https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki), the format used by
+//! some hardware wallets to register the multi-key descriptors they are allowed to sign for.
+//!
+//! A wallet policy is made of a *descriptor template*, where every key expression is replaced by
+//! a placeholder like `@0/**`, and a *vector of key information* that contains the extended keys
+//! referenced by the placeholders, with their origin. A single policy describes both the
+//! external and the internal descriptor of a wallet: `@0/**` is a shorthand for `@0/<0;1>/*`,
+//! which derives from `.../0/*` in the external descriptor and from `.../1/*` in the internal
+//! one. A key can be referenced more than once, as long as every placeholder uses different
+//! derivation steps, like `@0/**` and `@0/<2;3>/*`.
+//!
+//! Before signing for a policy, hardware wallets usually require the user to register it on the
+//! device: with the `external-signer` feature, this can be done with
+//! [`ExternalSigner::register_policy`](crate::wallet::external_signer::ExternalSigner::register_policy).
+//!
+//! ## Example
+//!
+//! ```
+//! # use bitcoin::*;
+//! # use bdk::descriptor::*;
+//! # use bdk::descriptor::wallet_policy::*;
+//! let (descriptor, _) = "wsh(sortedmulti(2,[73756c7f/48'/1'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*,[f9f62194/48'/1'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/0/*))".to_wallet_descriptor(Network::Testnet)?;
+//! let (change_descriptor, _) = "wsh(sortedmulti(2,[73756c7f/48'/1'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/1/*,[f9f62194/48'/1'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4/1/*))".to_wallet_descriptor(Network::Testnet)?;
+//!
+//! let policy = WalletPolicy::from_descriptors("Cold storage", &descriptor, &change_descriptor)?;
+//! assert_eq!(policy.descriptor_template, "wsh(sortedmulti(2,@0/**,@1/**))");
+//! assert_eq!(policy.keys_info.len(), 2);
+//!
+//! let (external, internal) = policy.to_descriptors()?;
+//! assert_eq!(external.to_string(), descriptor.to_string());
+//! assert_eq!(internal.to_string(), change_descriptor.to_string());
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPubKey};
+
+use serde::{Deserialize, Serialize};
+
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorXKey};
+use miniscript::Descriptor;
+
+use super::ExtendedDescriptor;
+
+/// Maximum length of the name of a wallet policy
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// Errors related to wallet policies
+#[derive(Debug)]
+pub enum WalletPolicyError {
+    /// The name is empty, too long or contains non-printable ASCII characters
+    InvalidName,
+    /// An entry of the key information vector is not an extended public key with an optional
+    /// origin and no derivation steps
+    InvalidKeyInfo(String),
+    /// The same key appears more than once in the key information vector
+    DuplicatedKeyInfo(String),
+    /// A key placeholder of the descriptor template is malformed, or references a key that
+    /// doesn't exist
+    InvalidPlaceholder(String),
+    /// The placeholders don't reference the keys in increasing order of first appearance
+    PlaceholdersOutOfOrder,
+    /// A key is referenced more than once by placeholders whose derivation steps overlap
+    OverlappingSteps(String),
+    /// The key at this index of the key information vector is never used by the template
+    UnusedKeyInfo(usize),
+    /// A key of the descriptor can't be represented in a wallet policy. Only ranged extended
+    /// public keys that derive from a single unhardened step are supported
+    UnsupportedKey(String),
+    /// The external and the internal descriptors don't share the same template and keys
+    DescriptorMismatch,
+    /// Miniscript error
+    Miniscript(miniscript::Error),
+}
+
+impl fmt::Display for WalletPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for WalletPolicyError {}
+
+impl From<miniscript::Error> for WalletPolicyError {
+    fn from(err: miniscript::Error) -> Self {
+        WalletPolicyError::Miniscript(err)
+    }
+}
+
+/// A BIP-388 wallet policy
+///
+/// The policy can be serialized to and from JSON with [`ToString`] and [`FromStr`]. Deserializing
+/// doesn't validate the policy: use [`WalletPolicy::validate`] or [`WalletPolicy::to_descriptors`]
+/// before trusting it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletPolicy {
+    /// Name of the policy, shown by the hardware wallet when it's registered
+    pub name: String,
+    /// Descriptor template, with key placeholders in place of the keys
+    pub descriptor_template: String,
+    /// Keys referenced by the placeholders of the template, with their origin
+    pub keys_info: Vec<String>,
+}
+
+impl ToString for WalletPolicy {
+    fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl FromStr for WalletPolicy {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+impl WalletPolicy {
+    /// Build and validate a new wallet policy
+    pub fn new(
+        name: &str,
+        descriptor_template: &str,
+        keys_info: Vec<String>,
+    ) -> Result<Self, WalletPolicyError> {
+        let policy = WalletPolicy {
+            name: name.to_string(),
+            descriptor_template: descriptor_template.to_string(),
+            keys_info,
+        };
+        policy.validate()?;
+
+        Ok(policy)
+    }
+
+    /// Build a wallet policy from the external and internal descriptors of a wallet
+    ///
+    /// Every key of the descriptors must be an extended public key that derives from a single
+    /// unhardened step followed by a wildcard, like `.../0/*` and `.../1/*`, and the two
+    /// descriptors must only differ in those steps.
+    pub fn from_descriptors(
+        name: &str,
+        descriptor: &ExtendedDescriptor,
+        change_descriptor: &ExtendedDescriptor,
+    ) -> Result<Self, WalletPolicyError> {
+        let keys_info = RefCell::new(Vec::<String>::new());
+
+        // Replace every key with `@<index>`, returning the steps of the keys in order of
+        // appearance
+        let make_template = |descriptor: &ExtendedDescriptor| {
+            let steps = RefCell::new(Vec::new());
+            let translate_key = |key: &DescriptorPublicKey| -> Result<String, WalletPolicyError> {
+                let (key_info, step) = split_key(key)?;
+
+                let mut keys_info = keys_info.borrow_mut();
+                let index = match keys_info.iter().position(|k| k == &key_info) {
+                    Some(index) => index,
+                    None => {
+                        keys_info.push(key_info);
+                        keys_info.len() - 1
+                    }
+                };
+                steps.borrow_mut().push((index, step));
+
+                Ok(format!("@{}", index))
+            };
+
+            let template = descriptor.translate_pk(translate_key, translate_key)?;
+            Ok::<_, WalletPolicyError>((template.to_string(), steps.into_inner()))
+        };
+
+        let (external_template, external_steps) = make_template(descriptor)?;
+        let (internal_template, internal_steps) = make_template(change_descriptor)?;
+        if external_template != internal_template
+            || external_steps
+                .iter()
+                .zip(internal_steps.iter())
+                .any(|((_, external), (_, internal))| external == internal)
+        {
+            return Err(WalletPolicyError::DescriptorMismatch);
+        }
+
+        let position = Cell::new(0);
+        let translate_key = |_: &DescriptorPublicKey| -> Result<String, WalletPolicyError> {
+            let (index, external) = external_steps[position.get()];
+            let (_, internal) = internal_steps[position.get()];
+            position.set(position.get() + 1);
+
+            Ok(match (external, internal) {
+                (0, 1) => format!("@{}/**", index),
+                (external, internal) => format!("@{}/<{};{}>/*", index, external, internal),
+            })
+        };
+        let descriptor_template = descriptor
+            .translate_pk(translate_key, translate_key)?
+            .to_string();
+
+        WalletPolicy::new(name, &descriptor_template, keys_info.into_inner())
+    }
+
+    /// Convert the wallet policy to a single descriptor that uses multipath derivation steps
+    /// like `<0;1>` for both the external and the internal descriptor
+    ///
+    /// This is the format used to register the policy on a device with tools like
+    /// [HWI](https://github.com/bitcoin-core/HWI).
+    pub fn to_multipath_descriptor(&self) -> Result<String, WalletPolicyError> {
+        let (template, _) = self.parse()?;
+
+        let translate_key = |placeholder: &String| -> Result<String, WalletPolicyError> {
+            let (index, external_step, internal_step) = parse_placeholder(placeholder)
+                .ok_or_else(|| WalletPolicyError::InvalidPlaceholder(placeholder.clone()))?;
+
+            Ok(format!(
+                "{}/<{};{}>/*",
+                self.keys_info[index], external_step, internal_step
+            ))
+        };
+
+        Ok(template
+            .translate_pk(translate_key, translate_key)?
+            .to_string())
+    }
+
+    /// Convert the wallet policy to its external and internal descriptors
+    pub fn to_descriptors(
+        &self,
+    ) -> Result<(ExtendedDescriptor, ExtendedDescriptor), WalletPolicyError> {
+        let (template, keys) = self.parse()?;
+
+        let instantiate = |internal: bool| {
+            let translate_key =
+                |placeholder: &String| -> Result<DescriptorPublicKey, WalletPolicyError> {
+                    let (index, external_step, internal_step) = parse_placeholder(placeholder)
+                        .ok_or_else(|| {
+                            WalletPolicyError::InvalidPlaceholder(placeholder.clone())
+                        })?;
+                    let step = if internal {
+                        internal_step
+                    } else {
+                        external_step
+                    };
+
+                    Ok(DescriptorPublicKey::XPub(DescriptorXKey {
+                        derivation_path: DerivationPath::from(vec![ChildNumber::Normal {
+                            index: step,
+                        }]),
+                        is_wildcard: true,
+                        ..keys[index].clone()
+                    }))
+                };
+
+            template.translate_pk(translate_key, translate_key)
+        };
+
+        Ok((instantiate(false)?, instantiate(true)?))
+    }
+
+    /// Validate the wallet policy
+    pub fn validate(&self) -> Result<(), WalletPolicyError> {
+        self.parse().map(|_| ())
+    }
+
+    fn parse(
+        &self,
+    ) -> Result<(Descriptor<String>, Vec<DescriptorXKey<ExtendedPubKey>>), WalletPolicyError> {
+        if self.name.is_empty()
+            || self.name.len() > MAX_NAME_LENGTH
+            || !self.name.chars().all(|c| c.is_ascii_graphic() || c == ' ')
+        {
+            return Err(WalletPolicyError::InvalidName);
+        }
+
+        let mut keys: Vec<DescriptorXKey<ExtendedPubKey>> = Vec::new();
+        for key_info in &self.keys_info {
+            let key = match DescriptorPublicKey::from_str(key_info) {
+                Ok(DescriptorPublicKey::XPub(xkey))
+                    if xkey.derivation_path.as_ref().is_empty() && !xkey.is_wildcard =>
+                {
+                    xkey
+                }
+                _ => return Err(WalletPolicyError::InvalidKeyInfo(key_info.clone())),
+            };
+            if keys.iter().any(|k| k.xkey == key.xkey) {
+                return Err(WalletPolicyError::DuplicatedKeyInfo(key_info.clone()));
+            }
+
+            keys.push(key);
+        }
+
+        let template = Descriptor::<String>::from_str(&self.descriptor_template)?;
+
+        // Every key must be used, and the first appearance of `@i` must come after the first
+        // appearance of `@(i-1)`. The placeholders of the same key can't share any derivation
+        // step, otherwise they would derive the same child keys
+        let used_steps = RefCell::new(vec![Vec::new(); keys.len()]);
+        let check_placeholder = |placeholder: &String| -> Result<String, WalletPolicyError> {
+            let mut used_steps = used_steps.borrow_mut();
+            match parse_placeholder(placeholder) {
+                Some((index, external, internal)) if index < used_steps.len() => {
+                    let steps = &used_steps[index];
+                    if steps.is_empty()
+                        && used_steps.iter().filter(|s| !s.is_empty()).count() != index
+                    {
+                        return Err(WalletPolicyError::PlaceholdersOutOfOrder);
+                    }
+                    if steps.contains(&external) || steps.contains(&internal) {
+                        return Err(WalletPolicyError::OverlappingSteps(placeholder.clone()));
+                    }
+                    used_steps[index].extend(&[external, internal]);
+
+                    Ok(placeholder.clone())
+                }
+                _ => Err(WalletPolicyError::InvalidPlaceholder(placeholder.clone())),
+            }
+        };
+        template.translate_pk(check_placeholder, check_placeholder)?;
+
+        if let Some(index) = used_steps.into_inner().iter().position(Vec::is_empty) {
+            return Err(WalletPolicyError::UnusedKeyInfo(index));
+        }
+
+        Ok((template, keys))
+    }
+}
+
+/// Split a ranged extended key into its key information and its derivation step
+fn split_key(key: &DescriptorPublicKey) -> Result<(String, u32), WalletPolicyError> {
+    match key {
+        DescriptorPublicKey::XPub(xkey) if xkey.is_wildcard => {
+            match xkey.derivation_path.as_ref() {
+                [ChildNumber::Normal { index }] => {
+                    let key_info = DescriptorPublicKey::XPub(DescriptorXKey {
+                        derivation_path: DerivationPath::from(vec![]),
+                        is_wildcard: false,
+                        ..xkey.clone()
+                    });
+
+                    Ok((key_info.to_string(), *index))
+                }
+                _ => Err(WalletPolicyError::UnsupportedKey(key.to_string())),
+            }
+        }
+        _ => Err(WalletPolicyError::UnsupportedKey(key.to_string())),
+    }
+}
+
+/// Parse a key placeholder like `@0/**` or `@0/<2;3>/*`, returning the index of the key and the
+/// external and internal derivation steps
+fn parse_placeholder(placeholder: &str) -> Option<(usize, u32, u32)> {
+    let placeholder = placeholder.strip_prefix('@')?;
+    let (index, steps) = placeholder.split_at(placeholder.find('/')?);
+    if index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let index = usize::from_str(index).ok()?;
+
+    let (external, internal) = match steps {
+        "/**" => (0, 1),
+        steps => {
+            let steps = steps.strip_prefix("/<")?.strip_suffix(">/*")?;
+            let mut steps = steps.split(';');
+            let external = u32::from_str(steps.next()?).ok()?;
+            let internal = u32::from_str(steps.next()?).ok()?;
+            if steps.next().is_some() {
+                return None;
+            }
+
+            (external, internal)
+        }
+    };
+
+    if external == internal
+        || ChildNumber::from_normal_idx(external).is_err()
+        || ChildNumber::from_normal_idx(internal).is_err()
+    {
+        return None;
+    }
+
+    Some((index, external, internal))
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::Network;
+
+    use super::*;
+    use crate::descriptor::ToWalletDescriptor;
+
+    const KEY_A: &str = "[73756c7f/48'/1'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3";
+    const KEY_B: &str = "[f9f62194/48'/1'/0'/2']tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4";
+
+    fn descriptor(desc: &str) -> ExtendedDescriptor {
+        desc.to_wallet_descriptor(Network::Testnet).unwrap().0
+    }
+
+    #[test]
+    fn test_wallet_policy_from_descriptors() {
+        let external = descriptor(&format!("wsh(sortedmulti(2,{}/0/*,{}/0/*))", KEY_A, KEY_B));
+        let internal = descriptor(&format!("wsh(sortedmulti(2,{}/1/*,{}/1/*))", KEY_A, KEY_B));
+
+        let policy = WalletPolicy::from_descriptors("Multisig", &external, &internal).unwrap();
+        assert_eq!(policy.name, "Multisig");
+        assert_eq!(
+            policy.descriptor_template,
+            "wsh(sortedmulti(2,@0/**,@1/**))"
+        );
+        assert_eq!(policy.keys_info, vec![KEY_A.to_string(), KEY_B.to_string()]);
+
+        let (new_external, new_internal) = policy.to_descriptors().unwrap();
+        assert_eq!(new_external.to_string(), external.to_string());
+        assert_eq!(new_internal.to_string(), internal.to_string());
+    }
+
+    #[test]
+    fn test_wallet_policy_custom_steps() {
+        let external = descriptor(&format!("wpkh({}/2/*)", KEY_A));
+        let internal = descriptor(&format!("wpkh({}/3/*)", KEY_A));
+
+        let policy = WalletPolicy::from_descriptors("Single", &external, &internal).unwrap();
+        assert_eq!(policy.descriptor_template, "wpkh(@0/<2;3>/*)");
+
+        let (new_external, new_internal) = policy.to_descriptors().unwrap();
+        assert_eq!(new_external.to_string(), external.to_string());
+        assert_eq!(new_internal.to_string(), internal.to_string());
+    }
+
+    #[test]
+    fn test_wallet_policy_reused_key() {
+        let external = descriptor(&format!(
+            "wsh(or_d(pk({}/0/*),and_v(v:pkh({}/2/*),older(144))))",
+            KEY_A, KEY_A
+        ));
+        let internal = descriptor(&format!(
+            "wsh(or_d(pk({}/1/*),and_v(v:pkh({}/3/*),older(144))))",
+            KEY_A, KEY_A
+        ));
+
+        let policy = WalletPolicy::from_descriptors("Reused", &external, &internal).unwrap();
+        assert_eq!(
+            policy.descriptor_template,
+            "wsh(or_d(pk(@0/**),and_v(v:pkh(@0/<2;3>/*),older(144))))"
+        );
+        assert_eq!(policy.keys_info, vec![KEY_A.to_string()]);
+
+        let (new_external, new_internal) = policy.to_descriptors().unwrap();
+        assert_eq!(new_external.to_string(), external.to_string());
+        assert_eq!(new_internal.to_string(), internal.to_string());
+    }
+
+    #[test]
+    fn test_wallet_policy_reused_key_same_steps() {
+        // the two placeholders would derive the same keys
+        let external = descriptor(&format!(
+            "wsh(or_d(pk({}/0/*),and_v(v:pkh({}/0/*),older(144))))",
+            KEY_A, KEY_A
+        ));
+        let internal = descriptor(&format!(
+            "wsh(or_d(pk({}/1/*),and_v(v:pkh({}/1/*),older(144))))",
+            KEY_A, KEY_A
+        ));
+        assert!(matches!(
+            WalletPolicy::from_descriptors("Reused", &external, &internal),
+            Err(WalletPolicyError::OverlappingSteps(_))
+        ));
+
+        // a step can't be reused even for a different keychain
+        assert!(matches!(
+            WalletPolicy::new(
+                "Reused",
+                "wsh(or_d(pk(@0/**),and_v(v:pkh(@0/<1;2>/*),older(144))))",
+                vec![KEY_A.to_string()]
+            ),
+            Err(WalletPolicyError::OverlappingSteps(_))
+        ));
+    }
+
+    #[test]
+    fn test_wallet_policy_descriptor_mismatch() {
+        let external = descriptor(&format!("wpkh({}/0/*)", KEY_A));
+        let internal = descriptor(&format!("wpkh({}/1/*)", KEY_B));
+        assert!(matches!(
+            WalletPolicy::from_descriptors("Mismatch", &external, &internal),
+            Err(WalletPolicyError::DescriptorMismatch)
+        ));
+
+        assert!(matches!(
+            WalletPolicy::from_descriptors("Mismatch", &external, &external),
+            Err(WalletPolicyError::DescriptorMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_wallet_policy_unsupported_key() {
+        let external = descriptor(&format!("wpkh({}/0/0/*)", KEY_A));
+        let internal = descriptor(&format!("wpkh({}/0/1/*)", KEY_A));
+        assert!(matches!(
+            WalletPolicy::from_descriptors("Unsupported", &external, &internal),
+            Err(WalletPolicyError::UnsupportedKey(_))
+        ));
+    }
+
+    #[test]
+    fn test_wallet_policy_validation() {
+        let keys = vec![KEY_A.to_string(), KEY_B.to_string()];
+
+        assert!(WalletPolicy::new("Valid", "wsh(multi(1,@0/**,@1/<2;3>/*))", keys.clone()).is_ok());
+        assert!(matches!(
+            WalletPolicy::new("", "wsh(multi(1,@0/**,@1/**))", keys.clone()),
+            Err(WalletPolicyError::InvalidName)
+        ));
+        assert!(matches!(
+            WalletPolicy::new("Order", "wsh(multi(1,@1/**,@0/**))", keys.clone()),
+            Err(WalletPolicyError::PlaceholdersOutOfOrder)
+        ));
+        assert!(matches!(
+            WalletPolicy::new("Unused", "wpkh(@0/**)", keys.clone()),
+            Err(WalletPolicyError::UnusedKeyInfo(1))
+        ));
+        assert!(matches!(
+            WalletPolicy::new("Placeholder", "wsh(multi(1,@0/*,@1/**))", keys.clone()),
+            Err(WalletPolicyError::InvalidPlaceholder(_))
+        ));
+        assert!(matches!(
+            WalletPolicy::new(
+                "Placeholder",
+                "wsh(multi(1,@0/<1;1>/*,@1/**))",
+                keys.clone()
+            ),
+            Err(WalletPolicyError::InvalidPlaceholder(_))
+        ));
+        assert!(matches!(
+            WalletPolicy::new("Missing", "wsh(multi(1,@0/**,@2/**))", keys),
+            Err(WalletPolicyError::InvalidPlaceholder(_))
+        ));
+        assert!(matches!(
+            WalletPolicy::new("Derived", "wpkh(@0/**)", vec![format!("{}/0", KEY_A)]),
+            Err(WalletPolicyError::InvalidKeyInfo(_))
+        ));
+        assert!(matches!(
+            WalletPolicy::new(
+                "Duplicated",
+                "wsh(multi(1,@0/**,@1/**))",
+                vec![KEY_A.to_string(), KEY_A.to_string()]
+            ),
+            Err(WalletPolicyError::DuplicatedKeyInfo(_))
+        ));
+    }
+
+    #[test]
+    fn test_wallet_policy_multipath_descriptor() {
+        let policy = WalletPolicy::new(
+            "Multisig",
+            "wsh(sortedmulti(2,@0/**,@1/<2;3>/*))",
+            vec![KEY_A.to_string(), KEY_B.to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy.to_multipath_descriptor().unwrap(),
+            format!("wsh(sortedmulti(2,{}/<0;1>/*,{}/<2;3>/*))", KEY_A, KEY_B)
+        );
+    }
+
+    #[test]
+    fn test_wallet_policy_json() {
+        let policy = WalletPolicy::new(
+            "Multisig",
+            "wsh(sortedmulti(2,@0/**,@1/**))",
+            vec![KEY_A.to_string(), KEY_B.to_string()],
+        )
+        .unwrap();
+
+        let json = policy.to_string();
+        assert_eq!(WalletPolicy::from_str(&json).unwrap(), policy);
+    }
+}
@@ -44,7 +44,7 @@ pub enum Error {
     NoRecipients,
     /// `manually_selected_only` option is selected but no utxo has been passed
     NoUtxosSelected,
-    /// Output created is under the dust limit, 546 satoshis
+    /// Output created is under the [`DustLimit`](crate::wallet::DustLimit)
     OutputBelowDustLimit(usize),
     /// Wallet's UTXO set is not enough to cover recipient's requested plus fee
     InsufficientFunds,
@@ -62,6 +62,8 @@ pub enum Error {
     TransactionConfirmed,
     /// Trying to replace a tx that has a sequence >= `0xFFFFFFFE`
     IrreplaceableTransaction,
+    /// Trying to broadcast a tx whose nLockTime hasn't been reached yet by the tip of the chain
+    TransactionNotFinal,
     /// When bumping a tx the fee rate requested is lower than required
     FeeRateTooLow {
         /// Required fee rate (satoshi/vbyte)
@@ -72,6 +74,22 @@ pub enum Error {
         /// Required fee absolute value (satoshi)
         required: u64,
     },
+    /// The fee rate of the transaction is higher than the maximum allowed by the wallet or the
+    /// [`TxBuilder`](crate::wallet::tx_builder::TxBuilder)
+    FeeRateTooHigh {
+        /// Estimated fee rate of the transaction (satoshi/vbyte)
+        fee_rate: crate::types::FeeRate,
+        /// Maximum fee rate allowed (satoshi/vbyte)
+        max: crate::types::FeeRate,
+    },
+    /// The absolute fee of the transaction is higher than the maximum allowed by the wallet or the
+    /// [`TxBuilder`](crate::wallet::tx_builder::TxBuilder)
+    FeeTooHigh {
+        /// Fee paid by the transaction (satoshi)
+        fee: u64,
+        /// Maximum fee allowed (satoshi)
+        max: u64,
+    },
     /// In order to use the [`TxBuilder::add_global_xpubs`] option every extended
     /// key in the descriptor must either be a master key itself (having depth = 0) or have an
     /// explicit origin provided
@@ -82,12 +100,26 @@ pub enum Error {
     Key(crate::keys::KeyError),
     /// Descriptor checksum mismatch
     ChecksumMismatch,
+    /// Requested a derivation index higher than [`Wallet::max_derivable_index`](crate::Wallet::max_derivable_index)
+    InvalidDerivationIndex(u32),
     /// Spending policy is not compatible with this [`KeychainKind`](crate::types::KeychainKind)
     SpendingPolicyRequired(crate::types::KeychainKind),
     /// Error while extracting and manipulating policies
     InvalidPolicyPathError(crate::descriptor::policy::PolicyError),
     /// Signing error
     Signer(crate::wallet::signer::SignerError),
+    /// Error while creating or verifying a proof of reserves
+    Proof(crate::wallet::reserves::ProofError),
+    /// Error while reading or verifying the BDK metadata of a PSBT
+    Metadata(crate::psbt::proprietary::MetadataError),
+    /// Error while parsing or paying a silent payment address
+    SilentPayment(crate::wallet::silent_payments::SilentPaymentError),
+    /// Error while building or parsing a BIP-388 wallet policy
+    WalletPolicy(crate::descriptor::wallet_policy::WalletPolicyError),
+    /// The PSBT doesn't contain the output spent by the input at this index
+    MissingInputUTXO(usize),
+    /// The input at this index of the PSBT is not finalized yet
+    InputNotFinalized(usize),
 
     // Blockchain interface errors
     /// Thrown when trying to call a method that requires a network connection, [`Wallet::sync`](crate::Wallet::sync) and [`Wallet::broadcast`](crate::Wallet::broadcast)
@@ -117,11 +149,12 @@ pub enum Error {
     JSON(serde_json::Error),
     /// Hex decoding error
     Hex(bitcoin::hashes::hex::Error),
+    /// Base64 decoding error
+    Base64(base64::DecodeError),
     /// Partially signed bitcoin transaction error
     PSBT(bitcoin::util::psbt::Error),
 
     //KeyMismatch(bitcoin::secp256k1::PublicKey, bitcoin::secp256k1::PublicKey),
-    //MissingInputUTXO(usize),
     //InvalidAddressNetwork(Address),
     //DifferentTransactions,
     //DifferentDescriptorStructure,
@@ -139,6 +172,9 @@ pub enum Error {
     #[cfg(feature = "key-value-db")]
     /// Sled database error
     Sled(sled::Error),
+    #[cfg(feature = "verify")]
+    /// Transaction verification error
+    Verification(crate::wallet::verify::VerifyError),
 }
 
 impl fmt::Display for Error {
@@ -166,6 +202,10 @@ impl_error!(descriptor::error::Error, Descriptor);
 impl_error!(address_validator::AddressValidatorError, AddressValidator);
 impl_error!(descriptor::policy::PolicyError, InvalidPolicyPathError);
 impl_error!(wallet::signer::SignerError, Signer);
+impl_error!(wallet::reserves::ProofError, Proof);
+impl_error!(crate::psbt::proprietary::MetadataError, Metadata);
+impl_error!(wallet::silent_payments::SilentPaymentError, SilentPayment);
+impl_error!(descriptor::wallet_policy::WalletPolicyError, WalletPolicy);
 
 impl From<crate::keys::KeyError> for Error {
     fn from(key_error: crate::keys::KeyError) -> Error {
@@ -184,6 +224,7 @@ impl_error!(bitcoin::util::bip32::Error, BIP32);
 impl_error!(bitcoin::secp256k1::Error, Secp256k1);
 impl_error!(serde_json::Error, JSON);
 impl_error!(bitcoin::hashes::hex::Error, Hex);
+impl_error!(base64::DecodeError, Base64);
 impl_error!(bitcoin::util::psbt::Error, PSBT);
 
 #[cfg(feature = "electrum")]
@@ -202,3 +243,13 @@ impl From<crate::blockchain::compact_filters::CompactFiltersError> for Error {
         }
     }
 }
+
+#[cfg(feature = "verify")]
+impl From<crate::wallet::verify::VerifyError> for Error {
+    fn from(other: crate::wallet::verify::VerifyError) -> Self {
+        match other {
+            crate::wallet::verify::VerifyError::Global(inner) => *inner,
+            err => Error::Verification(err),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Integration tests
+//!
+//! This module contains the tools used to test the [`Blockchain`](crate::blockchain::Blockchain)
+//! backends against a regtest `bitcoind` node and an `electrs` server, so that backends
+//! implemented outside of this library can be checked with the same suite of tests used
+//! internally.
+//!
+//! The nodes are not started automatically, their location is read from the following
+//! environment variables:
+//!
+//! * `MAGICAL_RPC_URL`: address of the `bitcoind` RPC interface, defaults to `127.0.0.1:18443`
+//! * `MAGICAL_RPC_AUTH`: either `USER_PASS`, to authenticate with `MAGICAL_RPC_USER` and
+//!   `MAGICAL_RPC_PASS`, or `COOKIEFILE`, to use the cookie file at `MAGICAL_RPC_COOKIEFILE`
+//!   (the default)
+//! * `MAGICAL_ELECTRUM_URL`: URL of the Electrum interface of `electrs`, defaults to
+//!   `tcp://127.0.0.1:50001`
+//! * `MAGICAL_ESPLORA_URL`: URL of the Esplora interface of `electrs`, defaults to
+//!   `http://127.0.0.1:3002`
+//!
+//! The `ci/start-core.sh` script in the repository starts both of them.
+//!
+//! ## Example
+//!
+//! Tagging a function that returns a [`Blockchain`](crate::blockchain::Blockchain) with
+//! [`bdk_blockchain_tests`] generates the whole suite of tests for it. Since the tests share the
+//! same nodes they are run serially, which requires the crate to also depend on `serial_test`.
+//!
+//! ```ignore
+//! use bdk::integration_test::bdk_blockchain_tests;
+//!
+//! #[bdk_blockchain_tests]
+//! fn my_blockchain() -> MyBlockchain {
+//!     MyBlockchain::new(&bdk::integration_test::get_electrum_url())
+//! }
+//! ```
+//!
+//! The [`TestClient`] can also be used directly to write more tests:
+//!
+//! ```no_run
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::integration_test::*;
+//! # use bdk::OfflineWallet;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let wallet: OfflineWallet<_> = bdk::Wallet::new_offline(descriptor, None, Network::Regtest, MemoryDatabase::default())?;
+//! let mut test_client = TestClient::new();
+//! let txid = fund_wallet(&mut test_client, &wallet, 50_000, Some(1))?;
+//! mine_blocks(&mut test_client, 6);
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+pub use bdk_macros::block_on_maybe_async;
+pub use testutils::*;
+pub use testutils_macros::bdk_blockchain_tests;
+
+use bitcoin::Txid;
+
+use crate::blockchain::BlockchainMarker;
+use crate::database::BatchDatabase;
+use crate::error::Error;
+use crate::wallet::Wallet;
+
+/// Send `amount` to a new address of `wallet`, and optionally wait until the transaction has
+/// `confirmations`
+pub fn fund_wallet<B, D>(
+    test_client: &mut TestClient,
+    wallet: &Wallet<B, D>,
+    amount: u64,
+    confirmations: Option<u64>,
+) -> Result<Txid, Error>
+where
+    B: BlockchainMarker,
+    D: BatchDatabase,
+{
+    let address = wallet.get_new_address()?;
+    let tx = TestIncomingTx::new(
+        vec![TestIncomingOutput::new(amount, address)],
+        confirmations,
+        None,
+        None,
+    );
+
+    Ok(test_client.receive(tx))
+}
+
+/// Mine `num_blocks` blocks, sending the rewards to an address of the node
+pub fn mine_blocks(test_client: &mut TestClient, num_blocks: u64) {
+    test_client.generate(num_blocks, None)
+}
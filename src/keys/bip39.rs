@@ -23,6 +23,36 @@
 // SOFTWARE.
 
 //! BIP-0039
+//!
+//! Mnemonics and seeds zero out their memory when they are dropped, and so do the passphrases
+//! once they have been used to compute the seed. Note that this doesn't apply to the extended
+//! private keys derived from them, which are kept by the wallet to sign transactions.
+//!
+//! Every standard word list is supported, see [`ALL_LANGUAGES`]. When restoring a mnemonic
+//! without specifying its language, the language is detected from the phrase.
+//!
+//! ## Example
+//!
+//! ```
+//! use bdk::keys::bip39::{MnemonicOptions, MnemonicType, MnemonicWithPassphrase};
+//! use bdk::keys::{GeneratableKey, GeneratedKey};
+//! use bdk::miniscript::Segwitv0;
+//! use bdk::template::BIP84;
+//! use bdk::KeychainKind;
+//!
+//! let options = MnemonicOptions::new()
+//!     .word_count(MnemonicType::Words12)
+//!     .passphrase("correct horse battery staple");
+//!
+//! let generated: GeneratedKey<_, Segwitv0> = MnemonicWithPassphrase::generate(options.clone())
+//!     .map_err(|_| bdk::keys::KeyError::Message("Unable to generate the mnemonic".into()))?;
+//! let phrase = generated.0.phrase().to_string();
+//!
+//! // later, restore it from the phrase
+//! let restored = options.restore(&phrase)?;
+//! let template = BIP84(restored, KeychainKind::External);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
 
 // TODO: maybe write our own implementation of bip39? Seems stupid to have an extra dependency for
 // something that should be fairly simple to re-implement.
@@ -32,13 +62,128 @@ use bitcoin::Network;
 
 use miniscript::ScriptContext;
 
-use bip39::{Language, Mnemonic, MnemonicType, Seed};
+pub use bip39::{Language, Mnemonic, MnemonicType, Seed};
+
+use zeroize::Zeroize;
 
 use super::{any_network, DerivableKey, DescriptorKey, GeneratableKey, GeneratedKey, KeyError};
 
 /// Type for a BIP39 mnemonic with an optional passphrase
+///
+/// The passphrase is zeroed out when the seed is computed by [`DerivableKey::add_metadata`], but
+/// since this is a plain tuple it stays in memory if the value is dropped without being used.
 pub type MnemonicWithPassphrase = (Mnemonic, Option<String>);
 
+/// Languages of the standard BIP39 word lists
+pub const ALL_LANGUAGES: [Language; 8] = [
+    Language::English,
+    Language::ChineseSimplified,
+    Language::ChineseTraditional,
+    Language::French,
+    Language::Italian,
+    Language::Japanese,
+    Language::Korean,
+    Language::Spanish,
+];
+
+/// Detect the language of a mnemonic phrase
+///
+/// Returns the first language in [`ALL_LANGUAGES`] for which `phrase` is a valid mnemonic,
+/// checksum included. The seed is computed from the phrase itself, so it doesn't change if a
+/// phrase happens to be valid in more than one language.
+pub fn detect_language(phrase: &str) -> Option<Language> {
+    ALL_LANGUAGES
+        .iter()
+        .find(|language| Mnemonic::validate(phrase, **language).is_ok())
+        .copied()
+}
+
+/// Options to generate or restore a [`MnemonicWithPassphrase`]
+///
+/// By default mnemonics have 24 words and no passphrase, new ones are generated in English and the
+/// language of the restored ones is detected from the phrase. The passphrase is zeroed out from
+/// memory when the options are dropped.
+#[derive(Clone)]
+pub struct MnemonicOptions {
+    language: Option<Language>,
+    mnemonic_type: MnemonicType,
+    passphrase: Option<String>,
+}
+
+impl std::fmt::Debug for MnemonicOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MnemonicOptions")
+            .field("language", &self.language)
+            .field("mnemonic_type", &self.mnemonic_type)
+            .field(
+                "passphrase",
+                &self.passphrase.as_ref().map(|_| "<redacted>"),
+            )
+            .finish()
+    }
+}
+
+impl Default for MnemonicOptions {
+    fn default() -> Self {
+        MnemonicOptions {
+            language: None,
+            mnemonic_type: MnemonicType::Words24,
+            passphrase: None,
+        }
+    }
+}
+
+impl Drop for MnemonicOptions {
+    fn drop(&mut self) {
+        if let Some(passphrase) = self.passphrase.as_mut() {
+            passphrase.zeroize();
+        }
+    }
+}
+
+impl MnemonicOptions {
+    /// Create the default options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the language of the mnemonic
+    pub fn language(mut self, language: Language) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    /// Set the number of words of the mnemonic
+    pub fn word_count(mut self, mnemonic_type: MnemonicType) -> Self {
+        self.mnemonic_type = mnemonic_type;
+        self
+    }
+
+    /// Set the passphrase used together with the mnemonic to compute the seed
+    pub fn passphrase<S: Into<String>>(mut self, passphrase: S) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+
+    /// Restore a [`MnemonicWithPassphrase`] from its phrase, using the language and the
+    /// passphrase of these options
+    ///
+    /// The number of words is taken from the phrase itself, and so is the language unless it has
+    /// been set with [`MnemonicOptions::language`].
+    pub fn restore(&self, phrase: &str) -> Result<MnemonicWithPassphrase, KeyError> {
+        let language = match self.language {
+            Some(language) => language,
+            None => detect_language(phrase).ok_or_else(|| {
+                KeyError::Message("The phrase is not valid in any language".to_string())
+            })?,
+        };
+        let mnemonic = Mnemonic::from_phrase(phrase, language)
+            .map_err(|e| KeyError::Message(e.to_string()))?;
+
+        Ok((mnemonic, self.passphrase.clone()))
+    }
+}
+
 #[cfg_attr(docsrs, doc(cfg(feature = "keys-bip39")))]
 impl<Ctx: ScriptContext> DerivableKey<Ctx> for Seed {
     fn add_metadata(
@@ -63,8 +208,12 @@ impl<Ctx: ScriptContext> DerivableKey<Ctx> for MnemonicWithPassphrase {
         source: Option<bip32::KeySource>,
         derivation_path: bip32::DerivationPath,
     ) -> Result<DescriptorKey<Ctx>, KeyError> {
-        let (mnemonic, passphrase) = self;
+        let (mnemonic, mut passphrase) = self;
         let seed = Seed::new(&mnemonic, passphrase.as_deref().unwrap_or(""));
+        if let Some(passphrase) = passphrase.as_mut() {
+            passphrase.zeroize();
+        }
+
         seed.add_metadata(source, derivation_path)
     }
 }
@@ -98,14 +247,37 @@ impl<Ctx: ScriptContext> GeneratableKey<Ctx> for Mnemonic {
     }
 }
 
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-bip39")))]
+impl<Ctx: ScriptContext> GeneratableKey<Ctx> for MnemonicWithPassphrase {
+    type Entropy = [u8; 32];
+
+    type Options = MnemonicOptions;
+    type Error = Option<bip39::ErrorKind>;
+
+    fn generate_with_entropy(
+        options: Self::Options,
+        entropy: Self::Entropy,
+    ) -> Result<GeneratedKey<Self, Ctx>, Self::Error> {
+        let language = options.language.unwrap_or(Language::English);
+        let generated: GeneratedKey<Mnemonic, Ctx> =
+            Mnemonic::generate_with_entropy((options.mnemonic_type, language), entropy)?;
+
+        Ok(GeneratedKey::new(
+            (generated.key, options.passphrase.clone()),
+            generated.valid_networks,
+        ))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
 
     use bitcoin::util::bip32;
 
-    use bip39::{Language, Mnemonic, MnemonicType};
+    use bip39::{Language, Mnemonic, MnemonicType, Seed};
 
+    use super::{detect_language, MnemonicOptions, MnemonicWithPassphrase, ALL_LANGUAGES};
     use crate::keys::{any_network, GeneratableKey, GeneratedKey};
 
     #[test]
@@ -160,6 +332,85 @@ mod test {
         assert_eq!(generated_mnemonic.to_string(), "primary fetch primary fetch primary fetch primary fetch primary fetch primary fetch primary fetch primary fetch primary fetch primary fetch primary fetch primary foster");
     }
 
+    #[test]
+    fn test_keys_generate_bip39_with_options() {
+        let options = MnemonicOptions::new()
+            .word_count(MnemonicType::Words12)
+            .passphrase("passphrase");
+
+        let generated: GeneratedKey<_, miniscript::Segwitv0> =
+            MnemonicWithPassphrase::generate_with_entropy(
+                options.clone(),
+                crate::keys::test::TEST_ENTROPY,
+            )
+            .unwrap();
+        assert_eq!(generated.valid_networks, any_network());
+        assert_eq!(
+            generated.0.phrase(),
+            "primary fetch primary fetch primary fetch primary fetch primary fetch primary fever"
+        );
+        assert_eq!(generated.1, Some("passphrase".to_string()));
+
+        let restored = options.restore(generated.0.phrase()).unwrap();
+        assert_eq!(restored.0.entropy(), generated.0.entropy());
+        assert_eq!(restored.1, generated.1);
+    }
+
+    #[test]
+    fn test_keys_bip39_restore_with_passphrase() {
+        let restored = MnemonicOptions::new()
+            .passphrase("passphrase")
+            .restore("aim bunker wash balance finish force paper analyst cabin spoon stable organ")
+            .unwrap();
+        let path = bip32::DerivationPath::from_str("m/44'/0'/0'/0").unwrap();
+
+        let (desc, _, _) = crate::descriptor!(wpkh((restored, path))).unwrap();
+        assert_eq!(desc.to_string(), "wpkh([8f6cb80c/44'/0'/0']xpub6DWYS8bbihFevy29M4cbw4ZR3P5E12jB8R88gBDWCTCNpYiDHhYWNywrCF9VZQYagzPmsZpxXpytzSoxynyeFr4ZyzheVjnpLKuse4fiwZw/0/*)");
+
+        assert!(MnemonicOptions::new().restore("aim bunker wash").is_err());
+    }
+
+    #[test]
+    fn test_keys_bip39_languages() {
+        for language in &ALL_LANGUAGES {
+            let options = MnemonicOptions::new()
+                .language(*language)
+                .word_count(MnemonicType::Words12);
+            let generated: GeneratedKey<_, miniscript::Segwitv0> =
+                MnemonicWithPassphrase::generate_with_entropy(
+                    options,
+                    crate::keys::test::TEST_ENTROPY,
+                )
+                .unwrap();
+
+            // the language is detected when it's not specified
+            let restored = MnemonicOptions::new()
+                .restore(generated.0.phrase())
+                .unwrap();
+            assert_eq!(
+                Seed::new(&restored.0, "").as_bytes(),
+                Seed::new(&generated.0, "").as_bytes()
+            );
+        }
+
+        let generated: GeneratedKey<_, miniscript::Segwitv0> = Mnemonic::generate_with_entropy(
+            (MnemonicType::Words12, Language::Japanese),
+            crate::keys::test::TEST_ENTROPY,
+        )
+        .unwrap();
+        assert!(matches!(
+            detect_language(generated.phrase()),
+            Some(Language::Japanese)
+        ));
+
+        // an english phrase can't be restored as a french one
+        assert!(MnemonicOptions::new()
+            .language(Language::French)
+            .restore("aim bunker wash balance finish force paper analyst cabin spoon stable organ")
+            .is_err());
+        assert!(detect_language("aim bunker wash").is_none());
+    }
+
     #[test]
     fn test_keys_generate_bip39_random() {
         let generated_mnemonic: GeneratedKey<_, miniscript::Segwitv0> =
@@ -0,0 +1,1066 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Import keys exported by hardware wallets
+//!
+//! This module parses the files exported by hardware wallets to set up watch-only and multisig
+//! wallets, turning them into [`ExportedKey`]s: extended public keys together with their origin
+//! and the type of script they are meant for.
+//!
+//! Supported formats are:
+//!
+//! * The Coldcard "generic JSON" export, with [`parse_coldcard_generic`]
+//! * The Coldcard multisig export, with [`parse_coldcard_multisig`]
+//! * The wallet files of Electrum multisig wallets, with [`parse_electrum_multisig`]
+//! * The multisig setup files of Coldcard, Sparrow and Specter, with [`parse_multisig_setup`]
+//!
+//! The wallet files of Electrum can also be converted to a pair of descriptors with
+//! [`parse_electrum_wallet`], to migrate a whole wallet, including its private keys, and a
+//! [`MultisigSetup`] can be converted to and from a pair of `sortedmulti` descriptors, to share
+//! the setup of a multisig wallet with its cosigners.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk::bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::{OfflineWallet, ScriptType, Wallet};
+//! use bdk::keys::import::parse_coldcard_generic;
+//! use bdk::template::BIP84Public;
+//! use bdk::KeychainKind;
+//!
+//! let export = r#"{
+//!     "chain": "XTN",
+//!     "xfp": "0F056943",
+//!     "bip84": {
+//!         "name": "p2wpkh",
+//!         "deriv": "m/84'/1'/0'",
+//!         "xpub": "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq"
+//!     }
+//! }"#;
+//!
+//! let keys = parse_coldcard_generic(export)?;
+//! let key = keys.iter().find(|k| k.script_type == ScriptType::P2wpkh).unwrap();
+//!
+//! let wallet: OfflineWallet<_> = Wallet::new_offline(
+//!     BIP84Public(key.xpub, key.fingerprint, KeychainKind::External),
+//!     Some(BIP84Public(key.xpub, key.fingerprint, KeychainKind::Internal)),
+//!     Network::Testnet,
+//!     MemoryDatabase::default(),
+//! )?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+use bitcoin::util::bip32;
+
+use miniscript::descriptor::DescriptorXKey;
+use miniscript::{Descriptor, ScriptContext};
+
+use super::slip132::Slip132Key;
+use super::{DerivableKey, DescriptorKey, DescriptorPublicKey, KeyError, ToDescriptorKey};
+use crate::descriptor::ExtendedDescriptor;
+use crate::types::ScriptType;
+
+/// Errors that can happen while parsing the files exported by hardware wallets
+#[derive(Debug)]
+pub enum ImportError {
+    /// The file is not valid JSON
+    Json(serde_json::Error),
+    /// A required field is missing, or it has the wrong type
+    MissingField(String),
+    /// An extended public key can't be parsed
+    InvalidKey(String),
+    /// A fingerprint can't be parsed
+    InvalidFingerprint(String),
+    /// A derivation path can't be parsed
+    InvalidDerivationPath(String),
+    /// The file doesn't contain any key
+    NoKeys,
+    /// The type of wallet or keystore is not supported
+    UnsupportedWallet(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl_error!(serde_json::Error, Json, ImportError);
+
+/// An extended public key exported by an hardware wallet, together with its origin
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportedKey {
+    /// The extended public key
+    pub xpub: bip32::ExtendedPubKey,
+    /// Fingerprint of the master key of the device
+    pub fingerprint: bip32::Fingerprint,
+    /// Derivation path from the master key to `xpub`
+    pub derivation_path: bip32::DerivationPath,
+    /// Type of script the key is meant for
+    pub script_type: ScriptType,
+}
+
+impl ExportedKey {
+    /// Return the origin of the key
+    pub fn key_source(&self) -> bip32::KeySource {
+        (self.fingerprint, self.derivation_path.clone())
+    }
+}
+
+/// Default number of unused addresses Electrum looks ahead, when the wallet file doesn't set it
+pub const ELECTRUM_DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Descriptors equivalent to an Electrum wallet, returned by [`parse_electrum_wallet`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElectrumWallet {
+    /// Descriptor of the receiving addresses
+    pub descriptor: String,
+    /// Descriptor of the change addresses
+    pub change_descriptor: String,
+    /// Keys of the wallet, in the order of the keystores of the file
+    pub keys: Vec<ExportedKey>,
+    /// Whether the descriptors contain the private key of at least one keystore
+    pub has_private_keys: bool,
+    /// Number of unused addresses Electrum looks ahead, to use as the `max_addresses` when
+    /// syncing the wallet
+    pub gap_limit: u32,
+}
+
+/// Setup of a multisig wallet, in the text format used by Coldcard, Sparrow and Specter
+///
+/// The file contains the name of the wallet, its policy, the type of script and the keys of the
+/// cosigners, identified by the fingerprint of their master key:
+///
+/// ```text
+/// Name: Cold storage
+/// Policy: 2 of 3
+/// Derivation: m/48'/0'/0'/2'
+/// Format: P2WSH
+///
+/// 0F056943: xpub6E...
+/// 3442193E: xpub6F...
+/// 5E3A7D42: xpub6D...
+/// ```
+///
+/// A file can be read with [`parse_multisig_setup`] and written with the [`Display`](fmt::Display)
+/// implementation. The wallet always uses `sortedmulti`, with the receiving addresses derived
+/// from `.../0/*` and the change addresses from `.../1/*`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultisigSetup {
+    /// Name of the wallet
+    pub name: String,
+    /// Number of signatures required to spend
+    pub threshold: usize,
+    /// Type of script of the wallet, either `p2sh`, `p2sh-p2wsh` or `p2wsh`
+    pub script_type: ScriptType,
+    /// Keys of the cosigners, in the order of the file
+    pub keys: Vec<ExportedKey>,
+}
+
+impl MultisigSetup {
+    /// Build the setup of a wallet from its `sortedmulti` descriptors
+    ///
+    /// Every key of `descriptor` must be an extended public key that derives from `.../0/*`, and
+    /// the same key must derive from `.../1/*` in `change_descriptor`. Keys without an origin are
+    /// considered master keys.
+    pub fn from_descriptors(
+        name: &str,
+        descriptor: &ExtendedDescriptor,
+        change_descriptor: &ExtendedDescriptor,
+    ) -> Result<Self, ImportError> {
+        let (script_type, threshold, external_keys) = sortedmulti_keys(descriptor)?;
+        let (change_script_type, change_threshold, internal_keys) =
+            sortedmulti_keys(change_descriptor)?;
+        if script_type != change_script_type
+            || threshold != change_threshold
+            || external_keys.len() != internal_keys.len()
+        {
+            return Err(ImportError::UnsupportedWallet(
+                "descriptors with different policies".into(),
+            ));
+        }
+
+        let keys = external_keys
+            .iter()
+            .zip(internal_keys)
+            .map(|(external, internal)| match (external, internal) {
+                (DescriptorPublicKey::XPub(external), DescriptorPublicKey::XPub(internal))
+                    if external.is_wildcard
+                        && internal.is_wildcard
+                        && external.xkey == internal.xkey
+                        && external.origin == internal.origin
+                        && external.derivation_path.as_ref()
+                            == [bip32::ChildNumber::Normal { index: 0 }]
+                        && internal.derivation_path.as_ref()
+                            == [bip32::ChildNumber::Normal { index: 1 }] =>
+                {
+                    let (fingerprint, derivation_path) =
+                        external.origin.clone().unwrap_or_else(|| {
+                            (
+                                external.xkey.fingerprint(),
+                                bip32::DerivationPath::from(vec![]),
+                            )
+                        });
+
+                    Ok(ExportedKey {
+                        xpub: external.xkey,
+                        fingerprint,
+                        derivation_path,
+                        script_type,
+                    })
+                }
+                _ => Err(ImportError::UnsupportedWallet(format!("key {}", external))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(MultisigSetup {
+            name: name.to_string(),
+            threshold,
+            script_type,
+            keys,
+        })
+    }
+
+    /// Return the descriptors of the receiving and change addresses of the wallet
+    pub fn descriptors(&self) -> Result<(String, String), ImportError> {
+        let descriptor_for_chain = |chain: u32| {
+            let key_exprs = self
+                .keys
+                .iter()
+                .map(|key| {
+                    // the derivation path always starts with "m"
+                    let path = key.derivation_path.to_string();
+                    format!(
+                        "[{}{}]{}/{}/*",
+                        key.fingerprint,
+                        &path[1..],
+                        key.xpub,
+                        chain
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            sortedmulti_descriptor(self.threshold, self.script_type, &key_exprs)
+        };
+
+        Ok((descriptor_for_chain(0)?, descriptor_for_chain(1)?))
+    }
+}
+
+impl fmt::Display for MultisigSetup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.script_type {
+            ScriptType::P2shP2wsh => "P2SH-P2WSH".to_string(),
+            script_type => script_type.to_string().to_uppercase(),
+        };
+        // a derivation path shared by every key is only written once, in the header
+        let common_path = match self.keys.first() {
+            Some(first)
+                if self
+                    .keys
+                    .iter()
+                    .all(|k| k.derivation_path == first.derivation_path) =>
+            {
+                Some(&first.derivation_path)
+            }
+            _ => None,
+        };
+
+        writeln!(f, "# Multisig setup file (created by BDK)")?;
+        writeln!(f, "#")?;
+        writeln!(f, "Name: {}", self.name)?;
+        writeln!(f, "Policy: {} of {}", self.threshold, self.keys.len())?;
+        if let Some(path) = common_path {
+            writeln!(f, "Derivation: {}", path)?;
+        }
+        writeln!(f, "Format: {}", format)?;
+
+        for (index, key) in self.keys.iter().enumerate() {
+            if common_path.is_none() {
+                writeln!(f)?;
+                writeln!(f, "Derivation: {}", key.derivation_path)?;
+            } else if index == 0 {
+                writeln!(f)?;
+            }
+            writeln!(
+                f,
+                "{}: {}",
+                key.fingerprint.to_string().to_uppercase(),
+                key.xpub
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Use the key with its origin, unless a different one is provided
+impl<Ctx: ScriptContext> DerivableKey<Ctx> for ExportedKey {
+    fn add_metadata(
+        self,
+        origin: Option<bip32::KeySource>,
+        derivation_path: bip32::DerivationPath,
+    ) -> Result<DescriptorKey<Ctx>, KeyError> {
+        let origin = origin.or_else(|| Some(self.key_source()));
+
+        DescriptorPublicKey::XPub(DescriptorXKey {
+            origin,
+            xkey: self.xpub,
+            derivation_path,
+            is_wildcard: true,
+        })
+        .to_descriptor_key()
+    }
+}
+
+/// Parse the "generic JSON" file exported by Coldcard
+///
+/// Returns the single-signature keys for every type of script contained in the file, which
+/// are generally `p2pkh`, `p2sh-p2wpkh` and `p2wpkh`.
+pub fn parse_coldcard_generic(json: &str) -> Result<Vec<ExportedKey>, ImportError> {
+    let json: Value = serde_json::from_str(json)?;
+    let fingerprint = parse_fingerprint(&json, "xfp")?;
+
+    let keys = [
+        ("bip44", ScriptType::P2pkh),
+        ("bip49", ScriptType::P2shP2wpkh),
+        ("bip84", ScriptType::P2wpkh),
+    ]
+    .iter()
+    .filter_map(|(section, script_type)| json.get(section).map(|s| (s, *script_type)))
+    .map(|(section, script_type)| {
+        Ok::<_, ImportError>(ExportedKey {
+            xpub: parse_xpub(get_str(section, "xpub")?)?.0,
+            fingerprint,
+            derivation_path: parse_derivation_path(get_str(section, "deriv")?)?,
+            script_type,
+        })
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Err(ImportError::NoKeys);
+    }
+
+    Ok(keys)
+}
+
+/// Parse the multisig export of Coldcard
+///
+/// Returns the keys for every type of multisig script contained in the file, which are
+/// generally `p2sh`, `p2sh-p2wsh` and `p2wsh`.
+pub fn parse_coldcard_multisig(json: &str) -> Result<Vec<ExportedKey>, ImportError> {
+    let json: Value = serde_json::from_str(json)?;
+    let fingerprint = parse_fingerprint(&json, "xfp")?;
+
+    let keys = [
+        ("p2sh", ScriptType::P2sh),
+        ("p2wsh_p2sh", ScriptType::P2shP2wsh),
+        ("p2wsh", ScriptType::P2wsh),
+    ]
+    .iter()
+    .filter(|(name, _)| json.get(name).is_some())
+    .map(|(name, script_type)| {
+        Ok::<_, ImportError>(ExportedKey {
+            xpub: parse_xpub(get_str(&json, name)?)?.0,
+            fingerprint,
+            derivation_path: parse_derivation_path(get_str(&json, &format!("{}_deriv", name))?)?,
+            script_type: *script_type,
+        })
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Err(ImportError::NoKeys);
+    }
+
+    Ok(keys)
+}
+
+/// Parse the wallet file of an Electrum multisig wallet
+///
+/// Returns the threshold of the multisig and the keys of every cosigner, in order. The script
+/// type is inferred from the SLIP-132 prefix of the keys, which Electrum uses to tell them apart.
+pub fn parse_electrum_multisig(json: &str) -> Result<(usize, Vec<ExportedKey>), ImportError> {
+    let json: Value = serde_json::from_str(json)?;
+    parse_electrum_multisig_keystores(&json)
+}
+
+/// Parse the wallet file of an Electrum wallet into a pair of descriptors
+///
+/// Standard (single signature) and multisig wallets are supported, as long as their keystores
+/// contain an extended key, its derivation path and the fingerprint of the master key: wallets
+/// of imported addresses or keys and the old pre-BIP32 keystores are not. The type of script is
+/// inferred from the SLIP-132 prefix of the keys, and the multisig descriptors use
+/// `sortedmulti` like Electrum does.
+///
+/// The private keys are included in the descriptors when the file contains them, unless they are
+/// encrypted with the wallet password: a multisig wallet can contain the private keys of some of
+/// its cosigners only. Files whose storage is encrypted must be decrypted with
+/// Electrum first.
+pub fn parse_electrum_wallet(json: &str) -> Result<ElectrumWallet, ImportError> {
+    let json: Value = serde_json::from_str(json)?;
+
+    let wallet_type = get_str(&json, "wallet_type")?;
+    let use_encryption = json
+        .get("use_encryption")
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+    let gap_limit = json
+        .get("gap_limit")
+        .and_then(Value::as_u64)
+        .map(|gap_limit| gap_limit as u32)
+        .unwrap_or(ELECTRUM_DEFAULT_GAP_LIMIT);
+
+    // the threshold is only present for multisig wallets
+    let (threshold, keystores, keys) = match wallet_type {
+        "standard" => {
+            let keystore = json
+                .get("keystore")
+                .ok_or_else(|| ImportError::MissingField("keystore".into()))?;
+            let key = parse_electrum_keystore(keystore, ScriptType::P2pkh)?;
+            (None, vec![keystore], vec![key])
+        }
+        // imported addresses and keys
+        _ if !wallet_type.contains("of") => {
+            return Err(ImportError::UnsupportedWallet(format!(
+                "{} wallet",
+                wallet_type
+            )))
+        }
+        _ => {
+            let (threshold, keys) = parse_electrum_multisig_keystores(&json)?;
+            let keystores = (1..=keys.len())
+                .map(|index| &json[format!("x{}/", index).as_str()])
+                .collect();
+            (Some(threshold), keystores, keys)
+        }
+    };
+
+    let mut secrets = vec![];
+    for keystore in keystores {
+        let keystore_type = get_str(keystore, "type")?;
+        if keystore_type != "bip32" && keystore_type != "hardware" {
+            return Err(ImportError::UnsupportedWallet(format!(
+                "{} keystore",
+                keystore_type
+            )));
+        }
+
+        let xprv = match keystore.get("xprv").and_then(Value::as_str) {
+            // encrypted keys can't be used without the password
+            Some(xprv) if !use_encryption => Some(
+                Slip132Key::<bip32::ExtendedPrivKey>::from_str(xprv)
+                    .map_err(|_| ImportError::InvalidKey(xprv.to_string()))?
+                    .key,
+            ),
+            _ => None,
+        };
+        secrets.push(xprv);
+    }
+    let has_private_keys = secrets.iter().any(Option::is_some);
+
+    let descriptor_for_chain = |chain: u32| {
+        let mut key_exprs = keys.iter().zip(&secrets).map(|(key, xprv)| {
+            // the derivation path always starts with "m"
+            let path = key.derivation_path.to_string();
+            let xkey = match xprv {
+                Some(xprv) => xprv.to_string(),
+                None => key.xpub.to_string(),
+            };
+            format!("[{}{}]{}/{}/*", key.fingerprint, &path[1..], xkey, chain)
+        });
+
+        match (threshold, keys[0].script_type) {
+            (None, ScriptType::P2pkh) => Ok(format!("pkh({})", key_exprs.next().unwrap())),
+            (None, ScriptType::P2shP2wpkh) => {
+                Ok(format!("sh(wpkh({}))", key_exprs.next().unwrap()))
+            }
+            (None, ScriptType::P2wpkh) => Ok(format!("wpkh({})", key_exprs.next().unwrap())),
+            (None, _) => Err(ImportError::UnsupportedWallet(
+                "standard wallet with a multisig key".into(),
+            )),
+            (Some(threshold), script_type) => {
+                sortedmulti_descriptor(threshold, script_type, &key_exprs.collect::<Vec<_>>())
+            }
+        }
+    };
+
+    Ok(ElectrumWallet {
+        descriptor: descriptor_for_chain(0)?,
+        change_descriptor: descriptor_for_chain(1)?,
+        keys,
+        has_private_keys,
+        gap_limit,
+    })
+}
+
+/// Parse the setup file of a multisig wallet, in the format used by Coldcard, Sparrow and Specter
+///
+/// The derivation path of the keys can be set once in the header, or before every key. When the
+/// file doesn't specify the type of script, it's inferred from the SLIP-132 prefix of the keys,
+/// falling back to `p2sh` like Coldcard does.
+pub fn parse_multisig_setup(text: &str) -> Result<MultisigSetup, ImportError> {
+    let mut name = None;
+    let mut policy = None;
+    let mut format = None;
+    let mut derivation_path = None;
+    let mut keys = vec![];
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, ':');
+        let (label, value) = match (parts.next(), parts.next()) {
+            (Some(label), Some(value)) => (label.trim(), value.trim()),
+            _ => return Err(ImportError::UnsupportedWallet(format!("line `{}`", line))),
+        };
+        match label.to_lowercase().as_str() {
+            "name" => name = Some(value.to_string()),
+            "policy" => policy = Some(value.to_string()),
+            "format" => format = Some(value.to_uppercase()),
+            "derivation" => derivation_path = Some(parse_derivation_path(value)?),
+            // every other line is a key, labeled with the fingerprint of its master key
+            _ => {
+                let fingerprint = bip32::Fingerprint::from_str(label)
+                    .map_err(|_| ImportError::InvalidFingerprint(label.to_string()))?;
+                let derivation_path = derivation_path
+                    .clone()
+                    .ok_or_else(|| ImportError::MissingField("Derivation".into()))?;
+                let (xpub, script_type) = parse_xpub(value)?;
+
+                keys.push((fingerprint, derivation_path, xpub, script_type));
+            }
+        }
+    }
+
+    let name = name.ok_or_else(|| ImportError::MissingField("Name".into()))?;
+    let policy = policy.ok_or_else(|| ImportError::MissingField("Policy".into()))?;
+    let (threshold, count) = parse_policy(&policy, "Policy")?;
+    if keys.is_empty() {
+        return Err(ImportError::NoKeys);
+    }
+    if keys.len() != count || threshold == 0 || threshold > count {
+        return Err(ImportError::UnsupportedWallet(format!(
+            "{} policy with {} keys",
+            policy,
+            keys.len()
+        )));
+    }
+
+    let script_type = match format.as_deref() {
+        Some("P2SH") => ScriptType::P2sh,
+        Some("P2SH-P2WSH") | Some("P2WSH-P2SH") => ScriptType::P2shP2wsh,
+        Some("P2WSH") => ScriptType::P2wsh,
+        Some(format) => return Err(ImportError::UnsupportedWallet(format!("{} format", format))),
+        None => keys
+            .iter()
+            .filter_map(|(_, _, _, script_type)| *script_type)
+            .find(|script_type| {
+                matches!(
+                    script_type,
+                    ScriptType::P2sh | ScriptType::P2shP2wsh | ScriptType::P2wsh
+                )
+            })
+            .unwrap_or(ScriptType::P2sh),
+    };
+
+    Ok(MultisigSetup {
+        name,
+        threshold,
+        script_type,
+        keys: keys
+            .into_iter()
+            .map(|(fingerprint, derivation_path, xpub, _)| ExportedKey {
+                xpub,
+                fingerprint,
+                derivation_path,
+                script_type,
+            })
+            .collect(),
+    })
+}
+
+fn parse_electrum_multisig_keystores(
+    json: &Value,
+) -> Result<(usize, Vec<ExportedKey>), ImportError> {
+    // the wallet type looks like "2of3"
+    let (threshold, count) = parse_policy(get_str(json, "wallet_type")?, "wallet_type")?;
+
+    let keys = (1..=count)
+        .map(|index| {
+            let name = format!("x{}/", index);
+            let keystore = json
+                .get(&name)
+                .ok_or_else(|| ImportError::MissingField(name.clone()))?;
+
+            parse_electrum_keystore(keystore, ScriptType::P2sh)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if keys.is_empty() {
+        return Err(ImportError::NoKeys);
+    }
+
+    Ok((threshold, keys))
+}
+
+// Parse the extended public key of an Electrum keystore, which uses `default_script_type` unless
+// its prefix implies a different one
+fn parse_electrum_keystore(
+    keystore: &Value,
+    default_script_type: ScriptType,
+) -> Result<ExportedKey, ImportError> {
+    let (xpub, script_type) = parse_xpub(get_str(keystore, "xpub")?)?;
+    Ok(ExportedKey {
+        xpub,
+        fingerprint: parse_fingerprint(keystore, "root_fingerprint")?,
+        derivation_path: parse_derivation_path(get_str(keystore, "derivation")?)?,
+        script_type: script_type.unwrap_or(default_script_type),
+    })
+}
+
+// Parse a multisig policy like "2of3" or "2 of 3", returning the threshold and the number of keys
+fn parse_policy(policy: &str, field: &str) -> Result<(usize, usize), ImportError> {
+    let mut parts = policy.splitn(2, "of").map(str::trim);
+    match (parts.next(), parts.next()) {
+        (Some(m), Some(n)) => Ok((
+            usize::from_str(m).map_err(|_| ImportError::MissingField(field.into()))?,
+            usize::from_str(n).map_err(|_| ImportError::MissingField(field.into()))?,
+        )),
+        _ => Err(ImportError::MissingField(field.into())),
+    }
+}
+
+// Build a `sortedmulti` descriptor for a multisig type of script
+fn sortedmulti_descriptor(
+    threshold: usize,
+    script_type: ScriptType,
+    key_exprs: &[String],
+) -> Result<String, ImportError> {
+    let sortedmulti = format!("sortedmulti({},{})", threshold, key_exprs.join(","));
+    match script_type {
+        ScriptType::P2sh => Ok(format!("sh({})", sortedmulti)),
+        ScriptType::P2shP2wsh => Ok(format!("sh(wsh({}))", sortedmulti)),
+        ScriptType::P2wsh => Ok(format!("wsh({})", sortedmulti)),
+        _ => Err(ImportError::UnsupportedWallet(
+            "multisig wallet with a single signature key".into(),
+        )),
+    }
+}
+
+// Return the type of script, the threshold and the keys of a `sortedmulti` descriptor
+fn sortedmulti_keys(
+    descriptor: &ExtendedDescriptor,
+) -> Result<(ScriptType, usize, &[DescriptorPublicKey]), ImportError> {
+    match descriptor {
+        Descriptor::ShSortedMulti(keys) => Ok((ScriptType::P2sh, keys.k, &keys.pks[..])),
+        Descriptor::ShWshSortedMulti(keys) => Ok((ScriptType::P2shP2wsh, keys.k, &keys.pks[..])),
+        Descriptor::WshSortedMulti(keys) => Ok((ScriptType::P2wsh, keys.k, &keys.pks[..])),
+        _ => Err(ImportError::UnsupportedWallet(
+            "descriptor without sortedmulti".into(),
+        )),
+    }
+}
+
+fn get_str<'a>(json: &'a Value, field: &str) -> Result<&'a str, ImportError> {
+    json.get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| ImportError::MissingField(field.to_string()))
+}
+
+fn parse_fingerprint(json: &Value, field: &str) -> Result<bip32::Fingerprint, ImportError> {
+    let fingerprint = get_str(json, field)?;
+    bip32::Fingerprint::from_str(fingerprint)
+        .map_err(|_| ImportError::InvalidFingerprint(fingerprint.to_string()))
+}
+
+fn parse_derivation_path(path: &str) -> Result<bip32::DerivationPath, ImportError> {
+    bip32::DerivationPath::from_str(path)
+        .map_err(|_| ImportError::InvalidDerivationPath(path.to_string()))
+}
+
+// Parse an extended public key, which can also use the SLIP-132 prefixes that encode the type of
+// script it's meant for, like `zpub` and `Vpub`
+fn parse_xpub(xpub: &str) -> Result<(bip32::ExtendedPubKey, Option<ScriptType>), ImportError> {
+    let key = Slip132Key::<bip32::ExtendedPubKey>::from_str(xpub)
+        .map_err(|_| ImportError::InvalidKey(xpub.to_string()))?;
+    Ok((key.key, key.script_type))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TPUB: &str = "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq";
+
+    #[test]
+    fn test_parse_coldcard_generic() {
+        let export = r#"{
+            "chain": "XTN",
+            "xfp": "0F056943",
+            "account": 0,
+            "xpub": "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq",
+            "bip44": {
+                "name": "p2pkh",
+                "deriv": "m/44'/1'/0'",
+                "xpub": "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq"
+            },
+            "bip84": {
+                "name": "p2wpkh",
+                "deriv": "m/84'/1'/0'",
+                "xpub": "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq",
+                "_pub": "vpub5SLqN2bLY4WeZH5meCqeSQGMxYpqPMFSXDgKoDgUxtyiGiThVH7HRNU5EjKUBCpzDZfTC2bNdXdBGJkUYXf87AzY5ABNrJebRYMa4JKtehx"
+            }
+        }"#;
+
+        let keys = parse_coldcard_generic(export).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].script_type, ScriptType::P2pkh);
+        assert_eq!(keys[1].script_type, ScriptType::P2wpkh);
+        assert_eq!(keys[1].xpub.to_string(), TPUB);
+        assert_eq!(
+            keys[1].key_source(),
+            (
+                bip32::Fingerprint::from_str("0f056943").unwrap(),
+                bip32::DerivationPath::from_str("m/84'/1'/0'").unwrap()
+            )
+        );
+
+        let key = (
+            keys[1].clone(),
+            bip32::DerivationPath::from_str("m/0").unwrap(),
+        );
+        let (desc, _, _) = crate::descriptor!(wpkh(key)).unwrap();
+        assert_eq!(
+            desc.to_string(),
+            format!("wpkh([0f056943/84'/1'/0']{}/0/*)", TPUB)
+        );
+    }
+
+    #[test]
+    fn test_parse_coldcard_multisig() {
+        let export = r#"{
+            "p2sh_deriv": "m/45'",
+            "p2sh": "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq",
+            "p2wsh_p2sh_deriv": "m/48'/1'/0'/1'",
+            "p2wsh_p2sh": "Upub5JQfBberxLXY8Z42kWX14PWfWNief5wXvNomw648xfSEqoDP12KztRfs2EKMjjPy3PcdK8zw45f8WBkg84Q4TRRc3HbMfoJ73YZoFghJThC",
+            "p2wsh_deriv": "m/48'/1'/0'/2'",
+            "p2wsh": "Vpub5dEvVGKn7251yrF9asJdGUcAgLs6bhw2qVKziUx2Lfp7tu2cFgVZWVL13SGwje3tT2jS4cbVWk1gPUNEqkp5Ff7CudHnFi7bKGdSeDJGbvS",
+            "xfp": "0F056943"
+        }"#;
+
+        let keys = parse_coldcard_multisig(export).unwrap();
+        assert_eq!(
+            keys.iter().map(|k| k.script_type).collect::<Vec<_>>(),
+            vec![ScriptType::P2sh, ScriptType::P2shP2wsh, ScriptType::P2wsh]
+        );
+        // the SLIP-132 keys are converted to the standard format
+        assert!(keys.iter().all(|k| k.xpub.to_string() == TPUB));
+        assert_eq!(
+            keys[2].derivation_path,
+            bip32::DerivationPath::from_str("m/48'/1'/0'/2'").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_electrum_multisig() {
+        let export = r#"{
+            "wallet_type": "2of2",
+            "x1/": {
+                "type": "hardware",
+                "hw_type": "coldcard",
+                "xpub": "Zpub6vZyhw1ShkEwP45J3TumYQietzUhSMreYW7k4sCza1iYaH9LrzR3inCtQ91szWGaMYWVNy74YBE9n1gmPHBzq2wEFGR83SMcFGuAbGkfiwg",
+                "derivation": "m/48'/0'/0'/2'",
+                "root_fingerprint": "0f056943"
+            },
+            "x2/": {
+                "type": "bip32",
+                "xpub": "Zpub6vZyhw1ShkEwP45J3TumYQietzUhSMreYW7k4sCza1iYaH9LrzR3inCtQ91szWGaMYWVNy74YBE9n1gmPHBzq2wEFGR83SMcFGuAbGkfiwg",
+                "derivation": "m/48'/0'/1'/2'",
+                "root_fingerprint": "3442193e"
+            }
+        }"#;
+
+        let (threshold, keys) = parse_electrum_multisig(export).unwrap();
+        assert_eq!(threshold, 2);
+        assert_eq!(keys.len(), 2);
+        assert!(keys.iter().all(|k| k.script_type == ScriptType::P2wsh));
+        assert_eq!(keys[0].xpub.to_string(), "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8");
+        assert_eq!(
+            keys[1].fingerprint,
+            bip32::Fingerprint::from_str("3442193e").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_electrum_wallet_standard() {
+        let export = r#"{
+            "wallet_type": "standard",
+            "use_encryption": false,
+            "gap_limit": 50,
+            "seed_type": "segwit",
+            "keystore": {
+                "type": "bip32",
+                "xpub": "zpub6jftahH18ngZyGvHrnLq9GsJuWqr58wMWoZpPE7BZ8Tjwjb5MH48Nupvng8cybjf1hy8LNxrkbKc5s12SMPL37RHEh8JEq4pVvi8D1Yc1cH",
+                "xprv": "zprvAWgYBBk7JR8Gknqpkkopn8vaMV1MfgDW9aeDaqhZznvm4wFvojjsq7WSwQsSkseHcjg6xn5vQU8hPH1SptyKYoFmeT2q8oMj6hLdUwFh7rm",
+                "derivation": "m/0'",
+                "root_fingerprint": "0f056943"
+            }
+        }"#;
+
+        let wallet = parse_electrum_wallet(export).unwrap();
+        assert_eq!(wallet.descriptor, "wpkh([0f056943/0']xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/0/*)");
+        assert_eq!(wallet.change_descriptor, "wpkh([0f056943/0']xprv9s21ZrQH143K4CTb63EaMxja1YiTnSEWKMbn23uoEnAzxjdUJRQkazCAtzxGm4LSoTSVTptoV9RbchnKPW9HxKtZumdyxyikZFDLhogJ5Uj/1/*)");
+        assert!(wallet.has_private_keys);
+        assert_eq!(wallet.gap_limit, 50);
+        assert_eq!(wallet.keys[0].script_type, ScriptType::P2wpkh);
+
+        // the encrypted private key is ignored, and the gap limit has a default
+        let export = export
+            .replace("\"use_encryption\": false", "\"use_encryption\": true")
+            .replace("\"gap_limit\": 50,", "");
+        let wallet = parse_electrum_wallet(&export).unwrap();
+        assert_eq!(wallet.descriptor, "wpkh([0f056943/0']xpub661MyMwAqRbcGgY4C4maj6gJZaYxBtxMgaXNpSKQo7hyqXxcqxj18nWekGDSynRpCRjWqRmjqGcWKHmtzxZJSe45W1jT51RqxUaqRs9PNVs/0/*)");
+        assert!(!wallet.has_private_keys);
+        assert_eq!(wallet.gap_limit, ELECTRUM_DEFAULT_GAP_LIMIT);
+    }
+
+    #[test]
+    fn test_parse_electrum_wallet_multisig() {
+        let export = r#"{
+            "wallet_type": "2of2",
+            "gap_limit": 30,
+            "x1/": {
+                "type": "hardware",
+                "hw_type": "coldcard",
+                "xpub": "Zpub6vZyhw1ShkEwP45J3TumYQietzUhSMreYW7k4sCza1iYaH9LrzR3inCtQ91szWGaMYWVNy74YBE9n1gmPHBzq2wEFGR83SMcFGuAbGkfiwg",
+                "derivation": "m/48'/0'/0'/2'",
+                "root_fingerprint": "0f056943"
+            },
+            "x2/": {
+                "type": "bip32",
+                "xpub": "Zpub6vZyhw1ShkEwP45J3TumYQietzUhSMreYW7k4sCza1iYaH9LrzR3inCtQ91szWGaMYWVNy74YBE9n1gmPHBzq2wEFGR83SMcFGuAbGkfiwg",
+                "derivation": "m/48'/0'/1'/2'",
+                "root_fingerprint": "3442193e"
+            }
+        }"#;
+
+        let wallet = parse_electrum_wallet(export).unwrap();
+        assert_eq!(wallet.descriptor, "wsh(sortedmulti(2,\
+            [0f056943/48'/0'/0'/2']xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*,\
+            [3442193e/48'/0'/1'/2']xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8/0/*))");
+        assert!(wallet.change_descriptor.ends_with("/1/*))"));
+        assert!(!wallet.has_private_keys);
+        assert_eq!(wallet.gap_limit, 30);
+        assert_eq!(wallet.keys.len(), 2);
+
+        assert!(matches!(
+            parse_electrum_wallet(
+                r#"{"wallet_type": "imported", "keystore": {"type": "imported"}}"#
+            ),
+            Err(ImportError::UnsupportedWallet(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_multisig_setup() {
+        let export = r#"
+            # Coldcard Multisig setup file (created by Sparrow)
+            #
+            Name: Cold storage
+            Policy: 2 of 2
+            Derivation: m/48'/1'/0'/2'
+            Format: P2WSH
+
+            0F056943: tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq
+            3442193E: Vpub5dEvVGKn7251yrF9asJdGUcAgLs6bhw2qVKziUx2Lfp7tu2cFgVZWVL13SGwje3tT2jS4cbVWk1gPUNEqkp5Ff7CudHnFi7bKGdSeDJGbvS
+        "#;
+
+        let setup = parse_multisig_setup(export).unwrap();
+        assert_eq!(setup.name, "Cold storage");
+        assert_eq!(setup.threshold, 2);
+        assert_eq!(setup.script_type, ScriptType::P2wsh);
+        assert!(setup.keys.iter().all(|k| k.xpub.to_string() == TPUB));
+        assert_eq!(
+            setup.keys[1].fingerprint,
+            bip32::Fingerprint::from_str("3442193e").unwrap()
+        );
+
+        let (descriptor, change_descriptor) = setup.descriptors().unwrap();
+        assert_eq!(
+            descriptor,
+            format!(
+                "wsh(sortedmulti(2,[0f056943/48'/1'/0'/2']{}/0/*,[3442193e/48'/1'/0'/2']{}/0/*))",
+                TPUB, TPUB
+            )
+        );
+        assert_eq!(
+            change_descriptor,
+            format!(
+                "wsh(sortedmulti(2,[0f056943/48'/1'/0'/2']{}/1/*,[3442193e/48'/1'/0'/2']{}/1/*))",
+                TPUB, TPUB
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_multisig_setup_per_key_derivation() {
+        // without a format the type of script comes from the SLIP-132 prefix of the keys
+        let export = r#"
+            Name: Vault
+            Policy: 1 of 2
+
+            Derivation: m/48'/1'/0'/1'
+            0F056943: Upub5JQfBberxLXY8Z42kWX14PWfWNief5wXvNomw648xfSEqoDP12KztRfs2EKMjjPy3PcdK8zw45f8WBkg84Q4TRRc3HbMfoJ73YZoFghJThC
+
+            Derivation: m/48'/1'/1'/1'
+            3442193E: tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq
+        "#;
+
+        let setup = parse_multisig_setup(export).unwrap();
+        assert_eq!(setup.threshold, 1);
+        assert_eq!(setup.script_type, ScriptType::P2shP2wsh);
+        assert_eq!(
+            setup.keys[1].derivation_path,
+            bip32::DerivationPath::from_str("m/48'/1'/1'/1'").unwrap()
+        );
+
+        // keys with different derivation paths are written one by one
+        let written = setup.to_string();
+        assert!(written.contains("Format: P2SH-P2WSH\n\nDerivation: m/48'/1'/0'/1'\n0F056943: "));
+        assert_eq!(parse_multisig_setup(&written).unwrap(), setup);
+    }
+
+    #[test]
+    fn test_multisig_setup_from_descriptors() {
+        use crate::descriptor::ToWalletDescriptor;
+        use bitcoin::Network;
+
+        let tpub_b = "tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3";
+        let tpub_c = "tpubDDp3ZSH1yCwusRppH7zgSxq2t1VEUyXSeEp8E5aFS8m43MknUjiF1bSLo3CGWAxbDyhF1XowA5ukPzyJZjznYk3kYi6oe7QxtX2euvKWsk4";
+
+        let (descriptor, _) = format!(
+            "sh(sortedmulti(2,[0f056943/45']{}/0/*,[3442193e/45']{}/0/*,{}/0/*))",
+            TPUB, tpub_b, tpub_c
+        )
+        .to_wallet_descriptor(Network::Testnet)
+        .unwrap();
+        let (change_descriptor, _) = format!(
+            "sh(sortedmulti(2,[0f056943/45']{}/1/*,[3442193e/45']{}/1/*,{}/1/*))",
+            TPUB, tpub_b, tpub_c
+        )
+        .to_wallet_descriptor(Network::Testnet)
+        .unwrap();
+
+        let setup =
+            MultisigSetup::from_descriptors("Legacy", &descriptor, &change_descriptor).unwrap();
+        assert_eq!(setup.script_type, ScriptType::P2sh);
+        assert_eq!(setup.threshold, 2);
+        assert_eq!(setup.keys.len(), 3);
+        // keys without an origin are master keys
+        assert_eq!(
+            setup.keys[2].derivation_path,
+            bip32::DerivationPath::from(vec![])
+        );
+        assert_eq!(setup.keys[2].fingerprint, setup.keys[2].xpub.fingerprint());
+
+        let parsed = parse_multisig_setup(&setup.to_string()).unwrap();
+        assert_eq!(parsed, setup);
+
+        // the change descriptor must use the same keys
+        assert!(matches!(
+            MultisigSetup::from_descriptors("Legacy", &descriptor, &descriptor),
+            Err(ImportError::UnsupportedWallet(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_multisig_setup_errors() {
+        let key = format!("0F056943: {}", TPUB);
+
+        assert!(matches!(
+            parse_multisig_setup(&format!("Policy: 1 of 1\nDerivation: m/45'\n{}", key)),
+            Err(ImportError::MissingField(field)) if field == "Name"
+        ));
+        assert!(matches!(
+            parse_multisig_setup(&format!("Name: a\nPolicy: 1 of 1\n{}", key)),
+            Err(ImportError::MissingField(field)) if field == "Derivation"
+        ));
+        assert!(matches!(
+            parse_multisig_setup(&format!(
+                "Name: a\nPolicy: 2 of 2\nDerivation: m/45'\n{}",
+                key
+            )),
+            Err(ImportError::UnsupportedWallet(_))
+        ));
+        assert!(matches!(
+            parse_multisig_setup(&format!(
+                "Name: a\nPolicy: 1 of 1\nDerivation: m/45'\nFormat: P2TR\n{}",
+                key
+            )),
+            Err(ImportError::UnsupportedWallet(_))
+        ));
+        assert!(matches!(
+            parse_multisig_setup("Name: a\nPolicy: 1 of 1\nDerivation: m/45'\nXFP: tpub"),
+            Err(ImportError::InvalidFingerprint(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            parse_coldcard_generic("not json"),
+            Err(ImportError::Json(_))
+        ));
+        assert!(matches!(
+            parse_coldcard_generic(r#"{"xfp": "0F056943"}"#),
+            Err(ImportError::NoKeys)
+        ));
+        assert!(matches!(
+            parse_coldcard_generic(r#"{"bip84": {}}"#),
+            Err(ImportError::MissingField(field)) if field == "xfp"
+        ));
+        assert!(matches!(
+            parse_coldcard_generic(
+                r#"{"xfp": "0F056943", "bip84": {"deriv": "m/84'/1'/0'", "xpub": "tpubinvalid"}}"#
+            ),
+            Err(ImportError::InvalidKey(_))
+        ));
+        assert!(matches!(
+            parse_electrum_multisig(r#"{"wallet_type": "standard"}"#),
+            Err(ImportError::MissingField(_))
+        ));
+    }
+}
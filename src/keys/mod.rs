@@ -43,11 +43,17 @@ use miniscript::descriptor::{DescriptorXKey, KeyMap};
 pub use miniscript::ScriptContext;
 use miniscript::{Miniscript, Terminal};
 
+use crate::types::ScriptType;
 use crate::wallet::utils::SecpCtx;
 
 #[cfg(feature = "keys-bip39")]
 #[cfg_attr(docsrs, doc(cfg(feature = "keys-bip39")))]
 pub mod bip39;
+pub mod import;
+pub mod slip132;
+#[cfg(feature = "keys-slip39")]
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-slip39")))]
+pub mod slip39;
 
 /// Set of valid networks for a key
 pub type ValidNetworks = HashSet<Network>;
@@ -442,6 +448,50 @@ pub trait GeneratableKey<Ctx: ScriptContext>: Sized {
         thread_rng().fill(entropy.as_mut());
         Self::generate_with_entropy(options, entropy)
     }
+
+    /// Generate a key given the options with a random entropy, mixing in some extra entropy
+    /// provided by the user
+    ///
+    /// This can be used to avoid trusting only the random number generator of the OS, by adding
+    /// the result of some dice rolls or the output of an hardware RNG. See [`mix_entropy`] for
+    /// the details on how the two sources are combined.
+    fn generate_with_extra_entropy(
+        options: Self::Options,
+        extra_entropy: &[u8],
+    ) -> Result<GeneratedKey<Self, Ctx>, Self::Error> {
+        use rand::{thread_rng, Rng};
+
+        let mut entropy = Self::Entropy::default();
+        thread_rng().fill(entropy.as_mut());
+        mix_entropy(entropy.as_mut(), extra_entropy);
+        Self::generate_with_entropy(options, entropy)
+    }
+}
+
+/// Mix some extra entropy provided by the user into `entropy`
+///
+/// The extra entropy is first expanded to the length of `entropy` by concatenating the blocks
+/// `SHA256("bdk/extra-entropy" || counter || extra_entropy)`, where `counter` is the index of the
+/// block encoded as a 4-bytes big-endian integer, starting from zero. The result is then XORed
+/// into `entropy`.
+///
+/// As long as the two sources are independent, the result is at least as unpredictable as the
+/// best of them: a weak random number generator is fixed by enough extra entropy, and a poor
+/// choice of extra entropy doesn't make a good generator any weaker.
+pub fn mix_entropy(entropy: &mut [u8], extra_entropy: &[u8]) {
+    use bitcoin::hashes::{sha256, Hash, HashEngine};
+
+    for (counter, chunk) in entropy.chunks_mut(sha256::Hash::LEN).enumerate() {
+        let mut engine = sha256::Hash::engine();
+        engine.input(b"bdk/extra-entropy");
+        engine.input(&(counter as u32).to_be_bytes());
+        engine.input(extra_entropy);
+        let block = sha256::Hash::from_engine(engine).into_inner();
+
+        for (byte, mask) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= mask;
+        }
+    }
 }
 
 /// Trait that allows generating a key with the default options
@@ -463,6 +513,16 @@ where
     fn generate_default() -> Result<GeneratedKey<Self, Ctx>, Self::Error> {
         Self::generate(Default::default())
     }
+
+    /// Generate a key with the default options and a random entropy, mixing in some extra
+    /// entropy provided by the user
+    ///
+    /// See [`GeneratableKey::generate_with_extra_entropy`].
+    fn generate_default_with_extra_entropy(
+        extra_entropy: &[u8],
+    ) -> Result<GeneratedKey<Self, Ctx>, Self::Error> {
+        Self::generate_with_extra_entropy(Default::default(), extra_entropy)
+    }
 }
 
 /// Automatic implementation of [`GeneratableDefaultOptions`] for [`GeneratableKey`]s where
@@ -686,6 +746,65 @@ impl<Ctx: ScriptContext> ToDescriptorKey<Ctx> for PrivateKey {
     }
 }
 
+// Testnet and regtest keys share the same encoding, so only mainnet can be told apart
+fn check_key_network(key_network: Network, expected: Network) -> Result<(), KeyError> {
+    if (key_network == Network::Bitcoin) != (expected == Network::Bitcoin) {
+        Err(KeyError::NetworkMismatch {
+            key_network,
+            expected,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Import a private key encoded in the WIF format, checking that it's valid in `network`
+///
+/// Returns [`KeyError::NetworkMismatch`] if the key was encoded for a different network, like a
+/// mainnet key imported in a testnet wallet. Since testnet and regtest keys share the same
+/// encoding, they can be used interchangeably.
+///
+/// ## Example
+///
+/// ```
+/// # use bdk::bitcoin::Network;
+/// # use bdk::keys::{import_wif, DescriptorKey};
+/// # use bdk::miniscript::Segwitv0;
+/// let key: DescriptorKey<Segwitv0> = import_wif(
+///     "cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW",
+///     Network::Testnet,
+/// )?;
+/// let (descriptor, key_map, _) = bdk::descriptor!(wpkh(key))?;
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub fn import_wif<Ctx: ScriptContext>(
+    wif: &str,
+    network: Network,
+) -> Result<DescriptorKey<Ctx>, KeyError> {
+    let key = PrivateKey::from_wif(wif).map_err(|e| KeyError::Message(e.to_string()))?;
+    check_key_network(key.network, network)?;
+
+    key.to_descriptor_key()
+}
+
+/// Import an extended private key encoded in base58, checking that it's valid in `network`
+///
+/// The key is used with the given `derivation_path` followed by a wildcard, and returns
+/// [`KeyError::NetworkMismatch`] if it was encoded for a different network, like a `xprv`
+/// imported in a testnet wallet. Since testnet and regtest keys share the same encoding, they
+/// can be used interchangeably.
+pub fn import_xprv<Ctx: ScriptContext>(
+    xprv: &str,
+    derivation_path: bip32::DerivationPath,
+    network: Network,
+) -> Result<DescriptorKey<Ctx>, KeyError> {
+    let xprv =
+        bip32::ExtendedPrivKey::from_str(xprv).map_err(|e| KeyError::Message(e.to_string()))?;
+    check_key_network(xprv.network, network)?;
+
+    xprv.add_metadata(None, derivation_path)
+}
+
 /// Errors thrown while working with [`keys`](crate::keys)
 #[derive(Debug)]
 pub enum KeyError {
@@ -695,10 +814,32 @@ pub enum KeyError {
     InvalidNetwork,
     /// The key has an invalid checksum
     InvalidChecksum,
+    /// The descriptor contains a hardened wildcard (`/*'`), which can't be derived from an extended
+    /// public key
+    HardenedDerivationXpub,
+    /// The key was encoded for a different network, like a mainnet key imported in a testnet
+    /// wallet
+    NetworkMismatch {
+        /// Network the key was encoded for
+        key_network: Network,
+        /// Network of the wallet
+        expected: Network,
+    },
+
+    /// The type of script implied by the SLIP-132 prefix of a key doesn't match the type of the
+    /// descriptor it's used in
+    ScriptTypeMismatch {
+        /// Type of script implied by the prefix of the key
+        key_script_type: ScriptType,
+        /// Type of script of the descriptor
+        descriptor_script_type: ScriptType,
+    },
 
     /// Custom error message
     Message(String),
 
+    /// Base58 error
+    Base58(bitcoin::util::base58::Error),
     /// BIP32 error
     BIP32(bitcoin::util::bip32::Error),
     /// Miniscript error
@@ -706,6 +847,7 @@ pub enum KeyError {
 }
 
 impl_error!(miniscript::Error, Miniscript, KeyError);
+impl_error!(bitcoin::util::base58::Error, Base58, KeyError);
 impl_error!(bitcoin::util::bip32::Error, BIP32, KeyError);
 
 impl std::fmt::Display for KeyError {
@@ -718,6 +860,7 @@ impl std::error::Error for KeyError {}
 
 #[cfg(test)]
 pub mod test {
+    use bitcoin::hashes::hex::ToHex;
     use bitcoin::util::bip32;
 
     use super::*;
@@ -744,4 +887,74 @@ pub mod test {
             "L2wTu6hQrnDMiFNWA5na6jB12ErGQqtXwqpSL7aWquJaZG8Ai3ch"
         );
     }
+
+    #[test]
+    fn test_keys_import_wif() {
+        let wif = "cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW";
+
+        assert!(import_wif::<miniscript::Segwitv0>(wif, Network::Testnet).is_ok());
+        assert!(import_wif::<miniscript::Segwitv0>(wif, Network::Regtest).is_ok());
+        assert!(matches!(
+            import_wif::<miniscript::Segwitv0>(wif, Network::Bitcoin),
+            Err(KeyError::NetworkMismatch {
+                key_network: Network::Testnet,
+                expected: Network::Bitcoin
+            })
+        ));
+        assert!(matches!(
+            import_wif::<miniscript::Segwitv0>("not a key", Network::Testnet),
+            Err(KeyError::Message(_))
+        ));
+    }
+
+    #[test]
+    fn test_keys_import_xprv() {
+        let xprv = "tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS";
+        let path = bip32::DerivationPath::from_str("m/0").unwrap();
+
+        let key =
+            import_xprv::<miniscript::Segwitv0>(xprv, path.clone(), Network::Testnet).unwrap();
+        let (desc, key_map, networks) = crate::descriptor!(wpkh(key)).unwrap();
+        assert_eq!(desc.to_string(), "wpkh(tpubD6NzVbkrYhZ4WWGhiCNyq7EUNpoFdcVrxB4SqRUv55oSXHPAt47E5Et1xYM8S5ZqtwPuPZ5j2s1pFALLGg9uK5emMbKqdi8r2B2SRx9oQSk/0/*)");
+        assert_eq!(key_map.len(), 1);
+        assert_eq!(networks, test_networks());
+
+        assert!(matches!(
+            import_xprv::<miniscript::Segwitv0>(xprv, path, Network::Bitcoin),
+            Err(KeyError::NetworkMismatch {
+                key_network: Network::Testnet,
+                expected: Network::Bitcoin
+            })
+        ));
+    }
+
+    #[test]
+    fn test_keys_mix_entropy() {
+        let mut entropy = TEST_ENTROPY;
+        mix_entropy(&mut entropy, b"dice: 3 6 1 4");
+        assert_eq!(
+            entropy.to_hex(),
+            "09ffc1a360159c54efbf3267356423a69f0ace63b9a8da003eb8c3c2ece9cff6"
+        );
+
+        // longer entropy uses more blocks
+        let mut entropy = [0u8; 40];
+        mix_entropy(&mut entropy, b"dice: 3 6 1 4");
+        assert_eq!(
+            entropy.to_hex(),
+            "a3556b09cabf36fe451598cd9fce890c35a064c9130270aa941269684643655c67e94ed5698a6587"
+        );
+    }
+
+    #[test]
+    fn test_keys_generate_with_extra_entropy() {
+        let first: GeneratedKey<_, miniscript::Segwitv0> =
+            bip32::ExtendedPrivKey::generate_default_with_extra_entropy(b"dice: 3 6 1 4").unwrap();
+        let second: GeneratedKey<_, miniscript::Segwitv0> =
+            bip32::ExtendedPrivKey::generate_default_with_extra_entropy(b"dice: 3 6 1 4").unwrap();
+
+        assert_eq!(first.valid_networks, any_network());
+        // the entropy of the OS is still used
+        assert_ne!(first.to_string(), second.to_string());
+    }
 }
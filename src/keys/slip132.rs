@@ -0,0 +1,253 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! SLIP-132 extended keys
+//!
+//! [SLIP-132](https://github.com/satoshilabs/slips/blob/master/slip-0132.md) defines alternative
+//! version bytes for extended keys, used by some wallets to encode the type of script the keys
+//! are meant for: a `zpub`, for instance, is an `xpub` for `p2wpkh` scripts.
+//!
+//! Descriptors already describe the type of script, so BDK always works with the standard
+//! `xpub`/`tpub` encoding. This module converts SLIP-132 keys to it, and keeps track of the type
+//! of script implied by their prefix. Descriptors parsed from strings accept SLIP-132 keys too,
+//! as long as their prefix agrees with the type of the descriptor.
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::str::FromStr;
+//! # use bdk::bitcoin::util::bip32::ExtendedPubKey;
+//! use bdk::keys::slip132::Slip132Key;
+//! use bdk::ScriptType;
+//!
+//! let key = Slip132Key::<ExtendedPubKey>::from_str("vpub5SLqN2bLY4WeZH5meCqeSQGMxYpqPMFSXDgKoDgUxtyiGiThVH7HRNU5EjKUBCpzDZfTC2bNdXdBGJkUYXf87AzY5ABNrJebRYMa4JKtehx")?;
+//!
+//! assert_eq!(key.key.to_string(), "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq");
+//! assert_eq!(key.script_type, Some(ScriptType::P2wpkh));
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::str::FromStr;
+
+use bitcoin::util::base58;
+use bitcoin::util::bip32;
+
+use super::{DerivableKey, DescriptorKey, KeyError, ScriptContext};
+use crate::types::ScriptType;
+
+const XPUB: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+const XPRV: [u8; 4] = [0x04, 0x88, 0xAD, 0xE4];
+const TPUB: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
+const TPRV: [u8; 4] = [0x04, 0x35, 0x83, 0x94];
+
+// Version bytes, prefix, standard version bytes and implied script type of every known encoding
+#[rustfmt::skip]
+const VERSIONS: [([u8; 4], &str, [u8; 4], Option<ScriptType>); 20] = [
+    (XPUB,                     "xpub", XPUB, None),
+    ([0x04, 0x9D, 0x7C, 0xB2], "ypub", XPUB, Some(ScriptType::P2shP2wpkh)),
+    ([0x04, 0xB2, 0x47, 0x46], "zpub", XPUB, Some(ScriptType::P2wpkh)),
+    ([0x02, 0x95, 0xB4, 0x3F], "Ypub", XPUB, Some(ScriptType::P2shP2wsh)),
+    ([0x02, 0xAA, 0x7E, 0xD3], "Zpub", XPUB, Some(ScriptType::P2wsh)),
+    (XPRV,                     "xprv", XPRV, None),
+    ([0x04, 0x9D, 0x78, 0x78], "yprv", XPRV, Some(ScriptType::P2shP2wpkh)),
+    ([0x04, 0xB2, 0x43, 0x0C], "zprv", XPRV, Some(ScriptType::P2wpkh)),
+    ([0x02, 0x95, 0xB0, 0x05], "Yprv", XPRV, Some(ScriptType::P2shP2wsh)),
+    ([0x02, 0xAA, 0x7A, 0x99], "Zprv", XPRV, Some(ScriptType::P2wsh)),
+    (TPUB,                     "tpub", TPUB, None),
+    ([0x04, 0x4A, 0x52, 0x62], "upub", TPUB, Some(ScriptType::P2shP2wpkh)),
+    ([0x04, 0x5F, 0x1C, 0xF6], "vpub", TPUB, Some(ScriptType::P2wpkh)),
+    ([0x02, 0x42, 0x89, 0xEF], "Upub", TPUB, Some(ScriptType::P2shP2wsh)),
+    ([0x02, 0x57, 0x54, 0x83], "Vpub", TPUB, Some(ScriptType::P2wsh)),
+    (TPRV,                     "tprv", TPRV, None),
+    ([0x04, 0x4A, 0x4E, 0x28], "uprv", TPRV, Some(ScriptType::P2shP2wpkh)),
+    ([0x04, 0x5F, 0x18, 0xBC], "vprv", TPRV, Some(ScriptType::P2wpkh)),
+    ([0x02, 0x42, 0x85, 0xB5], "Uprv", TPRV, Some(ScriptType::P2shP2wsh)),
+    ([0x02, 0x57, 0x50, 0x48], "Vprv", TPRV, Some(ScriptType::P2wsh)),
+];
+
+/// An extended key parsed from its SLIP-132 encoding
+///
+/// `K` is either [`ExtendedPubKey`](bip32::ExtendedPubKey) or
+/// [`ExtendedPrivKey`](bip32::ExtendedPrivKey). Keys using the standard encoding are accepted as
+/// well, and don't imply any script type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Slip132Key<K> {
+    /// The extended key
+    pub key: K,
+    /// Type of script implied by the prefix of the key
+    pub script_type: Option<ScriptType>,
+}
+
+impl FromStr for Slip132Key<bip32::ExtendedPubKey> {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (standard, script_type) = to_standard_encoding(s)?;
+        Ok(Slip132Key {
+            key: bip32::ExtendedPubKey::from_str(&standard)?,
+            script_type,
+        })
+    }
+}
+
+impl FromStr for Slip132Key<bip32::ExtendedPrivKey> {
+    type Err = KeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (standard, script_type) = to_standard_encoding(s)?;
+        Ok(Slip132Key {
+            key: bip32::ExtendedPrivKey::from_str(&standard)?,
+            script_type,
+        })
+    }
+}
+
+impl<Ctx: ScriptContext, K: DerivableKey<Ctx>> DerivableKey<Ctx> for Slip132Key<K> {
+    fn add_metadata(
+        self,
+        origin: Option<bip32::KeySource>,
+        derivation_path: bip32::DerivationPath,
+    ) -> Result<DescriptorKey<Ctx>, KeyError> {
+        self.key.add_metadata(origin, derivation_path)
+    }
+}
+
+/// Convert an extended key to the standard `xpub`/`xprv`/`tpub`/`tprv` encoding
+///
+/// Returns the re-encoded key and the type of script implied by its original prefix, if any.
+pub fn to_standard_encoding(key: &str) -> Result<(String, Option<ScriptType>), KeyError> {
+    let mut data = base58::from_check(key)?;
+    if data.len() != 78 {
+        return Err(base58::Error::InvalidLength(data.len()).into());
+    }
+
+    let (standard, script_type) = VERSIONS
+        .iter()
+        .find(|(version, _, _, _)| version[..] == data[..4])
+        .map(|(_, _, standard, script_type)| (standard, *script_type))
+        .ok_or_else(|| base58::Error::InvalidVersion(data[..4].to_vec()))?;
+    data[..4].copy_from_slice(standard);
+
+    Ok((base58::check_encode_slice(&data), script_type))
+}
+
+// Replace every SLIP-132 key in a descriptor with its standard encoding, returning the new
+// descriptor and the types of script implied by the keys that have been replaced
+pub(crate) fn replace_slip132_keys(
+    descriptor: &str,
+) -> Result<(String, Vec<ScriptType>), KeyError> {
+    let mut replaced = String::with_capacity(descriptor.len());
+    let mut script_types = Vec::new();
+
+    // keys are made of alphanumeric characters, so the descriptor is split at any other character
+    let mut start = 0;
+    let separators = descriptor
+        .char_indices()
+        .filter(|(_, c)| !c.is_ascii_alphanumeric())
+        .map(|(pos, c)| (pos, Some(c)))
+        .chain(std::iter::once((descriptor.len(), None)));
+    for (end, separator) in separators {
+        let token = &descriptor[start..end];
+        let is_slip132 = VERSIONS
+            .iter()
+            .any(|(_, prefix, _, script_type)| script_type.is_some() && token.starts_with(prefix));
+
+        if is_slip132 {
+            let (standard, script_type) = to_standard_encoding(token)?;
+            replaced.push_str(&standard);
+            script_types.extend(script_type);
+        } else {
+            replaced.push_str(token);
+        }
+
+        if let Some(c) = separator {
+            replaced.push(c);
+            start = end + c.len_utf8();
+        }
+    }
+
+    Ok((replaced, script_types))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TPUB_KEY: &str = "tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq";
+    const XPUB_KEY: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    #[test]
+    fn test_to_standard_encoding() {
+        let vectors = [
+            ("upub57Wa4MvRPNyAhyteor42EKArnagPSjFwc7A71pnbatbqDceUEcwioJowDXMtBJB4ovYeSYzpAsGdP28upqF7JwJwCpUxGPq79pHvfkdSwjJ", TPUB_KEY, Some(ScriptType::P2shP2wpkh)),
+            ("vpub5SLqN2bLY4WeZH5meCqeSQGMxYpqPMFSXDgKoDgUxtyiGiThVH7HRNU5EjKUBCpzDZfTC2bNdXdBGJkUYXf87AzY5ABNrJebRYMa4JKtehx", TPUB_KEY, Some(ScriptType::P2wpkh)),
+            ("Upub5JQfBberxLXY8Z42kWX14PWfWNief5wXvNomw648xfSEqoDP12KztRfs2EKMjjPy3PcdK8zw45f8WBkg84Q4TRRc3HbMfoJ73YZoFghJThC", TPUB_KEY, Some(ScriptType::P2shP2wsh)),
+            ("Vpub5dEvVGKn7251yrF9asJdGUcAgLs6bhw2qVKziUx2Lfp7tu2cFgVZWVL13SGwje3tT2jS4cbVWk1gPUNEqkp5Ff7CudHnFi7bKGdSeDJGbvS", TPUB_KEY, Some(ScriptType::P2wsh)),
+            ("ypub6QqdH2c5z7967BioGSfAWFHM1EHzHPBZK7wrND3ZpEWFtzmCqvsD1bgpaE6pSAPkiSKhkuWPCJV6mZTSNMd2tK8xYTcJ48585pZecmSUzWp", XPUB_KEY, Some(ScriptType::P2shP2wpkh)),
+            ("zpub6jftahH18ngZxUuv6oSniLNrBCSSE1B4EEU59bwTCEt8x6aS6b2mdfLxbS4QS53g85SWWP6wexqeer516433gYpZQoJie2tcMYdJ1SYYYAL", XPUB_KEY, Some(ScriptType::P2wpkh)),
+            ("Ypub6bjiQGLXZ4hTXktBD789LKd9j2LFVjs9dPbXHUK7C1LfXBL7cLFV6iYkNw4HzbcewuPgdVWW5Wsbtj5Cfamz2oFdNvihTXY7yYqXCoB1LjT", XPUB_KEY, Some(ScriptType::P2shP2wsh)),
+            ("Zpub6vZyhw1ShkEwP45J3TumYQietzUhSMreYW7k4sCza1iYaH9LrzR3inCtQ91szWGaMYWVNy74YBE9n1gmPHBzq2wEFGR83SMcFGuAbGkfiwg", XPUB_KEY, Some(ScriptType::P2wsh)),
+            (TPUB_KEY, TPUB_KEY, None),
+            (XPUB_KEY, XPUB_KEY, None),
+        ];
+
+        for (key, standard, script_type) in &vectors {
+            assert_eq!(
+                to_standard_encoding(key).unwrap(),
+                (standard.to_string(), *script_type)
+            );
+        }
+
+        assert!(matches!(
+            to_standard_encoding("zpub6jftahH18ngZxUuv6oSniLNrBCSSE1B4EEU59bwTCEt8x6aS6b2mdfLxbS4QS53g85SWWP6wexqeer516433gYpZQoJie2tcMYdJ1SYYYAM"),
+            Err(KeyError::Base58(_))
+        ));
+    }
+
+    #[test]
+    fn test_slip132_private_key() {
+        let key = Slip132Key::<bip32::ExtendedPrivKey>::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS").unwrap();
+        assert_eq!(key.script_type, None);
+
+        // a public key can't be parsed as a private one
+        assert!(Slip132Key::<bip32::ExtendedPrivKey>::from_str(TPUB_KEY).is_err());
+    }
+
+    #[test]
+    fn test_replace_slip132_keys() {
+        let (descriptor, script_types) = replace_slip132_keys("wsh(multi(2,[0f056943/48'/1'/0'/2']Vpub5dEvVGKn7251yrF9asJdGUcAgLs6bhw2qVKziUx2Lfp7tu2cFgVZWVL13SGwje3tT2jS4cbVWk1gPUNEqkp5Ff7CudHnFi7bKGdSeDJGbvS/0/*,tpubD6NzVbkrYhZ4WWGhiCNyq7EUNpoFdcVrxB4SqRUv55oSXHPAt47E5Et1xYM8S5ZqtwPuPZ5j2s1pFALLGg9uK5emMbKqdi8r2B2SRx9oQSk/0/*))").unwrap();
+        assert_eq!(descriptor, format!("wsh(multi(2,[0f056943/48'/1'/0'/2']{}/0/*,tpubD6NzVbkrYhZ4WWGhiCNyq7EUNpoFdcVrxB4SqRUv55oSXHPAt47E5Et1xYM8S5ZqtwPuPZ5j2s1pFALLGg9uK5emMbKqdi8r2B2SRx9oQSk/0/*))", TPUB_KEY));
+        assert_eq!(script_types, vec![ScriptType::P2wsh]);
+
+        let (descriptor, script_types) = replace_slip132_keys(
+            "pkh(02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737)",
+        )
+        .unwrap();
+        assert_eq!(
+            descriptor,
+            "pkh(02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737)"
+        );
+        assert!(script_types.is_empty());
+    }
+}
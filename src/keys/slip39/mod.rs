@@ -0,0 +1,960 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! SLIP-0039
+//!
+//! This module implements [SLIP-0039](https://github.com/satoshilabs/slips/blob/master/slip-0039.md)
+//! Shamir backups: a [`MasterSecret`] is split into groups of mnemonic shares, and can later be
+//! recovered by combining enough shares from enough groups. Once recovered, the master secret is
+//! used like a BIP39 seed, so it can be directly passed to the descriptor templates.
+//!
+//! ## Example
+//!
+//! ```
+//! use bdk::keys::slip39::{MasterSecret, MasterSecretLength};
+//! use bdk::keys::{GeneratableKey, GeneratedKey};
+//! use bdk::miniscript::Segwitv0;
+//! use bdk::template::BIP84;
+//! use bdk::KeychainKind;
+//!
+//! let secret: GeneratedKey<_, Segwitv0> = MasterSecret::generate(MasterSecretLength::Bits128)?;
+//!
+//! // two groups are required: the first one is split "2-of-3", the second one "1-of-1"
+//! let groups = secret.split(2, &[(2, 3), (1, 1)], "passphrase", 0)?;
+//!
+//! let recovered =
+//!     MasterSecret::combine(&[&groups[0][0], &groups[0][2], &groups[1][0]], "passphrase")?;
+//! assert_eq!(recovered, *secret);
+//!
+//! let template = BIP84(recovered, KeychainKind::External);
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoin::util::bip32;
+use bitcoin::Network;
+
+use miniscript::ScriptContext;
+
+use rand::{thread_rng, RngCore};
+
+use zeroize::{Zeroize, Zeroizing};
+
+use super::{any_network, DerivableKey, DescriptorKey, GeneratableKey, GeneratedKey, KeyError};
+
+mod wordlist;
+use self::wordlist::WORDLIST;
+
+const RADIX_BITS: usize = 10;
+const CHECKSUM_WORDS: usize = 3;
+// two words for the identifier and the iteration exponent, two for the group and member params
+const METADATA_WORDS: usize = 4 + CHECKSUM_WORDS;
+const MIN_SECRET_LEN: usize = 16;
+const MIN_MNEMONIC_WORDS: usize =
+    METADATA_WORDS + (MIN_SECRET_LEN * 8 + RADIX_BITS - 1) / RADIX_BITS;
+const MAX_SHARE_COUNT: u8 = 16;
+const MAX_ITERATION_EXPONENT: u8 = 15;
+
+const DIGEST_LEN: usize = 4;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+
+const BASE_ITERATION_COUNT: u32 = 10_000;
+const ROUND_COUNT: u8 = 4;
+
+const CUSTOMIZATION_STRING: &[u8] = b"shamir";
+const CUSTOMIZATION_STRING_EXTENDABLE: &[u8] = b"shamir_extendable";
+
+/// Errors that can happen while splitting or combining SLIP-0039 shares
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Slip39Error {
+    /// A word is not part of the SLIP-0039 wordlist
+    UnknownWord(String),
+    /// The mnemonic doesn't have a valid number of words
+    InvalidLength,
+    /// The checksum of the mnemonic doesn't match
+    InvalidChecksum,
+    /// The padding bits of the share value are not zero
+    InvalidPadding,
+    /// The shares don't belong to the same set, or contain conflicting values
+    InconsistentShares,
+    /// Not enough groups or not enough shares in a group to reach the thresholds
+    NotEnoughShares,
+    /// The digest of the recovered secret doesn't match, meaning that at least one share is
+    /// corrupted
+    InvalidDigest,
+    /// The parameters used to split the secret are not valid
+    InvalidParameters(String),
+}
+
+impl fmt::Display for Slip39Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Slip39Error {}
+
+/// Length of a generated [`MasterSecret`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MasterSecretLength {
+    /// 128 bits, which produces shares of 20 words
+    Bits128,
+    /// 256 bits, which produces shares of 33 words
+    Bits256,
+}
+
+impl Default for MasterSecretLength {
+    fn default() -> Self {
+        MasterSecretLength::Bits128
+    }
+}
+
+/// SLIP-0039 master secret
+///
+/// This is the secret that gets split into shares and later recovered. Like a BIP39 seed, it is
+/// used to derive the BIP32 master key of the wallet. Its memory is zeroed out when it's dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MasterSecret(Vec<u8>);
+
+impl Drop for MasterSecret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl MasterSecret {
+    /// Wrap an existing master secret
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        MasterSecret(bytes.to_vec())
+    }
+
+    /// Return the raw bytes of the master secret
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Recover the master secret from a set of mnemonic shares
+    ///
+    /// The shares can be given in any order: they are grouped together and every group that
+    /// has enough shares to reach its member threshold is recovered. The `passphrase` must be the
+    /// same one that was used when splitting the secret, but note that using a different one
+    /// doesn't produce an error: it simply recovers a different, but still valid, master secret.
+    pub fn combine<S: AsRef<str>>(mnemonics: &[S], passphrase: &str) -> Result<Self, Slip39Error> {
+        let shares = mnemonics
+            .iter()
+            .map(|mnemonic| Share::from_mnemonic(mnemonic.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let first = shares.first().ok_or(Slip39Error::NotEnoughShares)?;
+
+        if shares.iter().any(|share| !share.is_same_set(first)) {
+            return Err(Slip39Error::InconsistentShares);
+        }
+
+        let mut groups: BTreeMap<u8, Vec<&Share>> = BTreeMap::new();
+        for share in &shares {
+            let members = groups.entry(share.group_index).or_default();
+            if members
+                .iter()
+                .any(|other| other.member_threshold != share.member_threshold)
+            {
+                return Err(Slip39Error::InconsistentShares);
+            }
+
+            let existing = members
+                .iter()
+                .find(|other| other.member_index == share.member_index)
+                .map(|other| other.value == share.value);
+            match existing {
+                // the same share given twice
+                Some(true) => {}
+                Some(false) => return Err(Slip39Error::InconsistentShares),
+                None => members.push(share),
+            }
+        }
+
+        let mut group_shares = Vec::new();
+        for (group_index, members) in groups {
+            let member_threshold = members[0].member_threshold;
+            if members.len() < member_threshold as usize {
+                continue;
+            }
+
+            let member_shares = members
+                .iter()
+                .take(member_threshold as usize)
+                .map(|share| (share.member_index, Zeroizing::new(share.value.clone())))
+                .collect::<Vec<_>>();
+            group_shares.push((
+                group_index,
+                recover_secret(member_threshold, &member_shares)?,
+            ));
+        }
+
+        if group_shares.len() < first.group_threshold as usize {
+            return Err(Slip39Error::NotEnoughShares);
+        }
+        group_shares.truncate(first.group_threshold as usize);
+
+        let encrypted_secret = recover_secret(first.group_threshold, &group_shares)?;
+        let secret = feistel(
+            &encrypted_secret,
+            passphrase.as_bytes(),
+            first.iteration_exponent,
+            first.identifier,
+            first.extendable,
+            false,
+        );
+        Ok(MasterSecret::from_bytes(&secret))
+    }
+
+    /// Split the master secret into groups of mnemonic shares
+    ///
+    /// `groups` contains the member threshold and the member count of each group, and
+    /// `group_threshold` is the number of groups required to recover the secret. The
+    /// `iteration_exponent` increases exponentially the number of PBKDF2 iterations used to
+    /// encrypt the secret with the `passphrase`, and can be at most 15.
+    ///
+    /// Returns the mnemonics of the shares of every group.
+    pub fn split(
+        &self,
+        group_threshold: u8,
+        groups: &[(u8, u8)],
+        passphrase: &str,
+        iteration_exponent: u8,
+    ) -> Result<Vec<Vec<String>>, Slip39Error> {
+        self.split_with_rng(
+            group_threshold,
+            groups,
+            passphrase,
+            iteration_exponent,
+            &mut thread_rng(),
+        )
+    }
+
+    fn split_with_rng<R: RngCore>(
+        &self,
+        group_threshold: u8,
+        groups: &[(u8, u8)],
+        passphrase: &str,
+        iteration_exponent: u8,
+        rng: &mut R,
+    ) -> Result<Vec<Vec<String>>, Slip39Error> {
+        let invalid = |msg: &str| Err(Slip39Error::InvalidParameters(msg.to_string()));
+
+        if self.0.len() < MIN_SECRET_LEN || self.0.len() % 2 != 0 {
+            return invalid("the master secret must be at least 16 bytes long, and even");
+        }
+        if group_threshold == 0 || group_threshold as usize > groups.len() {
+            return invalid("the group threshold must be between 1 and the number of groups");
+        }
+        if groups.len() > MAX_SHARE_COUNT as usize {
+            return invalid("there can't be more than 16 groups");
+        }
+        for &(member_threshold, member_count) in groups {
+            if member_threshold == 0
+                || member_threshold > member_count
+                || member_count > MAX_SHARE_COUNT
+            {
+                return invalid("the member threshold must be between 1 and the member count, which can't be more than 16");
+            }
+            if member_threshold == 1 && member_count > 1 {
+                return invalid("multiple member shares with a threshold of 1 are not allowed, use a 1-of-1 group instead");
+            }
+        }
+        if iteration_exponent > MAX_ITERATION_EXPONENT {
+            return invalid("the iteration exponent can't be more than 15");
+        }
+        if passphrase.bytes().any(|c| !(32..=126).contains(&c)) {
+            return invalid("the passphrase can only contain printable ASCII characters");
+        }
+
+        let identifier = (rng.next_u32() & 0x7FFF) as u16;
+        let encrypted_secret = feistel(
+            &self.0,
+            passphrase.as_bytes(),
+            iteration_exponent,
+            identifier,
+            false,
+            true,
+        );
+
+        let group_secrets =
+            split_secret(group_threshold, groups.len() as u8, &encrypted_secret, rng);
+
+        let mut mnemonics = Vec::with_capacity(groups.len());
+        for (&(member_threshold, member_count), (group_index, group_secret)) in
+            groups.iter().zip(group_secrets)
+        {
+            let members = split_secret(member_threshold, member_count, &group_secret, rng)
+                .into_iter()
+                .map(|(member_index, value)| {
+                    Share {
+                        identifier,
+                        extendable: false,
+                        iteration_exponent,
+                        group_index,
+                        group_threshold,
+                        group_count: groups.len() as u8,
+                        member_index,
+                        member_threshold,
+                        value: value.to_vec(),
+                    }
+                    .to_mnemonic()
+                })
+                .collect();
+            mnemonics.push(members);
+        }
+
+        Ok(mnemonics)
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-slip39")))]
+impl<Ctx: ScriptContext> DerivableKey<Ctx> for MasterSecret {
+    fn add_metadata(
+        self,
+        source: Option<bip32::KeySource>,
+        derivation_path: bip32::DerivationPath,
+    ) -> Result<DescriptorKey<Ctx>, KeyError> {
+        let xprv = bip32::ExtendedPrivKey::new_master(Network::Bitcoin, &self.0)?;
+        let descriptor_key = xprv.add_metadata(source, derivation_path)?;
+
+        // like bip39, slip39 doesn't encode the network, so the key is valid everywhere
+        Ok(descriptor_key.override_valid_networks(any_network()))
+    }
+}
+
+#[cfg_attr(docsrs, doc(cfg(feature = "keys-slip39")))]
+impl<Ctx: ScriptContext> GeneratableKey<Ctx> for MasterSecret {
+    type Entropy = [u8; 32];
+
+    type Options = MasterSecretLength;
+    type Error = Slip39Error;
+
+    fn generate_with_entropy(
+        length: Self::Options,
+        entropy: Self::Entropy,
+    ) -> Result<GeneratedKey<Self, Ctx>, Self::Error> {
+        let len = match length {
+            MasterSecretLength::Bits128 => 16,
+            MasterSecretLength::Bits256 => 32,
+        };
+
+        Ok(GeneratedKey::new(
+            MasterSecret::from_bytes(&entropy[..len]),
+            any_network(),
+        ))
+    }
+}
+
+// The x coordinate and the value of a share of a secret, zeroed out when dropped
+type SecretShare = (u8, Zeroizing<Vec<u8>>);
+
+// A single share, as encoded in a mnemonic
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Vec<u8>,
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+impl Share {
+    fn from_mnemonic(mnemonic: &str) -> Result<Self, Slip39Error> {
+        let words = mnemonic
+            .split_whitespace()
+            .map(word_index)
+            .collect::<Result<Vec<_>, _>>()?;
+        if words.len() < MIN_MNEMONIC_WORDS {
+            return Err(Slip39Error::InvalidLength);
+        }
+        let padding_bits = (RADIX_BITS * (words.len() - METADATA_WORDS)) % 16;
+        if padding_bits > 8 {
+            return Err(Slip39Error::InvalidLength);
+        }
+
+        let id_exp = (u32::from(words[0]) << 10) | u32::from(words[1]);
+        let extendable = id_exp & 0x10 != 0;
+        if !rs1024_verify_checksum(customization_string(extendable), &words) {
+            return Err(Slip39Error::InvalidChecksum);
+        }
+
+        let params = (u32::from(words[2]) << 10) | u32::from(words[3]);
+        let share = Share {
+            identifier: (id_exp >> 5) as u16,
+            extendable,
+            iteration_exponent: (id_exp & 0x0F) as u8,
+            group_index: (params >> 16) as u8,
+            group_threshold: ((params >> 12) & 0x0F) as u8 + 1,
+            group_count: ((params >> 8) & 0x0F) as u8 + 1,
+            member_index: ((params >> 4) & 0x0F) as u8,
+            member_threshold: (params & 0x0F) as u8 + 1,
+            value: words_to_bytes(&words[4..words.len() - CHECKSUM_WORDS], padding_bits)?,
+        };
+        if share.group_threshold > share.group_count {
+            return Err(Slip39Error::InconsistentShares);
+        }
+
+        Ok(share)
+    }
+
+    fn to_mnemonic(&self) -> String {
+        let id_exp = (u32::from(self.identifier) << 5)
+            | (u32::from(self.extendable) << 4)
+            | u32::from(self.iteration_exponent);
+        let params = (u32::from(self.group_index) << 16)
+            | (u32::from(self.group_threshold - 1) << 12)
+            | (u32::from(self.group_count - 1) << 8)
+            | (u32::from(self.member_index) << 4)
+            | u32::from(self.member_threshold - 1);
+
+        let mut words = vec![
+            (id_exp >> 10) as u16,
+            (id_exp & 0x3FF) as u16,
+            (params >> 10) as u16,
+            (params & 0x3FF) as u16,
+        ];
+        words.extend(bytes_to_words(&self.value));
+        let checksum = rs1024_create_checksum(customization_string(self.extendable), &words);
+        words.extend(&checksum);
+
+        words
+            .into_iter()
+            .map(|index| WORDLIST[index as usize])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // Whether the two shares belong to the same set of shares
+    fn is_same_set(&self, other: &Share) -> bool {
+        self.identifier == other.identifier
+            && self.extendable == other.extendable
+            && self.iteration_exponent == other.iteration_exponent
+            && self.group_threshold == other.group_threshold
+            && self.group_count == other.group_count
+            && self.value.len() == other.value.len()
+    }
+}
+
+fn word_index(word: &str) -> Result<u16, Slip39Error> {
+    WORDLIST
+        .binary_search(&word.to_lowercase().as_str())
+        .map(|index| index as u16)
+        .map_err(|_| Slip39Error::UnknownWord(word.to_string()))
+}
+
+// Convert the words of a share value to bytes, checking that the leading padding bits are zero
+fn words_to_bytes(words: &[u16], padding_bits: usize) -> Result<Vec<u8>, Slip39Error> {
+    let mut bytes = Vec::with_capacity((words.len() * RADIX_BITS - padding_bits) / 8);
+    let mut acc = 0u32;
+    let mut acc_bits = 0;
+    let mut padding_bits = padding_bits;
+
+    for word in words {
+        acc = (acc << RADIX_BITS) | u32::from(*word);
+        acc_bits += RADIX_BITS;
+
+        if padding_bits > 0 {
+            if acc >> (acc_bits - padding_bits) != 0 {
+                return Err(Slip39Error::InvalidPadding);
+            }
+            acc_bits -= padding_bits;
+            acc &= (1 << acc_bits) - 1;
+            padding_bits = 0;
+        }
+
+        while acc_bits >= 8 {
+            acc_bits -= 8;
+            bytes.push((acc >> acc_bits) as u8);
+            acc &= (1 << acc_bits) - 1;
+        }
+    }
+
+    Ok(bytes)
+}
+
+// Convert bytes to words, adding zero bits at the beginning to fill the first word
+fn bytes_to_words(bytes: &[u8]) -> Vec<u16> {
+    let word_count = (bytes.len() * 8 + RADIX_BITS - 1) / RADIX_BITS;
+    let mut words = Vec::with_capacity(word_count);
+    let mut acc = 0u32;
+    let mut acc_bits = word_count * RADIX_BITS - bytes.len() * 8;
+
+    for byte in bytes {
+        acc = (acc << 8) | u32::from(*byte);
+        acc_bits += 8;
+
+        while acc_bits >= RADIX_BITS {
+            acc_bits -= RADIX_BITS;
+            words.push((acc >> acc_bits) as u16);
+            acc &= (1 << acc_bits) - 1;
+        }
+    }
+
+    words
+}
+
+fn customization_string(extendable: bool) -> &'static [u8] {
+    if extendable {
+        CUSTOMIZATION_STRING_EXTENDABLE
+    } else {
+        CUSTOMIZATION_STRING
+    }
+}
+
+fn rs1024_polymod(values: impl Iterator<Item = u16>) -> u32 {
+    const GEN: [u32; 10] = [
+        0x00E0_E040,
+        0x01C1_C080,
+        0x0383_8100,
+        0x0707_0200,
+        0x0E0E_0009,
+        0x1C0C_2412,
+        0x3808_6C24,
+        0x3090_FC48,
+        0x21B1_F890,
+        0x03F3_F120,
+    ];
+
+    let mut chk = 1u32;
+    for value in values {
+        let b = chk >> 20;
+        chk = ((chk & 0xF_FFFF) << 10) ^ u32::from(value);
+        for (i, gen) in GEN.iter().enumerate() {
+            if (b >> i) & 1 == 1 {
+                chk ^= gen;
+            }
+        }
+    }
+
+    chk
+}
+
+fn rs1024_verify_checksum(customization: &[u8], words: &[u16]) -> bool {
+    let values = customization
+        .iter()
+        .map(|c| u16::from(*c))
+        .chain(words.iter().cloned());
+    rs1024_polymod(values) == 1
+}
+
+fn rs1024_create_checksum(customization: &[u8], words: &[u16]) -> [u16; CHECKSUM_WORDS] {
+    let values = customization
+        .iter()
+        .map(|c| u16::from(*c))
+        .chain(words.iter().cloned())
+        .chain(std::iter::repeat(0).take(CHECKSUM_WORDS));
+    let polymod = rs1024_polymod(values) ^ 1;
+
+    [
+        ((polymod >> 20) & 0x3FF) as u16,
+        ((polymod >> 10) & 0x3FF) as u16,
+        (polymod & 0x3FF) as u16,
+    ]
+}
+
+// Exponentiation and logarithm tables of GF(256), with the Rijndael polynomial and 3 as the
+// generator
+fn gf256_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+
+    let mut poly = 1u16;
+    for (i, item) in exp.iter_mut().enumerate() {
+        *item = poly as u8;
+        log[poly as usize] = i as u8;
+
+        poly = (poly << 1) ^ poly;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11B;
+        }
+    }
+
+    (exp, log)
+}
+
+// Evaluate at `x` the polynomial that goes through the points in `shares`, for every byte of
+// the values. The x coordinates of the shares must be distinct
+fn interpolate(shares: &[SecretShare], x: u8) -> Zeroizing<Vec<u8>> {
+    if let Some((_, value)) = shares.iter().find(|(index, _)| *index == x) {
+        return value.clone();
+    }
+
+    let (exp, log) = gf256_tables();
+    let log_prod: i64 = shares
+        .iter()
+        .map(|(index, _)| i64::from(log[(index ^ x) as usize]))
+        .sum();
+
+    let mut result = Zeroizing::new(vec![0u8; shares[0].1.len()]);
+    for (index, value) in shares {
+        let log_basis_eval = (log_prod
+            - i64::from(log[(index ^ x) as usize])
+            - shares
+                .iter()
+                .map(|(other, _)| i64::from(log[(index ^ other) as usize]))
+                .sum::<i64>())
+        .rem_euclid(255);
+
+        for (byte, result) in value.iter().zip(result.iter_mut()) {
+            if *byte != 0 {
+                *result ^= exp[((i64::from(log[*byte as usize]) + log_basis_eval) % 255) as usize];
+            }
+        }
+    }
+
+    result
+}
+
+fn create_digest(random_data: &[u8], secret: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut engine = HmacEngine::<sha256::Hash>::new(random_data);
+    engine.input(secret);
+
+    let mut digest = [0u8; DIGEST_LEN];
+    digest.copy_from_slice(&Hmac::<sha256::Hash>::from_engine(engine).into_inner()[..DIGEST_LEN]);
+    digest
+}
+
+fn split_secret<R: RngCore>(
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+    rng: &mut R,
+) -> Vec<SecretShare> {
+    if threshold == 1 {
+        return (0..count)
+            .map(|index| (index, Zeroizing::new(secret.to_vec())))
+            .collect();
+    }
+
+    let random_share_count = threshold - 2;
+    let mut shares = (0..random_share_count)
+        .map(|index| {
+            let mut value = Zeroizing::new(vec![0u8; secret.len()]);
+            rng.fill_bytes(&mut value);
+            (index, value)
+        })
+        .collect::<Vec<_>>();
+
+    let mut random_part = vec![0u8; secret.len() - DIGEST_LEN];
+    rng.fill_bytes(&mut random_part);
+    let mut digest_share = Zeroizing::new(create_digest(&random_part, secret).to_vec());
+    digest_share.extend(random_part);
+
+    let mut base_shares = shares.clone();
+    base_shares.push((DIGEST_INDEX, digest_share));
+    base_shares.push((SECRET_INDEX, Zeroizing::new(secret.to_vec())));
+
+    for index in random_share_count..count {
+        shares.push((index, interpolate(&base_shares, index)));
+    }
+
+    shares
+}
+
+fn recover_secret(
+    threshold: u8,
+    shares: &[SecretShare],
+) -> Result<Zeroizing<Vec<u8>>, Slip39Error> {
+    if threshold == 1 {
+        return Ok(shares[0].1.clone());
+    }
+
+    let secret = interpolate(shares, SECRET_INDEX);
+    let digest_share = interpolate(shares, DIGEST_INDEX);
+    let (digest, random_part) = digest_share.split_at(DIGEST_LEN);
+
+    if digest != create_digest(random_part, &secret) {
+        return Err(Slip39Error::InvalidDigest);
+    }
+
+    Ok(secret)
+}
+
+fn pbkdf2_hmac_sha256(
+    password: &[u8],
+    salt: &[u8],
+    iterations: u32,
+    len: usize,
+) -> Zeroizing<Vec<u8>> {
+    let mut output = Zeroizing::new(Vec::with_capacity(len));
+
+    let mut block = 1u32;
+    while output.len() < len {
+        let mut engine = HmacEngine::<sha256::Hash>::new(password);
+        engine.input(salt);
+        engine.input(&block.to_be_bytes());
+        let mut u = Hmac::<sha256::Hash>::from_engine(engine).into_inner();
+
+        let mut t = u;
+        for _ in 1..iterations {
+            let mut engine = HmacEngine::<sha256::Hash>::new(password);
+            engine.input(&u);
+            u = Hmac::<sha256::Hash>::from_engine(engine).into_inner();
+
+            for (t, u) in t.iter_mut().zip(u.iter()) {
+                *t ^= u;
+            }
+        }
+
+        let missing = len - output.len();
+        output.extend(&t[..missing.min(t.len())]);
+        t.zeroize();
+        u.zeroize();
+        block += 1;
+    }
+
+    output
+}
+
+// Encrypt or decrypt the master secret with the four rounds Feistel network defined by SLIP-0039
+fn feistel(
+    secret: &[u8],
+    passphrase: &[u8],
+    iteration_exponent: u8,
+    identifier: u16,
+    extendable: bool,
+    encrypt: bool,
+) -> Zeroizing<Vec<u8>> {
+    let salt = if extendable {
+        vec![]
+    } else {
+        let mut salt = CUSTOMIZATION_STRING.to_vec();
+        salt.extend(&identifier.to_be_bytes());
+        salt
+    };
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / u32::from(ROUND_COUNT);
+
+    let rounds: Vec<u8> = if encrypt {
+        (0..ROUND_COUNT).collect()
+    } else {
+        (0..ROUND_COUNT).rev().collect()
+    };
+
+    let (left, right) = secret.split_at(secret.len() / 2);
+    let (mut left, mut right) = (
+        Zeroizing::new(left.to_vec()),
+        Zeroizing::new(right.to_vec()),
+    );
+    for round in rounds {
+        let mut password = Zeroizing::new(vec![round]);
+        password.extend(passphrase);
+        let mut round_salt = Zeroizing::new(salt.clone());
+        round_salt.extend(right.iter());
+
+        let f = pbkdf2_hmac_sha256(&password, &round_salt, iterations, right.len());
+        let new_right = Zeroizing::new(
+            left.iter()
+                .zip(f.iter())
+                .map(|(l, f)| l ^ f)
+                .collect::<Vec<_>>(),
+        );
+        left = std::mem::replace(&mut right, new_right);
+    }
+
+    // allocate the result at once, so that growing it doesn't leave copies of the secret around
+    let mut result = Zeroizing::new(Vec::with_capacity(secret.len()));
+    result.extend_from_slice(&right);
+    result.extend_from_slice(&left);
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::ToHex;
+    use bitcoin::util::bip32;
+
+    use super::*;
+    use crate::keys::GeneratableDefaultOptions;
+
+    #[test]
+    fn test_slip39_combine_vectors() {
+        let secret = MasterSecret::combine(
+            &["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"],
+            "TREZOR",
+        )
+        .unwrap();
+        assert_eq!(
+            secret.as_bytes().to_hex(),
+            "bb54aac4b89dc868ba37d9cc21b2cece"
+        );
+
+        let secret = MasterSecret::combine(
+            &[
+                "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+                "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+            ],
+            "TREZOR",
+        )
+        .unwrap();
+        assert_eq!(
+            secret.as_bytes().to_hex(),
+            "b43ceb7e57a0ea8766221624d01b0864"
+        );
+    }
+
+    #[test]
+    fn test_slip39_combine_errors() {
+        assert_eq!(
+            MasterSecret::combine(
+                &["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney"],
+                "TREZOR",
+            ),
+            Err(Slip39Error::InvalidChecksum)
+        );
+        assert_eq!(
+            MasterSecret::combine(
+                &["shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed"],
+                "TREZOR",
+            ),
+            Err(Slip39Error::NotEnoughShares)
+        );
+        assert_eq!(
+            MasterSecret::combine(&["duckling enlarge academic"], "TREZOR"),
+            Err(Slip39Error::InvalidLength)
+        );
+        assert_eq!(
+            MasterSecret::combine(&["duckling satoshi bitcoin"], "TREZOR"),
+            Err(Slip39Error::UnknownWord("bitcoin".to_string()))
+        );
+        assert_eq!(
+            MasterSecret::combine(
+                &[
+                    "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard",
+                    "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+                ],
+                "TREZOR",
+            ),
+            Err(Slip39Error::InconsistentShares)
+        );
+    }
+
+    #[test]
+    fn test_slip39_split_combine() {
+        let secret = MasterSecret::from_bytes(&[0x42; 32]);
+        let groups = secret
+            .split(2, &[(1, 1), (2, 3), (3, 5)], "TREZOR", 0)
+            .unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[1].len(), 3);
+        assert_eq!(groups[2].len(), 5);
+        assert!(groups.iter().flatten().all(|m| m.split(' ').count() == 33));
+
+        let recovered = MasterSecret::combine(
+            &[
+                &groups[2][4],
+                &groups[1][2],
+                &groups[2][0],
+                &groups[1][0],
+                &groups[2][1],
+            ],
+            "TREZOR",
+        )
+        .unwrap();
+        assert_eq!(recovered, secret);
+
+        let recovered =
+            MasterSecret::combine(&[&groups[0][0], &groups[1][1], &groups[1][2]], "TREZOR")
+                .unwrap();
+        assert_eq!(recovered, secret);
+
+        // a different passphrase recovers a different secret
+        let recovered =
+            MasterSecret::combine(&[&groups[0][0], &groups[1][1], &groups[1][2]], "").unwrap();
+        assert_ne!(recovered, secret);
+
+        // only one group is complete
+        assert_eq!(
+            MasterSecret::combine(&[&groups[0][0], &groups[1][1]], "TREZOR"),
+            Err(Slip39Error::NotEnoughShares)
+        );
+    }
+
+    #[test]
+    fn test_slip39_split_invalid_parameters() {
+        let secret = MasterSecret::from_bytes(&[0x42; 16]);
+        assert!(matches!(
+            secret.split(2, &[(1, 1)], "", 0),
+            Err(Slip39Error::InvalidParameters(_))
+        ));
+        assert!(matches!(
+            secret.split(1, &[(1, 2)], "", 0),
+            Err(Slip39Error::InvalidParameters(_))
+        ));
+        assert!(matches!(
+            secret.split(1, &[(3, 2)], "", 0),
+            Err(Slip39Error::InvalidParameters(_))
+        ));
+        assert!(matches!(
+            MasterSecret::from_bytes(&[0x42; 15]).split(1, &[(1, 1)], "", 0),
+            Err(Slip39Error::InvalidParameters(_))
+        ));
+    }
+
+    #[test]
+    fn test_slip39_derivable_key() {
+        let secret = MasterSecret::from_bytes(&[0x42; 16]);
+        let xprv = bip32::ExtendedPrivKey::new_master(Network::Bitcoin, &[0x42; 16]).unwrap();
+        let path = bip32::DerivationPath::from_str("m/84'/0'/0'/0").unwrap();
+
+        let (desc, keys, networks) = crate::descriptor!(wpkh((secret, path.clone()))).unwrap();
+        let (expected, _, _) = crate::descriptor!(wpkh((xprv, path))).unwrap();
+        assert_eq!(desc.to_string(), expected.to_string());
+        assert_eq!(keys.len(), 1);
+        assert_eq!(networks.len(), 3);
+    }
+
+    #[test]
+    fn test_slip39_generate() {
+        let generated: GeneratedKey<_, miniscript::Segwitv0> =
+            MasterSecret::generate_with_entropy_default(crate::keys::test::TEST_ENTROPY).unwrap();
+        assert_eq!(generated.valid_networks, any_network());
+        assert_eq!(generated.as_bytes(), &[0xAA; 16]);
+
+        let generated: GeneratedKey<_, miniscript::Segwitv0> = MasterSecret::generate_with_entropy(
+            MasterSecretLength::Bits256,
+            crate::keys::test::TEST_ENTROPY,
+        )
+        .unwrap();
+        assert_eq!(generated.as_bytes(), &[0xAA; 32]);
+    }
+}
@@ -157,6 +157,7 @@
 //! use base64::decode;
 //! use bdk::{Wallet, OfflineWallet};
 //! use bdk::database::MemoryDatabase;
+//! use bdk::signer::SignOptions;
 //!
 //! use bitcoin::consensus::deserialize;
 //!
@@ -171,7 +172,7 @@
 //!     let psbt = "...";
 //!     let psbt = deserialize(&base64::decode(psbt).unwrap())?;
 //!
-//!     let (signed_psbt, finalized) = wallet.sign(psbt, None)?;
+//!     let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default())?;
 //!
 //!     Ok(())
 //! }
@@ -190,9 +191,14 @@
 //! Below is a list of the available feature flags and the additional functionality they provide.
 //!
 //! * `all-keys`: all features for working with bitcoin keys
-//! * `async-interface`: async functions in bdk traits
+//! * `async-interface`: async functions in bdk traits and a `Stream` of sync updates
 //! * `cli-utils`: utilities for creating a command line interface wallet
+//! * `dangerous-ops`: operations that can leak the wallet's private keys, like exporting the key of a single address
+//! * `external-signer`: [`external_signer`](crate::wallet::external_signer) to sign with external programs like HWI, following Bitcoin Core's interface
+//! * `integration-test`: [`integration_test`](crate::integration_test) tools to test `Blockchain` backends against regtest nodes
 //! * `keys-bip39`: [BIP-39](https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki) mnemonic codes for generating deterministic keys
+//! * `keys-slip39`: [SLIP-39](https://github.com/satoshilabs/slips/blob/master/slip-0039.md) Shamir backups to split a master secret into mnemonic shares
+//! * `verify`: [`verify`](crate::wallet::verify) transactions downloaded during sync with `libbitcoinconsensus`
 //!
 //! ## Internal features
 //!
@@ -203,6 +209,7 @@
 //! * `electrum`: [`electrum`](crate::blockchain::electrum) client protocol for interacting with electrum servers
 //! * `esplora`: [`esplora`](crate::blockchain::esplora) client protocol for interacting with blockstream [electrs](https://github.com/Blockstream/electrs) servers
 //! * `key-value-db`: key value [`database`](crate::database) based on [`sled`](crate::sled) for caching blockchain data
+//! * `parallel-derivation`: derive the scripts cached during sync and the addresses returned by `Wallet::list_addresses` in parallel, using [rayon](https://docs.rs/rayon)
 
 pub extern crate bitcoin;
 extern crate log;
@@ -214,6 +221,12 @@ extern crate serde_json;
 #[cfg(feature = "keys-bip39")]
 extern crate bip39;
 
+#[cfg(feature = "verify")]
+extern crate bitcoinconsensus;
+
+#[cfg(feature = "parallel-derivation")]
+extern crate rayon;
+
 #[cfg(any(target_arch = "wasm32", feature = "async-interface"))]
 #[macro_use]
 extern crate async_trait;
@@ -256,8 +269,11 @@ pub mod database;
 pub mod descriptor;
 #[cfg(feature = "test-md-docs")]
 mod doctest;
+#[cfg(feature = "integration-test")]
+#[cfg_attr(docsrs, doc(cfg(feature = "integration-test")))]
+pub mod integration_test;
 pub mod keys;
-pub(crate) mod psbt;
+pub mod psbt;
 pub(crate) mod types;
 pub mod wallet;
 
@@ -0,0 +1,552 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! PSBT diff
+//!
+//! This module compares two versions of the same PSBT, for example the one sent to a signer and
+//! the one it returned, and reports what changed. Coordinators can use it to detect tampering
+//! between signing rounds: a signer is only expected to add signatures or finalize inputs, which
+//! can be checked with [`PSBTDiff::only_adds_signatures`].
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+//! use bdk::psbt::diff::PSBTDiff;
+//!
+//! # let sent: PSBT = unimplemented!();
+//! # let received: PSBT = unimplemented!();
+//! let diff = PSBTDiff::compare(&sent, &received);
+//! if !diff.only_adds_signatures() {
+//!     println!("The PSBT has been tampered with: {:?}", diff.changes);
+//! }
+//! ```
+
+use bitcoin::util::psbt::{self, PartiallySignedTransaction as PSBT};
+use bitcoin::{OutPoint, PublicKey, TxOut};
+
+/// A change between two versions of a PSBT
+///
+/// Inputs are identified by the outpoint they spend, outputs by their index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PSBTChange {
+    /// The version of the transaction changed
+    Version {
+        /// Old version
+        old: i32,
+        /// New version
+        new: i32,
+    },
+    /// The lock time of the transaction changed
+    LockTime {
+        /// Old lock time
+        old: u32,
+        /// New lock time
+        new: u32,
+    },
+    /// A global field, stored in the `unknown` map, was added, removed or modified
+    GlobalFieldModified,
+    /// An input spending this outpoint was added
+    InputAdded(OutPoint),
+    /// The input spending this outpoint was removed
+    InputRemoved(OutPoint),
+    /// The input spending `outpoint` was moved to a different index
+    InputMoved {
+        /// Outpoint spent by the input
+        outpoint: OutPoint,
+        /// Old index of the input
+        old: usize,
+        /// New index of the input
+        new: usize,
+    },
+    /// The sequence of the input spending `outpoint` changed
+    SequenceModified {
+        /// Outpoint spent by the input
+        outpoint: OutPoint,
+        /// Old sequence
+        old: u32,
+        /// New sequence
+        new: u32,
+    },
+    /// A signature was added to the input spending `outpoint`
+    SignatureAdded {
+        /// Outpoint spent by the input
+        outpoint: OutPoint,
+        /// Key of the signature
+        key: PublicKey,
+    },
+    /// A signature was removed from the input spending `outpoint`
+    SignatureRemoved {
+        /// Outpoint spent by the input
+        outpoint: OutPoint,
+        /// Key of the signature
+        key: PublicKey,
+    },
+    /// A signature of the input spending `outpoint` was replaced
+    SignatureModified {
+        /// Outpoint spent by the input
+        outpoint: OutPoint,
+        /// Key of the signature
+        key: PublicKey,
+    },
+    /// The input spending this outpoint was finalized
+    ///
+    /// A finalizer can clear the signatures, the scripts, the key paths and the sighash type of
+    /// the input, so their removal is not reported. Changes to the previous outputs and the
+    /// unknown fields still are.
+    InputFinalized(OutPoint),
+    /// A field of the input spending `outpoint` was modified
+    InputFieldModified {
+        /// Outpoint spent by the input
+        outpoint: OutPoint,
+        /// Name of the field in [`psbt::Input`]
+        field: &'static str,
+    },
+    /// An output was added at this index
+    OutputAdded(usize),
+    /// The output at this index was removed
+    OutputRemoved(usize),
+    /// The value or the script of the output at `index` changed
+    OutputModified {
+        /// Index of the output
+        index: usize,
+        /// Old output
+        old: TxOut,
+        /// New output
+        new: TxOut,
+    },
+    /// A field of the output at `index` was modified
+    OutputFieldModified {
+        /// Index of the output
+        index: usize,
+        /// Name of the field in [`psbt::Output`]
+        field: &'static str,
+    },
+}
+
+/// Differences between two versions of a PSBT
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PSBTDiff {
+    /// List of the changes
+    pub changes: Vec<PSBTChange>,
+}
+
+macro_rules! compare_fields {
+    ($old:expr, $new:expr, $changes:expr, $make_change:expr, [$($field:ident),*]) => {
+        $(
+            if $old.$field != $new.$field {
+                $changes.push($make_change(stringify!($field)));
+            }
+        )*
+    };
+}
+
+impl PSBTDiff {
+    /// Compare two versions of a PSBT, reporting the changes made to `old` to obtain `new`
+    pub fn compare(old: &PSBT, new: &PSBT) -> Self {
+        let mut changes = vec![];
+        let (old_tx, new_tx) = (&old.global.unsigned_tx, &new.global.unsigned_tx);
+
+        if old_tx.version != new_tx.version {
+            changes.push(PSBTChange::Version {
+                old: old_tx.version,
+                new: new_tx.version,
+            });
+        }
+        if old_tx.lock_time != new_tx.lock_time {
+            changes.push(PSBTChange::LockTime {
+                old: old_tx.lock_time,
+                new: new_tx.lock_time,
+            });
+        }
+        if old.global.unknown != new.global.unknown {
+            changes.push(PSBTChange::GlobalFieldModified);
+        }
+
+        for (old_index, (old_txin, old_input)) in
+            old_tx.input.iter().zip(old.inputs.iter()).enumerate()
+        {
+            let outpoint = old_txin.previous_output;
+            let (new_index, (new_txin, new_input)) = match new_tx
+                .input
+                .iter()
+                .zip(new.inputs.iter())
+                .enumerate()
+                .find(|(_, (txin, _))| txin.previous_output == outpoint)
+            {
+                Some(found) => found,
+                None => {
+                    changes.push(PSBTChange::InputRemoved(outpoint));
+                    continue;
+                }
+            };
+
+            if old_index != new_index {
+                changes.push(PSBTChange::InputMoved {
+                    outpoint,
+                    old: old_index,
+                    new: new_index,
+                });
+            }
+            if old_txin.sequence != new_txin.sequence {
+                changes.push(PSBTChange::SequenceModified {
+                    outpoint,
+                    old: old_txin.sequence,
+                    new: new_txin.sequence,
+                });
+            }
+            compare_inputs(outpoint, old_input, new_input, &mut changes);
+        }
+        for new_txin in &new_tx.input {
+            if !old_tx
+                .input
+                .iter()
+                .any(|txin| txin.previous_output == new_txin.previous_output)
+            {
+                changes.push(PSBTChange::InputAdded(new_txin.previous_output));
+            }
+        }
+
+        for (index, (old_txout, old_output)) in
+            old_tx.output.iter().zip(old.outputs.iter()).enumerate()
+        {
+            let (new_txout, new_output) = match (new_tx.output.get(index), new.outputs.get(index)) {
+                (Some(txout), Some(output)) => (txout, output),
+                _ => {
+                    changes.push(PSBTChange::OutputRemoved(index));
+                    continue;
+                }
+            };
+
+            if old_txout != new_txout {
+                changes.push(PSBTChange::OutputModified {
+                    index,
+                    old: old_txout.clone(),
+                    new: new_txout.clone(),
+                });
+            }
+            compare_fields!(
+                old_output,
+                new_output,
+                changes,
+                |field| PSBTChange::OutputFieldModified { index, field },
+                [redeem_script, witness_script, hd_keypaths, unknown]
+            );
+        }
+        for index in old_tx.output.len()..new_tx.output.len() {
+            changes.push(PSBTChange::OutputAdded(index));
+        }
+
+        PSBTDiff { changes }
+    }
+
+    /// Return whether the two PSBTs are identical
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Return whether the only changes are signatures added or inputs finalized
+    ///
+    /// This is what is expected from a signer: any other change means the PSBT has been tampered
+    /// with and should be inspected before finalizing it.
+    pub fn only_adds_signatures(&self) -> bool {
+        self.changes.iter().all(|change| {
+            matches!(
+                change,
+                PSBTChange::SignatureAdded { .. } | PSBTChange::InputFinalized(_)
+            )
+        })
+    }
+}
+
+fn compare_inputs(
+    outpoint: OutPoint,
+    old: &psbt::Input,
+    new: &psbt::Input,
+    changes: &mut Vec<PSBTChange>,
+) {
+    let is_finalized = |input: &psbt::Input| {
+        input.final_script_sig.is_some() || input.final_script_witness.is_some()
+    };
+    let make_change = |field: &'static str| PSBTChange::InputFieldModified { outpoint, field };
+
+    if !is_finalized(old) && is_finalized(new) {
+        changes.push(PSBTChange::InputFinalized(outpoint));
+
+        compare_fields!(
+            old,
+            new,
+            changes,
+            make_change,
+            [non_witness_utxo, witness_utxo, unknown]
+        );
+        if new.sighash_type.is_some() && new.sighash_type != old.sighash_type {
+            changes.push(make_change("sighash_type"));
+        }
+        return;
+    }
+
+    for (key, sig) in &old.partial_sigs {
+        match new.partial_sigs.get(key) {
+            None => changes.push(PSBTChange::SignatureRemoved {
+                outpoint,
+                key: *key,
+            }),
+            Some(new_sig) if new_sig != sig => changes.push(PSBTChange::SignatureModified {
+                outpoint,
+                key: *key,
+            }),
+            _ => {}
+        }
+    }
+    for key in new.partial_sigs.keys() {
+        if !old.partial_sigs.contains_key(key) {
+            changes.push(PSBTChange::SignatureAdded {
+                outpoint,
+                key: *key,
+            });
+        }
+    }
+
+    compare_fields!(
+        old,
+        new,
+        changes,
+        make_change,
+        [
+            non_witness_utxo,
+            witness_utxo,
+            sighash_type,
+            redeem_script,
+            witness_script,
+            hd_keypaths,
+            final_script_sig,
+            final_script_witness,
+            unknown
+        ]
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::{Script, Transaction, TxIn};
+
+    use super::*;
+
+    fn get_test_psbt() -> PSBT {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::from_str(
+                    "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+                )
+                .unwrap(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 50_000,
+                ..Default::default()
+            }],
+        };
+
+        PSBT::from_unsigned_tx(tx).unwrap()
+    }
+
+    fn get_test_key() -> PublicKey {
+        PublicKey::from_str("02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737")
+            .unwrap()
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let diff = PSBTDiff::compare(&get_test_psbt(), &get_test_psbt());
+        assert!(diff.is_empty());
+        assert!(diff.only_adds_signatures());
+    }
+
+    #[test]
+    fn test_diff_signature_added() {
+        let old = get_test_psbt();
+        let mut new = old.clone();
+        new.inputs[0]
+            .partial_sigs
+            .insert(get_test_key(), vec![0x01]);
+
+        let diff = PSBTDiff::compare(&old, &new);
+        assert_eq!(
+            diff.changes,
+            vec![PSBTChange::SignatureAdded {
+                outpoint: old.global.unsigned_tx.input[0].previous_output,
+                key: get_test_key(),
+            }]
+        );
+        assert!(diff.only_adds_signatures());
+
+        let diff = PSBTDiff::compare(&new, &old);
+        assert!(!diff.only_adds_signatures());
+    }
+
+    #[test]
+    fn test_diff_finalized() {
+        let mut old = get_test_psbt();
+        old.inputs[0]
+            .partial_sigs
+            .insert(get_test_key(), vec![0x01]);
+        let mut new = get_test_psbt();
+        new.inputs[0].final_script_witness = Some(vec![vec![0x01], vec![0x02]]);
+
+        let diff = PSBTDiff::compare(&old, &new);
+        assert_eq!(
+            diff.changes,
+            vec![PSBTChange::InputFinalized(
+                old.global.unsigned_tx.input[0].previous_output
+            )]
+        );
+        assert!(diff.only_adds_signatures());
+    }
+
+    #[test]
+    fn test_diff_finalized_tampered() {
+        let mut old = get_test_psbt();
+        old.inputs[0].witness_utxo = Some(TxOut {
+            value: 60_000,
+            ..Default::default()
+        });
+        let mut new = old.clone();
+        new.inputs[0].final_script_witness = Some(vec![vec![0x01], vec![0x02]]);
+        new.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000_000,
+            ..Default::default()
+        });
+        new.inputs[0].sighash_type = Some(bitcoin::SigHashType::None);
+
+        let outpoint = old.global.unsigned_tx.input[0].previous_output;
+        let diff = PSBTDiff::compare(&old, &new);
+        assert_eq!(
+            diff.changes,
+            vec![
+                PSBTChange::InputFinalized(outpoint),
+                PSBTChange::InputFieldModified {
+                    outpoint,
+                    field: "witness_utxo",
+                },
+                PSBTChange::InputFieldModified {
+                    outpoint,
+                    field: "sighash_type",
+                },
+            ]
+        );
+        assert!(!diff.only_adds_signatures());
+    }
+
+    #[test]
+    fn test_diff_tampered() {
+        let old = get_test_psbt();
+        let mut new = old.clone();
+        new.global.unsigned_tx.output[0].script_pubkey = Script::from(vec![0x51]);
+        new.global.unsigned_tx.output.push(TxOut::default());
+        new.outputs.push(Default::default());
+        new.global.unsigned_tx.input[0].sequence = 0xFFFFFFFD;
+        new.outputs[0].redeem_script = Some(Script::from(vec![0x51]));
+
+        let diff = PSBTDiff::compare(&old, &new);
+        assert_eq!(
+            diff.changes,
+            vec![
+                PSBTChange::SequenceModified {
+                    outpoint: old.global.unsigned_tx.input[0].previous_output,
+                    old: 0xFFFFFFFF,
+                    new: 0xFFFFFFFD,
+                },
+                PSBTChange::OutputModified {
+                    index: 0,
+                    old: old.global.unsigned_tx.output[0].clone(),
+                    new: new.global.unsigned_tx.output[0].clone(),
+                },
+                PSBTChange::OutputFieldModified {
+                    index: 0,
+                    field: "redeem_script",
+                },
+                PSBTChange::OutputAdded(1),
+            ]
+        );
+        assert!(!diff.only_adds_signatures());
+    }
+
+    #[test]
+    fn test_diff_inputs() {
+        let old = get_test_psbt();
+        let mut new = old.clone();
+        let new_outpoint = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+        )
+        .unwrap();
+        new.global.unsigned_tx.input[0].previous_output = new_outpoint;
+
+        let diff = PSBTDiff::compare(&old, &new);
+        assert_eq!(
+            diff.changes,
+            vec![
+                PSBTChange::InputRemoved(old.global.unsigned_tx.input[0].previous_output),
+                PSBTChange::InputAdded(new_outpoint),
+            ]
+        );
+    }
+    #[test]
+    fn test_diff_inputs_reordered() {
+        let mut old = get_test_psbt();
+        old.global.unsigned_tx.input.push(TxIn {
+            previous_output: OutPoint::from_str(
+                "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+            )
+            .unwrap(),
+            ..Default::default()
+        });
+        old.inputs.push(Default::default());
+        let mut new = old.clone();
+        new.global.unsigned_tx.input.swap(0, 1);
+        new.inputs.swap(0, 1);
+
+        let diff = PSBTDiff::compare(&old, &new);
+        assert_eq!(
+            diff.changes,
+            vec![
+                PSBTChange::InputMoved {
+                    outpoint: old.global.unsigned_tx.input[0].previous_output,
+                    old: 0,
+                    new: 1,
+                },
+                PSBTChange::InputMoved {
+                    outpoint: old.global.unsigned_tx.input[1].previous_output,
+                    old: 1,
+                    new: 0,
+                },
+            ]
+        );
+        assert!(!diff.only_adds_signatures());
+    }
+}
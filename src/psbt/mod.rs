@@ -22,31 +22,597 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+//! PSBT utilities
+//!
+//! This module contains helpers to work with PSBTs, like the [`sighash`] computation used by the
+//! signers, [`combine`] to merge PSBTs signed by different parties and [`join`] to build a
+//! collaborative transaction out of the PSBTs of different wallets, plus the support for
+//! [version 2](v2) PSBTs and the [`proprietary`] fields used by BDK to store wallet metadata.
+//! The [`diff`] module reports the changes between two versions of the same PSBT.
+//!
+//! PSBTs can be encoded in base64 with [`to_base64`] and decoded with [`from_base64`], or in hex
+//! with [`to_hex`] and [`from_hex`]. The [`serde_base64`] module can be used with
+//! `#[serde(with = "...")]` to (de)serialize the PSBTs contained in other structures.
+//!
+//! ## Example
+//!
+//! ```
+//! # use bdk::psbt;
+//! let base64 = "cHNidP8BAFICAAAAAVaUTF0/mEE+9Fz1RUVTgQPMnymOBXWCCtNZE3bi4PZdAQAAAAD/////AVDDAAAAAAAAFgAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+//!
+//! let psbt = psbt::from_base64(base64)?;
+//! assert_eq!(psbt::to_base64(&psbt), base64);
+//! # Ok::<_, bdk::Error>(())
+//! ```
+
+use std::collections::{BTreeMap, HashSet};
+
+use bitcoin::consensus::encode::{deserialize, serialize, serialize_hex};
+use bitcoin::hashes::hex::FromHex;
 use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
-use bitcoin::TxOut;
+use bitcoin::{Transaction, TxOut};
+
+use crate::error::Error;
+use crate::types::FeeRate;
+use crate::wallet::finalizer::Finalizer;
+
+pub mod diff;
+pub mod proprietary;
+pub mod sighash;
+pub mod v2;
+
+/// Combine several PSBTs for the same transaction into one, implementing the Combiner role of
+/// [BIP174](https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki)
+///
+/// The signatures, key paths, scripts and any other field of the inputs and outputs are merged.
+/// When a field that can only have one value is set in more than one PSBT, the value of the
+/// first one is kept.
+///
+/// Returns an error if the PSBTs don't spend the same unsigned transaction, or if no PSBT is
+/// provided.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+/// # let signed_by_alice: PSBT = unimplemented!();
+/// # let signed_by_bob: PSBT = unimplemented!();
+/// let psbt = bdk::psbt::combine(vec![signed_by_alice, signed_by_bob])?;
+/// # Ok::<_, bdk::Error>(())
+/// ```
+pub fn combine<I: IntoIterator<Item = PSBT>>(psbts: I) -> Result<PSBT, Error> {
+    let mut psbts = psbts.into_iter();
+    let mut combined = psbts
+        .next()
+        .ok_or_else(|| Error::Generic("No PSBTs to combine".to_string()))?;
+
+    for psbt in psbts {
+        combined.merge(psbt)?;
+    }
+
+    Ok(combined)
+}
+
+/// Inputs, outputs and fee brought by one of the PSBTs merged with [`join`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinContribution {
+    /// Indexes of the inputs of the joined transaction that come from this PSBT
+    pub inputs: Vec<usize>,
+    /// Indexes of the outputs of the joined transaction that come from this PSBT
+    pub outputs: Vec<usize>,
+    /// Fee paid by this PSBT, in satoshi: the value of its inputs minus the value of its outputs
+    pub fee: u64,
+}
+
+/// Join the inputs and the outputs of PSBTs built by different wallets into a single transaction
+///
+/// Unlike [`combine`], the PSBTs spend different transactions: the inputs and the outputs of each
+/// of them are appended to the joined transaction, in order, together with their PSBT fields.
+/// This is the building block for collaborative transactions like coinjoins or dual-funded
+/// channels. The inputs and outputs are not shuffled, which is left to the caller.
+///
+/// Every PSBT must have the same version and lock time, none of them can be signed yet (the
+/// signatures would commit to a different transaction) and every input must have either the
+/// `witness_utxo` or the `non_witness_utxo`, so that the fee paid by each party can be computed.
+///
+/// Returns the joined PSBT and the [contribution](JoinContribution) of each PSBT, in the same
+/// order.
+///
+/// ## Example
+///
+/// ```no_run
+/// # use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+/// # let from_alice: PSBT = unimplemented!();
+/// # let from_bob: PSBT = unimplemented!();
+/// let (psbt, contributions) = bdk::psbt::join(vec![from_alice, from_bob])?;
+/// println!("Alice pays {} sat of fees", contributions[0].fee);
+/// # Ok::<_, bdk::Error>(())
+/// ```
+pub fn join<I: IntoIterator<Item = PSBT>>(
+    psbts: I,
+) -> Result<(PSBT, Vec<JoinContribution>), Error> {
+    let mut psbts = psbts.into_iter().peekable();
+    let (version, lock_time) = match psbts.peek() {
+        Some(psbt) => (
+            psbt.global.unsigned_tx.version,
+            psbt.global.unsigned_tx.lock_time,
+        ),
+        None => return Err(Error::Generic("No PSBTs to join".to_string())),
+    };
+
+    let mut tx = Transaction {
+        version,
+        lock_time,
+        input: vec![],
+        output: vec![],
+    };
+    let mut inputs = vec![];
+    let mut outputs = vec![];
+    let mut global_unknown = BTreeMap::new();
+    let mut contributions = vec![];
+    let mut spent = HashSet::new();
 
+    for (n, psbt) in psbts.enumerate() {
+        if psbt.global.unsigned_tx.version != version
+            || psbt.global.unsigned_tx.lock_time != lock_time
+        {
+            return Err(Error::Generic(format!(
+                "The PSBT at index {} has a different version or lock time",
+                n
+            )));
+        }
+        if psbt.inputs.iter().any(|input| {
+            !input.partial_sigs.is_empty()
+                || input.final_script_sig.is_some()
+                || input.final_script_witness.is_some()
+        }) {
+            return Err(Error::Generic(format!(
+                "The PSBT at index {} is already signed",
+                n
+            )));
+        }
+
+        let fee = psbt.fee_amount()?;
+        let contribution = JoinContribution {
+            inputs: (tx.input.len()..tx.input.len() + psbt.inputs.len()).collect(),
+            outputs: (tx.output.len()..tx.output.len() + psbt.outputs.len()).collect(),
+            fee,
+        };
+
+        for txin in &psbt.global.unsigned_tx.input {
+            if !spent.insert(txin.previous_output) {
+                return Err(Error::Generic(format!(
+                    "The input `{}` is spent by more than one PSBT",
+                    txin.previous_output
+                )));
+            }
+        }
+        for (key, value) in psbt.global.unknown {
+            global_unknown.entry(key).or_insert(value);
+        }
+
+        tx.input.extend(psbt.global.unsigned_tx.input);
+        tx.output.extend(psbt.global.unsigned_tx.output);
+        inputs.extend(psbt.inputs);
+        outputs.extend(psbt.outputs);
+        contributions.push(contribution);
+    }
+
+    let mut joined = PSBT::from_unsigned_tx(tx)?;
+    joined.global.unknown = global_unknown;
+    joined.inputs = inputs;
+    joined.outputs = outputs;
+
+    Ok((joined, contributions))
+}
+
+/// Encode a PSBT in base64
+pub fn to_base64(psbt: &PSBT) -> String {
+    base64::encode(&serialize(psbt))
+}
+
+/// Decode a PSBT encoded in base64
+pub fn from_base64(s: &str) -> Result<PSBT, Error> {
+    Ok(deserialize(&base64::decode(s.trim())?)?)
+}
+
+/// Encode a PSBT in hex
+pub fn to_hex(psbt: &PSBT) -> String {
+    serialize_hex(psbt)
+}
+
+/// Decode a PSBT encoded in hex
+pub fn from_hex(s: &str) -> Result<PSBT, Error> {
+    Ok(deserialize(&Vec::<u8>::from_hex(s.trim())?)?)
+}
+
+/// Serde support for PSBTs, encoded as base64 strings
+///
+/// ## Example
+///
+/// ```
+/// # use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct SignRequest {
+///     #[serde(with = "bdk::psbt::serde_base64")]
+///     psbt: PSBT,
+///     message: String,
+/// }
+/// ```
+pub mod serde_base64 {
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::PSBT;
+
+    /// Serialize a PSBT as a base64 string
+    pub fn serialize<S: Serializer>(psbt: &PSBT, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::to_base64(psbt))
+    }
+
+    /// Deserialize a PSBT from a base64 string
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PSBT, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        super::from_base64(&s).map_err(D::Error::custom)
+    }
+}
+
+/// Trait to add extra methods to a PSBT
 pub trait PSBTUtils {
+    /// Return the output spent by the input at `input_index`, taken from the `non_witness_utxo`
+    /// or, when it's missing, from the `witness_utxo`
+    ///
+    /// Returns `None` if the `non_witness_utxo` is not the transaction spent by the input.
     fn get_utxo_for(&self, input_index: usize) -> Option<TxOut>;
+
+    /// Return the absolute fee paid by the transaction, in satoshi
+    ///
+    /// Every input must have either the `witness_utxo` or a valid `non_witness_utxo`, otherwise
+    /// [`Error::MissingInputUTXO`] is returned.
+    fn fee_amount(&self) -> Result<u64, Error>;
+
+    /// Return the fee rate of the transaction
+    ///
+    /// The weight of the transaction is only known once it's signed, so every input must be
+    /// finalized, otherwise [`Error::InputNotFinalized`] is returned. Use
+    /// [`Wallet::estimate_psbt_fee_rate`](crate::Wallet::estimate_psbt_fee_rate) to get the fee
+    /// rate of a PSBT that spends the outputs of a wallet before it's signed.
+    fn fee_rate(&self) -> Result<FeeRate, Error>;
 }
 
 impl PSBTUtils for PSBT {
     fn get_utxo_for(&self, input_index: usize) -> Option<TxOut> {
-        let tx = &self.global.unsigned_tx;
+        let input = self.inputs.get(input_index)?;
+        let prevout = self
+            .global
+            .unsigned_tx
+            .input
+            .get(input_index)?
+            .previous_output;
 
-        if input_index >= tx.input.len() {
-            return None;
+        // The amount in the `witness_utxo` isn't committed to by the txid, so the
+        // `non_witness_utxo` is preferred when it's available
+        match &input.non_witness_utxo {
+            Some(prev_tx) if prev_tx.txid() == prevout.txid => {
+                prev_tx.output.get(prevout.vout as usize).cloned()
+            }
+            Some(_) => None,
+            None => input.witness_utxo.clone(),
         }
+    }
 
-        if let Some(input) = self.inputs.get(input_index) {
-            if let Some(wit_utxo) = &input.witness_utxo {
-                Some(wit_utxo.clone())
-            } else if let Some(in_tx) = &input.non_witness_utxo {
-                Some(in_tx.output[tx.input[input_index].previous_output.vout as usize].clone())
-            } else {
-                None
-            }
-        } else {
-            None
+    fn fee_amount(&self) -> Result<u64, Error> {
+        let overflow = || Error::Generic("The value of the PSBT overflows".into());
+
+        let input_value = (0..self.inputs.len()).try_fold(0u64, |sum, n| {
+            let txout = self.get_utxo_for(n).ok_or(Error::MissingInputUTXO(n))?;
+            sum.checked_add(txout.value).ok_or_else(overflow)
+        })?;
+        let output_value = self
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .try_fold(0u64, |sum, txout| sum.checked_add(txout.value))
+            .ok_or_else(overflow)?;
+
+        input_value.checked_sub(output_value).ok_or_else(|| {
+            Error::Generic("The outputs of the PSBT are worth more than its inputs".into())
+        })
+    }
+
+    fn fee_rate(&self) -> Result<FeeRate, Error> {
+        let fee_amount = self.fee_amount()?;
+        if let Some(n) = (0..self.inputs.len()).find(|n| !Finalizer::is_finalized(self, *n)) {
+            return Err(Error::InputNotFinalized(n));
         }
+
+        let weight = self.clone().extract_tx().get_weight();
+        Ok(FeeRate::from_sat_per_vb(
+            fee_amount as f32 / (weight as f32 / 4.0),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::util::bip32;
+    use bitcoin::{OutPoint, PublicKey, TxIn};
+
+    use super::*;
+
+    fn get_test_psbt() -> PSBT {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::from_str(
+                    "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+                )
+                .unwrap(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 50_000,
+                ..Default::default()
+            }],
+        };
+
+        PSBT::from_unsigned_tx(tx).unwrap()
+    }
+
+    #[test]
+    fn test_combine() {
+        let alice = PublicKey::from_str(
+            "02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737",
+        )
+        .unwrap();
+        let bob = PublicKey::from_str(
+            "02864bb4ad00cefa806098a69e192bbda937494e69eb452b87bb3f20f6283baedb",
+        )
+        .unwrap();
+
+        let mut signed_by_alice = get_test_psbt();
+        signed_by_alice.inputs[0]
+            .partial_sigs
+            .insert(alice, vec![0x01]);
+        signed_by_alice.inputs[0].hd_keypaths.insert(
+            alice,
+            (
+                bip32::Fingerprint::from_str("0f056943").unwrap(),
+                bip32::DerivationPath::from_str("m/48'/1'/0'/2'/0/0").unwrap(),
+            ),
+        );
+
+        let mut signed_by_bob = get_test_psbt();
+        signed_by_bob.inputs[0].partial_sigs.insert(bob, vec![0x02]);
+        signed_by_bob.inputs[0].witness_utxo = Some(TxOut {
+            value: 60_000,
+            ..Default::default()
+        });
+
+        let combined = combine(vec![signed_by_alice, signed_by_bob]).unwrap();
+        assert_eq!(combined.inputs[0].partial_sigs.len(), 2);
+        assert_eq!(combined.inputs[0].hd_keypaths.len(), 1);
+        assert_eq!(
+            combined.inputs[0]
+                .witness_utxo
+                .as_ref()
+                .map(|utxo| utxo.value),
+            Some(60_000)
+        );
+    }
+
+    #[test]
+    fn test_fee_amount_and_rate() {
+        let mut psbt = get_test_psbt();
+        assert!(matches!(psbt.fee_amount(), Err(Error::MissingInputUTXO(0))));
+
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 60_000,
+            ..Default::default()
+        });
+        assert_eq!(psbt.fee_amount().unwrap(), 10_000);
+        assert!(matches!(psbt.fee_rate(), Err(Error::InputNotFinalized(0))));
+
+        psbt.inputs[0].final_script_witness = Some(vec![vec![0x00; 72], vec![0x00; 33]]);
+        let weight = psbt.clone().extract_tx().get_weight();
+        assert_eq!(
+            psbt.fee_rate().unwrap(),
+            FeeRate::from_sat_per_vb(10_000.0 / (weight as f32 / 4.0))
+        );
+
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 40_000,
+            ..Default::default()
+        });
+        assert!(matches!(psbt.fee_amount(), Err(Error::Generic(_))));
+
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: u64::MAX,
+            ..Default::default()
+        });
+        psbt.global.unsigned_tx.output.push(TxOut {
+            value: u64::MAX,
+            ..Default::default()
+        });
+        assert!(matches!(psbt.fee_amount(), Err(Error::Generic(_))));
+    }
+
+    #[test]
+    fn test_fee_amount_non_witness_utxo() {
+        let prev_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: 60_000,
+                ..Default::default()
+            }],
+        };
+
+        let mut psbt = get_test_psbt();
+        psbt.global.unsigned_tx.input[0].previous_output = OutPoint {
+            txid: prev_tx.txid(),
+            vout: 0,
+        };
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx.clone());
+        // the `witness_utxo` can lie about the amount, the `non_witness_utxo` wins
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: 1_000_000,
+            ..Default::default()
+        });
+        assert_eq!(psbt.fee_amount().unwrap(), 10_000);
+
+        // an out of range vout doesn't panic
+        psbt.global.unsigned_tx.input[0].previous_output.vout = 1;
+        assert_eq!(psbt.get_utxo_for(0), None);
+        assert!(matches!(psbt.fee_amount(), Err(Error::MissingInputUTXO(0))));
+
+        // neither does a `non_witness_utxo` that isn't the transaction spent by the input
+        let mut psbt = get_test_psbt();
+        psbt.inputs[0].non_witness_utxo = Some(prev_tx);
+        assert_eq!(psbt.get_utxo_for(0), None);
+    }
+
+    #[test]
+    fn test_base64_and_hex() {
+        let psbt = get_test_psbt();
+
+        let encoded = to_base64(&psbt);
+        assert_eq!(from_base64(&encoded).unwrap(), psbt);
+        // surrounding whitespace, like a newline from a file, is ignored
+        assert_eq!(from_base64(&format!("{}\n", encoded)).unwrap(), psbt);
+        assert_eq!(from_hex(&to_hex(&psbt)).unwrap(), psbt);
+
+        assert!(matches!(from_base64("not base64!"), Err(Error::Base64(_))));
+        assert!(matches!(from_base64("cHNidA=="), Err(Error::Encode(_))));
+        assert!(matches!(from_hex("7073"), Err(Error::Encode(_))));
+    }
+
+    #[test]
+    fn test_serde_base64() {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct Wrapper {
+            #[serde(with = "serde_base64")]
+            psbt: PSBT,
+        }
+
+        let psbt = get_test_psbt();
+        let json = serde_json::to_string(&Wrapper { psbt: psbt.clone() }).unwrap();
+        assert_eq!(json, format!("{{\"psbt\":\"{}\"}}", to_base64(&psbt)));
+
+        let wrapper: Wrapper = serde_json::from_str(&json).unwrap();
+        assert_eq!(wrapper.psbt, psbt);
+        assert!(serde_json::from_str::<Wrapper>("{\"psbt\":\"cHNidA==\"}").is_err());
+    }
+
+    fn get_funded_test_psbt(outpoint: &str, value_in: u64, value_out: u64) -> PSBT {
+        let mut psbt = get_test_psbt();
+        psbt.global.unsigned_tx.input[0].previous_output = OutPoint::from_str(outpoint).unwrap();
+        psbt.global.unsigned_tx.output[0].value = value_out;
+        psbt.inputs[0].witness_utxo = Some(TxOut {
+            value: value_in,
+            ..Default::default()
+        });
+
+        psbt
+    }
+
+    #[test]
+    fn test_join() {
+        let alice = get_funded_test_psbt(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+            60_000,
+            50_000,
+        );
+        let mut bob = get_funded_test_psbt(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+            30_000,
+            10_000,
+        );
+        bob.global.unsigned_tx.output.push(TxOut {
+            value: 15_000,
+            ..Default::default()
+        });
+        bob.outputs.push(Default::default());
+
+        let (joined, contributions) = join(vec![alice, bob.clone()]).unwrap();
+        assert_eq!(joined.global.unsigned_tx.input.len(), 2);
+        assert_eq!(joined.global.unsigned_tx.output.len(), 3);
+        assert_eq!(joined.inputs[1], bob.inputs[0]);
+        assert_eq!(joined.fee_amount().unwrap(), 15_000);
+        assert_eq!(
+            contributions,
+            vec![
+                JoinContribution {
+                    inputs: vec![0],
+                    outputs: vec![0],
+                    fee: 10_000,
+                },
+                JoinContribution {
+                    inputs: vec![1],
+                    outputs: vec![1, 2],
+                    fee: 5_000,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_join_invalid() {
+        let alice = get_funded_test_psbt(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+            60_000,
+            50_000,
+        );
+
+        // same input spent twice
+        assert!(matches!(
+            join(vec![alice.clone(), alice.clone()]),
+            Err(Error::Generic(_))
+        ));
+
+        let mut bob = get_funded_test_psbt(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+            30_000,
+            10_000,
+        );
+        bob.global.unsigned_tx.lock_time = 100;
+        assert!(matches!(
+            join(vec![alice.clone(), bob.clone()]),
+            Err(Error::Generic(_))
+        ));
+
+        bob.global.unsigned_tx.lock_time = 0;
+        bob.inputs[0].witness_utxo = None;
+        assert!(matches!(
+            join(vec![alice.clone(), bob.clone()]),
+            Err(Error::MissingInputUTXO(0))
+        ));
+
+        bob.inputs[0].final_script_witness = Some(vec![vec![0x00; 72]]);
+        assert!(matches!(join(vec![alice, bob]), Err(Error::Generic(_))));
+        assert!(matches!(join(Vec::<PSBT>::new()), Err(Error::Generic(_))));
+    }
+
+    #[test]
+    fn test_combine_different_transactions() {
+        let mut other = get_test_psbt();
+        other.global.unsigned_tx.output[0].value = 40_000;
+
+        assert!(matches!(
+            combine(vec![get_test_psbt(), other]),
+            Err(Error::PSBT(
+                bitcoin::util::psbt::Error::UnexpectedUnsignedTx { .. }
+            ))
+        ));
+        assert!(matches!(
+            combine(Vec::<PSBT>::new()),
+            Err(Error::Generic(_))
+        ));
     }
 }
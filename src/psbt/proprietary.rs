@@ -0,0 +1,366 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! BDK proprietary PSBT fields
+//!
+//! [BIP174](https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki) reserves the `0xFC`
+//! key type for proprietary fields, namespaced by an identifier. Signers must keep the fields they
+//! don't understand, so this module uses the `bdk` identifier to attach some wallet metadata to
+//! the PSBTs, that can later be read back with [`BdkMetadata::from_psbt`] once the PSBT has been
+//! signed by somebody else.
+//!
+//! The fields defined are:
+//!
+//! | Map    | Subtype | Key data               | Value                                                |
+//! |--------|---------|------------------------|------------------------------------------------------|
+//! | global | `0x00`  | -                      | Version of BDK that built the PSBT, as UTF-8         |
+//! | global | `0x01`  | Keychain (`e` or `i`)  | Policy path used for the keychain, as JSON           |
+//! | output | `0x00`  | -                      | Keychain (`e` or `i`) and derivation index (u32 LE) |
+//!
+//! The metadata is added by the wallet when the
+//! [`TxBuilder::add_bdk_metadata`](crate::wallet::tx_builder::TxBuilder::add_bdk_metadata) option
+//! is enabled, and the outputs can be checked with
+//! [`Wallet::verify_bdk_metadata`](crate::wallet::Wallet::verify_bdk_metadata).
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+
+use bitcoin::util::psbt::raw;
+use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+
+use crate::types::KeychainKind;
+
+/// Key type of the proprietary fields
+pub const PSBT_PROPRIETARY: u8 = 0xFC;
+/// Identifier of the fields defined by BDK
+pub const BDK_IDENTIFIER: &[u8] = b"bdk";
+
+/// Global field: version of BDK that built the PSBT
+pub const PSBT_BDK_GLOBAL_VERSION: u8 = 0x00;
+/// Global field: policy path used for a keychain
+pub const PSBT_BDK_GLOBAL_POLICY_PATH: u8 = 0x01;
+/// Output field: keychain and derivation index of an output that belongs to the wallet
+pub const PSBT_BDK_OUT_KEYCHAIN: u8 = 0x00;
+
+/// Errors thrown while reading or verifying the BDK metadata of a PSBT
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MetadataError {
+    /// The value of the field with this subtype can't be parsed
+    InvalidField(u8),
+    /// The byte doesn't represent a [`KeychainKind`]
+    InvalidKeychain(u8),
+    /// The keychain and derivation index of the output at this index don't derive its script
+    OutputMismatch(usize),
+}
+
+impl fmt::Display for MetadataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for MetadataError {}
+
+/// Metadata stored by BDK in the proprietary fields of a PSBT
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BdkMetadata {
+    /// Version of BDK that built the PSBT
+    pub version: Option<String>,
+    /// Policy path used for the external keychain
+    pub external_policy_path: Option<BTreeMap<String, Vec<usize>>>,
+    /// Policy path used for the internal keychain
+    pub internal_policy_path: Option<BTreeMap<String, Vec<usize>>>,
+    /// Keychain and derivation index of each output, if it belongs to the wallet
+    pub outputs: Vec<Option<(KeychainKind, u32)>>,
+}
+
+impl BdkMetadata {
+    /// Read the metadata from the proprietary fields of a PSBT
+    ///
+    /// Fields that don't belong to the `bdk` namespace, or that have a subtype unknown to this
+    /// version, are ignored.
+    pub fn from_psbt(psbt: &PSBT) -> Result<Self, MetadataError> {
+        let mut metadata = BdkMetadata::default();
+
+        for (key, value) in &psbt.global.unknown {
+            match parse_key(key) {
+                Some((PSBT_BDK_GLOBAL_VERSION, [])) => {
+                    let version = String::from_utf8(value.clone())
+                        .map_err(|_| MetadataError::InvalidField(PSBT_BDK_GLOBAL_VERSION))?;
+                    metadata.version = Some(version);
+                }
+                Some((PSBT_BDK_GLOBAL_POLICY_PATH, [keychain])) => {
+                    let path = serde_json::from_slice(value)
+                        .map_err(|_| MetadataError::InvalidField(PSBT_BDK_GLOBAL_POLICY_PATH))?;
+                    match keychain_from_byte(*keychain)? {
+                        KeychainKind::External => metadata.external_policy_path = Some(path),
+                        KeychainKind::Internal => metadata.internal_policy_path = Some(path),
+                    }
+                }
+                Some((subtype, _))
+                    if subtype == PSBT_BDK_GLOBAL_VERSION
+                        || subtype == PSBT_BDK_GLOBAL_POLICY_PATH =>
+                {
+                    return Err(MetadataError::InvalidField(subtype))
+                }
+                _ => continue,
+            }
+        }
+
+        for psbt_output in &psbt.outputs {
+            let mut output = None;
+            for (key, value) in &psbt_output.unknown {
+                match parse_key(key) {
+                    Some((PSBT_BDK_OUT_KEYCHAIN, [])) if value.len() == 5 => {
+                        let keychain = keychain_from_byte(value[0])?;
+                        let index = u32::from_le_bytes(value[1..].try_into().unwrap());
+                        output = Some((keychain, index));
+                    }
+                    Some((PSBT_BDK_OUT_KEYCHAIN, _)) => {
+                        return Err(MetadataError::InvalidField(PSBT_BDK_OUT_KEYCHAIN))
+                    }
+                    _ => continue,
+                }
+            }
+            metadata.outputs.push(output);
+        }
+
+        Ok(metadata)
+    }
+
+    /// Write the metadata to the proprietary fields of a PSBT
+    ///
+    /// The fields of the `bdk` namespace already present in the PSBT are replaced, while the other
+    /// ones are left untouched. The entries of `outputs` that don't have a corresponding output in
+    /// the PSBT are ignored.
+    pub fn add_to_psbt(&self, psbt: &mut PSBT) {
+        remove_bdk_fields(&mut psbt.global.unknown);
+        for psbt_output in &mut psbt.outputs {
+            remove_bdk_fields(&mut psbt_output.unknown);
+        }
+
+        if let Some(version) = &self.version {
+            psbt.global.unknown.insert(
+                proprietary_key(PSBT_BDK_GLOBAL_VERSION, &[]),
+                version.as_bytes().to_vec(),
+            );
+        }
+        let policy_paths = vec![
+            (KeychainKind::External, &self.external_policy_path),
+            (KeychainKind::Internal, &self.internal_policy_path),
+        ];
+        for (keychain, path) in policy_paths {
+            if let Some(path) = path {
+                psbt.global.unknown.insert(
+                    proprietary_key(PSBT_BDK_GLOBAL_POLICY_PATH, &[keychain.as_byte()]),
+                    serde_json::to_vec(path).expect("Internal serialization error"),
+                );
+            }
+        }
+
+        for (psbt_output, output) in psbt.outputs.iter_mut().zip(self.outputs.iter()) {
+            if let Some((keychain, index)) = output {
+                let mut value = vec![keychain.as_byte()];
+                value.extend_from_slice(&index.to_le_bytes());
+                psbt_output
+                    .unknown
+                    .insert(proprietary_key(PSBT_BDK_OUT_KEYCHAIN, &[]), value);
+            }
+        }
+    }
+}
+
+/// Build the key of a proprietary field in the `bdk` namespace
+///
+/// The key is made of the length of the identifier, the identifier itself, the subtype and the
+/// key data. The subtypes defined by BDK are always encoded as a single byte.
+pub fn proprietary_key(subtype: u8, key_data: &[u8]) -> raw::Key {
+    let mut key = vec![BDK_IDENTIFIER.len() as u8];
+    key.extend_from_slice(BDK_IDENTIFIER);
+    key.push(subtype);
+    key.extend_from_slice(key_data);
+
+    raw::Key {
+        type_value: PSBT_PROPRIETARY,
+        key,
+    }
+}
+
+// Return the subtype and the key data of a proprietary key in the `bdk` namespace
+fn parse_key(key: &raw::Key) -> Option<(u8, &[u8])> {
+    if key.type_value != PSBT_PROPRIETARY {
+        return None;
+    }
+
+    match key.key.split_first() {
+        Some((&len, rest))
+            if len as usize == BDK_IDENTIFIER.len() && rest.starts_with(BDK_IDENTIFIER) =>
+        {
+            rest[BDK_IDENTIFIER.len()..]
+                .split_first()
+                .map(|(subtype, key_data)| (*subtype, key_data))
+        }
+        _ => None,
+    }
+}
+
+fn remove_bdk_fields(map: &mut BTreeMap<raw::Key, Vec<u8>>) {
+    let bdk_keys = map
+        .keys()
+        .filter(|key| parse_key(key).is_some())
+        .cloned()
+        .collect::<Vec<_>>();
+    for key in bdk_keys {
+        map.remove(&key);
+    }
+}
+
+fn keychain_from_byte(byte: u8) -> Result<KeychainKind, MetadataError> {
+    match byte {
+        b'e' => Ok(KeychainKind::External),
+        b'i' => Ok(KeychainKind::Internal),
+        _ => Err(MetadataError::InvalidKeychain(byte)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::consensus::encode::{deserialize, serialize};
+    use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+
+    use super::*;
+
+    fn get_test_psbt() -> PSBT {
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::default(),
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![],
+            }],
+            output: vec![
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: Script::new(),
+                },
+                TxOut {
+                    value: 25_000,
+                    script_pubkey: Script::new(),
+                },
+            ],
+        };
+
+        PSBT::from_unsigned_tx(tx).unwrap()
+    }
+
+    fn get_test_metadata() -> BdkMetadata {
+        let mut path = BTreeMap::new();
+        path.insert("aabbccdd".to_string(), vec![1]);
+
+        BdkMetadata {
+            version: Some("0.1.0".to_string()),
+            external_policy_path: Some(path),
+            internal_policy_path: None,
+            outputs: vec![None, Some((KeychainKind::Internal, 42))],
+        }
+    }
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let metadata = get_test_metadata();
+
+        let mut psbt = get_test_psbt();
+        metadata.add_to_psbt(&mut psbt);
+        assert_eq!(psbt.global.unknown.len(), 2);
+        assert!(psbt.outputs[0].unknown.is_empty());
+        assert_eq!(
+            psbt.outputs[1]
+                .unknown
+                .get(&proprietary_key(PSBT_BDK_OUT_KEYCHAIN, &[])),
+            Some(&vec![b'i', 42, 0, 0, 0])
+        );
+
+        let psbt: PSBT = deserialize(&serialize(&psbt)).unwrap();
+        assert_eq!(BdkMetadata::from_psbt(&psbt).unwrap(), metadata);
+    }
+
+    #[test]
+    fn test_metadata_keeps_other_fields() {
+        let other_key = raw::Key {
+            type_value: PSBT_PROPRIETARY,
+            key: b"\x05other\x00".to_vec(),
+        };
+
+        let mut psbt = get_test_psbt();
+        psbt.global.unknown.insert(other_key.clone(), vec![0x01]);
+
+        get_test_metadata().add_to_psbt(&mut psbt);
+        BdkMetadata::default().add_to_psbt(&mut psbt);
+
+        assert_eq!(psbt.global.unknown.len(), 1);
+        assert_eq!(psbt.global.unknown.get(&other_key), Some(&vec![0x01]));
+        assert_eq!(
+            BdkMetadata::from_psbt(&psbt).unwrap().outputs,
+            vec![None, None]
+        );
+    }
+
+    #[test]
+    fn test_metadata_no_fields() {
+        let metadata = BdkMetadata::from_psbt(&get_test_psbt()).unwrap();
+        assert_eq!(metadata.version, None);
+        assert_eq!(metadata.outputs, vec![None, None]);
+    }
+
+    #[test]
+    fn test_metadata_invalid_keychain() {
+        let mut psbt = get_test_psbt();
+        psbt.outputs[0].unknown.insert(
+            proprietary_key(PSBT_BDK_OUT_KEYCHAIN, &[]),
+            vec![b'x', 0, 0, 0, 0],
+        );
+
+        assert_eq!(
+            BdkMetadata::from_psbt(&psbt),
+            Err(MetadataError::InvalidKeychain(b'x'))
+        );
+    }
+
+    #[test]
+    fn test_metadata_invalid_field() {
+        let mut psbt = get_test_psbt();
+        psbt.global.unknown.insert(
+            proprietary_key(PSBT_BDK_GLOBAL_POLICY_PATH, b"e"),
+            vec![0x00],
+        );
+
+        assert_eq!(
+            BdkMetadata::from_psbt(&psbt),
+            Err(MetadataError::InvalidField(PSBT_BDK_GLOBAL_POLICY_PATH))
+        );
+    }
+}
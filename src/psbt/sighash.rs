@@ -0,0 +1,234 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Sighash computation
+//!
+//! This module computes the hash that is signed by the inputs of a PSBT, so that custom
+//! [`Signer`](crate::signer::Signer)s don't have to reimplement it. It supports legacy inputs
+//! (P2PKH and P2SH), with the [`Legacy`] context, and segwit v0 inputs, either native or wrapped
+//! in P2SH, with the [`Segwitv0`] context.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+//! # use bdk::psbt::sighash;
+//! # let psbt: PSBT = unimplemented!();
+//! let (hash, sighash_type) = sighash::sighash(&psbt, 0)?;
+//! println!("Input #0 has to sign {} with {:?}", hash, sighash_type);
+//! # Ok::<_, bdk::signer::SignerError>(())
+//! ```
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder as ScriptBuilder;
+use bitcoin::util::{bip143, psbt};
+use bitcoin::{Script, SigHash, SigHashType};
+
+use miniscript::{Legacy, Segwitv0};
+
+use crate::signer::SignerError;
+
+/// Compute the sighash of the input at `input_index`
+///
+/// The presence of the `witness_utxo` is used to tell whether the input spends a segwit output:
+/// in that case the [`Segwitv0`] algorithm from BIP143 is used, otherwise the [`Legacy`] one.
+pub fn sighash(
+    psbt: &psbt::PartiallySignedTransaction,
+    input_index: usize,
+) -> Result<(SigHash, SigHashType), SignerError> {
+    match psbt.inputs.get(input_index) {
+        Some(psbt_input) if psbt_input.witness_utxo.is_some() => {
+            Segwitv0::sighash(psbt, input_index)
+        }
+        Some(_) => Legacy::sighash(psbt, input_index),
+        None => Err(SignerError::InputIndexOutOfRange),
+    }
+}
+
+/// Trait to compute the sighash of a PSBT input for a specific [`ScriptContext`](miniscript::ScriptContext)
+pub trait ComputeSighash {
+    /// Compute the sighash of the input at `input_index`, returning it together with the sighash
+    /// type taken from the input
+    fn sighash(
+        psbt: &psbt::PartiallySignedTransaction,
+        input_index: usize,
+    ) -> Result<(SigHash, SigHashType), SignerError>;
+}
+
+impl ComputeSighash for Legacy {
+    fn sighash(
+        psbt: &psbt::PartiallySignedTransaction,
+        input_index: usize,
+    ) -> Result<(SigHash, SigHashType), SignerError> {
+        if input_index >= psbt.inputs.len() {
+            return Err(SignerError::InputIndexOutOfRange);
+        }
+
+        let psbt_input = &psbt.inputs[input_index];
+        let tx_input = &psbt.global.unsigned_tx.input[input_index];
+
+        let sighash = psbt_input.sighash_type.unwrap_or(SigHashType::All);
+        let script = match psbt_input.redeem_script {
+            Some(ref redeem_script) => redeem_script.clone(),
+            None => {
+                let non_witness_utxo = psbt_input
+                    .non_witness_utxo
+                    .as_ref()
+                    .ok_or(SignerError::MissingNonWitnessUtxo)?;
+                let prev_out = non_witness_utxo
+                    .output
+                    .get(tx_input.previous_output.vout as usize)
+                    .ok_or(SignerError::InvalidNonWitnessUtxo)?;
+
+                prev_out.script_pubkey.clone()
+            }
+        };
+
+        Ok((
+            psbt.global
+                .unsigned_tx
+                .signature_hash(input_index, &script, sighash.as_u32()),
+            sighash,
+        ))
+    }
+}
+
+fn p2wpkh_script_code(script: &Script) -> Script {
+    ScriptBuilder::new()
+        .push_opcode(opcodes::all::OP_DUP)
+        .push_opcode(opcodes::all::OP_HASH160)
+        .push_slice(&script[2..])
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+        .into_script()
+}
+
+impl ComputeSighash for Segwitv0 {
+    fn sighash(
+        psbt: &psbt::PartiallySignedTransaction,
+        input_index: usize,
+    ) -> Result<(SigHash, SigHashType), SignerError> {
+        if input_index >= psbt.inputs.len() {
+            return Err(SignerError::InputIndexOutOfRange);
+        }
+
+        let psbt_input = &psbt.inputs[input_index];
+
+        let sighash = psbt_input.sighash_type.unwrap_or(SigHashType::All);
+
+        let witness_utxo = psbt_input
+            .witness_utxo
+            .as_ref()
+            .ok_or(SignerError::MissingNonWitnessUtxo)?;
+        let value = witness_utxo.value;
+
+        let script = match psbt_input.witness_script {
+            Some(ref witness_script) => witness_script.clone(),
+            None => {
+                if witness_utxo.script_pubkey.is_v0_p2wpkh() {
+                    p2wpkh_script_code(&witness_utxo.script_pubkey)
+                } else if psbt_input
+                    .redeem_script
+                    .as_ref()
+                    .map(Script::is_v0_p2wpkh)
+                    .unwrap_or(false)
+                {
+                    p2wpkh_script_code(psbt_input.redeem_script.as_ref().unwrap())
+                } else {
+                    return Err(SignerError::MissingWitnessScript);
+                }
+            }
+        };
+
+        Ok((
+            bip143::SigHashCache::new(&psbt.global.unsigned_tx).signature_hash(
+                input_index,
+                &script,
+                value,
+                sighash,
+            ),
+            sighash,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{Message, Signature};
+    use bitcoin::Address;
+
+    use super::*;
+    use crate::signer::SignOptions;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::TxBuilder;
+
+    #[test]
+    fn test_sighash_matches_signature() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        let (hash, sighash_type) = sighash(&psbt, 0).unwrap();
+        assert_eq!(sighash_type, SigHashType::All);
+
+        let sign_options = SignOptions {
+            try_finalize: false,
+            ..Default::default()
+        };
+        let (psbt, _) = wallet.sign(psbt, sign_options).unwrap();
+        let (public_key, signature) = psbt.inputs[0].partial_sigs.iter().next().unwrap();
+        let signature = Signature::from_der(&signature[..signature.len() - 1]).unwrap();
+        let message = Message::from_slice(&hash.into_inner()).unwrap();
+        assert!(wallet
+            .secp_ctx()
+            .verify(&message, &signature, &public_key.key)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_sighash_missing_data() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (mut psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(sighash(&psbt, 1), Err(SignerError::InputIndexOutOfRange));
+
+        psbt.inputs[0].witness_utxo = None;
+        psbt.inputs[0].non_witness_utxo = None;
+        assert_eq!(sighash(&psbt, 0), Err(SignerError::MissingNonWitnessUtxo));
+    }
+}
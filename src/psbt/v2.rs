@@ -0,0 +1,654 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! PSBT version 2
+//!
+//! This module implements [BIP370](https://github.com/bitcoin/bips/blob/master/bip-0370.mediawiki).
+//! Version 2 PSBTs don't contain the unsigned transaction: every input carries its own outpoint
+//! and sequence, and every output its own amount and script, so that inputs and outputs can be
+//! added after the PSBT has been created.
+//!
+//! The [`Wallet`](crate::Wallet) works with version 0 PSBTs: they can be converted to
+//! version 2 with [`PSBTv2::from`] and back with [`PSBTv2::into_v0`]. [`PSBTv2`] implements
+//! [`Encodable`] and [`Decodable`], so it can be serialized with the same functions used for
+//! version 0 PSBTs.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+//! use bitcoin::consensus::{deserialize, serialize};
+//! use bdk::psbt::v2::PSBTv2;
+//!
+//! # let psbt: PSBT = unimplemented!();
+//! // send a version 2 PSBT to the coordinator
+//! let bytes = serialize(&PSBTv2::from(psbt));
+//!
+//! // and convert the one we get back to version 0 to sign it
+//! let psbt_v2: PSBTv2 = deserialize(&bytes)?;
+//! let psbt = psbt_v2.into_v0()?;
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+
+use bitcoin::consensus::encode::{self, deserialize, serialize, VarInt};
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::util::psbt::{self, raw, PartiallySignedTransaction as PSBT};
+use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut, Txid};
+
+const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const PSBT_GLOBAL_TX_VERSION: u8 = 0x02;
+const PSBT_GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const PSBT_GLOBAL_INPUT_COUNT: u8 = 0x04;
+const PSBT_GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const PSBT_GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const PSBT_GLOBAL_VERSION: u8 = 0xFB;
+
+const PSBT_IN_PREVIOUS_TXID: u8 = 0x0E;
+const PSBT_IN_OUTPUT_INDEX: u8 = 0x0F;
+const PSBT_IN_SEQUENCE: u8 = 0x10;
+const PSBT_IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const PSBT_IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+
+const PSBT_OUT_AMOUNT: u8 = 0x03;
+const PSBT_OUT_SCRIPT: u8 = 0x04;
+
+// Locktimes lower than this are block heights, the others are timestamps
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Flag of [`PSBTv2::tx_modifiable`] set when inputs can be added or removed
+pub const INPUTS_MODIFIABLE: u8 = 0x01;
+/// Flag of [`PSBTv2::tx_modifiable`] set when outputs can be added or removed
+pub const OUTPUTS_MODIFIABLE: u8 = 0x02;
+/// Flag of [`PSBTv2::tx_modifiable`] set when an input is signed with `SIGHASH_SINGLE`, so the
+/// number of inputs and outputs must be kept in sync
+pub const HAS_SIGHASH_SINGLE: u8 = 0x04;
+
+/// Errors that can happen while working with version 2 PSBTs
+#[derive(Debug)]
+pub enum PSBTv2Error {
+    /// The flags of the PSBT don't allow adding inputs
+    InputsNotModifiable,
+    /// The flags of the PSBT don't allow adding outputs
+    OutputsNotModifiable,
+    /// The inputs require both a height-based and a time-based locktime, so the locktime of the
+    /// transaction can't be determined
+    IncompatibleLocktimes,
+    /// PSBT error
+    PSBT(psbt::Error),
+}
+
+impl fmt::Display for PSBTv2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for PSBTv2Error {}
+
+impl_error!(psbt::Error, PSBT, PSBTv2Error);
+
+/// An input of a version 2 PSBT
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputV2 {
+    /// Output spent by the input
+    pub previous_output: OutPoint,
+    /// Sequence number of the input, `0xFFFFFFFF` if not set
+    pub sequence: Option<u32>,
+    /// Minimum time-based locktime required by the input
+    pub required_time_locktime: Option<u32>,
+    /// Minimum height-based locktime required by the input
+    pub required_height_locktime: Option<u32>,
+    /// Fields shared with version 0 PSBTs, like the UTXO and the signatures
+    pub psbt_input: psbt::Input,
+}
+
+impl InputV2 {
+    /// Create an input that spends `previous_output`
+    pub fn new(previous_output: OutPoint) -> Self {
+        InputV2 {
+            previous_output,
+            sequence: None,
+            required_time_locktime: None,
+            required_height_locktime: None,
+            psbt_input: Default::default(),
+        }
+    }
+}
+
+/// An output of a version 2 PSBT
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputV2 {
+    /// Value of the output, in satoshi
+    pub amount: u64,
+    /// Script of the output
+    pub script_pubkey: Script,
+    /// Fields shared with version 0 PSBTs, like the key paths
+    pub psbt_output: psbt::Output,
+}
+
+impl OutputV2 {
+    /// Create an output that pays `amount` to `script_pubkey`
+    pub fn new(amount: u64, script_pubkey: Script) -> Self {
+        OutputV2 {
+            amount,
+            script_pubkey,
+            psbt_output: Default::default(),
+        }
+    }
+}
+
+/// A version 2 PSBT
+#[derive(Debug, Clone, PartialEq)]
+pub struct PSBTv2 {
+    /// Version of the transaction
+    pub tx_version: u32,
+    /// Locktime of the transaction when no input requires a specific one
+    pub fallback_locktime: Option<u32>,
+    /// Combination of [`INPUTS_MODIFIABLE`], [`OUTPUTS_MODIFIABLE`] and [`HAS_SIGHASH_SINGLE`]
+    pub tx_modifiable: Option<u8>,
+    /// Inputs of the transaction
+    pub inputs: Vec<InputV2>,
+    /// Outputs of the transaction
+    pub outputs: Vec<OutputV2>,
+    /// Other global fields, like the extended public keys
+    pub unknown: BTreeMap<raw::Key, Vec<u8>>,
+}
+
+impl PSBTv2 {
+    /// Create an empty PSBT, that allows adding inputs and outputs
+    pub fn new(tx_version: u32) -> Self {
+        PSBTv2 {
+            tx_version,
+            fallback_locktime: None,
+            tx_modifiable: Some(INPUTS_MODIFIABLE | OUTPUTS_MODIFIABLE),
+            inputs: vec![],
+            outputs: vec![],
+            unknown: BTreeMap::new(),
+        }
+    }
+
+    /// Add an input, if allowed by [`PSBTv2::tx_modifiable`]
+    ///
+    /// The input is rejected if its locktime requirements are incompatible with the ones of the
+    /// other inputs.
+    pub fn add_input(&mut self, input: InputV2) -> Result<(), PSBTv2Error> {
+        if self.tx_modifiable.unwrap_or(0) & INPUTS_MODIFIABLE == 0 {
+            return Err(PSBTv2Error::InputsNotModifiable);
+        }
+
+        self.inputs.push(input);
+        if self.locktime().is_none() {
+            self.inputs.pop();
+            return Err(PSBTv2Error::IncompatibleLocktimes);
+        }
+
+        Ok(())
+    }
+
+    /// Add an output, if allowed by [`PSBTv2::tx_modifiable`]
+    pub fn add_output(&mut self, output: OutputV2) -> Result<(), PSBTv2Error> {
+        if self.tx_modifiable.unwrap_or(0) & OUTPUTS_MODIFIABLE == 0 {
+            return Err(PSBTv2Error::OutputsNotModifiable);
+        }
+
+        self.outputs.push(output);
+        Ok(())
+    }
+
+    /// Return the locktime of the transaction, following the rules of BIP370
+    ///
+    /// If no input requires a locktime the fallback one is used, or `0` if that's not set either.
+    /// Otherwise the highest locktime required by the inputs is used, preferring block heights
+    /// when every input supports them. Returns `None` if some inputs only support heights while
+    /// others only support timestamps.
+    pub fn locktime(&self) -> Option<u32> {
+        let constrained = self
+            .inputs
+            .iter()
+            .filter(|input| {
+                input.required_height_locktime.is_some() || input.required_time_locktime.is_some()
+            })
+            .collect::<Vec<_>>();
+
+        if constrained.is_empty() {
+            Some(self.fallback_locktime.unwrap_or(0))
+        } else if constrained
+            .iter()
+            .all(|input| input.required_height_locktime.is_some())
+        {
+            constrained
+                .iter()
+                .filter_map(|input| input.required_height_locktime)
+                .max()
+        } else if constrained
+            .iter()
+            .all(|input| input.required_time_locktime.is_some())
+        {
+            constrained
+                .iter()
+                .filter_map(|input| input.required_time_locktime)
+                .max()
+        } else {
+            None
+        }
+    }
+
+    /// Build the unsigned transaction described by the PSBT
+    pub fn unsigned_tx(&self) -> Result<Transaction, PSBTv2Error> {
+        Ok(Transaction {
+            version: self.tx_version as i32,
+            lock_time: self.locktime().ok_or(PSBTv2Error::IncompatibleLocktimes)?,
+            input: self
+                .inputs
+                .iter()
+                .map(|input| TxIn {
+                    previous_output: input.previous_output,
+                    script_sig: Script::new(),
+                    sequence: input.sequence.unwrap_or(0xFFFFFFFF),
+                    witness: vec![],
+                })
+                .collect(),
+            output: self
+                .outputs
+                .iter()
+                .map(|output| TxOut {
+                    value: output.amount,
+                    script_pubkey: output.script_pubkey.clone(),
+                })
+                .collect(),
+        })
+    }
+
+    /// Convert the PSBT to version 0
+    pub fn into_v0(self) -> Result<PSBT, PSBTv2Error> {
+        let mut psbt = PSBT::from_unsigned_tx(self.unsigned_tx()?)?;
+        psbt.global.unknown = self.unknown;
+        psbt.inputs = self
+            .inputs
+            .into_iter()
+            .map(|input| input.psbt_input)
+            .collect();
+        psbt.outputs = self
+            .outputs
+            .into_iter()
+            .map(|output| output.psbt_output)
+            .collect();
+
+        Ok(psbt)
+    }
+}
+
+impl From<PSBT> for PSBTv2 {
+    fn from(psbt: PSBT) -> Self {
+        let tx = psbt.global.unsigned_tx;
+
+        PSBTv2 {
+            tx_version: tx.version as u32,
+            fallback_locktime: Some(tx.lock_time),
+            tx_modifiable: None,
+            inputs: tx
+                .input
+                .into_iter()
+                .zip(psbt.inputs.into_iter())
+                .map(|(txin, psbt_input)| InputV2 {
+                    previous_output: txin.previous_output,
+                    sequence: Some(txin.sequence),
+                    required_time_locktime: None,
+                    required_height_locktime: None,
+                    psbt_input,
+                })
+                .collect(),
+            outputs: tx
+                .output
+                .into_iter()
+                .zip(psbt.outputs.into_iter())
+                .map(|(txout, psbt_output)| OutputV2 {
+                    amount: txout.value,
+                    script_pubkey: txout.script_pubkey,
+                    psbt_output,
+                })
+                .collect(),
+            unknown: psbt.global.unknown,
+        }
+    }
+}
+
+fn field_key(type_value: u8) -> raw::Key {
+    raw::Key {
+        type_value,
+        key: vec![],
+    }
+}
+
+// Remove a field from a map and decode its value
+fn take_field<T: Decodable>(
+    map: &mut BTreeMap<raw::Key, Vec<u8>>,
+    type_value: u8,
+) -> Result<Option<T>, encode::Error> {
+    map.remove(&field_key(type_value))
+        .map(|value| deserialize(&value))
+        .transpose()
+}
+
+impl Encodable for PSBTv2 {
+    fn consensus_encode<W: Write>(&self, mut e: W) -> Result<usize, encode::Error> {
+        let mut written = b"psbt".consensus_encode(&mut e)?;
+        written += 0xFFu8.consensus_encode(&mut e)?;
+
+        let mut global = self.unknown.clone();
+        global.insert(
+            field_key(PSBT_GLOBAL_TX_VERSION),
+            serialize(&self.tx_version),
+        );
+        if let Some(locktime) = self.fallback_locktime {
+            global.insert(
+                field_key(PSBT_GLOBAL_FALLBACK_LOCKTIME),
+                serialize(&locktime),
+            );
+        }
+        global.insert(
+            field_key(PSBT_GLOBAL_INPUT_COUNT),
+            serialize(&VarInt(self.inputs.len() as u64)),
+        );
+        global.insert(
+            field_key(PSBT_GLOBAL_OUTPUT_COUNT),
+            serialize(&VarInt(self.outputs.len() as u64)),
+        );
+        if let Some(flags) = self.tx_modifiable {
+            global.insert(field_key(PSBT_GLOBAL_TX_MODIFIABLE), serialize(&flags));
+        }
+        global.insert(field_key(PSBT_GLOBAL_VERSION), serialize(&2u32));
+
+        for (key, value) in global {
+            written += raw::Pair { key, value }.consensus_encode(&mut e)?;
+        }
+        written += 0x00u8.consensus_encode(&mut e)?;
+
+        // the new fields are encoded together with the ones shared with version 0
+        for input in &self.inputs {
+            let mut psbt_input = input.psbt_input.clone();
+            let fields = &mut psbt_input.unknown;
+            fields.insert(
+                field_key(PSBT_IN_PREVIOUS_TXID),
+                serialize(&input.previous_output.txid),
+            );
+            fields.insert(
+                field_key(PSBT_IN_OUTPUT_INDEX),
+                serialize(&input.previous_output.vout),
+            );
+            if let Some(sequence) = input.sequence {
+                fields.insert(field_key(PSBT_IN_SEQUENCE), serialize(&sequence));
+            }
+            if let Some(locktime) = input.required_time_locktime {
+                fields.insert(
+                    field_key(PSBT_IN_REQUIRED_TIME_LOCKTIME),
+                    serialize(&locktime),
+                );
+            }
+            if let Some(locktime) = input.required_height_locktime {
+                fields.insert(
+                    field_key(PSBT_IN_REQUIRED_HEIGHT_LOCKTIME),
+                    serialize(&locktime),
+                );
+            }
+
+            written += psbt_input.consensus_encode(&mut e)?;
+        }
+
+        for output in &self.outputs {
+            let mut psbt_output = output.psbt_output.clone();
+            let fields = &mut psbt_output.unknown;
+            fields.insert(field_key(PSBT_OUT_AMOUNT), serialize(&output.amount));
+            fields.insert(field_key(PSBT_OUT_SCRIPT), output.script_pubkey.to_bytes());
+
+            written += psbt_output.consensus_encode(&mut e)?;
+        }
+
+        Ok(written)
+    }
+}
+
+impl Decodable for PSBTv2 {
+    fn consensus_decode<D: Read>(mut d: D) -> Result<Self, encode::Error> {
+        let magic: [u8; 4] = Decodable::consensus_decode(&mut d)?;
+        if &magic != b"psbt" {
+            return Err(psbt::Error::InvalidMagic.into());
+        }
+        if u8::consensus_decode(&mut d)? != 0xFF {
+            return Err(psbt::Error::InvalidSeparator.into());
+        }
+
+        let mut global = BTreeMap::new();
+        loop {
+            match raw::Pair::consensus_decode(&mut d) {
+                Ok(pair) if global.contains_key(&pair.key) => {
+                    return Err(psbt::Error::DuplicateKey(pair.key).into())
+                }
+                Ok(pair) => {
+                    global.insert(pair.key, pair.value);
+                }
+                Err(encode::Error::Psbt(psbt::Error::NoMorePairs)) => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if take_field::<u32>(&mut global, PSBT_GLOBAL_VERSION)? != Some(2) {
+            return Err(encode::Error::ParseFailed("Not a version 2 PSBT"));
+        }
+        if let Some((key, _)) = global.remove_entry(&field_key(PSBT_GLOBAL_UNSIGNED_TX)) {
+            return Err(psbt::Error::InvalidKey(key).into());
+        }
+        let missing = |field| encode::Error::ParseFailed(field);
+        let tx_version = take_field(&mut global, PSBT_GLOBAL_TX_VERSION)?
+            .ok_or_else(|| missing("Missing PSBT_GLOBAL_TX_VERSION"))?;
+        let fallback_locktime = take_field(&mut global, PSBT_GLOBAL_FALLBACK_LOCKTIME)?;
+        let VarInt(input_count) = take_field(&mut global, PSBT_GLOBAL_INPUT_COUNT)?
+            .ok_or_else(|| missing("Missing PSBT_GLOBAL_INPUT_COUNT"))?;
+        let VarInt(output_count) = take_field(&mut global, PSBT_GLOBAL_OUTPUT_COUNT)?
+            .ok_or_else(|| missing("Missing PSBT_GLOBAL_OUTPUT_COUNT"))?;
+        let tx_modifiable = take_field(&mut global, PSBT_GLOBAL_TX_MODIFIABLE)?;
+
+        let mut inputs = vec![];
+        for _ in 0..input_count {
+            let mut psbt_input: psbt::Input = Decodable::consensus_decode(&mut d)?;
+            let fields = &mut psbt_input.unknown;
+
+            let txid: Txid = take_field(fields, PSBT_IN_PREVIOUS_TXID)?
+                .ok_or_else(|| missing("Missing PSBT_IN_PREVIOUS_TXID"))?;
+            let vout = take_field(fields, PSBT_IN_OUTPUT_INDEX)?
+                .ok_or_else(|| missing("Missing PSBT_IN_OUTPUT_INDEX"))?;
+            let sequence = take_field(fields, PSBT_IN_SEQUENCE)?;
+            let required_time_locktime: Option<u32> =
+                take_field(fields, PSBT_IN_REQUIRED_TIME_LOCKTIME)?;
+            let required_height_locktime: Option<u32> =
+                take_field(fields, PSBT_IN_REQUIRED_HEIGHT_LOCKTIME)?;
+
+            if required_time_locktime.map_or(false, |locktime| locktime < LOCKTIME_THRESHOLD) {
+                return Err(encode::Error::ParseFailed(
+                    "Invalid PSBT_IN_REQUIRED_TIME_LOCKTIME",
+                ));
+            }
+            if required_height_locktime.map_or(false, |locktime| locktime >= LOCKTIME_THRESHOLD) {
+                return Err(encode::Error::ParseFailed(
+                    "Invalid PSBT_IN_REQUIRED_HEIGHT_LOCKTIME",
+                ));
+            }
+
+            inputs.push(InputV2 {
+                previous_output: OutPoint { txid, vout },
+                sequence,
+                required_time_locktime,
+                required_height_locktime,
+                psbt_input,
+            });
+        }
+
+        let mut outputs = vec![];
+        for _ in 0..output_count {
+            let mut psbt_output: psbt::Output = Decodable::consensus_decode(&mut d)?;
+            let fields = &mut psbt_output.unknown;
+
+            let amount = take_field(fields, PSBT_OUT_AMOUNT)?
+                .ok_or_else(|| missing("Missing PSBT_OUT_AMOUNT"))?;
+            let script_pubkey = fields
+                .remove(&field_key(PSBT_OUT_SCRIPT))
+                .map(Script::from)
+                .ok_or_else(|| missing("Missing PSBT_OUT_SCRIPT"))?;
+
+            outputs.push(OutputV2 {
+                amount,
+                script_pubkey,
+                psbt_output,
+            });
+        }
+
+        Ok(PSBTv2 {
+            tx_version,
+            fallback_locktime,
+            tx_modifiable,
+            inputs,
+            outputs,
+            unknown: global,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::consensus::deserialize;
+    use bitcoin::util::bip32;
+    use bitcoin::PublicKey;
+
+    use super::*;
+
+    fn get_test_psbt() -> PSBTv2 {
+        let mut psbt = PSBTv2::new(2);
+        let mut input = InputV2::new(
+            OutPoint::from_str(
+                "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+            )
+            .unwrap(),
+        );
+        input.sequence = Some(0xFFFFFFFD);
+        input.psbt_input.witness_utxo = Some(TxOut {
+            value: 60_000,
+            script_pubkey: Script::from(vec![0x00; 22]),
+        });
+        input.psbt_input.hd_keypaths.insert(
+            PublicKey::from_str(
+                "02b4632d08485ff1df2db55b9dafd23347d1c47a457072a1e87be26896549a8737",
+            )
+            .unwrap(),
+            (
+                bip32::Fingerprint::from_str("0f056943").unwrap(),
+                bip32::DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap(),
+            ),
+        );
+        psbt.add_input(input).unwrap();
+        psbt.add_output(OutputV2::new(50_000, Script::from(vec![0x00; 34])))
+            .unwrap();
+
+        psbt
+    }
+
+    #[test]
+    fn test_psbt_v2_serialization() {
+        let psbt = get_test_psbt();
+        let bytes = serialize(&psbt);
+        assert_eq!(&bytes[..5], b"psbt\xff");
+
+        let decoded: PSBTv2 = deserialize(&bytes).unwrap();
+        assert_eq!(decoded, psbt);
+    }
+
+    #[test]
+    fn test_psbt_v2_conversion() {
+        let mut psbt = get_test_psbt();
+        psbt.fallback_locktime = Some(100_000);
+
+        let v0 = psbt.clone().into_v0().unwrap();
+        let tx = &v0.global.unsigned_tx;
+        assert_eq!(tx.lock_time, 100_000);
+        assert_eq!(tx.input[0].sequence, 0xFFFFFFFD);
+        assert_eq!(tx.output[0].value, 50_000);
+        assert_eq!(v0.inputs[0], psbt.inputs[0].psbt_input);
+
+        // the version 0 PSBT can't be decoded as a version 2 one
+        assert!(deserialize::<PSBTv2>(&serialize(&v0)).is_err());
+
+        let v2 = PSBTv2::from(v0.clone());
+        assert_eq!(v2.into_v0().unwrap(), v0);
+    }
+
+    #[test]
+    fn test_psbt_v2_locktime() {
+        let mut psbt = get_test_psbt();
+        assert_eq!(psbt.locktime(), Some(0));
+
+        let mut input = InputV2::new(OutPoint::default());
+        input.required_height_locktime = Some(100_000);
+        input.required_time_locktime = Some(1_600_000_000);
+        psbt.add_input(input).unwrap();
+        assert_eq!(psbt.locktime(), Some(100_000));
+
+        let mut input = InputV2::new(OutPoint::default());
+        input.required_time_locktime = Some(1_700_000_000);
+        psbt.add_input(input).unwrap();
+        assert_eq!(psbt.locktime(), Some(1_700_000_000));
+
+        let mut input = InputV2::new(OutPoint::default());
+        input.required_height_locktime = Some(200_000);
+        assert!(matches!(
+            psbt.add_input(input),
+            Err(PSBTv2Error::IncompatibleLocktimes)
+        ));
+        assert_eq!(psbt.inputs.len(), 3);
+    }
+
+    #[test]
+    fn test_psbt_v2_modifiable() {
+        let mut psbt = get_test_psbt();
+        psbt.tx_modifiable = Some(OUTPUTS_MODIFIABLE);
+
+        assert!(matches!(
+            psbt.add_input(InputV2::new(OutPoint::default())),
+            Err(PSBTv2Error::InputsNotModifiable)
+        ));
+        assert!(psbt
+            .add_output(OutputV2::new(10_000, Script::new()))
+            .is_ok());
+
+        psbt.tx_modifiable = None;
+        assert!(matches!(
+            psbt.add_output(OutputV2::new(10_000, Script::new())),
+            Err(PSBTv2Error::OutputsNotModifiable)
+        ));
+    }
+}
@@ -23,12 +23,17 @@
 // SOFTWARE.
 
 use std::convert::AsRef;
+use std::fmt;
+use std::str::FromStr;
 
 use bitcoin::blockdata::transaction::{OutPoint, Transaction, TxOut};
 use bitcoin::hash_types::Txid;
+use bitcoin::Script;
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::Error;
+
 /// Types of keychains
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum KeychainKind {
@@ -57,6 +62,98 @@ impl AsRef<[u8]> for KeychainKind {
     }
 }
 
+impl fmt::Display for KeychainKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeychainKind::External => write!(f, "external"),
+            KeychainKind::Internal => write!(f, "internal"),
+        }
+    }
+}
+
+impl FromStr for KeychainKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "external" => Ok(KeychainKind::External),
+            "internal" => Ok(KeychainKind::Internal),
+            _ => Err(Error::Generic(format!("Unknown keychain kind `{}`", s))),
+        }
+    }
+}
+
+/// Type of the scripts generated by a descriptor
+///
+/// See [`Wallet::script_type`](crate::wallet::Wallet::script_type).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptType {
+    /// Bare script
+    Bare,
+    /// Pay to public key
+    P2pk,
+    /// Pay to public key hash
+    P2pkh,
+    /// Pay to script hash
+    P2sh,
+    /// Pay to witness public key hash
+    P2wpkh,
+    /// Pay to witness script hash
+    P2wsh,
+    /// Pay to witness public key hash, nested in pay to script hash
+    P2shP2wpkh,
+    /// Pay to witness script hash, nested in pay to script hash
+    P2shP2wsh,
+}
+
+impl ScriptType {
+    /// Return whether the scripts spend with a witness
+    pub fn is_witness(&self) -> bool {
+        match self {
+            ScriptType::Bare | ScriptType::P2pk | ScriptType::P2pkh | ScriptType::P2sh => false,
+            ScriptType::P2wpkh
+            | ScriptType::P2wsh
+            | ScriptType::P2shP2wpkh
+            | ScriptType::P2shP2wsh => true,
+        }
+    }
+}
+
+impl fmt::Display for ScriptType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ScriptType::Bare => "bare",
+            ScriptType::P2pk => "p2pk",
+            ScriptType::P2pkh => "p2pkh",
+            ScriptType::P2sh => "p2sh",
+            ScriptType::P2wpkh => "p2wpkh",
+            ScriptType::P2wsh => "p2wsh",
+            ScriptType::P2shP2wpkh => "p2sh-p2wpkh",
+            ScriptType::P2shP2wsh => "p2sh-p2wsh",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ScriptType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bare" => Ok(ScriptType::Bare),
+            "p2pk" => Ok(ScriptType::P2pk),
+            "p2pkh" => Ok(ScriptType::P2pkh),
+            "p2sh" => Ok(ScriptType::P2sh),
+            "p2wpkh" => Ok(ScriptType::P2wpkh),
+            "p2wsh" => Ok(ScriptType::P2wsh),
+            "p2sh-p2wpkh" => Ok(ScriptType::P2shP2wpkh),
+            "p2sh-p2wsh" => Ok(ScriptType::P2shP2wsh),
+            _ => Err(Error::Generic(format!("Unknown script type `{}`", s))),
+        }
+    }
+}
+
 /// Fee rate
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd)]
 // Internally stored as satoshi/vbyte
@@ -118,4 +215,119 @@ pub struct TransactionDetails {
     pub fees: u64,
     /// Confirmed in block height, `None` means unconfirmed
     pub height: Option<u32>,
+    /// Breakdown of the amounts sent to every recipient
+    ///
+    /// This is only filled for transactions built by [`Wallet::create_tx`] and
+    /// [`Wallet::bump_fee`], and it's empty for the ones found while syncing.
+    ///
+    /// [`Wallet::create_tx`]: crate::wallet::Wallet::create_tx
+    /// [`Wallet::bump_fee`]: crate::wallet::Wallet::bump_fee
+    #[serde(default)]
+    pub recipients: Vec<RecipientDetails>,
+}
+
+/// Amount sent to one of the recipients of a transaction
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecipientDetails {
+    /// Script of the recipient
+    pub script_pubkey: Script,
+    /// Amount (sats) to send before the fee is subtracted
+    ///
+    /// For a single recipient this is the total value of the transaction's inputs.
+    pub amount: u64,
+    /// Whether the fee was subtracted from the output of this recipient
+    pub fee_subtracted: bool,
+    /// Value (sats) of the output actually received by the recipient
+    pub effective_amount: u64,
+}
+
+/// Summary of the transaction that would be created by [`Wallet::create_tx`]
+///
+/// Returned by [`Wallet::preview_tx`]
+///
+/// [`Wallet::create_tx`]: crate::wallet::Wallet::create_tx
+/// [`Wallet::preview_tx`]: crate::wallet::Wallet::preview_tx
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TxPreview {
+    /// Fee value (sats)
+    pub fee: u64,
+    /// Total value of the inputs selected (sats)
+    pub input_value: u64,
+    /// Total value sent back to the wallet as change (sats)
+    pub change: u64,
+    /// Estimated virtual size of the signed transaction (vbytes)
+    pub vsize: usize,
+}
+
+/// Object a label can be attached to
+///
+/// See [`Wallet::set_label`](crate::wallet::Wallet::set_label).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LabelRef {
+    /// A transaction
+    Transaction(Txid),
+    /// An address, identified by its script_pubkey
+    Address(Script),
+    /// A transaction input, identified by the txid of the spending transaction and the index of
+    /// the input
+    Input(OutPoint),
+    /// A transaction output, like one of the wallet's [`UTXO`]s
+    Output(OutPoint),
+}
+
+/// Ownership of an output of a PSBT
+///
+/// Returned by [`Wallet::verify_psbt_outputs`](crate::wallet::Wallet::verify_psbt_outputs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputOwnership {
+    /// The output belongs to the wallet
+    Mine {
+        /// Keychain the output belongs to
+        keychain: KeychainKind,
+        /// Derivation index of the output
+        index: u32,
+    },
+    /// The output doesn't belong to the wallet
+    External,
+    /// The `hd_keypaths` of the output claim that it derives from one of the wallet's keys, but
+    /// its script doesn't match. This is most likely an attempt to disguise an external output as
+    /// change
+    InvalidDerivation,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keychain_kind_string_roundtrip() {
+        for keychain in &[KeychainKind::External, KeychainKind::Internal] {
+            assert_eq!(
+                KeychainKind::from_str(&keychain.to_string()).unwrap(),
+                *keychain
+            );
+        }
+        assert!(KeychainKind::from_str("change").is_err());
+    }
+
+    #[test]
+    fn test_script_type_string_roundtrip() {
+        for script_type in &[
+            ScriptType::Bare,
+            ScriptType::P2pk,
+            ScriptType::P2pkh,
+            ScriptType::P2sh,
+            ScriptType::P2wpkh,
+            ScriptType::P2wsh,
+            ScriptType::P2shP2wpkh,
+            ScriptType::P2shP2wsh,
+        ] {
+            assert_eq!(
+                ScriptType::from_str(&script_type.to_string()).unwrap(),
+                *script_type
+            );
+        }
+        assert_eq!(ScriptType::P2shP2wpkh.to_string(), "p2sh-p2wpkh");
+        assert!(ScriptType::from_str("p2tr").is_err());
+    }
 }
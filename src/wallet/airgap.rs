@@ -0,0 +1,978 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Air-gapped PSBT transport
+//!
+//! This module implements two ways of moving a PSBT to and from an air-gapped signing device:
+//!
+//! * As [Uniform Resources](https://github.com/BlockchainCommons/Research/blob/master/papers/bcr-2020-005-ur.md)
+//!   of type `crypto-psbt`, usually displayed as an animated sequence of QR codes. Large PSBTs are
+//!   split with a fountain code, so the receiver can rebuild them from enough parts received in any
+//!   order, even if some of them are missed.
+//! * As a sequence of files of limited size, for devices that can only read small files.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+//! # use bdk::wallet::airgap::*;
+//! # fn show_qr(_: &str) {}
+//! # fn scan_qr() -> String { unimplemented!() }
+//! fn round_trip(psbt: &PSBT) -> Result<PSBT, AirgapError> {
+//!     let mut encoder = UrEncoder::new(psbt, 200)?;
+//!     for _ in 0..encoder.seq_len() {
+//!         show_qr(&encoder.next_part());
+//!     }
+//!
+//!     let mut decoder = UrDecoder::new();
+//!     loop {
+//!         if let Some(signed_psbt) = decoder.receive_part(&scan_qr())? {
+//!             return Ok(signed_psbt);
+//!         }
+//!         println!("Progress: {:.0}%", decoder.progress() * 100.0);
+//!     }
+//! }
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use bitcoin::consensus::encode::{self, deserialize, serialize};
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+
+/// Type of the Uniform Resources that contain a PSBT
+pub const UR_TYPE_PSBT: &str = "crypto-psbt";
+
+/// Minimum length of the fragments of a multi-part UR
+pub const MIN_FRAGMENT_LEN: usize = 10;
+
+/// Maximum number of fragments of a multi-part UR
+///
+/// Every part received by a [`UrDecoder`] takes a time proportional to the number of fragments,
+/// so larger messages are refused.
+pub const MAX_SEQ_LEN: usize = 10_000;
+
+/// Errors thrown while encoding or decoding a PSBT for an air-gapped transport
+#[derive(Debug)]
+pub enum AirgapError {
+    /// The maximum length of the fragments or files is too small
+    InvalidMaxLength(usize),
+    /// The string is not a valid UR
+    InvalidUr,
+    /// The type of the UR is not [`UR_TYPE_PSBT`]
+    UnsupportedType(String),
+    /// A byteword is not valid
+    InvalidByteword,
+    /// The checksum of a part or of the reassembled message doesn't match
+    InvalidChecksum,
+    /// The CBOR payload is malformed
+    InvalidCbor,
+    /// The part doesn't belong to the same message as the ones received before
+    InconsistentPart,
+    /// The message is split in more than [`MAX_SEQ_LEN`] fragments
+    TooManyParts(usize),
+    /// The reassembled PSBT can't be deserialized
+    Psbt(encode::Error),
+}
+
+impl fmt::Display for AirgapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for AirgapError {}
+
+/// Encode `psbt` as a single-part UR
+///
+/// This is only practical for small PSBTs: larger ones should be split with a [`UrEncoder`].
+pub fn encode_ur(psbt: &PSBT) -> String {
+    format!(
+        "ur:{}/{}",
+        UR_TYPE_PSBT,
+        bytewords_encode(&psbt_to_cbor(psbt))
+    )
+}
+
+/// Decode a PSBT from a single-part UR
+///
+/// Multi-part URs have to be decoded with a [`UrDecoder`].
+pub fn decode_ur(ur: &str) -> Result<PSBT, AirgapError> {
+    match parse_ur(ur)? {
+        UrPayload::Single(message) => psbt_from_cbor(&message),
+        UrPayload::Multi { .. } => Err(AirgapError::InvalidUr),
+    }
+}
+
+/// Fountain encoder that splits a PSBT into a sequence of multi-part URs
+///
+/// The first [`seq_len`](UrEncoder::seq_len) parts contain the fragments of the PSBT, and the
+/// following ones contain random combinations of them, so that a receiver that missed some
+/// parts can still rebuild the PSBT by keeping on scanning.
+#[derive(Debug, Clone)]
+pub struct UrEncoder {
+    message_len: usize,
+    checksum: u32,
+    fragments: Vec<Vec<u8>>,
+    seq_num: u32,
+}
+
+impl UrEncoder {
+    /// Create an encoder for `psbt`, with fragments of at most `max_fragment_len` bytes
+    pub fn new(psbt: &PSBT, max_fragment_len: usize) -> Result<Self, AirgapError> {
+        if max_fragment_len < MIN_FRAGMENT_LEN {
+            return Err(AirgapError::InvalidMaxLength(max_fragment_len));
+        }
+
+        UrEncoder::from_message(psbt_to_cbor(psbt), max_fragment_len)
+    }
+
+    fn from_message(mut message: Vec<u8>, max_fragment_len: usize) -> Result<Self, AirgapError> {
+        let message_len = message.len();
+        let checksum = crc32(&message);
+
+        let fragment_len = nominal_fragment_len(message_len, max_fragment_len);
+        let seq_len = (message_len + fragment_len - 1) / fragment_len;
+        if seq_len > MAX_SEQ_LEN {
+            return Err(AirgapError::InvalidMaxLength(max_fragment_len));
+        }
+        message.resize(seq_len * fragment_len, 0);
+
+        Ok(UrEncoder {
+            message_len,
+            checksum,
+            fragments: message.chunks(fragment_len).map(<[u8]>::to_vec).collect(),
+            seq_num: 0,
+        })
+    }
+
+    /// Return the number of fragments of the PSBT
+    pub fn seq_len(&self) -> usize {
+        self.fragments.len()
+    }
+
+    /// Return whether the PSBT fits in a single part
+    pub fn is_single_part(&self) -> bool {
+        self.seq_len() == 1
+    }
+
+    /// Return the next part
+    ///
+    /// If the PSBT fits in a single part the same single-part UR is always returned.
+    pub fn next_part(&mut self) -> String {
+        if self.is_single_part() {
+            return format!(
+                "ur:{}/{}",
+                UR_TYPE_PSBT,
+                bytewords_encode(&self.fragments[0])
+            );
+        }
+
+        self.seq_num += 1;
+
+        let mut data = vec![0; self.fragments[0].len()];
+        for index in choose_fragments(self.seq_num, self.seq_len(), self.checksum) {
+            xor_into(&mut data, &self.fragments[index]);
+        }
+
+        let part = Part {
+            seq_num: self.seq_num,
+            seq_len: self.seq_len(),
+            message_len: self.message_len,
+            checksum: self.checksum,
+            data,
+        };
+        format!(
+            "ur:{}/{}-{}/{}",
+            UR_TYPE_PSBT,
+            part.seq_num,
+            part.seq_len,
+            bytewords_encode(&part.to_cbor())
+        )
+    }
+}
+
+/// Fountain decoder that rebuilds a PSBT from the parts created by a [`UrEncoder`]
+///
+/// Single-part URs are also accepted.
+#[derive(Debug, Default)]
+pub struct UrDecoder {
+    expected: Option<(usize, usize, u32, usize)>,
+    fragments: BTreeMap<usize, Vec<u8>>,
+    mixed: Vec<(BTreeSet<usize>, Vec<u8>)>,
+}
+
+impl UrDecoder {
+    /// Create an empty decoder
+    pub fn new() -> Self {
+        UrDecoder::default()
+    }
+
+    /// Receive a part, returning the PSBT once enough parts have been received
+    ///
+    /// Parts can be received in any order, and duplicates are ignored.
+    pub fn receive_part(&mut self, ur: &str) -> Result<Option<PSBT>, AirgapError> {
+        match self.receive_message_part(ur)? {
+            Some(message) => psbt_from_cbor(&message).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Receive a part, returning the reassembled message once complete
+    fn receive_message_part(&mut self, ur: &str) -> Result<Option<Vec<u8>>, AirgapError> {
+        let (seq_num, seq_len, data) = match parse_ur(ur)? {
+            UrPayload::Single(message) => return Ok(Some(message)),
+            UrPayload::Multi {
+                seq_num,
+                seq_len,
+                data,
+            } => (seq_num, seq_len, data),
+        };
+
+        if seq_len > MAX_SEQ_LEN {
+            return Err(AirgapError::TooManyParts(seq_len));
+        }
+
+        let part = Part::from_cbor(&data)?;
+        if part.seq_num != seq_num || part.seq_len != seq_len {
+            return Err(AirgapError::InconsistentPart);
+        }
+
+        let (seq_len, message_len, checksum) = (part.seq_len, part.message_len, part.checksum);
+        let part_info = (seq_len, message_len, checksum, part.data.len());
+        match self.expected {
+            Some(expected) if expected != part_info => return Err(AirgapError::InconsistentPart),
+            Some(_) => {}
+            // the encoder uses the minimum number of fragments of this length
+            None if (message_len + part.data.len() - 1) / part.data.len() != seq_len => {
+                return Err(AirgapError::InvalidCbor)
+            }
+            None => self.expected = Some(part_info),
+        }
+
+        self.add_part(choose_fragments(part.seq_num, seq_len, checksum), part.data);
+        if self.fragments.len() < seq_len {
+            return Ok(None);
+        }
+
+        let mut message = self
+            .fragments
+            .values()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>();
+        message.truncate(message_len);
+        if crc32(&message) != checksum {
+            return Err(AirgapError::InvalidChecksum);
+        }
+
+        Ok(Some(message))
+    }
+
+    /// Return the fraction of the fragments that have been recovered so far, between `0.0` and `1.0`
+    pub fn progress(&self) -> f32 {
+        match self.expected {
+            Some((seq_len, _, _, _)) => self.fragments.len() as f32 / seq_len as f32,
+            None => 0.0,
+        }
+    }
+
+    fn add_part(&mut self, mut indexes: BTreeSet<usize>, mut data: Vec<u8>) {
+        // remove the fragments we already know from the part
+        for index in indexes.clone() {
+            if let Some(fragment) = self.fragments.get(&index) {
+                xor_into(&mut data, fragment);
+                indexes.remove(&index);
+            }
+        }
+
+        match indexes.len() {
+            0 => {}
+            1 => {
+                self.fragments.insert(*indexes.iter().next().unwrap(), data);
+
+                // the new fragment may allow reducing the mixed parts we have stored
+                for (indexes, data) in std::mem::take(&mut self.mixed) {
+                    self.add_part(indexes, data);
+                }
+            }
+            _ => {
+                if !self.mixed.iter().any(|(other, _)| other == &indexes) {
+                    self.mixed.push((indexes, data));
+                }
+            }
+        }
+    }
+}
+
+/// Split the serialized `psbt` in chunks of at most `max_len` bytes, to be saved as separate files
+pub fn split_psbt(psbt: &PSBT, max_len: usize) -> Result<Vec<Vec<u8>>, AirgapError> {
+    if max_len == 0 {
+        return Err(AirgapError::InvalidMaxLength(max_len));
+    }
+
+    Ok(serialize(psbt)
+        .chunks(max_len)
+        .map(<[u8]>::to_vec)
+        .collect())
+}
+
+/// Rebuild a PSBT from the chunks returned by [`split_psbt`], which must be in the same order
+pub fn join_psbt<I, T>(chunks: I) -> Result<PSBT, AirgapError>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let data = chunks
+        .into_iter()
+        .flat_map(|chunk| chunk.as_ref().to_vec())
+        .collect::<Vec<_>>();
+
+    deserialize(&data).map_err(AirgapError::Psbt)
+}
+
+enum UrPayload {
+    Single(Vec<u8>),
+    Multi {
+        seq_num: u32,
+        seq_len: usize,
+        data: Vec<u8>,
+    },
+}
+
+fn parse_ur(ur: &str) -> Result<UrPayload, AirgapError> {
+    // QR codes in alphanumeric mode are upper-case
+    let ur = ur.trim().to_lowercase();
+    let components = ur
+        .strip_prefix("ur:")
+        .ok_or(AirgapError::InvalidUr)?
+        .split('/')
+        .collect::<Vec<_>>();
+
+    let ur_type = components[0];
+    if ur_type != UR_TYPE_PSBT {
+        return Err(AirgapError::UnsupportedType(ur_type.to_string()));
+    }
+
+    match components.as_slice() {
+        [_, payload] => Ok(UrPayload::Single(bytewords_decode(payload)?)),
+        [_, seq, payload] => {
+            let mut seq = seq.splitn(2, '-');
+            let mut parse_next = || {
+                seq.next()
+                    .and_then(|n| n.parse::<u32>().ok())
+                    .ok_or(AirgapError::InvalidUr)
+            };
+            let seq_num = parse_next()?;
+            let seq_len = parse_next()? as usize;
+
+            Ok(UrPayload::Multi {
+                seq_num,
+                seq_len,
+                data: bytewords_decode(payload)?,
+            })
+        }
+        _ => Err(AirgapError::InvalidUr),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Part {
+    seq_num: u32,
+    seq_len: usize,
+    message_len: usize,
+    checksum: u32,
+    data: Vec<u8>,
+}
+
+impl Part {
+    fn to_cbor(&self) -> Vec<u8> {
+        let mut cbor = Vec::new();
+        cbor_write_header(&mut cbor, CBOR_ARRAY, 5);
+        cbor_write_header(&mut cbor, CBOR_UINT, self.seq_num as u64);
+        cbor_write_header(&mut cbor, CBOR_UINT, self.seq_len as u64);
+        cbor_write_header(&mut cbor, CBOR_UINT, self.message_len as u64);
+        cbor_write_header(&mut cbor, CBOR_UINT, self.checksum as u64);
+        cbor_write_header(&mut cbor, CBOR_BYTES, self.data.len() as u64);
+        cbor.extend_from_slice(&self.data);
+
+        cbor
+    }
+
+    fn from_cbor(cbor: &[u8]) -> Result<Self, AirgapError> {
+        let mut pos = 0;
+        if cbor_read_header(cbor, &mut pos)? != (CBOR_ARRAY, 5) {
+            return Err(AirgapError::InvalidCbor);
+        }
+
+        let seq_num = cbor_read_uint(cbor, &mut pos, u32::MAX as u64)? as u32;
+        let seq_len = cbor_read_uint(cbor, &mut pos, u32::MAX as u64)? as usize;
+        let message_len = cbor_read_uint(cbor, &mut pos, u32::MAX as u64)? as usize;
+        let checksum = cbor_read_uint(cbor, &mut pos, u32::MAX as u64)? as u32;
+        let data = cbor_read_bytes(cbor, &mut pos)?.to_vec();
+
+        if pos != cbor.len() || seq_num == 0 || seq_len == 0 || data.is_empty() {
+            return Err(AirgapError::InvalidCbor);
+        }
+
+        Ok(Part {
+            seq_num,
+            seq_len,
+            message_len,
+            checksum,
+            data,
+        })
+    }
+}
+
+fn psbt_to_cbor(psbt: &PSBT) -> Vec<u8> {
+    let psbt = serialize(psbt);
+
+    let mut cbor = Vec::new();
+    cbor_write_header(&mut cbor, CBOR_BYTES, psbt.len() as u64);
+    cbor.extend_from_slice(&psbt);
+
+    cbor
+}
+
+fn psbt_from_cbor(cbor: &[u8]) -> Result<PSBT, AirgapError> {
+    let mut pos = 0;
+    let psbt = cbor_read_bytes(cbor, &mut pos)?;
+    if pos != cbor.len() {
+        return Err(AirgapError::InvalidCbor);
+    }
+
+    deserialize(psbt).map_err(AirgapError::Psbt)
+}
+
+const CBOR_UINT: u8 = 0;
+const CBOR_BYTES: u8 = 2;
+const CBOR_ARRAY: u8 = 4;
+
+fn cbor_write_header(cbor: &mut Vec<u8>, major_type: u8, value: u64) {
+    let major_type = major_type << 5;
+    if value < 24 {
+        cbor.push(major_type | value as u8);
+    } else if value <= 0xFF {
+        cbor.push(major_type | 24);
+        cbor.push(value as u8);
+    } else if value <= 0xFFFF {
+        cbor.push(major_type | 25);
+        cbor.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= 0xFFFF_FFFF {
+        cbor.push(major_type | 26);
+        cbor.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        cbor.push(major_type | 27);
+        cbor.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn cbor_read_header(cbor: &[u8], pos: &mut usize) -> Result<(u8, u64), AirgapError> {
+    let initial_byte = *cbor.get(*pos).ok_or(AirgapError::InvalidCbor)?;
+    *pos += 1;
+
+    let len = match initial_byte & 0x1F {
+        value @ 0..=23 => return Ok((initial_byte >> 5, value as u64)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        _ => return Err(AirgapError::InvalidCbor),
+    };
+    let bytes = cbor.get(*pos..*pos + len).ok_or(AirgapError::InvalidCbor)?;
+    *pos += len;
+
+    Ok((
+        initial_byte >> 5,
+        bytes.iter().fold(0, |acc, b| acc << 8 | *b as u64),
+    ))
+}
+
+fn cbor_read_uint(cbor: &[u8], pos: &mut usize, max: u64) -> Result<u64, AirgapError> {
+    match cbor_read_header(cbor, pos)? {
+        (CBOR_UINT, value) if value <= max => Ok(value),
+        _ => Err(AirgapError::InvalidCbor),
+    }
+}
+
+fn cbor_read_bytes<'a>(cbor: &'a [u8], pos: &mut usize) -> Result<&'a [u8], AirgapError> {
+    let len = match cbor_read_header(cbor, pos)? {
+        (CBOR_BYTES, len) => len as usize,
+        _ => return Err(AirgapError::InvalidCbor),
+    };
+    let bytes = pos
+        .checked_add(len)
+        .and_then(|end| cbor.get(*pos..end))
+        .ok_or(AirgapError::InvalidCbor)?;
+    *pos += len;
+
+    Ok(bytes)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+fn xor_into(target: &mut [u8], other: &[u8]) {
+    for (a, b) in target.iter_mut().zip(other) {
+        *a ^= b;
+    }
+}
+
+/// Encode `data` followed by its CRC32 using the minimal form of the bytewords, i.e. the first
+/// and last letter of each word
+fn bytewords_encode(data: &[u8]) -> String {
+    let checksum = crc32(data).to_be_bytes();
+    data.iter()
+        .chain(checksum.iter())
+        .flat_map(|byte| {
+            let word = BYTEWORDS[*byte as usize].as_bytes();
+            vec![word[0] as char, word[3] as char]
+        })
+        .collect()
+}
+
+fn bytewords_decode(encoded: &str) -> Result<Vec<u8>, AirgapError> {
+    if !encoded.is_ascii() || encoded.len() % 2 != 0 {
+        return Err(AirgapError::InvalidByteword);
+    }
+
+    let mut data = encoded
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            BYTEWORDS
+                .iter()
+                .position(|word| word.as_bytes()[0] == pair[0] && word.as_bytes()[3] == pair[1])
+                .map(|byte| byte as u8)
+                .ok_or(AirgapError::InvalidByteword)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if data.len() < 4 {
+        return Err(AirgapError::InvalidChecksum);
+    }
+    let checksum = data.split_off(data.len() - 4);
+    if checksum != crc32(&data).to_be_bytes() {
+        return Err(AirgapError::InvalidChecksum);
+    }
+
+    Ok(data)
+}
+
+/// Find the shortest fragment length that splits a message of `message_len` bytes in fragments of
+/// at most `max_fragment_len` bytes, without going under [`MIN_FRAGMENT_LEN`]
+fn nominal_fragment_len(message_len: usize, max_fragment_len: usize) -> usize {
+    let max_fragment_count = std::cmp::max(message_len / MIN_FRAGMENT_LEN, 1);
+    (1..=max_fragment_count)
+        .map(|count| (message_len + count - 1) / count)
+        .find(|fragment_len| *fragment_len <= max_fragment_len)
+        .unwrap_or((message_len + max_fragment_count - 1) / max_fragment_count)
+}
+
+/// Return the indexes of the fragments combined in part `seq_num`
+fn choose_fragments(seq_num: u32, seq_len: usize, checksum: u32) -> BTreeSet<usize> {
+    if seq_num as usize <= seq_len {
+        return std::iter::once(seq_num as usize - 1).collect();
+    }
+
+    let mut seed = seq_num.to_be_bytes().to_vec();
+    seed.extend_from_slice(&checksum.to_be_bytes());
+    let mut rng = Xoshiro256::from_seed(&seed);
+
+    let degree = choose_degree(seq_len, &mut rng);
+    shuffled((0..seq_len).collect(), &mut rng)
+        .into_iter()
+        .take(degree)
+        .collect()
+}
+
+/// Pick how many fragments to combine, with a probability inversely proportional to the number of
+/// fragments, using Vose's alias method
+fn choose_degree(seq_len: usize, rng: &mut Xoshiro256) -> usize {
+    let probabilities = (1..=seq_len)
+        .map(|degree| 1.0 / degree as f64)
+        .collect::<Vec<_>>();
+    let sum: f64 = probabilities.iter().sum();
+
+    let n = probabilities.len();
+    let mut scaled = probabilities
+        .iter()
+        .map(|p| p * n as f64 / sum)
+        .collect::<Vec<_>>();
+
+    let (mut small, mut large) = (Vec::new(), Vec::new());
+    for (i, p) in scaled.iter().enumerate().rev() {
+        if *p < 1.0 {
+            small.push(i);
+        } else {
+            large.push(i);
+        }
+    }
+
+    let mut thresholds = vec![0.0; n];
+    let mut aliases = vec![0; n];
+    while !small.is_empty() && !large.is_empty() {
+        let a = small.pop().unwrap();
+        let g = large.pop().unwrap();
+        thresholds[a] = scaled[a];
+        aliases[a] = g;
+        scaled[g] = (scaled[g] + scaled[a]) - 1.0;
+        if scaled[g] < 1.0 {
+            small.push(g);
+        } else {
+            large.push(g);
+        }
+    }
+    for i in large.into_iter().chain(small.into_iter()) {
+        thresholds[i] = 1.0;
+    }
+
+    let r1 = rng.next_double();
+    let r2 = rng.next_double();
+    let i = (n as f64 * r1) as usize;
+    if r2 < thresholds[i] {
+        i + 1
+    } else {
+        aliases[i] + 1
+    }
+}
+
+fn shuffled(mut items: Vec<usize>, rng: &mut Xoshiro256) -> Vec<usize> {
+    let mut result = Vec::with_capacity(items.len());
+    while !items.is_empty() {
+        let index = rng.next_int(0, items.len() - 1);
+        result.push(items.remove(index));
+    }
+
+    result
+}
+
+/// The `xoshiro256**` generator, seeded with the SHA256 of the seed
+struct Xoshiro256 {
+    state: [u64; 4],
+}
+
+impl Xoshiro256 {
+    fn from_seed(seed: &[u8]) -> Self {
+        let hash = sha256::Hash::hash(seed);
+
+        let mut state = [0; 4];
+        for (i, chunk) in hash.into_inner().chunks(8).enumerate() {
+            state[i] = chunk.iter().fold(0, |acc, b| acc << 8 | *b as u64);
+        }
+
+        Xoshiro256 { state }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let s = &mut self.state;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+
+        result
+    }
+
+    fn next_double(&mut self) -> f64 {
+        self.next_u64() as f64 / (u64::MAX as f64 + 1.0)
+    }
+
+    fn next_int(&mut self, low: usize, high: usize) -> usize {
+        (self.next_double() * (high - low + 1) as f64) as usize + low
+    }
+}
+
+/// The bytewords from [BCR-2020-012](https://github.com/BlockchainCommons/Research/blob/master/papers/bcr-2020-012-bytewords.md)
+const BYTEWORDS: [&str; 256] = [
+    "able", "acid", "also", "apex", "aqua", "arch", "atom", "aunt", "away", "axis", "back", "bald",
+    "barn", "belt", "beta", "bias", "blue", "body", "brag", "brew", "bulb", "buzz", "calm", "cash",
+    "cats", "chef", "city", "claw", "code", "cola", "cook", "cost", "crux", "curl", "cusp", "cyan",
+    "dark", "data", "days", "deli", "dice", "diet", "door", "down", "draw", "drop", "drum", "dull",
+    "duty", "each", "easy", "echo", "edge", "epic", "even", "exam", "exit", "eyes", "fact", "fair",
+    "fern", "figs", "film", "fish", "fizz", "flap", "flew", "flux", "foxy", "free", "frog", "fuel",
+    "fund", "gala", "game", "gear", "gems", "gift", "girl", "glow", "good", "gray", "grim", "guru",
+    "gush", "gyro", "half", "hang", "hard", "hawk", "heat", "help", "high", "hill", "holy", "hope",
+    "horn", "huts", "iced", "idea", "idle", "inch", "inky", "into", "iris", "iron", "item", "jade",
+    "jazz", "join", "jolt", "jowl", "judo", "jugs", "jump", "junk", "jury", "keep", "keno", "kept",
+    "keys", "kick", "kiln", "king", "kite", "kiwi", "knob", "lamb", "lava", "lazy", "leaf", "legs",
+    "liar", "limp", "lion", "list", "logo", "loud", "love", "luau", "luck", "lung", "main", "many",
+    "math", "maze", "memo", "menu", "meow", "mild", "mint", "miss", "monk", "nail", "navy", "need",
+    "news", "next", "noon", "note", "numb", "obey", "oboe", "omit", "onyx", "open", "oval", "owls",
+    "paid", "part", "peck", "play", "plus", "poem", "pool", "pose", "puff", "puma", "purr", "quad",
+    "quiz", "race", "ramp", "real", "redo", "rich", "road", "rock", "roof", "ruby", "ruin", "runs",
+    "rust", "safe", "saga", "scar", "sets", "silk", "skew", "slot", "soap", "solo", "song", "stub",
+    "surf", "swan", "taco", "task", "taxi", "tent", "tied", "time", "tiny", "toil", "tomb", "toys",
+    "trip", "tuna", "twin", "ugly", "undo", "unit", "urge", "user", "vast", "very", "veto", "vial",
+    "vibe", "view", "visa", "void", "vows", "wall", "wand", "warm", "wasp", "wave", "waxy", "webs",
+    "what", "when", "whiz", "wolf", "work", "yank", "yawn", "yell", "yoga", "yurt", "zaps", "zero",
+    "zest", "zinc", "zone", "zoom",
+];
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::Address;
+
+    use super::*;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::TxBuilder;
+
+    fn get_test_psbt() -> PSBT {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        psbt
+    }
+
+    #[test]
+    fn test_bytewords() {
+        assert_eq!(bytewords_encode(&[0, 1, 2, 128, 255]), "aeadaolazmjendeoti");
+        assert_eq!(
+            bytewords_decode("aeadaolazmjendeoti").unwrap(),
+            vec![0, 1, 2, 128, 255]
+        );
+        assert!(matches!(
+            bytewords_decode("aeadaolazmjendeota"),
+            Err(AirgapError::InvalidChecksum)
+        ));
+        assert!(matches!(
+            bytewords_decode("aeadaolazmjendeoxx"),
+            Err(AirgapError::InvalidByteword)
+        ));
+    }
+
+    #[test]
+    fn test_xoshiro() {
+        let mut rng = Xoshiro256::from_seed(b"Wolf");
+        let values = (0..10).map(|_| rng.next_u64() % 100).collect::<Vec<_>>();
+        assert_eq!(values, vec![42, 81, 85, 8, 82, 84, 76, 73, 70, 88]);
+
+        let mut rng = Xoshiro256::from_seed(b"Wolf");
+        assert_eq!(
+            shuffled((1..=10).collect(), &mut rng),
+            vec![6, 4, 9, 3, 10, 5, 7, 8, 1, 2]
+        );
+    }
+
+    #[test]
+    fn test_single_part_ur() {
+        let psbt = get_test_psbt();
+
+        let ur = encode_ur(&psbt);
+        assert!(ur.starts_with("ur:crypto-psbt/"));
+        assert_eq!(decode_ur(&ur.to_uppercase()).unwrap(), psbt);
+        assert_eq!(UrDecoder::new().receive_part(&ur).unwrap(), Some(psbt));
+    }
+
+    #[test]
+    fn test_multi_part_ur() {
+        let psbt = get_test_psbt();
+        let mut encoder = UrEncoder::new(&psbt, 30).unwrap();
+        assert!(!encoder.is_single_part());
+
+        // skip half of the fragments, the decoder has to recover them from the mixed parts
+        let mut decoder = UrDecoder::new();
+        let mut result = None;
+        for seq_num in 1..1000 {
+            let part = encoder.next_part();
+            if seq_num <= encoder.seq_len() && seq_num % 2 == 0 {
+                continue;
+            }
+
+            if let Some(psbt) = decoder.receive_part(&part).unwrap() {
+                result = Some(psbt);
+                break;
+            }
+        }
+
+        assert_eq!(result, Some(psbt));
+        assert!((decoder.progress() - 1.0).abs() < f32::EPSILON);
+    }
+
+    // The 256-byte message generated with the seed "Wolf", wrapped in a CBOR byte string like in
+    // the examples of BCR-2020-005
+    fn get_reference_message() -> Vec<u8> {
+        let mut rng = Xoshiro256::from_seed(b"Wolf");
+        let data = (0..256)
+            .map(|_| rng.next_int(0, 255) as u8)
+            .collect::<Vec<_>>();
+
+        let mut message = Vec::new();
+        cbor_write_header(&mut message, CBOR_BYTES, data.len() as u64);
+        message.extend_from_slice(&data);
+
+        message
+    }
+
+    // The first parts of the multi-part UR of the reference message, with fragments of at most
+    // 30 bytes
+    const REFERENCE_PARTS: [&str; 20] = [
+        "ur:bytes/1-9/lpadascfadaxcywenbpljkhdcahkadaemejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtdkgslpgh",
+        "ur:bytes/2-9/lpaoascfadaxcywenbpljkhdcagwdpfnsboxgwlbaawzuefywkdplrsrjynbvygabwjldapfcsgmghhkhstlrdcxaefz",
+        "ur:bytes/3-9/lpaxascfadaxcywenbpljkhdcahelbknlkuejnbadmssfhfrdpsbiegecpasvssovlgeykssjykklronvsjksopdzmol",
+        "ur:bytes/4-9/lpaaascfadaxcywenbpljkhdcasotkhemthydawydtaxneurlkosgwcekonertkbrlwmplssjtammdplolsbrdzcrtas",
+        "ur:bytes/5-9/lpahascfadaxcywenbpljkhdcatbbdfmssrkzmcwnezelennjpfzbgmuktrhtejscktelgfpdlrkfyfwdajldejokbwf",
+        "ur:bytes/6-9/lpamascfadaxcywenbpljkhdcackjlhkhybssklbwefectpfnbbectrljectpavyrolkzczcpkmwidmwoxkilghdsowp",
+        "ur:bytes/7-9/lpatascfadaxcywenbpljkhdcavszmwnjkwtclrtvaynhpahrtoxmwvwatmedibkaegdosftvandiodagdhthtrlnnhy",
+        "ur:bytes/8-9/lpayascfadaxcywenbpljkhdcadmsponkkbbhgsoltjntegepmttmoonftnbuoiyrehfrtsabzsttorodklubbuyaetk",
+        "ur:bytes/9-9/lpasascfadaxcywenbpljkhdcajskecpmdckihdyhphfotjojtfmlnwmadspaxrkytbztpbauotbgtgtaeaevtgavtny",
+        "ur:bytes/10-9/lpbkascfadaxcywenbpljkhdcahkadaemejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtwdkiplzs",
+        "ur:bytes/11-9/lpbdascfadaxcywenbpljkhdcahelbknlkuejnbadmssfhfrdpsbiegecpasvssovlgeykssjykklronvsjkvetiiapk",
+        "ur:bytes/12-9/lpbnascfadaxcywenbpljkhdcarllaluzmdmgstospeyiefmwejlwtpedamktksrvlcygmzemovovllarodtmtbnptrs",
+        "ur:bytes/13-9/lpbtascfadaxcywenbpljkhdcamtkgtpknghchchyketwsvwgwfdhpgmgtylctotzopdrpayoschcmhplffziachrfgd",
+        "ur:bytes/14-9/lpbaascfadaxcywenbpljkhdcapazewnvonnvdnsbyleynwtnsjkjndeoldydkbkdslgjkbbkortbelomueekgvstegt",
+        "ur:bytes/15-9/lpbsascfadaxcywenbpljkhdcaynmhpddpzmversbdqdfyrehnqzlugmjzmnmtwmrouohtstgsbsahpawkditkckynwt",
+        "ur:bytes/16-9/lpbeascfadaxcywenbpljkhdcawygekobamwtlihsnpalnsghenskkiynthdzotsimtojetprsttmukirlrsbtamjtpd",
+        "ur:bytes/17-9/lpbyascfadaxcywenbpljkhdcamklgftaxykpewyrtqzhydntpnytyisincxmhtbceaykolduortotiaiaiafhiaoyce",
+        "ur:bytes/18-9/lpbgascfadaxcywenbpljkhdcahkadaemejtswhhylkepmykhhtsytsnoyoyaxaedsuttydmmhhpktpmsrjtntwkbkwy",
+        "ur:bytes/19-9/lpbwascfadaxcywenbpljkhdcadekicpaajootjzpsdrbalpeywllbdsnbinaerkurspbncxgslgftvtsrjtksplcpeo",
+        "ur:bytes/20-9/lpbbascfadaxcywenbpljkhdcayapmrleeleaxpasfrtrdkncffwjyjzgyetdmlewtkpktgllepfrltataztksmhkbot",
+    ];
+
+    #[test]
+    fn test_multi_part_ur_reference_vectors() {
+        let message = get_reference_message();
+
+        let mut encoder = UrEncoder::from_message(message.clone(), 30).unwrap();
+        for expected in REFERENCE_PARTS.iter() {
+            let part = encoder.next_part();
+            assert_eq!(part.replacen(UR_TYPE_PSBT, "bytes", 1), *expected);
+        }
+
+        // the first fragments are missing and have to be recovered from the mixed parts
+        let mut decoder = UrDecoder::new();
+        let mut result = None;
+        for part in REFERENCE_PARTS[4..].iter() {
+            result = decoder
+                .receive_message_part(&part.replacen("bytes", UR_TYPE_PSBT, 1))
+                .unwrap();
+            if result.is_some() {
+                break;
+            }
+        }
+        assert_eq!(result, Some(message));
+    }
+
+    #[test]
+    fn test_multi_part_ur_invalid_seq_len() {
+        let part = REFERENCE_PARTS[0].replacen("bytes", UR_TYPE_PSBT, 1);
+
+        // the sequence length doesn't match the one in the payload
+        assert!(matches!(
+            UrDecoder::new().receive_part(&part.replacen("1-9", "1-99999", 1)),
+            Err(AirgapError::TooManyParts(99999))
+        ));
+        assert!(matches!(
+            UrDecoder::new().receive_part(&part.replacen("1-9", "1-10", 1)),
+            Err(AirgapError::InconsistentPart)
+        ));
+
+        // a payload that claims more fragments than the message needs
+        let mut payload = Part::from_cbor(&bytewords_decode(&part[19..]).unwrap()).unwrap();
+        payload.seq_len = 5_000;
+        let part = format!(
+            "ur:{}/1-5000/{}",
+            UR_TYPE_PSBT,
+            bytewords_encode(&payload.to_cbor())
+        );
+        assert!(matches!(
+            UrDecoder::new().receive_part(&part),
+            Err(AirgapError::InvalidCbor)
+        ));
+    }
+
+    #[test]
+    fn test_multi_part_ur_inconsistent() {
+        let psbt = get_test_psbt();
+        let mut first = UrEncoder::new(&psbt, 30).unwrap();
+        let mut second = UrEncoder::new(&psbt, 40).unwrap();
+
+        let mut decoder = UrDecoder::new();
+        assert_eq!(decoder.receive_part(&first.next_part()).unwrap(), None);
+        assert!(matches!(
+            decoder.receive_part(&second.next_part()),
+            Err(AirgapError::InconsistentPart)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_ur() {
+        assert!(matches!(
+            UrEncoder::new(&get_test_psbt(), 5),
+            Err(AirgapError::InvalidMaxLength(5))
+        ));
+        assert!(matches!(
+            decode_ur("crypto-psbt/aeadaolazmjendeoti"),
+            Err(AirgapError::InvalidUr)
+        ));
+        assert!(matches!(
+            decode_ur("ur:bytes/aeadaolazmjendeoti"),
+            Err(AirgapError::UnsupportedType(t)) if t == "bytes"
+        ));
+    }
+
+    #[test]
+    fn test_split_join_psbt() {
+        let psbt = get_test_psbt();
+
+        let chunks = split_psbt(&psbt, 50).unwrap();
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 50));
+        assert_eq!(join_psbt(&chunks).unwrap(), psbt);
+
+        assert!(matches!(
+            split_psbt(&psbt, 0),
+            Err(AirgapError::InvalidMaxLength(0))
+        ));
+        assert!(matches!(join_psbt(&chunks[1..]), Err(AirgapError::Psbt(_))));
+    }
+}
@@ -0,0 +1,313 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Batch payouts
+//!
+//! This module implements [`Wallet::create_batch_txs`], which splits a long list of recipients into
+//! the smallest number of transactions that respect the [`BatchLimits`], usually the standardness
+//! rules enforced by the nodes on the network.
+//!
+//! Every transaction spends a different set of UTXOs, so they can be signed and broadcast
+//! independently, in any order.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::wallet::batch::BatchLimits;
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+//! let recipients = vec![(to_address.script_pubkey(), 50_000); 5_000];
+//!
+//! let txs = wallet.create_batch_txs(recipients, BatchLimits::default(), || {
+//!     TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(5.0))
+//! })?;
+//! for (psbt, details) in txs {
+//!     // sign and broadcast ...
+//! }
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+use bitcoin::{OutPoint, Script};
+
+use crate::blockchain::BlockchainMarker;
+use crate::database::BatchDatabase;
+use crate::error::Error;
+use crate::types::TransactionDetails;
+use crate::wallet::coin_selection::CoinSelectionAlgorithm;
+use crate::wallet::tx_builder::{CreateTx, TxBuilder};
+use crate::wallet::Wallet;
+
+/// Maximum weight of a transaction considered standard by Bitcoin Core
+pub const MAX_STANDARD_TX_WEIGHT: usize = 400_000;
+
+/// Limits for the transactions created by [`Wallet::create_batch_txs`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchLimits {
+    /// Maximum weight of a transaction, including the estimated weight of the signatures
+    pub max_weight: usize,
+    /// Maximum number of recipients in a transaction
+    pub max_recipients: usize,
+}
+
+impl Default for BatchLimits {
+    /// Only limit the weight of the transactions to [`MAX_STANDARD_TX_WEIGHT`]
+    fn default() -> Self {
+        BatchLimits {
+            max_weight: MAX_STANDARD_TX_WEIGHT,
+            max_recipients: usize::MAX,
+        }
+    }
+}
+
+impl<B, D> Wallet<B, D>
+where
+    B: BlockchainMarker,
+    D: BatchDatabase,
+{
+    /// Pay a long list of `recipients` using as few transactions as possible
+    ///
+    /// The recipients are split, in order, in chunks that fit in a transaction within the
+    /// `limits`, and for each chunk a transaction is created with the builder returned by
+    /// `make_builder`. The recipients set on the builder are replaced, every other option is
+    /// preserved.
+    ///
+    /// Since the transactions don't spend each other's outputs they can be signed and broadcast
+    /// independently. Returns an error if a single recipient doesn't fit within the `limits`, or if
+    /// the wallet doesn't have enough funds to create all the transactions.
+    pub fn create_batch_txs<Cs, F>(
+        &self,
+        recipients: Vec<(Script, u64)>,
+        limits: BatchLimits,
+        make_builder: F,
+    ) -> Result<Vec<(PSBT, TransactionDetails)>, Error>
+    where
+        Cs: CoinSelectionAlgorithm<D>,
+        F: Fn() -> TxBuilder<D, Cs, CreateTx>,
+    {
+        if recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+        if limits.max_recipients == 0 {
+            return Err(Error::Generic(
+                "The batch limits must allow at least one recipient".into(),
+            ));
+        }
+
+        let mut spent = Vec::<OutPoint>::new();
+        let mut remaining = &recipients[..];
+        let mut txs = Vec::new();
+
+        // Try to build a transaction with the first `count` remaining recipients, without deriving
+        // any new address. Returns the UTXOs selected if the transaction fits in the limits.
+        let try_chunk = |remaining: &[(Script, u64)],
+                         spent: &[OutPoint],
+                         count: usize|
+         -> Result<Option<Vec<OutPoint>>, Error> {
+            let mut builder = make_builder().set_recipients(remaining[..count].to_vec());
+            for outpoint in spent {
+                builder = builder.add_unspendable(*outpoint);
+            }
+
+            let (tx, selected, _, _) = self.build_tx(&builder, true)?;
+            match self.estimate_signed_weight(&tx, &selected, &builder.foreign_utxos)?
+                <= limits.max_weight
+            {
+                true => Ok(Some(selected.into_iter().map(|u| u.outpoint).collect())),
+                false => Ok(None),
+            }
+        };
+
+        while !remaining.is_empty() {
+            let max = std::cmp::min(remaining.len(), limits.max_recipients);
+
+            // start with the largest chunk, which is the most common case, and then binary search
+            // the largest one that fits
+            let (count, selected) = match try_chunk(remaining, &spent, max)? {
+                Some(selected) => (max, selected),
+                None => {
+                    let mut best = None;
+                    let (mut low, mut high) = (1, max - 1);
+                    while low <= high {
+                        let mid = low + (high - low) / 2;
+                        match try_chunk(remaining, &spent, mid)? {
+                            Some(selected) => {
+                                best = Some((mid, selected));
+                                low = mid + 1;
+                            }
+                            None => high = mid - 1,
+                        }
+                    }
+
+                    best.ok_or_else(|| {
+                        Error::Generic("A single recipient doesn't fit within the limits".into())
+                    })?
+                }
+            };
+
+            // pin the UTXOs we've just selected, so that the actual transaction matches the one
+            // we've checked
+            let builder = make_builder()
+                .set_recipients(remaining[..count].to_vec())
+                .utxos(selected.clone())
+                .manually_selected_only();
+            txs.push(self.create_tx(builder)?);
+
+            spent.extend(selected);
+            remaining = &remaining[count..];
+        }
+
+        Ok(txs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    use bitcoin::Address;
+
+    use super::*;
+    use crate::wallet::test::get_funded_wallet;
+
+    const DESCRIPTOR: &str = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)";
+
+    fn get_recipients(count: usize) -> Vec<(Script, u64)> {
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        vec![(addr.script_pubkey(), 5_000); count]
+    }
+
+    fn check_txs(txs: &[(PSBT, TransactionDetails)], recipients: usize) {
+        let recipient_script = get_recipients(1).remove(0).0;
+
+        let paid = txs
+            .iter()
+            .flat_map(|(psbt, _)| psbt.global.unsigned_tx.output.iter())
+            .filter(|out| out.script_pubkey == recipient_script)
+            .count();
+        assert_eq!(paid, recipients);
+
+        let inputs = txs
+            .iter()
+            .flat_map(|(psbt, _)| psbt.global.unsigned_tx.input.iter())
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let unique_inputs = inputs.iter().collect::<HashSet<_>>();
+        assert_eq!(inputs.len(), unique_inputs.len());
+    }
+
+    #[test]
+    fn test_create_batch_txs_single_tx() {
+        let (wallet, _, _) = get_funded_wallet(DESCRIPTOR);
+
+        let txs = wallet
+            .create_batch_txs(get_recipients(5), BatchLimits::default(), TxBuilder::new)
+            .unwrap();
+
+        assert_eq!(txs.len(), 1);
+        check_txs(&txs, 5);
+    }
+
+    #[test]
+    fn test_create_batch_txs_max_recipients() {
+        let (wallet, descriptors, _) = get_funded_wallet(DESCRIPTOR);
+        for index in 1..3 {
+            wallet.database.borrow_mut().received_tx(
+                testutils! {
+                    @tx ( (@external descriptors, index) => 50_000 ) (@confirmations 1)
+                },
+                Some(100),
+            );
+        }
+
+        let limits = BatchLimits {
+            max_recipients: 2,
+            ..Default::default()
+        };
+        let txs = wallet
+            .create_batch_txs(get_recipients(5), limits, TxBuilder::new)
+            .unwrap();
+
+        assert_eq!(txs.len(), 3);
+        check_txs(&txs, 5);
+    }
+
+    #[test]
+    fn test_create_batch_txs_max_weight() {
+        let (wallet, descriptors, _) = get_funded_wallet(DESCRIPTOR);
+        for index in 1..6 {
+            wallet.database.borrow_mut().received_tx(
+                testutils! {
+                    @tx ( (@external descriptors, index) => 50_000 ) (@confirmations 1)
+                },
+                Some(100),
+            );
+        }
+
+        let limits = BatchLimits {
+            max_weight: 1_000,
+            ..Default::default()
+        };
+        let txs = wallet
+            .create_batch_txs(get_recipients(10), limits, TxBuilder::new)
+            .unwrap();
+
+        assert!(txs.len() > 1);
+        check_txs(&txs, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "A single recipient doesn't fit within the limits")]
+    fn test_create_batch_txs_too_small_limit() {
+        let (wallet, _, _) = get_funded_wallet(DESCRIPTOR);
+
+        let limits = BatchLimits {
+            max_weight: 100,
+            ..Default::default()
+        };
+        wallet
+            .create_batch_txs(get_recipients(1), limits, TxBuilder::new)
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_create_batch_txs_insufficient_funds() {
+        let (wallet, _, _) = get_funded_wallet(DESCRIPTOR);
+
+        let limits = BatchLimits {
+            max_recipients: 2,
+            ..Default::default()
+        };
+        wallet
+            .create_batch_txs(get_recipients(4), limits, TxBuilder::new)
+            .unwrap();
+    }
+}
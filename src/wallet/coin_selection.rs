@@ -27,13 +27,13 @@
 //! This module provides the trait [`CoinSelectionAlgorithm`] that can be implemented to
 //! define custom coin selection algorithms.
 //!
-//! The coin selection algorithm is not globally part of a [`Wallet`](super::Wallet), instead it
-//! is selected whenever a [`Wallet::create_tx`](super::Wallet::create_tx) call is made, through
-//! the use of the [`TxBuilder`] structure, specifically with
+//! The coin selection algorithm is selected whenever a [`Wallet::create_tx`](super::Wallet::create_tx)
+//! call is made, through the use of the [`TxBuilder`] structure, specifically with
 //! [`TxBuilder::coin_selection`](super::tx_builder::TxBuilder::coin_selection) method.
 //!
 //! The [`DefaultCoinSelectionAlgorithm`] selects the default coin selection algorithm that
-//! [`TxBuilder`] uses, if it's not explicitly overridden.
+//! [`TxBuilder`] uses, if it's not explicitly overridden. A different default can be set for a
+//! [`Wallet`](super::Wallet) with [`Wallet::set_coin_selection`](super::Wallet::set_coin_selection).
 //!
 //! [`TxBuilder`]: super::tx_builder::TxBuilder
 //!
@@ -59,6 +59,7 @@
 //!         amount_needed: u64,
 //!         fee_amount: f32,
 //!     ) -> Result<CoinSelectionResult, bdk::Error> {
+//!         let candidates = required_utxos.iter().chain(optional_utxos.iter()).cloned().collect();
 //!         let mut selected_amount = 0;
 //!         let mut additional_weight = 0;
 //!         let all_utxos_selected = required_utxos
@@ -80,6 +81,7 @@
 //!             selected: all_utxos_selected,
 //!             selected_amount,
 //!             fee_amount: fee_amount + additional_fees,
+//!             candidates,
 //!         })
 //!     }
 //! }
@@ -98,6 +100,11 @@
 //! # Ok::<(), bdk::Error>(())
 //! ```
 
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use bitcoin::Script;
+
 use crate::database::Database;
 use crate::error::Error;
 use crate::types::{FeeRate, UTXO};
@@ -105,7 +112,6 @@ use crate::types::{FeeRate, UTXO};
 use rand::seq::SliceRandom;
 #[cfg(not(test))]
 use rand::thread_rng;
-#[cfg(test)]
 use rand::{rngs::StdRng, SeedableRng};
 
 /// Default coin selection algorithm used by [`TxBuilder`](super::tx_builder::TxBuilder) if not
@@ -119,6 +125,13 @@ pub type DefaultCoinSelectionAlgorithm = LargestFirstCoinSelection; // make the
 // prev_txid (32 bytes) + prev_vout (4 bytes) + sequence (4 bytes) + script_len (1 bytes)
 pub(crate) const TXIN_BASE_WEIGHT: usize = (32 + 4 + 4 + 1) * 4;
 
+// Value of an UTXO minus the fees required to spend it at `fee_rate`. UTXOs with a non-positive
+// effective value cost more than they contribute, so they are not worth spending
+fn effective_value(utxo: &UTXO, satisfaction_weight: usize, fee_rate: FeeRate) -> i64 {
+    let fee = (TXIN_BASE_WEIGHT + satisfaction_weight) as f32 / 4.0 * fee_rate.as_sat_vb();
+    utxo.txout.value as i64 - fee.ceil() as i64
+}
+
 /// Result of a successful coin selection
 #[derive(Debug)]
 pub struct CoinSelectionResult {
@@ -128,6 +141,83 @@ pub struct CoinSelectionResult {
     pub selected_amount: u64,
     /// Total fee amount in satoshi
     pub fee_amount: f32,
+    /// UTXOs considered by the algorithm, with their satisfaction weight
+    ///
+    /// This includes the required UTXOs and the optional ones that were not discarded upfront,
+    /// for instance because they were uneconomical to spend.
+    pub candidates: Vec<(UTXO, usize)>,
+}
+
+impl CoinSelectionResult {
+    /// Compute the [`Waste`] of the selection
+    ///
+    /// - `amount_needed`: the amount in satoshi that was selected, as passed to
+    ///                    [`CoinSelectionAlgorithm::coin_select`]
+    /// - `fee_rate`: fee rate used for the selection
+    /// - `long_term_fee_rate`: fee rate at which the inputs could be spent in the future
+    /// - `cost_of_change`: fees to create a change output now and to spend it later. If the excess
+    ///                     is not higher than this, it's assumed to be dropped to fees instead of
+    ///                     being sent to a change output
+    pub fn waste(
+        &self,
+        amount_needed: u64,
+        fee_rate: FeeRate,
+        long_term_fee_rate: FeeRate,
+        cost_of_change: u64,
+    ) -> Waste {
+        let fee_rate_delta = fee_rate.as_sat_vb() - long_term_fee_rate.as_sat_vb();
+        let inputs_cost = self
+            .selected
+            .iter()
+            .map(|utxo| {
+                let weight = self
+                    .candidates
+                    .iter()
+                    .find(|(candidate, _)| candidate.outpoint == utxo.outpoint)
+                    .map(|(_, weight)| *weight)
+                    .unwrap_or(0);
+                (TXIN_BASE_WEIGHT + weight) as f32 / 4.0 * fee_rate_delta
+            })
+            .sum::<f32>()
+            .round() as i64;
+
+        let excess = self
+            .selected_amount
+            .saturating_sub(amount_needed + self.fee_amount.ceil() as u64);
+        let (excess, change_cost) = if excess > cost_of_change {
+            (0, cost_of_change)
+        } else {
+            (excess, 0)
+        };
+
+        Waste {
+            inputs_cost,
+            excess,
+            change_cost,
+        }
+    }
+}
+
+/// Waste of a coin selection, as described in Bitcoin Core's coin selection
+///
+/// Lower values are better: comparing the waste of the results of different algorithms shows which
+/// one makes the best use of the wallet's coins at the current fee rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Waste {
+    /// Fees paid to spend the selected inputs now rather than at the long-term fee rate, negative
+    /// when the current fee rate is lower
+    pub inputs_cost: i64,
+    /// Value in excess of the target that is dropped to fees because no change output is created
+    pub excess: u64,
+    /// Cost of creating and later spending the change output, if one is created
+    pub change_cost: u64,
+}
+
+impl Waste {
+    /// Total waste of the selection in satoshi
+    pub fn total(&self) -> i64 {
+        self.inputs_cost + self.excess as i64 + self.change_cost as i64
+    }
 }
 
 /// Trait for generalized coin selection algorithms
@@ -136,7 +226,7 @@ pub struct CoinSelectionResult {
 /// selection algorithm when it creates transactions.
 ///
 /// For an example see [this module](crate::wallet::coin_selection)'s documentation.
-pub trait CoinSelectionAlgorithm<D: Database>: std::fmt::Debug {
+pub trait CoinSelectionAlgorithm<D>: std::fmt::Debug {
     /// Perform the coin selection
     ///
     /// - `database`: a reference to the wallet's database that can be used to lookup additional
@@ -163,7 +253,8 @@ pub trait CoinSelectionAlgorithm<D: Database>: std::fmt::Debug {
 /// Simple and dumb coin selection
 ///
 /// This coin selection algorithm sorts the available UTXOs by value and then picks them starting
-/// from the largest ones until the required amount is reached.
+/// from the largest ones until the required amount is reached. Optional UTXOs whose value doesn't
+/// cover the fees required to spend them are never selected.
 #[derive(Debug, Default)]
 pub struct LargestFirstCoinSelection;
 
@@ -186,6 +277,15 @@ impl<D: Database> CoinSelectionAlgorithm<D> for LargestFirstCoinSelection {
             fee_rate
         );
 
+        // Discard the optional UTXOs that are uneconomical to spend at this fee rate
+        optional_utxos.retain(|(utxo, weight)| effective_value(utxo, *weight, fee_rate) > 0);
+
+        let candidates = required_utxos
+            .iter()
+            .chain(optional_utxos.iter())
+            .cloned()
+            .collect();
+
         // We put the "required UTXOs" first and make sure the optional UTXOs are sorted,
         // initially smallest to largest, before being reversed with `.rev()`.
         let utxos = {
@@ -229,6 +329,272 @@ impl<D: Database> CoinSelectionAlgorithm<D> for LargestFirstCoinSelection {
             selected,
             fee_amount,
             selected_amount,
+            candidates,
+        })
+    }
+}
+
+/// Privacy-aware coin selection
+///
+/// This coin selection algorithm groups the available UTXOs by the script they were received on
+/// and tries to spend as few of those groups as possible, to avoid linking different addresses
+/// together in the same transaction:
+///
+/// - the UTXOs that share their script with a required UTXO are used first, since their address
+///   is already linked to the transaction;
+/// - if a single group is enough to fund the transaction, the smallest one that does is used;
+/// - otherwise groups are added starting from the largest ones.
+///
+/// When [`spend_full_groups`](Self::spend_full_groups) is enabled every UTXO of a group is spent
+/// as soon as one of them is used, similarly to Bitcoin Core's `avoid_partial_spends`. Otherwise
+/// only the largest UTXOs of a group that are needed to reach the target are selected, skipping
+/// those that are uneconomical to spend.
+#[derive(Debug, Default, Clone)]
+pub struct AddressGroupCoinSelection {
+    spend_full_groups: bool,
+}
+
+impl AddressGroupCoinSelection {
+    /// Create a new instance that only spends the UTXOs of a group that are needed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spend every UTXO of a group whenever one of them is selected
+    pub fn spend_full_groups(mut self, spend_full_groups: bool) -> Self {
+        self.spend_full_groups = spend_full_groups;
+        self
+    }
+
+    fn select_group(
+        &self,
+        selection: &mut GroupSelection,
+        mut group: Vec<(UTXO, usize)>,
+        fee_rate: FeeRate,
+        amount_needed: u64,
+    ) {
+        group.sort_unstable_by_key(|(utxo, _)| Reverse(utxo.txout.value));
+        for (utxo, weight) in group {
+            if !self.spend_full_groups {
+                if selection.is_enough(amount_needed) {
+                    break;
+                } else if effective_value(&utxo, weight, fee_rate) <= 0 {
+                    continue;
+                }
+            }
+
+            selection.add(utxo, weight, fee_rate);
+        }
+    }
+}
+
+// Selection being built by `AddressGroupCoinSelection`
+struct GroupSelection {
+    selected: Vec<UTXO>,
+    selected_amount: u64,
+    fee_amount: f32,
+}
+
+impl GroupSelection {
+    fn add(&mut self, utxo: UTXO, weight: usize, fee_rate: FeeRate) {
+        self.fee_amount += (TXIN_BASE_WEIGHT + weight) as f32 * fee_rate.as_sat_vb() / 4.0;
+        self.selected_amount += utxo.txout.value;
+
+        log::debug!(
+            "Selected {}, updated fee_amount = `{}`",
+            utxo.outpoint,
+            self.fee_amount
+        );
+
+        self.selected.push(utxo);
+    }
+
+    fn missing(&self, amount_needed: u64) -> i64 {
+        (amount_needed + self.fee_amount.ceil() as u64) as i64 - self.selected_amount as i64
+    }
+
+    fn is_enough(&self, amount_needed: u64) -> bool {
+        self.missing(amount_needed) <= 0
+    }
+}
+
+impl<D: Database> CoinSelectionAlgorithm<D> for AddressGroupCoinSelection {
+    fn coin_select(
+        &self,
+        _database: &D,
+        required_utxos: Vec<(UTXO, usize)>,
+        optional_utxos: Vec<(UTXO, usize)>,
+        fee_rate: FeeRate,
+        amount_needed: u64,
+        fee_amount: f32,
+    ) -> Result<CoinSelectionResult, Error> {
+        log::debug!(
+            "amount_needed = `{}`, fee_amount = `{}`, fee_rate = `{:?}`",
+            amount_needed,
+            fee_amount,
+            fee_rate
+        );
+
+        let candidates = required_utxos
+            .iter()
+            .chain(optional_utxos.iter())
+            .cloned()
+            .collect();
+
+        let mut groups: BTreeMap<Script, Vec<(UTXO, usize)>> = BTreeMap::new();
+        for (utxo, weight) in optional_utxos {
+            groups
+                .entry(utxo.txout.script_pubkey.clone())
+                .or_default()
+                .push((utxo, weight));
+        }
+
+        let mut selection = GroupSelection {
+            selected: vec![],
+            selected_amount: 0,
+            fee_amount,
+        };
+
+        // The addresses of the required UTXOs are linked to the transaction anyways, so their
+        // other UTXOs can be spent without revealing anything new
+        let mut linked = vec![];
+        for (utxo, weight) in required_utxos {
+            if let Some(group) = groups.remove(&utxo.txout.script_pubkey) {
+                linked.extend(group);
+            }
+
+            selection.add(utxo, weight, fee_rate);
+        }
+        self.select_group(&mut selection, linked, fee_rate, amount_needed);
+
+        if !selection.is_enough(amount_needed) {
+            // Sort the groups by their effective value, i.e. the sum of their values minus the fees
+            // required to spend them, ignoring the ones that would only make the fees grow
+            let mut groups = groups
+                .into_iter()
+                .map(|(_, group)| {
+                    let effective_value = group.iter().fold(0, |acc, (utxo, weight)| {
+                        acc + effective_value(utxo, *weight, fee_rate)
+                    });
+                    (effective_value, group)
+                })
+                .filter(|(effective_value, _)| *effective_value > 0)
+                .collect::<Vec<_>>();
+            groups.sort_by_key(|(effective_value, _)| *effective_value);
+
+            let missing = selection.missing(amount_needed);
+            match groups
+                .iter()
+                .position(|(effective_value, _)| *effective_value >= missing)
+            {
+                Some(index) => {
+                    let (_, group) = groups.remove(index);
+                    self.select_group(&mut selection, group, fee_rate, amount_needed);
+                }
+                None => {
+                    for (_, group) in groups.into_iter().rev() {
+                        if selection.is_enough(amount_needed) {
+                            break;
+                        }
+
+                        self.select_group(&mut selection, group, fee_rate, amount_needed);
+                    }
+                }
+            }
+        }
+
+        if !selection.is_enough(amount_needed) {
+            return Err(Error::InsufficientFunds);
+        }
+
+        Ok(CoinSelectionResult {
+            selected: selection.selected,
+            selected_amount: selection.selected_amount,
+            fee_amount: selection.fee_amount,
+            candidates,
+        })
+    }
+}
+
+/// Consolidation coin selection
+///
+/// This coin selection algorithm is meant for consolidation transactions: instead of minimizing
+/// the number of inputs it spends as many UTXOs as possible, starting from the smallest ones,
+/// until the weight of the inputs reaches `max_weight`. UTXOs that are uneconomical to spend at the
+/// chosen fee rate are skipped, so consolidations should be scheduled when fees are low.
+///
+/// See [`TxBuilder::consolidate`](super::tx_builder::TxBuilder::consolidate) for a shortcut to
+/// create a consolidation transaction.
+#[derive(Debug, Clone)]
+pub struct ConsolidationCoinSelection {
+    max_weight: usize,
+}
+
+impl ConsolidationCoinSelection {
+    /// Create a new instance that limits the total weight of the inputs to `max_weight`
+    pub fn new(max_weight: usize) -> Self {
+        Self { max_weight }
+    }
+}
+
+impl<D: Database> CoinSelectionAlgorithm<D> for ConsolidationCoinSelection {
+    fn coin_select(
+        &self,
+        _database: &D,
+        required_utxos: Vec<(UTXO, usize)>,
+        mut optional_utxos: Vec<(UTXO, usize)>,
+        fee_rate: FeeRate,
+        amount_needed: u64,
+        mut fee_amount: f32,
+    ) -> Result<CoinSelectionResult, Error> {
+        let calc_fee_bytes = |wu| (wu as f32) * fee_rate.as_sat_vb() / 4.0;
+
+        log::debug!(
+            "amount_needed = `{}`, fee_amount = `{}`, fee_rate = `{:?}`, max_weight = `{}`",
+            amount_needed,
+            fee_amount,
+            fee_rate,
+            self.max_weight
+        );
+
+        optional_utxos.retain(|(utxo, weight)| effective_value(utxo, *weight, fee_rate) > 0);
+        optional_utxos.sort_unstable_by_key(|(utxo, _)| utxo.txout.value);
+
+        let candidates = required_utxos
+            .iter()
+            .chain(optional_utxos.iter())
+            .cloned()
+            .collect();
+
+        let mut selected = vec![];
+        let mut selected_amount = 0;
+        let mut total_weight = 0;
+        for (must_use, (utxo, weight)) in required_utxos
+            .into_iter()
+            .map(|utxo| (true, utxo))
+            .chain(optional_utxos.into_iter().map(|utxo| (false, utxo)))
+        {
+            let input_weight = TXIN_BASE_WEIGHT + weight;
+            if !must_use && total_weight + input_weight > self.max_weight {
+                // the UTXOs are sorted by value, not by weight, so a later one may still fit
+                continue;
+            }
+
+            total_weight += input_weight;
+            fee_amount += calc_fee_bytes(input_weight);
+            selected_amount += utxo.txout.value;
+            selected.push(utxo);
+        }
+
+        if selected_amount < amount_needed + (fee_amount.ceil() as u64) {
+            return Err(Error::InsufficientFunds);
+        }
+
+        Ok(CoinSelectionResult {
+            selected,
+            selected_amount,
+            fee_amount,
+            candidates,
         })
     }
 }
@@ -248,7 +614,7 @@ struct OutputGroup {
 impl OutputGroup {
     fn new(utxo: UTXO, satisfaction_weight: usize, fee_rate: FeeRate) -> Self {
         let fee = (TXIN_BASE_WEIGHT + satisfaction_weight) as f32 / 4.0 * fee_rate.as_sat_vb();
-        let effective_value = utxo.txout.value as i64 - fee.ceil() as i64;
+        let effective_value = effective_value(&utxo, satisfaction_weight, fee_rate);
         OutputGroup {
             utxo,
             satisfaction_weight,
@@ -264,6 +630,7 @@ impl OutputGroup {
 #[derive(Debug)]
 pub struct BranchAndBoundCoinSelection {
     size_of_change: u64,
+    rng_seed: Option<u64>,
 }
 
 impl Default for BranchAndBoundCoinSelection {
@@ -271,6 +638,7 @@ impl Default for BranchAndBoundCoinSelection {
         Self {
             // P2WPKH cost of change -> value (8 bytes) + script len (1 bytes) + script (22 bytes)
             size_of_change: 8 + 1 + 22,
+            rng_seed: None,
         }
     }
 }
@@ -278,7 +646,19 @@ impl Default for BranchAndBoundCoinSelection {
 impl BranchAndBoundCoinSelection {
     /// Create new instance with target size for change output
     pub fn new(size_of_change: u64) -> Self {
-        Self { size_of_change }
+        Self {
+            size_of_change,
+            rng_seed: None,
+        }
+    }
+
+    /// Seed the RNG used when no exact match is found and the UTXOs are picked at random
+    ///
+    /// With a fixed seed the selection is deterministic, which makes it possible to reproduce a
+    /// transaction exactly given the same UTXOs.
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
     }
 }
 
@@ -324,7 +704,13 @@ impl<D: Database> CoinSelectionAlgorithm<D> for BranchAndBoundCoinSelection {
             return Err(Error::InsufficientFunds);
         }
 
-        Ok(self
+        let candidates = required_utxos
+            .iter()
+            .chain(optional_utxos.iter())
+            .map(|u| (u.utxo.clone(), u.satisfaction_weight))
+            .collect();
+
+        let mut result = self
             .bnb(
                 required_utxos.clone(),
                 optional_utxos.clone(),
@@ -342,7 +728,10 @@ impl<D: Database> CoinSelectionAlgorithm<D> for BranchAndBoundCoinSelection {
                     actual_target,
                     fee_amount,
                 )
-            }))
+            });
+        result.candidates = candidates;
+
+        Ok(result)
     }
 }
 
@@ -468,13 +857,16 @@ impl BranchAndBoundCoinSelection {
         actual_target: u64,
         fee_amount: f32,
     ) -> CoinSelectionResult {
-        #[cfg(not(test))]
-        optional_utxos.shuffle(&mut thread_rng());
-        #[cfg(test)]
-        {
-            let seed = [0; 32];
-            let mut rng: StdRng = SeedableRng::from_seed(seed);
-            optional_utxos.shuffle(&mut rng);
+        match self.rng_seed {
+            Some(seed) => optional_utxos.shuffle(&mut StdRng::seed_from_u64(seed)),
+            #[cfg(not(test))]
+            None => optional_utxos.shuffle(&mut thread_rng()),
+            #[cfg(test)]
+            None => {
+                let seed = [0; 32];
+                let mut rng: StdRng = SeedableRng::from_seed(seed);
+                optional_utxos.shuffle(&mut rng);
+            }
         }
 
         let selected_utxos = optional_utxos
@@ -509,6 +901,7 @@ impl BranchAndBoundCoinSelection {
             selected,
             fee_amount,
             selected_amount,
+            candidates: vec![],
         }
     }
 }
@@ -630,6 +1023,83 @@ mod test {
         assert_eq!(result.fee_amount, 186.0);
     }
 
+    #[test]
+    fn test_coin_selection_result_waste() {
+        let utxos = get_test_utxos();
+        let database = MemoryDatabase::default();
+
+        let result = LargestFirstCoinSelection::default()
+            .coin_select(
+                &database,
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                250_000,
+                50.0,
+            )
+            .unwrap();
+        assert_eq!(result.candidates.len(), 2);
+        assert_eq!(result.fee_amount, 186.0);
+
+        // the excess is large enough to create a change output
+        let waste = result.waste(
+            250_000,
+            FeeRate::from_sat_per_vb(1.0),
+            FeeRate::from_sat_per_vb(2.0),
+            100,
+        );
+        assert_eq!(
+            waste,
+            Waste {
+                inputs_cost: -136,
+                excess: 0,
+                change_cost: 100,
+            }
+        );
+        assert_eq!(waste.total(), -36);
+
+        // the excess is dropped to fees
+        let waste = result.waste(
+            250_000,
+            FeeRate::from_sat_per_vb(3.0),
+            FeeRate::from_sat_per_vb(2.0),
+            50_000,
+        );
+        assert_eq!(
+            waste,
+            Waste {
+                inputs_cost: 136,
+                excess: 49_814,
+                change_cost: 0,
+            }
+        );
+        assert_eq!(waste.total(), 49_950);
+    }
+
+    #[test]
+    fn test_largest_first_coin_selection_skip_uneconomical() {
+        let mut utxos = get_test_utxos();
+        let mut dust = utxos[0].clone();
+        dust.0.txout.value = 60;
+        utxos.push(dust);
+        let database = MemoryDatabase::default();
+
+        let result = LargestFirstCoinSelection::default()
+            .coin_select(
+                &database,
+                vec![],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                299_000,
+                50.0,
+            )
+            .unwrap();
+        // spending the 60 sat UTXO would cost 68 sats, so it's not even considered
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount, 300_000);
+        assert_eq!(result.candidates.len(), 2);
+    }
+
     #[test]
     fn test_largest_first_coin_selection_use_all() {
         let utxos = get_test_utxos();
@@ -994,4 +1464,161 @@ mod test {
             50.0 + result.selected.len() as f32 * 68.0
         );
     }
+
+    #[test]
+    fn test_single_random_draw_rng_seed() {
+        let seed = [0; 32];
+        let mut rng: StdRng = SeedableRng::from_seed(seed);
+        let mut utxos = generate_random_utxos(&mut rng, 300);
+        let target_amount = sum_random_utxos(&mut rng, &mut utxos);
+
+        let fee_rate = FeeRate::from_sat_per_vb(1.0);
+        let utxos: Vec<OutputGroup> = utxos
+            .into_iter()
+            .map(|u| OutputGroup::new(u.0, u.1, fee_rate))
+            .collect();
+
+        let draw = |seed| {
+            BranchAndBoundCoinSelection::default()
+                .rng_seed(seed)
+                .single_random_draw(vec![], utxos.clone(), 0, target_amount, 50.0)
+                .selected
+                .into_iter()
+                .map(|utxo| utxo.txout.value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+    }
+
+    fn get_grouped_utxos() -> Vec<(UTXO, usize)> {
+        [(1u8, 45_000), (1, 5_000), (2, 60_000), (3, 100_000)]
+            .iter()
+            .enumerate()
+            .map(|(vout, (script, value))| {
+                (
+                    UTXO {
+                        outpoint: OutPoint::from_str(&format!(
+                            "ebd9813ecebc57ff8f30797de7c205e3c7498ca950ea4341ee51a685ff2fa30a:{}",
+                            vout
+                        ))
+                        .unwrap(),
+                        txout: TxOut {
+                            value: *value,
+                            script_pubkey: Script::from(vec![*script]),
+                        },
+                        keychain: KeychainKind::External,
+                    },
+                    P2WPKH_WITNESS_SIZE,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_address_group_coin_selection_single_group() {
+        let database = MemoryDatabase::default();
+
+        let result = AddressGroupCoinSelection::new()
+            .coin_select(
+                &database,
+                vec![],
+                get_grouped_utxos(),
+                FeeRate::from_sat_per_vb(1.0),
+                40_000,
+                50.0,
+            )
+            .unwrap();
+
+        // the smallest group that covers the amount is used, only with the coins that are needed
+        assert_eq!(result.selected.len(), 1);
+        assert_eq!(result.selected_amount, 45_000);
+        assert_eq!(result.fee_amount, 118.0);
+    }
+
+    #[test]
+    fn test_address_group_coin_selection_full_groups() {
+        let database = MemoryDatabase::default();
+
+        let result = AddressGroupCoinSelection::new()
+            .spend_full_groups(true)
+            .coin_select(
+                &database,
+                vec![],
+                get_grouped_utxos(),
+                FeeRate::from_sat_per_vb(1.0),
+                40_000,
+                50.0,
+            )
+            .unwrap();
+
+        assert_eq!(result.selected.len(), 2);
+        assert!(result
+            .selected
+            .iter()
+            .all(|utxo| utxo.txout.script_pubkey == Script::from(vec![1])));
+        assert_eq!(result.selected_amount, 50_000);
+        assert_eq!(result.fee_amount, 186.0);
+    }
+
+    #[test]
+    fn test_address_group_coin_selection_multiple_groups() {
+        let database = MemoryDatabase::default();
+
+        let result = AddressGroupCoinSelection::new()
+            .coin_select(
+                &database,
+                vec![],
+                get_grouped_utxos(),
+                FeeRate::from_sat_per_vb(1.0),
+                150_000,
+                50.0,
+            )
+            .unwrap();
+
+        // no group is enough on its own, the largest ones are used first
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount, 160_000);
+        assert_eq!(result.fee_amount, 186.0);
+    }
+
+    #[test]
+    fn test_address_group_coin_selection_required_group() {
+        let mut utxos = get_grouped_utxos();
+        let required = utxos.remove(1);
+        let database = MemoryDatabase::default();
+
+        let result = AddressGroupCoinSelection::new()
+            .coin_select(
+                &database,
+                vec![required],
+                utxos,
+                FeeRate::from_sat_per_vb(1.0),
+                40_000,
+                50.0,
+            )
+            .unwrap();
+
+        // the other coin on the address of the required one is enough, even though the other
+        // groups are larger
+        assert_eq!(result.selected.len(), 2);
+        assert_eq!(result.selected_amount, 50_000);
+    }
+
+    #[test]
+    fn test_address_group_coin_selection_insufficient_funds() {
+        let database = MemoryDatabase::default();
+
+        let result = AddressGroupCoinSelection::new().coin_select(
+            &database,
+            vec![],
+            get_grouped_utxos(),
+            FeeRate::from_sat_per_vb(1.0),
+            500_000,
+            50.0,
+        );
+
+        assert!(matches!(result, Err(Error::InsufficientFunds)));
+    }
 }
@@ -24,7 +24,9 @@
 
 //! Wallet export
 //!
-//! This modules implements the wallet export format used by [FullyNoded](https://github.com/Fonta1n3/FullyNoded/blob/10b7808c8b929b171cca537fb50522d015168ac9/Docs/Wallets/Wallet-Export-Spec.md).
+//! This modules implements the wallet export format used by [FullyNoded](https://github.com/Fonta1n3/FullyNoded/blob/10b7808c8b929b171cca537fb50522d015168ac9/Docs/Wallets/Wallet-Export-Spec.md),
+//! and the list of descriptors accepted by the `importdescriptors` RPC of Bitcoin Core, which can
+//! be used to mirror a wallet into a watch-only descriptor wallet of Core.
 //!
 //! ## Examples
 //!
@@ -71,17 +73,44 @@
 //! println!("Exported: {}", export.to_string());
 //! # Ok::<_, bdk::Error>(())
 //! ```
+//!
+//! ### Mirror a `Wallet` into Bitcoin Core
+//! ```
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::wallet::export::*;
+//! # use bdk::*;
+//! let wallet: OfflineWallet<_> = Wallet::new_offline(
+//!     "wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)",
+//!     Some("wpkh([c258d2e4/84h/1h/0h]tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/1/*)"),
+//!     Network::Testnet,
+//!     MemoryDatabase::default()
+//! )?;
+//! let import = CoreImport::export_wallet(&wallet, None)
+//!     .map_err(ToString::to_string)
+//!     .map_err(bdk::Error::Generic)?;
+//!
+//! // bitcoin-cli -rpcwallet=<watch-only wallet> importdescriptors '<import>'
+//! println!("Import: {}", import.to_string());
+//! # Ok::<_, bdk::Error>(())
+//! ```
 
 use std::str::FromStr;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
 use miniscript::{Descriptor, DescriptorPublicKey, ScriptContext, Terminal};
 
 use crate::blockchain::BlockchainMarker;
 use crate::database::BatchDatabase;
+use crate::descriptor::{add_checksum, DescriptorMeta};
+use crate::types::KeychainKind;
 use crate::wallet::Wallet;
 
+/// Number of addresses imported in Bitcoin Core after the last one used by the wallet, the same
+/// as the default size of its keypool
+const CORE_IMPORT_LOOKAHEAD: u32 = 1000;
+
 /// Structure that contains the export of a wallet
 ///
 /// For a usage example see [this module](crate::wallet::export)'s documentation.
@@ -199,6 +228,126 @@ impl WalletExport {
     }
 }
 
+/// Time from which Bitcoin Core rescans the chain looking for the transactions of an imported
+/// descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RescanSince {
+    /// Don't rescan, the descriptor has never been used
+    Now,
+    /// UNIX timestamp of the earliest block to rescan
+    Timestamp(u64),
+}
+
+impl Serialize for RescanSince {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            RescanSince::Now => serializer.serialize_str("now"),
+            RescanSince::Timestamp(timestamp) => serializer.serialize_u64(*timestamp),
+        }
+    }
+}
+
+/// One of the descriptors of a [`CoreImport`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CoreDescriptorImport {
+    /// Public descriptor, with its checksum
+    pub desc: String,
+    /// Whether Core should use the descriptor to generate new addresses
+    pub active: bool,
+    /// Range of derivation indexes to import, for ranged descriptors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<(u32, u32)>,
+    /// First derivation index Core should use to generate new addresses, for ranged descriptors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_index: Option<u32>,
+    /// Earliest time to rescan
+    pub timestamp: RescanSince,
+    /// Whether the descriptor generates change addresses
+    pub internal: bool,
+}
+
+/// List of descriptors in the format accepted by the `importdescriptors` RPC of Bitcoin Core
+///
+/// For a usage example see [this module](crate::wallet::export)'s documentation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct CoreImport {
+    /// Descriptors to import
+    pub descriptors: Vec<CoreDescriptorImport>,
+}
+
+impl ToString for CoreImport {
+    fn to_string(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl CoreImport {
+    /// Export the public descriptors of a wallet
+    ///
+    /// The descriptors are always exported without their private keys, so that they can be
+    /// imported in a watch-only wallet. This function returns an error if they are not supported
+    /// by Bitcoin Core.
+    ///
+    /// The rescan starts at `birthday`, a UNIX timestamp, if present. Otherwise it starts at the
+    /// oldest transaction in the `wallet`'s database, and it's skipped if the database is empty:
+    /// wallets that haven't been synced yet should provide a `birthday`.
+    pub fn export_wallet<B: BlockchainMarker, D: BatchDatabase>(
+        wallet: &Wallet<B, D>,
+        birthday: Option<u64>,
+    ) -> Result<Self, &'static str> {
+        let database = wallet.database.borrow();
+
+        let timestamp = match birthday {
+            Some(birthday) => RescanSince::Timestamp(birthday),
+            None => database
+                .iter_txs(false)
+                .map_err(|_| "Can't read the wallet's transactions")?
+                .into_iter()
+                .map(|tx| tx.timestamp)
+                .min()
+                .map(RescanSince::Timestamp)
+                .unwrap_or(RescanSince::Now),
+        };
+
+        let descriptors = std::iter::once((&wallet.descriptor, KeychainKind::External))
+            .chain(
+                wallet
+                    .change_descriptor
+                    .as_ref()
+                    .map(|desc| (desc, KeychainKind::Internal)),
+            )
+            .map(|(descriptor, keychain)| {
+                let desc = descriptor.to_string();
+                WalletExport::is_compatible_with_core(&desc)?;
+                let desc = add_checksum(&desc).map_err(|_| "Invalid descriptor")?;
+
+                let ranged = !descriptor.is_fixed();
+                let next_index = match database.get_last_index(keychain) {
+                    Ok(Some(index)) => index + 1,
+                    Ok(None) => 0,
+                    Err(_) => return Err("Can't read the wallet's derivation indexes"),
+                };
+
+                Ok(CoreDescriptorImport {
+                    desc,
+                    active: ranged,
+                    range: if ranged {
+                        Some((0, next_index + CORE_IMPORT_LOOKAHEAD - 1))
+                    } else {
+                        None
+                    },
+                    next_index: if ranged { Some(next_index) } else { None },
+                    timestamp,
+                    internal: keychain == KeychainKind::Internal,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(CoreImport { descriptors })
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::str::FromStr;
@@ -223,6 +372,7 @@ mod test {
             sent: 0,
             fees: 500,
             height: Some(5000),
+            recipients: vec![],
         })
         .unwrap();
 
@@ -340,4 +490,66 @@ mod test {
         assert_eq!(export.blockheight, 5000);
         assert_eq!(export.label, "Test Label");
     }
+
+    #[test]
+    fn test_export_core() {
+        let descriptor = "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/0/*)";
+        let change_descriptor = "wpkh([c258d2e4/84'/1'/0']tpubDD3ynpHgJQW8VvWRzQ5WFDCrs4jqVFGHB3vLC3r49XHJSqP8bHKdK4AriuUKLccK68zfzowx7YhmDN8SiSkgCDENUFx9qVw65YyqM78vyVe/1/*)";
+
+        let mut db = get_test_db();
+        db.set_last_index(KeychainKind::External, 4).unwrap();
+        let wallet: OfflineWallet<_> =
+            Wallet::new_offline(descriptor, Some(change_descriptor), Network::Testnet, db).unwrap();
+        let import = CoreImport::export_wallet(&wallet, None).unwrap();
+
+        assert_eq!(import.descriptors.len(), 2);
+        let external = &import.descriptors[0];
+        assert_eq!(external.desc, add_checksum(descriptor).unwrap());
+        assert!(external.active);
+        assert!(!external.internal);
+        assert_eq!(external.next_index, Some(5));
+        assert_eq!(external.range, Some((0, 1004)));
+        assert_eq!(external.timestamp, RescanSince::Timestamp(12345678));
+
+        let internal = &import.descriptors[1];
+        assert_eq!(internal.desc, add_checksum(change_descriptor).unwrap());
+        assert!(internal.internal);
+        assert_eq!(internal.next_index, Some(0));
+        assert_eq!(internal.range, Some((0, 999)));
+
+        let json: serde_json::Value = serde_json::from_str(&import.to_string()).unwrap();
+        assert_eq!(json[0]["range"], serde_json::json!([0, 1004]));
+        assert_eq!(json[0]["timestamp"], 12345678);
+        assert_eq!(json[1]["internal"], true);
+    }
+
+    #[test]
+    fn test_export_core_single_key() {
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)",
+            None,
+            Network::Testnet,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let import = CoreImport::export_wallet(&wallet, Some(1_600_000_000)).unwrap();
+
+        assert_eq!(import.descriptors.len(), 1);
+        // the private keys are never exported
+        assert!(!import.descriptors[0]
+            .desc
+            .contains("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW"));
+        assert!(!import.descriptors[0].active);
+        assert_eq!(import.descriptors[0].range, None);
+        assert_eq!(
+            import.descriptors[0].timestamp,
+            RescanSince::Timestamp(1_600_000_000)
+        );
+
+        let json = CoreImport::export_wallet(&wallet, None)
+            .unwrap()
+            .to_string();
+        assert!(json.contains("\"timestamp\":\"now\""));
+        assert!(!json.contains("range"));
+    }
 }
@@ -0,0 +1,488 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! External signers
+//!
+//! This module, enabled by the `external-signer` feature, provides [`ExternalSigner`], a
+//! [`Signer`] that delegates signing to an external program following the
+//! [external signer interface](https://github.com/bitcoin/bitcoin/blob/master/doc/external-signer.md)
+//! of Bitcoin Core. Tools that implement it, like [HWI](https://github.com/bitcoin-core/HWI), can be
+//! used to sign with hardware wallets or other signing bridges without a dedicated integration.
+//!
+//! The program is executed as `<command> [args] --stdin --fingerprint <fingerprint> --chain <chain>`
+//! and it receives `signtx <psbt>` on its standard input, with the PSBT encoded in base64. It must
+//! print a JSON object like `{"psbt": "<signed psbt>"}` on its standard output, or
+//! `{"error": "<message>"}` if the PSBT can't be signed.
+//!
+//! Some devices must also register a [`WalletPolicy`] before signing for it, which is done with
+//! [`ExternalSigner::register_policy`]: the program receives `register --desc <descriptor> --name
+//! <name>` on its standard input, with the arguments quoted like in a shell, and it prints
+//! `{"hmac": "<hmac>"}` if the device returns a proof of registration.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use std::str::FromStr;
+//! # use bitcoin::util::bip32::Fingerprint;
+//! # use bitcoin::Network;
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::signer::SignerOrdering;
+//! # use bdk::wallet::external_signer::ExternalSigner;
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! let mut wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//!
+//! let fingerprint = Fingerprint::from_str("e30f11b8").unwrap();
+//! let signer = ExternalSigner::new("hwi", fingerprint, Network::Testnet);
+//! wallet.add_signer(
+//!     KeychainKind::External,
+//!     fingerprint.into(),
+//!     SignerOrdering(200),
+//!     Arc::new(signer),
+//! );
+//! # Ok::<_, bdk::Error>(())
+//! ```
+
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use bitcoin::util::bip32::Fingerprint;
+use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+use bitcoin::Network;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::signer::{NoopSignerProgress, SignOptions, Signer, SignerError, SignerProgress};
+use super::utils::SecpCtx;
+use crate::descriptor::checksum::add_checksum;
+use crate::descriptor::wallet_policy::WalletPolicy;
+use crate::error::Error;
+use crate::psbt;
+
+// Read `pipe` until it's closed on a separate thread, so that the program never blocks because
+// a pipe is full while we wait for it to exit
+fn read_pipe<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        pipe.read_to_end(&mut buf)?;
+        Ok(buf)
+    })
+}
+
+// Quote an argument so that it's read as a single word, like a shell would
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r#"'"'"'"#))
+}
+
+#[derive(Debug, Deserialize)]
+struct SignTxResponse {
+    psbt: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RegisterResponse {
+    hmac: Option<String>,
+    error: Option<String>,
+}
+
+/// Signer that runs an external program to sign PSBTs
+///
+/// See [the module](crate::wallet::external_signer)'s documentation for the protocol.
+#[derive(Debug, Clone)]
+pub struct ExternalSigner {
+    command: String,
+    args: Vec<String>,
+    fingerprint: Fingerprint,
+    network: Network,
+    timeout: Option<Duration>,
+}
+
+impl ExternalSigner {
+    /// Create a signer that runs `command` to sign with the device identified by `fingerprint`
+    pub fn new<S: Into<String>>(command: S, fingerprint: Fingerprint, network: Network) -> Self {
+        ExternalSigner {
+            command: command.into(),
+            args: vec![],
+            fingerprint,
+            network,
+            timeout: None,
+        }
+    }
+
+    /// Add an argument passed to the command before the ones defined by the protocol
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Stop waiting for the command after `timeout`, returning [`SignerError::Timeout`]
+    ///
+    /// By default the signer waits until the command exits, which can take forever if the user
+    /// never confirms the transaction on the device.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn chain(&self) -> &'static str {
+        match self.network {
+            Network::Bitcoin => "main",
+            Network::Testnet => "test",
+            Network::Regtest => "regtest",
+        }
+    }
+
+    /// Register a wallet policy on the device, returning the proof of registration if the device
+    /// provides one
+    ///
+    /// The user usually has to confirm the policy on the device, so like when signing this
+    /// waits until the command exits, or until the [`timeout`](ExternalSigner::timeout). Some
+    /// devices return an HMAC that must be stored and provided again when signing, while others
+    /// keep the registered policies themselves.
+    pub fn register_policy(&self, policy: &WalletPolicy) -> Result<Option<String>, Error> {
+        let descriptor = add_checksum(&policy.to_multipath_descriptor()?)?;
+        let request = format!(
+            "register --desc {} --name {}",
+            quote(&descriptor),
+            quote(&policy.name)
+        );
+
+        match self.run::<RegisterResponse>(&request)? {
+            RegisterResponse {
+                error: Some(error), ..
+            } => Err(SignerError::External(error).into()),
+            RegisterResponse { hmac, .. } => Ok(hmac),
+        }
+    }
+
+    fn sign_tx(&self, psbt: &PSBT) -> Result<PSBT, SignerError> {
+        match self.run::<SignTxResponse>(&format!("signtx {}", psbt::to_base64(psbt)))? {
+            SignTxResponse {
+                error: Some(error), ..
+            } => Err(SignerError::External(error)),
+            SignTxResponse {
+                psbt: Some(psbt), ..
+            } => psbt::from_base64(&psbt).map_err(|_| {
+                SignerError::External(format!("`{}` returned an invalid PSBT", self.command))
+            }),
+            _ => Err(SignerError::External(format!(
+                "`{}` didn't return a PSBT",
+                self.command
+            ))),
+        }
+    }
+
+    // Send `request` to the program and parse the JSON object it prints
+    fn run<T: DeserializeOwned>(&self, request: &str) -> Result<T, SignerError> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .arg("--stdin")
+            .arg("--fingerprint")
+            .arg(self.fingerprint.to_string())
+            .arg("--chain")
+            .arg(self.chain())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| SignerError::External(format!("Can't run `{}`: {}", self.command, e)))?;
+
+        let stdout = read_pipe(child.stdout.take().expect("stdout is piped"));
+        let stderr = read_pipe(child.stderr.take().expect("stderr is piped"));
+
+        // dropping stdin closes it, so that the program knows the request is complete
+        {
+            let mut stdin = child.stdin.take().expect("stdin is piped");
+            writeln!(stdin, "{}", request).map_err(|e| {
+                SignerError::External(format!("Can't write to `{}`: {}", self.command, e))
+            })?;
+        }
+
+        if let Some(timeout) = self.timeout {
+            let start = Instant::now();
+            while child
+                .try_wait()
+                .map_err(|e| {
+                    SignerError::External(format!("Can't wait for `{}`: {}", self.command, e))
+                })?
+                .is_none()
+            {
+                if start.elapsed() >= timeout {
+                    // the process may have exited in the meantime, so ignore the errors
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(SignerError::Timeout);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let status = child.wait().map_err(|e| {
+            SignerError::External(format!("Can't wait for `{}`: {}", self.command, e))
+        })?;
+        let read_output = |reader: JoinHandle<io::Result<Vec<u8>>>| {
+            reader
+                .join()
+                .unwrap_or_else(|_| Err(io::ErrorKind::Other.into()))
+                .map_err(|e| {
+                    SignerError::External(format!("Can't read from `{}`: {}", self.command, e))
+                })
+        };
+        let stdout = read_output(stdout)?;
+        let stderr = read_output(stderr)?;
+
+        serde_json::from_slice(&stdout).map_err(|_| {
+            SignerError::External(format!(
+                "`{}` returned an invalid response ({}): {}",
+                self.command,
+                status,
+                String::from_utf8_lossy(&stderr).trim()
+            ))
+        })
+    }
+}
+
+impl Signer for ExternalSigner {
+    fn sign(
+        &self,
+        psbt: &mut PSBT,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        self.sign_with_progress(psbt, input_index, sign_options, &NoopSignerProgress, secp)
+    }
+
+    fn sign_with_progress(
+        &self,
+        psbt: &mut PSBT,
+        _input_index: Option<usize>,
+        _sign_options: &SignOptions,
+        progress: &dyn SignerProgress,
+        _secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        progress.awaiting_user_action(&format!(
+            "Confirm the transaction on the device `{}`",
+            self.fingerprint
+        ))?;
+
+        let signed = self.sign_tx(psbt)?;
+        psbt.merge(signed).map_err(|e| {
+            SignerError::External(format!(
+                "`{}` returned a different transaction: {}",
+                self.command, e
+            ))
+        })
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        true
+    }
+
+    fn requires_interaction(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::TxBuilder;
+
+    fn get_psbt() -> PSBT {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::new().add_recipient(addr.script_pubkey(), 25_000))
+            .unwrap();
+
+        psbt
+    }
+
+    fn shell_signer(script: &str) -> ExternalSigner {
+        ExternalSigner::new(
+            "sh",
+            Fingerprint::from_str("e30f11b8").unwrap(),
+            Network::Regtest,
+        )
+        .arg("-c")
+        .arg(script)
+        .arg("signer")
+    }
+
+    #[test]
+    fn test_external_signer_protocol() {
+        // check the arguments and send the PSBT back unchanged
+        let signer = shell_signer(
+            r#"[ "$*" = "--stdin --fingerprint e30f11b8 --chain regtest" ] || exit 1
+            read cmd psbt
+            [ "$cmd" = "signtx" ] || exit 1
+            echo "{\"psbt\": \"$psbt\"}""#,
+        );
+        let mut psbt = get_psbt();
+        let original = psbt.clone();
+
+        signer
+            .sign(
+                &mut psbt,
+                None,
+                &SignOptions::default(),
+                &bitcoin::secp256k1::Secp256k1::new(),
+            )
+            .unwrap();
+        assert_eq!(psbt, original);
+    }
+
+    #[test]
+    fn test_external_signer_error() {
+        let signer = shell_signer(r#"cat > /dev/null; echo '{"error": "Not connected"}'"#);
+
+        assert_eq!(
+            signer.sign(
+                &mut get_psbt(),
+                None,
+                &SignOptions::default(),
+                &bitcoin::secp256k1::Secp256k1::new()
+            ),
+            Err(SignerError::External("Not connected".into()))
+        );
+    }
+
+    #[test]
+    fn test_external_signer_invalid_response() {
+        let signer = shell_signer("cat > /dev/null; echo 'oops' >&2; exit 1");
+
+        assert!(matches!(
+            signer.sign(
+                &mut get_psbt(),
+                None,
+                &SignOptions::default(),
+                &bitcoin::secp256k1::Secp256k1::new()
+            ),
+            Err(SignerError::External(_))
+        ));
+    }
+
+    #[test]
+    fn test_external_signer_timeout() {
+        let signer = shell_signer("sleep 5").timeout(Duration::from_millis(100));
+
+        assert_eq!(
+            signer.sign(
+                &mut get_psbt(),
+                None,
+                &SignOptions::default(),
+                &bitcoin::secp256k1::Secp256k1::new()
+            ),
+            Err(SignerError::Timeout)
+        );
+    }
+
+    #[test]
+    fn test_external_signer_large_output() {
+        // more than a pipe can buffer is written before the program exits
+        let signer = shell_signer(
+            r#"head -c 200000 /dev/zero >&2; cat > /dev/null; echo '{"error": "Not connected"}'"#,
+        )
+        .timeout(Duration::from_secs(5));
+
+        assert_eq!(
+            signer.sign(
+                &mut get_psbt(),
+                None,
+                &SignOptions::default(),
+                &bitcoin::secp256k1::Secp256k1::new()
+            ),
+            Err(SignerError::External("Not connected".into()))
+        );
+    }
+
+    #[test]
+    fn test_external_signer_register_policy() {
+        let policy = WalletPolicy::new(
+            "Cold 'storage'",
+            "wpkh(@0/**)",
+            vec!["[73756c7f/48'/1'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3".to_string()],
+        )
+        .unwrap();
+        let descriptor = add_checksum(&policy.to_multipath_descriptor().unwrap()).unwrap();
+
+        // check that the descriptor and the name are read as single arguments
+        let signer = shell_signer(&format!(
+            r#"read line
+            eval "set -- $line"
+            [ "$#" = 5 ] && [ "$1" = register ] && [ "$3" = {} ] || exit 1
+            [ "$5" = {} ] || exit 1
+            echo '{{"hmac": "0011"}}'"#,
+            quote(&descriptor),
+            quote(&policy.name)
+        ));
+        assert_eq!(
+            signer.register_policy(&policy).unwrap(),
+            Some("0011".to_string())
+        );
+
+        let signer = shell_signer(r#"cat > /dev/null; echo '{"error": "Not supported"}'"#);
+        assert!(matches!(
+            signer.register_policy(&policy),
+            Err(Error::Signer(SignerError::External(ref e))) if e == "Not supported"
+        ));
+    }
+
+    #[test]
+    fn test_external_signer_awaiting_user_action() {
+        struct CancelProgress;
+        impl SignerProgress for CancelProgress {
+            fn update(&self, _progress: f32, _message: Option<String>) -> Result<(), SignerError> {
+                Ok(())
+            }
+
+            fn awaiting_user_action(&self, message: &str) -> Result<(), SignerError> {
+                assert!(message.contains("e30f11b8"));
+                Err(SignerError::UserCanceled)
+            }
+        }
+
+        // the command is never executed
+        let signer = shell_signer("exit 1");
+        assert_eq!(
+            signer.sign_with_progress(
+                &mut get_psbt(),
+                None,
+                &SignOptions::default(),
+                &CancelProgress,
+                &bitcoin::secp256k1::Secp256k1::new()
+            ),
+            Err(SignerError::UserCanceled)
+        );
+    }
+}
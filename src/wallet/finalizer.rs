@@ -0,0 +1,111 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! PSBT finalizer
+//!
+//! This module contains the [`Finalizer`], which builds the final `scriptSig` and witness of a
+//! PSBT input once enough signatures and preimages have been collected. It's kept separate from
+//! the [`signer`](super::signer) module so that a wallet without any signer of its own can still
+//! finalize PSBTs signed by third parties.
+
+use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+use bitcoin::TxIn;
+
+use miniscript::psbt::PsbtInputSatisfier;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace};
+
+use super::utils::{descriptor_to_pk_ctx, After, Older, SecpCtx};
+use crate::descriptor::ExtendedDescriptor;
+
+/// Builds the final `scriptSig` and witness of PSBT inputs
+///
+/// Timelocks are satisfied if either the `nLockTime`/`nSequence` of the transaction or the
+/// `current_height` and `median_time_past` are enough to reach them.
+pub(crate) struct Finalizer<'s> {
+    secp: &'s SecpCtx,
+    current_height: Option<u32>,
+    median_time_past: Option<u32>,
+}
+
+impl<'s> Finalizer<'s> {
+    /// Create a new finalizer, assuming the chain tip is at `current_height` and has the given
+    /// `median_time_past`
+    pub(crate) fn new(
+        secp: &'s SecpCtx,
+        current_height: Option<u32>,
+        median_time_past: Option<u32>,
+    ) -> Self {
+        Finalizer {
+            secp,
+            current_height,
+            median_time_past,
+        }
+    }
+
+    /// Return whether the input at `index` has already been finalized
+    pub(crate) fn is_finalized(psbt: &PSBT, index: usize) -> bool {
+        let psbt_input = &psbt.inputs[index];
+        psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some()
+    }
+
+    /// Try to finalize the input at `index`, which spends an output of `desc` confirmed at
+    /// `create_height`
+    ///
+    /// Returns `true` if the input has been finalized, `false` if the data in the PSBT is not
+    /// enough to satisfy the descriptor.
+    pub(crate) fn finalize_input(
+        &self,
+        psbt: &mut PSBT,
+        index: usize,
+        desc: &ExtendedDescriptor,
+        create_height: Option<u32>,
+    ) -> bool {
+        let mut tmp_input = TxIn::default();
+        let deriv_ctx = descriptor_to_pk_ctx(self.secp);
+        match desc.satisfy(
+            &mut tmp_input,
+            (
+                PsbtInputSatisfier::new(psbt, index),
+                After::new(self.current_height, self.median_time_past, false),
+                Older::new(self.current_height, create_height, false),
+            ),
+            deriv_ctx,
+        ) {
+            Ok(_) => {
+                let psbt_input = &mut psbt.inputs[index];
+                psbt_input.final_script_sig = Some(tmp_input.script_sig);
+                psbt_input.final_script_witness = Some(tmp_input.witness);
+
+                true
+            }
+            Err(e) => {
+                debug!("satisfy error {:?} for input {}", e, index);
+
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Labels
+//!
+//! This module allows attaching labels to transactions, addresses, inputs and outputs. The labels
+//! are stored in the wallet's [`database`](crate::database), and they can be exported and imported
+//! using the JSON Lines format described in
+//! [BIP-329](https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki), so that they can be
+//! moved to a different wallet software.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! let address = wallet.get_new_address()?;
+//! wallet.set_label(&LabelRef::Address(address.script_pubkey()), "Donations")?;
+//!
+//! let exported = wallet.export_labels()?;
+//! println!("{}", exported);
+//!
+//! // later, maybe on a different device
+//! let imported = wallet.import_labels(&exported)?;
+//! assert_eq!(imported, 1);
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use std::str::FromStr;
+
+use bitcoin::{Address, OutPoint, Txid};
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::BlockchainMarker;
+use crate::database::{BatchDatabase, BatchOperations};
+use crate::error::Error;
+use crate::types::LabelRef;
+use crate::wallet::Wallet;
+
+// A single line of a BIP-329 export
+#[derive(Debug, Serialize, Deserialize)]
+struct Bip329Record {
+    #[serde(rename = "type")]
+    label_type: String,
+    #[serde(rename = "ref")]
+    reference: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+fn parse_outpoint(reference: &str) -> Result<OutPoint, Error> {
+    OutPoint::from_str(reference)
+        .map_err(|_| Error::Generic(format!("Invalid BIP-329 reference: `{}`", reference)))
+}
+
+impl<B, D> Wallet<B, D>
+where
+    B: BlockchainMarker,
+    D: BatchDatabase,
+{
+    /// Attach a label to an object, replacing the previous one
+    pub fn set_label(&self, label_ref: &LabelRef, label: &str) -> Result<(), Error> {
+        self.database.borrow_mut().set_label(label_ref, label)
+    }
+
+    /// Return the label attached to an object
+    pub fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        self.database.borrow().get_label(label_ref)
+    }
+
+    /// Remove the label attached to an object, returning it
+    pub fn remove_label(&self, label_ref: &LabelRef) -> Result<Option<String>, Error> {
+        self.database.borrow_mut().del_label(label_ref)
+    }
+
+    /// Return the list of labels and the objects they are attached to
+    pub fn list_labels(&self) -> Result<Vec<(LabelRef, String)>, Error> {
+        self.database.borrow().iter_labels()
+    }
+
+    /// Export every label in the BIP-329 format, one JSON object per line
+    ///
+    /// This returns [`Error::ScriptDoesntHaveAddressForm`] if an address label is attached to a
+    /// script that can't be represented as an address.
+    pub fn export_labels(&self) -> Result<String, Error> {
+        let mut lines = vec![];
+        for (label_ref, label) in self.list_labels()? {
+            let (label_type, reference) = match label_ref {
+                LabelRef::Transaction(txid) => ("tx", txid.to_string()),
+                LabelRef::Address(script) => (
+                    "addr",
+                    Address::from_script(&script, self.network)
+                        .ok_or(Error::ScriptDoesntHaveAddressForm)?
+                        .to_string(),
+                ),
+                LabelRef::Input(outpoint) => ("input", outpoint.to_string()),
+                LabelRef::Output(outpoint) => ("output", outpoint.to_string()),
+            };
+
+            lines.push(serde_json::to_string(&Bip329Record {
+                label_type: label_type.to_string(),
+                reference,
+                label: Some(label),
+            })?);
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    /// Import labels in the BIP-329 format, returning the number of labels imported
+    ///
+    /// Existing labels attached to the same objects are replaced. Records of types that aren't
+    /// supported by the wallet (`pubkey` and `xpub`) and records without a label are skipped.
+    /// Nothing is imported if any of the lines can't be parsed.
+    pub fn import_labels(&self, labels: &str) -> Result<usize, Error> {
+        let mut batch = self.database.borrow().begin_batch();
+        let mut count = 0;
+
+        for line in labels.lines().filter(|line| !line.trim().is_empty()) {
+            let record: Bip329Record = serde_json::from_str(line)?;
+
+            let label_ref = match record.label_type.as_str() {
+                "tx" => LabelRef::Transaction(Txid::from_str(&record.reference)?),
+                "addr" => LabelRef::Address(
+                    Address::from_str(&record.reference)
+                        .map_err(|_| {
+                            Error::Generic(format!(
+                                "Invalid BIP-329 reference: `{}`",
+                                record.reference
+                            ))
+                        })?
+                        .script_pubkey(),
+                ),
+                "input" => LabelRef::Input(parse_outpoint(&record.reference)?),
+                "output" => LabelRef::Output(parse_outpoint(&record.reference)?),
+                _ => continue,
+            };
+
+            if let Some(label) = record.label {
+                batch.set_label(&label_ref, &label)?;
+                count += 1;
+            }
+        }
+
+        self.database.borrow_mut().commit_batch(batch)?;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use bitcoin::Network;
+
+    use super::*;
+    use crate::database::memory::MemoryDatabase;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::wallet::OfflineWallet;
+
+    #[test]
+    fn test_labels() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let tx_ref = LabelRef::Transaction(txid);
+
+        wallet.set_label(&tx_ref, "Salary").unwrap();
+        assert_eq!(wallet.get_label(&tx_ref).unwrap(), Some("Salary".into()));
+        assert_eq!(wallet.list_labels().unwrap().len(), 1);
+
+        assert_eq!(wallet.remove_label(&tx_ref).unwrap(), Some("Salary".into()));
+        assert_eq!(wallet.get_label(&tx_ref).unwrap(), None);
+    }
+
+    #[test]
+    fn test_export_import_labels() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let address = wallet.get_new_address().unwrap();
+
+        wallet
+            .set_label(&LabelRef::Transaction(txid), "Salary")
+            .unwrap();
+        wallet
+            .set_label(&LabelRef::Address(address.script_pubkey()), "Donations")
+            .unwrap();
+        wallet
+            .set_label(&LabelRef::Output(OutPoint { txid, vout: 0 }), "Cold")
+            .unwrap();
+
+        let exported = wallet.export_labels().unwrap();
+        assert_eq!(exported.lines().count(), 3);
+        assert!(exported.contains(&format!(
+            r#"{{"type":"addr","ref":"{}","label":"Donations"}}"#,
+            address
+        )));
+        assert!(exported.contains(&format!(
+            r#"{{"type":"output","ref":"{}:0","label":"Cold"}}"#,
+            txid
+        )));
+
+        let other: OfflineWallet<_> = Wallet::new_offline(
+            get_test_wpkh(),
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        assert_eq!(other.import_labels(&exported).unwrap(), 3);
+        assert_eq!(
+            other
+                .list_labels()
+                .unwrap()
+                .into_iter()
+                .collect::<HashSet<_>>(),
+            wallet
+                .list_labels()
+                .unwrap()
+                .into_iter()
+                .collect::<HashSet<_>>()
+        );
+    }
+
+    #[test]
+    fn test_import_labels_skip_unsupported() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+
+        let labels = format!(
+            concat!(
+                r#"{{"type":"xpub","ref":"tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq","label":"Main"}}"#,
+                "\n\n",
+                r#"{{"type":"tx","ref":"{txid}"}}"#,
+                "\n",
+                r#"{{"type":"tx","ref":"{txid}","label":"Salary","origin":"wpkh([d34db33f/84'/1'/0'])"}}"#,
+                "\n",
+            ),
+            txid = txid
+        );
+        assert_eq!(wallet.import_labels(&labels).unwrap(), 1);
+        assert_eq!(
+            wallet.get_label(&LabelRef::Transaction(txid)).unwrap(),
+            Some("Salary".into())
+        );
+    }
+
+    #[test]
+    fn test_import_labels_invalid() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+
+        let labels = format!(
+            concat!(
+                r#"{{"type":"tx","ref":"{}","label":"Salary"}}"#,
+                "\n",
+                r#"{{"type":"output","ref":"not an outpoint","label":"Cold"}}"#,
+            ),
+            txid
+        );
+        assert!(matches!(
+            wallet.import_labels(&labels),
+            Err(Error::Generic(_))
+        ));
+        // nothing is imported
+        assert!(wallet.list_labels().unwrap().is_empty());
+    }
+}
@@ -26,56 +26,117 @@
 //!
 //! This module defines the [`Wallet`] structure.
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::collections::{BTreeMap, HashSet};
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
 use std::sync::Arc;
 
 use bitcoin::secp256k1::Secp256k1;
 
 use bitcoin::consensus::encode::serialize;
 use bitcoin::util::base58;
-use bitcoin::util::bip32::ChildNumber;
+use bitcoin::util::bip32::{ChildNumber, KeySource};
+use bitcoin::util::psbt;
 use bitcoin::util::psbt::raw::Key as PSBTKey;
 use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
-use bitcoin::{Address, Network, OutPoint, Script, Transaction, TxOut, Txid};
+use bitcoin::{
+    Address, Network, OutPoint, PrivateKey, PublicKey, Script, SigHashType, Transaction, TxIn,
+    TxOut, Txid,
+};
 
-use miniscript::psbt::PsbtInputSatisfier;
+use miniscript::descriptor::{DescriptorPublicKey, DescriptorSecretKey};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace};
 
 pub mod address_validator;
+pub mod airgap;
+pub mod batch;
 pub mod coin_selection;
 pub mod export;
+#[cfg(feature = "external-signer")]
+#[cfg_attr(docsrs, doc(cfg(feature = "external-signer")))]
+pub mod external_signer;
+pub(crate) mod finalizer;
+pub mod labels;
+pub mod policy_signer;
+pub mod reserves;
 pub mod signer;
+pub mod silent_payments;
 pub mod time;
 pub mod tx_builder;
+pub mod tx_graph;
 pub(crate) mod utils;
+#[cfg(feature = "verify")]
+#[cfg_attr(docsrs, doc(cfg(feature = "verify")))]
+pub mod verify;
 
-pub use utils::IsDust;
+pub use utils::{DustLimit, IsDust};
 
 use address_validator::AddressValidator;
-use signer::{Signer, SignerId, SignerOrdering, SignersContainer};
+use finalizer::Finalizer;
+#[cfg(feature = "async-interface")]
+use signer::AsyncSigner;
+use signer::{
+    noop_signer_progress, AnySigner, MessageSignature, SignOptions, Signer, SignerError, SignerId,
+    SignerOrdering, SignerProgress, SignersContainer,
+};
 use tx_builder::{BumpFee, CreateTx, FeePolicy, TxBuilder, TxBuilderContext};
-use utils::{check_nlocktime, check_nsequence_rbf, descriptor_to_pk_ctx, After, Older, SecpCtx};
+use utils::{
+    check_nlocktime, check_nsequence_rbf, decoy_output_values, descriptor_to_pk_ctx,
+    recipient_details, split_change_value, tx_rng, SecpCtx,
+};
 
-use crate::blockchain::{Blockchain, BlockchainMarker, OfflineBlockchain, Progress};
+use crate::blockchain::{
+    Blockchain, BlockchainMarker, Capability, OfflineBlockchain, Progress, StaticTip, TipOracle,
+};
+#[cfg(feature = "async-interface")]
+use crate::blockchain::{SyncStream, SyncUpdate};
 use crate::database::{BatchDatabase, BatchOperations, DatabaseUtils};
+use crate::descriptor::wallet_policy::WalletPolicy;
 use crate::descriptor::{
-    get_checksum, DescriptorMeta, DescriptorScripts, ExtendedDescriptor, ExtractPolicy, Policy,
+    self, derive_script_pubkeys, get_checksum, DescriptorKeyOrigins, DescriptorMeta,
+    DescriptorScripts, ExtendedDescriptor, ExtractPolicy, Policy, SatisfactionWeight,
     ToWalletDescriptor, XKeyUtils,
 };
 use crate::error::Error;
+use crate::psbt::proprietary::{BdkMetadata, MetadataError};
 use crate::psbt::PSBTUtils;
 use crate::types::*;
 
 const CACHE_ADDR_BATCH_SIZE: u32 = 100;
+// Highest non-hardened BIP32 child index
+const MAX_DERIVABLE_INDEX: u32 = (1 << 31) - 1;
 
 /// Type alias for a [`Wallet`] that uses [`OfflineBlockchain`]
 pub type OfflineWallet<D> = Wallet<OfflineBlockchain, D>;
 
+/// Policy used to pick the change address of new transactions
+///
+/// By default every transaction created by the wallet derives a fresh change address, even if the
+/// transaction is later discarded. Wallets that create and throw away many transactions, for
+/// example because of retries or failed broadcasts, can end up handing out change addresses past
+/// the gap limit used during [`sync`](Wallet::sync), which makes funds sent to them invisible.
+///
+/// See [`Wallet::set_change_index_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeIndexPolicy {
+    /// Always derive a fresh change address (default)
+    Fresh,
+    /// Reuse the last derived change address, as long as it hasn't received any funds yet
+    ///
+    /// **Note**: the wallet only knows about the transactions in its database, so it should be
+    /// synced after broadcasting a transaction to avoid reusing its change address.
+    ReuseUnused,
+}
+
+impl Default for ChangeIndexPolicy {
+    fn default() -> Self {
+        ChangeIndexPolicy::Fresh
+    }
+}
+
 /// A Bitcoin wallet
 ///
 /// A wallet takes descriptors, a [`database`](trait@crate::database::Database) and a
@@ -89,15 +150,26 @@ pub type OfflineWallet<D> = Wallet<OfflineBlockchain, D>;
 pub struct Wallet<B, D> {
     descriptor: ExtendedDescriptor,
     change_descriptor: Option<ExtendedDescriptor>,
+    // whether the `change_descriptor` has been derived from `descriptor`
+    change_derived: bool,
 
     signers: Arc<SignersContainer>,
     change_signers: Arc<SignersContainer>,
 
     address_validators: Vec<Arc<dyn AddressValidator>>,
+    avoid_address_reuse: bool,
+    change_index_policy: ChangeIndexPolicy,
+    dust_limit: DustLimit,
+    max_fee_rate: Option<FeeRate>,
+    max_fee_absolute: Option<u64>,
+    coin_selection: Option<Box<dyn coin_selection::CoinSelectionAlgorithm<D> + Send + Sync>>,
 
     network: Network,
 
-    current_height: Option<u32>,
+    tip: Cell<Option<StaticTip>>,
+    tip_oracle: Option<Box<dyn TipOracle>>,
+
+    silent_payment_receiver: Option<(silent_payments::SilentPaymentReceiver, u32)>,
 
     client: Option<B>,
     database: RefCell<D>,
@@ -112,21 +184,39 @@ where
     D: BatchDatabase,
 {
     /// Create a new "offline" wallet
+    ///
+    /// The checksums of the descriptors are stored in the `database` the first time it's used,
+    /// and this method returns [`Error::ChecksumMismatch`] if the database has been created for
+    /// different descriptors.
+    ///
+    /// If the `descriptor` contains multipath derivation steps like `<0;1>`, it's split into the
+    /// external and the change descriptor (see
+    /// [`split_multipath_descriptor`](crate::descriptor::split_multipath_descriptor)), and the
+    /// `change_descriptor` must be `None`.
     pub fn new_offline<E: ToWalletDescriptor>(
         descriptor: E,
         change_descriptor: Option<E>,
         network: Network,
         mut database: D,
     ) -> Result<Self, Error> {
-        let (descriptor, keymap) = descriptor.to_wallet_descriptor(network)?;
+        let ((descriptor, keymap), multipath_change_descriptor) =
+            descriptor.to_wallet_descriptors(network)?;
         database.check_descriptor_checksum(
             KeychainKind::External,
             get_checksum(&descriptor.to_string())?.as_bytes(),
         )?;
         let signers = Arc::new(SignersContainer::from(keymap));
+        let change_descriptor = match (change_descriptor, multipath_change_descriptor) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Generic(
+                    "The change descriptor is already part of the multipath descriptor".to_string(),
+                ))
+            }
+            (Some(desc), None) => Some(desc.to_wallet_descriptor(network)?),
+            (None, multipath_change_descriptor) => multipath_change_descriptor,
+        };
         let (change_descriptor, change_signers) = match change_descriptor {
-            Some(desc) => {
-                let (change_descriptor, change_keymap) = desc.to_wallet_descriptor(network)?;
+            Some((change_descriptor, change_keymap)) => {
                 database.check_descriptor_checksum(
                     KeychainKind::Internal,
                     get_checksum(&change_descriptor.to_string())?.as_bytes(),
@@ -145,13 +235,23 @@ where
         Ok(Wallet {
             descriptor,
             change_descriptor,
+            change_derived: false,
             signers,
             change_signers,
             address_validators: Vec::new(),
+            avoid_address_reuse: false,
+            change_index_policy: ChangeIndexPolicy::default(),
+            dust_limit: DustLimit::default(),
+            max_fee_rate: None,
+            max_fee_absolute: None,
+            coin_selection: None,
 
             network,
 
-            current_height: None,
+            tip: Cell::new(None),
+            tip_oracle: None,
+
+            silent_payment_receiver: None,
 
             client: None,
             database: RefCell::new(database),
@@ -171,6 +271,36 @@ where
             .ok_or(Error::ScriptDoesntHaveAddressForm)
     }
 
+    /// Return the addresses of `keychain` at every child index in `range`
+    ///
+    /// The addresses are returned in the same order as the indexes, and the wallet state is not
+    /// changed: the last index used by [`Wallet::get_new_address`] isn't incremented. With the
+    /// `parallel-derivation` feature the addresses are derived in parallel, see
+    /// [`derive_script_pubkeys`](crate::descriptor::derive_script_pubkeys).
+    ///
+    /// A fixed descriptor only has one address, at index `0`.
+    pub fn list_addresses(
+        &self,
+        keychain: KeychainKind,
+        range: Range<u32>,
+    ) -> Result<Vec<Address>, Error> {
+        let (descriptor, _) = self.get_descriptor_for_keychain(keychain);
+        let range = match descriptor.is_fixed() {
+            true => range.start.min(1)..range.end.min(1),
+            false => range,
+        };
+        if range.end > MAX_DERIVABLE_INDEX + 1 {
+            return Err(Error::InvalidDerivationIndex(range.end - 1));
+        }
+
+        derive_script_pubkeys(descriptor, range, &self.secp)?
+            .iter()
+            .map(|script| {
+                Address::from_script(script, self.network).ok_or(Error::ScriptDoesntHaveAddressForm)
+            })
+            .collect()
+    }
+
     /// Return whether or not a `script` is part of this wallet (either internal or external)
     pub fn is_mine(&self, script: &Script) -> Result<bool, Error> {
         self.database.borrow().is_mine(script)
@@ -184,6 +314,65 @@ where
         self.database.borrow().iter_utxos()
     }
 
+    /// Return the list of unspent silent payments received by this wallet
+    ///
+    /// These outputs are only found if a receiver has been set with
+    /// [`Wallet::set_silent_payment_receiver`], and they are not part of [`Wallet::list_unspent`]
+    /// since the wallet can't spend them yet. See the
+    /// [`silent_payments`](silent_payments) module for more details.
+    pub fn list_silent_payment_outputs(
+        &self,
+    ) -> Result<Vec<silent_payments::SilentPaymentOutput>, Error> {
+        self.database.borrow().iter_silent_payment_outputs()
+    }
+
+    /// Return the list of unspent outputs of this wallet as of block `height`
+    ///
+    /// The UTXO set is reconstructed from the transactions stored in the database, considering
+    /// only the ones confirmed at or below `height`. This can be used, for instance, to compute
+    /// the balance of the wallet at the end of a year.
+    ///
+    /// Note that this method only operates on the internal database, which first needs to be
+    /// [`Wallet::sync`] manually. It returns [`Error::TransactionNotFound`] if the raw version of
+    /// one of the transactions is not available.
+    pub fn utxos_at_height(&self, height: u32) -> Result<Vec<UTXO>, Error> {
+        let database = self.database.borrow();
+
+        let txs = database
+            .iter_txs(true)?
+            .into_iter()
+            .filter(|details| matches!(details.height, Some(h) if h <= height))
+            .map(|details| details.transaction.ok_or(Error::TransactionNotFound))
+            .collect::<Result<Vec<_>, _>>()?;
+        let spent = txs
+            .iter()
+            .flat_map(|tx| tx.input.iter().map(|txin| txin.previous_output))
+            .collect::<HashSet<_>>();
+
+        let mut utxos = vec![];
+        for tx in &txs {
+            let txid = tx.txid();
+            for (vout, txout) in tx.output.iter().enumerate() {
+                let outpoint = OutPoint::new(txid, vout as u32);
+                if spent.contains(&outpoint) {
+                    continue;
+                }
+
+                if let Some((keychain, _)) =
+                    database.get_path_from_script_pubkey(&txout.script_pubkey)?
+                {
+                    utxos.push(UTXO {
+                        outpoint,
+                        txout: txout.clone(),
+                        keychain,
+                    });
+                }
+            }
+        }
+
+        Ok(utxos)
+    }
+
     /// Return the list of transactions made and received by the wallet
     ///
     /// Optionally fill the [`TransactionDetails::transaction`] field with the raw transaction if
@@ -195,15 +384,141 @@ where
         self.database.borrow().iter_txs(include_raw)
     }
 
+    /// Return the details of a transaction of the wallet, or `None` if it's unknown
+    ///
+    /// Optionally fill the [`TransactionDetails::transaction`] field with the raw transaction if
+    /// `include_raw` is `true`.
+    ///
+    /// Note that this methods only operate on the internal database, which first needs to be
+    /// [`Wallet::sync`] manually.
+    pub fn get_tx(
+        &self,
+        txid: &Txid,
+        include_raw: bool,
+    ) -> Result<Option<TransactionDetails>, Error> {
+        self.database.borrow().get_tx(txid, include_raw)
+    }
+
     /// Return the balance, meaning the sum of this wallet's unspent outputs' values
     ///
+    /// The silent payments received, listed by [`Wallet::list_silent_payment_outputs`], are part
+    /// of the balance too.
+    ///
     /// Note that this methods only operate on the internal database, which first needs to be
     /// [`Wallet::sync`] manually.
     pub fn get_balance(&self) -> Result<u64, Error> {
+        let silent_payments = self
+            .list_silent_payment_outputs()?
+            .iter()
+            .fold(0, |sum, i| sum + i.txout.value);
+
         Ok(self
             .list_unspent()?
             .iter()
-            .fold(0, |sum, i| sum + i.txout.value))
+            .fold(silent_payments, |sum, i| sum + i.txout.value))
+    }
+
+    /// Return the highest derivation index that can be used to generate addresses for `keychain`
+    ///
+    /// Descriptors without a wildcard always derive the same script, so `0` is returned for them.
+    /// Otherwise the limit is `2^31 - 1`, the highest non-hardened BIP32 child index: trying to
+    /// go past it returns an [`Error::InvalidDerivationIndex`].
+    pub fn max_derivable_index(&self, keychain: KeychainKind) -> u32 {
+        let (descriptor, _) = self.get_descriptor_for_keychain(keychain);
+        match descriptor.is_fixed() {
+            true => 0,
+            false => MAX_DERIVABLE_INDEX,
+        }
+    }
+
+    /// Export the private key used by the address at `index` of `keychain`
+    ///
+    /// The returned key can be printed in the WIF format and imported into a different software,
+    /// which is sometimes needed for interoperability, for instance to claim the coins of a fork
+    /// of the chain. **This is dangerous**: anyone who knows the key can spend the funds sent to
+    /// that address, and in the case of a non-hardened child key it can also be combined with the
+    /// wallet's extended public key to compute the private keys for every other address.
+    ///
+    /// Only descriptors made of a single key are supported. An error is returned if the wallet
+    /// doesn't know the private key for `keychain`.
+    #[cfg(feature = "dangerous-ops")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "dangerous-ops")))]
+    pub fn export_private_key(
+        &self,
+        keychain: KeychainKind,
+        index: u32,
+    ) -> Result<PrivateKey, Error> {
+        let (descriptor, keychain) = self.get_descriptor_for_keychain(keychain);
+
+        let mut keys = descriptor.get_keys()?;
+        if keys.len() != 1 {
+            return Err(Error::Generic(
+                "Only the private key of single-key descriptors can be exported".into(),
+            ));
+        }
+
+        self.derive_private_key(keychain, &keys.remove(0), index)?
+            .ok_or_else(|| {
+                Error::Generic(format!(
+                    "The wallet doesn't have the private key for the {} keychain",
+                    keychain
+                ))
+            })
+    }
+
+    /// Sign a message with the key used by the address at `index` of `keychain`
+    ///
+    /// The signers of `keychain` are tried in order until one of them supports
+    /// [`Signer::sign_message`] and has the right key, which makes it possible to prove the
+    /// ownership of an address with a hardware or a custom signer too. Only descriptors made of a
+    /// single key are supported.
+    ///
+    /// The signature is recoverable, and [`MessageSignature::to_base64`] encodes it like Bitcoin
+    /// Core's `signmessage`.
+    pub fn sign_message(
+        &self,
+        keychain: KeychainKind,
+        index: u32,
+        message: &str,
+    ) -> Result<MessageSignature, Error> {
+        let (descriptor, keychain) = self.get_descriptor_for_keychain(keychain);
+        let signers = match keychain {
+            KeychainKind::External => &self.signers,
+            KeychainKind::Internal => &self.change_signers,
+        };
+
+        let mut keys = descriptor.get_keys()?;
+        if keys.len() != 1 {
+            return Err(Error::Generic(
+                "Only single-key descriptors can sign messages".into(),
+            ));
+        }
+
+        let (public_key, key_source) = match keys.remove(0) {
+            DescriptorPublicKey::SinglePub(single) => {
+                (single.key, signer::single_key_source(&single.key))
+            }
+            DescriptorPublicKey::XPub(_) => descriptor
+                .get_hd_keypaths(index, &self.secp)?
+                .into_iter()
+                .next()
+                .expect("The descriptor contains an extended key"),
+        };
+
+        // a signer that signs with the wrong key doesn't stop the others from being tried
+        let mut error = SignerError::MissingKey;
+        for signer in signers.signers() {
+            match signer.sign_message(message, &key_source, &self.secp) {
+                Ok(signature) if signature.public_key == public_key => return Ok(signature),
+                Ok(_) => error = SignerError::InvalidKey,
+                Err(SignerError::MissingKey) | Err(SignerError::MessageSigningNotSupported) => {
+                    continue
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(error.into())
     }
 
     /// Add an external signer
@@ -224,6 +539,217 @@ where
         signers.add_external(id, ordering, signer);
     }
 
+    /// Add an async signer, which is only used by [`Wallet::sign_async`]
+    #[cfg(feature = "async-interface")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+    pub fn add_async_signer(
+        &mut self,
+        keychain: KeychainKind,
+        id: SignerId,
+        ordering: SignerOrdering,
+        signer: Arc<dyn AsyncSigner>,
+    ) {
+        let signers = match keychain {
+            KeychainKind::External => Arc::make_mut(&mut self.signers),
+            KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
+        };
+
+        signers.add_async(id, ordering, signer);
+    }
+
+    /// Remove all the signers registered with `id` for `keychain`, regardless of their
+    /// [`SignerOrdering`], and return them
+    pub fn remove_signer(&mut self, keychain: KeychainKind, id: SignerId) -> Vec<AnySigner> {
+        let signers = match keychain {
+            KeychainKind::External => Arc::make_mut(&mut self.signers),
+            KeychainKind::Internal => Arc::make_mut(&mut self.change_signers),
+        };
+
+        signers.remove_by_id(id)
+    }
+
+    /// Return the signers registered for `keychain`
+    ///
+    /// See [`SignersContainer::entries`] to list them with their id and ordering.
+    pub fn get_signers(&self, keychain: KeychainKind) -> &SignersContainer {
+        match keychain {
+            KeychainKind::External => &self.signers,
+            KeychainKind::Internal => &self.change_signers,
+        }
+    }
+
+    /// Set the source of the current tip of the chain, and fetch the tip from it
+    ///
+    /// The tip is used to set the default nLockTime of new transactions, to check whether the
+    /// timelocks of the wallet's descriptors have expired when finalizing a PSBT or analyzing a
+    /// policy (see [`Wallet::get_chain_state`]) and to tell whether a transaction can be
+    /// broadcast (see [`Wallet::is_final_tx`]). It takes precedence over the height fetched from
+    /// the blockchain, and it makes it possible to use timelocks with an offline wallet.
+    ///
+    /// The tip is cached by the wallet: call [`Wallet::update_tip`] to fetch it again. Online
+    /// wallets also refresh it at the end of every [`sync`](Wallet::sync).
+    #[maybe_async]
+    pub fn set_tip_oracle<T: 'static + TipOracle>(&mut self, oracle: T) -> Result<(), Error> {
+        self.tip_oracle = Some(Box::new(oracle));
+        maybe_await!(self.update_tip())
+    }
+
+    /// Fetch the current tip of the chain from the wallet's [`TipOracle`]
+    ///
+    /// Does nothing if no oracle has been set with [`Wallet::set_tip_oracle`].
+    #[maybe_async]
+    pub fn update_tip(&self) -> Result<(), Error> {
+        if let Some(oracle) = &self.tip_oracle {
+            let height = maybe_await!(oracle.get_tip_height())?;
+            let median_time_past = maybe_await!(oracle.get_tip_median_time_past())?;
+            self.tip.set(Some(StaticTip {
+                height,
+                median_time_past,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Return the last known tip of the chain, if any
+    pub fn get_tip(&self) -> Option<StaticTip> {
+        self.tip.get()
+    }
+
+    /// Return the [`ChainState`](crate::descriptor::policy::ChainState) used to check which
+    /// branches of the wallet's [policies](Wallet::policies) can be satisfied when spending
+    /// `outpoints`
+    ///
+    /// Returns `None` if the tip of the chain is not known. The coins are considered unconfirmed
+    /// if any of them is. Since the wallet doesn't store the time of the blocks, time-based
+    /// relative timelocks are never reported as expired, and time-based absolute timelocks are
+    /// only checked if the tip has a known median time past.
+    pub fn get_chain_state(
+        &self,
+        outpoints: &[OutPoint],
+    ) -> Result<Option<descriptor::policy::ChainState>, Error> {
+        let tip = match self.tip.get() {
+            Some(tip) => tip,
+            None => return Ok(None),
+        };
+
+        let mut coins_height = Some(0);
+        for outpoint in outpoints {
+            let height = self
+                .database
+                .borrow()
+                .get_tx(&outpoint.txid, false)?
+                .ok_or(Error::UnknownUTXO)?
+                .height;
+            coins_height = match (coins_height, height) {
+                (Some(coins_height), Some(height)) => Some(std::cmp::max(coins_height, height)),
+                _ => None,
+            };
+        }
+
+        Ok(Some(descriptor::policy::ChainState {
+            height: tip.height,
+            median_time_past: tip.median_time_past.unwrap_or(0),
+            coins_height,
+            coins_median_time_past: None,
+        }))
+    }
+
+    /// Return whether `tx` can be included in the block that follows the current tip
+    ///
+    /// Only the nLockTime of the transaction is checked, following BIP113 for time-based
+    /// locktimes. Applications can use this to schedule the broadcast of transactions that are
+    /// timelocked, which [`broadcast`](Wallet::broadcast) refuses until they are final.
+    ///
+    /// Returns `None` if the tip of the chain is not known, or if the locktime is time-based and
+    /// the median time past of the tip is not known.
+    pub fn is_final_tx(&self, tx: &Transaction) -> Option<bool> {
+        let tip = self.tip.get()?;
+        if tx.lock_time == 0 || tx.input.iter().all(|txin| txin.sequence == 0xFFFFFFFF) {
+            return Some(true);
+        }
+
+        if tx.lock_time < utils::BLOCKS_TIMELOCK_THRESHOLD {
+            Some(tx.lock_time <= tip.height)
+        } else {
+            tip.median_time_past.map(|mtp| tx.lock_time < mtp)
+        }
+    }
+
+    /// Set the rule used to decide whether the outputs of new transactions are dust
+    ///
+    /// This can be overridden for a single transaction with [`TxBuilder::dust_limit`]. See
+    /// [`DustLimit`] for the default.
+    pub fn set_dust_limit(&mut self, dust_limit: DustLimit) {
+        self.dust_limit = dust_limit;
+    }
+
+    /// Set the maximum fee rate that transactions created or signed by the wallet can pay
+    ///
+    /// Creating, bumping the fees of or signing a transaction with a higher fee rate fails with
+    /// [`Error::FeeRateTooHigh`]. When signing, the limit is only checked if the size of every
+    /// input can be estimated. There's no limit by default, and it can be overridden for a single
+    /// transaction with [`TxBuilder::max_fee_rate`].
+    pub fn set_max_fee_rate(&mut self, max_fee_rate: Option<FeeRate>) {
+        self.max_fee_rate = max_fee_rate;
+    }
+
+    /// Set the maximum absolute fee that transactions created or signed by the wallet can pay
+    ///
+    /// Creating, bumping the fees of or signing a transaction with a higher fee fails with
+    /// [`Error::FeeTooHigh`]. There's no limit by default, and it can be overridden for a single
+    /// transaction with [`TxBuilder::max_fee_absolute`].
+    pub fn set_max_fee_absolute(&mut self, max_fee_absolute: Option<u64>) {
+        self.max_fee_absolute = max_fee_absolute;
+    }
+
+    /// Scan the blockchain for the silent payments received by `receiver`
+    ///
+    /// Silent payments can't be found by looking for the wallet's script_pubkeys, so at the end
+    /// of every [`sync`](Wallet::sync) the new blocks are downloaded and scanned, starting from
+    /// `from_height` the first time. This requires a backend that supports
+    /// [`Capability::BlockTransactions`], otherwise the sync fails. The height of the next block
+    /// to scan is stored in the database and it takes precedence over `from_height`.
+    ///
+    /// The outputs found are stored in the database with the tweak needed to compute their
+    /// private keys, and they are listed by [`Wallet::list_silent_payment_outputs`].
+    pub fn set_silent_payment_receiver(
+        &mut self,
+        receiver: silent_payments::SilentPaymentReceiver,
+        from_height: u32,
+    ) {
+        self.silent_payment_receiver = Some((receiver, from_height));
+    }
+
+    /// Set the coin selection algorithm used by default when creating transactions or bumping
+    /// their fees
+    ///
+    /// It replaces the [`DefaultCoinSelectionAlgorithm`](coin_selection::DefaultCoinSelectionAlgorithm)
+    /// for every [`TxBuilder`] that doesn't explicitly choose an algorithm with
+    /// [`TxBuilder::coin_selection`].
+    pub fn set_coin_selection<Cs>(&mut self, coin_selection: Cs)
+    where
+        Cs: 'static + coin_selection::CoinSelectionAlgorithm<D> + Send + Sync,
+    {
+        self.coin_selection = Some(Box::new(coin_selection));
+    }
+
+    // Coin selection algorithm to use with `builder`: the one set in the builder if it was chosen
+    // explicitly, otherwise the wallet's default
+    fn get_coin_selection<
+        'a,
+        Cs: coin_selection::CoinSelectionAlgorithm<D>,
+        Ctx: TxBuilderContext,
+    >(
+        &'a self,
+        builder: &'a TxBuilder<D, Cs, Ctx>,
+    ) -> &'a dyn coin_selection::CoinSelectionAlgorithm<D> {
+        match &self.coin_selection {
+            Some(coin_selection) if !builder.custom_coin_selection => coin_selection.as_ref(),
+            _ => &builder.coin_selection,
+        }
+    }
+
     /// Add an address validator
     ///
     /// See [the `address_validator` module](address_validator) for an example.
@@ -231,6 +757,78 @@ where
         self.address_validators.push(validator);
     }
 
+    /// Enable or disable address reuse avoidance
+    ///
+    /// When enabled, [`get_new_address`](Wallet::get_new_address) and the change outputs created
+    /// by the wallet skip every derivation index whose script has already received funds
+    /// according to the database. This has no effect on descriptors without a wildcard.
+    pub fn set_avoid_address_reuse(&mut self, avoid: bool) {
+        self.avoid_address_reuse = avoid;
+    }
+
+    /// Set the [`ChangeIndexPolicy`] used by [`create_tx`](Wallet::create_tx) and
+    /// [`bump_fee`](Wallet::bump_fee) to pick the change address
+    ///
+    /// Only the first change output of a transaction can reuse a previous address, additional
+    /// change outputs like the ones created by [`TxBuilder::split_change`] or
+    /// [`TxBuilder::add_decoy_outputs`] always use fresh addresses. Wallets without a change
+    /// descriptor never reuse their change address, since it could be an external address that
+    /// has been shared with someone else.
+    pub fn set_change_index_policy(&mut self, policy: ChangeIndexPolicy) {
+        self.change_index_policy = policy;
+    }
+
+    /// Enable or disable the automatic derivation of the change descriptor
+    ///
+    /// A wallet created without a change descriptor normally sends the change back to its external
+    /// descriptor. When this option is enabled the wallet instead uses the conventional internal
+    /// descriptor derived with [`derive_internal_descriptor`](crate::descriptor::derive_internal_descriptor),
+    /// which replaces `/0/*` with `/1/*` in every extended key. Disabling it goes back to sending
+    /// the change to the external descriptor.
+    ///
+    /// Returns an error if the wallet was created with an explicit change descriptor, or if the
+    /// structure of the external descriptor doesn't allow deriving an internal one.
+    pub fn set_derive_change_descriptor(&mut self, enabled: bool) -> Result<(), Error> {
+        match (
+            enabled,
+            self.change_descriptor.is_some(),
+            self.change_derived,
+        ) {
+            (_, true, false) => Err(Error::Generic(
+                "The wallet already has an explicit change descriptor".into(),
+            )),
+            (true, true, true) | (false, false, _) => Ok(()),
+            (true, false, _) => {
+                let (change_descriptor, change_keymap) = descriptor::derive_internal_descriptor(
+                    &self.descriptor,
+                    &self.signers.as_key_map(&self.secp),
+                )
+                .ok_or_else(|| {
+                    Error::Generic(
+                        "Unable to derive an internal descriptor from the external one".into(),
+                    )
+                })?;
+                self.database.borrow_mut().check_descriptor_checksum(
+                    KeychainKind::Internal,
+                    get_checksum(&change_descriptor.to_string())?.as_bytes(),
+                )?;
+
+                self.change_descriptor = Some(change_descriptor);
+                self.change_signers = Arc::new(SignersContainer::from(change_keymap));
+                self.change_derived = true;
+
+                Ok(())
+            }
+            (false, true, true) => {
+                self.change_descriptor = None;
+                self.change_signers = Arc::new(SignersContainer::new());
+                self.change_derived = false;
+
+                Ok(())
+            }
+        }
+    }
+
     /// Create a new transaction following the options specified in the `builder`
     ///
     /// ## Example
@@ -253,288 +851,55 @@ where
         &self,
         builder: TxBuilder<D, Cs, CreateTx>,
     ) -> Result<(PSBT, TransactionDetails), Error> {
-        let external_policy = self
-            .descriptor
-            .extract_policy(&self.signers, &self.secp)?
-            .unwrap();
-        let internal_policy = self
-            .change_descriptor
-            .as_ref()
-            .map(|desc| {
-                Ok::<_, Error>(
-                    desc.extract_policy(&self.change_signers, &self.secp)?
-                        .unwrap(),
-                )
-            })
-            .transpose()?;
+        let (tx, selected, transaction_details, _) = self.build_tx(&builder, false)?;
+        self.check_max_fee(
+            transaction_details.fees,
+            Some(self.estimate_signed_weight(&tx, &selected, &builder.foreign_utxos)?),
+            builder.max_fee_rate.or(self.max_fee_rate),
+            builder.max_fee_absolute.or(self.max_fee_absolute),
+        )?;
+        let psbt = self.complete_transaction(tx, selected, builder)?;
 
-        // The policy allows spending external outputs, but it requires a policy path that hasn't been
-        // provided
-        if builder.change_policy != tx_builder::ChangeSpendPolicy::OnlyChange
-            && external_policy.requires_path()
-            && builder.external_policy_path.is_none()
-        {
-            return Err(Error::SpendingPolicyRequired(KeychainKind::External));
-        };
-        // Same for the internal_policy path, if present
-        if let Some(internal_policy) = &internal_policy {
-            if builder.change_policy != tx_builder::ChangeSpendPolicy::ChangeForbidden
-                && internal_policy.requires_path()
-                && builder.internal_policy_path.is_none()
-            {
-                return Err(Error::SpendingPolicyRequired(KeychainKind::Internal));
-            };
-        }
+        Ok((psbt, transaction_details))
+    }
 
-        let external_requirements = external_policy.get_condition(
-            builder
-                .external_policy_path
-                .as_ref()
-                .unwrap_or(&BTreeMap::new()),
-        )?;
-        let internal_requirements = internal_policy
-            .map(|policy| {
-                Ok::<_, Error>(
-                    policy.get_condition(
-                        builder
-                            .internal_policy_path
-                            .as_ref()
-                            .unwrap_or(&BTreeMap::new()),
-                    )?,
-                )
-            })
-            .transpose()?;
-
-        let requirements = external_requirements
-            .clone()
-            .merge(&internal_requirements.unwrap_or_default())?;
-        debug!("Policy requirements: {:?}", requirements);
-
-        let version = match builder.version {
-            Some(tx_builder::Version(0)) => {
-                return Err(Error::Generic("Invalid version `0`".into()))
-            }
-            Some(tx_builder::Version(1)) if requirements.csv.is_some() => {
-                return Err(Error::Generic(
-                    "TxBuilder requested version `1`, but at least `2` is needed to use OP_CSV"
-                        .into(),
-                ))
-            }
-            Some(tx_builder::Version(x)) => x,
-            None if requirements.csv.is_some() => 2,
-            _ => 1,
-        };
-
-        let lock_time = match builder.locktime {
-            // No nLockTime, default to 0
-            None => requirements.timelock.unwrap_or(0),
-            // Specific nLockTime required and we have no constraints, so just set to that value
-            Some(x) if requirements.timelock.is_none() => x,
-            // Specific nLockTime required and it's compatible with the constraints
-            Some(x) if check_nlocktime(x, requirements.timelock.unwrap()) => x,
-            // Invalid nLockTime required
-            Some(x) => return Err(Error::Generic(format!("TxBuilder requested timelock of `{}`, but at least `{}` is required to spend from this script", x, requirements.timelock.unwrap())))
-        };
-
-        let n_sequence = match (builder.rbf, requirements.csv) {
-            // No RBF or CSV but there's an nLockTime, so the nSequence cannot be final
-            (None, None) if lock_time != 0 => 0xFFFFFFFE,
-            // No RBF, CSV or nLockTime, make the transaction final
-            (None, None) => 0xFFFFFFFF,
-
-            // No RBF requested, use the value from CSV. Note that this value is by definition
-            // non-final, so even if a timelock is enabled this nSequence is fine, hence why we
-            // don't bother checking for it here. The same is true for all the other branches below
-            (None, Some(csv)) => csv,
-
-            // RBF with a specific value but that value is too high
-            (Some(tx_builder::RBFValue::Value(rbf)), _) if rbf >= 0xFFFFFFFE => {
-                return Err(Error::Generic(
-                    "Cannot enable RBF with a nSequence >= 0xFFFFFFFE".into(),
-                ))
-            }
-            // RBF with a specific value requested, but the value is incompatible with CSV
-            (Some(tx_builder::RBFValue::Value(rbf)), Some(csv))
-                if !check_nsequence_rbf(rbf, csv) =>
-            {
-                return Err(Error::Generic(format!(
-                    "Cannot enable RBF with nSequence `{}` given a required OP_CSV of `{}`",
-                    rbf, csv
-                )))
-            }
-
-            // RBF enabled with the default value with CSV also enabled. CSV takes precedence
-            (Some(tx_builder::RBFValue::Default), Some(csv)) => csv,
-            // Valid RBF, either default or with a specific value. We ignore the `CSV` value
-            // because we've already checked it before
-            (Some(rbf), _) => rbf.get_value(),
-        };
-
-        let mut tx = Transaction {
-            version,
-            lock_time,
-            input: vec![],
-            output: vec![],
-        };
-
-        let (fee_rate, mut fee_amount) = match builder
-            .fee_policy
-            .as_ref()
-            .unwrap_or(&FeePolicy::FeeRate(FeeRate::default()))
-        {
-            FeePolicy::FeeAmount(amount) => (FeeRate::from_sat_per_vb(0.0), *amount as f32),
-            FeePolicy::FeeRate(rate) => (*rate, 0.0),
-        };
-
-        // try not to move from `builder` because we still need to use it later.
-        let recipients = match &builder.single_recipient {
-            Some(recipient) => vec![(recipient, 0)],
-            None => builder.recipients.iter().map(|(r, v)| (r, *v)).collect(),
-        };
-        if builder.single_recipient.is_some()
-            && !builder.manually_selected_only
-            && !builder.drain_wallet
-        {
-            return Err(Error::SingleRecipientNoInputs);
-        }
-        if recipients.is_empty() {
-            return Err(Error::NoRecipients);
-        }
-
-        if builder.manually_selected_only && builder.utxos.is_empty() {
-            return Err(Error::NoUtxosSelected);
-        }
-
-        // we keep it as a float while we accumulate it, and only round it at the end
-        let mut outgoing: u64 = 0;
-        let mut received: u64 = 0;
-
-        let calc_fee_bytes = |wu| (wu as f32) * fee_rate.as_sat_vb() / 4.0;
-        fee_amount += calc_fee_bytes(tx.get_weight());
-
-        for (index, (script_pubkey, satoshi)) in recipients.into_iter().enumerate() {
-            let value = match builder.single_recipient {
-                Some(_) => 0,
-                None if satoshi.is_dust() => return Err(Error::OutputBelowDustLimit(index)),
-                None => satoshi,
-            };
-
-            if self.is_mine(script_pubkey)? {
-                received += value;
-            }
-
-            let new_out = TxOut {
-                script_pubkey: script_pubkey.clone(),
-                value,
-            };
-            fee_amount += calc_fee_bytes(serialize(&new_out).len() * 4);
-
-            tx.output.push(new_out);
-
-            outgoing += value;
-        }
-
-        if builder.change_policy != tx_builder::ChangeSpendPolicy::ChangeAllowed
-            && self.change_descriptor.is_none()
-        {
-            return Err(Error::Generic(
-                "The `change_policy` can be set only if the wallet has a change_descriptor".into(),
-            ));
-        }
-
-        let (required_utxos, optional_utxos) = self.preselect_utxos(
-            builder.change_policy,
-            &builder.unspendable,
-            &builder.utxos,
-            builder.drain_wallet,
-            builder.manually_selected_only,
-            false, // we don't mind using unconfirmed outputs here, hopefully coin selection will sort this out?
-        )?;
-
-        let coin_selection::CoinSelectionResult {
-            selected,
-            selected_amount,
-            mut fee_amount,
-        } = builder.coin_selection.coin_select(
-            self.database.borrow().deref(),
-            required_utxos,
-            optional_utxos,
-            fee_rate,
-            outgoing,
-            fee_amount,
-        )?;
-        tx.input = selected
-            .iter()
-            .map(|u| bitcoin::TxIn {
-                previous_output: u.outpoint,
-                script_sig: Script::default(),
-                sequence: n_sequence,
-                witness: vec![],
-            })
-            .collect();
-
-        // prepare the change output
-        let change_output = match builder.single_recipient {
-            Some(_) => None,
-            None => {
-                let change_script = self.get_change_address()?;
-                let change_output = TxOut {
-                    script_pubkey: change_script,
-                    value: 0,
-                };
-
-                // take the change into account for fees
-                fee_amount += calc_fee_bytes(serialize(&change_output).len() * 4);
-                Some(change_output)
-            }
-        };
-
-        let mut fee_amount = fee_amount.ceil() as u64;
-        let change_val = (selected_amount - outgoing).saturating_sub(fee_amount);
-
-        match change_output {
-            None if change_val.is_dust() => {
-                // single recipient, but the only output would be below dust limit
-                return Err(Error::InsufficientFunds); // TODO: or OutputBelowDustLimit?
-            }
-            Some(_) if change_val.is_dust() => {
-                // skip the change output because it's dust, this adds up to the fees
-                fee_amount += selected_amount - outgoing;
-            }
-            Some(mut change_output) => {
-                change_output.value = change_val;
-                received += change_val;
-
-                tx.output.push(change_output);
-            }
-            None => {
-                // there's only one output, send everything to it
-                tx.output[0].value = change_val;
-
-                // the single recipient is our address
-                if self.is_mine(&tx.output[0].script_pubkey)? {
-                    received = change_val;
-                }
-            }
-        }
-
-        // sort input/outputs according to the chosen algorithm
-        builder.ordering.sort_tx(&mut tx);
-
-        let txid = tx.txid();
-        let psbt = self.complete_transaction(tx, selected, builder)?;
-
-        let transaction_details = TransactionDetails {
-            transaction: None,
-            txid,
-            timestamp: time::get_timestamp(),
-            received,
-            sent: selected_amount,
-            fees: fee_amount,
-            height: None,
-        };
-
-        Ok((psbt, transaction_details))
+    /// Preview the transaction that would be created by [`create_tx`](Self::create_tx)
+    ///
+    /// This runs coin selection and estimates the fees and the size of the transaction without
+    /// building a PSBT or deriving new change addresses, so that the user can review the costs
+    /// before committing to the transaction. Since coin selection may be randomized, the
+    /// transaction later created with the same `builder` is not guaranteed to match the preview
+    /// exactly.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk::*;
+    /// # use bdk::database::*;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+    /// # let to_address = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+    /// let builder = TxBuilder::with_recipients(vec![(to_address.script_pubkey(), 50_000)]);
+    /// let preview = wallet.preview_tx(&builder)?;
+    /// println!("You will pay {} sats in fees", preview.fee);
+    ///
+    /// let (psbt, details) = wallet.create_tx(builder)?;
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    pub fn preview_tx<Cs: coin_selection::CoinSelectionAlgorithm<D>>(
+        &self,
+        builder: &TxBuilder<D, Cs, CreateTx>,
+    ) -> Result<TxPreview, Error> {
+        let (tx, selected, details, change) = self.build_tx(builder, true)?;
+
+        Ok(TxPreview {
+            fee: details.fees,
+            input_value: details.sent,
+            change,
+            vsize: (self.estimate_signed_weight(&tx, &selected, &builder.foreign_utxos)? + 3) / 4,
+        })
     }
 
     /// Bump the fee of a transaction following the options specified in the `builder`
@@ -545,6 +910,9 @@ where
     /// the [`TxBuilder::maintain_single_recipient`] flag should be enabled to correctly reduce the
     /// only output's value in order to increase the fees.
     ///
+    /// To reduce the value of a recipient other than the change, for instance one that agreed to
+    /// pay for the bump, use [`TxBuilder::allow_shrinking`].
+    ///
     /// If the `builder` specifies some `utxos` that must be spent, they will be added to the
     /// transaction regardless of whether they are necessary or not to cover additional fees.
     ///
@@ -573,7 +941,7 @@ where
         txid: &Txid,
         builder: TxBuilder<D, Cs, BumpFee>,
     ) -> Result<(PSBT, TransactionDetails), Error> {
-        let mut details = match self.database.borrow().get_tx(&txid, true)? {
+        let mut details = match self.database.borrow().get_tx(txid, true)? {
             None => return Err(Error::TransactionNotFound),
             Some(tx) if tx.transaction.is_none() => return Err(Error::TransactionNotFound),
             Some(tx) if tx.height.is_some() => return Err(Error::TransactionConfirmed),
@@ -588,12 +956,33 @@ where
         let vbytes = tx.get_weight() as f32 / 4.0;
         let required_feerate = FeeRate::from_sat_per_vb(details.fees as f32 / vbytes + 1.0);
 
-        // find the index of the output that we can update. either the change or the only one if
-        // it's `single_recipient`
-        let updatable_output = match builder.single_recipient {
-            Some(_) if tx.output.len() != 1 => return Err(Error::SingleRecipientMultipleOutputs),
-            Some(_) => Some(0),
-            None => {
+        // find the index of the output that we can update. either the change, the only one if
+        // it's `single_recipient` or the one the user allowed us to shrink
+        let updatable_output = match (&builder.single_recipient, &builder.allow_shrinking) {
+            (Some(_), Some(_)) => {
+                return Err(Error::Generic(
+                    "`maintain_single_recipient` and `allow_shrinking` can't be used together"
+                        .into(),
+                ))
+            }
+            (Some(_), None) if tx.output.len() != 1 => {
+                return Err(Error::SingleRecipientMultipleOutputs)
+            }
+            (Some(_), None) => Some(0),
+            (None, Some(script)) => match tx
+                .output
+                .iter()
+                .position(|txout| &txout.script_pubkey == script)
+            {
+                Some(index) => Some(index),
+                None => {
+                    return Err(Error::Generic(format!(
+                        "Output with script `{}` not found, it can't be shrunk",
+                        script
+                    )))
+                }
+            },
+            (None, None) => {
                 let mut change_output = None;
                 for (index, txout) in tx.output.iter().enumerate() {
                     // look for an output that we know and that has the right KeychainKind. We use
@@ -621,7 +1010,7 @@ where
             Some(updatable_output) => updatable_output,
             None => {
                 // we need a change output, add one here and take into account the extra fees for it
-                let change_script = self.get_change_address()?;
+                let change_script = self.get_change_address(true)?;
                 let change_txout = TxOut {
                     script_pubkey: change_script,
                     value: 0,
@@ -632,11 +1021,13 @@ where
             }
         };
 
+        // whether the output we can change belongs to a recipient, which pays for the bump,
+        // rather than being our change
+        let shrinking = builder.single_recipient.is_some() || builder.allow_shrinking.is_some();
+
         // initially always remove the output we can change
         let mut removed_updatable_output = tx.output.remove(updatable_output);
-        if self.is_mine(&removed_updatable_output.script_pubkey)? {
-            details.received -= removed_updatable_output.value;
-        }
+        let original_updatable_value = removed_updatable_output.value;
 
         let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
         let original_sequence = tx.input[0].sequence;
@@ -737,7 +1128,8 @@ where
             selected,
             selected_amount,
             fee_amount,
-        } = builder.coin_selection.coin_select(
+            ..
+        } = self.get_coin_selection(&builder).coin_select(
             self.database.borrow().deref(),
             required_utxos,
             optional_utxos,
@@ -766,44 +1158,263 @@ where
 
         let change_val = selected_amount - amount_needed - fee_amount;
         let change_val_after_add = change_val.saturating_sub(removed_output_fee_cost);
-        match builder.single_recipient {
-            None if change_val_after_add.is_dust() => {
+        let dust_limit = builder.dust_limit.unwrap_or(self.dust_limit);
+        let is_dust = dust_limit.is_dust(
+            change_val_after_add,
+            &removed_updatable_output.script_pubkey,
+        );
+        match (shrinking, is_dust) {
+            (false, true) => {
                 // skip the change output because it's dust, this adds up to the fees
                 fee_amount += change_val;
             }
-            Some(_) if change_val_after_add.is_dust() => {
-                // single_recipient but the only output would be below dust limit
+            (true, true) => {
+                // the output we are allowed to shrink would be below dust limit
                 return Err(Error::InsufficientFunds); // TODO: or OutputBelowDustLimit?
             }
-            None => {
+            (_, false) => {
                 removed_updatable_output.value = change_val_after_add;
                 fee_amount += removed_output_fee_cost;
-                details.received += change_val_after_add;
 
                 tx.output.push(removed_updatable_output);
             }
-            Some(_) => {
-                removed_updatable_output.value = change_val_after_add;
-                fee_amount += removed_output_fee_cost;
-
-                // single recipient and it's our address
-                if self.is_mine(&removed_updatable_output.script_pubkey)? {
-                    details.received = change_val_after_add;
-                }
+        }
 
-                tx.output.push(removed_updatable_output);
+        // the scripts of the silent payments depend on the inputs, so they have to be computed
+        // again for the new ones, otherwise the recipients wouldn't find their outputs
+        let mut silent_payments = vec![];
+        for (index, txout) in tx.output.iter().enumerate() {
+            if let Some(address) = self
+                .database
+                .borrow()
+                .get_silent_payment_recipient(&txout.script_pubkey)?
+            {
+                silent_payments.push((index, address));
             }
         }
-
-        // sort input/outputs according to the chosen algorithm
-        builder.ordering.sort_tx(&mut tx);
+        if !silent_payments.is_empty() {
+            let addresses = silent_payments
+                .iter()
+                .map(|(_, address)| *address)
+                .collect::<Vec<_>>();
+            let scripts = self.get_silent_payment_scripts(&addresses, &selected)?;
+            for ((index, address), script) in silent_payments.iter().zip(scripts) {
+                self.database
+                    .borrow_mut()
+                    .set_silent_payment_recipient(&script, address)?;
+                tx.output[*index].script_pubkey = script;
+            }
+        }
+
+        // compute what we receive and what the recipients are paid from the final outputs. the
+        // output we are allowed to shrink, if any, is the last one. outputs sent to our internal
+        // keychain, like decoys or split change, are not recipients
+        let (_, change_type) = self.get_descriptor_for_keychain(KeychainKind::Internal);
+        let shrunk_output = match shrinking {
+            true => Some(tx.output.len() - 1),
+            false => None,
+        };
+        let mut recipients = Vec::with_capacity(tx.output.len());
+        details.received = 0;
+        for (index, txout) in tx.output.iter().enumerate() {
+            if self.is_mine(&txout.script_pubkey)? {
+                details.received += txout.value;
+            }
+
+            if Some(index) == shrunk_output {
+                recipients.push(match builder.single_recipient {
+                    // the only recipient gets everything that's left after the fee
+                    Some(_) => recipient_details(txout, true, fee_amount),
+                    // the recipient was supposed to get the original value, the increase in fees
+                    // is subtracted from it
+                    None => RecipientDetails {
+                        script_pubkey: txout.script_pubkey.clone(),
+                        amount: original_updatable_value,
+                        fee_subtracted: true,
+                        effective_amount: txout.value,
+                    },
+                });
+                continue;
+            }
+
+            match self
+                .database
+                .borrow()
+                .get_path_from_script_pubkey(&txout.script_pubkey)?
+            {
+                Some((keychain, _)) if keychain == change_type => continue,
+                _ => recipients.push(recipient_details(txout, false, 0)),
+            }
+        }
+
+        // sort input/outputs according to the chosen algorithm
+        match builder.rng_seed {
+            Some(seed) => builder
+                .ordering
+                .sort_tx_with_rng(&mut tx, &mut tx_rng(Some(seed))),
+            None => builder.ordering.sort_tx(&mut tx),
+        }
 
         // TODO: check that we are not replacing more than 100 txs from mempool
 
         details.txid = tx.txid();
         details.fees = fee_amount;
         details.timestamp = time::get_timestamp();
+        details.recipients = recipients;
+
+        self.check_max_fee(
+            details.fees,
+            Some(self.estimate_signed_weight(&tx, &selected, &builder.foreign_utxos)?),
+            builder.max_fee_rate.or(self.max_fee_rate),
+            builder.max_fee_absolute.or(self.max_fee_absolute),
+        )?;
+        let psbt = self.complete_transaction(tx, selected, builder)?;
+
+        Ok((psbt, details))
+    }
+
+    /// Cancel an unconfirmed transaction, replacing it with one that sends its inputs back to the
+    /// wallet
+    ///
+    /// The replacement spends the same inputs of the original transaction, which must all belong
+    /// to the wallet, and has a single output to an internal address. Like for
+    /// [`Wallet::bump_fee`], the original transaction must signal RBF and the fee rate set in the
+    /// `builder` must be higher than its fee rate: when no fee is set, the lowest fee rate that
+    /// can replace it is used. The options of the `builder` that change the inputs and the outputs
+    /// of the transaction are ignored.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk::*;
+    /// # use bdk::database::*;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+    /// let txid = Txid::from_str("faff0a466b70f5d5f92bd757a92c1371d4838bdd5bc53a06764e2488e51ce8f8").unwrap();
+    /// let (psbt, details) = wallet.cancel_tx(&txid, TxBuilder::new())?;
+    /// // sign and broadcast ...
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    pub fn cancel_tx<Cs: coin_selection::CoinSelectionAlgorithm<D>>(
+        &self,
+        txid: &Txid,
+        builder: TxBuilder<D, Cs, BumpFee>,
+    ) -> Result<(PSBT, TransactionDetails), Error> {
+        let (original_tx, original_fees) = match self.database.borrow().get_tx(txid, true)? {
+            None => return Err(Error::TransactionNotFound),
+            Some(details) if details.height.is_some() => return Err(Error::TransactionConfirmed),
+            Some(details) => match details.transaction {
+                Some(tx) => (tx, details.fees),
+                None => return Err(Error::TransactionNotFound),
+            },
+        };
+        if !original_tx
+            .input
+            .iter()
+            .any(|txin| txin.sequence <= 0xFFFFFFFD)
+        {
+            return Err(Error::IrreplaceableTransaction);
+        }
+
+        let mut selected = Vec::with_capacity(original_tx.input.len());
+        for txin in &original_tx.input {
+            let txout = self
+                .database
+                .borrow()
+                .get_previous_output(&txin.previous_output)?
+                .ok_or(Error::UnknownUTXO)?;
+            let (keychain, _) = self
+                .database
+                .borrow()
+                .get_path_from_script_pubkey(&txout.script_pubkey)?
+                .ok_or(Error::UnknownUTXO)?;
+
+            selected.push(UTXO {
+                outpoint: txin.previous_output,
+                txout,
+                keychain,
+            });
+        }
+        let input_value = selected.iter().map(|utxo| utxo.txout.value).sum::<u64>();
+
+        let mut tx = Transaction {
+            version: original_tx.version,
+            lock_time: original_tx.lock_time,
+            input: original_tx
+                .input
+                .iter()
+                .map(|txin| TxIn {
+                    previous_output: txin.previous_output,
+                    script_sig: Script::default(),
+                    sequence: txin.sequence,
+                    witness: vec![],
+                })
+                .collect(),
+            // the change address is only derived once the fees are checked, so that a failure
+            // doesn't use up a derivation index. Here we just need a script of the right size
+            output: vec![TxOut {
+                script_pubkey: self.peek_change_script()?,
+                value: 0,
+            }],
+        };
+
+        // the new tx must pay a higher fee rate, and also "pay for its bandwidth" since it can be
+        // smaller than the original one
+        let weight = self.estimate_signed_weight(&tx, &selected, &[])?;
+        let vbytes = weight as f32 / 4.0;
+        let required_feerate = FeeRate::from_sat_per_vb(
+            original_fees as f32 / (original_tx.get_weight() as f32 / 4.0) + 1.0,
+        );
+        let min_fee = original_fees + vbytes.ceil() as u64;
+        let fee_amount = match builder.fee_policy.as_ref() {
+            Some(FeePolicy::FeeAmount(amount)) if *amount < min_fee => {
+                return Err(Error::FeeTooLow { required: min_fee })
+            }
+            Some(FeePolicy::FeeAmount(amount)) => *amount,
+            Some(FeePolicy::FeeRate(rate)) if *rate < required_feerate => {
+                return Err(Error::FeeRateTooLow {
+                    required: required_feerate,
+                })
+            }
+            Some(FeePolicy::FeeRate(rate)) => {
+                std::cmp::max((vbytes * rate.as_sat_vb()).ceil() as u64, min_fee)
+            }
+            None => std::cmp::max(
+                (vbytes * required_feerate.as_sat_vb()).ceil() as u64,
+                min_fee,
+            ),
+        };
+
+        let dust_limit = builder.dust_limit.unwrap_or(self.dust_limit);
+        let value = match input_value.checked_sub(fee_amount) {
+            Some(value) if !dust_limit.is_dust(value, &tx.output[0].script_pubkey) => value,
+            _ => return Err(Error::InsufficientFunds),
+        };
+
+        self.check_max_fee(
+            fee_amount,
+            Some(weight),
+            builder.max_fee_rate.or(self.max_fee_rate),
+            builder.max_fee_absolute.or(self.max_fee_absolute),
+        )?;
 
+        tx.output[0] = TxOut {
+            script_pubkey: self.get_change_address(true)?,
+            value,
+        };
+
+        let details = TransactionDetails {
+            transaction: None,
+            txid: tx.txid(),
+            timestamp: time::get_timestamp(),
+            received: value,
+            sent: input_value,
+            fees: fee_amount,
+            height: None,
+            recipients: vec![],
+        };
         let psbt = self.complete_transaction(tx, selected, builder)?;
 
         Ok((psbt, details))
@@ -812,6 +1423,9 @@ where
     /// Sign a transaction with all the wallet's signers, in the order specified by every signer's
     /// [`SignerOrdering`]
     ///
+    /// The [`SignOptions`] control which inputs can be signed and whether the PSBT is finalized
+    /// afterwards. The returned flag is `true` if the PSBT has been finalized.
+    ///
     /// ## Example
     ///
     /// ```no_run
@@ -819,32 +1433,151 @@ where
     /// # use bitcoin::*;
     /// # use bdk::*;
     /// # use bdk::database::*;
+    /// # use bdk::signer::SignOptions;
     /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
     /// # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
     /// # let (psbt, _) = wallet.create_tx(TxBuilder::new())?;
-    /// let (signed_psbt, finalized) = wallet.sign(psbt, None)?;
+    /// let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default())?;
     /// # Ok::<(), bdk::Error>(())
-    pub fn sign(&self, mut psbt: PSBT, assume_height: Option<u32>) -> Result<(PSBT, bool), Error> {
-        // this helps us doing our job later
-        self.add_input_hd_keypaths(&mut psbt)?;
+    pub fn sign(&self, psbt: PSBT, sign_options: SignOptions) -> Result<(PSBT, bool), Error> {
+        self.sign_with_progress(psbt, sign_options, noop_signer_progress())
+    }
 
-        for signer in self
-            .signers
-            .signers()
-            .iter()
-            .chain(self.change_signers.signers().iter())
-        {
-            if signer.sign_whole_tx() {
-                signer.sign(&mut psbt, None, &self.secp)?;
-            } else {
-                for index in 0..psbt.inputs.len() {
-                    signer.sign(&mut psbt, Some(index), &self.secp)?;
+    /// Sign a transaction like [`Wallet::sign`], awaiting the async signers
+    ///
+    /// Both the blocking signers and the ones added with [`Wallet::add_async_signer`] are
+    /// called, in the order specified by their [`SignerOrdering`].
+    #[cfg(feature = "async-interface")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+    pub async fn sign_async(
+        &self,
+        psbt: PSBT,
+        sign_options: SignOptions,
+    ) -> Result<(PSBT, bool), Error> {
+        self.sign_async_with_progress(psbt, sign_options, noop_signer_progress())
+            .await
+    }
+
+    /// Sign a transaction like [`Wallet::sign_async`], reporting the progress to `progress` like
+    /// [`Wallet::sign_with_progress`]
+    #[cfg(feature = "async-interface")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+    pub async fn sign_async_with_progress<P: SignerProgress>(
+        &self,
+        mut psbt: PSBT,
+        sign_options: SignOptions,
+        progress: P,
+    ) -> Result<(PSBT, bool), Error> {
+        self.prepare_psbt_for_signing(&mut psbt, &sign_options)?;
+
+        let signers = self.signing_plan(psbt.inputs.len());
+        for (n, (signer, input_indexes)) in signers.iter().enumerate() {
+            report_signer_progress(&progress, n, signers.len())?;
+
+            for input_index in input_indexes {
+                match signer {
+                    AnySigner::Blocking(signer) => signer.sign_with_progress(
+                        &mut psbt,
+                        *input_index,
+                        &sign_options,
+                        &progress,
+                        &self.secp,
+                    )?,
+                    AnySigner::Async(signer) => {
+                        signer
+                            .sign(&mut psbt, *input_index, &sign_options, &self.secp)
+                            .await?
+                    }
                 }
             }
         }
+        progress.update(100.0, None)?;
+
+        self.complete_signing(psbt, sign_options)
+    }
+
+    /// Sign a transaction like [`Wallet::sign`], reporting the progress to `progress`
+    ///
+    /// The `progress` is updated before every signer is called, and it's notified when a signer
+    /// waits for the user to interact with a device (see [`SignerProgress`]). Returning an error
+    /// from it cancels the signing, so a UI can run this in a separate thread and still let the
+    /// user abort the operation.
+    pub fn sign_with_progress<P: SignerProgress>(
+        &self,
+        mut psbt: PSBT,
+        sign_options: SignOptions,
+        progress: P,
+    ) -> Result<(PSBT, bool), Error> {
+        self.prepare_psbt_for_signing(&mut psbt, &sign_options)?;
+
+        // the async signers are only called by `sign_async`
+        let signers = self
+            .signing_plan(psbt.inputs.len())
+            .into_iter()
+            .filter_map(|(signer, input_indexes)| Some((signer.as_blocking()?, input_indexes)))
+            .collect::<Vec<_>>();
+        for (n, (signer, input_indexes)) in signers.iter().enumerate() {
+            report_signer_progress(&progress, n, signers.len())?;
+
+            for input_index in input_indexes {
+                signer.sign_with_progress(
+                    &mut psbt,
+                    *input_index,
+                    &sign_options,
+                    &progress,
+                    &self.secp,
+                )?;
+            }
+        }
+        progress.update(100.0, None)?;
+
+        self.complete_signing(psbt, sign_options)
+    }
+
+    // Check a PSBT before signing it and add the information the signers need
+    fn prepare_psbt_for_signing(
+        &self,
+        psbt: &mut PSBT,
+        sign_options: &SignOptions,
+    ) -> Result<(), Error> {
+        self.check_psbt_max_fee(psbt)?;
+        self.check_psbt_inputs(psbt, sign_options)?;
+        self.check_psbt_outputs(psbt)?;
+
+        // this helps us doing our job later
+        self.add_input_hd_keypaths(psbt)
+    }
+
+    // Return the signers of both keychains in the order they are called, each with the indexes
+    // of the inputs it's called for
+    fn signing_plan(&self, inputs: usize) -> Vec<(&AnySigner, Vec<Option<usize>>)> {
+        self.signers
+            .entries()
+            .into_iter()
+            .chain(self.change_signers.entries().into_iter())
+            .map(|(_, _, signer)| {
+                let input_indexes = if signer.sign_whole_tx() {
+                    vec![None]
+                } else {
+                    (0..inputs).map(Some).collect()
+                };
+                (signer, input_indexes)
+            })
+            .collect()
+    }
 
+    fn complete_signing(
+        &self,
+        mut psbt: PSBT,
+        sign_options: SignOptions,
+    ) -> Result<(PSBT, bool), Error> {
         // attempt to finalize
-        self.finalize_psbt(psbt, assume_height)
+        if sign_options.try_finalize {
+            let finalized = self.finalize_psbt(&mut psbt, sign_options)?;
+            Ok((psbt, finalized))
+        } else {
+            Ok((psbt, false))
+        }
     }
 
     /// Return the spending policies for the wallet's descriptor
@@ -875,32 +1608,138 @@ where
         }
     }
 
-    /// Try to finalize a PSBT
-    pub fn finalize_psbt(
+    /// Return the BIP-388 wallet policy that describes both descriptors of the wallet
+    ///
+    /// This is what should be registered on hardware wallets that only sign for known policies.
+    /// The wallet must have a change descriptor: see
+    /// [`WalletPolicy::from_descriptors`](crate::descriptor::wallet_policy::WalletPolicy::from_descriptors)
+    /// for the other requirements.
+    pub fn wallet_policy(&self, name: &str) -> Result<WalletPolicy, Error> {
+        let change_descriptor = self.change_descriptor.as_ref().ok_or_else(|| {
+            Error::Generic("Wallet policies require a change descriptor".to_string())
+        })?;
+
+        Ok(WalletPolicy::from_descriptors(
+            name,
+            &self.descriptor,
+            change_descriptor,
+        )?)
+    }
+
+    /// Return every key in the descriptor of a keychain together with its origin
+    ///
+    /// See [`DescriptorKeyOrigins::key_origins`].
+    pub fn key_origins(&self, keychain: KeychainKind) -> Vec<(DescriptorPublicKey, KeySource)> {
+        self.get_descriptor_for_keychain(keychain)
+            .0
+            .key_origins(&self.secp)
+    }
+
+    /// Return the key of the wallet that corresponds to an entry of the `hd_keypaths` map of a
+    /// PSBT input or output, together with the keychain it belongs to
+    ///
+    /// Coordinators of multisig wallets can use this to tell which participants have already
+    /// signed a PSBT, by looking up the keys in the `partial_sigs` of its inputs.
+    ///
+    /// ## Example
+    ///
+    /// ```
+    /// # use bdk::bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+    /// # use bdk::database::MemoryDatabase;
+    /// # use bdk::OfflineWallet;
+    /// fn print_signers(wallet: &OfflineWallet<MemoryDatabase>, psbt: &PSBT) {
+    ///     for input in &psbt.inputs {
+    ///         for (public_key, key_source) in &input.hd_keypaths {
+    ///             if let Some((_, key)) = wallet.find_key_for_keypath(public_key, key_source) {
+    ///                 let signed = input.partial_sigs.contains_key(public_key);
+    ///                 println!("{} ({}): signed = {}", key, key_source.0, signed);
+    ///             }
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn find_key_for_keypath(
         &self,
-        mut psbt: PSBT,
-        assume_height: Option<u32>,
-    ) -> Result<(PSBT, bool), Error> {
-        let tx = &psbt.global.unsigned_tx;
+        public_key: &PublicKey,
+        key_source: &KeySource,
+    ) -> Option<(KeychainKind, DescriptorPublicKey)> {
+        std::iter::once((&self.descriptor, KeychainKind::External))
+            .chain(
+                self.change_descriptor
+                    .as_ref()
+                    .map(|desc| (desc, KeychainKind::Internal)),
+            )
+            .find_map(|(desc, keychain)| {
+                desc.find_key_for_keypath(public_key, key_source, &self.secp)
+                    .map(|key| (keychain, key))
+            })
+    }
+
+    /// Return the [`ScriptType`] of the addresses generated for a keychain
+    ///
+    /// If the wallet doesn't have a change descriptor the type of the external descriptor is
+    /// returned for [`KeychainKind::Internal`], since that's where the change is sent to.
+    pub fn script_type(&self, keychain: KeychainKind) -> ScriptType {
+        self.get_descriptor_for_keychain(keychain).0.script_type()
+    }
+
+    /// Try to finalize a PSBT
+    ///
+    /// This builds the final `scriptSig` and witness of every input spending one of the wallet's
+    /// outputs, using the signatures already present in the PSBT. It doesn't require any signer,
+    /// so a watch-only wallet can finalize a PSBT signed by third parties. Only the
+    /// `assume_height` field of the [`SignOptions`] is taken into account: when it's `None` the
+    /// current height is asked to the wallet's [`TipOracle`].
+    ///
+    /// Returns `true` if every input of the PSBT has been finalized.
+    ///
+    /// ## Example
+    ///
+    /// ```no_run
+    /// # use std::str::FromStr;
+    /// # use bitcoin::*;
+    /// # use bdk::*;
+    /// # use bdk::database::*;
+    /// # use bdk::signer::SignOptions;
+    /// # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+    /// # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+    /// # let (mut psbt, _) = wallet.create_tx(TxBuilder::new())?;
+    /// // ... collect the signatures from the other participants ...
+    /// let finalized = wallet.finalize_psbt(&mut psbt, SignOptions::default())?;
+    /// if finalized {
+    ///     let tx = psbt.extract_tx();
+    /// }
+    /// # Ok::<(), bdk::Error>(())
+    /// ```
+    pub fn finalize_psbt(&self, psbt: &mut PSBT, sign_options: SignOptions) -> Result<bool, Error> {
+        let tip = self.tip.get();
+        let current_height = match sign_options.assume_height {
+            Some(height) => Some(height),
+            None => tip.map(|tip| tip.height),
+        };
+        let finalizer = Finalizer::new(
+            &self.secp,
+            current_height,
+            tip.and_then(|tip| tip.median_time_past),
+        );
         let mut finished = true;
 
-        for (n, input) in tx.input.iter().enumerate() {
-            let psbt_input = &psbt.inputs[n];
-            if psbt_input.final_script_sig.is_some() || psbt_input.final_script_witness.is_some() {
+        for n in 0..psbt.inputs.len() {
+            if Finalizer::is_finalized(psbt, n) {
                 continue;
             }
+            let previous_output = psbt.global.unsigned_tx.input[n].previous_output;
             // if the height is None in the database it means it's still unconfirmed, so consider
             // that as a very high value
             let create_height = self
                 .database
                 .borrow()
-                .get_tx(&input.previous_output.txid, false)?
+                .get_tx(&previous_output.txid, false)?
                 .map(|tx| tx.height.unwrap_or(std::u32::MAX));
-            let current_height = assume_height.or(self.current_height);
 
             debug!(
                 "Input #{} - {}, using `create_height` = {:?}, `current_height` = {:?}",
-                n, input.previous_output, create_height, current_height
+                n, previous_output, create_height, current_height
             );
 
             // - Try to derive the descriptor by looking at the txout. If it's in our database, we
@@ -909,6 +1748,7 @@ where
             //   is in `src/descriptor/mod.rs`, but it will basically look at `hd_keypaths`,
             //   `redeem_script` and `witness_script` to determine the right derivation
             // - If that also fails, it will try it on the internal descriptor, if present
+            let psbt_input = &psbt.inputs[n];
             let desc = psbt
                 .get_utxo_for(n)
                 .map(|txout| self.get_descriptor_for_txout(&txout))
@@ -929,33 +1769,15 @@ where
 
             match desc {
                 Some(desc) => {
-                    let mut tmp_input = bitcoin::TxIn::default();
-                    let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
-                    match desc.satisfy(
-                        &mut tmp_input,
-                        (
-                            PsbtInputSatisfier::new(&psbt, n),
-                            After::new(current_height, false),
-                            Older::new(current_height, create_height, false),
-                        ),
-                        deriv_ctx,
-                    ) {
-                        Ok(_) => {
-                            let psbt_input = &mut psbt.inputs[n];
-                            psbt_input.final_script_sig = Some(tmp_input.script_sig);
-                            psbt_input.final_script_witness = Some(tmp_input.witness);
-                        }
-                        Err(e) => {
-                            debug!("satisfy error {:?} for input {}", e, n);
-                            finished = false
-                        }
+                    if !finalizer.finalize_input(psbt, n, &desc, create_height) {
+                        finished = false;
                     }
                 }
                 None => finished = false,
             }
         }
 
-        Ok((psbt, finished))
+        Ok(finished)
     }
 
     /// Return the secp256k1 context used for all signing operations
@@ -963,1568 +1785,5345 @@ where
         &self.secp
     }
 
-    // Internals
-
-    fn get_descriptor_for_keychain(
+    /// Return a PSBT input that spends one of the wallet's UTXOs
+    ///
+    /// The input is populated with everything the wallet knows about the UTXO: the previous
+    /// output (`witness_utxo` and/or `non_witness_utxo`), the redeem and witness scripts and the
+    /// `hd_keypaths`. This lets an external coordinator include the wallet's coins in a shared
+    /// transaction, like a coinjoin or a payjoin.
+    ///
+    /// The `sighash_type` is only set if explicitly specified, otherwise it's left blank which
+    /// defaults to SIGHASH_ALL. The full previous transaction is always included for non-witness
+    /// scripts, and also for witness scripts if `force_non_witness_utxo` is enabled.
+    ///
+    /// Returns [`Error::UnknownUTXO`] if the UTXO's script doesn't belong to the wallet.
+    pub fn get_psbt_input(
         &self,
-        keychain: KeychainKind,
-    ) -> (&ExtendedDescriptor, KeychainKind) {
-        match keychain {
-            KeychainKind::Internal if self.change_descriptor.is_some() => (
-                self.change_descriptor.as_ref().unwrap(),
-                KeychainKind::Internal,
-            ),
-            _ => (&self.descriptor, KeychainKind::External),
+        utxo: UTXO,
+        sighash_type: Option<SigHashType>,
+        force_non_witness_utxo: bool,
+    ) -> Result<psbt::Input, Error> {
+        // Try to find the prev_script in our db to figure out if this is internal or external,
+        // and the derivation index
+        let (keychain, child) = self
+            .database
+            .borrow()
+            .get_path_from_script_pubkey(&utxo.txout.script_pubkey)?
+            .ok_or(Error::UnknownUTXO)?;
+
+        let mut psbt_input = psbt::Input {
+            sighash_type,
+            ..psbt::Input::default()
+        };
+
+        let (desc, _) = self.get_descriptor_for_keychain(keychain);
+        psbt_input.hd_keypaths = desc.get_hd_keypaths(child, &self.secp)?;
+        let derived_descriptor = desc.derive(ChildNumber::from_normal_idx(child)?);
+
+        psbt_input.redeem_script = derived_descriptor.psbt_redeem_script(&self.secp);
+        psbt_input.witness_script = derived_descriptor.psbt_witness_script(&self.secp);
+
+        let prev_output = utxo.outpoint;
+        if let Some(prev_tx) = self.database.borrow().get_raw_tx(&prev_output.txid)? {
+            if derived_descriptor.is_witness() {
+                let txout = prev_tx
+                    .output
+                    .get(prev_output.vout as usize)
+                    .ok_or(Error::UnknownUTXO)?;
+                psbt_input.witness_utxo = Some(txout.clone());
+            }
+            if !derived_descriptor.is_witness() || force_non_witness_utxo {
+                psbt_input.non_witness_utxo = Some(prev_tx);
+            }
         }
+
+        Ok(psbt_input)
     }
 
-    fn get_descriptor_for_txout(&self, txout: &TxOut) -> Result<Option<ExtendedDescriptor>, Error> {
-        Ok(self
-            .database
-            .borrow()
-            .get_path_from_script_pubkey(&txout.script_pubkey)?
-            .map(|(keychain, child)| (self.get_descriptor_for_keychain(keychain).0, child))
-            .map(|(desc, child)| desc.derive(ChildNumber::from_normal_idx(child).unwrap())))
+    /// Return which outputs of a PSBT belong to the wallet
+    ///
+    /// Outputs whose script is not already known to the wallet are matched against the
+    /// `hd_keypaths` set by whoever created the PSBT: the script is derived again from the wallet's
+    /// descriptors, so an output that claims to be change but pays somewhere else is reported as
+    /// [`OutputOwnership::InvalidDerivation`] instead of being trusted.
+    ///
+    /// The same check is done by [`Wallet::sign`], which refuses to sign such PSBTs.
+    pub fn verify_psbt_outputs(&self, psbt: &PSBT) -> Result<Vec<OutputOwnership>, Error> {
+        psbt.global
+            .unsigned_tx
+            .output
+            .iter()
+            .zip(psbt.outputs.iter())
+            .map(|(txout, psbt_output)| self.get_output_ownership(txout, psbt_output))
+            .collect()
     }
 
-    fn get_change_address(&self) -> Result<Script, Error> {
+    /// Read the [BDK metadata](crate::psbt::proprietary) of a PSBT and verify it
+    ///
+    /// Every output tagged with a keychain and a derivation index must have the script derived
+    /// from the wallet's descriptor at that index, otherwise [`MetadataError::OutputMismatch`] is
+    /// returned. The metadata is only added by the wallet when the
+    /// [`TxBuilder::add_bdk_metadata`] option is enabled.
+    ///
+    /// [`MetadataError::OutputMismatch`]: crate::psbt::proprietary::MetadataError::OutputMismatch
+    pub fn verify_bdk_metadata(&self, psbt: &PSBT) -> Result<BdkMetadata, Error> {
+        let metadata = BdkMetadata::from_psbt(psbt)?;
+
         let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+        for (n, (txout, output)) in psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .zip(metadata.outputs.iter())
+            .enumerate()
+        {
+            if let Some((keychain, index)) = output {
+                let (desc, _) = self.get_descriptor_for_keychain(*keychain);
+                let script_pubkey = desc
+                    .derive(ChildNumber::from_normal_idx(*index)?)
+                    .script_pubkey(deriv_ctx);
+                if script_pubkey != txout.script_pubkey {
+                    return Err(MetadataError::OutputMismatch(n).into());
+                }
+            }
+        }
 
-        let (desc, keychain) = self.get_descriptor_for_keychain(KeychainKind::Internal);
-        let index = self.fetch_and_increment_index(keychain)?;
+        Ok(metadata)
+    }
 
-        Ok(desc
-            .derive(ChildNumber::from_normal_idx(index)?)
-            .script_pubkey(deriv_ctx))
+    /// Return the maximum weight of the `scriptSig` and witness needed to spend an output of
+    /// `keychain`
+    ///
+    /// This is the weight used by the wallet to compute the fees of its inputs. Use
+    /// [`SatisfactionWeight::branch_satisfaction_weight`] on the descriptor to estimate the weight
+    /// of a specific spending branch.
+    ///
+    /// [`SatisfactionWeight::branch_satisfaction_weight`]: crate::descriptor::SatisfactionWeight::branch_satisfaction_weight
+    pub fn get_satisfaction_weight(&self, keychain: KeychainKind) -> Option<usize> {
+        let (desc, _) = self.get_descriptor_for_keychain(keychain);
+        desc.satisfaction_weight(&self.secp)
     }
 
-    fn fetch_and_increment_index(&self, keychain: KeychainKind) -> Result<u32, Error> {
-        let (descriptor, keychain) = self.get_descriptor_for_keychain(keychain);
-        let index = match descriptor.is_fixed() {
-            true => 0,
-            false => self.database.borrow_mut().increment_last_index(keychain)?,
-        };
+    /// Return the weight of the `scriptSig` and witness of each input of a PSBT
+    ///
+    /// Finalized inputs get their actual weight, while the inputs that spend outputs of the
+    /// wallet get the maximum satisfaction weight of their descriptor. The weight of the other
+    /// inputs is unknown to the wallet, so `None` is returned for them.
+    pub fn get_psbt_satisfaction_weights(&self, psbt: &PSBT) -> Result<Vec<Option<usize>>, Error> {
+        (0..psbt.inputs.len())
+            .map(|n| {
+                if Finalizer::is_finalized(psbt, n) {
+                    let psbt_input = &psbt.inputs[n];
+                    let script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+                    let witness = psbt_input.final_script_witness.clone().unwrap_or_default();
+                    return Ok(Some(
+                        serialize(&script_sig).len() * 4 + serialize(&witness).len(),
+                    ));
+                }
 
-        if self
-            .database
-            .borrow()
-            .get_script_pubkey_from_path(keychain, index)?
-            .is_none()
-        {
-            self.cache_addresses(keychain, index, CACHE_ADDR_BATCH_SIZE)?;
-        }
+                match psbt.get_utxo_for(n) {
+                    Some(txout) => Ok(self
+                        .get_descriptor_for_txout(&txout)?
+                        .and_then(|desc| desc.satisfaction_weight(&self.secp))),
+                    None => Ok(None),
+                }
+            })
+            .collect()
+    }
+
+    /// Estimate the fee rate of a PSBT once it's signed
+    ///
+    /// The weight of the inputs that are not finalized yet is estimated from the wallet's
+    /// descriptors, so they must spend outputs of the wallet. Finalized inputs are taken into
+    /// account with their actual weight. Like [`PSBTUtils::fee_amount`], this requires every
+    /// input to have either the `witness_utxo` or the `non_witness_utxo`.
+    pub fn estimate_psbt_fee_rate(&self, psbt: &PSBT) -> Result<FeeRate, Error> {
+        let fee_amount = psbt.fee_amount()?;
 
         let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+        let mut tx = psbt.global.unsigned_tx.clone();
+        let mut satisfaction_weight = 0;
+        for (n, (txin, psbt_input)) in tx.input.iter_mut().zip(psbt.inputs.iter()).enumerate() {
+            if Finalizer::is_finalized(psbt, n) {
+                txin.script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+                txin.witness = psbt_input.final_script_witness.clone().unwrap_or_default();
+                continue;
+            }
 
-        let hd_keypaths = descriptor.get_hd_keypaths(index, &self.secp)?;
-        let script = descriptor
-            .derive(ChildNumber::from_normal_idx(index)?)
-            .script_pubkey(deriv_ctx);
-        for validator in &self.address_validators {
-            validator.validate(keychain, &hd_keypaths, &script)?;
+            let txout = psbt.get_utxo_for(n).ok_or(Error::MissingInputUTXO(n))?;
+            satisfaction_weight += self
+                .get_descriptor_for_txout(&txout)?
+                .and_then(|desc| desc.max_satisfaction_weight(deriv_ctx))
+                .ok_or(Error::InputNotFinalized(n))?;
         }
 
-        Ok(index)
+        let weight = tx.get_weight() + satisfaction_weight;
+        Ok(FeeRate::from_sat_per_vb(
+            fee_amount as f32 / (weight as f32 / 4.0),
+        ))
     }
 
-    fn cache_addresses(
-        &self,
-        keychain: KeychainKind,
-        from: u32,
-        mut count: u32,
-    ) -> Result<(), Error> {
-        let (descriptor, keychain) = self.get_descriptor_for_keychain(keychain);
-        if descriptor.is_fixed() {
-            if from > 0 {
-                return Ok(());
-            }
+    // Internals
 
-            count = 1;
+    fn get_output_ownership(
+        &self,
+        txout: &TxOut,
+        psbt_output: &psbt::Output,
+    ) -> Result<OutputOwnership, Error> {
+        if let Some((keychain, index)) = self
+            .database
+            .borrow()
+            .get_path_from_script_pubkey(&txout.script_pubkey)?
+        {
+            return Ok(OutputOwnership::Mine { keychain, index });
         }
 
         let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+        let descriptors = std::iter::once((&self.descriptor, KeychainKind::External)).chain(
+            self.change_descriptor
+                .as_ref()
+                .map(|desc| (desc, KeychainKind::Internal)),
+        );
 
-        let mut address_batch = self.database.borrow().begin_batch();
+        let mut claimed = false;
+        for (desc, keychain) in descriptors {
+            let keys = desc.get_keys()?;
+            for (public_key, key_source) in &psbt_output.hd_keypaths {
+                let is_our_key = keys.iter().any(|key| match key {
+                    DescriptorPublicKey::XPub(xpub) => {
+                        xpub.matches(key_source, &self.secp).is_some()
+                    }
+                    DescriptorPublicKey::SinglePub(single) => &single.key == public_key,
+                });
+                if !is_our_key {
+                    continue;
+                }
+                claimed = true;
 
-        let start_time = time::Instant::new();
-        for i in from..(from + count) {
-            address_batch.set_script_pubkey(
-                &descriptor
-                    .derive(ChildNumber::from_normal_idx(i)?)
-                    .script_pubkey(deriv_ctx),
-                keychain,
-                i,
-            )?;
+                // the index is the last step of the path, unless the descriptor has no wildcard
+                let path: &[ChildNumber] = key_source.1.as_ref();
+                let index = match path.last() {
+                    Some(ChildNumber::Normal { index }) if !desc.is_fixed() => *index,
+                    _ => 0,
+                };
+                let script_pubkey = desc
+                    .derive(ChildNumber::from_normal_idx(index)?)
+                    .script_pubkey(deriv_ctx);
+                if script_pubkey == txout.script_pubkey {
+                    return Ok(OutputOwnership::Mine { keychain, index });
+                }
+            }
         }
 
-        info!(
-            "Derivation of {} addresses from {} took {} ms",
-            count,
-            from,
-            start_time.elapsed().as_millis()
-        );
-
-        self.database.borrow_mut().commit_batch(address_batch)?;
-
-        Ok(())
+        if claimed {
+            Ok(OutputOwnership::InvalidDerivation)
+        } else {
+            Ok(OutputOwnership::External)
+        }
     }
 
-    fn get_available_utxos(&self) -> Result<Vec<(UTXO, usize)>, Error> {
-        let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
-        Ok(self
-            .list_unspent()?
-            .into_iter()
-            .map(|utxo| {
-                let keychain = utxo.keychain;
-                (
-                    utxo,
-                    self.get_descriptor_for_keychain(keychain)
-                        .0
-                        .max_satisfaction_weight(deriv_ctx)
+    // Build the unsigned transaction for `create_tx`. When `dry_run` is enabled the derivation
+    // indexes are not incremented, and placeholder scripts are used for the change outputs.
+    //
+    // Returns the transaction, the UTXOs selected, its details and the change amount
+    #[allow(clippy::type_complexity)]
+    fn build_tx<Cs: coin_selection::CoinSelectionAlgorithm<D>>(
+        &self,
+        builder: &TxBuilder<D, Cs, CreateTx>,
+        dry_run: bool,
+    ) -> Result<(Transaction, Vec<UTXO>, TransactionDetails, u64), Error> {
+        let external_policy = self
+            .descriptor
+            .extract_policy(&self.signers, &self.secp)?
+            .unwrap();
+        let internal_policy = self
+            .change_descriptor
+            .as_ref()
+            .map(|desc| {
+                Ok::<_, Error>(
+                    desc.extract_policy(&self.change_signers, &self.secp)?
                         .unwrap(),
                 )
             })
-            .collect())
-    }
-
-    /// Given the options returns the list of utxos that must be used to form the
-    /// transaction and any further that may be used if needed.
-    #[allow(clippy::type_complexity)]
-    fn preselect_utxos(
-        &self,
-        change_policy: tx_builder::ChangeSpendPolicy,
-        unspendable: &HashSet<OutPoint>,
-        manually_selected: &[OutPoint],
-        must_use_all_available: bool,
-        manual_only: bool,
-        must_only_use_confirmed_tx: bool,
-    ) -> Result<(Vec<(UTXO, usize)>, Vec<(UTXO, usize)>), Error> {
-        //    must_spend <- manually selected utxos
-        //    may_spend  <- all other available utxos
-        let mut may_spend = self.get_available_utxos()?;
-        let mut must_spend = {
-            let must_spend_idx = manually_selected
-                .iter()
-                .map(|manually_selected| {
-                    may_spend
-                        .iter()
-                        .position(|available| available.0.outpoint == *manually_selected)
-                        .ok_or(Error::UnknownUTXO)
-                })
-                .collect::<Result<Vec<_>, _>>()?;
+            .transpose()?;
 
-            must_spend_idx
-                .into_iter()
-                .map(|i| may_spend.remove(i))
-                .collect()
+        // The policy allows spending external outputs, but it requires a policy path that hasn't been
+        // provided
+        if builder.change_policy != tx_builder::ChangeSpendPolicy::OnlyChange
+            && external_policy.requires_path()
+            && builder.external_policy_path.is_none()
+        {
+            return Err(Error::SpendingPolicyRequired(KeychainKind::External));
         };
-
-        // NOTE: we are intentionally ignoring `unspendable` here. i.e manual
-        // selection overrides unspendable.
-        if manual_only {
-            return Ok((must_spend, vec![]));
+        // Same for the internal_policy path, if present
+        if let Some(internal_policy) = &internal_policy {
+            if builder.change_policy != tx_builder::ChangeSpendPolicy::ChangeForbidden
+                && internal_policy.requires_path()
+                && builder.internal_policy_path.is_none()
+            {
+                return Err(Error::SpendingPolicyRequired(KeychainKind::Internal));
+            };
         }
 
-        let satisfies_confirmed = match must_only_use_confirmed_tx {
-            true => {
-                let database = self.database.borrow_mut();
-                may_spend
-                    .iter()
-                    .map(|u| {
-                        database
-                            .get_tx(&u.0.outpoint.txid, true)
-                            .map(|tx| match tx {
-                                None => false,
-                                Some(tx) => tx.height.is_some(),
-                            })
-                    })
-                    .collect::<Result<Vec<_>, _>>()?
-            }
-            false => vec![true; may_spend.len()],
-        };
+        let external_requirements = external_policy.get_condition(
+            builder
+                .external_policy_path
+                .as_ref()
+                .unwrap_or(&BTreeMap::new()),
+        )?;
+        let internal_requirements = internal_policy
+            .map(|policy| {
+                Ok::<_, Error>(
+                    policy.get_condition(
+                        builder
+                            .internal_policy_path
+                            .as_ref()
+                            .unwrap_or(&BTreeMap::new()),
+                    )?,
+                )
+            })
+            .transpose()?;
 
-        let mut i = 0;
-        may_spend.retain(|u| {
-            let retain = change_policy.is_satisfied_by(&u.0)
-                && !unspendable.contains(&u.0.outpoint)
-                && satisfies_confirmed[i];
-            i += 1;
-            retain
-        });
+        let requirements = external_requirements
+            .clone()
+            .merge(&internal_requirements.unwrap_or_default())?;
+        debug!("Policy requirements: {:?}", requirements);
 
-        if must_use_all_available {
-            must_spend.append(&mut may_spend);
-        }
+        let version = match builder.version {
+            Some(tx_builder::Version(0)) => {
+                return Err(Error::Generic("Invalid version `0`".into()))
+            }
+            Some(tx_builder::Version(1)) if requirements.csv.is_some() => {
+                return Err(Error::Generic(
+                    "TxBuilder requested version `1`, but at least `2` is needed to use OP_CSV"
+                        .into(),
+                ))
+            }
+            Some(tx_builder::Version(x)) => x,
+            None if requirements.csv.is_some() => 2,
+            _ => 1,
+        };
 
-        Ok((must_spend, may_spend))
-    }
+        let lock_time = match (builder.locktime, self.tip.get()) {
+            // No nLockTime but we know the tip of the chain: use its height to discourage fee
+            // sniping, like Bitcoin Core does, unless the descriptor requires a time-based or a
+            // higher value. Skipped if the nSequence of some inputs is overridden, since they
+            // could all be final
+            (None, Some(tip)) if builder.input_nsequence.is_empty() => {
+                match requirements.timelock {
+                    None => tip.height,
+                    Some(x) if x < utils::BLOCKS_TIMELOCK_THRESHOLD => std::cmp::max(x, tip.height),
+                    Some(x) => x,
+                }
+            }
+            // No nLockTime, default to 0
+            (None, _) => requirements.timelock.unwrap_or(0),
+            // Specific nLockTime required and we have no constraints, so just set to that value
+            (Some(x), _) if requirements.timelock.is_none() => x,
+            // Specific nLockTime required and it's compatible with the constraints
+            (Some(x), _) if check_nlocktime(x, requirements.timelock.unwrap()) => x,
+            // Invalid nLockTime required
+            (Some(x), _) => return Err(Error::Generic(format!("TxBuilder requested timelock of `{}`, but at least `{}` is required to spend from this script", x, requirements.timelock.unwrap())))
+        };
 
-    fn complete_transaction<
-        Cs: coin_selection::CoinSelectionAlgorithm<D>,
-        Ctx: TxBuilderContext,
-    >(
-        &self,
-        tx: Transaction,
-        selected: Vec<UTXO>,
-        builder: TxBuilder<D, Cs, Ctx>,
-    ) -> Result<PSBT, Error> {
-        use bitcoin::util::psbt::serialize::Serialize;
+        let n_sequence = match (builder.rbf, requirements.csv) {
+            // No RBF or CSV but there's an nLockTime, so the nSequence cannot be final
+            (None, None) if lock_time != 0 => 0xFFFFFFFE,
+            // No RBF, CSV or nLockTime, make the transaction final
+            (None, None) => 0xFFFFFFFF,
 
-        let mut psbt = PSBT::from_unsigned_tx(tx)?;
+            // No RBF requested, use the value from CSV. Note that this value is by definition
+            // non-final, so even if a timelock is enabled this nSequence is fine, hence why we
+            // don't bother checking for it here. The same is true for all the other branches below
+            (None, Some(csv)) => csv,
 
-        if builder.add_global_xpubs {
-            let mut all_xpubs = self.descriptor.get_extended_keys()?;
-            if let Some(change_descriptor) = &self.change_descriptor {
-                all_xpubs.extend(change_descriptor.get_extended_keys()?);
+            // RBF with a specific value but that value is too high
+            (Some(tx_builder::RBFValue::Value(rbf)), _) if rbf >= 0xFFFFFFFE => {
+                return Err(Error::Generic(
+                    "Cannot enable RBF with a nSequence >= 0xFFFFFFFE".into(),
+                ))
+            }
+            // RBF with a specific value requested, but the value is incompatible with CSV
+            (Some(tx_builder::RBFValue::Value(rbf)), Some(csv))
+                if !check_nsequence_rbf(rbf, csv) =>
+            {
+                return Err(Error::Generic(format!(
+                    "Cannot enable RBF with nSequence `{}` given a required OP_CSV of `{}`",
+                    rbf, csv
+                )))
             }
 
-            for xpub in all_xpubs {
-                let serialized_xpub = base58::from_check(&xpub.xkey.to_string())
-                    .expect("Internal serialization error");
-                let key = PSBTKey {
-                    type_value: 0x01,
-                    key: serialized_xpub,
-                };
-
-                let origin = match xpub.origin {
-                    Some(origin) => origin,
-                    None if xpub.xkey.depth == 0 => {
-                        (xpub.root_fingerprint(&self.secp), vec![].into())
-                    }
-                    _ => return Err(Error::MissingKeyOrigin(xpub.xkey.to_string())),
-                };
+            // RBF enabled with the default value with CSV also enabled. CSV takes precedence
+            (Some(tx_builder::RBFValue::Default), Some(csv)) => csv,
+            // Valid RBF, either default or with a specific value. We ignore the `CSV` value
+            // because we've already checked it before
+            (Some(rbf), _) => rbf.get_value(),
+        };
 
-                psbt.global.unknown.insert(key, origin.serialize());
+        if let Some(csv) = requirements.csv {
+            for (outpoint, nsequence) in &builder.input_nsequence {
+                if !check_nsequence_rbf(*nsequence, csv) {
+                    return Err(Error::Generic(format!(
+                        "TxBuilder requested nSequence `{}` for input `{}`, but a required OP_CSV of `{}` is not satisfied",
+                        nsequence, outpoint, csv
+                    )));
+                }
             }
         }
 
-        let lookup_output = selected
-            .into_iter()
-            .map(|utxo| (utxo.outpoint, utxo))
-            .collect::<HashMap<_, _>>();
+        let mut tx = Transaction {
+            version,
+            lock_time,
+            input: vec![],
+            output: vec![],
+        };
 
-        // add metadata for the inputs
-        for (psbt_input, input) in psbt
-            .inputs
-            .iter_mut()
-            .zip(psbt.global.unsigned_tx.input.iter())
-        {
-            let utxo = match lookup_output.get(&input.previous_output) {
-                Some(utxo) => utxo,
-                None => continue,
+        let (fee_rate, mut fee_amount) = match builder
+            .fee_policy
+            .as_ref()
+            .unwrap_or(&FeePolicy::FeeRate(FeeRate::default()))
+        {
+            FeePolicy::FeeAmount(amount) => (FeeRate::from_sat_per_vb(0.0), *amount as f32),
+            FeePolicy::FeeRate(rate) => (*rate, 0.0),
+        };
+
+        // try not to move from `builder` because we still need to use it later.
+        let recipients = match &builder.single_recipient {
+            Some(recipient) => vec![(recipient, 0)],
+            None => builder.recipients.iter().map(|(r, v)| (r, *v)).collect(),
+        };
+        if builder.single_recipient.is_some()
+            && !builder.manually_selected_only
+            && !builder.drain_wallet
+        {
+            return Err(Error::SingleRecipientNoInputs);
+        }
+        if recipients.is_empty() && !builder.consolidation {
+            return Err(Error::NoRecipients);
+        }
+        if builder
+            .silent_payments
+            .iter()
+            .any(|(_, address)| !address.is_valid_for_network(self.network))
+        {
+            return Err(silent_payments::SilentPaymentError::InvalidNetwork.into());
+        }
+
+        if builder.manually_selected_only
+            && builder.utxos.is_empty()
+            && builder.foreign_utxos.is_empty()
+        {
+            return Err(Error::NoUtxosSelected);
+        }
+
+        // we keep it as a float while we accumulate it, and only round it at the end
+        let mut outgoing: u64 = 0;
+        let mut received: u64 = 0;
+
+        let calc_fee_bytes = |wu| (wu as f32) * fee_rate.as_sat_vb() / 4.0;
+        fee_amount += calc_fee_bytes(tx.get_weight());
+
+        let dust_limit = builder.dust_limit.unwrap_or(self.dust_limit);
+        let recipients_count = recipients.len();
+        for (index, (script_pubkey, satoshi)) in recipients.into_iter().enumerate() {
+            let value = match builder.single_recipient {
+                Some(_) => 0,
+                None if dust_limit.is_dust(satoshi, script_pubkey) => {
+                    return Err(Error::OutputBelowDustLimit(index))
+                }
+                None => satoshi,
             };
 
-            // Only set it if the builder has a custom one, otherwise leave blank which defaults to
-            // SIGHASH_ALL
-            if let Some(sighash_type) = builder.sighash {
-                psbt_input.sighash_type = Some(sighash_type);
+            if self.is_mine(script_pubkey)? {
+                received += value;
             }
 
-            // Try to find the prev_script in our db to figure out if this is internal or external,
-            // and the derivation index
-            let (keychain, child) = match self
-                .database
-                .borrow()
-                .get_path_from_script_pubkey(&utxo.txout.script_pubkey)?
-            {
-                Some(x) => x,
-                None => continue,
+            let new_out = TxOut {
+                script_pubkey: script_pubkey.clone(),
+                value,
             };
+            fee_amount += calc_fee_bytes(serialize(&new_out).len() * 4);
 
-            let (desc, _) = self.get_descriptor_for_keychain(keychain);
-            psbt_input.hd_keypaths = desc.get_hd_keypaths(child, &self.secp)?;
-            let derived_descriptor = desc.derive(ChildNumber::from_normal_idx(child)?);
+            tx.output.push(new_out);
 
-            psbt_input.redeem_script = derived_descriptor.psbt_redeem_script(&self.secp);
-            psbt_input.witness_script = derived_descriptor.psbt_witness_script(&self.secp);
+            outgoing += value;
+        }
 
-            let prev_output = input.previous_output;
-            if let Some(prev_tx) = self.database.borrow().get_raw_tx(&prev_output.txid)? {
-                if derived_descriptor.is_witness() {
-                    psbt_input.witness_utxo =
-                        Some(prev_tx.output[prev_output.vout as usize].clone());
-                }
-                if !derived_descriptor.is_witness() || builder.force_non_witness_utxo {
-                    psbt_input.non_witness_utxo = Some(prev_tx);
-                }
+        // in dry-run mode we don't want to touch the derivation indexes, and the actual scripts
+        // don't matter since we only need them to estimate the size of the outputs. Only the
+        // first change script can reuse a previous index, the following ones must be fresh.
+        let first_change = Cell::new(true);
+        let next_change_script = || match dry_run {
+            true => self.peek_change_script(),
+            false => self.get_change_address(first_change.replace(false)),
+        };
+
+        let mut rng = tx_rng(builder.rng_seed);
+        let decoy_values = match builder.decoy_outputs {
+            0 => vec![],
+            _ if builder.single_recipient.is_some() => {
+                return Err(Error::Generic(
+                    "Decoy outputs can't be added to a transaction with a single recipient".into(),
+                ))
+            }
+            count => {
+                let amounts = builder
+                    .recipients
+                    .iter()
+                    .map(|(_, value)| *value)
+                    .collect::<Vec<_>>();
+                // decoys are sent to change scripts, which all have the same type
+                let script_pubkey = self.peek_change_script()?;
+                decoy_output_values(&amounts, count, &dust_limit, &script_pubkey, &mut rng)
+            }
+        };
+        if !decoy_values.is_empty() {
+            // the actual scripts are only derived once coin selection succeeds, here we just need
+            // an output of the right size to estimate the fees
+            let placeholder = TxOut {
+                script_pubkey: self.peek_change_script()?,
+                value: 0,
+            };
+            let output_fee = calc_fee_bytes(serialize(&placeholder).len() * 4);
+
+            for value in &decoy_values {
+                fee_amount += output_fee;
+                outgoing += value;
+                received += value;
             }
         }
 
-        // probably redundant but it doesn't hurt...
-        self.add_input_hd_keypaths(&mut psbt)?;
+        if builder.change_script.is_some()
+            && matches!(builder.split_change, Some((parts, _)) if parts > 1)
+        {
+            return Err(Error::Generic(
+                "The change can't be split when sending it to a custom script".into(),
+            ));
+        }
 
-        // add metadata for the outputs
-        for (psbt_output, tx_output) in psbt
-            .outputs
-            .iter_mut()
-            .zip(psbt.global.unsigned_tx.output.iter())
+        if builder.change_policy != tx_builder::ChangeSpendPolicy::ChangeAllowed
+            && self.change_descriptor.is_none()
         {
-            if let Some((keychain, child)) = self
-                .database
-                .borrow()
-                .get_path_from_script_pubkey(&tx_output.script_pubkey)?
-            {
-                let (desc, _) = self.get_descriptor_for_keychain(keychain);
-                psbt_output.hd_keypaths = desc.get_hd_keypaths(child, &self.secp)?;
-                if builder.include_output_redeem_witness_script {
-                    let derived_descriptor = desc.derive(ChildNumber::from_normal_idx(child)?);
-                    psbt_output.witness_script = derived_descriptor.psbt_witness_script(&self.secp);
-                    psbt_output.redeem_script = derived_descriptor.psbt_redeem_script(&self.secp);
-                };
+            return Err(Error::Generic(
+                "The `change_policy` can be set only if the wallet has a change_descriptor".into(),
+            ));
+        }
+
+        let (mut required_utxos, optional_utxos) = self.preselect_utxos(
+            builder.change_policy,
+            &builder.unspendable,
+            &builder.utxos,
+            builder.drain_wallet,
+            builder.manually_selected_only,
+            false, // we don't mind using unconfirmed outputs here, hopefully coin selection will sort this out?
+        )?;
+
+        // foreign utxos are always spent. The keychain is meaningless for them, it's only set
+        // because coin selection works with `UTXO`s
+        let mut foreign_amount = 0;
+        for (outpoint, psbt_input, satisfaction_weight) in &builder.foreign_utxos {
+            // the private keys of every input are needed to pay a silent payment address
+            if !builder.silent_payments.is_empty() {
+                return Err(silent_payments::SilentPaymentError::MissingInputKey(*outpoint).into());
             }
+
+            let txout = self.get_foreign_txout(outpoint, psbt_input)?;
+            foreign_amount += txout.value;
+            required_utxos.push((
+                UTXO {
+                    outpoint: *outpoint,
+                    txout,
+                    keychain: KeychainKind::External,
+                },
+                *satisfaction_weight,
+            ));
         }
 
-        Ok(psbt)
-    }
+        let coin_selection::CoinSelectionResult {
+            selected,
+            selected_amount,
+            mut fee_amount,
+            ..
+        } = self.get_coin_selection(builder).coin_select(
+            self.database.borrow().deref(),
+            required_utxos,
+            optional_utxos,
+            fee_rate,
+            outgoing,
+            fee_amount,
+        )?;
+        tx.input = selected
+            .iter()
+            .map(|u| bitcoin::TxIn {
+                previous_output: u.outpoint,
+                script_sig: Script::default(),
+                sequence: builder
+                    .input_nsequence
+                    .get(&u.outpoint)
+                    .cloned()
+                    .unwrap_or(n_sequence),
+                witness: vec![],
+            })
+            .collect();
 
-    fn add_input_hd_keypaths(&self, psbt: &mut PSBT) -> Result<(), Error> {
-        let mut input_utxos = Vec::with_capacity(psbt.inputs.len());
-        for n in 0..psbt.inputs.len() {
-            input_utxos.push(psbt.get_utxo_for(n).clone());
+        // Overriding the nSequence of the inputs must not disable the nLockTime
+        if lock_time != 0 && tx.input.iter().all(|txin| txin.sequence == 0xFFFFFFFF) {
+            return Err(Error::Generic(format!(
+                "Cannot use a final nSequence on every input given a nLockTime of `{}`",
+                lock_time
+            )));
         }
 
-        // try to add hd_keypaths if we've already seen the output
-        for (psbt_input, out) in psbt.inputs.iter_mut().zip(input_utxos.iter()) {
-            if let Some(out) = out {
-                if let Some((keychain, child)) = self
-                    .database
-                    .borrow()
-                    .get_path_from_script_pubkey(&out.script_pubkey)?
-                {
-                    debug!("Found descriptor {:?}/{}", keychain, child);
+        // the silent payments were added with a placeholder script of the same size, the actual
+        // scripts depend on the inputs. In dry-run mode the placeholders are good enough, and
+        // this doesn't require the private keys
+        if !builder.silent_payments.is_empty() && builder.single_recipient.is_none() && !dry_run {
+            let addresses = builder
+                .silent_payments
+                .iter()
+                .map(|(_, address)| *address)
+                .collect::<Vec<_>>();
+            let scripts = self.get_silent_payment_scripts(&addresses, &selected)?;
+            for ((index, address), script) in builder.silent_payments.iter().zip(scripts) {
+                // the address is needed to compute the script again if the fee is bumped
+                self.database
+                    .borrow_mut()
+                    .set_silent_payment_recipient(&script, address)?;
+                tx.output[*index].script_pubkey = script;
+            }
+        }
 
-                    // merge hd_keypaths
-                    let (desc, _) = self.get_descriptor_for_keychain(keychain);
-                    let mut hd_keypaths = desc.get_hd_keypaths(child, &self.secp)?;
-                    psbt_input.hd_keypaths.append(&mut hd_keypaths);
+        for value in decoy_values {
+            tx.output.push(TxOut {
+                script_pubkey: next_change_script()?,
+                value,
+            });
+        }
+
+        // prepare the change output
+        let change_output = match builder.single_recipient {
+            Some(_) => None,
+            None => {
+                let change_script = match &builder.change_script {
+                    Some(script) => script.clone(),
+                    None => next_change_script()?,
+                };
+                let change_output = TxOut {
+                    script_pubkey: change_script,
+                    value: 0,
+                };
+
+                // take the change into account for fees, unless we already know that it won't be
+                // added to the transaction
+                if builder.change_output_policy != tx_builder::ChangeOutputPolicy::Never {
+                    fee_amount += calc_fee_bytes(serialize(&change_output).len() * 4);
                 }
+                Some(change_output)
             }
-        }
+        };
 
-        Ok(())
-    }
-}
+        let mut fee_amount = fee_amount.ceil() as u64;
+        let change_val = (selected_amount - outgoing).saturating_sub(fee_amount);
 
-impl<B, D> Wallet<B, D>
-where
-    B: Blockchain,
-    D: BatchDatabase,
-{
-    /// Create a new "online" wallet
-    #[maybe_async]
-    pub fn new<E: ToWalletDescriptor>(
-        descriptor: E,
-        change_descriptor: Option<E>,
-        network: Network,
-        database: D,
-        client: B,
-    ) -> Result<Self, Error> {
-        let mut wallet = Self::new_offline(descriptor, change_descriptor, network, database)?;
+        let change_amount = match change_output {
+            None if dust_limit.is_dust(change_val, &tx.output[0].script_pubkey) => {
+                // single recipient, but the only output would be below dust limit
+                return Err(Error::InsufficientFunds); // TODO: or OutputBelowDustLimit?
+            }
+            Some(ref change_output)
+                if dust_limit.is_dust(change_val, &change_output.script_pubkey)
+                    || !builder.change_output_policy.keeps_change(change_val) =>
+            {
+                // skip the change output because it's dust or the policy doesn't allow it, this
+                // adds up to the fees
+                fee_amount += change_val;
+                0
+            }
+            Some(mut change_output) => {
+                let (values, extra_fee) = match builder.split_change {
+                    Some((parts, min_value)) if parts > 1 => {
+                        // every additional change output has the same size as the first one
+                        let output_fee =
+                            calc_fee_bytes(serialize(&change_output).len() * 4).ceil() as u64;
+                        split_change_value(
+                            change_val,
+                            parts,
+                            min_value,
+                            output_fee,
+                            &dust_limit,
+                            &change_output.script_pubkey,
+                            &mut rng,
+                        )
+                    }
+                    _ => (vec![change_val], 0),
+                };
+                fee_amount += extra_fee;
 
-        wallet.current_height = Some(maybe_await!(client.get_height())? as u32);
-        wallet.client = Some(client);
+                let mut values = values.into_iter();
+                change_output.value = values.next().expect("At least one change value");
+                if self.is_mine(&change_output.script_pubkey)? {
+                    received += change_output.value;
+                }
+                tx.output.push(change_output);
 
-        Ok(wallet)
-    }
+                for value in values {
+                    received += value;
+                    tx.output.push(TxOut {
+                        script_pubkey: next_change_script()?,
+                        value,
+                    });
+                }
 
-    /// Sync the internal database with the blockchain
-    #[maybe_async]
-    pub fn sync<P: 'static + Progress>(
-        &self,
-        progress_update: P,
-        max_address_param: Option<u32>,
-    ) -> Result<(), Error> {
-        debug!("Begin sync...");
+                change_val - extra_fee
+            }
+            None => {
+                // there's only one output, send everything to it
+                tx.output[0].value = change_val;
 
-        let mut run_setup = false;
+                // the single recipient is our address
+                if self.is_mine(&tx.output[0].script_pubkey)? {
+                    received = change_val;
+                }
 
-        let max_address = match self.descriptor.is_fixed() {
-            true => 0,
-            false => max_address_param.unwrap_or(CACHE_ADDR_BATCH_SIZE),
+                0
+            }
         };
-        if self
-            .database
-            .borrow()
-            .get_script_pubkey_from_path(KeychainKind::External, max_address.saturating_sub(1))?
-            .is_none()
-        {
-            run_setup = true;
-            self.cache_addresses(KeychainKind::External, 0, max_address)?;
+
+        // this can only happen when consolidating without recipients, if the change was dropped
+        if tx.output.is_empty() {
+            return Err(Error::Generic(
+                "The consolidation doesn't have any output, the change was dropped".into(),
+            ));
         }
 
-        if let Some(change_descriptor) = &self.change_descriptor {
-            let max_address = match change_descriptor.is_fixed() {
-                true => 0,
-                false => max_address_param.unwrap_or(CACHE_ADDR_BATCH_SIZE),
-            };
+        // the recipients are always the first outputs, before any decoy or change
+        let recipients = tx
+            .output
+            .iter()
+            .take(recipients_count)
+            .map(|txout| recipient_details(txout, builder.single_recipient.is_some(), fee_amount))
+            .collect();
 
-            if self
-                .database
-                .borrow()
-                .get_script_pubkey_from_path(KeychainKind::Internal, max_address.saturating_sub(1))?
-                .is_none()
-            {
-                run_setup = true;
-                self.cache_addresses(KeychainKind::Internal, 0, max_address)?;
-            }
+        // sort input/outputs according to the chosen algorithm
+        match builder.rng_seed {
+            Some(_) => builder.ordering.sort_tx_with_rng(&mut tx, &mut rng),
+            None => builder.ordering.sort_tx(&mut tx),
         }
 
-        // TODO: what if i generate an address first and cache some addresses?
-        // TODO: we should sync if generating an address triggers a new batch to be stored
-        if run_setup {
-            maybe_await!(self.client.as_ref().ok_or(Error::OfflineClient)?.setup(
-                None,
+        let transaction_details = TransactionDetails {
+            transaction: None,
+            txid: tx.txid(),
+            timestamp: time::get_timestamp(),
+            received,
+            sent: selected_amount - foreign_amount,
+            fees: fee_amount,
+            height: None,
+            recipients,
+        };
+
+        Ok((tx, selected, transaction_details, change_amount))
+    }
+
+    fn get_descriptor_for_keychain(
+        &self,
+        keychain: KeychainKind,
+    ) -> (&ExtendedDescriptor, KeychainKind) {
+        match keychain {
+            KeychainKind::Internal if self.change_descriptor.is_some() => (
+                self.change_descriptor.as_ref().unwrap(),
+                KeychainKind::Internal,
+            ),
+            _ => (&self.descriptor, KeychainKind::External),
+        }
+    }
+
+    fn get_descriptor_for_txout(&self, txout: &TxOut) -> Result<Option<ExtendedDescriptor>, Error> {
+        match self
+            .database
+            .borrow()
+            .get_path_from_script_pubkey(&txout.script_pubkey)?
+        {
+            Some((keychain, child)) => {
+                let (desc, _) = self.get_descriptor_for_keychain(keychain);
+                Ok(Some(desc.derive(ChildNumber::from_normal_idx(child)?)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Return the private key for `key` at `index`, if one of the signers of `keychain` has it
+    fn derive_private_key(
+        &self,
+        keychain: KeychainKind,
+        key: &DescriptorPublicKey,
+        index: u32,
+    ) -> Result<Option<PrivateKey>, Error> {
+        let signers = match keychain {
+            KeychainKind::External => &self.signers,
+            KeychainKind::Internal => &self.change_signers,
+        };
+
+        match signers.as_key_map(&self.secp).remove(key) {
+            Some(DescriptorSecretKey::SinglePriv(single)) => Ok(Some(single.key)),
+            Some(DescriptorSecretKey::XPrv(xprv)) => {
+                let mut derive_path = xprv.derivation_path.as_ref().to_vec();
+                if xprv.is_wildcard {
+                    derive_path.push(ChildNumber::from_normal_idx(index)?);
+                }
+
+                Ok(Some(
+                    xprv.xkey.derive_priv(&self.secp, &derive_path)?.private_key,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Compute the scripts of the silent payments of a transaction spending `selected`
+    ///
+    /// Only the inputs spending `pkh`, `wpkh` and `sh(wpkh)` outputs contribute to the shared
+    /// secret, but their private keys must all be known.
+    fn get_silent_payment_scripts(
+        &self,
+        addresses: &[silent_payments::SilentPaymentAddress],
+        selected: &[UTXO],
+    ) -> Result<Vec<Script>, Error> {
+        let mut input_keys = vec![];
+        for utxo in selected {
+            let (keychain, child) = match self
+                .database
+                .borrow()
+                .get_path_from_script_pubkey(&utxo.txout.script_pubkey)?
+            {
+                Some(path) => path,
+                None => {
+                    return Err(
+                        silent_payments::SilentPaymentError::MissingInputKey(utxo.outpoint).into(),
+                    )
+                }
+            };
+
+            let (descriptor, keychain) = self.get_descriptor_for_keychain(keychain);
+            let script_type = descriptor.script_type();
+            if !matches!(
+                script_type,
+                ScriptType::P2pkh | ScriptType::P2wpkh | ScriptType::P2shP2wpkh
+            ) {
+                continue;
+            }
+
+            let key = descriptor.get_keys()?.remove(0);
+            match self.derive_private_key(keychain, &key, child)? {
+                // uncompressed keys can't be used for silent payments
+                Some(private_key) if !private_key.compressed => continue,
+                Some(private_key) => input_keys.push(private_key.key),
+                None => {
+                    return Err(
+                        silent_payments::SilentPaymentError::MissingInputKey(utxo.outpoint).into(),
+                    )
+                }
+            }
+        }
+
+        let outpoints = selected
+            .iter()
+            .map(|utxo| utxo.outpoint)
+            .collect::<Vec<_>>();
+        Ok(silent_payments::output_scripts(
+            &self.secp,
+            &input_keys,
+            &outpoints,
+            addresses,
+        )?)
+    }
+
+    /// Store the silent payments received by `receiver` in the block at `height`, and remove the
+    /// ones spent by its transactions
+    fn process_silent_payment_block(
+        &self,
+        receiver: &silent_payments::SilentPaymentReceiver,
+        height: u32,
+        txs: &[(Transaction, Vec<TxOut>)],
+    ) -> Result<(), Error> {
+        let mut database = self.database.borrow_mut();
+        let mut batch = database.begin_batch();
+
+        // the outputs can be spent in the same block, so they are only stored at the end
+        let mut found = HashMap::new();
+        for (tx, prevouts) in txs {
+            for txin in &tx.input {
+                if found.remove(&txin.previous_output).is_some()
+                    || database
+                        .get_silent_payment_output(&txin.previous_output)?
+                        .is_some()
+                {
+                    debug!("Silent payment {} spent", txin.previous_output);
+                    batch.del_silent_payment_output(&txin.previous_output)?;
+                }
+            }
+
+            if prevouts.is_empty() {
+                continue;
+            }
+            for mut output in receiver.scan_transaction(&self.secp, tx, prevouts)? {
+                info!("Found silent payment {}", output.outpoint);
+                output.height = Some(height);
+                found.insert(output.outpoint, output);
+            }
+        }
+
+        for output in found.values() {
+            batch.set_silent_payment_output(output)?;
+        }
+        batch.set_silent_payment_scan_height(height + 1)?;
+
+        database.commit_batch(batch)
+    }
+
+    fn get_change_address(&self, allow_reuse: bool) -> Result<Script, Error> {
+        let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+
+        let (desc, keychain) = self.get_descriptor_for_keychain(KeychainKind::Internal);
+        // never reuse an external address, it could have been given to someone else
+        let reused_index = match (allow_reuse, self.change_index_policy, keychain) {
+            (true, ChangeIndexPolicy::ReuseUnused, KeychainKind::Internal) => {
+                self.get_unused_last_index(keychain)?
+            }
+            _ => None,
+        };
+        let index = match reused_index {
+            Some(index) => index,
+            None => self.fetch_and_increment_index(keychain)?,
+        };
+
+        Ok(desc
+            .derive(ChildNumber::from_normal_idx(index)?)
+            .script_pubkey(deriv_ctx))
+    }
+
+    // Estimate the weight of `tx` once all the `selected` inputs are signed
+    fn estimate_signed_weight(
+        &self,
+        tx: &Transaction,
+        selected: &[UTXO],
+        foreign_utxos: &[(OutPoint, psbt::Input, usize)],
+    ) -> Result<usize, Error> {
+        let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+        let satisfaction_weight = selected
+            .iter()
+            .map(|utxo| {
+                match foreign_utxos
+                    .iter()
+                    .find(|(outpoint, _, _)| *outpoint == utxo.outpoint)
+                {
+                    Some((_, _, satisfaction_weight)) => Some(*satisfaction_weight),
+                    None => self
+                        .get_descriptor_for_keychain(utxo.keychain)
+                        .0
+                        .max_satisfaction_weight(deriv_ctx),
+                }
+            })
+            .sum::<Option<usize>>()
+            .ok_or_else(|| Error::Generic("Unable to estimate the satisfaction weight".into()))?;
+
+        Ok(tx.get_weight() + satisfaction_weight)
+    }
+
+    // Make sure that the fees of a transaction don't exceed the limits. The fee rate is only
+    // checked if the `weight` of the signed transaction is known
+    fn check_max_fee(
+        &self,
+        fees: u64,
+        weight: Option<usize>,
+        max_fee_rate: Option<FeeRate>,
+        max_fee_absolute: Option<u64>,
+    ) -> Result<(), Error> {
+        if let Some(max) = max_fee_absolute {
+            if fees > max {
+                return Err(Error::FeeTooHigh { fee: fees, max });
+            }
+        }
+
+        if let (Some(max), Some(weight)) = (max_fee_rate, weight) {
+            let fee_rate = FeeRate::from_sat_per_vb(fees as f32 / (weight as f32 / 4.0));
+            if fee_rate > max {
+                return Err(Error::FeeRateTooHigh { fee_rate, max });
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check the fees of a PSBT against the limits of the wallet. Nothing can be checked if the
+    // value of some input is unknown, and the fee rate can only be checked if we know how to
+    // satisfy every input
+    fn check_psbt_max_fee(&self, psbt: &PSBT) -> Result<(), Error> {
+        if self.max_fee_rate.is_none() && self.max_fee_absolute.is_none() {
+            return Ok(());
+        }
+
+        let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+        let tx = &psbt.global.unsigned_tx;
+
+        let mut input_value = 0;
+        let mut satisfaction_weight = Some(0);
+        for n in 0..tx.input.len() {
+            let txout = match psbt.get_utxo_for(n) {
+                Some(txout) => txout,
+                None => return Ok(()),
+            };
+            input_value += txout.value;
+
+            let weight = self
+                .get_descriptor_for_txout(&txout)?
+                .and_then(|desc| desc.max_satisfaction_weight(deriv_ctx));
+            satisfaction_weight = satisfaction_weight.and_then(|acc| weight.map(|w| acc + w));
+        }
+        let output_value = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+
+        self.check_max_fee(
+            input_value.saturating_sub(output_value),
+            satisfaction_weight.map(|w| tx.get_weight() + w),
+            self.max_fee_rate,
+            self.max_fee_absolute,
+        )
+    }
+
+    // Make sure that the inputs of the wallet can be signed according to `sign_options`
+    fn check_psbt_inputs(&self, psbt: &PSBT, sign_options: &SignOptions) -> Result<(), Error> {
+        let tx = &psbt.global.unsigned_tx;
+        for (n, (txin, psbt_input)) in tx.input.iter().zip(psbt.inputs.iter()).enumerate() {
+            if Finalizer::is_finalized(psbt, n) {
+                continue;
+            }
+            // inputs that don't belong to the wallet won't be signed anyways
+            match psbt.get_utxo_for(n) {
+                Some(txout) if self.is_mine(&txout.script_pubkey)? => {}
+                _ => continue,
+            }
+
+            if !sign_options.allow_all_sighashes
+                && psbt_input.sighash_type.unwrap_or(SigHashType::All) != SigHashType::All
+            {
+                return Err(SignerError::NonStandardSighash.into());
+            }
+
+            if let (false, Some(witness_utxo)) =
+                (sign_options.trust_witness_utxo, &psbt_input.witness_utxo)
+            {
+                match &psbt_input.non_witness_utxo {
+                    None => return Err(SignerError::MissingNonWitnessUtxo.into()),
+                    Some(prev_tx)
+                        if prev_tx.txid() != txin.previous_output.txid
+                            || prev_tx.output.get(txin.previous_output.vout as usize)
+                                != Some(witness_utxo) =>
+                    {
+                        return Err(SignerError::InvalidNonWitnessUtxo.into())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_psbt_outputs(&self, psbt: &PSBT) -> Result<(), Error> {
+        for (n, ownership) in self.verify_psbt_outputs(psbt)?.into_iter().enumerate() {
+            if ownership == OutputOwnership::InvalidDerivation {
+                return Err(SignerError::InvalidChangeOutput(n).into());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Return the output spent by a foreign utxo, making sure that it's consistent with its psbt
+    // input and that it doesn't belong to the wallet
+    fn get_foreign_txout(
+        &self,
+        outpoint: &OutPoint,
+        psbt_input: &psbt::Input,
+    ) -> Result<TxOut, Error> {
+        let txout = match (&psbt_input.non_witness_utxo, &psbt_input.witness_utxo) {
+            (Some(prev_tx), _) if prev_tx.txid() != outpoint.txid => {
+                return Err(Error::Generic(format!(
+                    "The `non_witness_utxo` of the foreign UTXO `{}` has txid `{}`",
+                    outpoint,
+                    prev_tx.txid()
+                )))
+            }
+            (Some(prev_tx), _) => prev_tx
+                .output
+                .get(outpoint.vout as usize)
+                .cloned()
+                .ok_or_else(|| {
+                    Error::Generic(format!("The foreign UTXO `{}` doesn't exist", outpoint))
+                })?,
+            (None, Some(txout)) => txout.clone(),
+            (None, None) => {
+                return Err(Error::Generic(format!(
+                    "The PSBT input of the foreign UTXO `{}` has neither a `witness_utxo` nor a `non_witness_utxo`",
+                    outpoint
+                )))
+            }
+        };
+
+        if self.is_mine(&txout.script_pubkey)? {
+            return Err(Error::Generic(format!(
+                "The UTXO `{}` belongs to the wallet, it can't be added as a foreign UTXO",
+                outpoint
+            )));
+        }
+
+        Ok(txout)
+    }
+
+    // Return a change script without incrementing the derivation index. This is only meant to be
+    // used to estimate the size of change outputs.
+    fn peek_change_script(&self) -> Result<Script, Error> {
+        let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+
+        let (desc, _) = self.get_descriptor_for_keychain(KeychainKind::Internal);
+        Ok(desc
+            .derive(ChildNumber::from_normal_idx(0)?)
+            .script_pubkey(deriv_ctx))
+    }
+
+    fn fetch_and_increment_index(&self, keychain: KeychainKind) -> Result<u32, Error> {
+        let (descriptor, keychain) = self.get_descriptor_for_keychain(keychain);
+        // a fixed descriptor only has one script, so there's nothing else we could pick
+        let used_scripts = match self.avoid_address_reuse && !descriptor.is_fixed() {
+            true => Some(self.get_used_script_pubkeys()?),
+            false => None,
+        };
+
+        let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+
+        let (index, script) = loop {
+            let index = match descriptor.is_fixed() {
+                true => 0,
+                false => self.database.borrow_mut().increment_last_index(keychain)?,
+            };
+            if index > MAX_DERIVABLE_INDEX {
+                return Err(Error::InvalidDerivationIndex(index));
+            }
+
+            if self
+                .database
+                .borrow()
+                .get_script_pubkey_from_path(keychain, index)?
+                .is_none()
+            {
+                self.cache_addresses(keychain, index, CACHE_ADDR_BATCH_SIZE)?;
+            }
+
+            let script = descriptor
+                .derive(ChildNumber::from_normal_idx(index)?)
+                .script_pubkey(deriv_ctx);
+            match used_scripts {
+                Some(ref used) if used.contains(&script) => {
+                    debug!("Skipping {:?} index {}: already used", keychain, index)
+                }
+                _ => break (index, script),
+            }
+        };
+
+        let hd_keypaths = descriptor.get_hd_keypaths(index, &self.secp)?;
+        for validator in &self.address_validators {
+            validator.validate(keychain, &hd_keypaths, &script)?;
+        }
+
+        Ok(index)
+    }
+
+    // Returns the last index derived for `keychain`, if its script hasn't received any funds yet
+    fn get_unused_last_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        let index = match self.database.borrow().get_last_index(keychain)? {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let script = self
+            .database
+            .borrow()
+            .get_script_pubkey_from_path(keychain, index)?;
+
+        match script {
+            Some(script) if !self.get_used_script_pubkeys()?.contains(&script) => Ok(Some(index)),
+            _ => Ok(None),
+        }
+    }
+
+    // Returns every script that has ever received funds according to the database
+    fn get_used_script_pubkeys(&self) -> Result<HashSet<Script>, Error> {
+        let database = self.database.borrow();
+
+        let mut used = database
+            .iter_utxos()?
+            .into_iter()
+            .map(|utxo| utxo.txout.script_pubkey)
+            .collect::<HashSet<_>>();
+        for tx in database.iter_raw_txs()? {
+            used.extend(tx.output.into_iter().map(|txout| txout.script_pubkey));
+        }
+
+        Ok(used)
+    }
+
+    fn cache_addresses(
+        &self,
+        keychain: KeychainKind,
+        from: u32,
+        mut count: u32,
+    ) -> Result<(), Error> {
+        let (descriptor, keychain) = self.get_descriptor_for_keychain(keychain);
+        if descriptor.is_fixed() {
+            if from > 0 {
+                return Ok(());
+            }
+
+            count = 1;
+        }
+        if from > MAX_DERIVABLE_INDEX {
+            return Err(Error::InvalidDerivationIndex(from));
+        }
+        // don't try to cache addresses past the last derivable index
+        count = count.min(MAX_DERIVABLE_INDEX - from + 1);
+
+        let mut address_batch = self.database.borrow().begin_batch();
+
+        let start_time = time::Instant::new();
+        let script_pubkeys = derive_script_pubkeys(descriptor, from..(from + count), &self.secp)?;
+        for (i, script_pubkey) in (from..).zip(script_pubkeys.iter()) {
+            address_batch.set_script_pubkey(script_pubkey, keychain, i)?;
+        }
+
+        info!(
+            "Derivation of {} addresses from {} took {} ms",
+            count,
+            from,
+            start_time.elapsed().as_millis()
+        );
+
+        self.database.borrow_mut().commit_batch(address_batch)?;
+
+        Ok(())
+    }
+
+    fn get_available_utxos(&self) -> Result<Vec<(UTXO, usize)>, Error> {
+        let deriv_ctx = descriptor_to_pk_ctx(&self.secp);
+        Ok(self
+            .list_unspent()?
+            .into_iter()
+            .map(|utxo| {
+                let keychain = utxo.keychain;
+                (
+                    utxo,
+                    self.get_descriptor_for_keychain(keychain)
+                        .0
+                        .max_satisfaction_weight(deriv_ctx)
+                        .unwrap(),
+                )
+            })
+            .collect())
+    }
+
+    /// Given the options returns the list of utxos that must be used to form the
+    /// transaction and any further that may be used if needed.
+    #[allow(clippy::type_complexity)]
+    fn preselect_utxos(
+        &self,
+        change_policy: tx_builder::ChangeSpendPolicy,
+        unspendable: &HashSet<OutPoint>,
+        manually_selected: &[OutPoint],
+        must_use_all_available: bool,
+        manual_only: bool,
+        must_only_use_confirmed_tx: bool,
+    ) -> Result<(Vec<(UTXO, usize)>, Vec<(UTXO, usize)>), Error> {
+        //    must_spend <- manually selected utxos
+        //    may_spend  <- all other available utxos
+        let mut may_spend = self.get_available_utxos()?;
+        // remove the utxos one by one, in the order they were selected: removing an element
+        // shifts the position of the following ones
+        let mut must_spend = Vec::with_capacity(manually_selected.len());
+        for manually_selected in manually_selected {
+            // the same utxo could be listed more than once with `TxBuilder::utxos`
+            if must_spend
+                .iter()
+                .any(|(utxo, _): &(UTXO, usize)| utxo.outpoint == *manually_selected)
+            {
+                continue;
+            }
+
+            let index = may_spend
+                .iter()
+                .position(|available| available.0.outpoint == *manually_selected)
+                .ok_or(Error::UnknownUTXO)?;
+            must_spend.push(may_spend.remove(index));
+        }
+
+        // NOTE: we are intentionally ignoring `unspendable` here. i.e manual
+        // selection overrides unspendable.
+        if manual_only {
+            return Ok((must_spend, vec![]));
+        }
+
+        let satisfies_confirmed = match must_only_use_confirmed_tx {
+            true => {
+                let database = self.database.borrow_mut();
+                may_spend
+                    .iter()
+                    .map(|u| {
+                        database
+                            .get_tx(&u.0.outpoint.txid, true)
+                            .map(|tx| match tx {
+                                None => false,
+                                Some(tx) => tx.height.is_some(),
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            false => vec![true; may_spend.len()],
+        };
+
+        let mut i = 0;
+        may_spend.retain(|u| {
+            let retain = change_policy.is_satisfied_by(&u.0)
+                && !unspendable.contains(&u.0.outpoint)
+                && satisfies_confirmed[i];
+            i += 1;
+            retain
+        });
+
+        if must_use_all_available {
+            must_spend.append(&mut may_spend);
+        }
+
+        Ok((must_spend, may_spend))
+    }
+
+    fn complete_transaction<
+        Cs: coin_selection::CoinSelectionAlgorithm<D>,
+        Ctx: TxBuilderContext,
+    >(
+        &self,
+        tx: Transaction,
+        selected: Vec<UTXO>,
+        builder: TxBuilder<D, Cs, Ctx>,
+    ) -> Result<PSBT, Error> {
+        use bitcoin::util::psbt::serialize::Serialize;
+
+        let mut psbt = PSBT::from_unsigned_tx(tx)?;
+
+        if builder.add_global_xpubs {
+            let mut all_xpubs = self.descriptor.get_extended_keys()?;
+            if let Some(change_descriptor) = &self.change_descriptor {
+                all_xpubs.extend(change_descriptor.get_extended_keys()?);
+            }
+
+            for xpub in all_xpubs {
+                let serialized_xpub = base58::from_check(&xpub.xkey.to_string())
+                    .expect("Internal serialization error");
+                let key = PSBTKey {
+                    type_value: 0x01,
+                    key: serialized_xpub,
+                };
+
+                let origin = match xpub.origin {
+                    Some(origin) => origin,
+                    None if xpub.xkey.depth == 0 => {
+                        (xpub.root_fingerprint(&self.secp), vec![].into())
+                    }
+                    _ => return Err(Error::MissingKeyOrigin(xpub.xkey.to_string())),
+                };
+
+                psbt.global.unknown.insert(key, origin.serialize());
+            }
+        }
+
+        let lookup_output = selected
+            .into_iter()
+            .map(|utxo| (utxo.outpoint, utxo))
+            .collect::<HashMap<_, _>>();
+
+        // add metadata for the inputs
+        for (psbt_input, input) in psbt
+            .inputs
+            .iter_mut()
+            .zip(psbt.global.unsigned_tx.input.iter())
+        {
+            if let Some((_, foreign_input, _)) = builder
+                .foreign_utxos
+                .iter()
+                .find(|(outpoint, _, _)| *outpoint == input.previous_output)
+            {
+                *psbt_input = foreign_input.clone();
+                if psbt_input.sighash_type.is_none() {
+                    psbt_input.sighash_type = builder.sighash;
+                }
+                continue;
+            }
+
+            let utxo = match lookup_output.get(&input.previous_output) {
+                Some(utxo) => utxo,
+                None => continue,
+            };
+
+            match self.get_psbt_input(
+                utxo.clone(),
+                builder.sighash,
+                builder.force_non_witness_utxo,
+            ) {
+                Ok(input) => *psbt_input = input,
+                // Not one of our scripts, we can only set the sighash. Only set it if the builder
+                // has a custom one, otherwise leave blank which defaults to SIGHASH_ALL
+                Err(Error::UnknownUTXO) => psbt_input.sighash_type = builder.sighash,
+                Err(e) => return Err(e),
+            }
+        }
+
+        // probably redundant but it doesn't hurt...
+        self.add_input_hd_keypaths(&mut psbt)?;
+
+        // add metadata for the outputs
+        let mut output_paths = Vec::with_capacity(psbt.outputs.len());
+        for (psbt_output, tx_output) in psbt
+            .outputs
+            .iter_mut()
+            .zip(psbt.global.unsigned_tx.output.iter())
+        {
+            let path = self
+                .database
+                .borrow()
+                .get_path_from_script_pubkey(&tx_output.script_pubkey)?;
+            if let Some((keychain, child)) = path {
+                let (desc, _) = self.get_descriptor_for_keychain(keychain);
+                psbt_output.hd_keypaths = desc.get_hd_keypaths(child, &self.secp)?;
+                if builder.include_output_redeem_witness_script {
+                    let derived_descriptor = desc.derive(ChildNumber::from_normal_idx(child)?);
+                    psbt_output.witness_script = derived_descriptor.psbt_witness_script(&self.secp);
+                    psbt_output.redeem_script = derived_descriptor.psbt_redeem_script(&self.secp);
+                };
+            }
+            output_paths.push(path);
+        }
+
+        if builder.add_bdk_metadata {
+            let metadata = BdkMetadata {
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+                external_policy_path: builder.external_policy_path.clone(),
+                internal_policy_path: builder.internal_policy_path.clone(),
+                outputs: output_paths,
+            };
+            metadata.add_to_psbt(&mut psbt);
+        }
+
+        Ok(psbt)
+    }
+
+    fn add_input_hd_keypaths(&self, psbt: &mut PSBT) -> Result<(), Error> {
+        let mut input_utxos = Vec::with_capacity(psbt.inputs.len());
+        for n in 0..psbt.inputs.len() {
+            input_utxos.push(psbt.get_utxo_for(n).clone());
+        }
+
+        // try to add hd_keypaths if we've already seen the output
+        for (psbt_input, out) in psbt.inputs.iter_mut().zip(input_utxos.iter()) {
+            if let Some(out) = out {
+                if let Some((keychain, child)) = self
+                    .database
+                    .borrow()
+                    .get_path_from_script_pubkey(&out.script_pubkey)?
+                {
+                    debug!("Found descriptor {:?}/{}", keychain, child);
+
+                    // merge hd_keypaths
+                    let (desc, _) = self.get_descriptor_for_keychain(keychain);
+                    let mut hd_keypaths = desc.get_hd_keypaths(child, &self.secp)?;
+                    psbt_input.hd_keypaths.append(&mut hd_keypaths);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<B, D> Wallet<B, D>
+where
+    B: Blockchain,
+    D: BatchDatabase,
+{
+    /// Create a new "online" wallet
+    ///
+    /// Like [`new_offline`](Wallet::new_offline), this refuses to open a `database` created for
+    /// different descriptors.
+    #[maybe_async]
+    pub fn new<E: ToWalletDescriptor>(
+        descriptor: E,
+        change_descriptor: Option<E>,
+        network: Network,
+        database: D,
+        client: B,
+    ) -> Result<Self, Error> {
+        let mut wallet = Self::new_offline(descriptor, change_descriptor, network, database)?;
+
+        wallet.tip.set(Some(StaticTip {
+            height: maybe_await!(client.get_height())?,
+            median_time_past: None,
+        }));
+        wallet.client = Some(client);
+
+        Ok(wallet)
+    }
+
+    /// Sync the internal database with the blockchain, returning a [`Stream`](futures::stream::Stream)
+    /// of [`SyncUpdate`]s
+    ///
+    /// This is the equivalent of [`sync`](Wallet::sync), but instead of sending the progress
+    /// updates to a [`Progress`] type they are emitted by the stream, followed by exactly one
+    /// [`SyncUpdate::Finished`] with the result of the sync. The sync only makes progress while
+    /// the stream is polled, and dropping the stream before it's over cancels it.
+    #[cfg(feature = "async-interface")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+    pub fn sync_stream<'a>(
+        &'a self,
+        max_address_param: Option<u32>,
+    ) -> impl futures::stream::Stream<Item = SyncUpdate> + 'a {
+        SyncStream::new(move |progress| self.sync(progress, max_address_param))
+    }
+
+    /// Sync the internal database with the blockchain
+    #[maybe_async]
+    pub fn sync<P: 'static + Progress>(
+        &self,
+        progress_update: P,
+        max_address_param: Option<u32>,
+    ) -> Result<(), Error> {
+        debug!("Begin sync...");
+
+        let mut run_setup = false;
+
+        let max_address = match self.descriptor.is_fixed() {
+            true => 0,
+            false => max_address_param
+                .unwrap_or(CACHE_ADDR_BATCH_SIZE)
+                .min(MAX_DERIVABLE_INDEX + 1),
+        };
+        if self
+            .database
+            .borrow()
+            .get_script_pubkey_from_path(KeychainKind::External, max_address.saturating_sub(1))?
+            .is_none()
+        {
+            run_setup = true;
+            self.cache_addresses(KeychainKind::External, 0, max_address)?;
+        }
+
+        if let Some(change_descriptor) = &self.change_descriptor {
+            let max_address = match change_descriptor.is_fixed() {
+                true => 0,
+                false => max_address_param
+                    .unwrap_or(CACHE_ADDR_BATCH_SIZE)
+                    .min(MAX_DERIVABLE_INDEX + 1),
+            };
+
+            if self
+                .database
+                .borrow()
+                .get_script_pubkey_from_path(KeychainKind::Internal, max_address.saturating_sub(1))?
+                .is_none()
+            {
+                run_setup = true;
+                self.cache_addresses(KeychainKind::Internal, 0, max_address)?;
+            }
+        }
+
+        // TODO: what if i generate an address first and cache some addresses?
+        // TODO: we should sync if generating an address triggers a new batch to be stored
+        let client = self.client.as_ref().ok_or(Error::OfflineClient)?;
+        if run_setup {
+            maybe_await!(client.setup(
+                None,
                 self.database.borrow_mut().deref_mut(),
                 progress_update,
-            ))
+            ))?;
         } else {
-            maybe_await!(self.client.as_ref().ok_or(Error::OfflineClient)?.sync(
+            maybe_await!(client.sync(
+                None,
+                self.database.borrow_mut().deref_mut(),
+                progress_update,
+            ))?;
+        }
+
+        // refresh the tip of the chain, the oracle takes precedence over the blockchain
+        match &self.tip_oracle {
+            Some(_) => maybe_await!(self.update_tip())?,
+            None => {
+                self.tip.set(Some(StaticTip {
+                    height: maybe_await!(client.get_height())?,
+                    median_time_past: None,
+                }));
+            }
+        }
+
+        if let Some((receiver, from_height)) = &self.silent_payment_receiver {
+            if !maybe_await!(client.get_capabilities()).contains(&Capability::BlockTransactions) {
+                return Err(Error::Generic(
+                    "The blockchain backend can't scan the blocks for silent payments".into(),
+                ));
+            }
+
+            let first_height = self
+                .database
+                .borrow()
+                .get_silent_payment_scan_height()?
+                .unwrap_or(*from_height);
+            let last_height = maybe_await!(client.get_height())?;
+            debug!(
+                "Scanning blocks {} - {} for silent payments",
+                first_height, last_height
+            );
+            for height in first_height..=last_height {
+                let txs = maybe_await!(client.get_block_txs(height))?;
+                self.process_silent_payment_block(receiver, height, &txs)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return a reference to the internal blockchain client
+    pub fn client(&self) -> Option<&B> {
+        self.client.as_ref()
+    }
+
+    /// Get the Bitcoin network the wallet is using.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Broadcast a transaction to the network
+    ///
+    /// Fails with [`Error::TransactionNotFinal`] if the wallet knows that the nLockTime of the
+    /// transaction hasn't been reached yet, see [`Wallet::is_final_tx`].
+    #[maybe_async]
+    pub fn broadcast(&self, tx: Transaction) -> Result<Txid, Error> {
+        if self.is_final_tx(&tx) == Some(false) {
+            return Err(Error::TransactionNotFinal);
+        }
+
+        maybe_await!(self
+            .client
+            .as_ref()
+            .ok_or(Error::OfflineClient)?
+            .broadcast(&tx))?;
+
+        Ok(tx.txid())
+    }
+}
+
+// Report that the signer number `n` out of `count` is about to be called
+fn report_signer_progress(
+    progress: &dyn SignerProgress,
+    n: usize,
+    count: usize,
+) -> Result<(), SignerError> {
+    progress.update(
+        100.0 * n as f32 / count as f32,
+        Some(format!("Signing with signer {} of {}", n + 1, count)),
+    )
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::str::FromStr;
+
+    use bitcoin::Network;
+
+    use crate::blockchain::StaticTip;
+    use crate::database::memory::MemoryDatabase;
+    use crate::database::Database;
+    use crate::types::KeychainKind;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_addresses_fixed() {
+        let db = MemoryDatabase::new();
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(L5EZftvrYaSudiozVRzTqLcHLNDoVn7H5HSfM9BAN6tMJX8oTWz6)",
+            None,
+            Network::Testnet,
+            db,
+        )
+        .unwrap();
+
+        assert_eq!(
+            wallet.get_new_address().unwrap().to_string(),
+            "tb1qj08ys4ct2hzzc2hcz6h2hgrvlmsjynaw43s835"
+        );
+        assert_eq!(
+            wallet.get_new_address().unwrap().to_string(),
+            "tb1qj08ys4ct2hzzc2hcz6h2hgrvlmsjynaw43s835"
+        );
+
+        assert!(wallet
+            .database
+            .borrow_mut()
+            .get_script_pubkey_from_path(KeychainKind::External, 0)
+            .unwrap()
+            .is_some());
+        assert!(wallet
+            .database
+            .borrow_mut()
+            .get_script_pubkey_from_path(KeychainKind::Internal, 0)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_cache_addresses() {
+        let db = MemoryDatabase::new();
+        let wallet: OfflineWallet<_> = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", None, Network::Testnet, db).unwrap();
+
+        assert_eq!(
+            wallet.get_new_address().unwrap().to_string(),
+            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+        );
+        assert_eq!(
+            wallet.get_new_address().unwrap().to_string(),
+            "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+        );
+
+        assert!(wallet
+            .database
+            .borrow_mut()
+            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE - 1)
+            .unwrap()
+            .is_some());
+        assert!(wallet
+            .database
+            .borrow_mut()
+            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_list_addresses() {
+        let db = MemoryDatabase::new();
+        let wallet: OfflineWallet<_> = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", None, Network::Testnet, db).unwrap();
+
+        let addresses = wallet
+            .list_addresses(KeychainKind::External, 0..2)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            addresses,
+            vec![
+                "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a",
+                "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+            ]
+        );
+        assert_eq!(
+            wallet
+                .list_addresses(KeychainKind::External, 0..1000)
+                .unwrap()
+                .len(),
+            1000
+        );
+
+        // listing the addresses doesn't use them
+        assert_eq!(
+            wallet.get_new_address().unwrap().to_string(),
+            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+        );
+
+        assert!(matches!(
+            wallet.list_addresses(KeychainKind::External, 0..MAX_DERIVABLE_INDEX + 2),
+            Err(Error::InvalidDerivationIndex(_))
+        ));
+    }
+
+    #[test]
+    fn test_list_addresses_fixed() {
+        let db = MemoryDatabase::new();
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(L5EZftvrYaSudiozVRzTqLcHLNDoVn7H5HSfM9BAN6tMJX8oTWz6)",
+            None,
+            Network::Testnet,
+            db,
+        )
+        .unwrap();
+
+        let addresses = wallet
+            .list_addresses(KeychainKind::External, 0..10)
+            .unwrap();
+        assert_eq!(addresses.len(), 1);
+        assert_eq!(
+            addresses[0].to_string(),
+            "tb1qj08ys4ct2hzzc2hcz6h2hgrvlmsjynaw43s835"
+        );
+        assert!(wallet
+            .list_addresses(KeychainKind::External, 5..10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_cache_addresses_refill() {
+        let db = MemoryDatabase::new();
+        let wallet: OfflineWallet<_> = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", None, Network::Testnet, db).unwrap();
+
+        assert_eq!(
+            wallet.get_new_address().unwrap().to_string(),
+            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+        );
+        assert!(wallet
+            .database
+            .borrow_mut()
+            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE - 1)
+            .unwrap()
+            .is_some());
+
+        for _ in 0..CACHE_ADDR_BATCH_SIZE {
+            wallet.get_new_address().unwrap();
+        }
+
+        assert!(wallet
+            .database
+            .borrow_mut()
+            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE * 2 - 1)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_max_derivable_index() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        assert_eq!(wallet.max_derivable_index(KeychainKind::External), 0);
+
+        let wallet: OfflineWallet<_> = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", None, Network::Testnet, MemoryDatabase::new()).unwrap();
+        assert_eq!(
+            wallet.max_derivable_index(KeychainKind::External),
+            (1 << 31) - 1
+        );
+    }
+
+    #[test]
+    fn test_derivation_index_limit() {
+        let wallet: OfflineWallet<_> = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", None, Network::Testnet, MemoryDatabase::new()).unwrap();
+
+        wallet
+            .database
+            .borrow_mut()
+            .set_last_index(KeychainKind::External, MAX_DERIVABLE_INDEX - 1)
+            .unwrap();
+        // the last index can still be used
+        assert!(wallet.get_new_address().is_ok());
+        assert!(wallet
+            .database
+            .borrow_mut()
+            .get_script_pubkey_from_path(KeychainKind::External, MAX_DERIVABLE_INDEX)
+            .unwrap()
+            .is_some());
+
+        assert!(matches!(
+            wallet.get_new_address(),
+            Err(Error::InvalidDerivationIndex(index)) if index == MAX_DERIVABLE_INDEX + 1
+        ));
+    }
+
+    #[test]
+    fn test_avoid_address_reuse() {
+        let descriptors = testutils!(@descriptors ("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)"));
+        let mut wallet: OfflineWallet<_> = Wallet::new_offline(
+            &descriptors.0,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        wallet.database.borrow_mut().received_tx(
+            testutils! {
+                @tx ( (@external descriptors, 0) => 50_000, (@external descriptors, 1) => 20_000 ) (@confirmations 1)
+            },
+            Some(100),
+        );
+
+        wallet.set_avoid_address_reuse(true);
+        assert_eq!(
+            wallet.get_new_address().unwrap(),
+            testutils!(@external descriptors, 2)
+        );
+        assert_eq!(
+            wallet.get_new_address().unwrap(),
+            testutils!(@external descriptors, 3)
+        );
+    }
+
+    #[test]
+    fn test_descriptor_checksum_stored() {
+        let descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*)";
+        let change_descriptor = "wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)";
+        let get_database = || {
+            let wallet: OfflineWallet<_> = Wallet::new_offline(
+                descriptor,
+                Some(change_descriptor),
+                Network::Regtest,
+                MemoryDatabase::new(),
+            )
+            .unwrap();
+            wallet.database.into_inner()
+        };
+
+        // the same descriptors can be used again with the database
+        let res: Result<OfflineWallet<_>, _> = Wallet::new_offline(
+            descriptor,
+            Some(change_descriptor),
+            Network::Regtest,
+            get_database(),
+        );
+        assert!(res.is_ok());
+
+        let res: Result<OfflineWallet<_>, _> = Wallet::new_offline(
+            change_descriptor,
+            Some(descriptor),
+            Network::Regtest,
+            get_database(),
+        );
+        assert!(matches!(res, Err(Error::ChecksumMismatch)));
+
+        let res: Result<OfflineWallet<_>, _> = Wallet::new_offline(
+            descriptor,
+            Some("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/2/*)"),
+            Network::Regtest,
+            get_database(),
+        );
+        assert!(matches!(res, Err(Error::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn test_address_reuse_allowed_by_default() {
+        let descriptors = testutils!(@descriptors ("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)"));
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            &descriptors.0,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        wallet.database.borrow_mut().received_tx(
+            testutils! {
+                @tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)
+            },
+            Some(100),
+        );
+
+        assert_eq!(
+            wallet.get_new_address().unwrap(),
+            testutils!(@external descriptors, 0)
+        );
+    }
+
+    #[test]
+    fn test_script_type() {
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            "sh(wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*))",
+            Some("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)"),
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            wallet.script_type(KeychainKind::External),
+            ScriptType::P2shP2wpkh
+        );
+        assert_eq!(
+            wallet.script_type(KeychainKind::Internal),
+            ScriptType::P2wpkh
+        );
+
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        assert_eq!(
+            wallet.script_type(KeychainKind::Internal),
+            ScriptType::P2wsh
+        );
+    }
+
+    #[test]
+    fn test_utxos_at_height() {
+        let (wallet, descriptors, funding_txid) = get_funded_wallet(get_test_wpkh());
+        let old_txid = wallet.database.borrow_mut().received_tx(
+            testutils! {
+                @tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 10)
+            },
+            Some(100),
+        );
+        let old_outpoint = OutPoint::new(old_txid, 0);
+
+        // spend the older output at height 95
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, mut details) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .add_utxo(old_outpoint)
+                    .manually_selected_only(),
+            )
+            .unwrap();
+        details.transaction = Some(psbt.extract_tx());
+        details.height = Some(95);
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
+
+        let outpoints_at = |height| {
+            wallet
+                .utxos_at_height(height)
+                .unwrap()
+                .into_iter()
+                .map(|utxo| utxo.outpoint)
+                .collect::<Vec<_>>()
+        };
+        assert!(outpoints_at(89).is_empty());
+        assert_eq!(outpoints_at(90), vec![old_outpoint]);
+        assert!(outpoints_at(95).is_empty());
+        // the funding tx of `get_funded_wallet` has one confirmation at height 100
+        assert!(outpoints_at(98).is_empty());
+        assert_eq!(outpoints_at(99), vec![OutPoint::new(funding_txid, 0)]);
+    }
+
+    #[test]
+    fn test_get_psbt_input() {
+        let (wallet, _, _) =
+            get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        let utxo = wallet.list_unspent().unwrap().remove(0);
+
+        let psbt_input = wallet
+            .get_psbt_input(utxo.clone(), Some(bitcoin::SigHashType::Single), false)
+            .unwrap();
+        assert_eq!(psbt_input.witness_utxo, Some(utxo.txout.clone()));
+        assert!(psbt_input.non_witness_utxo.is_none());
+        assert_eq!(psbt_input.hd_keypaths.len(), 1);
+        assert_eq!(psbt_input.sighash_type, Some(bitcoin::SigHashType::Single));
+
+        let psbt_input = wallet.get_psbt_input(utxo.clone(), None, true).unwrap();
+        assert_eq!(
+            psbt_input.non_witness_utxo.map(|tx| tx.txid()),
+            Some(utxo.outpoint.txid)
+        );
+        assert!(psbt_input.sighash_type.is_none());
+    }
+
+    #[test]
+    fn test_get_psbt_input_unknown_utxo() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut utxo = wallet.list_unspent().unwrap().remove(0);
+        utxo.txout.script_pubkey = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX")
+            .unwrap()
+            .script_pubkey();
+
+        assert!(matches!(
+            wallet.get_psbt_input(utxo, None, false),
+            Err(Error::UnknownUTXO)
+        ));
+    }
+
+    #[test]
+    fn test_get_psbt_input_vout_out_of_range() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut utxo = wallet.list_unspent().unwrap().remove(0);
+        // the funding transaction only has one output
+        utxo.outpoint.vout = 10;
+
+        assert!(matches!(
+            wallet.get_psbt_input(utxo, None, false),
+            Err(Error::UnknownUTXO)
+        ));
+    }
+
+    #[test]
+    fn test_derive_change_descriptor() {
+        let descriptors = testutils!(@descriptors ("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/84'/1'/0'/0/*)"));
+        let mut wallet: OfflineWallet<_> = Wallet::new_offline(
+            &descriptors.0,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        wallet
+            .cache_addresses(KeychainKind::External, 0, CACHE_ADDR_BATCH_SIZE)
+            .unwrap();
+        wallet.database.borrow_mut().received_tx(
+            testutils! {
+                @tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)
+            },
+            Some(100),
+        );
+
+        wallet.set_derive_change_descriptor(true).unwrap();
+        let change_descriptor = wallet
+            .public_descriptor(KeychainKind::Internal)
+            .unwrap()
+            .unwrap();
+        assert!(change_descriptor.to_string().ends_with("/1/*)"));
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        let change_output = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|out| out.script_pubkey != addr.script_pubkey())
+            .unwrap();
+        assert_eq!(
+            wallet
+                .database
+                .borrow()
+                .get_path_from_script_pubkey(&change_output.script_pubkey)
+                .unwrap(),
+            Some((KeychainKind::Internal, 0))
+        );
+
+        let (_, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert!(finalized);
+
+        wallet.set_derive_change_descriptor(false).unwrap();
+        assert!(wallet
+            .public_descriptor(KeychainKind::Internal)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_derive_change_descriptor_errors() {
+        // non-standard derivation
+        let (mut wallet, _, _) = get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        assert!(matches!(
+            wallet.set_derive_change_descriptor(true),
+            Err(Error::Generic(_))
+        ));
+
+        // explicit change descriptor
+        let mut wallet: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/0/*)",
+            Some("wpkh(tpubD6NzVbkrYhZ4XHndKkuB8FifXm8r5FQHwrN6oZuWCz13qb93rtgKvD4PQsqC4HP4yhV3tA2fqr2RbY5mNXfM7RxXUoeABoDtsFUq2zJq6YK/1/*)"),
+            Network::Testnet,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        assert!(matches!(
+            wallet.set_derive_change_descriptor(true),
+            Err(Error::Generic(_))
+        ));
+        assert!(matches!(
+            wallet.set_derive_change_descriptor(false),
+            Err(Error::Generic(_))
+        ));
+    }
+
+    #[test]
+    fn test_key_origins() {
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh([0f056943/84'/1'/0']tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)",
+            Some("wpkh([0f056943/84'/1'/0']tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)"),
+            Network::Testnet,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+
+        let origins = wallet.key_origins(KeychainKind::Internal);
+        assert_eq!(origins.len(), 1);
+        assert_eq!(
+            origins[0].1,
+            (
+                bitcoin::util::bip32::Fingerprint::from_str("0f056943").unwrap(),
+                bitcoin::util::bip32::DerivationPath::from_str("m/84'/1'/0'/1").unwrap()
+            )
+        );
+
+        let (public_key, key_source) = wallet
+            .get_descriptor_for_keychain(KeychainKind::Internal)
+            .0
+            .get_hd_keypaths(3, &wallet.secp)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        assert_eq!(
+            wallet.find_key_for_keypath(&public_key, &key_source),
+            Some((KeychainKind::Internal, origins[0].0.clone()))
+        );
+
+        // the path doesn't lead to the public key
+        let mut wrong_source = key_source;
+        wrong_source.1 = bitcoin::util::bip32::DerivationPath::from_str("m/84'/1'/0'/1/4").unwrap();
+        assert_eq!(
+            wallet.find_key_for_keypath(&public_key, &wrong_source),
+            None
+        );
+    }
+
+    pub(crate) fn get_test_wpkh() -> &'static str {
+        "wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)"
+    }
+
+    pub(crate) fn get_test_single_sig_csv() -> &'static str {
+        // and(pk(Alice),older(6))
+        "wsh(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(6)))"
+    }
+
+    pub(crate) fn get_test_a_or_b_plus_csv() -> &'static str {
+        // or(pk(Alice),and(pk(Bob),older(144)))
+        "wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),and_v(v:pk(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(144))))"
+    }
+
+    pub(crate) fn get_test_single_sig_cltv() -> &'static str {
+        // and(pk(Alice),after(100000))
+        "wsh(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),after(100000)))"
+    }
+
+    pub(crate) fn get_funded_wallet(
+        descriptor: &str,
+    ) -> (
+        OfflineWallet<MemoryDatabase>,
+        (String, Option<String>),
+        bitcoin::Txid,
+    ) {
+        let descriptors = testutils!(@descriptors (descriptor));
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            &descriptors.0,
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        // cache the scripts like `sync` would, so that the wallet recognizes its own outputs
+        wallet
+            .cache_addresses(KeychainKind::External, 0, CACHE_ADDR_BATCH_SIZE)
+            .unwrap();
+
+        let txid = wallet.database.borrow_mut().received_tx(
+            testutils! {
+                @tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)
+            },
+            Some(100),
+        );
+
+        (wallet, descriptors, txid)
+    }
+
+    macro_rules! assert_fee_rate {
+        ($tx:expr, $fees:expr, $fee_rate:expr $( ,@dust_change $( $dust_change:expr )* )* $( ,@add_signature $( $add_signature:expr )* )* ) => ({
+            let mut tx = $tx.clone();
+            $(
+                $( $add_signature )*
+                for txin in &mut tx.input {
+                    txin.witness.push([0x00; 108].to_vec()); // fake signature
+                }
+            )*
+
+            #[allow(unused_mut)]
+            #[allow(unused_assignments)]
+            let mut dust_change = false;
+            $(
+                $( $dust_change )*
+                dust_change = true;
+            )*
+
+            let tx_fee_rate = $fees as f32 / (tx.get_weight() as f32 / 4.0);
+            let fee_rate = $fee_rate.as_sat_vb();
+
+            if !dust_change {
+                assert!((tx_fee_rate - fee_rate).abs() < 0.5, format!("Expected fee rate of {}, the tx has {}", fee_rate, tx_fee_rate));
+            } else {
+                assert!(tx_fee_rate >= fee_rate, format!("Expected fee rate of at least {}, the tx has {}", fee_rate, tx_fee_rate));
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "NoRecipients")]
+    fn test_create_tx_empty_recipients() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        wallet
+            .create_tx(TxBuilder::with_recipients(vec![]))
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "NoUtxosSelected")]
+    fn test_create_tx_manually_selected_empty_utxos() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .manually_selected_only()
+                    .utxos(vec![]),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_default_version() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.version, 1);
+    }
+
+    #[test]
+    fn test_create_tx_default_version_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.version, 2);
+    }
+
+    #[test]
+    fn test_create_tx_version_2() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).version(2))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.version, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid version `0`")]
+    fn test_create_tx_version_0() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).version(0))
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "TxBuilder requested version `1`, but at least `2` is needed to use OP_CSV"
+    )]
+    fn test_create_tx_version_1_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).version(1))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_custom_version() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).version(42))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.version, 42);
+    }
+
+    #[test]
+    fn test_create_tx_add_data() {
+        use super::tx_builder::TxOrdering;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .add_data(b"hello")
+                    .ordering(TxOrdering::Untouched),
+            )
+            .unwrap();
+
+        let data_output = &psbt.global.unsigned_tx.output[1];
+        assert!(data_output.script_pubkey.is_op_return());
+        assert_eq!(&data_output.script_pubkey.as_bytes()[2..], b"hello");
+        assert_eq!(data_output.value, 0);
+        assert_eq!(details.recipients.len(), 2);
+    }
+
+    const TEST_SILENT_PAYMENT_ADDRESS: &str = "tsp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc3wk4yh";
+
+    #[test]
+    fn test_create_tx_silent_payment() {
+        use super::silent_payments::{self, SilentPaymentAddress};
+        use super::tx_builder::TxOrdering;
+        use std::str::FromStr;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let address = SilentPaymentAddress::from_str(TEST_SILENT_PAYMENT_ADDRESS).unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .add_silent_payment(address, 25_000)
+                    .ordering(TxOrdering::Untouched),
+            )
+            .unwrap();
+
+        let private_key =
+            PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW").unwrap();
+        let outpoints = psbt
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let expected = silent_payments::output_scripts(
+            &wallet.secp,
+            &[private_key.key],
+            &outpoints,
+            &[address],
+        )
+        .unwrap();
+
+        let output = &psbt.global.unsigned_tx.output[0];
+        assert_eq!(output.script_pubkey, expected[0]);
+        assert_ne!(output.script_pubkey, silent_payments::placeholder_script());
+        assert_eq!(output.value, 25_000);
+        assert_eq!(details.recipients[0].script_pubkey, expected[0]);
+        assert_eq!(
+            wallet
+                .database
+                .borrow()
+                .get_silent_payment_recipient(&expected[0])
+                .unwrap(),
+            Some(address)
+        );
+    }
+
+    #[test]
+    fn test_bump_fee_silent_payment() {
+        use super::silent_payments::{self, SilentPaymentAddress};
+        use super::tx_builder::TxOrdering;
+        use std::str::FromStr;
+
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let address = SilentPaymentAddress::from_str(TEST_SILENT_PAYMENT_ADDRESS).unwrap();
+        let (psbt, mut original_details) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .add_silent_payment(address, 45_000)
+                    .ordering(TxOrdering::Untouched)
+                    .enable_rbf(),
+            )
+            .unwrap();
+        let original_script = psbt.global.unsigned_tx.output[0].script_pubkey.clone();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let (psbt, _) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(50.0)),
+            )
+            .unwrap();
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+
+        // the new input changes the shared secret, so the script must be computed again
+        let private_key =
+            PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW").unwrap();
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let expected = silent_payments::output_scripts(
+            &wallet.secp,
+            &[private_key.key, private_key.key],
+            &outpoints,
+            &[address],
+        )
+        .unwrap();
+
+        let output = tx
+            .output
+            .iter()
+            .find(|txout| txout.value == 45_000)
+            .unwrap();
+        assert_eq!(output.script_pubkey, expected[0]);
+        assert_ne!(output.script_pubkey, original_script);
+        assert_eq!(
+            wallet
+                .database
+                .borrow()
+                .get_silent_payment_recipient(&expected[0])
+                .unwrap(),
+            Some(address)
+        );
+    }
+
+    #[test]
+    fn test_scan_silent_payment_block() {
+        use super::silent_payments::{self, SilentPaymentReceiver};
+        use bitcoin::blockdata::script::Builder;
+        use bitcoin::hashes::{hash160, Hash};
+        use bitcoin::secp256k1::{PublicKey as SecpPublicKey, SecretKey};
+        use std::str::FromStr;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let balance = wallet.get_balance().unwrap();
+
+        let spend_secret = SecretKey::from_slice(&[0x02; 32]).unwrap();
+        let receiver = SilentPaymentReceiver::new(
+            SecretKey::from_slice(&[0x01; 32]).unwrap(),
+            SecpPublicKey::from_secret_key(&wallet.secp, &spend_secret),
+        );
+        let address = receiver.address(&wallet.secp, Network::Testnet);
+
+        // a p2wpkh input, with a dummy signature in the witness
+        let input_key = SecretKey::from_slice(&[0x03; 32]).unwrap();
+        let public_key = SecpPublicKey::from_secret_key(&wallet.secp, &input_key)
+            .serialize()
+            .to_vec();
+        let outpoint = OutPoint::from_str(
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+        )
+        .unwrap();
+        let prevout = TxOut {
+            value: 30_000,
+            script_pubkey: Builder::new()
+                .push_int(0)
+                .push_slice(&hash160::Hash::hash(&public_key)[..])
+                .into_script(),
+        };
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![vec![0x30; 71], public_key],
+            }],
+            output: vec![TxOut {
+                value: 25_000,
+                script_pubkey: silent_payments::output_scripts(
+                    &wallet.secp,
+                    &[input_key],
+                    &[outpoint],
+                    &[address],
+                )
+                .unwrap()
+                .remove(0),
+            }],
+        };
+
+        wallet
+            .process_silent_payment_block(&receiver, 100, &[(tx.clone(), vec![prevout])])
+            .unwrap();
+        let outputs = wallet.list_silent_payment_outputs().unwrap();
+        assert_eq!(outputs.len(), 1);
+        assert_eq!(outputs[0].outpoint, OutPoint::new(tx.txid(), 0));
+        assert_eq!(outputs[0].height, Some(100));
+        assert_eq!(wallet.get_balance().unwrap(), balance + 25_000);
+        assert_eq!(
+            wallet
+                .database
+                .borrow()
+                .get_silent_payment_scan_height()
+                .unwrap(),
+            Some(101)
+        );
+
+        // the output is removed once it's spent
+        let spending_tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: outputs[0].outpoint,
+                script_sig: Script::new(),
+                sequence: 0xFFFFFFFF,
+                witness: vec![vec![0x00; 64]],
+            }],
+            output: vec![TxOut {
+                value: 24_000,
+                script_pubkey: Script::new(),
+            }],
+        };
+        wallet
+            .process_silent_payment_block(&receiver, 101, &[(spending_tx, vec![])])
+            .unwrap();
+        assert!(wallet.list_silent_payment_outputs().unwrap().is_empty());
+        assert_eq!(wallet.get_balance().unwrap(), balance);
+    }
+
+    #[test]
+    fn test_create_tx_silent_payment_watch_only() {
+        use super::silent_payments::{SilentPaymentAddress, SilentPaymentError};
+        use std::str::FromStr;
+
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)");
+        let address = SilentPaymentAddress::from_str(TEST_SILENT_PAYMENT_ADDRESS).unwrap();
+        let builder = TxBuilder::new().add_silent_payment(address, 25_000);
+
+        // previewing the transaction doesn't need the private keys
+        assert!(wallet.preview_tx(&builder).is_ok());
+        assert!(matches!(
+            wallet.create_tx(builder),
+            Err(Error::SilentPayment(SilentPaymentError::MissingInputKey(_)))
+        ));
+    }
+
+    #[test]
+    fn test_create_tx_silent_payment_wrong_network() {
+        use super::silent_payments::{SilentPaymentAddress, SilentPaymentError};
+        use std::str::FromStr;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut address = SilentPaymentAddress::from_str(TEST_SILENT_PAYMENT_ADDRESS).unwrap();
+        address.network = Network::Bitcoin;
+
+        assert!(matches!(
+            wallet.create_tx(TxBuilder::new().add_silent_payment(address, 25_000)),
+            Err(Error::SilentPayment(SilentPaymentError::InvalidNetwork))
+        ));
+    }
+
+    #[test]
+    fn test_create_tx_default_locktime() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 0);
+    }
+
+    #[test]
+    fn test_create_tx_default_locktime_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 100_000);
+    }
+
+    #[test]
+    fn test_create_tx_custom_locktime() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).nlocktime(630_000),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
+    }
+
+    #[test]
+    fn test_create_tx_custom_locktime_compatible_with_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).nlocktime(630_000),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "TxBuilder requested timelock of `50000`, but at least `100000` is required to spend from this script"
+    )]
+    fn test_create_tx_custom_locktime_incompatible_with_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).nlocktime(50000),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_no_rbf_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
+    }
+
+    #[test]
+    fn test_create_tx_with_default_rbf_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+            )
+            .unwrap();
+
+        // When CSV is enabled it takes precedence over the rbf value (unless forced by the user).
+        // It will be set to the OP_CSV value, in this case 6
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Cannot enable RBF with nSequence `3` given a required OP_CSV of `6`"
+    )]
+    fn test_create_tx_with_custom_rbf_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .enable_rbf_with_sequence(3),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_no_rbf_cltv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFE);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot enable RBF with a nSequence >= 0xFFFFFFFE")]
+    fn test_create_tx_invalid_rbf_sequence() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .enable_rbf_with_sequence(0xFFFFFFFE),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_custom_rbf_sequence() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .enable_rbf_with_sequence(0xDEADBEEF),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xDEADBEEF);
+    }
+
+    #[test]
+    fn test_create_tx_default_sequence() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn test_tip_oracle_finalize_older() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
+
+        // the relative timelock is committed to by the nSequence of the input, so the wallet can
+        // finalize it even while offline
+        let (_, finalized) = wallet.sign(psbt.clone(), SignOptions::default()).unwrap();
+        assert!(finalized);
+
+        // and a tip that hasn't reached the timelock yet doesn't prevent it either: the
+        // transaction just can't be mined before height 105
+        block_on_maybe_async!(wallet.set_tip_oracle(StaticTip {
+            height: 104,
+            median_time_past: None,
+        }))
+        .unwrap();
+        let (_, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert!(finalized);
+    }
+
+    #[test]
+    fn test_tip_oracle_default_locktime() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        block_on_maybe_async!(wallet.set_tip_oracle(StaticTip {
+            height: 630_000,
+            median_time_past: None,
+        }))
+        .unwrap();
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFE);
+    }
+
+    #[test]
+    fn test_tip_oracle_default_locktime_cltv() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
+        block_on_maybe_async!(wallet.set_tip_oracle(StaticTip {
+            height: 50_000,
+            median_time_past: None,
+        }))
+        .unwrap();
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        // the timelock of the descriptor is higher than the tip
+        assert_eq!(psbt.global.unsigned_tx.lock_time, 100_000);
+    }
+
+    #[test]
+    fn test_tip_oracle_is_final_tx() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let mut tx = Transaction {
+            version: 2,
+            lock_time: 100_000,
+            input: vec![TxIn {
+                sequence: 0xFFFFFFFE,
+                ..Default::default()
+            }],
+            output: vec![],
+        };
+        assert_eq!(wallet.is_final_tx(&tx), None);
+
+        block_on_maybe_async!(wallet.set_tip_oracle(StaticTip {
+            height: 99_999,
+            median_time_past: Some(1_600_000_000),
+        }))
+        .unwrap();
+        assert_eq!(wallet.is_final_tx(&tx), Some(false));
+
+        block_on_maybe_async!(wallet.set_tip_oracle(StaticTip {
+            height: 100_000,
+            median_time_past: Some(1_600_000_000),
+        }))
+        .unwrap();
+        assert_eq!(wallet.is_final_tx(&tx), Some(true));
+
+        // time-based locktimes are checked against the median time past
+        tx.lock_time = 1_600_000_000;
+        assert_eq!(wallet.is_final_tx(&tx), Some(false));
+        tx.lock_time = 1_599_999_999;
+        assert_eq!(wallet.is_final_tx(&tx), Some(true));
+
+        // the locktime is ignored if every input is final
+        tx.lock_time = 1_700_000_000;
+        tx.input[0].sequence = 0xFFFFFFFF;
+        assert_eq!(wallet.is_final_tx(&tx), Some(true));
+    }
+
+    #[test]
+    fn test_tip_oracle_chain_state() {
+        let (mut wallet, _, txid) = get_funded_wallet(get_test_single_sig_csv());
+        let outpoint = OutPoint { txid, vout: 0 };
+        assert_eq!(wallet.get_chain_state(&[outpoint]).unwrap(), None);
+
+        block_on_maybe_async!(wallet.set_tip_oracle(StaticTip {
+            height: 105,
+            median_time_past: Some(1_600_000_000),
+        }))
+        .unwrap();
+        let chain = wallet.get_chain_state(&[outpoint]).unwrap().unwrap();
+        assert_eq!(chain.height, 105);
+        assert_eq!(chain.median_time_past, 1_600_000_000);
+        assert_eq!(chain.coins_height, Some(99));
+
+        let policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        assert!(policy.is_satisfiable(&chain));
+        let chain = descriptor::policy::ChainState {
+            height: 103,
+            ..chain
+        };
+        assert!(!policy.is_satisfiable(&chain));
+    }
+
+    #[test]
+    fn test_create_tx_input_nsequence() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .input_nsequence(OutPoint::new(txid, 0), 0xDEADBEEF),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xDEADBEEF);
+    }
+
+    #[test]
+    fn test_create_tx_input_nsequence_compatible_with_csv() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .input_nsequence(OutPoint::new(txid, 0), 10),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "but a required OP_CSV of `6` is not satisfied")]
+    fn test_create_tx_input_nsequence_incompatible_with_csv() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_single_sig_csv());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .input_nsequence(OutPoint::new(txid, 0), 3),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Cannot use a final nSequence on every input given a nLockTime of `630000`"
+    )]
+    fn test_create_tx_input_nsequence_final_with_locktime() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .nlocktime(630_000)
+                    .input_nsequence(OutPoint::new(txid, 0), 0xFFFFFFFF),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The `change_policy` can be set only if the wallet has a change_descriptor"
+    )]
+    fn test_create_tx_change_policy_no_internal() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .do_not_spend_change(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_single_recipient_drain_wallet() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            50_000 - details.fees
+        );
+    }
+
+    #[test]
+    fn test_create_tx_sweep() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        wallet.database.borrow_mut().received_tx(
+            testutils! {
+                @tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)
+            },
+            Some(100),
+        );
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::sweep(addr.script_pubkey()).fee_rate(FeeRate::from_sat_per_vb(5.0)),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 2);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            75_000 - details.fees
+        );
+        assert_eq!(details.sent, 75_000);
+        assert_eq!(details.received, 0);
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(5.0), @add_signature);
+    }
+
+    #[test]
+    fn test_create_tx_default_fee_rate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::default(), @add_signature);
+    }
+
+    #[test]
+    fn test_create_tx_custom_fee_rate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
+            )
+            .unwrap();
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(5.0), @add_signature);
+    }
+
+    #[test]
+    fn test_estimate_psbt_fee_rate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.fee_amount().unwrap(), details.fees);
+        assert!(matches!(psbt.fee_rate(), Err(Error::InputNotFinalized(0))));
+
+        let estimated = wallet.estimate_psbt_fee_rate(&psbt).unwrap();
+        assert!((estimated.as_sat_vb() - 5.0).abs() < 0.5);
+
+        let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert!(finalized);
+        // the signatures are at most as big as the estimated ones
+        let fee_rate = signed_psbt.fee_rate().unwrap();
+        assert!(fee_rate >= estimated);
+        assert_eq!(
+            wallet.estimate_psbt_fee_rate(&signed_psbt).unwrap(),
+            fee_rate
+        );
+    }
+
+    #[test]
+    fn test_create_tx_absolute_fee() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .fee_absolute(100),
+            )
+            .unwrap();
+
+        assert_eq!(details.fees, 100);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            50_000 - details.fees
+        );
+    }
+
+    #[test]
+    fn test_create_tx_absolute_zero_fee() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .fee_absolute(0),
+            )
+            .unwrap();
+
+        assert_eq!(details.fees, 0);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            50_000 - details.fees
+        );
+    }
+
+    #[test]
+    fn test_create_tx_absolute_fee_with_change() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .fee_absolute(1_000),
+            )
+            .unwrap();
+
+        assert_eq!(details.fees, 1_000);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+        assert_eq!(details.sent, 50_000);
+        assert_eq!(details.received, 50_000 - 25_000 - 1_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "FeeTooHigh { fee: 1000, max: 500 }")]
+    fn test_create_tx_max_fee_absolute() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .fee_absolute(1_000)
+                    .max_fee_absolute(500),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "FeeRateTooHigh")]
+    fn test_create_tx_max_fee_rate() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        wallet.set_max_fee_rate(Some(FeeRate::from_sat_per_vb(10.0)));
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .fee_rate(FeeRate::from_sat_per_vb(20.0)),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_below_max_fee_rate() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        wallet.set_max_fee_rate(Some(FeeRate::from_sat_per_vb(10.0)));
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
+            )
+            .unwrap();
+
+        let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert!(finalized);
+        assert_fee_rate!(
+            psbt.extract_tx(),
+            details.fees,
+            FeeRate::from_sat_per_vb(5.0)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "FeeTooHigh { fee: 5000, max: 1000 }")]
+    fn test_sign_max_fee_absolute() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        wallet.set_max_fee_absolute(Some(1_000));
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        // the builder can override the limit of the wallet, but signing still enforces it
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .fee_absolute(5_000)
+                    .max_fee_absolute(10_000),
+            )
+            .unwrap();
+
+        wallet.sign(psbt, SignOptions::default()).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_create_tx_absolute_high_fee() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (_psbt, _details) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .fee_absolute(60_000),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_add_change() {
+        use super::tx_builder::TxOrdering;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .ordering(TxOrdering::Untouched),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 25_000);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[1].value,
+            25_000 - details.fees
+        );
+    }
+
+    #[test]
+    fn test_create_tx_split_change() {
+        use super::tx_builder::TxOrdering;
+
+        let (wallet, _, _) =
+            get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .ordering(TxOrdering::Untouched)
+                    .split_change(3, 1_000),
+            )
+            .unwrap();
+
+        let outputs = &psbt.global.unsigned_tx.output;
+        assert_eq!(outputs.len(), 4);
+        assert_eq!(outputs[0].value, 25_000);
+        assert!(outputs[1..].iter().all(|out| out.value >= 1_000));
+        assert_eq!(
+            outputs[1..].iter().map(|out| out.value).sum::<u64>(),
+            25_000 - details.fees
+        );
+        assert_eq!(details.received, 25_000 - details.fees);
+
+        // every change output uses a different script
+        let scripts = outputs[1..]
+            .iter()
+            .map(|out| &out.script_pubkey)
+            .collect::<HashSet<_>>();
+        assert_eq!(scripts.len(), 3);
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::default(), @add_signature);
+    }
+
+    #[test]
+    fn test_create_tx_split_change_not_enough_value() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .split_change(3, 20_000),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "dangerous-ops")]
+    fn test_export_private_key() {
+        let secp = Secp256k1::new();
+        let wallet = OfflineWallet::new_offline(
+            "wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/84'/1'/0'/0/*)",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+
+        for index in 0..3 {
+            let address = wallet.get_new_address().unwrap();
+            let key = wallet
+                .export_private_key(KeychainKind::External, index)
+                .unwrap();
+            assert_eq!(
+                Address::p2wpkh(&key.public_key(&secp), Network::Regtest).unwrap(),
+                address
+            );
+        }
+
+        // without a change descriptor the external one is used
+        let key = wallet
+            .export_private_key(KeychainKind::Internal, 0)
+            .unwrap();
+        assert_eq!(
+            key,
+            wallet
+                .export_private_key(KeychainKind::External, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "dangerous-ops")]
+    fn test_export_private_key_single_key() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let key = wallet
+            .export_private_key(KeychainKind::External, 0)
+            .unwrap();
+        assert_eq!(
+            key.to_wif(),
+            "cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "dangerous-ops")]
+    #[should_panic(expected = "The wallet doesn't have the private key for the external keychain")]
+    fn test_export_private_key_watch_only() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        wallet
+            .export_private_key(KeychainKind::External, 0)
+            .unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "dangerous-ops")]
+    #[should_panic(expected = "Only the private key of single-key descriptors can be exported")]
+    fn test_export_private_key_multi_key() {
+        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+        wallet
+            .export_private_key(KeychainKind::External, 0)
+            .unwrap();
+    }
+
+    fn get_funded_wallet_with_change(policy: ChangeIndexPolicy) -> OfflineWallet<MemoryDatabase> {
+        let descriptors = testutils!(@descriptors ("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/0/*)"));
+        let mut wallet: OfflineWallet<_> = Wallet::new_offline(
+            descriptors.0.as_str(),
+            Some("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/1/*)"),
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        wallet.database.borrow_mut().received_tx(
+            testutils! {
+                @tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)
+            },
+            Some(100),
+        );
+        wallet.set_change_index_policy(policy);
+
+        wallet
+    }
+
+    fn get_change_script(wallet: &OfflineWallet<MemoryDatabase>) -> Script {
+        use super::tx_builder::TxOrdering;
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .ordering(TxOrdering::Untouched),
+            )
+            .unwrap();
+
+        psbt.global.unsigned_tx.output[1].script_pubkey.clone()
+    }
+
+    #[test]
+    fn test_change_index_policy_fresh_by_default() {
+        let wallet = get_funded_wallet_with_change(ChangeIndexPolicy::default());
+
+        assert_ne!(get_change_script(&wallet), get_change_script(&wallet));
+    }
+
+    #[test]
+    fn test_change_index_policy_reuse_unused() {
+        let wallet = get_funded_wallet_with_change(ChangeIndexPolicy::ReuseUnused);
+
+        let change_script = get_change_script(&wallet);
+        assert_eq!(get_change_script(&wallet), change_script);
+
+        // once the change address has received some funds a new one is derived
+        wallet.database.borrow_mut().received_tx(
+            testutils::TestIncomingTx::new(
+                vec![testutils::TestIncomingOutput::new(
+                    10_000,
+                    Address::from_script(&change_script, Network::Regtest).unwrap(),
+                )],
+                Some(1),
                 None,
-                self.database.borrow_mut().deref_mut(),
-                progress_update,
-            ))
+                None,
+            ),
+            Some(100),
+        );
+        assert_ne!(get_change_script(&wallet), change_script);
+    }
+
+    #[test]
+    fn test_change_index_policy_reuse_unused_split_change() {
+        let wallet = get_funded_wallet_with_change(ChangeIndexPolicy::ReuseUnused);
+        let change_script = get_change_script(&wallet);
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .split_change(2, 1_000),
+            )
+            .unwrap();
+
+        // only one of the change outputs reuses the previous address
+        let scripts = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .map(|out| &out.script_pubkey)
+            .collect::<HashSet<_>>();
+        assert_eq!(scripts.len(), 3);
+        assert!(scripts.contains(&change_script));
+    }
+
+    #[test]
+    fn test_create_tx_decoy_outputs() {
+        use super::tx_builder::TxOrdering;
+
+        let (wallet, _, _) =
+            get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 10_000)])
+                    .ordering(TxOrdering::Untouched)
+                    .add_decoy_outputs(2),
+            )
+            .unwrap();
+
+        let outputs = &psbt.global.unsigned_tx.output;
+        // recipient, two decoys and the change
+        assert_eq!(outputs.len(), 4);
+        assert_eq!(outputs[0].value, 10_000);
+        assert!(outputs[1..3]
+            .iter()
+            .all(|out| out.value >= 5_000 && out.value <= 15_000));
+        for out in &outputs[1..] {
+            assert!(wallet.is_mine(&out.script_pubkey).unwrap());
         }
+        assert_eq!(details.received, 50_000 - 10_000 - details.fees);
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::default(), @add_signature);
     }
 
-    /// Return a reference to the internal blockchain client
-    pub fn client(&self) -> Option<&B> {
-        self.client.as_ref()
+    #[test]
+    fn test_create_tx_rng_seed() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let output_values = |seed| {
+            let (psbt, _) = wallet
+                .create_tx(
+                    TxBuilder::with_recipients(vec![(addr.script_pubkey(), 10_000)])
+                        .add_decoy_outputs(2)
+                        .split_change(2, 1_000)
+                        .rng_seed(seed),
+                )
+                .unwrap();
+            psbt.global
+                .unsigned_tx
+                .output
+                .iter()
+                .map(|txout| txout.value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(output_values(42), output_values(42));
+        assert_ne!(output_values(42), output_values(43));
     }
 
-    /// Get the Bitcoin network the wallet is using.
-    pub fn network(&self) -> Network {
-        self.network
+    #[test]
+    #[should_panic(
+        expected = "Generic(\"Decoy outputs can't be added to a transaction with a single recipient\")"
+    )]
+    fn test_create_tx_decoy_outputs_single_recipient() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .add_decoy_outputs(1),
+            )
+            .unwrap();
     }
 
-    /// Broadcast a transaction to the network
-    #[maybe_async]
-    pub fn broadcast(&self, tx: Transaction) -> Result<Txid, Error> {
-        maybe_await!(self
-            .client
-            .as_ref()
-            .ok_or(Error::OfflineClient)?
-            .broadcast(&tx))?;
+    #[test]
+    fn test_preview_tx() {
+        let (wallet, _, _) =
+            get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let builder = TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+            .fee_rate(FeeRate::from_sat_per_vb(5.0));
 
-        Ok(tx.txid())
+        let preview = wallet.preview_tx(&builder).unwrap();
+        assert_eq!(preview.input_value, 50_000);
+        assert_eq!(preview.change, 25_000 - preview.fee);
+        // the fee is computed on the same size estimate
+        assert!((preview.fee as f32 / preview.vsize as f32 - 5.0).abs() < 0.1);
+
+        // no address has been derived
+        assert_eq!(
+            wallet
+                .database
+                .borrow()
+                .get_last_index(KeychainKind::External)
+                .unwrap(),
+            None
+        );
+
+        let (_, details) = wallet.create_tx(builder).unwrap();
+        assert_eq!(details.fees, preview.fee);
+        assert_eq!(details.sent, preview.input_value);
     }
-}
 
-#[cfg(test)]
-mod test {
-    use std::str::FromStr;
+    #[test]
+    fn test_create_tx_skip_change_dust() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                49_800,
+            )]))
+            .unwrap();
 
-    use bitcoin::Network;
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 49_800);
+        assert_eq!(details.fees, 200);
+    }
 
-    use crate::database::memory::MemoryDatabase;
-    use crate::database::Database;
-    use crate::types::KeychainKind;
+    #[test]
+    #[should_panic(expected = "OutputBelowDustLimit(0)")]
+    fn test_create_tx_recipient_below_dust_limit() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        // p2sh, which has a dust limit of 540 satoshi
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                500,
+            )]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_dust_limit_per_script() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        // p2wpkh, which has a dust limit of 294 satoshi
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                500,
+            )]))
+            .unwrap();
+
+        assert!(psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|txout| txout.value == 500));
+    }
+
+    #[test]
+    #[should_panic(expected = "OutputBelowDustLimit(0)")]
+    fn test_create_tx_wallet_dust_limit() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        wallet.set_dust_limit(DustLimit::Fixed(1_000));
+
+        let addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                500,
+            )]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_builder_dust_limit() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        wallet.set_dust_limit(DustLimit::Fixed(1_000));
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 40_000)])
+                    .dust_limit(DustLimit::Fixed(15_000)),
+            )
+            .unwrap();
+
+        // the change is below the limit set on the builder, so it's added to the fees, while the
+        // recipient is above it
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(details.fees, 10_000);
+    }
+
+    #[test]
+    fn test_create_tx_custom_change_script() {
+        use super::tx_builder::TxOrdering;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let change_addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .change_script(change_addr.script_pubkey())
+                    .ordering(TxOrdering::Untouched),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[1].script_pubkey,
+            change_addr.script_pubkey()
+        );
+        assert_eq!(
+            psbt.global.unsigned_tx.output[1].value,
+            25_000 - details.fees
+        );
+        // only the recipient belongs to the wallet
+        assert_eq!(details.received, 25_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "The change can't be split when sending it to a custom script")]
+    fn test_create_tx_custom_change_script_split() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let change_addr = wallet.get_new_address().unwrap();
+        wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .change_script(change_addr.script_pubkey())
+                    .split_change(2, 1_000),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_change_output_policy_never() {
+        use super::tx_builder::ChangeOutputPolicy;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .change_output_policy(ChangeOutputPolicy::Never),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 25_000);
+        assert_eq!(details.fees, 25_000);
+        assert_eq!(details.received, 0);
+    }
+
+    #[test]
+    fn test_create_tx_change_output_policy_above_threshold() {
+        use super::tx_builder::ChangeOutputPolicy;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .change_output_policy(ChangeOutputPolicy::AboveThreshold(20_000)),
+            )
+            .unwrap();
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
+        assert_eq!(details.received, 25_000 - details.fees);
+
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .change_output_policy(ChangeOutputPolicy::AboveThreshold(30_000)),
+            )
+            .unwrap();
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(details.fees, 25_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_create_tx_single_recipient_dust_amount() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        // very high fee rate, so that the only output would be below dust
+        wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .fee_rate(FeeRate::from_sat_per_vb(453.0)),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_create_tx_ordering_respected() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![
+                    (addr.script_pubkey(), 30_000),
+                    (addr.script_pubkey(), 10_000),
+                ])
+                .ordering(super::tx_builder::TxOrdering::BIP69Lexicographic),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 3);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            10_000 - details.fees
+        );
+        assert_eq!(psbt.global.unsigned_tx.output[1].value, 10_000);
+        assert_eq!(psbt.global.unsigned_tx.output[2].value, 30_000);
+    }
+
+    #[test]
+    fn test_create_tx_default_sighash() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                30_000,
+            )]))
+            .unwrap();
+
+        assert_eq!(psbt.inputs[0].sighash_type, None);
+    }
+
+    #[test]
+    fn test_create_tx_custom_sighash() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
+                    .sighash(bitcoin::SigHashType::Single),
+            )
+            .unwrap();
 
-    use super::*;
+        assert_eq!(
+            psbt.inputs[0].sighash_type,
+            Some(bitcoin::SigHashType::Single)
+        );
+    }
 
     #[test]
-    fn test_cache_addresses_fixed() {
-        let db = MemoryDatabase::new();
-        let wallet: OfflineWallet<_> = Wallet::new_offline(
-            "wpkh(L5EZftvrYaSudiozVRzTqLcHLNDoVn7H5HSfM9BAN6tMJX8oTWz6)",
-            None,
-            Network::Testnet,
-            db,
-        )
-        .unwrap();
+    fn test_create_tx_input_hd_keypaths() {
+        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+        use std::str::FromStr;
 
+        let (wallet, _, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.inputs[0].hd_keypaths.len(), 1);
         assert_eq!(
-            wallet.get_new_address().unwrap().to_string(),
-            "tb1qj08ys4ct2hzzc2hcz6h2hgrvlmsjynaw43s835"
-        );
-        assert_eq!(
-            wallet.get_new_address().unwrap().to_string(),
-            "tb1qj08ys4ct2hzzc2hcz6h2hgrvlmsjynaw43s835"
+            psbt.inputs[0].hd_keypaths.values().nth(0).unwrap(),
+            &(
+                Fingerprint::from_str("d34db33f").unwrap(),
+                DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
+            )
         );
-
-        assert!(wallet
-            .database
-            .borrow_mut()
-            .get_script_pubkey_from_path(KeychainKind::External, 0)
-            .unwrap()
-            .is_some());
-        assert!(wallet
-            .database
-            .borrow_mut()
-            .get_script_pubkey_from_path(KeychainKind::Internal, 0)
-            .unwrap()
-            .is_none());
     }
 
     #[test]
-    fn test_cache_addresses() {
-        let db = MemoryDatabase::new();
-        let wallet: OfflineWallet<_> = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", None, Network::Testnet, db).unwrap();
+    fn test_create_tx_output_hd_keypaths() {
+        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
+        use std::str::FromStr;
 
+        let (wallet, descriptors, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
+        // cache some addresses
+        wallet.get_new_address().unwrap();
+
+        let addr = testutils!(@external descriptors, 5);
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.outputs[0].hd_keypaths.len(), 1);
         assert_eq!(
-            wallet.get_new_address().unwrap().to_string(),
-            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
-        );
-        assert_eq!(
-            wallet.get_new_address().unwrap().to_string(),
-            "tb1q4er7kxx6sssz3q7qp7zsqsdx4erceahhax77d7"
+            psbt.outputs[0].hd_keypaths.values().nth(0).unwrap(),
+            &(
+                Fingerprint::from_str("d34db33f").unwrap(),
+                DerivationPath::from_str("m/44'/0'/0'/0/5").unwrap()
+            )
         );
-
-        assert!(wallet
-            .database
-            .borrow_mut()
-            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE - 1)
-            .unwrap()
-            .is_some());
-        assert!(wallet
-            .database
-            .borrow_mut()
-            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE)
-            .unwrap()
-            .is_none());
     }
 
     #[test]
-    fn test_cache_addresses_refill() {
-        let db = MemoryDatabase::new();
-        let wallet: OfflineWallet<_> = Wallet::new_offline("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)", None, Network::Testnet, db).unwrap();
+    fn test_create_tx_set_redeem_script_p2sh() {
+        use bitcoin::hashes::hex::FromHex;
+
+        let (wallet, _, _) =
+            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
 
         assert_eq!(
-            wallet.get_new_address().unwrap().to_string(),
-            "tb1q6yn66vajcctph75pvylgkksgpp6nq04ppwct9a"
+            psbt.inputs[0].redeem_script,
+            Some(Script::from(
+                Vec::<u8>::from_hex(
+                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+                )
+                .unwrap()
+            ))
         );
-        assert!(wallet
-            .database
-            .borrow_mut()
-            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE - 1)
-            .unwrap()
-            .is_some());
-
-        for _ in 0..CACHE_ADDR_BATCH_SIZE {
-            wallet.get_new_address().unwrap();
-        }
-
-        assert!(wallet
-            .database
-            .borrow_mut()
-            .get_script_pubkey_from_path(KeychainKind::External, CACHE_ADDR_BATCH_SIZE * 2 - 1)
-            .unwrap()
-            .is_some());
+        assert_eq!(psbt.inputs[0].witness_script, None);
     }
 
-    pub(crate) fn get_test_wpkh() -> &'static str {
-        "wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)"
-    }
+    #[test]
+    fn test_create_tx_set_witness_script_p2wsh() {
+        use bitcoin::hashes::hex::FromHex;
 
-    pub(crate) fn get_test_single_sig_csv() -> &'static str {
-        // and(pk(Alice),older(6))
-        "wsh(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),older(6)))"
-    }
+        let (wallet, _, _) =
+            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
 
-    pub(crate) fn get_test_a_or_b_plus_csv() -> &'static str {
-        // or(pk(Alice),and(pk(Bob),older(144)))
-        "wsh(or_d(pk(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu),and_v(v:pk(cMnkdebixpXMPfkcNEjjGin7s94hiehAH4mLbYkZoh9KSiNNmqC8),older(144))))"
+        assert_eq!(psbt.inputs[0].redeem_script, None);
+        assert_eq!(
+            psbt.inputs[0].witness_script,
+            Some(Script::from(
+                Vec::<u8>::from_hex(
+                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
+                )
+                .unwrap()
+            ))
+        );
     }
 
-    pub(crate) fn get_test_single_sig_cltv() -> &'static str {
-        // and(pk(Alice),after(100000))
-        "wsh(and_v(v:pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW),after(100000)))"
-    }
+    #[test]
+    fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
+        use bitcoin::hashes::hex::FromHex;
 
-    pub(crate) fn get_funded_wallet(
-        descriptor: &str,
-    ) -> (
-        OfflineWallet<MemoryDatabase>,
-        (String, Option<String>),
-        bitcoin::Txid,
-    ) {
-        let descriptors = testutils!(@descriptors (descriptor));
-        let wallet: OfflineWallet<_> = Wallet::new_offline(
-            &descriptors.0,
-            None,
-            Network::Regtest,
-            MemoryDatabase::new(),
-        )
-        .unwrap();
+        let (wallet, _, _) =
+            get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
 
-        let txid = wallet.database.borrow_mut().received_tx(
-            testutils! {
-                @tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)
-            },
-            Some(100),
+        let script = Script::from(
+            Vec::<u8>::from_hex(
+                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
+            )
+            .unwrap(),
         );
 
-        (wallet, descriptors, txid)
+        assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_v0_p2wsh()));
+        assert_eq!(psbt.inputs[0].witness_script, Some(script));
     }
 
-    macro_rules! assert_fee_rate {
-        ($tx:expr, $fees:expr, $fee_rate:expr $( ,@dust_change $( $dust_change:expr )* )* $( ,@add_signature $( $add_signature:expr )* )* ) => ({
-            let mut tx = $tx.clone();
-            $(
-                $( $add_signature )*
-                for txin in &mut tx.input {
-                    txin.witness.push([0x00; 108].to_vec()); // fake signature
-                }
-            )*
-
-            #[allow(unused_mut)]
-            #[allow(unused_assignments)]
-            let mut dust_change = false;
-            $(
-                $( $dust_change )*
-                dust_change = true;
-            )*
-
-            let tx_fee_rate = $fees as f32 / (tx.get_weight() as f32 / 4.0);
-            let fee_rate = $fee_rate.as_sat_vb();
-
-            if !dust_change {
-                assert!((tx_fee_rate - fee_rate).abs() < 0.5, format!("Expected fee rate of {}, the tx has {}", fee_rate, tx_fee_rate));
-            } else {
-                assert!(tx_fee_rate >= fee_rate, format!("Expected fee rate of at least {}, the tx has {}", fee_rate, tx_fee_rate));
-            }
-        });
+    #[test]
+    fn test_create_tx_non_witness_utxo() {
+        let (wallet, _, _) =
+            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        assert!(psbt.inputs[0].witness_utxo.is_none());
     }
 
     #[test]
-    #[should_panic(expected = "NoRecipients")]
-    fn test_create_tx_empty_recipients() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        wallet
-            .create_tx(TxBuilder::with_recipients(vec![]))
+    fn test_create_tx_only_witness_utxo() {
+        let (wallet, _, _) =
+            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
             .unwrap();
+
+        assert!(psbt.inputs[0].non_witness_utxo.is_none());
+        assert!(psbt.inputs[0].witness_utxo.is_some());
     }
 
     #[test]
-    #[should_panic(expected = "NoUtxosSelected")]
-    fn test_create_tx_manually_selected_empty_utxos() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_create_tx_shwpkh_has_witness_utxo() {
+        let (wallet, _, _) =
+            get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
         let addr = wallet.get_new_address().unwrap();
-        wallet
+        let (psbt, _) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
-                    .manually_selected_only()
-                    .utxos(vec![]),
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
             )
             .unwrap();
+
+        assert!(psbt.inputs[0].non_witness_utxo.is_none());
+        assert!(psbt.inputs[0].witness_utxo.is_some());
     }
 
     #[test]
-    #[should_panic(expected = "Invalid version `0`")]
-    fn test_create_tx_version_0() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_create_tx_both_non_witness_utxo_and_witness_utxo() {
+        let (wallet, _, _) =
+            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
         let addr = wallet.get_new_address().unwrap();
-        wallet
-            .create_tx(TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).version(0))
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .force_non_witness_utxo(),
+            )
             .unwrap();
+
+        assert!(psbt.inputs[0].non_witness_utxo.is_some());
+        assert!(psbt.inputs[0].witness_utxo.is_some());
     }
 
     #[test]
-    #[should_panic(
-        expected = "TxBuilder requested version `1`, but at least `2` is needed to use OP_CSV"
-    )]
-    fn test_create_tx_version_1_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
-        let addr = wallet.get_new_address().unwrap();
-        wallet
-            .create_tx(TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).version(1))
+    fn test_create_tx_add_utxo() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let small_output_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)]).add_utxo(
+                    OutPoint {
+                        txid: small_output_txid,
+                        vout: 0,
+                    },
+                ),
+            )
             .unwrap();
+
+        assert_eq!(
+            psbt.global.unsigned_tx.input.len(),
+            2,
+            "should add an additional input since 25_000 < 30_000"
+        );
+        assert_eq!(details.sent, 75_000, "total should be sum of both inputs");
     }
 
     #[test]
-    fn test_create_tx_custom_version() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).version(42))
+    fn test_create_tx_consolidate() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let small_outputs_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 1_000, (@external descriptors, 0) => 3_000, (@external descriptors, 0) => 2_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        // only enough weight for two inputs
+        let (psbt, details) = wallet
+            .create_tx(TxBuilder::consolidate(600).fee_rate(FeeRate::from_sat_per_vb(1.0)))
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.version, 42);
+        let tx = &psbt.global.unsigned_tx;
+        let mut inputs = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        inputs.sort();
+        assert_eq!(
+            inputs,
+            vec![
+                OutPoint {
+                    txid: small_outputs_txid,
+                    vout: 0
+                },
+                OutPoint {
+                    txid: small_outputs_txid,
+                    vout: 2
+                }
+            ]
+        );
+        assert_eq!(tx.output.len(), 1);
+        assert!(wallet.is_mine(&tx.output[0].script_pubkey).unwrap());
+        assert_eq!(details.sent, 3_000);
+        assert_eq!(details.received, 3_000 - details.fees);
+        assert!(details.recipients.is_empty());
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(1.0), @add_signature);
     }
 
     #[test]
-    fn test_create_tx_default_locktime() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(TxBuilder::with_recipients(vec![(
-                addr.script_pubkey(),
-                25_000,
-            )]))
+    fn test_create_tx_consolidate_all() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 1_000, (@external descriptors, 0) => 3_000, (@external descriptors, 0) => 2_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let (psbt, details) = wallet
+            .create_tx(TxBuilder::consolidate(100_000).fee_rate(FeeRate::from_sat_per_vb(1.0)))
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.lock_time, 0);
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 4);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(details.sent, 56_000);
     }
 
     #[test]
-    fn test_create_tx_default_locktime_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+    fn test_create_tx_wallet_coin_selection() {
+        use super::coin_selection::{ConsolidationCoinSelection, LargestFirstCoinSelection};
+
+        let (mut wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+        wallet.set_coin_selection(ConsolidationCoinSelection::new(100_000));
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
             .create_tx(TxBuilder::with_recipients(vec![(
                 addr.script_pubkey(),
-                25_000,
+                20_000,
             )]))
             .unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 2);
+        assert_eq!(details.sent, 75_000);
 
-        assert_eq!(psbt.global.unsigned_tx.lock_time, 100_000);
+        // the algorithm chosen in the builder takes precedence
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 20_000)])
+                    .coin_selection(LargestFirstCoinSelection),
+            )
+            .unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
+        assert_eq!(details.sent, 50_000);
     }
 
     #[test]
-    fn test_create_tx_custom_locktime() {
+    fn test_create_tx_manually_selected_only() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let small_output_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 20_000)])
+                    .add_utxo(OutPoint {
+                        txid: small_output_txid,
+                        vout: 0,
+                    })
+                    .manually_selected_only(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            psbt.global
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect::<Vec<_>>(),
+            vec![OutPoint {
+                txid: small_output_txid,
+                vout: 0
+            }]
+        );
+        assert_eq!(details.sent, 25_000);
+    }
+
+    #[test]
+    fn test_create_tx_untouched_ordering() {
+        use super::tx_builder::TxOrdering;
+
+        let (wallet, descriptors, funding_txid) = get_funded_wallet(get_test_wpkh());
+        let small_output_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+        let funding_outpoint = OutPoint::new(funding_txid, 0);
+        let small_outpoint = OutPoint::new(small_output_txid, 0);
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let own_addr = wallet.get_new_address().unwrap();
+
+        for utxos in &[
+            vec![funding_outpoint, small_outpoint],
+            vec![small_outpoint, funding_outpoint],
+        ] {
+            let (psbt, _) = wallet
+                .create_tx(
+                    TxBuilder::with_recipients(vec![
+                        (own_addr.script_pubkey(), 30_000),
+                        (addr.script_pubkey(), 20_000),
+                    ])
+                    .utxos(utxos.clone())
+                    .manually_selected_only()
+                    .ordering(TxOrdering::Untouched),
+                )
+                .unwrap();
+
+            let tx = &psbt.global.unsigned_tx;
+            assert_eq!(
+                &tx.input
+                    .iter()
+                    .map(|txin| txin.previous_output)
+                    .collect::<Vec<_>>(),
+                utxos
+            );
+            // the recipients come first, in the same order, followed by the change
+            assert_eq!(tx.output.len(), 3);
+            assert_eq!(tx.output[0].script_pubkey, own_addr.script_pubkey());
+            assert_eq!(tx.output[1].script_pubkey, addr.script_pubkey());
+        }
+    }
+
+    #[test]
+    fn test_create_tx_recipient_details() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let own_addr = wallet.get_new_address().unwrap();
+        let (_, details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).nlocktime(630_000),
+                TxBuilder::with_recipients(vec![
+                    (addr.script_pubkey(), 5_000),
+                    (own_addr.script_pubkey(), 2_000),
+                ])
+                .add_decoy_outputs(2),
             )
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
+        // decoys and change are not recipients
+
+        assert_eq!(
+            details.recipients,
+            vec![
+                RecipientDetails {
+                    script_pubkey: addr.script_pubkey(),
+                    amount: 5_000,
+                    fee_subtracted: false,
+                    effective_amount: 5_000,
+                },
+                RecipientDetails {
+                    script_pubkey: own_addr.script_pubkey(),
+                    amount: 2_000,
+                    fee_subtracted: false,
+                    effective_amount: 2_000,
+                },
+            ]
+        );
     }
 
     #[test]
-    fn test_create_tx_custom_locktime_compatible_with_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+    fn test_create_tx_recipient_details_single_recipient() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (_, details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).nlocktime(630_000),
+                TxBuilder::new()
+                    .drain_to(addr.script_pubkey())
+                    .drain_wallet(),
             )
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.lock_time, 630_000);
+        assert_eq!(
+            details.recipients,
+            vec![RecipientDetails {
+                script_pubkey: addr.script_pubkey(),
+                amount: 50_000,
+                fee_subtracted: true,
+                effective_amount: 50_000 - details.fees,
+            }]
+        );
     }
 
     #[test]
-    #[should_panic(
-        expected = "TxBuilder requested timelock of `50000`, but at least `100000` is required to spend from this script"
-    )]
-    fn test_create_tx_custom_locktime_incompatible_with_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
-        let addr = wallet.get_new_address().unwrap();
-        wallet
+    fn test_create_tx_drain_to_manually_selected() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let small_output_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).nlocktime(50000),
+                TxBuilder::new()
+                    .drain_to(addr.script_pubkey())
+                    .add_utxo(OutPoint {
+                        txid: small_output_txid,
+                        vout: 0,
+                    })
+                    .manually_selected_only(),
             )
             .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].script_pubkey,
+            addr.script_pubkey()
+        );
+        assert_eq!(
+            psbt.global.unsigned_tx.output[0].value,
+            25_000 - details.fees
+        );
+        assert_eq!(details.sent, 25_000);
+        assert_eq!(details.received, 0);
     }
 
     #[test]
-    fn test_create_tx_no_rbf_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(TxBuilder::with_recipients(vec![(
-                addr.script_pubkey(),
-                25_000,
-            )]))
+    fn test_create_tx_required_and_unspendable_utxos() {
+        let (wallet, descriptors, funding_txid) = get_funded_wallet(get_test_wpkh());
+        let receive = |amount| {
+            wallet.database.borrow_mut().received_tx(
+                testutils! (@tx ( (@external descriptors, 0) => amount ) (@confirmations 1)),
+                Some(100),
+            )
+        };
+        let required_a = receive(25_000);
+        let required_b = receive(20_000);
+        let optional = receive(30_000);
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 60_000)])
+                    .add_utxo(OutPoint {
+                        txid: required_a,
+                        vout: 0,
+                    })
+                    .add_utxo(OutPoint {
+                        txid: required_b,
+                        vout: 0,
+                    })
+                    .add_unspendable(OutPoint {
+                        txid: funding_txid,
+                        vout: 0,
+                    }),
+            )
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
+        let spent = psbt
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output.txid)
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            spent,
+            vec![required_a, required_b, optional].into_iter().collect()
+        );
+        assert_eq!(details.sent, 75_000);
     }
 
     #[test]
-    fn test_create_tx_with_default_rbf_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
-        let addr = wallet.get_new_address().unwrap();
+    fn test_create_tx_add_utxo_twice() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let outpoint = OutPoint { txid, vout: 0 };
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, _) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .utxos(vec![outpoint, outpoint])
+                    .manually_selected_only(),
             )
             .unwrap();
 
-        // When CSV is enabled it takes precedence over the rbf value (unless forced by the user).
-        // It will be set to the OP_CSV value, in this case 6
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 6);
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.global.unsigned_tx.input[0].previous_output, outpoint);
     }
 
     #[test]
-    #[should_panic(
-        expected = "Cannot enable RBF with nSequence `3` given a required OP_CSV of `6`"
-    )]
-    fn test_create_tx_with_custom_rbf_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_csv());
-        let addr = wallet.get_new_address().unwrap();
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_create_tx_manually_selected_insufficient() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        let small_output_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
-                    .enable_rbf_with_sequence(3),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
+                    .add_utxo(OutPoint {
+                        txid: small_output_txid,
+                        vout: 0,
+                    })
+                    .manually_selected_only(),
             )
             .unwrap();
     }
 
     #[test]
-    fn test_create_tx_no_rbf_cltv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_single_sig_cltv());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(TxBuilder::with_recipients(vec![(
-                addr.script_pubkey(),
-                25_000,
-            )]))
+    fn test_create_tx_add_foreign_utxo() {
+        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
+        let (wallet2, _, _) =
+            get_funded_wallet("wpkh(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)");
+
+        let utxo = wallet2.list_unspent().unwrap().remove(0);
+        let foreign_input = wallet2.get_psbt_input(utxo.clone(), None, false).unwrap();
+        let satisfaction_weight = wallet2
+            .descriptor
+            .max_satisfaction_weight(descriptor_to_pk_ctx(&wallet2.secp))
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFE);
-    }
-
-    #[test]
-    #[should_panic(expected = "Cannot enable RBF with a nSequence >= 0xFFFFFFFE")]
-    fn test_create_tx_invalid_rbf_sequence() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        wallet
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet1
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
-                    .enable_rbf_with_sequence(0xFFFFFFFE),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 60_000)]).add_foreign_utxo(
+                    utxo.outpoint,
+                    foreign_input.clone(),
+                    satisfaction_weight,
+                ),
             )
             .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 2);
+        assert_eq!(details.sent, 50_000);
+        assert_eq!(details.received, 100_000 - 60_000 - details.fees);
+
+        let foreign_index = psbt
+            .global
+            .unsigned_tx
+            .input
+            .iter()
+            .position(|txin| txin.previous_output == utxo.outpoint)
+            .unwrap();
+        assert_eq!(psbt.inputs[foreign_index], foreign_input);
+
+        // each wallet can only finalize its own input
+        let (psbt, finalized) = wallet1.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, false);
+        let (psbt, finalized) = wallet2.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+
+        let extracted = psbt.extract_tx();
+        assert!(extracted.input.iter().all(|txin| txin.witness.len() == 2));
     }
 
     #[test]
-    fn test_create_tx_custom_rbf_sequence() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+    fn test_create_tx_add_foreign_utxo_manually_selected_only() {
+        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
+        let (wallet2, _, _) =
+            get_funded_wallet("wpkh(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)");
+
+        let utxo = wallet2.list_unspent().unwrap().remove(0);
+        let foreign_input = wallet2.get_psbt_input(utxo.clone(), None, false).unwrap();
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, details) = wallet1
             .create_tx(
                 TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
-                    .enable_rbf_with_sequence(0xDEADBEEF),
+                    .add_foreign_utxo(utxo.outpoint, foreign_input, 108)
+                    .manually_selected_only(),
             )
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xDEADBEEF);
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 1);
+        assert_eq!(
+            psbt.global.unsigned_tx.input[0].previous_output,
+            utxo.outpoint
+        );
+        assert_eq!(details.sent, 0);
     }
 
     #[test]
-    fn test_create_tx_default_sequence() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(TxBuilder::with_recipients(vec![(
-                addr.script_pubkey(),
-                25_000,
-            )]))
-            .unwrap();
+    #[should_panic(expected = "has neither a `witness_utxo` nor a `non_witness_utxo`")]
+    fn test_create_tx_add_foreign_utxo_missing_utxo() {
+        let (wallet1, _, _) = get_funded_wallet(get_test_wpkh());
+        let (wallet2, _, _) =
+            get_funded_wallet("wpkh(cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)");
+        let utxo = wallet2.list_unspent().unwrap().remove(0);
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
-    }
-
-    #[test]
-    #[should_panic(
-        expected = "The `change_policy` can be set only if the wallet has a change_descriptor"
-    )]
-    fn test_create_tx_change_policy_no_internal() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        wallet
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        wallet1
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
-                    .do_not_spend_change(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 60_000)]).add_foreign_utxo(
+                    utxo.outpoint,
+                    psbt::Input::default(),
+                    108,
+                ),
             )
             .unwrap();
     }
 
     #[test]
-    fn test_create_tx_single_recipient_drain_wallet() {
+    #[should_panic(expected = "belongs to the wallet, it can't be added as a foreign UTXO")]
+    fn test_create_tx_add_foreign_utxo_owned() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, details) = wallet
+        let utxo = wallet.list_unspent().unwrap().remove(0);
+        let psbt_input = wallet.get_psbt_input(utxo.clone(), None, false).unwrap();
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_foreign_utxo(
+                    utxo.outpoint,
+                    psbt_input,
+                    108,
+                ),
             )
             .unwrap();
-
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
-        assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            50_000 - details.fees
-        );
     }
 
     #[test]
-    fn test_create_tx_default_fee_rate() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, details) = wallet
+    #[should_panic(expected = "SpendingPolicyRequired(External)")]
+    fn test_create_tx_policy_path_required() {
+        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        wallet
             .create_tx(TxBuilder::with_recipients(vec![(
                 addr.script_pubkey(),
-                25_000,
+                30_000,
             )]))
             .unwrap();
+    }
 
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::default(), @add_signature);
+    #[test]
+    fn test_create_tx_policy_path_no_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let root_id = external_policy.id;
+        // child #0 is just the key "A"
+        let path = vec![(root_id, vec![0])].into_iter().collect();
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
+                    .policy_path(path, KeychainKind::External),
+            )
+            .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
     }
 
     #[test]
-    fn test_create_tx_custom_fee_rate() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, details) = wallet
+    fn test_create_tx_policy_path_use_csv() {
+        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+
+        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
+        let root_id = external_policy.id;
+        // child #1 is or(pk(B),older(144))
+        let path = vec![(root_id, vec![1])].into_iter().collect();
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
-                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
+                    .policy_path(path, KeychainKind::External),
             )
             .unwrap();
 
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(5.0), @add_signature);
+        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 144);
     }
 
     #[test]
-    fn test_create_tx_absolute_fee() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_create_tx_global_xpubs_with_origin() {
+        use bitcoin::hashes::hex::FromHex;
+        use bitcoin::util::base58;
+        use bitcoin::util::psbt::raw::Key;
+
+        let (wallet, _, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, details) = wallet
+        let (psbt, _) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet()
-                    .fee_absolute(100),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_global_xpubs(),
             )
             .unwrap();
 
-        assert_eq!(details.fees, 100);
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
-        assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            50_000 - details.fees
-        );
+        let type_value = 0x01;
+        let key = base58::from_check("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
+
+        let psbt_key = Key { type_value, key };
+
+        // This key has an explicit origin, so it will be encoded here
+        let value_bytes = Vec::<u8>::from_hex("73756c7f30000080000000800000008002000080").unwrap();
+
+        assert_eq!(psbt.global.unknown.len(), 1);
+        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
     }
 
     #[test]
-    fn test_create_tx_absolute_zero_fee() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    #[should_panic(
+        expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
+    )]
+    fn test_create_tx_global_xpubs_origin_missing() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, details) = wallet
+        wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet()
-                    .fee_absolute(0),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_global_xpubs(),
             )
             .unwrap();
-
-        assert_eq!(details.fees, 0);
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
-        assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            50_000 - details.fees
-        );
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_create_tx_absolute_high_fee() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_create_tx_global_xpubs_master_without_origin() {
+        use bitcoin::hashes::hex::FromHex;
+        use bitcoin::util::base58;
+        use bitcoin::util::psbt::raw::Key;
+
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
         let addr = wallet.get_new_address().unwrap();
-        let (_psbt, _details) = wallet
+        let (psbt, _) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet()
-                    .fee_absolute(60_000),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_global_xpubs(),
             )
             .unwrap();
+
+        let type_value = 0x01;
+        let key = base58::from_check("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
+
+        let psbt_key = Key { type_value, key };
+
+        // This key doesn't have an explicit origin, but it's a master key (depth = 0). So we encode
+        // its fingerprint directly and an empty path
+        let value_bytes = Vec::<u8>::from_hex("997a323b").unwrap();
+
+        assert_eq!(psbt.global.unknown.len(), 1);
+        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
     }
 
     #[test]
-    fn test_create_tx_add_change() {
-        use super::tx_builder::TxOrdering;
-
+    fn test_create_tx_bdk_metadata() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, details) = wallet
+        let (psbt, _) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
-                    .ordering(TxOrdering::Untouched),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_bdk_metadata(),
             )
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 2);
-        assert_eq!(psbt.global.unsigned_tx.output[0].value, 25_000);
-        assert_eq!(
-            psbt.global.unsigned_tx.output[1].value,
-            25_000 - details.fees
-        );
+        let metadata = wallet.verify_bdk_metadata(&psbt).unwrap();
+        assert_eq!(metadata.version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert_eq!(metadata.external_policy_path, None);
+        // both the recipient and the change belong to the wallet
+        assert_eq!(metadata.outputs.len(), 2);
+        assert!(metadata.outputs.iter().all(Option::is_some));
     }
 
     #[test]
-    fn test_create_tx_skip_change_dust() {
+    fn test_create_tx_no_bdk_metadata() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
         let (psbt, _) = wallet
             .create_tx(TxBuilder::with_recipients(vec![(
                 addr.script_pubkey(),
-                49_800,
+                25_000,
             )]))
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 1);
-        assert_eq!(psbt.global.unsigned_tx.output[0].value, 49_800);
+        assert!(psbt.global.unknown.is_empty());
+        let metadata = wallet.verify_bdk_metadata(&psbt).unwrap();
+        assert_eq!(metadata.version, None);
+        assert!(metadata.outputs.iter().all(Option::is_none));
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_create_tx_single_recipient_dust_amount() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_verify_bdk_metadata_output_mismatch() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubEBr4i6yk5nf5DAaJpsi9N2pPYBeJ7fZ5Z9rmN4977iYLCGco1VyjB9tvvuvYtfZzjD5A8igzgw3HeWeeKFmanHYqksqZXYXGsw5zjnj7KM9/*)");
         let addr = wallet.get_new_address().unwrap();
-        // very high fee rate, so that the only output would be below dust
-        wallet
+        let (mut psbt, _) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet()
-                    .fee_rate(FeeRate::from_sat_per_vb(453.0)),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_bdk_metadata(),
             )
             .unwrap();
-    }
 
-    #[test]
-    fn test_create_tx_ordering_respected() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, details) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![
-                    (addr.script_pubkey(), 30_000),
-                    (addr.script_pubkey(), 10_000),
-                ])
-                .ordering(super::tx_builder::TxOrdering::BIP69Lexicographic),
-            )
-            .unwrap();
+        let mut metadata = BdkMetadata::from_psbt(&psbt).unwrap();
+        metadata.outputs[0] = Some((KeychainKind::External, 100));
+        metadata.add_to_psbt(&mut psbt);
 
-        assert_eq!(psbt.global.unsigned_tx.output.len(), 3);
-        assert_eq!(
-            psbt.global.unsigned_tx.output[0].value,
-            10_000 - details.fees
-        );
-        assert_eq!(psbt.global.unsigned_tx.output[1].value, 10_000);
-        assert_eq!(psbt.global.unsigned_tx.output[2].value, 30_000);
+        assert!(matches!(
+            wallet.verify_bdk_metadata(&psbt),
+            Err(Error::Metadata(MetadataError::OutputMismatch(0)))
+        ));
     }
 
     #[test]
-    fn test_create_tx_default_sighash() {
+    fn test_get_psbt_satisfaction_weights() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+        let (mut psbt, _) = wallet
             .create_tx(TxBuilder::with_recipients(vec![(
                 addr.script_pubkey(),
-                30_000,
+                25_000,
             )]))
             .unwrap();
 
-        assert_eq!(psbt.inputs[0].sighash_type, None);
-    }
-
-    #[test]
-    fn test_create_tx_custom_sighash() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
-                    .sighash(bitcoin::SigHashType::Single),
-            )
-            .unwrap();
-
+        let max_weight = wallet.get_satisfaction_weight(KeychainKind::External);
+        assert!(max_weight.is_some());
         assert_eq!(
-            psbt.inputs[0].sighash_type,
-            Some(bitcoin::SigHashType::Single)
+            wallet.get_psbt_satisfaction_weights(&psbt).unwrap(),
+            vec![max_weight]
         );
-    }
-
-    #[test]
-    fn test_create_tx_input_hd_keypaths() {
-        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
-        use std::str::FromStr;
 
-        let (wallet, _, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
-            )
-            .unwrap();
+        // once finalized the actual weight is returned, which can't be higher than the estimate
+        let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert!(finalized);
+        psbt = signed_psbt;
+        let weights = wallet.get_psbt_satisfaction_weights(&psbt).unwrap();
+        assert!(weights[0].unwrap() <= max_weight.unwrap());
 
-        assert_eq!(psbt.inputs[0].hd_keypaths.len(), 1);
+        psbt.inputs[0] = Default::default();
         assert_eq!(
-            psbt.inputs[0].hd_keypaths.values().nth(0).unwrap(),
-            &(
-                Fingerprint::from_str("d34db33f").unwrap(),
-                DerivationPath::from_str("m/44'/0'/0'/0/0").unwrap()
-            )
+            wallet.get_psbt_satisfaction_weights(&psbt).unwrap(),
+            vec![None]
         );
     }
 
     #[test]
-    fn test_create_tx_output_hd_keypaths() {
-        use bitcoin::util::bip32::{DerivationPath, Fingerprint};
-        use std::str::FromStr;
+    fn test_get_tx() {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
 
-        let (wallet, descriptors, _) = get_funded_wallet("wpkh([d34db33f/44'/0'/0']tpubDEnoLuPdBep9bzw5LoGYpsxUQYheRQ9gcgrJhJEcdKFB9cWQRyYmkCyRoTqeD4tJYiVVgt6A3rN6rWn9RYhR9sBsGxji29LYWHuKKbdb1ev/0/*)");
-        // cache some addresses
-        wallet.get_new_address().unwrap();
+        let details = wallet.get_tx(&txid, true).unwrap().unwrap();
+        assert_eq!(details.txid, txid);
+        assert!(details.transaction.is_some());
+        assert!(wallet
+            .get_tx(&txid, false)
+            .unwrap()
+            .unwrap()
+            .transaction
+            .is_none());
+        assert!(wallet.get_tx(&Txid::default(), false).unwrap().is_none());
+    }
 
-        let addr = testutils!(@external descriptors, 5);
-        let (psbt, _) = wallet
+    #[test]
+    fn test_cancel_tx() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
             )
             .unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx.clone());
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
 
-        assert_eq!(psbt.outputs[0].hd_keypaths.len(), 1);
-        assert_eq!(
-            psbt.outputs[0].hd_keypaths.values().nth(0).unwrap(),
-            &(
-                Fingerprint::from_str("d34db33f").unwrap(),
-                DerivationPath::from_str("m/44'/0'/0'/0/5").unwrap()
-            )
-        );
-    }
+        let (psbt, details) = wallet.cancel_tx(&txid, TxBuilder::new()).unwrap();
+        let new_tx = &psbt.global.unsigned_tx;
+        assert_eq!(new_tx.input.len(), tx.input.len());
+        assert_eq!(new_tx.input[0].previous_output, tx.input[0].previous_output);
+        assert_eq!(new_tx.output.len(), 1);
+        assert!(wallet.is_mine(&new_tx.output[0].script_pubkey).unwrap());
 
-    #[test]
-    fn test_create_tx_set_redeem_script_p2sh() {
-        use bitcoin::hashes::hex::FromHex;
+        assert_eq!(details.sent, original_details.sent);
+        assert_eq!(details.received, new_tx.output[0].value);
+        assert_eq!(details.received + details.fees, details.sent);
+        assert!(details.fees > original_details.fees);
 
-        let (wallet, _, _) =
-            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
-            )
+        // a failed cancellation doesn't use up a derivation index
+        let last_index = wallet
+            .database
+            .borrow()
+            .get_last_index(KeychainKind::External)
             .unwrap();
-
+        assert!(matches!(
+            wallet.cancel_tx(
+                &txid,
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(1.0))
+            ),
+            Err(Error::FeeRateTooLow { .. })
+        ));
         assert_eq!(
-            psbt.inputs[0].redeem_script,
-            Some(Script::from(
-                Vec::<u8>::from_hex(
-                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-                )
-                .unwrap()
-            ))
+            wallet
+                .database
+                .borrow()
+                .get_last_index(KeychainKind::External)
+                .unwrap(),
+            last_index
         );
-        assert_eq!(psbt.inputs[0].witness_script, None);
+        assert!(matches!(
+            wallet.cancel_tx(&Txid::default(), TxBuilder::new()),
+            Err(Error::TransactionNotFound)
+        ));
     }
 
     #[test]
-    fn test_create_tx_set_witness_script_p2wsh() {
-        use bitcoin::hashes::hex::FromHex;
-
-        let (wallet, _, _) =
-            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    #[should_panic(expected = "IrreplaceableTransaction")]
+    fn test_bump_fee_irreplaceable_tx() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
-            )
+        let (psbt, mut details) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
 
-        assert_eq!(psbt.inputs[0].redeem_script, None);
-        assert_eq!(
-            psbt.inputs[0].witness_script,
-            Some(Script::from(
-                Vec::<u8>::from_hex(
-                    "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac"
-                )
-                .unwrap()
-            ))
-        );
+        wallet.bump_fee(&txid, TxBuilder::new()).unwrap();
     }
 
     #[test]
-    fn test_create_tx_set_redeem_witness_script_p2wsh_p2sh() {
-        use bitcoin::hashes::hex::FromHex;
-
-        let (wallet, _, _) =
-            get_funded_wallet("sh(wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)))");
+    #[should_panic(expected = "TransactionConfirmed")]
+    fn test_bump_fee_confirmed_tx() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
-            )
+        let (psbt, mut details) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        details.height = Some(42);
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
 
-        let script = Script::from(
-            Vec::<u8>::from_hex(
-                "21032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3ac",
-            )
-            .unwrap(),
-        );
-
-        assert_eq!(psbt.inputs[0].redeem_script, Some(script.to_v0_p2wsh()));
-        assert_eq!(psbt.inputs[0].witness_script, Some(script));
+        wallet.bump_fee(&txid, TxBuilder::new()).unwrap();
     }
 
     #[test]
-    fn test_create_tx_non_witness_utxo() {
-        let (wallet, _, _) =
-            get_funded_wallet("sh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    #[should_panic(expected = "FeeRateTooLow")]
+    fn test_bump_fee_low_fee_rate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+        let (psbt, mut details) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+            )
+            .unwrap();
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
+
+        wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(1.0)),
             )
             .unwrap();
-
-        assert!(psbt.inputs[0].non_witness_utxo.is_some());
-        assert!(psbt.inputs[0].witness_utxo.is_none());
     }
 
     #[test]
-    fn test_create_tx_only_witness_utxo() {
-        let (wallet, _, _) =
-            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    #[should_panic(expected = "FeeTooLow")]
+    fn test_bump_fee_low_abs() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+        let (psbt, mut details) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
             )
             .unwrap();
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
 
-        assert!(psbt.inputs[0].non_witness_utxo.is_none());
-        assert!(psbt.inputs[0].witness_utxo.is_some());
+        wallet
+            .bump_fee(&txid, TxBuilder::new().fee_absolute(10))
+            .unwrap();
     }
 
     #[test]
-    fn test_create_tx_shwpkh_has_witness_utxo() {
-        let (wallet, _, _) =
-            get_funded_wallet("sh(wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    #[should_panic(expected = "FeeRateTooHigh")]
+    fn test_bump_fee_max_fee_rate() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+        let (psbt, mut details) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
             )
             .unwrap();
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
 
-        assert!(psbt.inputs[0].non_witness_utxo.is_none());
-        assert!(psbt.inputs[0].witness_utxo.is_some());
+        wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new()
+                    .fee_rate(FeeRate::from_sat_per_vb(50.0))
+                    .max_fee_rate(FeeRate::from_sat_per_vb(10.0)),
+            )
+            .unwrap();
     }
 
     #[test]
-    fn test_create_tx_both_non_witness_utxo_and_witness_utxo() {
-        let (wallet, _, _) =
-            get_funded_wallet("wsh(pk(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))");
+    #[should_panic(expected = "FeeTooLow")]
+    fn test_bump_fee_zero_abs() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
+        let (psbt, mut details) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet()
-                    .force_non_witness_utxo(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
             )
             .unwrap();
+        let tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the utxos, we know they can't be used anyways
+        details.transaction = Some(tx);
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
 
-        assert!(psbt.inputs[0].non_witness_utxo.is_some());
-        assert!(psbt.inputs[0].witness_utxo.is_some());
+        wallet
+            .bump_fee(&txid, TxBuilder::new().fee_absolute(0))
+            .unwrap();
     }
 
     #[test]
-    fn test_create_tx_add_utxo() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let small_output_txid = wallet.database.borrow_mut().received_tx(
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-
+    fn test_bump_fee_reduce_change() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let (psbt, details) = wallet
+        let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)]).add_utxo(
-                    OutPoint {
-                        txid: small_output_txid,
-                        vout: 0,
-                    },
-                ),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+            )
+            .unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let (psbt, details) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(2.5)),
             )
             .unwrap();
 
+        assert_eq!(details.sent, original_details.sent);
         assert_eq!(
-            psbt.global.unsigned_tx.input.len(),
-            2,
-            "should add an additional input since 25_000 < 30_000"
+            details.received + details.fees,
+            original_details.received + original_details.fees
         );
-        assert_eq!(details.sent, 75_000, "total should be sum of both inputs");
-    }
+        assert!(details.fees > original_details.fees);
 
-    #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_create_tx_manually_selected_insufficient() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let small_output_txid = wallet.database.borrow_mut().received_tx(
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            25_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
         );
+        assert_eq!(details.recipients, original_details.recipients);
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(2.5), @add_signature);
+    }
 
+    #[test]
+    fn test_bump_fee_split_change_not_recipient() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        wallet
+        let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
-                    .add_utxo(OutPoint {
-                        txid: small_output_txid,
-                        vout: 0,
-                    })
-                    .manually_selected_only(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .split_change(2, 1_000)
+                    .enable_rbf(),
+            )
+            .unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let (psbt, details) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(2.5)),
             )
             .unwrap();
+
+        assert_eq!(psbt.global.unsigned_tx.output.len(), 3);
+        assert_eq!(details.recipients.len(), 1);
+        assert_eq!(details.recipients[0].script_pubkey, addr.script_pubkey());
+        assert_eq!(details.recipients[0].amount, 25_000);
+        assert_eq!(details.recipients, original_details.recipients);
     }
 
     #[test]
-    #[should_panic(expected = "SpendingPolicyRequired(External)")]
-    fn test_create_tx_policy_path_required() {
-        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
+    fn test_bump_fee_absolute_reduce_change() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, mut original_details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+            )
+            .unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let (psbt, details) = wallet
+            .bump_fee(&txid, TxBuilder::new().fee_absolute(200))
+            .unwrap();
+
+        assert_eq!(details.sent, original_details.sent);
+        assert_eq!(
+            details.received + details.fees,
+            original_details.received + original_details.fees
+        );
+        assert!(
+            details.fees > original_details.fees,
+            "{} > {}",
+            details.fees,
+            original_details.fees
+        );
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            25_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
 
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        wallet
-            .create_tx(TxBuilder::with_recipients(vec![(
-                addr.script_pubkey(),
-                30_000,
-            )]))
-            .unwrap();
+        assert_eq!(details.fees, 200);
     }
 
     #[test]
-    fn test_create_tx_policy_path_no_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-        let root_id = external_policy.id;
-        // child #0 is just the key "A"
-        let path = vec![(root_id, vec![0])].into_iter().collect();
-
+    fn test_bump_fee_reduce_single_recipient() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let (psbt, _) = wallet
+        let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
-                    .policy_path(path, KeychainKind::External),
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .enable_rbf(),
             )
             .unwrap();
-
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 0xFFFFFFFF);
-    }
-
-    #[test]
-    fn test_create_tx_policy_path_use_csv() {
-        let (wallet, _, _) = get_funded_wallet(get_test_a_or_b_plus_csv());
-
-        let external_policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
-        let root_id = external_policy.id;
-        // child #1 is or(pk(B),older(144))
-        let path = vec![(root_id, vec![1])].into_iter().collect();
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 30_000)])
-                    .policy_path(path, KeychainKind::External),
-            )
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
             .unwrap();
 
-        assert_eq!(psbt.global.unsigned_tx.input[0].sequence, 144);
-    }
-
-    #[test]
-    fn test_create_tx_global_xpubs_with_origin() {
-        use bitcoin::hashes::hex::FromHex;
-        use bitcoin::util::base58;
-        use bitcoin::util::psbt::raw::Key;
-
-        let (wallet, _, _) = get_funded_wallet("wpkh([73756c7f/48'/0'/0'/2']tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_global_xpubs(),
+        let (psbt, details) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new()
+                    .maintain_single_recipient()
+                    .fee_rate(FeeRate::from_sat_per_vb(2.5)),
             )
             .unwrap();
 
-        let type_value = 0x01;
-        let key = base58::from_check("tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3").unwrap();
-
-        let psbt_key = Key { type_value, key };
+        assert_eq!(details.sent, original_details.sent);
+        assert!(details.fees > original_details.fees);
 
-        // This key has an explicit origin, so it will be encoded here
-        let value_bytes = Vec::<u8>::from_hex("73756c7f30000080000000800000008002000080").unwrap();
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value + details.fees, details.sent);
+        assert_eq!(
+            details.recipients,
+            vec![RecipientDetails {
+                script_pubkey: addr.script_pubkey(),
+                amount: details.sent,
+                fee_subtracted: true,
+                effective_amount: tx.output[0].value,
+            }]
+        );
 
-        assert_eq!(psbt.global.unknown.len(), 1);
-        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(2.5), @add_signature);
     }
 
     #[test]
-    #[should_panic(
-        expected = "MissingKeyOrigin(\"tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3\")"
-    )]
-    fn test_create_tx_global_xpubs_origin_missing() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tpubDCKxNyM3bLgbEX13Mcd8mYxbVg9ajDkWXMh29hMWBurKfVmBfWAM96QVP3zaUcN51HvkZ3ar4VwP82kC8JZhhux8vFQoJintSpVBwpFvyU3/0/*)");
-        let addr = wallet.get_new_address().unwrap();
-        wallet
+    fn test_bump_fee_allow_shrinking() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let other_addr = Address::from_str("2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt").unwrap();
+        let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_global_xpubs(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .add_recipient(other_addr.script_pubkey(), 10_000)
+                    .enable_rbf(),
             )
             .unwrap();
-    }
-
-    #[test]
-    fn test_create_tx_global_xpubs_master_without_origin() {
-        use bitcoin::hashes::hex::FromHex;
-        use bitcoin::util::base58;
-        use bitcoin::util::psbt::raw::Key;
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        let original_change = tx
+            .output
+            .iter()
+            .find(|txout| txout.value != 25_000 && txout.value != 10_000)
+            .unwrap()
+            .clone();
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
 
-        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL/0/*)");
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).add_global_xpubs(),
+        let (psbt, details) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new()
+                    .allow_shrinking(addr.script_pubkey())
+                    .fee_rate(FeeRate::from_sat_per_vb(2.5)),
             )
             .unwrap();
 
-        let type_value = 0x01;
-        let key = base58::from_check("tpubD6NzVbkrYhZ4Y55A58Gv9RSNF5hy84b5AJqYy7sCcjFrkcLpPre8kmgfit6kY1Zs3BLgeypTDBZJM222guPpdz7Cup5yzaMu62u7mYGbwFL").unwrap();
-
-        let psbt_key = Key { type_value, key };
-
-        // This key doesn't have an explicit origin, but it's a master key (depth = 0). So we encode
-        // its fingerprint directly and an empty path
-        let value_bytes = Vec::<u8>::from_hex("997a323b").unwrap();
+        assert!(details.fees > original_details.fees);
 
-        assert_eq!(psbt.global.unknown.len(), 1);
-        assert_eq!(psbt.global.unknown.get(&psbt_key), Some(&value_bytes));
-    }
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.output.len(), 3);
+        assert!(tx.output.contains(&original_change));
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            25_000 - (details.fees - original_details.fees)
+        );
+        assert!(tx.output.iter().any(|txout| txout.value == 10_000));
 
-    #[test]
-    #[should_panic(expected = "IrreplaceableTransaction")]
-    fn test_bump_fee_irreplaceable_tx() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, mut details) = wallet
-            .create_tx(TxBuilder::with_recipients(vec![(
-                addr.script_pubkey(),
-                25_000,
-            )]))
+        // the change is untouched, and the shrunk recipient pays for the increase in fees
+        assert_eq!(details.received, original_change.value);
+        assert_eq!(details.recipients.len(), 2);
+        let shrunk = details
+            .recipients
+            .iter()
+            .find(|r| r.script_pubkey == addr.script_pubkey())
             .unwrap();
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
-
-        wallet.bump_fee(&txid, TxBuilder::new()).unwrap();
-    }
-
-    #[test]
-    #[should_panic(expected = "TransactionConfirmed")]
-    fn test_bump_fee_confirmed_tx() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, mut details) = wallet
-            .create_tx(TxBuilder::with_recipients(vec![(
-                addr.script_pubkey(),
-                25_000,
-            )]))
+        assert_eq!(shrunk.amount, 25_000);
+        assert!(shrunk.fee_subtracted);
+        assert_eq!(
+            shrunk.effective_amount,
+            25_000 - (details.fees - original_details.fees)
+        );
+        let other = details
+            .recipients
+            .iter()
+            .find(|r| r.script_pubkey == other_addr.script_pubkey())
             .unwrap();
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        details.height = Some(42);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
+        assert_eq!(other.amount, 10_000);
+        assert!(!other.fee_subtracted);
+        assert_eq!(other.effective_amount, 10_000);
 
-        wallet.bump_fee(&txid, TxBuilder::new()).unwrap();
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(2.5), @add_signature);
     }
 
     #[test]
-    #[should_panic(expected = "FeeRateTooLow")]
-    fn test_bump_fee_low_fee_rate() {
+    #[should_panic(expected = "can't be used together")]
+    fn test_bump_fee_allow_shrinking_and_maintain_single_recipient() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, mut details) = wallet
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .enable_rbf(),
             )
             .unwrap();
         let tx = psbt.extract_tx();
         let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
 
         wallet
             .bump_fee(
                 &txid,
-                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(1.0)),
+                TxBuilder::new()
+                    .maintain_single_recipient()
+                    .allow_shrinking(addr.script_pubkey())
+                    .fee_rate(FeeRate::from_sat_per_vb(2.5)),
             )
             .unwrap();
     }
 
     #[test]
-    #[should_panic(expected = "FeeTooLow")]
-    fn test_bump_fee_low_abs() {
+    #[should_panic(expected = "it can't be shrunk")]
+    fn test_bump_fee_allow_shrinking_missing_output() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, mut details) = wallet
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, mut original_details) = wallet
             .create_tx(
                 TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
             )
             .unwrap();
         let tx = psbt.extract_tx();
         let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
-
+        original_details.transaction = Some(tx);
         wallet
-            .bump_fee(&txid, TxBuilder::new().fee_absolute(10))
-            .unwrap();
-    }
-
-    #[test]
-    #[should_panic(expected = "FeeTooLow")]
-    fn test_bump_fee_zero_abs() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
-        let addr = wallet.get_new_address().unwrap();
-        let (psbt, mut details) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
-            )
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
             .unwrap();
-        let tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the utxos, we know they can't be used anyways
-        details.transaction = Some(tx);
-        wallet.database.borrow_mut().set_tx(&details).unwrap();
 
+        // not one of the outputs of the transaction
+        let missing = Address::from_str("mjDZ34icH4V2k9GmC8niCrhzVuR3z8Mgkf").unwrap();
         wallet
-            .bump_fee(&txid, TxBuilder::new().fee_absolute(0))
+            .bump_fee(
+                &txid,
+                TxBuilder::new()
+                    .allow_shrinking(missing.script_pubkey())
+                    .fee_rate(FeeRate::from_sat_per_vb(2.5)),
+            )
             .unwrap();
     }
 
     #[test]
-    fn test_bump_fee_reduce_change() {
+    fn test_bump_fee_absolute_reduce_single_recipient() {
         let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .enable_rbf(),
             )
             .unwrap();
         let mut tx = psbt.extract_tx();
         let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
         for txin in &mut tx.input {
             txin.witness.push([0x00; 108].to_vec()); // fake signature
             wallet
@@ -2543,51 +7142,46 @@ mod test {
         let (psbt, details) = wallet
             .bump_fee(
                 &txid,
-                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(2.5)),
+                TxBuilder::new()
+                    .maintain_single_recipient()
+                    .fee_absolute(300),
             )
             .unwrap();
 
         assert_eq!(details.sent, original_details.sent);
-        assert_eq!(
-            details.received + details.fees,
-            original_details.received + original_details.fees
-        );
         assert!(details.fees > original_details.fees);
 
         let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            25_000
-        );
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
-        );
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value + details.fees, details.sent);
 
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(2.5), @add_signature);
+        assert_eq!(details.fees, 300);
     }
 
     #[test]
-    fn test_bump_fee_absolute_reduce_change() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_bump_fee_drain_wallet() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        // receive an extra tx so that our wallet has two utxos.
+        let incoming_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+        let outpoint = OutPoint {
+            txid: incoming_txid,
+            vout: 0,
+        };
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)]).enable_rbf(),
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .utxos(vec![outpoint])
+                    .manually_selected_only()
+                    .enable_rbf(),
             )
             .unwrap();
         let mut tx = psbt.extract_tx();
         let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
         for txin in &mut tx.input {
             txin.witness.push([0x00; 108].to_vec()); // fake signature
             wallet
@@ -2602,54 +7196,45 @@ mod test {
             .borrow_mut()
             .set_tx(&original_details)
             .unwrap();
+        assert_eq!(original_details.sent, 25_000);
 
-        let (psbt, details) = wallet
-            .bump_fee(&txid, TxBuilder::new().fee_absolute(200))
+        // for the new feerate, it should be enough to reduce the output, but since we specify
+        // `drain_wallet` we expect to spend everything
+        let (_, details) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new()
+                    .drain_wallet()
+                    .maintain_single_recipient()
+                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
+            )
             .unwrap();
-
-        assert_eq!(details.sent, original_details.sent);
-        assert_eq!(
-            details.received + details.fees,
-            original_details.received + original_details.fees
-        );
-        assert!(
-            details.fees > original_details.fees,
-            "{} > {}",
-            details.fees,
-            original_details.fees
-        );
-
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            25_000
-        );
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
-        );
-
-        assert_eq!(details.fees, 200);
+        assert_eq!(details.sent, 75_000);
     }
 
     #[test]
-    fn test_bump_fee_reduce_single_recipient() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    #[should_panic(expected = "InsufficientFunds")]
+    fn test_bump_fee_remove_output_manually_selected_only() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
+        // them, and make sure that `bump_fee` doesn't try to add more. eventually, it should fail
+        // because the fee rate is too high and the single utxo isn't enough to create a non-dust
+        // output
+        let incoming_txid = wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+        let outpoint = OutPoint {
+            txid: incoming_txid,
+            vout: 0,
+        };
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, mut original_details) = wallet
             .create_tx(
                 TxBuilder::new()
                     .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet()
+                    .utxos(vec![outpoint])
+                    .manually_selected_only()
                     .enable_rbf(),
             )
             .unwrap();
@@ -2669,40 +7254,101 @@ mod test {
             .borrow_mut()
             .set_tx(&original_details)
             .unwrap();
+        assert_eq!(original_details.sent, 25_000);
 
-        let (psbt, details) = wallet
+        wallet
             .bump_fee(
                 &txid,
                 TxBuilder::new()
-                    .maintain_single_recipient()
-                    .fee_rate(FeeRate::from_sat_per_vb(2.5)),
+                    .utxos(vec![outpoint])
+                    .manually_selected_only()
+                    .fee_rate(FeeRate::from_sat_per_vb(225.0)),
             )
             .unwrap();
+    }
 
-        assert_eq!(details.sent, original_details.sent);
-        assert!(details.fees > original_details.fees);
+    #[test]
+    fn test_bump_fee_add_input() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, mut original_details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 45_000)]).enable_rbf(),
+            )
+            .unwrap();
+        let mut tx = psbt.extract_tx();
+        let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
+        for txin in &mut tx.input {
+            txin.witness.push([0x00; 108].to_vec()); // fake signature
+            wallet
+                .database
+                .borrow_mut()
+                .del_utxo(&txin.previous_output)
+                .unwrap();
+        }
+        original_details.transaction = Some(tx);
+        wallet
+            .database
+            .borrow_mut()
+            .set_tx(&original_details)
+            .unwrap();
+
+        let (psbt, details) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(50.0)),
+            )
+            .unwrap();
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fees + details.received, 30_000);
 
         let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 1);
-        assert_eq!(tx.output[0].value + details.fees, details.sent);
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
 
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(2.5), @add_signature);
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(50.0), @add_signature);
     }
 
     #[test]
-    fn test_bump_fee_absolute_reduce_single_recipient() {
-        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+    fn test_bump_fee_absolute_add_input() {
+        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
+        wallet.database.borrow_mut().received_tx(
+            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
+            Some(100),
+        );
+
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet()
-                    .enable_rbf(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 45_000)]).enable_rbf(),
             )
             .unwrap();
         let mut tx = psbt.extract_tx();
         let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
         for txin in &mut tx.input {
             txin.witness.push([0x00; 108].to_vec()); // fake signature
             wallet
@@ -2719,48 +7365,60 @@ mod test {
             .unwrap();
 
         let (psbt, details) = wallet
-            .bump_fee(
-                &txid,
-                TxBuilder::new()
-                    .maintain_single_recipient()
-                    .fee_absolute(300),
-            )
+            .bump_fee(&txid, TxBuilder::new().fee_absolute(6_000))
             .unwrap();
 
-        assert_eq!(details.sent, original_details.sent);
-        assert!(details.fees > original_details.fees);
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fees + details.received, 30_000);
 
         let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.output.len(), 1);
-        assert_eq!(tx.output[0].value + details.fees, details.sent);
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
 
-        assert_eq!(details.fees, 300);
+        assert_eq!(details.fees, 6_000);
     }
 
     #[test]
-    fn test_bump_fee_drain_wallet() {
+    fn test_bump_fee_no_change_add_input_and_change() {
         let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        // receive an extra tx so that our wallet has two utxos.
         let incoming_txid = wallet.database.borrow_mut().received_tx(
             testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
             Some(100),
         );
-        let outpoint = OutPoint {
-            txid: incoming_txid,
-            vout: 0,
-        };
+
+        // initially make a tx without change by using `set_single_recipient`
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, mut original_details) = wallet
             .create_tx(
                 TxBuilder::new()
                     .set_single_recipient(addr.script_pubkey())
-                    .utxos(vec![outpoint])
+                    .add_utxo(OutPoint {
+                        txid: incoming_txid,
+                        vout: 0,
+                    })
                     .manually_selected_only()
                     .enable_rbf(),
             )
             .unwrap();
         let mut tx = psbt.extract_tx();
         let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
         for txin in &mut tx.input {
             txin.witness.push([0x00; 108].to_vec()); // fake signature
             wallet
@@ -2775,50 +7433,65 @@ mod test {
             .borrow_mut()
             .set_tx(&original_details)
             .unwrap();
-        assert_eq!(original_details.sent, 25_000);
 
-        // for the new feerate, it should be enough to reduce the output, but since we specify
-        // `drain_wallet` we expect to spend everything
-        let (_, details) = wallet
+        // now bump the fees without using `maintain_single_recipient`. the wallet should add an
+        // extra input and a change output, and leave the original output untouched
+        let (psbt, details) = wallet
             .bump_fee(
                 &txid,
-                TxBuilder::new()
-                    .drain_wallet()
-                    .maintain_single_recipient()
-                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(50.0)),
             )
             .unwrap();
-        assert_eq!(details.sent, 75_000);
+
+        let original_send_all_amount = original_details.sent - original_details.fees;
+        assert_eq!(details.sent, original_details.sent + 50_000);
+        assert_eq!(
+            details.received,
+            75_000 - original_send_all_amount - details.fees
+        );
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            original_send_all_amount
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            75_000 - original_send_all_amount - details.fees
+        );
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(50.0), @add_signature);
     }
 
     #[test]
-    #[should_panic(expected = "InsufficientFunds")]
-    fn test_bump_fee_remove_output_manually_selected_only() {
+    fn test_bump_fee_add_input_change_dust() {
         let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        // receive an extra tx so that our wallet has two utxos. then we manually pick only one of
-        // them, and make sure that `bump_fee` doesn't try to add more. eventually, it should fail
-        // because the fee rate is too high and the single utxo isn't enough to create a non-dust
-        // output
-        let incoming_txid = wallet.database.borrow_mut().received_tx(
+        wallet.database.borrow_mut().received_tx(
             testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
             Some(100),
         );
-        let outpoint = OutPoint {
-            txid: incoming_txid,
-            vout: 0,
-        };
+
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, mut original_details) = wallet
             .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .utxos(vec![outpoint])
-                    .manually_selected_only()
-                    .enable_rbf(),
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 45_000)]).enable_rbf(),
             )
             .unwrap();
         let mut tx = psbt.extract_tx();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.output.len(), 2);
         let txid = tx.txid();
+        // skip saving the new utxos, we know they can't be used anyways
         for txin in &mut tx.input {
             txin.witness.push([0x00; 108].to_vec()); // fake signature
             wallet
@@ -2833,23 +7506,40 @@ mod test {
             .borrow_mut()
             .set_tx(&original_details)
             .unwrap();
-        assert_eq!(original_details.sent, 25_000);
 
-        wallet
+        // the change left is below the dust limit of its p2wpkh script, 294 satoshi
+        let (psbt, details) = wallet
             .bump_fee(
                 &txid,
-                TxBuilder::new()
-                    .utxos(vec![outpoint])
-                    .manually_selected_only()
-                    .fee_rate(FeeRate::from_sat_per_vb(225.0)),
+                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(141.5)),
             )
             .unwrap();
+
+        assert_eq!(original_details.received, 5_000 - original_details.fees);
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fees, 30_000);
+        assert_eq!(details.received, 0);
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(141.5), @dust_change, @add_signature);
     }
 
     #[test]
-    fn test_bump_fee_add_input() {
+    fn test_bump_fee_force_add_input() {
         let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        wallet.database.borrow_mut().received_tx(
+        let incoming_txid = wallet.database.borrow_mut().received_tx(
             testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
             Some(100),
         );
@@ -2878,10 +7568,17 @@ mod test {
             .set_tx(&original_details)
             .unwrap();
 
+        // the new fee_rate is low enough that just reducing the change would be fine, but we force
+        // the addition of an extra input with `add_utxo()`
         let (psbt, details) = wallet
             .bump_fee(
                 &txid,
-                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(50.0)),
+                TxBuilder::new()
+                    .add_utxo(OutPoint {
+                        txid: incoming_txid,
+                        vout: 0,
+                    })
+                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
             )
             .unwrap();
 
@@ -2908,13 +7605,13 @@ mod test {
             details.received
         );
 
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(50.0), @add_signature);
+        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(5.0), @add_signature);
     }
 
     #[test]
-    fn test_bump_fee_absolute_add_input() {
+    fn test_bump_fee_absolute_force_add_input() {
         let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        wallet.database.borrow_mut().received_tx(
+        let incoming_txid = wallet.database.borrow_mut().received_tx(
             testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
             Some(100),
         );
@@ -2943,363 +7640,675 @@ mod test {
             .set_tx(&original_details)
             .unwrap();
 
-        let (psbt, details) = wallet
-            .bump_fee(&txid, TxBuilder::new().fee_absolute(6_000))
+        // the new fee_rate is low enough that just reducing the change would be fine, but we force
+        // the addition of an extra input with `add_utxo()`
+        let (psbt, details) = wallet
+            .bump_fee(
+                &txid,
+                TxBuilder::new()
+                    .add_utxo(OutPoint {
+                        txid: incoming_txid,
+                        vout: 0,
+                    })
+                    .fee_absolute(250),
+            )
+            .unwrap();
+
+        assert_eq!(details.sent, original_details.sent + 25_000);
+        assert_eq!(details.fees + details.received, 30_000);
+
+        let tx = &psbt.global.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey == addr.script_pubkey())
+                .unwrap()
+                .value,
+            45_000
+        );
+        assert_eq!(
+            tx.output
+                .iter()
+                .find(|txout| txout.script_pubkey != addr.script_pubkey())
+                .unwrap()
+                .value,
+            details.received
+        );
+
+        assert_eq!(details.fees, 250);
+    }
+
+    #[test]
+    fn test_sign_single_xprv() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+
+        let extracted = signed_psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_xprv_bip44_path() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+
+        let extracted = signed_psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_xprv_sh_wpkh() {
+        let (wallet, _, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+
+        let extracted = signed_psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_wif() {
+        let (wallet, _, _) =
+            get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+
+        let extracted = signed_psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_single_xprv_no_hd_keypaths() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_new_address().unwrap();
+        let (mut psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
+            )
+            .unwrap();
+
+        psbt.inputs[0].hd_keypaths.clear();
+        assert_eq!(psbt.inputs[0].hd_keypaths.len(), 0);
+
+        let (signed_psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+
+        let extracted = signed_psbt.extract_tx();
+        assert_eq!(extracted.input[0].witness.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_custom_sighash() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let sighash = bitcoin::SigHashType::SinglePlusAnyoneCanPay;
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .sighash(sighash),
+            )
             .unwrap();
 
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fees + details.received, 30_000);
+        let sign_options = SignOptions {
+            allow_all_sighashes: true,
+            ..Default::default()
+        };
+        let (signed_psbt, finalized) = wallet.sign(psbt, sign_options).unwrap();
+        assert_eq!(finalized, true);
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            45_000
-        );
+        // the sighash type is the last byte of the signature
+        let extracted = signed_psbt.extract_tx();
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
+            extracted.input[0].witness[0].last(),
+            Some(&(sighash.as_u32() as u8))
         );
-
-        assert_eq!(details.fees, 6_000);
     }
 
     #[test]
-    fn test_bump_fee_no_change_add_input_and_change() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let incoming_txid = wallet.database.borrow_mut().received_tx(
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
+    fn test_sign_nonstandard_sighash() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .sighash(bitcoin::SigHashType::NonePlusAnyoneCanPay),
+            )
+            .unwrap();
+
+        let result = wallet.sign(psbt, SignOptions::default());
+        assert!(
+            matches!(result, Err(Error::Signer(SignerError::NonStandardSighash))),
+            "Signing a non-standard sighash should fail by default"
         );
+    }
 
-        // initially make a tx without change by using `set_single_recipient`
+    #[test]
+    fn test_sign_dont_trust_witness_utxo() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let (psbt, mut original_details) = wallet
+        let sign_options = SignOptions {
+            trust_witness_utxo: false,
+            ..Default::default()
+        };
+
+        let (psbt, _) = wallet
             .create_tx(
                 TxBuilder::new()
                     .set_single_recipient(addr.script_pubkey())
-                    .add_utxo(OutPoint {
-                        txid: incoming_txid,
-                        vout: 0,
-                    })
-                    .manually_selected_only()
-                    .enable_rbf(),
+                    .drain_wallet(),
             )
             .unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
+        let result = wallet.sign(psbt, sign_options.clone());
+        assert!(
+            matches!(
+                result,
+                Err(Error::Signer(SignerError::MissingNonWitnessUtxo))
+            ),
+            "The `witness_utxo` shouldn't be trusted without a `non_witness_utxo`"
+        );
+
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet()
+                    .force_non_witness_utxo(),
+            )
             .unwrap();
+        let (_, finalized) = wallet.sign(psbt, sign_options).unwrap();
+        assert_eq!(finalized, true);
+    }
 
-        // now bump the fees without using `maintain_single_recipient`. the wallet should add an
-        // extra input and a change output, and leave the original output untouched
-        let (psbt, details) = wallet
-            .bump_fee(
-                &txid,
-                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(50.0)),
+    #[test]
+    fn test_sign_dont_finalize() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(
+                TxBuilder::new()
+                    .set_single_recipient(addr.script_pubkey())
+                    .drain_wallet(),
             )
             .unwrap();
 
-        let original_send_all_amount = original_details.sent - original_details.fees;
-        assert_eq!(details.sent, original_details.sent + 50_000);
+        let sign_options = SignOptions {
+            try_finalize: false,
+            ..Default::default()
+        };
+        let (signed_psbt, finalized) = wallet.sign(psbt, sign_options).unwrap();
+        assert_eq!(finalized, false);
+        assert!(signed_psbt.inputs[0].final_script_witness.is_none());
+        assert_eq!(signed_psbt.inputs[0].partial_sigs.len(), 1);
+    }
+
+    #[test]
+    fn test_sign_message_single_key() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let signature = wallet
+            .sign_message(KeychainKind::External, 0, "Hello World")
+            .unwrap();
+
         assert_eq!(
-            details.received,
-            75_000 - original_send_all_amount - details.fees
+            signature.public_key.to_string(),
+            "032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3"
         );
+        assert!(signature.verify("Hello World", wallet.secp_ctx()));
+        assert!(!signature.verify("Hello", wallet.secp_ctx()));
+    }
+
+    #[test]
+    fn test_sign_message_xprv() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = wallet.get_new_address().unwrap();
+        let signature = wallet
+            .sign_message(KeychainKind::External, 0, "Hello World")
+            .unwrap();
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            original_send_all_amount
+            Address::p2wpkh(&signature.public_key, Network::Regtest).unwrap(),
+            addr
         );
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            75_000 - original_send_all_amount - details.fees
+        assert!(signature.verify("Hello World", wallet.secp_ctx()));
+    }
+
+    #[test]
+    fn test_sign_message_skip_wrong_signer() {
+        use bitcoin::secp256k1::SecretKey;
+        use miniscript::MiniscriptKey;
+
+        // signs any message with its own key, regardless of the key requested
+        #[derive(Debug)]
+        struct WrongKeySigner(PrivateKey);
+        impl Signer for WrongKeySigner {
+            fn sign(
+                &self,
+                _psbt: &mut PSBT,
+                _input_index: Option<usize>,
+                _sign_options: &SignOptions,
+                _secp: &SecpCtx,
+            ) -> Result<(), SignerError> {
+                Ok(())
+            }
+
+            fn sign_whole_tx(&self) -> bool {
+                true
+            }
+
+            fn sign_message(
+                &self,
+                message: &str,
+                _key_source: &KeySource,
+                secp: &SecpCtx,
+            ) -> Result<MessageSignature, SignerError> {
+                let key_source = signer::single_key_source(&self.0.public_key(secp));
+                self.0.sign_message(message, &key_source, secp)
+            }
+        }
+
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let wrong_key = PrivateKey {
+            compressed: true,
+            network: Network::Regtest,
+            key: SecretKey::from_slice(&[0x01; 32]).unwrap(),
+        };
+        let wrong_id = SignerId::from(wrong_key.public_key(wallet.secp_ctx()).to_pubkeyhash());
+        wallet.add_signer(
+            KeychainKind::External,
+            wrong_id,
+            SignerOrdering(0),
+            Arc::new(WrongKeySigner(wrong_key)),
         );
 
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(50.0), @add_signature);
+        let signature = wallet
+            .sign_message(KeychainKind::External, 0, "Hello World")
+            .unwrap();
+        assert_eq!(
+            signature.public_key.to_string(),
+            "032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3"
+        );
+        assert!(signature.verify("Hello World", wallet.secp_ctx()));
     }
 
     #[test]
-    fn test_bump_fee_add_input_change_dust() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        wallet.database.borrow_mut().received_tx(
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
+    fn test_sign_message_watch_only() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)");
+        let result = wallet.sign_message(KeychainKind::External, 0, "Hello World");
+
+        assert!(matches!(
+            result,
+            Err(Error::Signer(SignerError::MissingKey))
+        ));
+    }
 
+    #[test]
+    fn test_remove_signer() {
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let (psbt, mut original_details) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 45_000)]).enable_rbf(),
-            )
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
-        let mut tx = psbt.extract_tx();
-        assert_eq!(tx.input.len(), 1);
-        assert_eq!(tx.output.len(), 2);
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
+
+        let entries = wallet.get_signers(KeychainKind::External).entries();
+        assert_eq!(entries.len(), 1);
+        let (id, ordering, signer) = entries[0];
+        assert_eq!(ordering, &SignerOrdering::default());
+        assert!(!signer.requires_interaction());
+        let id = id.clone();
+
+        assert_eq!(wallet.remove_signer(KeychainKind::External, id).len(), 1);
+        assert!(wallet.get_signers(KeychainKind::External).ids().is_empty());
+
+        let (_, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, false);
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordProgress(std::rc::Rc<RefCell<Vec<f32>>>);
+    impl SignerProgress for RecordProgress {
+        fn update(&self, progress: f32, _message: Option<String>) -> Result<(), SignerError> {
+            self.0.borrow_mut().push(progress);
+            Ok(())
         }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
+    }
+
+    #[test]
+    fn test_sign_with_progress() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
 
-        let (psbt, details) = wallet
-            .bump_fee(
-                &txid,
-                TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(140.0)),
-            )
+        let progress = RecordProgress::default();
+        let (_, finalized) = wallet
+            .sign_with_progress(psbt, SignOptions::default(), progress.clone())
             .unwrap();
+        assert_eq!(finalized, true);
+        assert_eq!(*progress.0.borrow(), vec![0.0, 100.0]);
+    }
 
-        assert_eq!(original_details.received, 5_000 - original_details.fees);
+    #[test]
+    fn test_sign_with_progress_cancel() {
+        struct CancelProgress;
+        impl SignerProgress for CancelProgress {
+            fn update(&self, _progress: f32, _message: Option<String>) -> Result<(), SignerError> {
+                Err(SignerError::UserCanceled)
+            }
+        }
 
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fees, 30_000);
-        assert_eq!(details.received, 0);
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
+            .unwrap();
+
+        let result = wallet.sign_with_progress(psbt, SignOptions::default(), CancelProgress);
+        assert!(matches!(
+            result,
+            Err(Error::Signer(SignerError::UserCanceled))
+        ));
+    }
+
+    #[test]
+    fn test_sortedmulti_key_order() {
+        let wallet_ab: OfflineWallet<_> = Wallet::new_offline(
+            "wsh(sortedmulti(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu))",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let wallet_ba: OfflineWallet<_> = Wallet::new_offline(
+            "wsh(sortedmulti(2,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW))",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 1);
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            45_000
+            wallet_ab.get_new_address().unwrap(),
+            wallet_ba.get_new_address().unwrap()
         );
-
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(140.0), @dust_change, @add_signature);
     }
 
     #[test]
-    fn test_bump_fee_force_add_input() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let incoming_txid = wallet.database.borrow_mut().received_tx(
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
-        );
-
-        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let (psbt, mut original_details) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 45_000)]).enable_rbf(),
-            )
-            .unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
+    fn test_sign_sortedmulti() {
+        for descriptor in &[
+            "sh(sortedmulti(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu))",
+            "wsh(sortedmulti(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu))",
+            "sh(wsh(sortedmulti(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))",
+        ] {
+            let (wallet, _, _) = get_funded_wallet(descriptor);
+            let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+            let (psbt, _) = wallet
+                .create_tx(
+                    TxBuilder::new()
+                        .set_single_recipient(addr.script_pubkey())
+                        .drain_wallet(),
+                )
                 .unwrap();
+
+            let (_, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+            assert_eq!(finalized, true, "failed to finalize {}", descriptor);
         }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
-            .unwrap();
+    }
 
-        // the new fee_rate is low enough that just reducing the change would be fine, but we force
-        // the addition of an extra input with `add_utxo()`
-        let (psbt, details) = wallet
-            .bump_fee(
-                &txid,
-                TxBuilder::new()
-                    .add_utxo(OutPoint {
-                        txid: incoming_txid,
-                        vout: 0,
-                    })
-                    .fee_rate(FeeRate::from_sat_per_vb(5.0)),
-            )
+    #[test]
+    fn test_create_tx_sh_wsh_sortedmulti_redeem_script() {
+        let (wallet, _, _) = get_funded_wallet("sh(wsh(sortedmulti(2,cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW,cRjo6jqfVNP33HhSS76UhXETZsGTZYx8FMFvR9kpbtCSV1PmdZdu)))");
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
 
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fees + details.received, 30_000);
-
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            45_000
-        );
+        let witness_script = psbt.inputs[0].witness_script.clone().unwrap();
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
+            psbt.inputs[0].redeem_script,
+            Some(witness_script.to_v0_p2wsh())
         );
-
-        assert_fee_rate!(psbt.extract_tx(), details.fees, FeeRate::from_sat_per_vb(5.0), @add_signature);
     }
 
     #[test]
-    fn test_bump_fee_absolute_force_add_input() {
-        let (wallet, descriptors, _) = get_funded_wallet(get_test_wpkh());
-        let incoming_txid = wallet.database.borrow_mut().received_tx(
-            testutils! (@tx ( (@external descriptors, 0) => 25_000 ) (@confirmations 1)),
-            Some(100),
+    fn test_new_offline_multipath_descriptor() {
+        let wallet: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<0;1>/*)",
+            None,
+            Network::Testnet,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let split_wallet: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/0/*)",
+            Some("wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)"),
+            Network::Testnet,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+
+        assert_eq!(wallet.descriptor, split_wallet.descriptor);
+        assert_eq!(wallet.change_descriptor, split_wallet.change_descriptor);
+
+        let result: Result<OfflineWallet<_>, _> = Wallet::new_offline(
+            "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/<0;1>/*)",
+            Some("wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/1/*)"),
+            Network::Testnet,
+            MemoryDatabase::new(),
         );
+        assert!(matches!(result, Err(Error::Generic(_))));
+    }
 
+    #[test]
+    fn test_verify_psbt_outputs() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
         let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
-        let (psbt, mut original_details) = wallet
-            .create_tx(
-                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 45_000)]).enable_rbf(),
-            )
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
-        let mut tx = psbt.extract_tx();
-        let txid = tx.txid();
-        // skip saving the new utxos, we know they can't be used anyways
-        for txin in &mut tx.input {
-            txin.witness.push([0x00; 108].to_vec()); // fake signature
-            wallet
-                .database
-                .borrow_mut()
-                .del_utxo(&txin.previous_output)
-                .unwrap();
-        }
-        original_details.transaction = Some(tx);
-        wallet
-            .database
-            .borrow_mut()
-            .set_tx(&original_details)
+
+        let ownership = wallet.verify_psbt_outputs(&psbt).unwrap();
+        let recipient_index = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == addr.script_pubkey())
             .unwrap();
+        assert_eq!(ownership[recipient_index], OutputOwnership::External);
+        assert!(matches!(
+            ownership[1 - recipient_index],
+            OutputOwnership::Mine {
+                keychain: KeychainKind::External,
+                ..
+            }
+        ));
+    }
 
-        // the new fee_rate is low enough that just reducing the change would be fine, but we force
-        // the addition of an extra input with `add_utxo()`
-        let (psbt, details) = wallet
-            .bump_fee(
-                &txid,
-                TxBuilder::new()
-                    .add_utxo(OutPoint {
-                        txid: incoming_txid,
-                        vout: 0,
-                    })
-                    .fee_absolute(250),
-            )
+    #[test]
+    fn test_sign_disguised_change_output() {
+        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (mut psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
 
-        assert_eq!(details.sent, original_details.sent + 25_000);
-        assert_eq!(details.fees + details.received, 30_000);
+        // make the recipient look like change by copying the keypaths of the real change output
+        let recipient_index = psbt
+            .global
+            .unsigned_tx
+            .output
+            .iter()
+            .position(|txout| txout.script_pubkey == addr.script_pubkey())
+            .unwrap();
+        psbt.outputs[recipient_index].hd_keypaths =
+            psbt.outputs[1 - recipient_index].hd_keypaths.clone();
 
-        let tx = &psbt.global.unsigned_tx;
-        assert_eq!(tx.input.len(), 2);
-        assert_eq!(tx.output.len(), 2);
-        assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey == addr.script_pubkey())
-                .unwrap()
-                .value,
-            45_000
-        );
         assert_eq!(
-            tx.output
-                .iter()
-                .find(|txout| txout.script_pubkey != addr.script_pubkey())
-                .unwrap()
-                .value,
-            details.received
+            wallet.verify_psbt_outputs(&psbt).unwrap()[recipient_index],
+            OutputOwnership::InvalidDerivation
         );
-
-        assert_eq!(details.fees, 250);
+        assert!(matches!(
+            wallet.sign(psbt, SignOptions::default()),
+            Err(Error::Signer(SignerError::InvalidChangeOutput(n))) if n == recipient_index
+        ));
     }
 
+    #[cfg(feature = "async-interface")]
     #[test]
-    fn test_sign_single_xprv() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-        let addr = wallet.get_new_address().unwrap();
+    fn test_sign_async() {
+        use miniscript::MiniscriptKey;
+
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
-            )
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
 
-        let (signed_psbt, finalized) = wallet.sign(psbt, None).unwrap();
-        assert_eq!(finalized, true);
+        // a watch-only wallet with the key of the signer added as an async signer
+        let mut coordinator: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3)",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let key =
+            bitcoin::PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")
+                .unwrap();
+        coordinator.add_async_signer(
+            KeychainKind::External,
+            SignerId::from(key.public_key(&wallet.secp).to_pubkeyhash()),
+            SignerOrdering::default(),
+            Arc::new(key),
+        );
 
-        let extracted = signed_psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        // the blocking version ignores async signers
+        let (_, finalized) = coordinator
+            .sign(psbt.clone(), SignOptions::default())
+            .unwrap();
+        assert_eq!(finalized, false);
+
+        let (signed_psbt, finalized) =
+            futures::executor::block_on(coordinator.sign_async(psbt, SignOptions::default()))
+                .unwrap();
+        assert_eq!(finalized, true);
+        assert!(signed_psbt.inputs[0].final_script_witness.is_some());
     }
 
+    #[cfg(feature = "async-interface")]
     #[test]
-    fn test_sign_single_xprv_bip44_path() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/44'/0'/0'/0/*)");
-        let addr = wallet.get_new_address().unwrap();
+    fn test_sign_async_with_progress() {
+        use miniscript::MiniscriptKey;
+
+        let (mut wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, _) = wallet
-            .create_tx(
-                TxBuilder::new()
-                    .set_single_recipient(addr.script_pubkey())
-                    .drain_wallet(),
-            )
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                25_000,
+            )]))
             .unwrap();
 
-        let (signed_psbt, finalized) = wallet.sign(psbt, None).unwrap();
+        let key =
+            bitcoin::PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")
+                .unwrap();
+        let id = SignerId::from(key.public_key(&wallet.secp).to_pubkeyhash());
+        wallet.add_async_signer(
+            KeychainKind::External,
+            id.clone(),
+            SignerOrdering(200),
+            Arc::new(key),
+        );
+        // the async signer is listed with the blocking one
+        assert_eq!(wallet.get_signers(KeychainKind::External).ids().len(), 2);
+
+        let progress = RecordProgress::default();
+        let (_, finalized) = futures::executor::block_on(wallet.sign_async_with_progress(
+            psbt,
+            SignOptions::default(),
+            progress.clone(),
+        ))
+        .unwrap();
         assert_eq!(finalized, true);
+        assert_eq!(*progress.0.borrow(), vec![0.0, 50.0, 100.0]);
 
-        let extracted = signed_psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        let removed = wallet.remove_signer(KeychainKind::External, id);
+        assert_eq!(removed.len(), 2);
+        assert!(removed[0].as_blocking().is_some());
+        assert!(removed[1].as_blocking().is_none());
     }
 
     #[test]
-    fn test_sign_single_xprv_sh_wpkh() {
-        let (wallet, _, _) = get_funded_wallet("sh(wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*))");
-        let addr = wallet.get_new_address().unwrap();
+    fn test_sign_grinding() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, _) = wallet
             .create_tx(
                 TxBuilder::new()
@@ -3308,18 +8317,23 @@ mod test {
             )
             .unwrap();
 
-        let (signed_psbt, finalized) = wallet.sign(psbt, None).unwrap();
-        assert_eq!(finalized, true);
+        let (signed_psbt, _) = wallet.sign(psbt.clone(), SignOptions::default()).unwrap();
+        // DER signature plus the sighash byte
+        assert!(signed_psbt.inputs[0].final_script_witness.as_ref().unwrap()[0].len() <= 71);
 
-        let extracted = signed_psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        let sign_options = SignOptions {
+            allow_grinding: false,
+            ..Default::default()
+        };
+        let (signed_psbt, finalized) = wallet.sign(psbt, sign_options).unwrap();
+        assert_eq!(finalized, true);
+        assert!(signed_psbt.inputs[0].final_script_witness.as_ref().unwrap()[0].len() <= 72);
     }
 
     #[test]
-    fn test_sign_single_wif() {
-        let (wallet, _, _) =
-            get_funded_wallet("wpkh(cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW)");
-        let addr = wallet.get_new_address().unwrap();
+    fn test_finalize_psbt_without_signers() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (psbt, _) = wallet
             .create_tx(
                 TxBuilder::new()
@@ -3327,18 +8341,31 @@ mod test {
                     .drain_wallet(),
             )
             .unwrap();
+        let sign_options = SignOptions {
+            try_finalize: false,
+            ..Default::default()
+        };
+        let (mut psbt, _) = wallet.sign(psbt, sign_options).unwrap();
 
-        let (signed_psbt, finalized) = wallet.sign(psbt, None).unwrap();
+        // a watch-only wallet with the public key of the signer
+        let coordinator: OfflineWallet<_> = Wallet::new_offline(
+            "wpkh(032b0558078bec38694a84933d659303e2575dae7e91685911454115bfd64487e3)",
+            None,
+            Network::Regtest,
+            MemoryDatabase::new(),
+        )
+        .unwrap();
+        let finalized = coordinator
+            .finalize_psbt(&mut psbt, SignOptions::default())
+            .unwrap();
         assert_eq!(finalized, true);
-
-        let extracted = signed_psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        assert!(psbt.inputs[0].final_script_witness.is_some());
     }
 
     #[test]
-    fn test_sign_single_xprv_no_hd_keypaths() {
-        let (wallet, _, _) = get_funded_wallet("wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)");
-        let addr = wallet.get_new_address().unwrap();
+    fn test_finalize_psbt_missing_signatures() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
         let (mut psbt, _) = wallet
             .create_tx(
                 TxBuilder::new()
@@ -3347,14 +8374,11 @@ mod test {
             )
             .unwrap();
 
-        psbt.inputs[0].hd_keypaths.clear();
-        assert_eq!(psbt.inputs[0].hd_keypaths.len(), 0);
-
-        let (signed_psbt, finalized) = wallet.sign(psbt, None).unwrap();
-        assert_eq!(finalized, true);
-
-        let extracted = signed_psbt.extract_tx();
-        assert_eq!(extracted.input[0].witness.len(), 2);
+        let finalized = wallet
+            .finalize_psbt(&mut psbt, SignOptions::default())
+            .unwrap();
+        assert_eq!(finalized, false);
+        assert!(psbt.inputs[0].final_script_witness.is_none());
     }
 
     #[test]
@@ -3398,7 +8422,7 @@ mod test {
         });
         psbt.inputs.push(dud_input);
         psbt.global.unsigned_tx.input.push(bitcoin::TxIn::default());
-        let (psbt, is_final) = wallet.sign(psbt, None).unwrap();
+        let (psbt, is_final) = wallet.sign(psbt, SignOptions::default()).unwrap();
         assert!(
             !is_final,
             "shouldn't be final since we can't sign one of the inputs"
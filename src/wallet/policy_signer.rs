@@ -0,0 +1,638 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Policy-enforcing signer
+//!
+//! This module provides [`PolicySigner`], a [`Signer`] that wraps another signer and only lets
+//! it sign the PSBTs that respect a set of spending rules: a maximum amount per transaction, a
+//! list of allowed recipients, a minimum fee and a maximum amount spent over a period of time.
+//! It can be used to enforce organizational spending controls at the signing layer.
+//!
+//! Messages can only be signed if [`PolicySigner::allow_message_signing`] is called, since a
+//! signed message can't be checked against the rules.
+//!
+//! The amounts are computed on the outputs that don't belong to the descriptors registered with
+//! [`PolicySigner::own_descriptor`]: the ownership of an output is verified by deriving its
+//! script from the descriptor, so it can't be faked by a malicious PSBT.
+//!
+//! The wallet shouldn't have the private keys of the wrapped signer in its descriptor, otherwise
+//! its own signers would sign the transaction regardless of the rules. For the same reason the
+//! [`PolicySigner`] never exposes the secret key of the inner signer.
+//!
+//! ## Example
+//!
+//! ```
+//! # use std::sync::Arc;
+//! # use bitcoin::{Network, PrivateKey};
+//! # use bdk::database::MemoryDatabase;
+//! # use bdk::miniscript::MiniscriptKey;
+//! # use bdk::signer::{SignerId, SignerOrdering};
+//! # use bdk::wallet::policy_signer::PolicySigner;
+//! # use bdk::*;
+//! let key = PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")?;
+//! let public_key = key.public_key(&bitcoin::secp256k1::Secp256k1::new());
+//!
+//! let descriptor = format!("wpkh({})", public_key);
+//! let mut wallet: OfflineWallet<_> = Wallet::new_offline(&descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//!
+//! let signer = PolicySigner::new(key)
+//!     .own_descriptor(wallet.public_descriptor(KeychainKind::External)?.unwrap())
+//!     .max_amount(1_000_000)
+//!     .velocity_limit(5_000_000, 24 * 60 * 60);
+//! wallet.add_signer(
+//!     KeychainKind::External,
+//!     SignerId::from(public_key.to_pubkeyhash()),
+//!     SignerOrdering::default(),
+//!     Arc::new(signer),
+//! );
+//! # Ok::<_, Box<dyn std::error::Error>>(())
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Mutex;
+
+use bitcoin::util::bip32::KeySource;
+use bitcoin::util::psbt::{self, PartiallySignedTransaction as PSBT};
+use bitcoin::{Script, TxOut};
+
+use super::signer::{
+    MessageSignature, NoopSignerProgress, SignOptions, Signer, SignerError, SignerProgress,
+};
+use super::time;
+use super::utils::{descriptor_to_pk_ctx, SecpCtx};
+use crate::database::{Database, MemoryDatabase};
+use crate::descriptor::{DescriptorMeta, ExtendedDescriptor};
+use crate::psbt::PSBTUtils;
+use crate::types::TransactionDetails;
+
+/// Maximum amount that can be spent in a period of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VelocityLimit {
+    /// Maximum amount, in satoshi
+    pub max_amount: u64,
+    /// Length of the period, in seconds
+    pub window: u64,
+}
+
+/// Rules enforced by a [`PolicySigner`]
+///
+/// Every rule is disabled by default, and messages can't be signed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpendingRules {
+    /// Maximum amount sent by a single transaction
+    pub max_amount: Option<u64>,
+    /// Scripts that are allowed to receive funds, if restricted
+    pub allowed_scripts: Option<HashSet<Script>>,
+    /// Minimum absolute fee of a transaction
+    pub min_fee: Option<u64>,
+    /// Maximum amount sent in a period of time
+    pub velocity_limit: Option<VelocityLimit>,
+    /// Whether messages can be signed
+    pub sign_messages: bool,
+}
+
+/// Signer that enforces a set of [`SpendingRules`] before delegating to an inner signer
+///
+/// See [the module documentation](self) for an example. The transactions signed are recorded in
+/// a [`Database`] to enforce the [`VelocityLimit`]: [`PolicySigner::new`] uses a
+/// [`MemoryDatabase`], while [`PolicySigner::with_database`] allows keeping the history across
+/// restarts.
+pub struct PolicySigner<S, D = MemoryDatabase> {
+    inner: S,
+    rules: SpendingRules,
+    own_descriptors: Vec<ExtendedDescriptor>,
+    history: Mutex<D>,
+}
+
+impl<S: Signer> PolicySigner<S, MemoryDatabase> {
+    /// Wrap `inner`, keeping the history of the signed transactions in memory
+    pub fn new(inner: S) -> Self {
+        PolicySigner::with_database(inner, MemoryDatabase::new())
+    }
+}
+
+impl<S: Signer, D: Database + Send> PolicySigner<S, D> {
+    /// Wrap `inner`, keeping the history of the signed transactions in `database`
+    ///
+    /// The database should be dedicated to the signer and not shared with a wallet.
+    pub fn with_database(inner: S, database: D) -> Self {
+        PolicySigner {
+            inner,
+            rules: SpendingRules::default(),
+            own_descriptors: vec![],
+            history: Mutex::new(database),
+        }
+    }
+
+    /// Replace all the rules
+    pub fn rules(mut self, rules: SpendingRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Consider the outputs of `descriptor` as change, which doesn't count towards the limits
+    pub fn own_descriptor(mut self, descriptor: ExtendedDescriptor) -> Self {
+        self.own_descriptors.push(descriptor);
+        self
+    }
+
+    /// Limit the amount sent by a single transaction
+    pub fn max_amount(mut self, max_amount: u64) -> Self {
+        self.rules.max_amount = Some(max_amount);
+        self
+    }
+
+    /// Allow sending funds to `script`
+    ///
+    /// Once a script has been allowed, transactions that send funds to any other script are
+    /// refused.
+    pub fn allow_script(mut self, script: Script) -> Self {
+        self.rules
+            .allowed_scripts
+            .get_or_insert_with(HashSet::new)
+            .insert(script);
+        self
+    }
+
+    /// Require transactions to pay at least `min_fee` satoshi in fees
+    pub fn min_fee(mut self, min_fee: u64) -> Self {
+        self.rules.min_fee = Some(min_fee);
+        self
+    }
+
+    /// Limit the amount sent in `window` seconds
+    pub fn velocity_limit(mut self, max_amount: u64, window: u64) -> Self {
+        self.rules.velocity_limit = Some(VelocityLimit { max_amount, window });
+        self
+    }
+
+    /// Allow signing messages with the inner signer
+    pub fn allow_message_signing(mut self) -> Self {
+        self.rules.sign_messages = true;
+        self
+    }
+
+    /// Return the rules enforced by the signer
+    pub fn get_rules(&self) -> &SpendingRules {
+        &self.rules
+    }
+
+    fn is_own_output(&self, psbt_output: &psbt::Output, txout: &TxOut, secp: &SecpCtx) -> bool {
+        let deriv_ctx = descriptor_to_pk_ctx(secp);
+        self.own_descriptors.iter().any(|descriptor| {
+            let derived = if descriptor.is_fixed() {
+                Some(descriptor.clone())
+            } else {
+                descriptor.derive_from_hd_keypaths(&psbt_output.hd_keypaths, secp)
+            };
+
+            derived
+                .map(|derived| derived.script_pubkey(deriv_ctx) == txout.script_pubkey)
+                .unwrap_or(false)
+        })
+    }
+
+    // Check the rules that don't depend on the history, returning the amount sent and the fees
+    // of the transaction
+    fn check_rules(&self, psbt: &PSBT, secp: &SecpCtx) -> Result<(u64, u64), SignerError> {
+        let tx = &psbt.global.unsigned_tx;
+        if psbt.outputs.len() != tx.output.len() {
+            return Err(SignerError::PolicyViolation(
+                "The PSBT doesn't have an entry for every output".into(),
+            ));
+        }
+
+        let mut amount = 0;
+        for (psbt_output, txout) in psbt.outputs.iter().zip(tx.output.iter()) {
+            if self.is_own_output(psbt_output, txout, secp) {
+                continue;
+            }
+
+            if let Some(allowed_scripts) = &self.rules.allowed_scripts {
+                if !allowed_scripts.contains(&txout.script_pubkey) {
+                    return Err(SignerError::PolicyViolation(format!(
+                        "The script `{}` is not allowed to receive funds",
+                        txout.script_pubkey
+                    )));
+                }
+            }
+            amount += txout.value;
+        }
+
+        if let Some(max_amount) = self.rules.max_amount {
+            if amount > max_amount {
+                return Err(SignerError::PolicyViolation(format!(
+                    "The transaction sends {} sat, above the limit of {} sat",
+                    amount, max_amount
+                )));
+            }
+        }
+
+        let input_value = (0..tx.input.len())
+            .map(|n| psbt.get_utxo_for(n).map(|txout| txout.value))
+            .try_fold(0u64, |sum, value| sum.checked_add(value?));
+        let output_value = tx.output.iter().map(|txout| txout.value).sum::<u64>();
+        let fees = match input_value {
+            Some(input_value) => input_value.saturating_sub(output_value),
+            None if self.rules.min_fee.is_some() => {
+                return Err(SignerError::PolicyViolation(
+                    "The fees can't be computed without the previous outputs of every input".into(),
+                ))
+            }
+            None => 0,
+        };
+
+        if let Some(min_fee) = self.rules.min_fee {
+            if fees < min_fee {
+                return Err(SignerError::PolicyViolation(format!(
+                    "The transaction pays {} sat in fees, below the minimum of {} sat",
+                    fees, min_fee
+                )));
+            }
+        }
+
+        Ok((amount, fees))
+    }
+
+    // Check the velocity limit against the history. The caller must keep `history` locked until
+    // the transaction is recorded, so that concurrent signings can't exceed the limit together
+    fn check_velocity_limit(
+        &self,
+        history: &D,
+        psbt: &PSBT,
+        amount: u64,
+    ) -> Result<(), SignerError> {
+        let VelocityLimit { max_amount, window } = match self.rules.velocity_limit {
+            Some(velocity_limit) => velocity_limit,
+            None => return Ok(()),
+        };
+
+        let since = time::get_timestamp().saturating_sub(window);
+        let txid = psbt.global.unsigned_tx.txid();
+        let spent = history
+            .iter_txs(false)
+            .map_err(|e| SignerError::PolicyHistory(format!("Can't read the history: {}", e)))?
+            .into_iter()
+            .filter(|details| details.timestamp >= since && details.txid != txid)
+            .map(|details| details.sent)
+            .sum::<u64>();
+
+        if spent.saturating_add(amount) > max_amount {
+            return Err(SignerError::PolicyViolation(format!(
+                "The transaction sends {} sat, but only {} sat can be spent in {} seconds",
+                amount,
+                max_amount.saturating_sub(spent),
+                window
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn count_signatures(psbt: &PSBT) -> usize {
+    psbt.inputs
+        .iter()
+        .map(|input| input.partial_sigs.len())
+        .sum()
+}
+
+impl<S: Signer, D: Database + Send> Signer for PolicySigner<S, D> {
+    fn sign(
+        &self,
+        psbt: &mut PSBT,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        self.sign_with_progress(psbt, input_index, sign_options, &NoopSignerProgress, secp)
+    }
+
+    fn sign_with_progress(
+        &self,
+        psbt: &mut PSBT,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        progress: &dyn SignerProgress,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        let (amount, fees) = self.check_rules(psbt, secp)?;
+
+        // the history stays locked from the check of the velocity limit until the transaction
+        // is recorded
+        let mut history = self.history.lock().unwrap();
+        self.check_velocity_limit(&history, psbt, amount)?;
+
+        let signatures = count_signatures(psbt);
+        self.inner
+            .sign_with_progress(psbt, input_index, sign_options, progress, secp)?;
+        if count_signatures(psbt) == signatures {
+            // the inner signer didn't sign anything, nothing has been spent
+            return Ok(());
+        }
+
+        // signers that work on individual inputs are called multiple times for the same
+        // transaction, but since the record is keyed by txid it's only counted once
+        let details = TransactionDetails {
+            txid: psbt.global.unsigned_tx.txid(),
+            timestamp: time::get_timestamp(),
+            sent: amount,
+            fees,
+            ..Default::default()
+        };
+        history
+            .set_tx(&details)
+            .map_err(|e| SignerError::PolicyHistory(format!("Can't update the history: {}", e)))
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        self.inner.sign_whole_tx()
+    }
+
+    fn requires_interaction(&self) -> bool {
+        self.inner.requires_interaction()
+    }
+
+    fn sign_message(
+        &self,
+        message: &str,
+        key_source: &KeySource,
+        secp: &SecpCtx,
+    ) -> Result<MessageSignature, SignerError> {
+        if !self.rules.sign_messages {
+            return Err(SignerError::PolicyViolation(
+                "The signer is not allowed to sign messages".into(),
+            ));
+        }
+
+        self.inner.sign_message(message, key_source, secp)
+    }
+}
+
+impl<S: fmt::Debug, D> fmt::Debug for PolicySigner<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicySigner")
+            .field("inner", &self.inner)
+            .field("rules", &self.rules)
+            .field("own_descriptors", &self.own_descriptors)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{Address, PrivateKey};
+
+    use miniscript::MiniscriptKey;
+
+    use super::*;
+    use crate::database::memory::MemoryDatabase;
+    use crate::error::Error;
+    use crate::signer::{SignerId, SignerOrdering};
+    use crate::types::KeychainKind;
+    use crate::wallet::test::get_funded_wallet;
+    use crate::wallet::OfflineWallet;
+    use crate::TxBuilder;
+
+    const WIF: &str = "cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW";
+
+    fn get_wallet<F>(configure: F) -> OfflineWallet<MemoryDatabase>
+    where
+        F: FnOnce(PolicySigner<PrivateKey>) -> PolicySigner<PrivateKey>,
+    {
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let public_key = key.public_key(&Secp256k1::new());
+        let (mut wallet, _, _) = get_funded_wallet(&format!("wpkh({})", public_key));
+
+        let signer = configure(
+            PolicySigner::new(key).own_descriptor(
+                wallet
+                    .public_descriptor(KeychainKind::External)
+                    .unwrap()
+                    .unwrap(),
+            ),
+        );
+        wallet.add_signer(
+            KeychainKind::External,
+            SignerId::from(public_key.to_pubkeyhash()),
+            SignerOrdering::default(),
+            Arc::new(signer),
+        );
+
+        wallet
+    }
+
+    fn sign_payment(
+        wallet: &OfflineWallet<MemoryDatabase>,
+        address: &str,
+        amount: u64,
+    ) -> Result<bool, Error> {
+        let addr = Address::from_str(address).unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                amount,
+            )]))
+            .unwrap();
+
+        wallet
+            .sign(psbt, SignOptions::default())
+            .map(|(_, finalized)| finalized)
+    }
+
+    fn is_violation(result: Result<bool, Error>) -> bool {
+        matches!(result, Err(Error::Signer(SignerError::PolicyViolation(_))))
+    }
+
+    #[test]
+    fn test_policy_signer_max_amount() {
+        let wallet = get_wallet(|signer| signer.max_amount(20_000));
+
+        assert!(is_violation(sign_payment(
+            &wallet,
+            "2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX",
+            25_000
+        )));
+        assert_eq!(
+            sign_payment(&wallet, "2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX", 20_000).unwrap(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_policy_signer_allowed_scripts() {
+        let allowed = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let wallet = get_wallet(|signer| signer.allow_script(allowed.script_pubkey()));
+
+        assert!(is_violation(sign_payment(
+            &wallet,
+            "2N4eQYCbKUHCCTUjBJeHcJp9ok6J2GZsTDt",
+            25_000
+        )));
+        // the change is not restricted
+        assert_eq!(
+            sign_payment(&wallet, "2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX", 25_000).unwrap(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_policy_signer_min_fee() {
+        let wallet = get_wallet(|signer| signer.min_fee(10_000));
+
+        assert!(is_violation(sign_payment(
+            &wallet,
+            "2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX",
+            25_000
+        )));
+    }
+
+    #[test]
+    fn test_policy_signer_velocity_limit() {
+        let wallet = get_wallet(|signer| signer.velocity_limit(30_000, 60 * 60));
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                20_000,
+            )]))
+            .unwrap();
+
+        let (_, finalized) = wallet.sign(psbt.clone(), SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+        // signing the same transaction again doesn't count twice
+        let (_, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert_eq!(finalized, true);
+
+        assert!(is_violation(sign_payment(
+            &wallet,
+            "2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX",
+            15_000
+        )));
+        assert_eq!(
+            sign_payment(&wallet, "2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX", 10_000).unwrap(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_policy_signer_min_fee_non_witness_utxo() {
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let public_key = key.public_key(&Secp256k1::new());
+        let (wallet, _, _) = get_funded_wallet(&format!("wpkh({})", public_key));
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (mut psbt, details) = wallet
+            .create_tx(
+                TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                    .force_non_witness_utxo(),
+            )
+            .unwrap();
+        // a fake `witness_utxo` can't make the fees look higher
+        psbt.inputs[0].witness_utxo.as_mut().unwrap().value += 10_000;
+
+        let signer = PolicySigner::new(key).min_fee(details.fees + 1);
+        let result = signer.sign(
+            &mut psbt,
+            Some(0),
+            &SignOptions::default(),
+            wallet.secp_ctx(),
+        );
+        assert!(matches!(result, Err(SignerError::PolicyViolation(_))));
+    }
+
+    #[test]
+    fn test_policy_signer_velocity_limit_only_records_signatures() {
+        let key = PrivateKey::from_wif(WIF).unwrap();
+        let public_key = key.public_key(&Secp256k1::new());
+        let (wallet, _, _) = get_funded_wallet(&format!("wpkh({})", public_key));
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+        let (mut psbt, _) = wallet
+            .create_tx(TxBuilder::with_recipients(vec![(
+                addr.script_pubkey(),
+                20_000,
+            )]))
+            .unwrap();
+
+        let signer = PolicySigner::new(key)
+            .own_descriptor(
+                wallet
+                    .public_descriptor(KeychainKind::External)
+                    .unwrap()
+                    .unwrap(),
+            )
+            .velocity_limit(30_000, 60 * 60);
+        let recorded = |signer: &PolicySigner<PrivateKey>| {
+            signer
+                .history
+                .lock()
+                .unwrap()
+                .iter_txs(false)
+                .unwrap()
+                .len()
+        };
+
+        // the input is already finalized, so nothing is signed
+        psbt.inputs[0].final_script_witness = Some(vec![]);
+        signer
+            .sign(
+                &mut psbt,
+                Some(0),
+                &SignOptions::default(),
+                wallet.secp_ctx(),
+            )
+            .unwrap();
+        assert_eq!(recorded(&signer), 0);
+
+        psbt.inputs[0].final_script_witness = None;
+        signer
+            .sign(
+                &mut psbt,
+                Some(0),
+                &SignOptions::default(),
+                wallet.secp_ctx(),
+            )
+            .unwrap();
+        assert_eq!(recorded(&signer), 1);
+    }
+
+    #[test]
+    fn test_policy_signer_sign_message() {
+        let wallet = get_wallet(|signer| signer);
+        assert!(matches!(
+            wallet.sign_message(KeychainKind::External, 0, "Hello World"),
+            Err(Error::Signer(SignerError::PolicyViolation(_)))
+        ));
+
+        let wallet = get_wallet(|signer| signer.allow_message_signing());
+        let signature = wallet
+            .sign_message(KeychainKind::External, 0, "Hello World")
+            .unwrap();
+        assert!(signature.verify("Hello World", wallet.secp_ctx()));
+    }
+}
@@ -0,0 +1,450 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Proof of reserves
+//!
+//! This module implements a way for a wallet to prove that it controls a certain amount of coins,
+//! following the format described in [BIP-127](https://github.com/bitcoin/bips/blob/master/bip-0127.mediawiki).
+//!
+//! A proof is a [`PSBT`] that spends every UTXO of the wallet, plus an extra "challenge" input that
+//! commits to a message chosen by whoever requested the proof. The challenge input points to a
+//! transaction that doesn't exist, which means that the proof can be signed but can never be
+//! broadcast. The only output sends the total amount to a script that can't be spent.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::*;
+//! # use bdk::signer::SignOptions;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! let message = "Proof of reserves requested by the auditor on 2020-11-01";
+//!
+//! let psbt = wallet.create_proof(message)?;
+//! let (psbt, finalized) = wallet.sign(psbt, SignOptions::default())?;
+//! assert!(finalized);
+//!
+//! // this can be done by anyone who has a watch-only version of the wallet
+//! let amount = wallet.verify_proof(&psbt, message, None)?;
+//! println!("The wallet controls {} satoshi", amount);
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::fmt;
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::{hash160, sha256d, Hash};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::util::psbt::PartiallySignedTransaction as PSBT;
+use bitcoin::{OutPoint, PublicKey, Script, SigHashType, Transaction, TxIn, TxOut, Txid};
+
+use miniscript::interpreter::Interpreter;
+use miniscript::BitcoinSig;
+
+use super::coin_selection::DefaultCoinSelectionAlgorithm;
+use super::tx_builder::{CreateTx, TxBuilder};
+use super::Wallet;
+use crate::blockchain::BlockchainMarker;
+use crate::database::BatchDatabase;
+use crate::error::Error;
+
+/// Prefix prepended to the message before hashing it into the challenge input
+pub const PROOF_MESSAGE_PREFIX: &str = "Proof-of-Reserves: ";
+
+/// Errors thrown while creating or verifying a proof of reserves
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ProofError {
+    /// The message committed to by the proof is empty
+    EmptyMessage,
+    /// The wallet doesn't have any UTXO to prove
+    NoUtxos,
+    /// The proof doesn't contain the challenge input and at least one other input
+    WrongNumberOfInputs,
+    /// The proof must contain exactly one output
+    WrongNumberOfOutputs,
+    /// The first input doesn't commit to the expected message
+    ChallengeInputMismatch,
+    /// The only output doesn't pay to the expected unspendable script
+    InvalidOutput,
+    /// The input at this index is not finalized
+    NotFinalized(usize),
+    /// The input at this index spends an output that is unknown or already spent
+    NonSpendableInput(usize),
+    /// The input at this index spends the same output as a previous input
+    DuplicatedInput(usize),
+    /// The input at this index is not signed with `SIGHASH_ALL`
+    UnsupportedSighashType(usize),
+    /// The scripts or signatures of the input at this index are not valid
+    SignatureValidation(usize),
+    /// The value of the output doesn't match the sum of the inputs
+    InAndOutValueNotEqual,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+/// Return the challenge input that commits to `message`
+///
+/// The input spends the first output of a non-existent transaction whose txid is the double-SHA256
+/// of the message prefixed with [`PROOF_MESSAGE_PREFIX`].
+pub fn challenge_txin(message: &str) -> TxIn {
+    let message = format!("{}{}", PROOF_MESSAGE_PREFIX, message);
+    let hash = sha256d::Hash::hash(message.as_bytes());
+
+    TxIn {
+        previous_output: OutPoint::new(Txid::from_hash(hash), 0),
+        script_sig: Script::default(),
+        sequence: 0xFFFFFFFF,
+        witness: vec![],
+    }
+}
+
+// P2PKH of HASH160(0x00): nobody knows a public key that hashes to this value
+fn unspendable_script() -> Script {
+    let hash = hash160::Hash::hash(&[0]);
+
+    Builder::new()
+        .push_opcode(opcodes::all::OP_DUP)
+        .push_opcode(opcodes::all::OP_HASH160)
+        .push_slice(&hash[..])
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+        .into_script()
+}
+
+/// Verify a proof of reserves
+///
+/// `unspents` is the list of outputs that are considered unspent by the verifier, generally
+/// obtained by syncing a watch-only wallet built from the prover's descriptor. Every input of the
+/// proof (except the challenge) must spend a different one of them, and every signature must use
+/// `SIGHASH_ALL`, so that it commits to the challenge and to the whole proof.
+///
+/// On success, return the total amount of satoshi proven.
+pub fn verify_proof(
+    psbt: &PSBT,
+    message: &str,
+    unspents: &[(OutPoint, TxOut)],
+) -> Result<u64, ProofError> {
+    let tx = &psbt.global.unsigned_tx;
+
+    if message.is_empty() {
+        return Err(ProofError::EmptyMessage);
+    }
+    if tx.input.len() < 2 || psbt.inputs.len() != tx.input.len() {
+        return Err(ProofError::WrongNumberOfInputs);
+    }
+    if tx.output.len() != 1 {
+        return Err(ProofError::WrongNumberOfOutputs);
+    }
+    if tx.input[0].previous_output != challenge_txin(message).previous_output {
+        return Err(ProofError::ChallengeInputMismatch);
+    }
+    if tx.output[0].script_pubkey != unspendable_script() {
+        return Err(ProofError::InvalidOutput);
+    }
+
+    // check this before the signatures, since duplicating an input invalidates all of them
+    let mut spent = HashSet::new();
+    if let Some(index) = tx
+        .input
+        .iter()
+        .position(|txin| !spent.insert(txin.previous_output))
+    {
+        return Err(ProofError::DuplicatedInput(index));
+    }
+
+    let secp = Secp256k1::verification_only();
+    let mut sum = 0;
+    for (index, (txin, psbt_input)) in tx.input.iter().zip(psbt.inputs.iter()).enumerate().skip(1) {
+        match psbt_input.sighash_type {
+            None | Some(SigHashType::All) => {}
+            Some(_) => return Err(ProofError::UnsupportedSighashType(index)),
+        }
+
+        let prev_out = unspents
+            .iter()
+            .find(|(outpoint, _)| outpoint == &txin.previous_output)
+            .map(|(_, txout)| txout)
+            .ok_or(ProofError::NonSpendableInput(index))?;
+
+        if psbt_input.final_script_sig.is_none() && psbt_input.final_script_witness.is_none() {
+            return Err(ProofError::NotFinalized(index));
+        }
+        let script_sig = psbt_input.final_script_sig.clone().unwrap_or_default();
+        let witness = psbt_input.final_script_witness.clone().unwrap_or_default();
+
+        let mut interpreter = Interpreter::from_txdata(
+            &prev_out.script_pubkey,
+            &script_sig,
+            &witness,
+            txin.sequence,
+            tx.lock_time,
+        )
+        .map_err(|_| ProofError::SignatureValidation(index))?;
+        let sighash_verify = interpreter.sighash_verify(&secp, tx, index, prev_out.value);
+        let only_sighash_all = Cell::new(true);
+        let verify_sig = |pk: &PublicKey, sig: BitcoinSig| {
+            if sig.1 != SigHashType::All {
+                only_sighash_all.set(false);
+                return false;
+            }
+            sighash_verify(pk, sig)
+        };
+        let invalid = interpreter.iter(verify_sig).any(|res| res.is_err());
+        if !only_sighash_all.get() {
+            return Err(ProofError::UnsupportedSighashType(index));
+        }
+        if invalid {
+            return Err(ProofError::SignatureValidation(index));
+        }
+
+        sum += prev_out.value;
+    }
+
+    if tx.output[0].value != sum {
+        return Err(ProofError::InAndOutValueNotEqual);
+    }
+
+    Ok(sum)
+}
+
+impl<B, D> Wallet<B, D>
+where
+    B: BlockchainMarker,
+    D: BatchDatabase,
+{
+    /// Create a proof of reserves for every UTXO of the wallet, committing to `message`
+    ///
+    /// The returned PSBT has to be signed like any other transaction, with [`Wallet::sign`]. The
+    /// challenge input is already finalized, so signers will skip it.
+    ///
+    /// For a usage example see [this module](crate::wallet::reserves)'s documentation.
+    pub fn create_proof(&self, message: &str) -> Result<PSBT, Error> {
+        if message.is_empty() {
+            return Err(ProofError::EmptyMessage.into());
+        }
+
+        let utxos = self.list_unspent()?;
+        if utxos.is_empty() {
+            return Err(ProofError::NoUtxos.into());
+        }
+
+        let mut input = vec![challenge_txin(message)];
+        input.extend(utxos.iter().map(|utxo| TxIn {
+            previous_output: utxo.outpoint,
+            script_sig: Script::default(),
+            sequence: 0xFFFFFFFF,
+            witness: vec![],
+        }));
+        let tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input,
+            output: vec![TxOut {
+                value: utxos.iter().map(|utxo| utxo.txout.value).sum(),
+                script_pubkey: unspendable_script(),
+            }],
+        };
+
+        let builder = TxBuilder::<D, DefaultCoinSelectionAlgorithm, CreateTx>::new();
+        let mut psbt = self.complete_transaction(tx, utxos, builder)?;
+
+        // the challenge input is spendable by anyone: mark it as already finalized
+        let challenge_input = &mut psbt.inputs[0];
+        challenge_input.witness_utxo = Some(TxOut {
+            value: 0,
+            script_pubkey: Builder::new()
+                .push_opcode(opcodes::all::OP_PUSHNUM_1)
+                .into_script(),
+        });
+        challenge_input.final_script_sig = Some(Script::default());
+        challenge_input.final_script_witness = Some(vec![]);
+
+        Ok(psbt)
+    }
+
+    /// Verify a proof of reserves against the UTXOs of this wallet
+    ///
+    /// If `max_block_height` is specified, only UTXOs confirmed at or below that height are
+    /// considered valid. This allows to check a proof against a snapshot of the chain.
+    ///
+    /// The wallet doesn't need to hold any private key, a watch-only wallet synced with the
+    /// blockchain is enough. On success, return the total amount of satoshi proven.
+    ///
+    /// See [`verify_proof`] for the details of the checks performed.
+    pub fn verify_proof(
+        &self,
+        psbt: &PSBT,
+        message: &str,
+        max_block_height: Option<u32>,
+    ) -> Result<u64, Error> {
+        let mut unspents = Vec::new();
+        for utxo in self.list_unspent()? {
+            if let Some(max_block_height) = max_block_height {
+                let height = self
+                    .database
+                    .borrow()
+                    .get_tx(&utxo.outpoint.txid, false)?
+                    .and_then(|tx| tx.height);
+                match height {
+                    Some(height) if height <= max_block_height => {}
+                    _ => continue,
+                }
+            }
+
+            unspents.push((utxo.outpoint, utxo.txout));
+        }
+
+        Ok(verify_proof(psbt, message, &unspents)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wallet::signer::SignOptions;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+
+    #[test]
+    fn test_proof_of_reserves() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let message = "This belongs to me.";
+
+        let psbt = wallet.create_proof(message).unwrap();
+        assert_eq!(psbt.global.unsigned_tx.input.len(), 2);
+        assert_eq!(
+            psbt.global.unsigned_tx.input[0].previous_output,
+            challenge_txin(message).previous_output
+        );
+        assert_eq!(psbt.global.unsigned_tx.output[0].value, 50_000);
+
+        let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert!(finalized);
+
+        assert_eq!(wallet.verify_proof(&psbt, message, None).unwrap(), 50_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "ChallengeInputMismatch")]
+    fn test_proof_wrong_message() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+
+        let psbt = wallet.create_proof("This belongs to me.").unwrap();
+        let (psbt, _) = wallet.sign(psbt, SignOptions::default()).unwrap();
+
+        wallet
+            .verify_proof(&psbt, "This belongs to someone else.", None)
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "NotFinalized(1)")]
+    fn test_proof_not_signed() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let message = "This belongs to me.";
+
+        let psbt = wallet.create_proof(message).unwrap();
+        wallet.verify_proof(&psbt, message, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "NonSpendableInput(1)")]
+    fn test_proof_max_block_height() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let message = "This belongs to me.";
+
+        let psbt = wallet.create_proof(message).unwrap();
+        let (psbt, _) = wallet.sign(psbt, SignOptions::default()).unwrap();
+
+        // the funding tx is confirmed at height 99
+        wallet.verify_proof(&psbt, message, Some(98)).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "SignatureValidation(1)")]
+    fn test_proof_tampered_output() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let message = "This belongs to me.";
+
+        let psbt = wallet.create_proof(message).unwrap();
+        let (mut psbt, _) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        psbt.global.unsigned_tx.output[0].value = 100_000;
+
+        wallet.verify_proof(&psbt, message, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "DuplicatedInput(2)")]
+    fn test_proof_duplicated_input() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let message = "This belongs to me.";
+
+        let psbt = wallet.create_proof(message).unwrap();
+        let (mut psbt, _) = wallet.sign(psbt, SignOptions::default()).unwrap();
+
+        // spend the same UTXO twice to double the amount proven
+        let txin = psbt.global.unsigned_tx.input[1].clone();
+        let psbt_input = psbt.inputs[1].clone();
+        psbt.global.unsigned_tx.input.push(txin);
+        psbt.inputs.push(psbt_input);
+        psbt.global.unsigned_tx.output[0].value = 100_000;
+
+        wallet.verify_proof(&psbt, message, None).unwrap();
+    }
+
+    #[test]
+    #[should_panic(expected = "UnsupportedSighashType(1)")]
+    fn test_proof_sighash_none() {
+        let (wallet, _, _) = get_funded_wallet(get_test_wpkh());
+        let message = "This belongs to me.";
+
+        let mut psbt = wallet.create_proof(message).unwrap();
+        psbt.inputs[1].sighash_type = Some(SigHashType::None);
+        let (mut psbt, finalized) = wallet
+            .sign(
+                psbt,
+                SignOptions {
+                    allow_all_sighashes: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(finalized);
+
+        // the signature doesn't commit to the output, but the sighash type is checked even
+        // when the PSBT doesn't report it
+        psbt.inputs[1].sighash_type = None;
+        wallet.verify_proof(&psbt, message, None).unwrap();
+    }
+}
@@ -63,6 +63,7 @@
 //!         &self,
 //!         psbt: &mut psbt::PartiallySignedTransaction,
 //!         input_index: Option<usize>,
+//!         _sign_options: &SignOptions,
 //!         _secp: &Secp256k1<All>,
 //!     ) -> Result<(), SignerError> {
 //!         let input_index = input_index.ok_or(SignerError::InputIndexOutOfRange)?;
@@ -91,24 +92,27 @@
 //! ```
 
 use std::cmp::Ordering;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::ops::Bound::Included;
 use std::sync::Arc;
 
-use bitcoin::blockdata::opcodes;
-use bitcoin::blockdata::script::Builder as ScriptBuilder;
 use bitcoin::hashes::{hash160, Hash};
-use bitcoin::secp256k1::{Message, Secp256k1};
-use bitcoin::util::bip32::{ExtendedPrivKey, Fingerprint};
-use bitcoin::util::{bip143, psbt};
-use bitcoin::{PrivateKey, Script, SigHash, SigHashType};
+use bitcoin::secp256k1::constants::SECRET_KEY_SIZE;
+use bitcoin::secp256k1::ffi::{self, CPtr};
+use bitcoin::secp256k1::recovery::RecoverableSignature;
+use bitcoin::secp256k1::{Message, Secp256k1, SecretKey, Signature};
+use bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint, KeySource};
+use bitcoin::util::misc::signed_msg_hash;
+use bitcoin::util::psbt;
+use bitcoin::{PrivateKey, PublicKey};
 
 use miniscript::descriptor::{DescriptorSecretKey, DescriptorSinglePriv, DescriptorXKey, KeyMap};
-use miniscript::{Legacy, MiniscriptKey, Segwitv0};
+use miniscript::MiniscriptKey;
 
 use super::utils::SecpCtx;
 use crate::descriptor::XKeyUtils;
+use crate::psbt::sighash;
 
 /// Identifier of a signer in the `SignersContainers`. Used as a key to find the right signer among
 /// multiple of them
@@ -153,6 +157,24 @@ pub enum SignerError {
     MissingWitnessScript,
     /// The fingerprint and derivation path are missing from the psbt input
     MissingHDKeypath,
+    /// An external signer failed, with the error it reported
+    External(String),
+    /// The input requires a sighash other than `SIGHASH_ALL`, which is not allowed by the
+    /// [`SignOptions`]
+    NonStandardSighash,
+    /// The signer can't sign messages
+    MessageSigningNotSupported,
+    /// The transaction violates the rules of a
+    /// [`PolicySigner`](crate::wallet::policy_signer::PolicySigner)
+    PolicyViolation(String),
+    /// The history of a [`PolicySigner`](crate::wallet::policy_signer::PolicySigner) can't be
+    /// read or updated
+    PolicyHistory(String),
+    /// The user didn't complete the required action in time
+    Timeout,
+    /// The output at this index claims to derive from one of the wallet's keys in its
+    /// `hd_keypaths`, but its script doesn't match
+    InvalidChangeOutput(usize),
 }
 
 impl fmt::Display for SignerError {
@@ -172,18 +194,64 @@ pub trait Signer: fmt::Debug + Send + Sync {
     ///
     /// The `input_index` argument is only provided if the wallet doesn't declare to sign the whole
     /// transaction in one go (see [`Signer::sign_whole_tx`]). Otherwise its value is `None` and
-    /// can be ignored.
+    /// can be ignored. The `sign_options` are the ones passed to
+    /// [`Wallet::sign`](super::Wallet::sign).
     fn sign(
         &self,
         psbt: &mut psbt::PartiallySignedTransaction,
         input_index: Option<usize>,
+        sign_options: &SignOptions,
         secp: &SecpCtx,
     ) -> Result<(), SignerError>;
 
+    /// Sign a PSBT, reporting the progress and the requests for user interaction to `progress`
+    ///
+    /// Signers that need the user to interact with a device should implement this and call
+    /// [`SignerProgress::awaiting_user_action`] before blocking, so that the caller can tell the
+    /// user what to do or cancel the operation. The default implementation calls
+    /// [`Signer::sign`].
+    fn sign_with_progress(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        _progress: &dyn SignerProgress,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        self.sign(psbt, input_index, sign_options, secp)
+    }
+
     /// Return whether or not the signer signs the whole transaction in one go instead of every
     /// input individually
     fn sign_whole_tx(&self) -> bool;
 
+    /// Return whether the signer needs the user to interact with it, for instance to confirm the
+    /// transaction on a hardware wallet
+    ///
+    /// The default implementation returns `false`.
+    fn requires_interaction(&self) -> bool {
+        false
+    }
+
+    /// Sign a message with the key at `key_source`, to prove the ownership of an address
+    ///
+    /// The message is hashed like Bitcoin Core's `signmessage` does, with
+    /// [`signed_msg_hash`](bitcoin::util::misc::signed_msg_hash). Keys that are not part of a
+    /// BIP32 tree are identified by the first four bytes of the HASH160 of the public key and an
+    /// empty derivation path.
+    ///
+    /// This is optional: the default implementation returns
+    /// [`SignerError::MessageSigningNotSupported`]. Signers that don't have the requested key
+    /// should return [`SignerError::MissingKey`].
+    fn sign_message(
+        &self,
+        _message: &str,
+        _key_source: &KeySource,
+        _secp: &SecpCtx,
+    ) -> Result<MessageSignature, SignerError> {
+        Err(SignerError::MessageSigningNotSupported)
+    }
+
     /// Return the secret key for the signer
     ///
     /// This is used internally to reconstruct the original descriptor that may contain secrets.
@@ -199,39 +267,41 @@ impl Signer for DescriptorXKey<ExtendedPrivKey> {
         &self,
         psbt: &mut psbt::PartiallySignedTransaction,
         input_index: Option<usize>,
+        sign_options: &SignOptions,
         secp: &SecpCtx,
     ) -> Result<(), SignerError> {
-        let input_index = input_index.unwrap();
-        if input_index >= psbt.inputs.len() {
-            return Err(SignerError::InputIndexOutOfRange);
-        }
-
-        let (public_key, deriv_path) = match psbt.inputs[input_index]
-            .hd_keypaths
-            .iter()
-            .filter_map(|(pk, &(fingerprint, ref path))| {
-                if self.matches(&(fingerprint, path.clone()), &secp).is_some() {
-                    Some((pk, path))
-                } else {
-                    None
-                }
-            })
-            .next()
-        {
-            Some((pk, full_path)) => (pk, full_path.clone()),
-            None => return Ok(()),
-        };
+        // the derived keys are cached for the whole call, so that the inputs spending from the same
+        // branch of the tree don't repeat the same (possibly hardened) derivations
+        let mut cache = DerivationCache::default();
 
-        let derived_key = self.xkey.derive_priv(&secp, &deriv_path).unwrap();
-        if &derived_key.private_key.public_key(&secp) != public_key {
-            Err(SignerError::InvalidKey)
-        } else {
-            derived_key.private_key.sign(psbt, Some(input_index), secp)
+        match input_index {
+            Some(input_index) => {
+                sign_xkey_input(self, psbt, input_index, sign_options, &mut cache, secp)
+            }
+            None => (0..psbt.inputs.len()).try_for_each(|input_index| {
+                sign_xkey_input(self, psbt, input_index, sign_options, &mut cache, secp)
+            }),
         }
     }
 
+    // the wallet calls the signer once for all the inputs, so that they share the cache of the
+    // derived keys instead of repeating the derivations for every input
     fn sign_whole_tx(&self) -> bool {
-        false
+        true
+    }
+
+    fn sign_message(
+        &self,
+        message: &str,
+        key_source: &KeySource,
+        secp: &SecpCtx,
+    ) -> Result<MessageSignature, SignerError> {
+        if self.matches(key_source, secp).is_none() {
+            return Err(SignerError::MissingKey);
+        }
+
+        let derived_key = self.xkey.derive_priv(secp, &key_source.1).unwrap();
+        sign_message_with_key(message, &derived_key.private_key, secp)
     }
 
     fn descriptor_secret_key(&self) -> Option<DescriptorSecretKey> {
@@ -239,11 +309,100 @@ impl Signer for DescriptorXKey<ExtendedPrivKey> {
     }
 }
 
+fn sign_xkey_input(
+    xkey: &DescriptorXKey<ExtendedPrivKey>,
+    psbt: &mut psbt::PartiallySignedTransaction,
+    input_index: usize,
+    sign_options: &SignOptions,
+    cache: &mut DerivationCache,
+    secp: &SecpCtx,
+) -> Result<(), SignerError> {
+    if input_index >= psbt.inputs.len() {
+        return Err(SignerError::InputIndexOutOfRange);
+    }
+
+    let (public_key, fingerprint, deriv_path) = match psbt.inputs[input_index]
+        .hd_keypaths
+        .iter()
+        .filter_map(|(pk, &(fingerprint, ref path))| {
+            if xkey.matches(&(fingerprint, path.clone()), secp).is_some() {
+                Some((pk, fingerprint, path))
+            } else {
+                None
+            }
+        })
+        .next()
+    {
+        Some((pk, fingerprint, full_path)) => (*pk, fingerprint, full_path.clone()),
+        None => return Ok(()),
+    };
+
+    let derived_key = cache.derive_priv(&xkey.xkey, fingerprint, &deriv_path, secp);
+    if derived_key.private_key.public_key(secp) != public_key {
+        Err(SignerError::InvalidKey)
+    } else {
+        Signer::sign(
+            &derived_key.private_key,
+            psbt,
+            Some(input_index),
+            sign_options,
+            secp,
+        )
+    }
+}
+
+/// Cache of the parents of the keys derived while signing, indexed by the fingerprint and the
+/// derivation path of the parent
+#[derive(Default)]
+struct DerivationCache(HashMap<(Fingerprint, DerivationPath), ExtendedPrivKey>);
+
+impl DerivationCache {
+    fn derive_priv(
+        &mut self,
+        xkey: &ExtendedPrivKey,
+        fingerprint: Fingerprint,
+        path: &DerivationPath,
+        secp: &SecpCtx,
+    ) -> ExtendedPrivKey {
+        let path: &[ChildNumber] = path.as_ref();
+        let (child, parent_path) = match path.split_last() {
+            Some((child, parent_path)) => (*child, DerivationPath::from(parent_path)),
+            None => return *xkey,
+        };
+
+        let parent = match self.0.get(&(fingerprint, parent_path.clone())) {
+            Some(parent) => *parent,
+            None => {
+                let parent = xkey.derive_priv(secp, &parent_path).unwrap();
+                self.0.insert((fingerprint, parent_path), parent);
+                parent
+            }
+        };
+        parent.ckd_priv(secp, child).unwrap()
+    }
+}
+
+// `ExtendedPrivKey` doesn't clear its memory when dropped, so the cached keys are overwritten
+// before being freed. This is a best effort, since the keys can still be copied around while
+// signing
+impl Drop for DerivationCache {
+    fn drop(&mut self) {
+        for xprv in self.0.values_mut() {
+            let key = xprv.private_key.key.as_mut_c_ptr();
+            for i in 0..SECRET_KEY_SIZE {
+                // Safety: the pointer is valid for the whole secret key, which is never used again
+                unsafe { std::ptr::write_volatile(key.add(i), 0) };
+            }
+        }
+    }
+}
+
 impl Signer for PrivateKey {
     fn sign(
         &self,
         psbt: &mut psbt::PartiallySignedTransaction,
         input_index: Option<usize>,
+        sign_options: &SignOptions,
         secp: &SecpCtx,
     ) -> Result<(), SignerError> {
         let input_index = input_index.unwrap();
@@ -255,20 +414,21 @@ impl Signer for PrivateKey {
         if psbt.inputs[input_index].partial_sigs.contains_key(&pubkey) {
             return Ok(());
         }
+        // nothing to do if the input has already been finalized
+        if psbt.inputs[input_index].final_script_sig.is_some()
+            || psbt.inputs[input_index].final_script_witness.is_some()
+        {
+            return Ok(());
+        }
 
-        // FIXME: use the presence of `witness_utxo` as an indication that we should make a bip143
-        // sig. Does this make sense? Should we add an extra argument to explicitly swith between
-        // these? The original idea was to declare sign() as sign<Ctx: ScriptContex>() and use Ctx,
-        // but that violates the rules for trait-objects, so we can't do it.
-        let (hash, sighash) = match psbt.inputs[input_index].witness_utxo {
-            Some(_) => Segwitv0::sighash(psbt, input_index)?,
-            None => Legacy::sighash(psbt, input_index)?,
-        };
+        let (hash, sighash) = sighash::sighash(psbt, input_index)?;
 
-        let signature = secp.sign(
-            &Message::from_slice(&hash.into_inner()[..]).unwrap(),
-            &self.key,
-        );
+        let msg = &Message::from_slice(&hash.into_inner()[..]).unwrap();
+        let signature = if sign_options.allow_grinding {
+            sign_low_r(secp, msg, &self.key)
+        } else {
+            secp.sign(msg, &self.key)
+        };
 
         let mut final_signature = Vec::with_capacity(75);
         final_signature.extend_from_slice(&signature.serialize_der());
@@ -285,6 +445,19 @@ impl Signer for PrivateKey {
         false
     }
 
+    fn sign_message(
+        &self,
+        message: &str,
+        key_source: &KeySource,
+        secp: &SecpCtx,
+    ) -> Result<MessageSignature, SignerError> {
+        if key_source != &single_key_source(&self.public_key(secp)) {
+            return Err(SignerError::MissingKey);
+        }
+
+        sign_message_with_key(message, self, secp)
+    }
+
     fn descriptor_secret_key(&self) -> Option<DescriptorSecretKey> {
         Some(DescriptorSecretKey::SinglePriv(DescriptorSinglePriv {
             key: *self,
@@ -293,6 +466,197 @@ impl Signer for PrivateKey {
     }
 }
 
+/// Trait for types that receive the progress of the signers, see
+/// [`Wallet::sign_with_progress`](super::Wallet::sign_with_progress)
+///
+/// Returning an error from any of the methods, usually [`SignerError::UserCanceled`], aborts the
+/// signing.
+pub trait SignerProgress {
+    /// Send a new progress update
+    ///
+    /// The `progress` value is in the range 0.0 - 100.0, and the `message` value is an optional
+    /// text message that can be displayed to the user.
+    fn update(&self, progress: f32, message: Option<String>) -> Result<(), SignerError>;
+
+    /// Notify that a signer is waiting for the user to do something, described by `message`
+    ///
+    /// This is called before the signer blocks, for instance waiting for a button press on a
+    /// hardware wallet. The default implementation does nothing.
+    fn awaiting_user_action(&self, _message: &str) -> Result<(), SignerError> {
+        Ok(())
+    }
+}
+
+/// Type that implements [`SignerProgress`] and drops every update received
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NoopSignerProgress;
+
+/// Create a new instance of [`NoopSignerProgress`]
+pub fn noop_signer_progress() -> NoopSignerProgress {
+    NoopSignerProgress
+}
+
+impl SignerProgress for NoopSignerProgress {
+    fn update(&self, _progress: f32, _message: Option<String>) -> Result<(), SignerError> {
+        Ok(())
+    }
+}
+
+/// Signature of a message made by a [`Signer`]
+///
+/// See [`Signer::sign_message`] for the format of the message. The signature can be exported in
+/// the format used by Bitcoin Core's `signmessage` with [`MessageSignature::to_base64`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageSignature {
+    /// Public key that made the signature
+    pub public_key: PublicKey,
+    /// Recoverable ECDSA signature of the hash of the message
+    pub signature: RecoverableSignature,
+}
+
+impl MessageSignature {
+    /// Verify the signature of `message`
+    ///
+    /// Like Bitcoin Core's `verifymessage`, the public key is recovered from the signature and
+    /// compared with [`MessageSignature::public_key`].
+    pub fn verify(&self, message: &str, secp: &SecpCtx) -> bool {
+        let msg = Message::from_slice(&signed_msg_hash(message).into_inner()[..]).unwrap();
+        match secp.recover(&msg, &self.signature) {
+            Ok(public_key) => public_key == self.public_key.key,
+            Err(_) => false,
+        }
+    }
+
+    /// Serialize the signature in the 65 bytes "compact" format
+    ///
+    /// The first byte is a header that encodes the recovery id and whether the public key is
+    /// compressed, followed by the `r` and `s` values of the signature.
+    pub fn serialize(&self) -> [u8; 65] {
+        let (recovery_id, compact) = self.signature.serialize_compact();
+        let compressed_flag = match self.public_key.compressed {
+            true => 4,
+            false => 0,
+        };
+
+        let mut serialized = [0u8; 65];
+        serialized[0] = 27 + recovery_id.to_i32() as u8 + compressed_flag;
+        serialized[1..].copy_from_slice(&compact);
+        serialized
+    }
+
+    /// Serialize the signature in base64, like Bitcoin Core's `signmessage`
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.serialize()[..])
+    }
+}
+
+// Key source used to identify the keys that are not part of a BIP32 tree
+pub(crate) fn single_key_source(public_key: &PublicKey) -> KeySource {
+    let hash = hash160::Hash::hash(&public_key.to_bytes());
+    (Fingerprint::from(&hash[..4]), DerivationPath::from(vec![]))
+}
+
+fn sign_message_with_key(
+    message: &str,
+    key: &PrivateKey,
+    secp: &SecpCtx,
+) -> Result<MessageSignature, SignerError> {
+    let msg = Message::from_slice(&signed_msg_hash(message).into_inner()[..]).unwrap();
+
+    Ok(MessageSignature {
+        public_key: key.public_key(secp),
+        signature: secp.sign_recoverable(&msg, &key.key),
+    })
+}
+
+/// Options for [`Wallet::sign`](super::Wallet::sign)
+///
+/// The default values are fine for the PSBTs created by the wallet itself, they can be relaxed to
+/// sign PSBTs produced by other software or by hardware wallets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignOptions {
+    /// Whether the `witness_utxo` of segwit inputs can be trusted without a `non_witness_utxo`
+    ///
+    /// When this is `false`, every segwit input of the wallet must also have a `non_witness_utxo`
+    /// that matches its `witness_utxo`, which protects from a fee attack where the amounts of the
+    /// inputs are misrepresented. The PSBTs created by the wallet only include it when
+    /// [`TxBuilder::force_non_witness_utxo`](super::tx_builder::TxBuilder::force_non_witness_utxo)
+    /// is used.
+    ///
+    /// Defaults to `true`.
+    pub trust_witness_utxo: bool,
+    /// Whether to sign inputs that require a sighash other than `SIGHASH_ALL`
+    ///
+    /// Other sighashes allow parts of the transaction to be changed after signing, so they have
+    /// to be explicitly enabled.
+    ///
+    /// Defaults to `false`.
+    pub allow_all_sighashes: bool,
+    /// Height used to check the timelocks when finalizing the PSBT, instead of the current height
+    /// of the chain
+    ///
+    /// Defaults to `None`.
+    pub assume_height: Option<u32>,
+    /// Whether to try finalizing the PSBT after signing it
+    ///
+    /// Defaults to `true`.
+    pub try_finalize: bool,
+    /// Whether the software signers should grind the nonce to produce signatures with a low `r`
+    ///
+    /// Like Bitcoin Core, the signatures are made one byte shorter by retrying with a different
+    /// nonce until the `r` value fits in 32 bytes without padding, which makes the transaction
+    /// cheaper and its size indistinguishable from one made by Core.
+    ///
+    /// Defaults to `true`.
+    pub allow_grinding: bool,
+}
+
+impl Default for SignOptions {
+    fn default() -> Self {
+        SignOptions {
+            trust_witness_utxo: true,
+            allow_all_sighashes: false,
+            assume_height: None,
+            try_finalize: true,
+            allow_grinding: true,
+        }
+    }
+}
+
+// Sign `msg` with `key`, grinding the nonce until the `r` value of the signature is below 2^255
+// like Bitcoin Core does, so that its DER encoding is at most 70 bytes long. The extra entropy
+// fed to the RFC6979 nonce function is a counter encoded in little-endian, so the result is
+// still deterministic.
+fn sign_low_r(secp: &SecpCtx, msg: &Message, key: &SecretKey) -> Signature {
+    let mut signature = secp.sign(msg, key);
+    let mut counter = 0u32;
+    let mut extra_entropy = [0u8; 32];
+
+    while signature.serialize_compact()[0] >= 0x80 {
+        counter += 1;
+        extra_entropy[..4].copy_from_slice(&counter.to_le_bytes());
+
+        // Safety: all the pointers are valid for the duration of the call and the signature
+        // can't be invalid, since it's made from a valid `Message` and `SecretKey`
+        signature = unsafe {
+            let mut ret = ffi::Signature::new();
+            let res = ffi::secp256k1_ecdsa_sign(
+                *secp.ctx(),
+                &mut ret,
+                msg.as_c_ptr(),
+                key.as_c_ptr(),
+                ffi::secp256k1_nonce_function_rfc6979,
+                extra_entropy.as_ptr() as *const ffi::types::c_void,
+            );
+            assert_eq!(res, 1);
+
+            Signature::from(ret)
+        };
+    }
+
+    signature
+}
+
 /// Defines the order in which signers are called
 ///
 /// The default value is `100`. Signers with an ordering above that will be called later,
@@ -322,15 +686,114 @@ impl From<(SignerId, SignerOrdering)> for SignersContainerKey {
     }
 }
 
+/// Async version of the [`Signer`] trait
+///
+/// This can be implemented by signers that talk to a remote service, like a network-attached HSM
+/// or a co-signing API, so that they don't block the executor while waiting for the response.
+/// Every [`Signer`] is also an [`AsyncSigner`].
+///
+/// Async signers are added with [`Wallet::add_async_signer`](super::Wallet::add_async_signer)
+/// and they are only called by [`Wallet::sign_async`](super::Wallet::sign_async).
+#[cfg(feature = "async-interface")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+#[async_trait]
+pub trait AsyncSigner: fmt::Debug + Send + Sync {
+    /// Sign a PSBT, see [`Signer::sign`]
+    async fn sign(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError>;
+
+    /// Return whether or not the signer signs the whole transaction in one go instead of every
+    /// input individually
+    fn sign_whole_tx(&self) -> bool;
+
+    /// Return whether the signer needs the user to interact with it, see
+    /// [`Signer::requires_interaction`]
+    fn requires_interaction(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "async-interface")]
+#[async_trait]
+impl<T: Signer> AsyncSigner for T {
+    async fn sign(
+        &self,
+        psbt: &mut psbt::PartiallySignedTransaction,
+        input_index: Option<usize>,
+        sign_options: &SignOptions,
+        secp: &SecpCtx,
+    ) -> Result<(), SignerError> {
+        Signer::sign(self, psbt, input_index, sign_options, secp)
+    }
+
+    fn sign_whole_tx(&self) -> bool {
+        Signer::sign_whole_tx(self)
+    }
+
+    fn requires_interaction(&self) -> bool {
+        Signer::requires_interaction(self)
+    }
+}
+
+/// Signer stored in a [`SignersContainer`]
+#[derive(Debug, Clone)]
+pub enum AnySigner {
+    /// Blocking signer, added with [`SignersContainer::add_external`]
+    Blocking(Arc<dyn Signer>),
+    /// Async signer, added with [`SignersContainer::add_async`]
+    #[cfg(feature = "async-interface")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+    Async(Arc<dyn AsyncSigner>),
+}
+
+impl AnySigner {
+    /// Return the signer if it's a blocking one
+    pub fn as_blocking(&self) -> Option<&Arc<dyn Signer>> {
+        match self {
+            AnySigner::Blocking(signer) => Some(signer),
+            #[cfg(feature = "async-interface")]
+            AnySigner::Async(_) => None,
+        }
+    }
+
+    /// Return whether or not the signer signs the whole transaction in one go instead of every
+    /// input individually
+    pub fn sign_whole_tx(&self) -> bool {
+        match self {
+            AnySigner::Blocking(signer) => signer.sign_whole_tx(),
+            #[cfg(feature = "async-interface")]
+            AnySigner::Async(signer) => signer.sign_whole_tx(),
+        }
+    }
+
+    /// Return whether the signer needs the user to interact with it, see
+    /// [`Signer::requires_interaction`]
+    pub fn requires_interaction(&self) -> bool {
+        match self {
+            AnySigner::Blocking(signer) => signer.requires_interaction(),
+            #[cfg(feature = "async-interface")]
+            AnySigner::Async(signer) => signer.requires_interaction(),
+        }
+    }
+}
+
 /// Container for multiple signers
+///
+/// The blocking and the async signers are kept together, sorted by their [`SignerOrdering`].
 #[derive(Debug, Default, Clone)]
-pub struct SignersContainer(BTreeMap<SignersContainerKey, Arc<dyn Signer>>);
+pub struct SignersContainer(BTreeMap<SignersContainerKey, AnySigner>);
 
 impl SignersContainer {
     /// Create a map of public keys to secret keys
     pub fn as_key_map(&self, secp: &SecpCtx) -> KeyMap {
         self.0
             .values()
+            .filter_map(AnySigner::as_blocking)
             .filter_map(|signer| signer.descriptor_secret_key())
             .filter_map(|secret| secret.as_public(secp).ok().map(|public| (public, secret)))
             .collect()
@@ -369,7 +832,7 @@ impl From<KeyMap> for SignersContainer {
 impl SignersContainer {
     /// Default constructor
     pub fn new() -> Self {
-        SignersContainer(Default::default())
+        SignersContainer::default()
     }
 
     /// Adds an external signer to the container for the specified id. Optionally returns the
@@ -379,12 +842,40 @@ impl SignersContainer {
         id: SignerId,
         ordering: SignerOrdering,
         signer: Arc<dyn Signer>,
-    ) -> Option<Arc<dyn Signer>> {
-        self.0.insert((id, ordering).into(), signer)
+    ) -> Option<AnySigner> {
+        self.0
+            .insert((id, ordering).into(), AnySigner::Blocking(signer))
+    }
+
+    /// Adds an async signer to the container for the specified id. Optionally returns the signer
+    /// that was previously in the container, if any
+    #[cfg(feature = "async-interface")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+    pub fn add_async(
+        &mut self,
+        id: SignerId,
+        ordering: SignerOrdering,
+        signer: Arc<dyn AsyncSigner>,
+    ) -> Option<AnySigner> {
+        self.0
+            .insert((id, ordering).into(), AnySigner::Async(signer))
+    }
+
+    /// Returns the list of async signers in the container, sorted by lowest to highest `ordering`
+    #[cfg(feature = "async-interface")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "async-interface")))]
+    pub fn async_signers(&self) -> Vec<&Arc<dyn AsyncSigner>> {
+        self.0
+            .values()
+            .filter_map(|signer| match signer {
+                AnySigner::Async(signer) => Some(signer),
+                AnySigner::Blocking(_) => None,
+            })
+            .collect()
     }
 
     /// Removes a signer from the container and returns it
-    pub fn remove(&mut self, id: SignerId, ordering: SignerOrdering) -> Option<Arc<dyn Signer>> {
+    pub fn remove(&mut self, id: SignerId, ordering: SignerOrdering) -> Option<AnySigner> {
         self.0.remove(&(id, ordering).into())
     }
 
@@ -396,13 +887,40 @@ impl SignersContainer {
             .collect()
     }
 
-    /// Returns the list of signers in the container, sorted by lowest to highest `ordering`
+    /// Returns the list of blocking signers in the container, sorted by lowest to highest
+    /// `ordering`
     pub fn signers(&self) -> Vec<&Arc<dyn Signer>> {
-        self.0.values().collect()
+        self.0.values().filter_map(AnySigner::as_blocking).collect()
+    }
+
+    /// Returns the id and the ordering of every signer in the container, sorted by lowest to
+    /// highest `ordering`
+    ///
+    /// The capabilities of the signers can be inspected with [`AnySigner::sign_whole_tx`] and
+    /// [`AnySigner::requires_interaction`].
+    pub fn entries(&self) -> Vec<(&SignerId, &SignerOrdering, &AnySigner)> {
+        self.0
+            .iter()
+            .map(|(SignersContainerKey { id, ordering }, signer)| (id, ordering, signer))
+            .collect()
+    }
+
+    /// Removes all the signers with the given id, regardless of their ordering, and returns them
+    pub fn remove_by_id(&mut self, id: SignerId) -> Vec<AnySigner> {
+        let keys = self
+            .0
+            .keys()
+            .filter(|key| key.id == id)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        keys.into_iter()
+            .filter_map(|key| self.0.remove(&key))
+            .collect()
     }
 
     /// Finds the signer with lowest ordering for a given id in the container.
-    pub fn find(&self, id: SignerId) -> Option<&Arc<dyn Signer>> {
+    pub fn find(&self, id: SignerId) -> Option<&AnySigner> {
         self.0
             .range((
                 Included(&(id.clone(), SignerOrdering(0)).into()),
@@ -414,110 +932,6 @@ impl SignersContainer {
     }
 }
 
-pub(crate) trait ComputeSighash {
-    fn sighash(
-        psbt: &psbt::PartiallySignedTransaction,
-        input_index: usize,
-    ) -> Result<(SigHash, SigHashType), SignerError>;
-}
-
-impl ComputeSighash for Legacy {
-    fn sighash(
-        psbt: &psbt::PartiallySignedTransaction,
-        input_index: usize,
-    ) -> Result<(SigHash, SigHashType), SignerError> {
-        if input_index >= psbt.inputs.len() {
-            return Err(SignerError::InputIndexOutOfRange);
-        }
-
-        let psbt_input = &psbt.inputs[input_index];
-        let tx_input = &psbt.global.unsigned_tx.input[input_index];
-
-        let sighash = psbt_input.sighash_type.unwrap_or(SigHashType::All);
-        let script = match psbt_input.redeem_script {
-            Some(ref redeem_script) => redeem_script.clone(),
-            None => {
-                let non_witness_utxo = psbt_input
-                    .non_witness_utxo
-                    .as_ref()
-                    .ok_or(SignerError::MissingNonWitnessUtxo)?;
-                let prev_out = non_witness_utxo
-                    .output
-                    .get(tx_input.previous_output.vout as usize)
-                    .ok_or(SignerError::InvalidNonWitnessUtxo)?;
-
-                prev_out.script_pubkey.clone()
-            }
-        };
-
-        Ok((
-            psbt.global
-                .unsigned_tx
-                .signature_hash(input_index, &script, sighash.as_u32()),
-            sighash,
-        ))
-    }
-}
-
-fn p2wpkh_script_code(script: &Script) -> Script {
-    ScriptBuilder::new()
-        .push_opcode(opcodes::all::OP_DUP)
-        .push_opcode(opcodes::all::OP_HASH160)
-        .push_slice(&script[2..])
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_CHECKSIG)
-        .into_script()
-}
-
-impl ComputeSighash for Segwitv0 {
-    fn sighash(
-        psbt: &psbt::PartiallySignedTransaction,
-        input_index: usize,
-    ) -> Result<(SigHash, SigHashType), SignerError> {
-        if input_index >= psbt.inputs.len() {
-            return Err(SignerError::InputIndexOutOfRange);
-        }
-
-        let psbt_input = &psbt.inputs[input_index];
-
-        let sighash = psbt_input.sighash_type.unwrap_or(SigHashType::All);
-
-        let witness_utxo = psbt_input
-            .witness_utxo
-            .as_ref()
-            .ok_or(SignerError::MissingNonWitnessUtxo)?;
-        let value = witness_utxo.value;
-
-        let script = match psbt_input.witness_script {
-            Some(ref witness_script) => witness_script.clone(),
-            None => {
-                if witness_utxo.script_pubkey.is_v0_p2wpkh() {
-                    p2wpkh_script_code(&witness_utxo.script_pubkey)
-                } else if psbt_input
-                    .redeem_script
-                    .as_ref()
-                    .map(Script::is_v0_p2wpkh)
-                    .unwrap_or(false)
-                {
-                    p2wpkh_script_code(&psbt_input.redeem_script.as_ref().unwrap())
-                } else {
-                    return Err(SignerError::MissingWitnessScript);
-                }
-            }
-        };
-
-        Ok((
-            bip143::SigHashCache::new(&psbt.global.unsigned_tx).signature_hash(
-                input_index,
-                &script,
-                value,
-                sighash,
-            ),
-            sighash,
-        ))
-    }
-}
-
 impl PartialOrd for SignersContainerKey {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -600,6 +1014,44 @@ mod signers_container_tests {
         assert_eq!(Arc::as_ptr(signers[2]), Arc::as_ptr(&signer3));
     }
 
+    #[test]
+    fn remove_signers_by_id() {
+        let mut signers = SignersContainer::new();
+        let signer1: Arc<dyn Signer> = Arc::new(DummySigner);
+        let signer2: Arc<dyn Signer> = Arc::new(DummySigner);
+        let signer3: Arc<dyn Signer> = Arc::new(DummySigner);
+
+        let id1 = SignerId::Fingerprint(b"cafe"[..].into());
+        let id2 = SignerId::Fingerprint(b"babe"[..].into());
+
+        signers.add_external(id1.clone(), SignerOrdering(1), signer1.clone());
+        signers.add_external(id2.clone(), SignerOrdering(2), signer2.clone());
+        signers.add_external(id1.clone(), SignerOrdering(3), signer3.clone());
+
+        let entries = signers.entries();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].0, &id2);
+        assert_eq!(entries[1].1, &SignerOrdering(2));
+        assert_eq!(
+            Arc::as_ptr(entries[1].2.as_blocking().unwrap()),
+            Arc::as_ptr(&signer2)
+        );
+
+        let removed = signers.remove_by_id(id1.clone());
+        assert_eq!(removed.len(), 2);
+        assert_eq!(
+            Arc::as_ptr(removed[0].as_blocking().unwrap()),
+            Arc::as_ptr(&signer1)
+        );
+        assert_eq!(
+            Arc::as_ptr(removed[1].as_blocking().unwrap()),
+            Arc::as_ptr(&signer3)
+        );
+        assert_eq!(signers.ids(), vec![&id2]);
+
+        assert!(signers.remove_by_id(id1).is_empty());
+    }
+
     #[test]
     fn find_signer_by_id() {
         let mut signers = SignersContainer::new();
@@ -618,26 +1070,107 @@ mod signers_container_tests {
         signers.add_external(id3.clone(), SignerOrdering(3), signer3.clone());
 
         assert!(
-            matches!(signers.find(id1), Some(signer) if Arc::as_ptr(&signer1) == Arc::as_ptr(signer))
+            matches!(signers.find(id1).and_then(AnySigner::as_blocking), Some(signer) if Arc::as_ptr(&signer1) == Arc::as_ptr(signer))
         );
         assert!(
-            matches!(signers.find(id2), Some(signer) if Arc::as_ptr(&signer2) == Arc::as_ptr(signer))
+            matches!(signers.find(id2).and_then(AnySigner::as_blocking), Some(signer) if Arc::as_ptr(&signer2) == Arc::as_ptr(signer))
         );
         assert!(
-            matches!(signers.find(id3.clone()), Some(signer) if Arc::as_ptr(&signer3) == Arc::as_ptr(signer))
+            matches!(signers.find(id3.clone()).and_then(AnySigner::as_blocking), Some(signer) if Arc::as_ptr(&signer3) == Arc::as_ptr(signer))
         );
 
         // The `signer4` has the same ID as `signer3` but lower ordering.
         // It should be found by `id3` instead of `signer3`.
         signers.add_external(id3.clone(), SignerOrdering(2), signer4.clone());
         assert!(
-            matches!(signers.find(id3), Some(signer) if Arc::as_ptr(&signer4) == Arc::as_ptr(signer))
+            matches!(signers.find(id3).and_then(AnySigner::as_blocking), Some(signer) if Arc::as_ptr(&signer4) == Arc::as_ptr(signer))
         );
 
         // Can't find anything with ID that doesn't exist
         assert!(matches!(signers.find(id_nonexistent), None));
     }
 
+    #[test]
+    fn test_derivation_cache() {
+        let secp = Secp256k1::new();
+        let xprv = bip32::ExtendedPrivKey::from_str("tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS").unwrap();
+        let fingerprint = xprv.fingerprint(&secp);
+
+        let mut cache = DerivationCache::default();
+        for path in &["m/84'/1'/0'/0/0", "m/84'/1'/0'/0/1", "m/84'/1'/0'/1/0"] {
+            let path = bip32::DerivationPath::from_str(path).unwrap();
+            assert_eq!(
+                cache.derive_priv(&xprv, fingerprint, &path, &secp),
+                xprv.derive_priv(&secp, &path).unwrap()
+            );
+        }
+        // the first two keys share the same parent
+        assert_eq!(cache.0.len(), 2);
+    }
+
+    #[test]
+    fn test_sign_message_key_source() {
+        let secp: Secp256k1<All> = Secp256k1::new();
+        let key =
+            PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW").unwrap();
+        let key_source = single_key_source(&key.public_key(&secp));
+
+        let signature = key.sign_message("Hello World", &key_source, &secp).unwrap();
+        assert!(signature.verify("Hello World", &secp));
+        assert!(!signature.verify("Hello", &secp));
+
+        let (_, _, fingerprint) = setup_keys::<miniscript::Segwitv0>(TPRV0_STR);
+        let wrong_source = (fingerprint, key_source.1);
+        assert_eq!(
+            key.sign_message("Hello World", &wrong_source, &secp),
+            Err(SignerError::MissingKey)
+        );
+    }
+
+    #[test]
+    fn test_message_signature_base64() {
+        use bitcoin::secp256k1::recovery::RecoveryId;
+
+        let secp: Secp256k1<All> = Secp256k1::new();
+        let key =
+            PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW").unwrap();
+        let key_source = single_key_source(&key.public_key(&secp));
+        let signature = key.sign_message("Hello World", &key_source, &secp).unwrap();
+
+        let serialized = base64::decode(&signature.to_base64()).unwrap();
+        assert_eq!(serialized.len(), 65);
+        // the header of a signature made with a compressed key is in 31..=34
+        assert!((31..=34).contains(&serialized[0]));
+
+        let recovery_id = RecoveryId::from_i32((serialized[0] - 31) as i32).unwrap();
+        let parsed = RecoverableSignature::from_compact(&serialized[1..], recovery_id).unwrap();
+        let msg = Message::from_slice(&signed_msg_hash("Hello World").into_inner()[..]).unwrap();
+        assert_eq!(
+            secp.recover(&msg, &parsed).unwrap(),
+            key.public_key(&secp).key
+        );
+    }
+
+    #[test]
+    fn test_sign_low_r() {
+        let secp: Secp256k1<All> = Secp256k1::new();
+        let key = PrivateKey::from_wif("cVpPVruEDdmutPzisEsYvtST1usBR3ntr8pXSyt6D2YYqXRyPcFW")
+            .unwrap()
+            .key;
+        let pubkey = bitcoin::secp256k1::PublicKey::from_secret_key(&secp, &key);
+
+        // on average half of the signatures need grinding, so this covers both cases
+        for i in 1..=32u8 {
+            let msg = Message::from_slice(&[i; 32]).unwrap();
+            let signature = sign_low_r(&secp, &msg, &key);
+
+            assert!(signature.serialize_der().len() <= 70);
+            assert!(secp.verify(&msg, &signature, &pubkey).is_ok());
+            // grinding is deterministic
+            assert_eq!(sign_low_r(&secp, &msg, &key), signature);
+        }
+    }
+
     #[derive(Debug)]
     struct DummySigner;
     impl Signer for DummySigner {
@@ -645,6 +1178,7 @@ mod signers_container_tests {
             &self,
             _psbt: &mut PartiallySignedTransaction,
             _input_index: Option<usize>,
+            _sign_options: &SignOptions,
             _secp: &SecpCtx,
         ) -> Result<(), SignerError> {
             Ok(())
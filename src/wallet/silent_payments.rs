@@ -0,0 +1,812 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Silent payments
+//!
+//! This module implements the sender side of
+//! [BIP-352](https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki). A silent payment
+//! address contains two public keys, and the sender combines them with the private keys of the
+//! inputs of the transaction to compute a one-time taproot output that only the recipient can
+//! detect and spend. This means that the same address can be reused without linking the payments
+//! together on-chain.
+//!
+//! Payments are added to a transaction with [`TxBuilder::add_silent_payment`]: the scripts of
+//! the outputs are computed by [`Wallet::create_tx`] once the inputs have been selected, so the
+//! wallet must know the private keys of its descriptors.
+//!
+//! On the receiving side, [`SilentPaymentReceiver::scan_transaction`] detects the outputs of a
+//! transaction that pay the receiver and returns the tweak needed to spend them. Since the
+//! payments can't be told apart from any other taproot output, every transaction of a block has
+//! to be scanned, together with the outputs spent by its inputs: once a receiver is set with
+//! [`Wallet::set_silent_payment_receiver`], [`Wallet::sync`] does it for the new blocks if the
+//! backend supports [`Capability::BlockTransactions`]. The outputs found are stored in the
+//! database with their tweak and they are added to the balance, but they can't be spent by
+//! [`Wallet::create_tx`] since that requires a taproot signer, which is not supported yet: use
+//! [`SilentPaymentOutput::private_key`] to export their keys instead.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use std::str::FromStr;
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::*;
+//! # use bdk::wallet::silent_payments::SilentPaymentAddress;
+//! # let descriptor = "wpkh(tprv8ZgxMBicQKsPd3EupYiPRhaMooHKUHJxNsTfYuScep13go8QFfHdtkG9nRkFGb7busX4isf6X9dURGCoKgitaApQ6MupRhZMcELAxTBRJgS/*)";
+//! # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! let address = SilentPaymentAddress::from_str("tsp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc3wk4yh")?;
+//! let builder = TxBuilder::new().add_silent_payment(address, 50_000);
+//! let (psbt, details) = wallet.create_tx(builder)?;
+//! # Ok::<(), bdk::Error>(())
+//! ```
+//!
+//! [`TxBuilder::add_silent_payment`]: super::tx_builder::TxBuilder::add_silent_payment
+//! [`Wallet::set_silent_payment_receiver`]: super::Wallet::set_silent_payment_receiver
+//! [`Wallet::sync`]: super::Wallet::sync
+//! [`Capability::BlockTransactions`]: crate::blockchain::Capability::BlockTransactions
+//! [`Wallet::create_tx`]: super::Wallet::create_tx
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::blockdata::script::{Builder, Instruction};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::{hash160, sha256, Hash, HashEngine};
+use bitcoin::secp256k1::{self, PublicKey, Secp256k1, SecretKey, Signing, Verification};
+use bitcoin::{Network, OutPoint, Script, Transaction, TxIn, TxOut};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// Human-readable part of the silent payment addresses on mainnet
+pub const MAINNET_HRP: &str = "sp";
+/// Human-readable part of the silent payment addresses on the test networks
+pub const TESTNET_HRP: &str = "tsp";
+
+const INPUTS_TAG: &str = "BIP0352/Inputs";
+const SHARED_SECRET_TAG: &str = "BIP0352/SharedSecret";
+
+const CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const MAX_ADDRESS_LEN: usize = 1023;
+const KEYS_LEN: usize = 2 * secp256k1::constants::PUBLIC_KEY_SIZE;
+
+// x coordinate of the "nothing up my sleeve" point used as internal key by the taproot outputs that
+// can only be spent with a script: inputs using it don't contribute to the shared secret
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// Errors thrown while parsing silent payment addresses or computing the outputs paying them
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SilentPaymentError {
+    /// The address is not a valid bech32m string
+    InvalidEncoding,
+    /// The human-readable part of the address is not `sp` or `tsp`
+    InvalidPrefix(String),
+    /// The version of the address is not supported
+    UnsupportedVersion(u8),
+    /// The address doesn't contain exactly two public keys
+    InvalidLength(usize),
+    /// One of the keys of the address is invalid
+    InvalidKey,
+    /// The address is not valid for the network of the wallet
+    InvalidNetwork,
+    /// None of the inputs of the transaction can be used to compute the shared secret
+    NoEligibleInputs,
+    /// The wallet doesn't know the private key of the input spending this outpoint
+    MissingInputKey(OutPoint),
+    /// The keys of the inputs don't produce a valid shared secret
+    InvalidSharedSecret,
+    /// The number of outputs spent doesn't match the number of inputs of the transaction
+    InvalidPrevouts,
+}
+
+impl fmt::Display for SilentPaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for SilentPaymentError {}
+
+/// A silent payment address, made of a scan key and a spend key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SilentPaymentAddress {
+    /// Network of the address
+    ///
+    /// Addresses for the test networks all share the same prefix and are parsed as
+    /// [`Network::Testnet`].
+    pub network: Network,
+    /// Key used by the recipient to detect the payments
+    pub scan_key: PublicKey,
+    /// Key tweaked to compute the outputs paying the recipient
+    pub spend_key: PublicKey,
+}
+
+impl SilentPaymentAddress {
+    /// Create a new address from the keys of the recipient
+    pub fn new(scan_key: PublicKey, spend_key: PublicKey, network: Network) -> Self {
+        SilentPaymentAddress {
+            network,
+            scan_key,
+            spend_key,
+        }
+    }
+
+    /// Return whether the address can be used on `network`
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        (self.network == Network::Bitcoin) == (network == Network::Bitcoin)
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hrp = match self.network {
+            Network::Bitcoin => MAINNET_HRP,
+            _ => TESTNET_HRP,
+        };
+
+        let mut keys = Vec::with_capacity(KEYS_LEN);
+        keys.extend_from_slice(&self.scan_key.serialize());
+        keys.extend_from_slice(&self.spend_key.serialize());
+
+        let mut data = vec![0u8];
+        data.extend(convert_bits(&keys, 8, 5, true).expect("Padding is allowed"));
+        let checksum = bech32m_checksum(hrp, &data);
+
+        write!(f, "{}1", hrp)?;
+        for value in data.iter().chain(checksum.iter()) {
+            write!(f, "{}", CHARSET[*value as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
+// addresses are serialized as strings, like they are shown to the users
+impl Serialize for SilentPaymentAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SilentPaymentAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        SilentPaymentAddress::from_str(&s).map_err(de::Error::custom)
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = SilentPaymentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() > MAX_ADDRESS_LEN
+            || (s.chars().any(|c| c.is_ascii_lowercase())
+                && s.chars().any(|c| c.is_ascii_uppercase()))
+        {
+            return Err(SilentPaymentError::InvalidEncoding);
+        }
+        let s = s.to_ascii_lowercase();
+
+        let (hrp, data) = match s.rfind('1') {
+            Some(pos) if pos > 0 && s.len() - pos > 7 => (&s[..pos], &s[pos + 1..]),
+            _ => return Err(SilentPaymentError::InvalidEncoding),
+        };
+        let data = data
+            .bytes()
+            .map(|c| CHARSET.iter().position(|x| *x == c).map(|v| v as u8))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(SilentPaymentError::InvalidEncoding)?;
+        if bech32m_polymod(&hrp_expand(hrp), &data) != BECH32M_CONST {
+            return Err(SilentPaymentError::InvalidEncoding);
+        }
+
+        let network = match hrp {
+            MAINNET_HRP => Network::Bitcoin,
+            TESTNET_HRP => Network::Testnet,
+            _ => return Err(SilentPaymentError::InvalidPrefix(hrp.to_string())),
+        };
+
+        let (version, payload) = data[..data.len() - 6]
+            .split_first()
+            .ok_or(SilentPaymentError::InvalidEncoding)?;
+        let keys = convert_bits(payload, 5, 8, false).ok_or(SilentPaymentError::InvalidEncoding)?;
+        let keys = match *version {
+            0 if keys.len() == KEYS_LEN => &keys[..],
+            0 => return Err(SilentPaymentError::InvalidLength(keys.len())),
+            // future versions are meant to be backward compatible: the first two keys are the
+            // ones of version 0, followed by some extra data
+            1..=30 if keys.len() >= KEYS_LEN => &keys[..KEYS_LEN],
+            1..=30 => return Err(SilentPaymentError::InvalidLength(keys.len())),
+            v => return Err(SilentPaymentError::UnsupportedVersion(v)),
+        };
+
+        let (scan_key, spend_key) = keys.split_at(secp256k1::constants::PUBLIC_KEY_SIZE);
+        Ok(SilentPaymentAddress {
+            network,
+            scan_key: PublicKey::from_slice(scan_key)
+                .map_err(|_| SilentPaymentError::InvalidKey)?,
+            spend_key: PublicKey::from_slice(spend_key)
+                .map_err(|_| SilentPaymentError::InvalidKey)?,
+        })
+    }
+}
+
+/// Compute the scripts of the outputs paying `recipients`
+///
+/// `input_keys` are the private keys of the inputs of the transaction that are eligible for
+/// silent payments, while `outpoints` must contain the outpoints spent by every input. The
+/// scripts are returned in the same order as the recipients: the outputs paying the same scan
+/// key are numbered in that order, so the recipients must be added to the transaction in the
+/// same order too.
+pub fn output_scripts<C: Signing + Verification>(
+    secp: &Secp256k1<C>,
+    input_keys: &[SecretKey],
+    outpoints: &[OutPoint],
+    recipients: &[SilentPaymentAddress],
+) -> Result<Vec<Script>, SilentPaymentError> {
+    let (first_key, other_keys) = input_keys
+        .split_first()
+        .ok_or(SilentPaymentError::NoEligibleInputs)?;
+    let mut input_key = *first_key;
+    for key in other_keys {
+        input_key
+            .add_assign(&key[..])
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+    }
+
+    let input_public_key = PublicKey::from_secret_key(secp, &input_key);
+    let input_hash =
+        input_hash(outpoints, &input_public_key).ok_or(SilentPaymentError::NoEligibleInputs)?;
+    input_key
+        .mul_assign(&input_hash[..])
+        .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+
+    let mut counters = HashMap::new();
+    recipients
+        .iter()
+        .map(|recipient| {
+            let mut shared_secret = recipient.scan_key;
+            shared_secret
+                .mul_assign(secp, &input_key[..])
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+
+            let k: &mut u32 = counters.entry(recipient.scan_key).or_default();
+            let tweak = output_tweak(&shared_secret, *k);
+            *k += 1;
+
+            let mut output_key = recipient.spend_key;
+            output_key
+                .add_exp_assign(secp, &tweak[..])
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+
+            Ok(taproot_script(&output_key))
+        })
+        .collect()
+}
+
+/// Keys of the recipient of silent payments, used to detect the outputs paying them
+///
+/// Scanning only requires the private scan key, so it can be done by a watch-only wallet. The
+/// private spend key is only needed to compute the keys of the outputs found, with
+/// [`SilentPaymentOutput::private_key`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentReceiver {
+    scan_secret: SecretKey,
+    spend_key: PublicKey,
+}
+
+impl SilentPaymentReceiver {
+    /// Create a new receiver from its private scan key and public spend key
+    pub fn new(scan_secret: SecretKey, spend_key: PublicKey) -> Self {
+        SilentPaymentReceiver {
+            scan_secret,
+            spend_key,
+        }
+    }
+
+    /// Return the address to give to the senders
+    pub fn address<C: Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        network: Network,
+    ) -> SilentPaymentAddress {
+        SilentPaymentAddress::new(
+            PublicKey::from_secret_key(secp, &self.scan_secret),
+            self.spend_key,
+            network,
+        )
+    }
+
+    /// Find the outputs of `tx` that pay the receiver
+    ///
+    /// `prevouts` must contain the outputs spent by the inputs of the transaction, in the same
+    /// order, since their type determines which inputs contribute to the shared secret. An empty
+    /// list is returned if the transaction can't contain silent payments.
+    pub fn scan_transaction<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+    ) -> Result<Vec<SilentPaymentOutput>, SilentPaymentError> {
+        if prevouts.len() != tx.input.len() {
+            return Err(SilentPaymentError::InvalidPrevouts);
+        }
+        if !tx
+            .output
+            .iter()
+            .any(|txout| is_taproot(&txout.script_pubkey))
+        {
+            return Ok(vec![]);
+        }
+
+        let mut input_keys = tx
+            .input
+            .iter()
+            .zip(prevouts)
+            .filter_map(|(txin, prevout)| input_public_key(txin, prevout));
+        let input_public_key = match input_keys.next() {
+            Some(first) => input_keys.try_fold(first, |sum, key| sum.combine(&key).ok()),
+            None => None,
+        };
+        let input_public_key = match input_public_key {
+            Some(key) => key,
+            // either there are no eligible inputs or their keys cancel each other out
+            None => return Ok(vec![]),
+        };
+
+        let outpoints = tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<Vec<_>>();
+        let input_hash = input_hash(&outpoints, &input_public_key)
+            .ok_or(SilentPaymentError::NoEligibleInputs)?;
+
+        let mut tweak_key = self.scan_secret;
+        tweak_key
+            .mul_assign(&input_hash[..])
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+        let mut shared_secret = input_public_key;
+        shared_secret
+            .mul_assign(secp, &tweak_key[..])
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+
+        // the outputs paying the same receiver are numbered sequentially, so the scan stops at
+        // the first number without a match
+        let mut found = vec![];
+        for k in 0.. {
+            let tweak = output_tweak(&shared_secret, k);
+            let mut output_key = self.spend_key;
+            output_key
+                .add_exp_assign(secp, &tweak[..])
+                .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+            let output_script = taproot_script(&output_key);
+
+            match tx
+                .output
+                .iter()
+                .position(|txout| txout.script_pubkey == output_script)
+            {
+                Some(vout) => found.push(SilentPaymentOutput {
+                    outpoint: OutPoint::new(tx.txid(), vout as u32),
+                    txout: tx.output[vout].clone(),
+                    tweak,
+                    height: None,
+                }),
+                None => break,
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+/// Output paying a [`SilentPaymentReceiver`], found by
+/// [`SilentPaymentReceiver::scan_transaction`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SilentPaymentOutput {
+    /// Outpoint of the output
+    pub outpoint: OutPoint,
+    /// The output itself
+    pub txout: TxOut,
+    /// Tweak added to the spend key of the receiver to obtain the key of the output
+    pub tweak: [u8; 32],
+    /// Height of the block that contains the transaction, if known
+    pub height: Option<u32>,
+}
+
+impl SilentPaymentOutput {
+    /// Return the private key of the output, given the private spend key of the receiver
+    ///
+    /// The output only commits to the x coordinate of the key: like with any other taproot
+    /// output, the key has to be negated before signing if the y coordinate of its public key is
+    /// odd.
+    pub fn private_key(&self, spend_secret: &SecretKey) -> Result<SecretKey, SilentPaymentError> {
+        let mut key = *spend_secret;
+        key.add_assign(&self.tweak[..])
+            .map_err(|_| SilentPaymentError::InvalidSharedSecret)?;
+
+        Ok(key)
+    }
+}
+
+/// Script with the same size as the outputs paying a silent payment address, used to estimate
+/// the fees before the inputs are selected
+pub(crate) fn placeholder_script() -> Script {
+    Builder::new()
+        .push_int(1)
+        .push_slice(&[0u8; 32])
+        .into_script()
+}
+
+fn taproot_script(output_key: &PublicKey) -> Script {
+    Builder::new()
+        .push_int(1)
+        .push_slice(&output_key.serialize()[1..])
+        .into_script()
+}
+
+pub(crate) fn is_taproot(script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    bytes.len() == 34 && bytes[0] == 0x51 && bytes[1] == 0x20
+}
+
+/// Return the public key of an input that contributes to the shared secret, if it's eligible
+fn input_public_key(txin: &TxIn, prevout: &TxOut) -> Option<PublicKey> {
+    let script = &prevout.script_pubkey;
+    let script_sig = txin.script_sig.as_bytes();
+
+    let key = if script.is_p2pkh() {
+        // the scriptSig can push anything, the key is the one matching the hash of the output
+        txin.script_sig
+            .instructions()
+            .filter_map(|instruction| match instruction {
+                Ok(Instruction::PushBytes(data)) if data.len() == 33 => Some(data),
+                _ => None,
+            })
+            .find(|data| hash160::Hash::hash(data)[..] == script.as_bytes()[3..23])?
+            .to_vec()
+    } else if script.is_v0_p2wpkh()
+        || (script.is_p2sh() && script_sig.len() == 23 && script_sig[..3] == [0x16, 0x00, 0x14])
+    {
+        txin.witness.last()?.clone()
+    } else if is_taproot(script) {
+        let mut witness = &txin.witness[..];
+        // ignore the annex, if present
+        if witness.len() > 1 && witness.last()?.first() == Some(&0x50) {
+            witness = &witness[..witness.len() - 1];
+        }
+        // script path spend, the internal key is at the beginning of the control block
+        if witness.len() > 1 && witness.last()?.get(1..33) == Some(&NUMS_H[..]) {
+            return None;
+        }
+
+        let mut key = vec![0x02];
+        key.extend_from_slice(&script.as_bytes()[2..]);
+        key
+    } else {
+        return None;
+    };
+
+    // only compressed keys are eligible
+    if key.len() != secp256k1::constants::PUBLIC_KEY_SIZE {
+        return None;
+    }
+    PublicKey::from_slice(&key).ok()
+}
+
+fn input_hash(outpoints: &[OutPoint], input_public_key: &PublicKey) -> Option<[u8; 32]> {
+    let smallest_outpoint = outpoints.iter().map(serialize).min()?;
+    Some(tagged_hash(
+        INPUTS_TAG,
+        &[&smallest_outpoint, &input_public_key.serialize()],
+    ))
+}
+
+fn output_tweak(shared_secret: &PublicKey, k: u32) -> [u8; 32] {
+    tagged_hash(
+        SHARED_SECRET_TAG,
+        &[&shared_secret.serialize(), &k.to_be_bytes()],
+    )
+}
+
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag_hash[..]);
+    engine.input(&tag_hash[..]);
+    for chunk in data {
+        engine.input(chunk);
+    }
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+// bech32m, as defined in BIP-350. The `bech32` crate re-exported by `bitcoin` only supports the
+// original bech32 encoding and limits the strings to 90 characters, which is too short for these
+// addresses.
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    hrp.bytes()
+        .map(|c| c >> 5)
+        .chain(std::iter::once(0))
+        .chain(hrp.bytes().map(|c| c & 31))
+        .collect()
+}
+
+fn bech32m_polymod(hrp: &[u8], data: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+
+    let mut chk = 1u32;
+    for value in hrp.iter().chain(data.iter()) {
+        let top = chk >> 25;
+        chk = (chk & 0x01ff_ffff) << 5 ^ u32::from(*value);
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+fn bech32m_checksum(hrp: &str, data: &[u8]) -> [u8; 6] {
+    let mut values = data.to_vec();
+    values.extend_from_slice(&[0; 6]);
+    let polymod = bech32m_polymod(&hrp_expand(hrp), &values) ^ BECH32M_CONST;
+
+    let mut checksum = [0u8; 6];
+    for (i, value) in checksum.iter_mut().enumerate() {
+        *value = ((polymod >> (5 * (5 - i))) & 31) as u8;
+    }
+    checksum
+}
+
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let max_value = (1 << to) - 1;
+    let mut result = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+
+    for value in data {
+        acc = (acc << from) | u32::from(*value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push(((acc >> bits) & max_value) as u8);
+        }
+    }
+
+    if pad {
+        if bits > 0 {
+            result.push(((acc << (to - bits)) & max_value) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max_value != 0 {
+        return None;
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::hashes::hex::FromHex;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::{Network, OutPoint, Script};
+
+    use super::*;
+
+    const ADDRESS: &str = "sp1qqgste7k9hx0qftg6qmwlkqtwuy6cycyavzmzj85c6qdfhjdpdjtdgqjuexzk6murw56suy3e0rd2cgqvycxttddwsvgxe2usfpxumr70xc9pkqwv";
+
+    #[test]
+    fn test_address_roundtrip() {
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+        assert_eq!(address.network, Network::Bitcoin);
+        assert_eq!(address.to_string(), ADDRESS);
+        assert_eq!(
+            SilentPaymentAddress::from_str(&ADDRESS.to_uppercase()).unwrap(),
+            address
+        );
+
+        let testnet =
+            SilentPaymentAddress::new(address.scan_key, address.spend_key, Network::Regtest);
+        let testnet = SilentPaymentAddress::from_str(&testnet.to_string()).unwrap();
+        assert_eq!(testnet.network, Network::Testnet);
+        assert!(testnet.is_valid_for_network(Network::Regtest));
+        assert!(!testnet.is_valid_for_network(Network::Bitcoin));
+    }
+
+    #[test]
+    fn test_address_invalid() {
+        let mut corrupted = ADDRESS.to_string();
+        corrupted.pop();
+        corrupted.push('q');
+        assert_eq!(
+            SilentPaymentAddress::from_str(&corrupted),
+            Err(SilentPaymentError::InvalidEncoding)
+        );
+        // a regular bech32 address
+        assert_eq!(
+            SilentPaymentAddress::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"),
+            Err(SilentPaymentError::InvalidEncoding)
+        );
+    }
+
+    #[test]
+    fn test_output_scripts() {
+        // "Simple send: two inputs" from the test vectors of BIP-352
+        let secp = Secp256k1::new();
+        let input_keys = vec![
+            SecretKey::from_str("eadc78165ff1f8ea94ad7cfdc54990738a4c53f6e0507b42154201b8e5dff3b1")
+                .unwrap(),
+            SecretKey::from_str("93f5ed907ad5b2bdbbdcb5d9116ebc0a4e1f92f910d5260237fa45a9408aad16")
+                .unwrap(),
+        ];
+        let outpoints = vec![
+            OutPoint::from_str(
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+            )
+            .unwrap(),
+            OutPoint::from_str(
+                "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d:0",
+            )
+            .unwrap(),
+        ];
+        let address = SilentPaymentAddress::from_str(ADDRESS).unwrap();
+
+        let scripts = output_scripts(&secp, &input_keys, &outpoints, &[address]).unwrap();
+        assert_eq!(
+            scripts,
+            vec![Script::from(
+                Vec::<u8>::from_hex(
+                    "51203e9fce73d4e77a4809908e3c3a2e54ee147b9312dc5044a193d1fc85de46e3c1"
+                )
+                .unwrap()
+            )]
+        );
+        // the order of the inputs doesn't matter
+        assert_eq!(
+            output_scripts(
+                &secp,
+                &[input_keys[1], input_keys[0]],
+                &[outpoints[1], outpoints[0]],
+                &[address]
+            )
+            .unwrap(),
+            scripts
+        );
+
+        // paying the same address twice produces two different outputs
+        let scripts = output_scripts(&secp, &input_keys, &outpoints, &[address, address]).unwrap();
+        assert_ne!(scripts[0], scripts[1]);
+
+        assert_eq!(
+            output_scripts(&secp, &[], &outpoints, &[address]),
+            Err(SilentPaymentError::NoEligibleInputs)
+        );
+    }
+
+    #[test]
+    fn test_scan_transaction() {
+        let secp = Secp256k1::new();
+        let scan_secret = SecretKey::from_slice(&[0x01; 32]).unwrap();
+        let spend_secret = SecretKey::from_slice(&[0x02; 32]).unwrap();
+        let receiver = SilentPaymentReceiver::new(
+            scan_secret,
+            PublicKey::from_secret_key(&secp, &spend_secret),
+        );
+        let address = receiver.address(&secp, Network::Testnet);
+
+        let input_keys = vec![
+            SecretKey::from_slice(&[0x03; 32]).unwrap(),
+            SecretKey::from_slice(&[0x04; 32]).unwrap(),
+        ];
+        let outpoints = vec![
+            OutPoint::from_str(
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16:0",
+            )
+            .unwrap(),
+            OutPoint::from_str(
+                "a1075db55d416d3ca199f55b6084e2115b9345e16c5cf302fc80e9d5fbf5d48d:1",
+            )
+            .unwrap(),
+        ];
+        let scripts = output_scripts(&secp, &input_keys, &outpoints, &[address, address]).unwrap();
+
+        // both inputs spend a p2wpkh output, with a dummy signature in the witness
+        let (input, prevouts): (Vec<_>, Vec<_>) = input_keys
+            .iter()
+            .zip(&outpoints)
+            .map(|(key, outpoint)| {
+                let public_key = PublicKey::from_secret_key(&secp, key).serialize().to_vec();
+                let txin = TxIn {
+                    previous_output: *outpoint,
+                    script_sig: Script::new(),
+                    sequence: 0xFFFFFFFF,
+                    witness: vec![vec![0x30; 71], public_key.clone()],
+                };
+                let prevout = TxOut {
+                    value: 100_000,
+                    script_pubkey: Builder::new()
+                        .push_int(0)
+                        .push_slice(&hash160::Hash::hash(&public_key)[..])
+                        .into_script(),
+                };
+                (txin, prevout)
+            })
+            .unzip();
+        let tx = Transaction {
+            version: 2,
+            lock_time: 0,
+            input,
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: placeholder_script(),
+                },
+                TxOut {
+                    value: 20_000,
+                    script_pubkey: scripts[1].clone(),
+                },
+                TxOut {
+                    value: 30_000,
+                    script_pubkey: scripts[0].clone(),
+                },
+            ],
+        };
+
+        let found = receiver.scan_transaction(&secp, &tx, &prevouts).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].outpoint, OutPoint::new(tx.txid(), 2));
+        assert_eq!(found[1].outpoint, OutPoint::new(tx.txid(), 1));
+        for output in &found {
+            let private_key = output.private_key(&spend_secret).unwrap();
+            let public_key = PublicKey::from_secret_key(&secp, &private_key);
+            assert_eq!(output.txout.script_pubkey, taproot_script(&public_key));
+        }
+
+        // a different receiver doesn't find anything
+        let other = SilentPaymentReceiver::new(spend_secret, receiver.spend_key);
+        assert!(other
+            .scan_transaction(&secp, &tx, &prevouts)
+            .unwrap()
+            .is_empty());
+
+        assert_eq!(
+            receiver.scan_transaction(&secp, &tx, &prevouts[..1]),
+            Err(SilentPaymentError::InvalidPrevouts)
+        );
+    }
+}
@@ -43,17 +43,32 @@
 //! ```
 
 use std::collections::BTreeMap;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
 use std::marker::PhantomData;
 
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::util::psbt;
 use bitcoin::{OutPoint, Script, SigHashType, Transaction};
 
-use super::coin_selection::{CoinSelectionAlgorithm, DefaultCoinSelectionAlgorithm};
+use rand::RngCore;
+
+use super::coin_selection::{
+    CoinSelectionAlgorithm, ConsolidationCoinSelection, DefaultCoinSelectionAlgorithm,
+};
+use super::silent_payments::{self, SilentPaymentAddress};
+use super::utils::DustLimit;
 use crate::database::Database;
 use crate::types::{FeeRate, KeychainKind, UTXO};
 
 /// Context in which the [`TxBuilder`] is valid
+///
+/// Methods that only make sense when creating a transaction, like
+/// [`add_recipient`](TxBuilder::add_recipient), are only implemented for the [`CreateTx`]
+/// context, while those that only apply to fee bumps, like
+/// [`allow_shrinking`](TxBuilder::allow_shrinking), are only implemented for [`BumpFee`]: using
+/// them in the wrong context is a compile-time error.
 pub trait TxBuilderContext: std::fmt::Debug + Default + Clone {}
 
 /// [`Wallet::create_tx`](super::Wallet::create_tx) context
@@ -74,24 +89,39 @@ impl TxBuilderContext for BumpFee {}
 #[derive(Debug)]
 pub struct TxBuilder<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> {
     pub(crate) recipients: Vec<(Script, u64)>,
+    pub(crate) silent_payments: Vec<(usize, SilentPaymentAddress)>,
     pub(crate) drain_wallet: bool,
     pub(crate) single_recipient: Option<Script>,
+    pub(crate) allow_shrinking: Option<Script>,
     pub(crate) fee_policy: Option<FeePolicy>,
+    pub(crate) max_fee_rate: Option<FeeRate>,
+    pub(crate) max_fee_absolute: Option<u64>,
     pub(crate) internal_policy_path: Option<BTreeMap<String, Vec<usize>>>,
     pub(crate) external_policy_path: Option<BTreeMap<String, Vec<usize>>>,
     pub(crate) utxos: Vec<OutPoint>,
+    pub(crate) foreign_utxos: Vec<(OutPoint, psbt::Input, usize)>,
     pub(crate) unspendable: HashSet<OutPoint>,
     pub(crate) manually_selected_only: bool,
     pub(crate) sighash: Option<SigHashType>,
     pub(crate) ordering: TxOrdering,
     pub(crate) locktime: Option<u32>,
     pub(crate) rbf: Option<RBFValue>,
+    pub(crate) input_nsequence: HashMap<OutPoint, u32>,
     pub(crate) version: Option<Version>,
     pub(crate) change_policy: ChangeSpendPolicy,
+    pub(crate) change_script: Option<Script>,
+    pub(crate) change_output_policy: ChangeOutputPolicy,
+    pub(crate) dust_limit: Option<DustLimit>,
     pub(crate) force_non_witness_utxo: bool,
     pub(crate) add_global_xpubs: bool,
+    pub(crate) add_bdk_metadata: bool,
     pub(crate) coin_selection: Cs,
+    pub(crate) custom_coin_selection: bool,
     pub(crate) include_output_redeem_witness_script: bool,
+    pub(crate) split_change: Option<(usize, u64)>,
+    pub(crate) decoy_outputs: usize,
+    pub(crate) consolidation: bool,
+    pub(crate) rng_seed: Option<u64>,
 
     phantom: PhantomData<(D, Ctx)>,
 }
@@ -117,24 +147,39 @@ where
     fn default() -> Self {
         TxBuilder {
             recipients: Default::default(),
+            silent_payments: Default::default(),
             drain_wallet: Default::default(),
             single_recipient: Default::default(),
+            allow_shrinking: Default::default(),
             fee_policy: Default::default(),
+            max_fee_rate: Default::default(),
+            max_fee_absolute: Default::default(),
             internal_policy_path: Default::default(),
             external_policy_path: Default::default(),
             utxos: Default::default(),
+            foreign_utxos: Default::default(),
             unspendable: Default::default(),
             manually_selected_only: Default::default(),
             sighash: Default::default(),
             ordering: Default::default(),
             locktime: Default::default(),
             rbf: Default::default(),
+            input_nsequence: Default::default(),
             version: Default::default(),
             change_policy: Default::default(),
+            change_script: Default::default(),
+            change_output_policy: Default::default(),
+            dust_limit: Default::default(),
             force_non_witness_utxo: Default::default(),
             add_global_xpubs: Default::default(),
+            add_bdk_metadata: Default::default(),
             coin_selection: Default::default(),
+            custom_coin_selection: Default::default(),
             include_output_redeem_witness_script: Default::default(),
+            split_change: Default::default(),
+            decoy_outputs: Default::default(),
+            consolidation: Default::default(),
+            rng_seed: Default::default(),
 
             phantom: PhantomData,
         }
@@ -158,11 +203,47 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
     }
 
     /// Set an absolute fee
+    ///
+    /// The transaction will pay exactly `fee_amount` satoshis of fees, regardless of its size. Any
+    /// amount left over after paying the recipients and the fee goes to the change output, if
+    /// one is needed.
+    ///
+    /// When used with [`Wallet::bump_fee`](crate::wallet::Wallet::bump_fee) the amount must be
+    /// at least the fee paid by the original transaction, otherwise [`Error::FeeTooLow`] is
+    /// returned. Keep in mind that BIP-125 also requires the replacement to pay for its own
+    /// bandwidth at the minimum relay fee rate.
+    ///
+    /// [`Error::FeeTooLow`]: crate::Error::FeeTooLow
     pub fn fee_absolute(mut self, fee_amount: u64) -> Self {
         self.fee_policy = Some(FeePolicy::FeeAmount(fee_amount));
         self
     }
 
+    /// Set the maximum fee rate the transaction is allowed to pay
+    ///
+    /// This is a safety net against mistakes in the fee estimation: if the estimated fee rate of
+    /// the signed transaction is higher than `max_fee_rate`, building it fails with
+    /// [`Error::FeeRateTooHigh`]. It overrides the limit set on the wallet with
+    /// [`Wallet::set_max_fee_rate`](super::Wallet::set_max_fee_rate).
+    ///
+    /// [`Error::FeeRateTooHigh`]: crate::Error::FeeRateTooHigh
+    pub fn max_fee_rate(mut self, max_fee_rate: FeeRate) -> Self {
+        self.max_fee_rate = Some(max_fee_rate);
+        self
+    }
+
+    /// Set the maximum absolute fee the transaction is allowed to pay
+    ///
+    /// If the fee is higher than `max_fee_absolute` building the transaction fails with
+    /// [`Error::FeeTooHigh`]. It overrides the limit set on the wallet with
+    /// [`Wallet::set_max_fee_absolute`](super::Wallet::set_max_fee_absolute).
+    ///
+    /// [`Error::FeeTooHigh`]: crate::Error::FeeTooHigh
+    pub fn max_fee_absolute(mut self, max_fee_absolute: u64) -> Self {
+        self.max_fee_absolute = Some(max_fee_absolute);
+        self
+    }
+
     /// Set the policy path to use while creating the transaction for a given keychain.
     ///
     /// This method accepts a map where the key is the policy node id (see
@@ -236,6 +317,11 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
     ///
     /// These have priority over the "unspendable" utxos, meaning that if a utxo is present both in
     /// the "utxos" and the "unspendable" list, it will be spent.
+    ///
+    /// Unless [`manually_selected_only`](Self::manually_selected_only) is enabled, coin selection
+    /// is still free to add more utxos if these aren't enough, picking them among the ones that
+    /// are not "unspendable". This allows, for instance, to spend two specific utxos plus
+    /// whatever else is needed while never touching a third one.
     pub fn utxos(mut self, utxos: Vec<OutPoint>) -> Self {
         self.utxos = utxos;
         self
@@ -244,18 +330,27 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
     /// Add a utxo to the internal list of utxos that **must** be spent
     ///
     /// These have priority over the "unspendable" utxos, meaning that if a utxo is present both in
-    /// the "utxos" and the "unspendable" list, it will be spent.
+    /// the "utxos" and the "unspendable" list, it will be spent. Adding the same utxo more than
+    /// once has no effect.
+    ///
+    /// See [`utxos`](Self::utxos) for how this interacts with coin selection.
     pub fn add_utxo(mut self, utxo: OutPoint) -> Self {
-        self.utxos.push(utxo);
+        if !self.utxos.contains(&utxo) {
+            self.utxos.push(utxo);
+        }
         self
     }
 
     /// Only spend utxos added by [`add_utxo`] and [`utxos`].
     ///
     /// The wallet will **not** add additional utxos to the transaction even if they are needed to
-    /// make the transaction valid.
+    /// make the transaction valid: either exactly the selected utxos are spent, or building the
+    /// transaction fails with [`Error::InsufficientFunds`]. If no utxo was selected
+    /// [`Error::NoUtxosSelected`] is returned.
     ///
     /// [`add_utxo`]: Self::add_utxo
+    /// [`Error::InsufficientFunds`]: crate::Error::InsufficientFunds
+    /// [`Error::NoUtxosSelected`]: crate::Error::NoUtxosSelected
     /// [`utxos`]: Self::utxos
     pub fn manually_selected_only(mut self) -> Self {
         self.manually_selected_only = true;
@@ -284,6 +379,11 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
 
     /// Sign with a specific sig hash
     ///
+    /// The sighash type is set on every input of the PSBT and the wallet's signers will use it
+    /// instead of the default `SIGHASH_ALL`. Other types, like `SIGHASH_SINGLE |
+    /// SIGHASH_ANYONECANPAY`, allow for instance creating offers that can be completed by adding
+    /// more inputs and outputs.
+    ///
     /// **Use this option very carefully**
     pub fn sighash(mut self, sighash: SigHashType) -> Self {
         self.sighash = Some(sighash);
@@ -296,6 +396,20 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
         self
     }
 
+    /// Seed the RNG used to build the transaction
+    ///
+    /// The RNG is used to [shuffle](TxOrdering::Shuffle) the outputs, to pick the values of the
+    /// [decoy outputs](Self::add_decoy_outputs) and to [split the change](Self::split_change).
+    /// With a fixed seed the same wallet state and builder always produce the same transaction,
+    /// which is useful for tests and audits.
+    ///
+    /// The coin selection algorithm has its own randomness, see for instance
+    /// [`BranchAndBoundCoinSelection::rng_seed`](super::coin_selection::BranchAndBoundCoinSelection::rng_seed).
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
     /// Use a specific nLockTime while creating the transaction
     ///
     /// This can cause conflicts if the wallet's descriptors contain an "after" (OP_CLTV) operator.
@@ -308,6 +422,9 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
     ///
     /// The `version` should always be greater than `0` and greater than `1` if the wallet's
     /// descriptors contain an "older" (OP_CSV) operator.
+    ///
+    /// If this is not called the transaction is built with version `2` when the spending policy
+    /// requires a relative timelock, and with version `1` otherwise.
     pub fn version(mut self, version: i32) -> Self {
         self.version = Some(Version(version));
         self
@@ -366,6 +483,30 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
         self
     }
 
+    /// Store the version of BDK, the policy paths and the keychain of the outputs that belong to
+    /// the wallet in the [BDK proprietary fields](crate::psbt::proprietary) of the PSBT
+    ///
+    /// The fields are kept by other signers, so they can be checked with
+    /// [`Wallet::verify_bdk_metadata`](super::Wallet::verify_bdk_metadata) when the PSBT comes
+    /// back.
+    pub fn add_bdk_metadata(mut self) -> Self {
+        self.add_bdk_metadata = true;
+        self
+    }
+
+    /// Set the rule used to decide whether the outputs of the transaction are dust
+    ///
+    /// Building the transaction fails with [`Error::OutputBelowDustLimit`] if the amount sent to
+    /// a recipient is below the limit, while a change output below the limit is dropped and its
+    /// value is added to the fees. This overrides the limit set on the wallet with
+    /// [`Wallet::set_dust_limit`](super::Wallet::set_dust_limit).
+    ///
+    /// [`Error::OutputBelowDustLimit`]: crate::Error::OutputBelowDustLimit
+    pub fn dust_limit(mut self, dust_limit: DustLimit) -> Self {
+        self.dust_limit = Some(dust_limit);
+        self
+    }
+
     /// Spend all the available inputs. This respects filters like [`TxBuilder::unspendable`] and the change policy.
     pub fn drain_wallet(mut self) -> Self {
         self.drain_wallet = true;
@@ -374,31 +515,47 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>, Ctx: TxBuilderContext> TxBuilde
 
     /// Choose the coin selection algorithm
     ///
-    /// Overrides the [`DefaultCoinSelectionAlgorithm`](super::coin_selection::DefaultCoinSelectionAlgorithm).
+    /// Overrides the [`DefaultCoinSelectionAlgorithm`](super::coin_selection::DefaultCoinSelectionAlgorithm)
+    /// and the algorithm set with [`Wallet::set_coin_selection`](super::Wallet::set_coin_selection).
     pub fn coin_selection<P: CoinSelectionAlgorithm<D>>(
         self,
         coin_selection: P,
     ) -> TxBuilder<D, P, Ctx> {
         TxBuilder {
             recipients: self.recipients,
+            silent_payments: self.silent_payments,
             drain_wallet: self.drain_wallet,
             single_recipient: self.single_recipient,
+            allow_shrinking: self.allow_shrinking,
             fee_policy: self.fee_policy,
+            max_fee_rate: self.max_fee_rate,
+            max_fee_absolute: self.max_fee_absolute,
             internal_policy_path: self.internal_policy_path,
             external_policy_path: self.external_policy_path,
             utxos: self.utxos,
+            foreign_utxos: self.foreign_utxos,
             unspendable: self.unspendable,
             manually_selected_only: self.manually_selected_only,
             sighash: self.sighash,
             ordering: self.ordering,
             locktime: self.locktime,
             rbf: self.rbf,
+            input_nsequence: self.input_nsequence,
             version: self.version,
             change_policy: self.change_policy,
+            change_script: self.change_script,
+            change_output_policy: self.change_output_policy,
+            dust_limit: self.dust_limit,
             force_non_witness_utxo: self.force_non_witness_utxo,
             add_global_xpubs: self.add_global_xpubs,
+            add_bdk_metadata: self.add_bdk_metadata,
             include_output_redeem_witness_script: self.include_output_redeem_witness_script,
+            split_change: self.split_change,
+            decoy_outputs: self.decoy_outputs,
+            consolidation: self.consolidation,
+            rng_seed: self.rng_seed,
             coin_selection,
+            custom_coin_selection: true,
 
             phantom: PhantomData,
         }
@@ -411,6 +568,39 @@ impl<D: Database> TxBuilder<D, DefaultCoinSelectionAlgorithm, CreateTx> {
     pub fn with_recipients(recipients: Vec<(Script, u64)>) -> Self {
         Self::default().set_recipients(recipients)
     }
+
+    /// Create a builder that sweeps the wallet, sending all of its funds to a single recipient
+    ///
+    /// This is a shortcut for [`set_single_recipient`](Self::set_single_recipient) combined with
+    /// [`drain_wallet`](Self::drain_wallet): every spendable UTXO is used and the fee is
+    /// subtracted from the only output of the transaction, so there's no need to compute the
+    /// amount to send. Filters like [`unspendable`](Self::unspendable) and the
+    /// [`change_policy`](Self::change_policy) are still respected.
+    pub fn sweep(recipient: Script) -> Self {
+        Self::default()
+            .set_single_recipient(recipient)
+            .drain_wallet()
+    }
+
+    /// Create a builder that consolidates the wallet's UTXOs into a single change output
+    ///
+    /// The transaction spends as many UTXOs as possible, starting from the smallest ones, as long
+    /// as the total weight of the inputs stays below `max_weight`, and sends them to an internal
+    /// address of the wallet. It doesn't need any recipient, but more can be added with
+    /// [`add_recipient`](TxBuilder::add_recipient).
+    ///
+    /// Consolidations are usually made when fees are low, so a [`fee_rate`](TxBuilder::fee_rate)
+    /// should be set explicitly: UTXOs that are not worth spending at that fee rate are skipped.
+    /// See [`ConsolidationCoinSelection`] for more details.
+    ///
+    /// [`ConsolidationCoinSelection`]: super::coin_selection::ConsolidationCoinSelection
+    pub fn consolidate(max_weight: usize) -> TxBuilder<D, ConsolidationCoinSelection, CreateTx> {
+        let mut builder =
+            Self::default().coin_selection(ConsolidationCoinSelection::new(max_weight));
+        builder.consolidation = true;
+
+        builder
+    }
 }
 
 // methods supported only by create_tx, for any `CoinSelectionAlgorithm`
@@ -418,6 +608,7 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>> TxBuilder<D, Cs, CreateTx> {
     /// Replace the recipients already added with a new list
     pub fn set_recipients(mut self, recipients: Vec<(Script, u64)>) -> Self {
         self.recipients = recipients;
+        self.silent_payments.clear();
         self
     }
 
@@ -427,6 +618,37 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>> TxBuilder<D, Cs, CreateTx> {
         self
     }
 
+    /// Add an `OP_RETURN` output with a value of zero that carries `data`
+    ///
+    /// The output is added like a recipient, so it's removed by
+    /// [`set_single_recipient`](Self::set_single_recipient). Note that the default policy of
+    /// Bitcoin Core only relays transactions with at most one `OP_RETURN` output, carrying up to
+    /// 80 bytes of data.
+    pub fn add_data(self, data: &[u8]) -> Self {
+        let script_pubkey = Builder::new()
+            .push_opcode(opcodes::all::OP_RETURN)
+            .push_slice(data)
+            .into_script();
+        self.add_recipient(script_pubkey, 0)
+    }
+
+    /// Add a recipient identified by a [silent payment address](super::silent_payments)
+    ///
+    /// The output is added like a recipient, but its script is only computed once the inputs of
+    /// the transaction have been selected, since it depends on their private keys. The wallet
+    /// must know the private keys of every input that spends a `pkh`, `wpkh` or `sh(wpkh)`
+    /// output, and foreign UTXOs can't be added to the transaction.
+    ///
+    /// The script depends on the inputs, so the address is stored in the database together with
+    /// the script: when the fee of the transaction is bumped with [`Wallet::bump_fee`], the
+    /// script is computed again for the new inputs.
+    ///
+    /// [`Wallet::bump_fee`]: super::Wallet::bump_fee
+    pub fn add_silent_payment(mut self, address: SilentPaymentAddress, amount: u64) -> Self {
+        self.silent_payments.push((self.recipients.len(), address));
+        self.add_recipient(silent_payments::placeholder_script(), amount)
+    }
+
     /// Set a single recipient that will get all the selected funds minus the fee. No change will
     /// be created
     ///
@@ -444,10 +666,70 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>> TxBuilder<D, Cs, CreateTx> {
     pub fn set_single_recipient(mut self, recipient: Script) -> Self {
         self.single_recipient = Some(recipient);
         self.recipients.clear();
+        self.silent_payments.clear();
 
         self
     }
 
+    /// Send all the selected funds minus the fee to `recipient`, without creating a change output
+    ///
+    /// This is the same as [`set_single_recipient`](Self::set_single_recipient), named after the
+    /// "send max" flow it's usually part of: combined with [`add_utxo`](Self::add_utxo) and
+    /// [`manually_selected_only`](Self::manually_selected_only) it spends exactly the chosen
+    /// coins, while with [`drain_wallet`](Self::drain_wallet) it empties the wallet.
+    pub fn drain_to(self, recipient: Script) -> Self {
+        self.set_single_recipient(recipient)
+    }
+
+    /// Add a UTXO that doesn't belong to the wallet to the inputs of the transaction
+    ///
+    /// This is useful for transactions funded by more than one party, like payjoins or
+    /// collaborative spends. The `psbt_input` must contain either the `witness_utxo` or the
+    /// `non_witness_utxo` for `outpoint`, and it's copied as-is to the PSBT, so it should also
+    /// include everything the other party needs to sign it. The `satisfaction_weight` is the
+    /// weight of the `scriptSig` and witness required to spend the output, which is used to
+    /// account for the input in the fees: when it's underestimated the transaction will end up
+    /// paying a lower fee rate than requested. If the descriptor of the other party is known, the
+    /// weight can be computed with [`SatisfactionWeight`](crate::descriptor::SatisfactionWeight).
+    ///
+    /// Foreign UTXOs are always spent, even when [`manually_selected_only`](Self::manually_selected_only)
+    /// is enabled. Their value is not counted in [`TransactionDetails::sent`](crate::types::TransactionDetails::sent),
+    /// and the wallet won't be able to sign or finalize their inputs.
+    pub fn add_foreign_utxo(
+        mut self,
+        outpoint: OutPoint,
+        psbt_input: psbt::Input,
+        satisfaction_weight: usize,
+    ) -> Self {
+        self.foreign_utxos
+            .retain(|(existing, _, _)| *existing != outpoint);
+        self.foreign_utxos
+            .push((outpoint, psbt_input, satisfaction_weight));
+        self
+    }
+
+    /// Send the change to `script_pubkey` instead of a new address of the internal keychain
+    ///
+    /// The script doesn't have to belong to the wallet: when it doesn't, the change is not counted
+    /// in [`TransactionDetails::received`](crate::types::TransactionDetails::received), and
+    /// [`Wallet::bump_fee`](super::Wallet::bump_fee) will later treat that output as a recipient.
+    ///
+    /// This can't be combined with [`split_change`](Self::split_change), since that would
+    /// require sending more than one output to the same script.
+    pub fn change_script(mut self, script_pubkey: Script) -> Self {
+        self.change_script = Some(script_pubkey);
+        self
+    }
+
+    /// Choose when a change output should be added to the transaction
+    ///
+    /// See [`ChangeOutputPolicy`] for the available options. By default a change output is
+    /// always created, unless its value would be below the [`dust_limit`](Self::dust_limit).
+    pub fn change_output_policy(mut self, change_output_policy: ChangeOutputPolicy) -> Self {
+        self.change_output_policy = change_output_policy;
+        self
+    }
+
     /// Enable signaling RBF
     ///
     /// This will use the default nSequence value of `0xFFFFFFFD`.
@@ -467,6 +749,45 @@ impl<D: Database, Cs: CoinSelectionAlgorithm<D>> TxBuilder<D, Cs, CreateTx> {
         self.rbf = Some(RBFValue::Value(nsequence));
         self
     }
+
+    /// Use a specific nSequence for the input spending `outpoint`
+    ///
+    /// This overrides the value that would otherwise be picked for that input based on the RBF
+    /// settings and the OP_CSV requirements of the wallet's descriptors, while every other input
+    /// keeps using the default one. The override only applies if the UTXO ends up being spent, so
+    /// it's usually combined with [`add_utxo`](Self::add_utxo).
+    ///
+    /// An error will be thrown if the wallet's descriptors contain an "older" (OP_CSV) operator
+    /// and the given `nsequence` doesn't satisfy it, or if a specific nLockTime is required but
+    /// this override would make every input of the transaction final.
+    pub fn input_nsequence(mut self, outpoint: OutPoint, nsequence: u32) -> Self {
+        self.input_nsequence.insert(outpoint, nsequence);
+        self
+    }
+
+    /// Split the change into up to `n` outputs of randomized value
+    ///
+    /// This makes it harder for an observer to tell which output is the change. Every change output
+    /// will be worth at least `min_value` satoshi, and never less than the dust limit: if the change
+    /// isn't large enough to also pay the fees for the extra outputs, fewer of them are created,
+    /// possibly down to a single one.
+    pub fn split_change(mut self, n: usize, min_value: u64) -> Self {
+        self.split_change = Some((n, min_value));
+        self
+    }
+
+    /// Pad the transaction with `count` additional outputs that send funds back to the wallet
+    ///
+    /// The decoy outputs use the change descriptor and their value is picked at random in a range
+    /// around the amounts sent to the recipients, so that the payments can't be told apart by
+    /// their value. The decoys are funded like any other recipient, so this option increases the
+    /// fees paid and the amount of funds required to build the transaction.
+    ///
+    /// This option can't be used together with [`set_single_recipient`](Self::set_single_recipient).
+    pub fn add_decoy_outputs(mut self, count: usize) -> Self {
+        self.decoy_outputs = count;
+        self
+    }
 }
 
 // methods supported only by bump_fee
@@ -488,14 +809,43 @@ impl<D: Database> TxBuilder<D, DefaultCoinSelectionAlgorithm, BumpFee> {
         self.single_recipient = Some(Script::default());
         self
     }
+
+    /// Allow `bump_fee` to reduce the value of the output with the given `script_pubkey` to pay
+    /// for the higher fees, instead of shrinking the change output
+    ///
+    /// Every other output of the transaction is left untouched, including the change output if
+    /// there's one. If extra inputs are added with [`add_utxo`] or [`utxos`] and they are not
+    /// entirely consumed in fees the selected output will grow in value instead.
+    ///
+    /// Fails if the transaction doesn't have an output with the given `script_pubkey` or if its
+    /// value would fall below the dust limit.
+    ///
+    /// This option can't be used together with
+    /// [`maintain_single_recipient`](Self::maintain_single_recipient), since both tell `bump_fee`
+    /// which output can be reduced.
+    ///
+    /// [`add_utxo`]: Self::add_utxo
+    /// [`utxos`]: Self::utxos
+    pub fn allow_shrinking(mut self, script_pubkey: Script) -> Self {
+        self.allow_shrinking = Some(script_pubkey);
+        self
+    }
 }
 
 /// Ordering of the transaction's inputs and outputs
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Copy)]
 pub enum TxOrdering {
     /// Randomized (default)
+    ///
+    /// Only the outputs are shuffled, the inputs are left in the order picked by the coin
+    /// selection.
     Shuffle,
     /// Unchanged
+    ///
+    /// The inputs are in the order picked by the coin selection, which starts with the utxos
+    /// added with [`TxBuilder::add_utxo`] and [`TxBuilder::utxos`] in the order they were added.
+    /// The outputs of the recipients come first, in the order they were added, followed by the
+    /// decoys and the change. Useful for protocols that require a specific ordering, like payjoin.
     Untouched,
     /// BIP69 / Lexicographic
     BIP69Lexicographic,
@@ -510,19 +860,25 @@ impl Default for TxOrdering {
 impl TxOrdering {
     /// Sort transaction inputs and outputs by [`TxOrdering`] variant
     pub fn sort_tx(&self, tx: &mut Transaction) {
+        #[cfg(test)]
+        use rand::SeedableRng;
+
+        #[cfg(not(test))]
+        let mut rng = rand::thread_rng();
+        #[cfg(test)]
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        self.sort_tx_with_rng(tx, &mut rng);
+    }
+
+    /// Sort transaction inputs and outputs by [`TxOrdering`] variant, using `rng` to shuffle them
+    pub fn sort_tx_with_rng<R: RngCore>(&self, tx: &mut Transaction, rng: &mut R) {
         match self {
             TxOrdering::Untouched => {}
             TxOrdering::Shuffle => {
                 use rand::seq::SliceRandom;
-                #[cfg(test)]
-                use rand::SeedableRng;
 
-                #[cfg(not(test))]
-                let mut rng = rand::thread_rng();
-                #[cfg(test)]
-                let mut rng = rand::rngs::StdRng::seed_from_u64(0);
-
-                tx.output.shuffle(&mut rng);
+                tx.output.shuffle(rng);
             }
             TxOrdering::BIP69Lexicographic => {
                 tx.input.sort_unstable_by_key(|txin| {
@@ -592,6 +948,36 @@ impl ChangeSpendPolicy {
     }
 }
 
+/// Policy regarding the creation of a change output
+///
+/// Whenever the change is not added to the transaction, its value is paid as fees.
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone, Copy)]
+pub enum ChangeOutputPolicy {
+    /// Always add a change output, unless its value would be dust (default)
+    Always,
+    /// Only add a change output if its value is at least the given amount of satoshi, and it
+    /// wouldn't be dust
+    AboveThreshold(u64),
+    /// Never add a change output
+    Never,
+}
+
+impl Default for ChangeOutputPolicy {
+    fn default() -> Self {
+        ChangeOutputPolicy::Always
+    }
+}
+
+impl ChangeOutputPolicy {
+    pub(crate) fn keeps_change(&self, value: u64) -> bool {
+        match self {
+            ChangeOutputPolicy::Always => true,
+            ChangeOutputPolicy::AboveThreshold(threshold) => value >= *threshold,
+            ChangeOutputPolicy::Never => false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     const ORDERING_TEST_TX: &'static str = "0200000003c26f3eb7932f7acddc5ddd26602b77e7516079b03090a16e2c2f54\
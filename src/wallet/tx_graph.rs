@@ -0,0 +1,252 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Transaction graph
+//!
+//! This module provides a read-only view of the transactions stored in the wallet's
+//! [`database`](crate::database) and of the links between them, which can be used to inspect
+//! the history of the wallet, to find conflicting transactions or to visualize how the funds
+//! moved, without having to look at how the data is stored internally.
+//!
+//! ## Example
+//!
+//! ```no_run
+//! # use bitcoin::*;
+//! # use bdk::database::*;
+//! # use bdk::*;
+//! # let descriptor = "wpkh(tpubD6NzVbkrYhZ4Xferm7Pz4VnjdcDPFyjVu5K4iZXQ4pVN8Cks4pHVowTBXBKRhX64pkRyJZJN5xAKj4UDNnLPb5p2sSKXhewoYx5GbTdUFWq/*)";
+//! # let wallet: OfflineWallet<_> = Wallet::new_offline(descriptor, None, Network::Testnet, MemoryDatabase::default())?;
+//! let graph = wallet.tx_graph()?;
+//! for details in graph.transactions() {
+//!     println!(
+//!         "{} spends from {:?} and conflicts with {:?}",
+//!         details.txid,
+//!         graph.parents(&details.txid),
+//!         graph.conflicts(&details.txid)
+//!     );
+//! }
+//! # Ok::<(), bdk::Error>(())
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::{OutPoint, Txid};
+
+use crate::blockchain::BlockchainMarker;
+use crate::database::BatchDatabase;
+use crate::error::Error;
+use crate::types::TransactionDetails;
+use crate::wallet::Wallet;
+
+/// Read-only view of the transactions stored by a wallet
+///
+/// The graph is a snapshot taken when [`Wallet::tx_graph`] is called, so it won't reflect any
+/// later change to the database. Transactions stored without their raw content are part of the
+/// graph, but they have no links to other transactions since their inputs are unknown.
+#[derive(Debug, Clone, Default)]
+pub struct TxGraph {
+    txs: BTreeMap<Txid, TransactionDetails>,
+    spends: HashMap<OutPoint, Vec<Txid>>,
+}
+
+impl TxGraph {
+    fn new(txs: Vec<TransactionDetails>) -> Self {
+        let mut graph = TxGraph::default();
+
+        for details in txs {
+            if let Some(tx) = &details.transaction {
+                for input in &tx.input {
+                    graph
+                        .spends
+                        .entry(input.previous_output)
+                        .or_default()
+                        .push(details.txid);
+                }
+            }
+
+            graph.txs.insert(details.txid, details);
+        }
+
+        graph
+    }
+
+    /// Iterate over the transactions in the graph, ordered by txid
+    pub fn transactions(&self) -> impl Iterator<Item = &TransactionDetails> {
+        self.txs.values()
+    }
+
+    /// Return a transaction in the graph
+    pub fn get_tx(&self, txid: &Txid) -> Option<&TransactionDetails> {
+        self.txs.get(txid)
+    }
+
+    /// Return the transactions in the graph that spend `outpoint`
+    ///
+    /// More than one transaction is returned if the outpoint is spent by conflicting
+    /// transactions.
+    pub fn spenders(&self, outpoint: &OutPoint) -> &[Txid] {
+        self.spends
+            .get(outpoint)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Return the transactions in the graph that `txid` spends from
+    pub fn parents(&self, txid: &Txid) -> Vec<Txid> {
+        let mut parents = self
+            .inputs(txid)
+            .map(|outpoint| outpoint.txid)
+            .filter(|parent| self.txs.contains_key(parent))
+            .collect::<Vec<_>>();
+        parents.sort();
+        parents.dedup();
+
+        parents
+    }
+
+    /// Return the transactions in the graph that spend from `txid`
+    pub fn children(&self, txid: &Txid) -> Vec<Txid> {
+        let outputs = self
+            .get_tx(txid)
+            .and_then(|details| details.transaction.as_ref())
+            .map(|tx| tx.output.len())
+            .unwrap_or(0);
+
+        let mut children = (0..outputs)
+            .flat_map(|vout| self.spenders(&OutPoint::new(*txid, vout as u32)))
+            .cloned()
+            .collect::<Vec<_>>();
+        children.sort();
+        children.dedup();
+
+        children
+    }
+
+    /// Return the transactions in the graph that spend at least one of the inputs of `txid`
+    pub fn conflicts(&self, txid: &Txid) -> Vec<Txid> {
+        let mut conflicts = self
+            .inputs(txid)
+            .flat_map(|outpoint| self.spenders(outpoint))
+            .filter(|other| *other != txid)
+            .cloned()
+            .collect::<Vec<_>>();
+        conflicts.sort();
+        conflicts.dedup();
+
+        conflicts
+    }
+
+    fn inputs<'a>(&'a self, txid: &Txid) -> impl Iterator<Item = &'a OutPoint> {
+        self.get_tx(txid)
+            .and_then(|details| details.transaction.as_ref())
+            .into_iter()
+            .flat_map(|tx| tx.input.iter().map(|input| &input.previous_output))
+    }
+}
+
+impl<B, D> Wallet<B, D>
+where
+    B: BlockchainMarker,
+    D: BatchDatabase,
+{
+    /// Return a read-only view of the transactions stored in the database and of the links
+    /// between them
+    pub fn tx_graph(&self) -> Result<TxGraph, Error> {
+        Ok(TxGraph::new(self.database.borrow().iter_txs(true)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoin::Address;
+
+    use super::*;
+    use crate::database::{BatchOperations, Database};
+    use crate::types::FeeRate;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::TxBuilder;
+
+    #[test]
+    fn test_tx_graph() {
+        let (wallet, _, funding_txid) = get_funded_wallet(get_test_wpkh());
+        let funding_outpoint = OutPoint::new(funding_txid, 0);
+        let addr = Address::from_str("2N1Ffz3WaNzbeLFBb51xyFMHYSEUXcbiSoX").unwrap();
+
+        // two transactions spending the same output
+        let mut spends = vec![];
+        for fee_rate in &[1.0, 5.0] {
+            let (psbt, mut details) = wallet
+                .create_tx(
+                    TxBuilder::with_recipients(vec![(addr.script_pubkey(), 25_000)])
+                        .fee_rate(FeeRate::from_sat_per_vb(*fee_rate)),
+                )
+                .unwrap();
+            details.transaction = Some(psbt.extract_tx());
+            wallet.database.borrow_mut().set_tx(&details).unwrap();
+            spends.push(details.txid);
+        }
+        spends.sort();
+
+        let graph = wallet.tx_graph().unwrap();
+        assert_eq!(graph.transactions().count(), 3);
+        assert_eq!(graph.get_tx(&funding_txid).unwrap().txid, funding_txid);
+        assert_eq!(graph.spenders(&funding_outpoint), spends.as_slice());
+        assert!(graph.spenders(&OutPoint::new(spends[0], 0)).is_empty());
+
+        assert!(graph.parents(&funding_txid).is_empty());
+        assert_eq!(graph.children(&funding_txid), spends);
+        assert_eq!(graph.parents(&spends[0]), vec![funding_txid]);
+        assert!(graph.children(&spends[0]).is_empty());
+
+        assert!(graph.conflicts(&funding_txid).is_empty());
+        assert_eq!(graph.conflicts(&spends[0]), vec![spends[1]]);
+        assert_eq!(graph.conflicts(&spends[1]), vec![spends[0]]);
+    }
+
+    #[test]
+    fn test_tx_graph_without_raw_tx() {
+        let (wallet, _, funding_txid) = get_funded_wallet(get_test_wpkh());
+        let mut details = wallet
+            .database
+            .borrow()
+            .get_tx(&funding_txid, false)
+            .unwrap()
+            .unwrap();
+        details.transaction = None;
+        details.txid = Txid::default();
+        wallet.database.borrow_mut().set_tx(&details).unwrap();
+
+        let graph = wallet.tx_graph().unwrap();
+        assert_eq!(graph.transactions().count(), 2);
+        assert!(graph
+            .get_tx(&Txid::default())
+            .unwrap()
+            .transaction
+            .is_none());
+        assert!(graph.parents(&Txid::default()).is_empty());
+        assert!(graph.children(&Txid::default()).is_empty());
+    }
+}
@@ -22,14 +22,23 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+use bitcoin::consensus::encode::serialize;
 use bitcoin::secp256k1::{All, Secp256k1};
 use bitcoin::util::bip32;
+use bitcoin::{Script, TxOut};
+
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
 
 use miniscript::descriptor::DescriptorPublicKeyCtx;
 use miniscript::{MiniscriptKey, Satisfier, ToPublicKey};
 
+use crate::types::{FeeRate, RecipientDetails};
+
 // De-facto standard "dust limit" (even though it should change based on the output type)
 const DUST_LIMIT_SATOSHI: u64 = 546;
+// Default fee rate used by Bitcoin Core to compute the dust limit of an output
+const DUST_RELAY_FEE_SAT_VB: f32 = 3.0;
 
 // MSB of the nSequence. If set there's no consensus-constraint, so it must be disabled when
 // spending using CSV in order to enforce CSV rules
@@ -58,15 +67,69 @@ impl IsDust for u64 {
     }
 }
 
+/// Rule used to decide whether an output is too small to be relayed
+///
+/// By default the limit depends on the type of the output, following the same rules used by
+/// Bitcoin Core: it's the cost of creating the output and later spending it at the "dust relay"
+/// fee rate of 3 sat/vbyte. This is 546 satoshi for P2PKH outputs, but only 294 satoshi for
+/// P2WPKH ones.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DustLimit {
+    /// Compute the limit for every output given the "dust relay" fee rate
+    PerScript(FeeRate),
+    /// Use the same limit for every output
+    Fixed(u64),
+}
+
+impl Default for DustLimit {
+    fn default() -> Self {
+        DustLimit::PerScript(FeeRate::from_sat_per_vb(DUST_RELAY_FEE_SAT_VB))
+    }
+}
+
+impl DustLimit {
+    /// Return the minimum value that an output with `script_pubkey` must have not to be dust
+    pub fn limit_for(&self, script_pubkey: &Script) -> u64 {
+        match self {
+            DustLimit::Fixed(limit) => *limit,
+            // provably unspendable outputs are never considered dust
+            DustLimit::PerScript(_) if script_pubkey.is_op_return() => 0,
+            DustLimit::PerScript(dust_relay_fee) => {
+                // value, followed by the length-prefixed script
+                let output_size = 8 + serialize(script_pubkey).len();
+                // outpoint, scriptSig length, nSequence and the size of a typical signature
+                // with its public key, which is discounted for witness programs
+                let input_size = match script_pubkey.is_witness_program() {
+                    true => 32 + 4 + 1 + 4 + 107 / 4,
+                    false => 32 + 4 + 1 + 4 + 107,
+                };
+
+                ((output_size + input_size) as f32 * dust_relay_fee.as_sat_vb()) as u64
+            }
+        }
+    }
+
+    /// Check whether an output with `script_pubkey` and `value` would be dust
+    pub fn is_dust(&self, value: u64, script_pubkey: &Script) -> bool {
+        value < self.limit_for(script_pubkey)
+    }
+}
+
 pub struct After {
     pub current_height: Option<u32>,
+    pub median_time_past: Option<u32>,
     pub assume_height_reached: bool,
 }
 
 impl After {
-    pub(crate) fn new(current_height: Option<u32>, assume_height_reached: bool) -> After {
+    pub(crate) fn new(
+        current_height: Option<u32>,
+        median_time_past: Option<u32>,
+        assume_height_reached: bool,
+    ) -> After {
         After {
             current_height,
+            median_time_past,
             assume_height_reached,
         }
     }
@@ -112,8 +175,14 @@ pub(crate) fn check_nlocktime(nlocktime: u32, required: u32) -> bool {
 
 impl<ToPkCtx: Copy, Pk: MiniscriptKey + ToPublicKey<ToPkCtx>> Satisfier<ToPkCtx, Pk> for After {
     fn check_after(&self, n: u32) -> bool {
-        if let Some(current_height) = self.current_height {
-            current_height >= n
+        // time-based locktimes are compared with the median time past of the tip (BIP113)
+        let current = match n < BLOCKS_TIMELOCK_THRESHOLD {
+            true => self.current_height,
+            false => self.median_time_past.map(|mtp| mtp.saturating_sub(1)),
+        };
+
+        if let Some(current) = current {
+            current >= n
         } else {
             self.assume_height_reached
         }
@@ -151,6 +220,96 @@ impl<ToPkCtx: Copy, Pk: MiniscriptKey + ToPublicKey<ToPkCtx>> Satisfier<ToPkCtx,
     }
 }
 
+/// Split `change` into at most `parts` values, each one worth at least `min_value` and not dust
+/// according to `dust_limit` for outputs with `script_pubkey`
+///
+/// Every output after the first one costs `output_fee` satoshi, which is subtracted from `change`.
+/// Returns the values and the total extra fee paid. If `change` can't be split a single value
+/// equal to `change` is returned.
+pub(crate) fn split_change_value<R: Rng>(
+    change: u64,
+    parts: usize,
+    min_value: u64,
+    output_fee: u64,
+    dust_limit: &DustLimit,
+    script_pubkey: &Script,
+    rng: &mut R,
+) -> (Vec<u64>, u64) {
+    let min_value = std::cmp::max(min_value, dust_limit.limit_for(script_pubkey));
+
+    for parts in (2..=parts as u64).rev() {
+        let extra_fee = output_fee * (parts - 1);
+        let available = match change.checked_sub(extra_fee) {
+            Some(available) => available,
+            None => continue,
+        };
+        let surplus = match min_value
+            .checked_mul(parts)
+            .and_then(|min_total| available.checked_sub(min_total))
+        {
+            Some(surplus) => surplus,
+            None => continue,
+        };
+
+        // distribute the surplus according to random weights, and give whatever is left due to
+        // rounding to the first output
+        let weights = (0..parts)
+            .map(|_| rng.gen_range(1, 1000))
+            .collect::<Vec<u64>>();
+        let total_weight: u64 = weights.iter().sum();
+        let mut values = weights
+            .into_iter()
+            .map(|w| min_value + ((surplus as u128 * w as u128) / total_weight as u128) as u64)
+            .collect::<Vec<_>>();
+        values[0] += available - values.iter().sum::<u64>();
+
+        return (values, extra_fee);
+    }
+
+    (vec![change], 0)
+}
+
+/// Pick `count` random values for decoy outputs, in a range around the `amounts` of the payments
+///
+/// No value is dust according to `dust_limit` for outputs with `script_pubkey`.
+pub(crate) fn decoy_output_values<R: Rng>(
+    amounts: &[u64],
+    count: usize,
+    dust_limit: &DustLimit,
+    script_pubkey: &Script,
+    rng: &mut R,
+) -> Vec<u64> {
+    let min = amounts.iter().min().cloned().unwrap_or(0) / 2;
+    let max = amounts.iter().max().cloned().unwrap_or(0);
+    let max = max.saturating_add(max / 2);
+
+    let low = std::cmp::max(min, dust_limit.limit_for(script_pubkey));
+    let high = std::cmp::max(max, low) + 1;
+
+    (0..count).map(|_| rng.gen_range(low, high)).collect()
+}
+
+/// RNG used to build a transaction, deterministic if a `seed` is provided
+pub(crate) fn tx_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_rng(thread_rng()).expect("Failed to seed the RNG"),
+    }
+}
+
+/// Describe the payment made by `txout`, which paid `fee` if `fee_subtracted` is `true`
+pub(crate) fn recipient_details(txout: &TxOut, fee_subtracted: bool, fee: u64) -> RecipientDetails {
+    RecipientDetails {
+        script_pubkey: txout.script_pubkey.clone(),
+        amount: match fee_subtracted {
+            true => txout.value + fee,
+            false => txout.value,
+        },
+        fee_subtracted,
+        effective_amount: txout.value,
+    }
+}
+
 pub(crate) type SecpCtx = Secp256k1<All>;
 pub(crate) fn descriptor_to_pk_ctx(secp: &SecpCtx) -> DescriptorPublicKeyCtx<'_, All> {
     // Create a `to_pk_ctx` with a dummy derivation index, since we always use this on descriptor
@@ -195,9 +354,17 @@ impl<I: Iterator> Iterator for ChunksIterator<I> {
 
 #[cfg(test)]
 mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use std::str::FromStr;
+
+    use bitcoin::blockdata::opcodes;
+    use bitcoin::blockdata::script::Builder;
+    use bitcoin::{Address, Script};
+
     use super::{
-        check_nlocktime, check_nsequence_rbf, BLOCKS_TIMELOCK_THRESHOLD,
-        SEQUENCE_LOCKTIME_TYPE_FLAG,
+        check_nlocktime, check_nsequence_rbf, decoy_output_values, split_change_value, DustLimit,
+        BLOCKS_TIMELOCK_THRESHOLD, SEQUENCE_LOCKTIME_TYPE_FLAG,
     };
     use crate::types::FeeRate;
 
@@ -284,4 +451,150 @@ mod test {
         );
         assert_eq!(result, true);
     }
+
+    fn p2wpkh_script() -> Script {
+        Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+            .unwrap()
+            .script_pubkey()
+    }
+
+    #[test]
+    fn test_split_change_value() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let dust_limit = DustLimit::default();
+        let (values, extra_fee) = split_change_value(
+            100_000,
+            4,
+            1_000,
+            100,
+            &dust_limit,
+            &p2wpkh_script(),
+            &mut rng,
+        );
+
+        assert_eq!(values.len(), 4);
+        assert_eq!(extra_fee, 300);
+        assert_eq!(values.iter().sum::<u64>(), 100_000 - 300);
+        assert!(values.iter().all(|v| *v >= 1_000));
+    }
+
+    #[test]
+    fn test_split_change_value_fewer_parts() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let dust_limit = DustLimit::default();
+        let (values, extra_fee) = split_change_value(
+            10_000,
+            10,
+            3_000,
+            100,
+            &dust_limit,
+            &p2wpkh_script(),
+            &mut rng,
+        );
+
+        assert_eq!(values.len(), 3);
+        assert_eq!(extra_fee, 200);
+        assert_eq!(values.iter().sum::<u64>(), 10_000 - 200);
+        assert!(values.iter().all(|v| *v >= 3_000));
+    }
+
+    #[test]
+    fn test_split_change_value_dust() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let dust_limit = DustLimit::default();
+        let script = p2wpkh_script();
+        let (values, _) = split_change_value(5_000, 20, 0, 100, &dust_limit, &script, &mut rng);
+
+        assert!(values.len() > 1);
+        assert!(values.iter().all(|v| !dust_limit.is_dust(*v, &script)));
+
+        // a p2wpkh output is dust below 294 satoshi
+        let (values, extra_fee) =
+            split_change_value(700, 2, 0, 100, &dust_limit, &script, &mut rng);
+        assert_eq!(values.len(), 2);
+        assert_eq!(extra_fee, 100);
+
+        // with a custom limit the same change can't be split
+        let dust_limit = DustLimit::Fixed(1_000);
+        let (values, extra_fee) =
+            split_change_value(700, 2, 0, 100, &dust_limit, &script, &mut rng);
+        assert_eq!(values, vec![700]);
+        assert_eq!(extra_fee, 0);
+    }
+
+    #[test]
+    fn test_decoy_output_values() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let values = decoy_output_values(
+            &[10_000, 30_000],
+            50,
+            &DustLimit::default(),
+            &p2wpkh_script(),
+            &mut rng,
+        );
+
+        assert_eq!(values.len(), 50);
+        assert!(values.iter().all(|v| *v >= 5_000 && *v <= 45_000));
+    }
+
+    #[test]
+    fn test_decoy_output_values_dust() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let dust_limit = DustLimit::default();
+        let script = p2wpkh_script();
+        let values = decoy_output_values(&[200], 50, &dust_limit, &script, &mut rng);
+        assert!(values
+            .iter()
+            .all(|v| !dust_limit.is_dust(*v, &script) && *v <= 300));
+
+        let dust_limit = DustLimit::Fixed(1_000);
+        let values = decoy_output_values(&[600], 50, &dust_limit, &script, &mut rng);
+        assert!(values.iter().all(|v| *v == 1_000));
+    }
+
+    #[test]
+    fn test_dust_limit_per_script() {
+        let dust_limit = DustLimit::default();
+        let script = |addr: &str| Address::from_str(addr).unwrap().script_pubkey();
+
+        // p2pkh
+        assert_eq!(
+            dust_limit.limit_for(&script("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2")),
+            546
+        );
+        // p2sh
+        assert_eq!(
+            dust_limit.limit_for(&script("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy")),
+            540
+        );
+        // p2wpkh
+        let p2wpkh = script("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+        assert_eq!(dust_limit.limit_for(&p2wpkh), 294);
+        assert!(dust_limit.is_dust(293, &p2wpkh));
+        assert!(!dust_limit.is_dust(294, &p2wpkh));
+        // p2wsh
+        assert_eq!(
+            dust_limit.limit_for(&script(
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3"
+            )),
+            330
+        );
+
+        let op_return = Builder::new()
+            .push_opcode(opcodes::all::OP_RETURN)
+            .push_slice(&[0x00; 10])
+            .into_script();
+        assert!(!dust_limit.is_dust(0, &op_return));
+    }
+
+    #[test]
+    fn test_dust_limit_fixed() {
+        let dust_limit = DustLimit::Fixed(1_000);
+        let p2wpkh = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+            .unwrap()
+            .script_pubkey();
+
+        assert!(dust_limit.is_dust(999, &p2wpkh));
+        assert!(!dust_limit.is_dust(1_000, &p2wpkh));
+    }
 }
@@ -0,0 +1,169 @@
+// Magical Bitcoin Library
+// Written in 2020 by
+//     Alekos Filini <alekos.filini@gmail.com>
+//
+// Copyright (c) 2020 Magical Bitcoin
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Transaction verification
+//!
+//! This module, enabled by the `verify` feature, runs transactions through the consensus script
+//! verification of `libbitcoinconsensus`. It's used during [`sync`](crate::Wallet::sync) to make sure
+//! that the transactions returned by the backend are valid before storing them in the database.
+
+use std::fmt;
+
+use bitcoin::consensus::serialize;
+use bitcoin::{OutPoint, Transaction, Txid};
+
+use crate::database::Database;
+use crate::error::Error;
+
+/// Errors that can be returned while verifying a transaction
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The transaction spending an input couldn't be found
+    MissingInputTx(Txid),
+    /// The previous output spent by an input doesn't exist
+    InvalidInput(OutPoint),
+    /// The script verification failed for the input at this index
+    Consensus(usize, bitcoinconsensus::Error),
+    /// Other error, for example while reading from the database
+    Global(Box<Error>),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl From<Error> for VerifyError {
+    fn from(other: Error) -> Self {
+        VerifyError::Global(Box::new(other))
+    }
+}
+
+/// Verify the scripts of every input of a transaction
+///
+/// The previous transactions must be available in the `database`. Coinbase inputs are skipped.
+pub fn verify_tx<D: Database>(tx: &Transaction, database: &D) -> Result<(), VerifyError> {
+    verify_tx_with(tx, |txid| database.get_raw_tx(txid))
+}
+
+// Verify a transaction, looking up the previous transactions with `get_prev_tx`
+pub(crate) fn verify_tx_with<F>(tx: &Transaction, get_prev_tx: F) -> Result<(), VerifyError>
+where
+    F: Fn(&Txid) -> Result<Option<Transaction>, Error>,
+{
+    let serialized_tx = serialize(tx);
+
+    for (index, input) in tx.input.iter().enumerate() {
+        if input.previous_output.is_null() {
+            continue;
+        }
+
+        let prev_tx = get_prev_tx(&input.previous_output.txid)?
+            .ok_or(VerifyError::MissingInputTx(input.previous_output.txid))?;
+        let spent_output = prev_tx
+            .output
+            .get(input.previous_output.vout as usize)
+            .ok_or(VerifyError::InvalidInput(input.previous_output))?;
+
+        bitcoinconsensus::verify(
+            &spent_output.script_pubkey.to_bytes(),
+            spent_output.value,
+            &serialized_tx,
+            index,
+        )
+        .map_err(|e| VerifyError::Consensus(index, e))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::database::{BatchOperations, MemoryDatabase};
+    use crate::wallet::signer::SignOptions;
+    use crate::wallet::test::{get_funded_wallet, get_test_wpkh};
+    use crate::TxBuilder;
+
+    fn get_signed_tx() -> (Transaction, MemoryDatabase) {
+        let (wallet, _, txid) = get_funded_wallet(get_test_wpkh());
+        let addr = wallet.get_new_address().unwrap();
+        let (psbt, _) = wallet
+            .create_tx(TxBuilder::new().add_recipient(addr.script_pubkey(), 25_000))
+            .unwrap();
+        let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
+        assert!(finalized);
+
+        let mut database = MemoryDatabase::new();
+        let prev_tx = wallet.database.borrow().get_raw_tx(&txid).unwrap().unwrap();
+        database.set_raw_tx(&prev_tx).unwrap();
+
+        (psbt.extract_tx(), database)
+    }
+
+    #[test]
+    fn test_verify_tx() {
+        let (tx, database) = get_signed_tx();
+        assert!(verify_tx(&tx, &database).is_ok());
+    }
+
+    #[test]
+    fn test_verify_tx_invalid_signature() {
+        let (mut tx, database) = get_signed_tx();
+        // changing the value of an output invalidates the signature
+        tx.output[0].value -= 1;
+
+        assert!(matches!(
+            verify_tx(&tx, &database),
+            Err(VerifyError::Consensus(0, _))
+        ));
+    }
+
+    #[test]
+    fn test_verify_tx_missing_input_tx() {
+        let (tx, _) = get_signed_tx();
+
+        assert!(matches!(
+            verify_tx(&tx, &MemoryDatabase::new()),
+            Err(VerifyError::MissingInputTx(txid)) if txid == tx.input[0].previous_output.txid
+        ));
+    }
+
+    #[test]
+    fn test_verify_tx_invalid_input() {
+        let (mut tx, database) = get_signed_tx();
+        tx.input[0].previous_output = OutPoint {
+            vout: 42,
+            ..tx.input[0].previous_output
+        };
+
+        assert!(matches!(
+            verify_tx(&tx, &database),
+            Err(VerifyError::InvalidInput(_))
+        ));
+    }
+}
@@ -73,15 +73,16 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
             #parsed
 
             mod #mod_name {
-                use bitcoin::Network;
+                use #root_ident::bitcoin::{self, Network};
 
-                use miniscript::Descriptor;
+                use #root_ident::miniscript::{self, Descriptor};
 
-                use testutils::{TestClient, serial};
+                use #root_ident::integration_test::{block_on_maybe_async, serial, testutils, TestClient};
 
                 use #root_ident::blockchain::{Blockchain, noop_progress};
                 use #root_ident::descriptor::ExtendedDescriptor;
                 use #root_ident::database::MemoryDatabase;
+                use #root_ident::signer::SignOptions;
                 use #root_ident::types::KeychainKind;
                 use #root_ident::{Wallet, TxBuilder, FeeRate};
 
@@ -92,7 +93,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                 }
 
                 fn get_wallet_from_descriptors(descriptors: &(String, Option<String>)) -> Wallet<#return_type, MemoryDatabase> {
-                    Wallet::new(&descriptors.0.to_string(), descriptors.1.as_ref(), Network::Regtest, MemoryDatabase::new(), get_blockchain()).unwrap()
+                    block_on_maybe_async!(Wallet::new(&descriptors.0.to_string(), descriptors.1.as_ref(), Network::Regtest, MemoryDatabase::new(), get_blockchain())).unwrap()
                 }
 
                 fn init_single_sig() -> (Wallet<#return_type, MemoryDatabase>, (String, Option<String>), TestClient) {
@@ -117,7 +118,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                     println!("{:?}", tx);
                     let txid = test_client.receive(tx);
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
                     assert_eq!(wallet.list_unspent().unwrap()[0].keychain, KeychainKind::External);
@@ -141,7 +142,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 25) => 50_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 100_000);
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 2);
@@ -152,14 +153,14 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                 fn test_sync_before_and_after_receive() {
                     let (wallet, descriptors, mut test_client) = init_single_sig();
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 0);
 
                     test_client.receive(testutils! {
                         @tx ( (@external descriptors, 0) => 50_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 1);
@@ -174,7 +175,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000, (@external descriptors, 1) => 25_000, (@external descriptors, 5) => 30_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 105_000);
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 1);
@@ -199,7 +200,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 5) => 25_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 75_000);
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 2);
@@ -215,14 +216,14 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
 
                     test_client.receive(testutils! {
                         @tx ( (@external descriptors, 0) => 25_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 75_000);
                 }
 
@@ -235,7 +236,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 ) ( @replaceable true )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 1);
@@ -249,7 +250,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
 
                     let new_txid = test_client.bump_fee(&txid);
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 1);
@@ -271,7 +272,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 ) ( @confirmations 1 ) ( @replaceable true )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 1);
@@ -284,7 +285,7 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                     // Invalidate 1 block
                     test_client.invalidate(1);
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
 
@@ -304,17 +305,17 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
 
                     let (psbt, details) = wallet.create_tx(TxBuilder::with_recipients(vec![(node_addr.script_pubkey(), 25_000)])).unwrap();
-                    let (psbt, finalized) = wallet.sign(psbt, None).unwrap();
+                    let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
                     let tx = psbt.extract_tx();
                     println!("{}", bitcoin::consensus::encode::serialize_hex(&tx));
-                    wallet.broadcast(tx).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(tx)).unwrap();
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), details.received);
 
                     assert_eq!(wallet.list_transactions(false).unwrap().len(), 2);
@@ -331,20 +332,20 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
 
                     let (psbt, details) = wallet.create_tx(TxBuilder::with_recipients(vec![(node_addr.script_pubkey(), 25_000)])).unwrap();
-                    let (psbt, finalized) = wallet.sign(psbt, None).unwrap();
+                    let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    let sent_txid = wallet.broadcast(psbt.extract_tx()).unwrap();
+                    let sent_txid = block_on_maybe_async!(wallet.broadcast(psbt.extract_tx())).unwrap();
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), details.received);
 
                     // empty wallet
                     let wallet = get_wallet_from_descriptors(&descriptors);
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                     let tx_map = wallet.list_transactions(false).unwrap().into_iter().map(|tx| (tx.txid, tx)).collect::<std::collections::HashMap<_, _>>();
 
@@ -368,27 +369,27 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 )
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
 
                     let mut total_sent = 0;
                     for _ in 0..5 {
                         let (psbt, details) = wallet.create_tx(TxBuilder::with_recipients(vec![(node_addr.script_pubkey().clone(), 5_000)])).unwrap();
-                        let (psbt, finalized) = wallet.sign(psbt, None).unwrap();
+                        let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
                         assert!(finalized, "Cannot finalize transaction");
-                        wallet.broadcast(psbt.extract_tx()).unwrap();
+                        block_on_maybe_async!(wallet.broadcast(psbt.extract_tx())).unwrap();
 
-                        wallet.sync(noop_progress(), None).unwrap();
+                        block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
 
                         total_sent += 5_000 + details.fees;
                     }
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000 - total_sent);
 
                     // empty wallet
                     let wallet = get_wallet_from_descriptors(&descriptors);
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000 - total_sent);
                 }
 
@@ -402,22 +403,22 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
 
                     let (psbt, details) = wallet.create_tx(TxBuilder::with_recipients(vec![(node_addr.script_pubkey().clone(), 5_000)]).enable_rbf()).unwrap();
-                    let (psbt, finalized) = wallet.sign(psbt, None).unwrap();
+                    let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000 - details.fees - 5_000);
                     assert_eq!(wallet.get_balance().unwrap(), details.received);
 
                     let (new_psbt, new_details) = wallet.bump_fee(&details.txid, TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(2.1))).unwrap();
-                    let (new_psbt, finalized) = wallet.sign(new_psbt, None).unwrap();
+                    let (new_psbt, finalized) = wallet.sign(new_psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(new_psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(new_psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000 - new_details.fees - 5_000);
                     assert_eq!(wallet.get_balance().unwrap(), new_details.received);
 
@@ -434,23 +435,23 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000 ) (@confirmations 1)
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 50_000);
 
                     let (psbt, details) = wallet.create_tx(TxBuilder::with_recipients(vec![(node_addr.script_pubkey().clone(), 49_000)]).enable_rbf()).unwrap();
-                    let (psbt, finalized) = wallet.sign(psbt, None).unwrap();
+                    let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 1_000 - details.fees);
                     assert_eq!(wallet.get_balance().unwrap(), details.received);
 
                     let (new_psbt, new_details) = wallet.bump_fee(&details.txid, TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(5.0))).unwrap();
 
-                    let (new_psbt, finalized) = wallet.sign(new_psbt, None).unwrap();
+                    let (new_psbt, finalized) = wallet.sign(new_psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(new_psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(new_psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 0);
                     assert_eq!(new_details.received, 0);
 
@@ -467,23 +468,23 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000, (@external descriptors, 1) => 25_000 ) (@confirmations 1)
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 75_000);
 
                     let (psbt, details) = wallet.create_tx(TxBuilder::with_recipients(vec![(node_addr.script_pubkey().clone(), 49_000)]).enable_rbf()).unwrap();
-                    let (psbt, finalized) = wallet.sign(psbt, None).unwrap();
+                    let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 26_000 - details.fees);
                     assert_eq!(details.received, 1_000 - details.fees);
 
                     let (new_psbt, new_details) = wallet.bump_fee(&details.txid, TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(10.0))).unwrap();
 
-                    let (new_psbt, finalized) = wallet.sign(new_psbt, None).unwrap();
+                    let (new_psbt, finalized) = wallet.sign(new_psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(new_psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(new_psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(new_details.sent, 75_000);
                     assert_eq!(wallet.get_balance().unwrap(), new_details.received);
                 }
@@ -498,24 +499,24 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                         @tx ( (@external descriptors, 0) => 50_000, (@external descriptors, 1) => 25_000 ) (@confirmations 1)
                     });
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 75_000);
 
                     let (psbt, details) = wallet.create_tx(TxBuilder::with_recipients(vec![(node_addr.script_pubkey().clone(), 49_000)]).enable_rbf()).unwrap();
-                    let (psbt, finalized) = wallet.sign(psbt, None).unwrap();
+                    let (psbt, finalized) = wallet.sign(psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 26_000 - details.fees);
                     assert_eq!(details.received, 1_000 - details.fees);
 
                     let (new_psbt, new_details) = wallet.bump_fee(&details.txid, TxBuilder::new().fee_rate(FeeRate::from_sat_per_vb(123.0))).unwrap();
                     println!("{:#?}", new_details);
 
-                    let (new_psbt, finalized) = wallet.sign(new_psbt, None).unwrap();
+                    let (new_psbt, finalized) = wallet.sign(new_psbt, SignOptions::default()).unwrap();
                     assert!(finalized, "Cannot finalize transaction");
-                    wallet.broadcast(new_psbt.extract_tx()).unwrap();
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.broadcast(new_psbt.extract_tx())).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(new_details.sent, 75_000);
                     assert_eq!(wallet.get_balance().unwrap(), 0);
                     assert_eq!(new_details.received, 0);
@@ -527,14 +528,36 @@ pub fn bdk_blockchain_tests(attr: TokenStream, item: TokenStream) -> TokenStream
                     let (wallet, descriptors, mut test_client) = init_single_sig();
                     let wallet_addr = wallet.get_new_address().unwrap();
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert_eq!(wallet.get_balance().unwrap(), 0);
 
                     test_client.generate(1, Some(wallet_addr));
 
-                    wallet.sync(noop_progress(), None).unwrap();
+                    block_on_maybe_async!(wallet.sync(noop_progress(), None)).unwrap();
                     assert!(wallet.get_balance().unwrap() > 0);
                 }
+
+                #[test]
+                #[serial]
+                fn test_get_height() {
+                    let (_, _, mut test_client) = init_single_sig();
+                    let blockchain = get_blockchain();
+
+                    let height = block_on_maybe_async!(blockchain.get_height()).unwrap();
+                    test_client.generate(1, None);
+
+                    assert_eq!(block_on_maybe_async!(blockchain.get_height()).unwrap(), height + 1);
+                }
+
+                #[test]
+                #[serial]
+                fn test_estimate_fee() {
+                    let blockchain = get_blockchain();
+
+                    // regtest nodes usually can't estimate the fees, so we can only check that the
+                    // call succeeds
+                    assert!(block_on_maybe_async!(blockchain.estimate_fee(6)).is_ok());
+                }
             }
 
                         };
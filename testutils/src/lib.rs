@@ -65,6 +65,10 @@ pub fn get_electrum_url() -> String {
     env::var("MAGICAL_ELECTRUM_URL").unwrap_or("tcp://127.0.0.1:50001".to_string())
 }
 
+pub fn get_esplora_url() -> String {
+    env::var("MAGICAL_ESPLORA_URL").unwrap_or("http://127.0.0.1:3002".to_string())
+}
+
 pub struct TestClient {
     client: RpcClient,
     electrum: ElectrumClient,
@@ -131,12 +135,12 @@ macro_rules! testutils {
         let parsed = Descriptor::<DescriptorPublicKey>::parse_descriptor(&$descriptors.1.expect("Missing internal descriptor")).expect("Failed to parse descriptor in `testutils!(@internal)`").0;
         parsed.derive(bitcoin::util::bip32::ChildNumber::from_normal_idx($child).unwrap()).address(bitcoin::Network::Regtest).expect("No address form")
     });
-    ( @e $descriptors:expr, $child:expr ) => ({ testutils!(@external $descriptors, $child) });
-    ( @i $descriptors:expr, $child:expr ) => ({ testutils!(@internal $descriptors, $child) });
+    ( @e $descriptors:expr, $child:expr ) => ({ $crate::testutils!(@external $descriptors, $child) });
+    ( @i $descriptors:expr, $child:expr ) => ({ $crate::testutils!(@internal $descriptors, $child) });
 
     ( @tx ( $( ( $( $addr:tt )* ) => $amount:expr ),+ ) $( ( @locktime $locktime:expr ) )* $( ( @confirmations $confirmations:expr ) )* $( ( @replaceable $replaceable:expr ) )* ) => ({
         let mut outs = Vec::new();
-        $( outs.push(testutils::TestIncomingOutput::new($amount, testutils!( $($addr)* ))); )+
+        $( outs.push($crate::TestIncomingOutput::new($amount, $crate::testutils!( $($addr)* ))); )+
 
         let mut locktime = None::<i64>;
         $( locktime = Some($locktime); )*
@@ -147,7 +151,7 @@ macro_rules! testutils {
         let mut replaceable = None::<bool>;
         $( replaceable = Some($replaceable); )*
 
-        testutils::TestIncomingTx::new(outs, min_confirmations, locktime, replaceable)
+        $crate::TestIncomingTx::new(outs, min_confirmations, locktime, replaceable)
     });
 
     ( @literal $key:expr ) => ({
@@ -190,7 +194,7 @@ macro_rules! testutils {
         let mut map = std::collections::HashMap::new();
         $(
             let alias: &str = $alias;
-            map.insert(alias, testutils!( $($key_type)* ));
+            map.insert(alias, $crate::testutils!( $($key_type)* ));
         )+
 
         map
@@ -205,7 +209,7 @@ macro_rules! testutils {
 
         let mut keys: HashMap<&'static str, (String, Option<String>, Option<String>)> = HashMap::new();
         $(
-            keys = testutils!{ @keys $( $keys )* };
+            keys = $crate::testutils!{ @keys $( $keys )* };
         )*
 
         let external: Descriptor<String> = FromStr::from_str($external_descriptor).unwrap();